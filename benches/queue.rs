@@ -0,0 +1,159 @@
+//! 10k random changes to a queue of about a thousand entries: adds, deletes
+//! and moves anywhere in it, like an auto-DJ script keeps making. Each is its
+//! own transaction, as it would be coming from a client.
+//!
+//! Time on a desktop says little about an SD card, what hurts there is how
+//! much gets written. `written_per_change` prints that, as the pages the
+//! changes left in the write-ahead log.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{env, fs};
+
+use camino::Utf8PathBuf;
+use mpdhaj::mpd_protocol::{PosOrRange, Position, Range};
+use mpdhaj::system::play_order::Rng;
+use mpdhaj::{Config, System};
+use rusqlite::Connection;
+
+fn main() {
+    divan::main();
+}
+
+const SONGS: usize = 1000;
+const CHANGES: usize = 10_000;
+
+/// A system with a full queue in a database of its own, removed when dropped
+struct Queue {
+    dir: PathBuf,
+    system: System,
+    rng: Rng,
+}
+
+impl Queue {
+    fn new() -> Self {
+        // divan may make the next input before dropping the last
+        static MADE: AtomicUsize = AtomicUsize::new(0);
+        let made = MADE.fetch_add(1, Ordering::Relaxed);
+        let dir = env::temp_dir().join(format!("mpdhaj-bench-queue-{}-{made}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let db = Connection::open(dir.join("state.sqlite")).unwrap();
+        let music_dir = Utf8PathBuf::from_path_buf(dir.join("music")).unwrap();
+        let mut system = System::open(Config::new(music_dir), db).unwrap();
+
+        let paths: Vec<Utf8PathBuf> = (0..SONGS)
+            .map(|i| format!("album/{i:04}.flac").into())
+            .collect();
+        let mut insert = system
+            .db
+            .prepare("INSERT INTO songs (path, mtime) VALUES (?1, '')")
+            .unwrap();
+        for path in &paths {
+            insert.execute([path.as_str()]).unwrap();
+        }
+        drop(insert);
+        system.add_many_to_queue(&paths, None).unwrap();
+
+        // only what the changes write ends up in the log
+        system
+            .db
+            .pragma_update(None, "wal_autocheckpoint", 0)
+            .unwrap();
+        system
+            .db
+            .query_one("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))
+            .unwrap();
+        Self {
+            dir,
+            system,
+            rng: Rng::new(42),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.system
+            .db
+            .query_one("SELECT COUNT(*) FROM queue", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    fn churn(mut self, runtime: &tokio::runtime::Runtime) -> Self {
+        let mut len = self.len();
+        for _ in 0..CHANGES {
+            let pos = self.rng.below(len) as u32;
+            match self.rng.below(3) {
+                // keeps the queue around the same length
+                0 if len < SONGS => {
+                    let song = self.rng.below(SONGS);
+                    let paths = [Utf8PathBuf::from(format!("album/{song:04}.flac"))];
+                    let at = Position::Absolute(self.rng.below(len + 1) as u32);
+                    self.system.add_many_to_queue(&paths, Some(at)).unwrap();
+                    len += 1;
+                }
+                0 | 1 => {
+                    let what = PosOrRange::Position(Position::Absolute(pos));
+                    runtime
+                        .block_on(self.system.delete_pos_or_range(Some(what)))
+                        .unwrap();
+                    len -= 1;
+                }
+                _ => {
+                    let end = pos + 1 + self.rng.below(3.min(len - pos as usize)) as u32;
+                    let to = self.rng.below(len - (end - pos) as usize + 1) as u32;
+                    let what = PosOrRange::Range(Range::new(pos, Some(end)));
+                    self.system
+                        .move_entries(what, Position::Absolute(to))
+                        .unwrap();
+                }
+            }
+        }
+        self
+    }
+
+    /// Bytes of pages in the write-ahead log since [`Queue::new`]
+    fn written(&self) -> u64 {
+        let db = &self.system.db;
+        let frames: u64 = db
+            .query_one("PRAGMA wal_checkpoint(PASSIVE)", [], |row| row.get(1))
+            .unwrap();
+        let page_size: u64 = db
+            .query_one("PRAGMA page_size", [], |row| row.get(0))
+            .unwrap();
+        frames * page_size
+    }
+}
+
+impl Drop for Queue {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[divan::bench(sample_count = 5, sample_size = 1)]
+fn random_changes(bencher: divan::Bencher) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    bencher
+        .with_inputs(Queue::new)
+        .bench_local_values(|queue| queue.churn(&runtime))
+}
+
+/// The same changes as [`random_changes`], once, printing what they wrote
+#[divan::bench(sample_count = 1, sample_size = 1)]
+fn written_per_change(bencher: divan::Bencher) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    bencher.with_inputs(Queue::new).bench_local_values(|queue| {
+        let queue = queue.churn(&runtime);
+        let bytes = queue.written();
+        println!(
+            "{CHANGES} changes wrote {} KiB to the log, {} bytes each",
+            bytes / 1024,
+            bytes / CHANGES as u64
+        );
+        queue
+    });
+}
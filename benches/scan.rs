@@ -0,0 +1,110 @@
+//! Runs `mpdhaj scan` over a generated library of 10k tagged files. Once
+//! into an empty database and once with everything already scanned, the
+//! second is what happens on every start.
+//!
+//! The files are tiny wavs with an ID3 title. Metadata extraction is cheap
+//! for them so this mostly measures the database writes.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::{env, fs};
+
+use lofty::config::WriteOptions;
+use lofty::tag::{Accessor, Tag, TagExt, TagType};
+
+fn main() {
+    divan::main();
+}
+
+const ALBUMS: usize = 100;
+const SONGS_PER_ALBUM: usize = 100;
+
+/// Removed when dropped
+struct Library(PathBuf);
+
+impl Library {
+    fn generate() -> Self {
+        let root = env::temp_dir().join(format!("mpdhaj-bench-scan-{}", std::process::id()));
+        let library = Library(root);
+        let template = library.0.join("template.wav");
+        fs::create_dir_all(library.music()).unwrap();
+        write_song(&template);
+        let song = fs::read(&template).unwrap();
+
+        for album in 0..ALBUMS {
+            let dir = library.music().join(format!("album {album}"));
+            fs::create_dir_all(&dir).unwrap();
+            for track in 0..SONGS_PER_ALBUM {
+                fs::write(dir.join(format!("{track:02}.wav")), &song).unwrap();
+            }
+        }
+        library
+    }
+
+    fn music(&self) -> PathBuf {
+        self.0.join("music")
+    }
+
+    /// Where the database ends up
+    fn cache(&self) -> PathBuf {
+        self.0.join("cache")
+    }
+
+    fn scan(&self) {
+        let status = Command::new(env!("CARGO_BIN_EXE_mpdhaj"))
+            .arg("scan")
+            .arg(self.music())
+            .env("XDG_CACHE_HOME", self.cache())
+            .env("RUST_LOG", "warn")
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A tenth of a second of silence
+fn write_song(path: &Path) {
+    let data_len = 4410u32 * 2;
+    let mut wav = Vec::new();
+    wav.extend(b"RIFF");
+    wav.extend((36 + data_len).to_le_bytes());
+    wav.extend(b"WAVEfmt ");
+    wav.extend(16u32.to_le_bytes());
+    wav.extend(1u16.to_le_bytes()); // PCM
+    wav.extend(1u16.to_le_bytes()); // mono
+    wav.extend(44100u32.to_le_bytes());
+    wav.extend((44100u32 * 2).to_le_bytes());
+    wav.extend(2u16.to_le_bytes());
+    wav.extend(16u16.to_le_bytes());
+    wav.extend(b"data");
+    wav.extend(data_len.to_le_bytes());
+    wav.resize(wav.len() + data_len as usize, 0);
+    fs::write(path, wav).unwrap();
+
+    let mut tag = Tag::new(TagType::Id3v2);
+    tag.set_title("Bench".into());
+    tag.save_to_path(path, WriteOptions::default()).unwrap();
+}
+
+#[divan::bench(sample_count = 5, sample_size = 1)]
+fn fresh(bencher: divan::Bencher) {
+    let library = Library::generate();
+    bencher
+        .with_inputs(|| {
+            let _ = fs::remove_dir_all(library.cache());
+        })
+        .bench_local_values(|()| library.scan());
+}
+
+#[divan::bench(sample_count = 5, sample_size = 1)]
+fn rescan(bencher: divan::Bencher) {
+    let library = Library::generate();
+    library.scan();
+    bencher.bench_local(|| library.scan());
+}
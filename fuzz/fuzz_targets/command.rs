@@ -0,0 +1,10 @@
+//! Whatever a client sends, parsing it never panics
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mpdhaj::mpd_protocol::command_parser;
+
+fuzz_target!(|line: &str| {
+    let _ = command_parser::parse(line);
+});
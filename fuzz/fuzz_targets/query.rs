@@ -0,0 +1,11 @@
+//! The filters of find, search and friends on their own, so the fuzzer does
+//! not have to find its way past a command first
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mpdhaj::mpd_protocol::command_parser::query;
+
+fuzz_target!(|filter: &str| {
+    let _ = query::parse(filter, 0);
+});
@@ -0,0 +1,73 @@
+//! Pulling the player's output chain per sample versus in blocks, see the
+//! `block` module. Every variant produces the same samples, only the way
+//! they are pulled differs.
+
+use std::hint::black_box;
+use std::time::Duration;
+
+use rodio2::const_source::limiter::LimitSettings;
+use rodio2::fixed_source::FixedSourceExt;
+use rodio2::fixed_source::amplify::Factor;
+use rodio2::fixed_source::buffer::SamplesBuffer;
+use rodio2::fixed_source::queue::uniform::UniformQueue;
+use rodio2::{Block, ConstSource, FixedSource, nz};
+
+fn main() {
+    divan::main();
+}
+
+/// Ten seconds of stereo
+const SAMPLES: usize = 10 * 44100 * 2;
+const BLOCK_LENS: &[usize] = &[64, 512, 4096];
+
+/// Like the chain the player builds, minus decoding
+fn chain() -> impl FixedSource + Block {
+    let (queue, handle) = UniformQueue::new(nz!(2), nz!(44100));
+    let samples: Vec<_> = (0..SAMPLES / 2).map(|i| (i % 100) as f32 / 100.0).collect();
+    let song = SamplesBuffer::new(nz!(1), nz!(44100), samples)
+        .with_channel_count(nz!(2))
+        .amplify(Factor::Linear(0.8))
+        .periodic_access(Duration::from_millis(5), |_| ())
+        .stoppable();
+    handle.add(song).unwrap();
+    queue
+        .pausable(false)
+        .amplify(Factor::Linear(0.5))
+        .try_into_const_source::<44100, 2>()
+        .unwrap()
+        .balance(0.2)
+        .periodic_access(Duration::from_millis(5), |_| ())
+        .limit(LimitSettings::default())
+        .into_fixed_source()
+}
+
+#[divan::bench]
+fn per_sample(bencher: divan::Bencher) {
+    bencher
+        .with_inputs(chain)
+        .bench_local_values(|source| black_box(source.take(SAMPLES).sum::<f32>()));
+}
+
+#[divan::bench(args = BLOCK_LENS)]
+fn next_block(bencher: divan::Bencher, block_len: usize) {
+    bencher.with_inputs(chain).bench_local_values(|mut source| {
+        let mut block = vec![0.0; block_len];
+        let mut sum = 0.0;
+        let mut left = SAMPLES;
+        while left > 0 {
+            let want = block_len.min(left);
+            let pulled = source.next_block(&mut block[..want]);
+            sum += block[..pulled].iter().sum::<f32>();
+            left -= want;
+        }
+        black_box(sum)
+    });
+}
+
+/// What the output sees, per sample on the outside but blocks within
+#[divan::bench]
+fn pull_blocks(bencher: divan::Bencher) {
+    bencher
+        .with_inputs(chain)
+        .bench_local_values(|source| black_box(source.pull_blocks().take(SAMPLES).sum::<f32>()));
+}
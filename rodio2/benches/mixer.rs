@@ -46,7 +46,8 @@ mod const_source {
 
     use super::*;
     use rodio2::ConstSource;
-    use rodio2::const_source::mixer::ConstMix;
+    use rodio2::const_source::CollectConstSource;
+    use rodio2::const_source::mixer::{ConstMix, DynamicMixer};
 
     fn consume_mixer(queue: impl ConstSource<44100, 2>, num: usize) -> usize {
         queue
@@ -61,15 +62,44 @@ mod const_source {
         black_box(consume_mixer(black_box(source), N));
     }
 
-    #[divan::bench] // TODO larger tuples
-    fn tuple() {
+    #[divan::bench(consts = SINES)]
+    fn uniform_array_collect_mixed<const N: usize>() {
+        let sources: [_; N] = core::array::from_fn(|_| sine());
+        let source = sources.collect_mixed();
+        black_box(consume_mixer(black_box(source), N));
+    }
+
+    #[divan::bench]
+    fn tuple_2() {
         let source = (sine(), sine()).mix();
         black_box(consume_mixer(black_box(source), 2));
     }
 
+    #[divan::bench]
+    fn tuple_4() {
+        let source = (sine(), sine(), sine(), sine()).mix();
+        black_box(consume_mixer(black_box(source), 4));
+    }
+
+    #[divan::bench]
+    fn tuple_8() {
+        let source = (
+            sine(),
+            sine(),
+            sine(),
+            sine(),
+            sine(),
+            sine(),
+            sine(),
+            sine(),
+        )
+            .mix();
+        black_box(consume_mixer(black_box(source), 8));
+    }
+
     #[divan::bench(consts = SINES)]
     fn uniform_vec<const N: usize>() {
-        let sources: Vec<_> = (0..N).into_iter().map(|_| sine()).collect();
+        let sources: Vec<_> = (0..N).map(|_| sine()).collect();
         let source = sources.mix();
         black_box(consume_mixer(black_box(source), N));
     }
@@ -77,7 +107,6 @@ mod const_source {
     #[divan::bench(consts = SINES)]
     fn vec<const N: usize>() {
         let sources: Vec<_> = (0..N)
-            .into_iter()
             .map(|_| Box::new(sine()) as Box<dyn ConstSource<44100, 2>>)
             .collect();
         let source = sources.mix();
@@ -104,6 +133,27 @@ mod const_source {
         let source = rx.mix();
         black_box(consume_mixer(black_box(source), N));
     }
+
+    #[divan::bench(consts = SINES)]
+    fn dynamic<const N: usize>() {
+        let (source, handle) = DynamicMixer::new();
+        for _ in 0..N {
+            handle.add(sine()).unwrap();
+        }
+        black_box(consume_mixer(black_box(source), N));
+    }
+
+    #[divan::bench(consts = SINES)]
+    fn dynamic_with_removal<const N: usize>() {
+        let (source, handle) = DynamicMixer::new();
+        for i in 0..2 * N {
+            let id = handle.add(sine()).unwrap();
+            if i % 2 == 0 {
+                handle.remove(id).unwrap();
+            }
+        }
+        black_box(consume_mixer(black_box(source), N));
+    }
 }
 //
 // mod fixed_source {
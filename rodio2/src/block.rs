@@ -0,0 +1,257 @@
+//! Pulling many samples at once.
+//!
+//! Getting a sample out of a chain of sources goes through every `next` in
+//! the chain, each with its own branches. [`Block::next_block`] fills a whole
+//! slice in one call so the hot sources (amplify, the queues, the converters)
+//! do that work once per block. Sources without a faster way fall back to
+//! calling `next`, and `next` keeps working as before, both produce exactly
+//! the same samples.
+//!
+//! Whatever plays the chain still calls `next`, put [`PullBlocks`] in front
+//! of it so the rest of the chain is pulled in blocks.
+
+use std::time::Duration;
+
+use rodio::{ChannelCount, FixedSource, Sample, SampleRate};
+
+/// Samples [`PullBlocks`] asks for at once, about 6ms of 44.1kHz stereo
+pub const BLOCK_LEN: usize = 512;
+
+pub trait Block: Iterator<Item = Sample> {
+    /// Fills `out` from the start and returns how many samples were written.
+    /// That is less than `out.len()` only when the source ended. Gives the
+    /// same samples as calling `next` that many times.
+    fn next_block(&mut self, out: &mut [Sample]) -> usize {
+        for (written, slot) in out.iter_mut().enumerate() {
+            match self.next() {
+                Some(sample) => *slot = sample,
+                None => return written,
+            }
+        }
+        out.len()
+    }
+}
+
+/// Samples left before a periodic access is due, for a source that is
+/// `sample_counter` samples into its frame with `frames_until_update` frames
+/// to go. Zero when it is due now.
+pub(crate) fn until_access(sample_counter: u16, frames_until_update: u32, channels: u16) -> usize {
+    let channels = channels as usize;
+    (channels - sample_counter as usize) % channels + frames_until_update as usize * channels
+}
+
+/// Moves the counters of a periodic access on by `pulled` calls to `next`.
+/// No access may be due within them.
+pub(crate) fn advance(
+    sample_counter: &mut u16,
+    frames_until_update: &mut u32,
+    channels: u16,
+    pulled: usize,
+) {
+    let channels = channels as usize;
+    let counter = *sample_counter as usize;
+    let frames_started = (counter + pulled).div_ceil(channels) - counter.div_ceil(channels);
+    *frames_until_update -= frames_started as u32;
+    *sample_counter = ((counter + pulled) % channels) as u16;
+}
+
+/// Pulls `S` a block at a time and hands the samples out one by one, see
+/// the [module docs](self).
+pub struct PullBlocks<S> {
+    inner: S,
+    block: Box<[Sample]>,
+    /// The next sample to hand out
+    pos: usize,
+    /// Samples in `block`, only short once `inner` ended
+    len: usize,
+}
+
+impl<S: Block> PullBlocks<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            block: vec![0.0; BLOCK_LEN].into_boxed_slice(),
+            pos: 0,
+            len: 0,
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Samples already pulled out of inner but not yet handed out are lost
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: Block> Iterator for PullBlocks<S> {
+    type Item = Sample;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos == self.len {
+            self.len = self.inner.next_block(&mut self.block);
+            self.pos = 0;
+        }
+        let sample = self.block[..self.len].get(self.pos).copied();
+        self.pos += usize::from(sample.is_some());
+        sample
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.inner.size_hint();
+        let left = self.len - self.pos;
+        (
+            lower.saturating_add(left),
+            upper.and_then(|upper| upper.checked_add(left)),
+        )
+    }
+}
+
+impl<S: Block> Block for PullBlocks<S> {
+    fn next_block(&mut self, out: &mut [Sample]) -> usize {
+        let left = &self.block[self.pos..self.len];
+        let copied = left.len().min(out.len());
+        out[..copied].copy_from_slice(&left[..copied]);
+        self.pos += copied;
+        copied + self.inner.next_block(&mut out[copied..])
+    }
+}
+
+impl<S: FixedSource + Block> FixedSource for PullBlocks<S> {
+    fn channels(&self) -> ChannelCount {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> SampleRate {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rodio::nz;
+
+    use super::*;
+    use crate::ConstSource;
+    use crate::const_source::limiter::LimitSettings;
+    use crate::fixed_source::FixedSourceExt;
+    use crate::fixed_source::amplify::Factor;
+    use crate::fixed_source::buffer::SamplesBuffer;
+    use crate::fixed_source::queue::uniform::UniformQueue;
+
+    fn ramp(channels: u16, samples: usize) -> SamplesBuffer {
+        let data: Vec<_> = (0..samples).map(|i| (i % 1000) as f32 / 1000.0).collect();
+        SamplesBuffer::new(channels.try_into().unwrap(), nz!(44100), data)
+    }
+
+    /// The player's output chain. The periodic accesses change the volume so
+    /// they must happen on the same samples in both paths.
+    fn player_like_chain() -> impl FixedSource + Block {
+        let (queue, handle) = UniformQueue::new(nz!(2), nz!(44100));
+        for samples in [1001, 4410, 2] {
+            let mut calls = 0;
+            let song = ramp(1, samples)
+                .with_channel_count(nz!(2))
+                .amplify(Factor::Linear(1.0))
+                .periodic_access(Duration::from_millis(3), move |amplify| {
+                    calls += 1;
+                    amplify.set_factor(Factor::Linear(1.0 / calls as f32));
+                })
+                .stoppable();
+            handle.add(song).unwrap();
+        }
+        let mut calls = 0;
+        queue
+            .pausable(false)
+            // loud enough for the limiter to kick in
+            .amplify(Factor::Linear(1.5))
+            .try_into_const_source::<44100, 2>()
+            .unwrap()
+            .balance(0.3)
+            .periodic_access(Duration::from_millis(2), move |balance| {
+                calls += 1;
+                balance.set_balance((calls % 10) as f32 / 10.0);
+                balance
+                    .inner_mut()
+                    .inner_mut()
+                    .inner_mut()
+                    .set_paused(calls % 7 == 0);
+            })
+            .limit(LimitSettings::default())
+            .into_fixed_source()
+    }
+
+    fn bits(samples: impl IntoIterator<Item = Sample>) -> Vec<u32> {
+        samples.into_iter().map(f32::to_bits).collect()
+    }
+
+    fn in_blocks(mut source: impl Block, block_len: usize, total: usize) -> Vec<Sample> {
+        let mut out = Vec::new();
+        let mut block = vec![0.0; block_len];
+        while out.len() < total {
+            let want = block_len.min(total - out.len());
+            let pulled = source.next_block(&mut block[..want]);
+            out.extend_from_slice(&block[..pulled]);
+            if pulled < want {
+                break;
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn blocks_match_the_per_sample_path() {
+        const TOTAL: usize = 20_000;
+        let expected = bits(player_like_chain().take(TOTAL));
+        for block_len in [1, 2, 7, 64, BLOCK_LEN, 3000] {
+            let got = bits(in_blocks(player_like_chain(), block_len, TOTAL));
+            assert_eq!(got, expected, "block length {block_len}");
+        }
+        let pulled = bits(player_like_chain().pull_blocks().take(TOTAL));
+        assert_eq!(pulled, expected);
+    }
+
+    #[test]
+    fn converters_match_the_per_sample_path() {
+        let stereo_to_mono = || ramp(2, 10_000).with_channel_count(nz!(1));
+        let mono_to_surround = || ramp(1, 10_001).with_channel_count(nz!(6));
+        let resampled = || ramp(2, 10_000).with_sample_rate(nz!(48000));
+        for block_len in [1, 5, BLOCK_LEN] {
+            assert_eq!(
+                bits(in_blocks(stereo_to_mono(), block_len, usize::MAX)),
+                bits(stereo_to_mono())
+            );
+            assert_eq!(
+                bits(in_blocks(mono_to_surround(), block_len, usize::MAX)),
+                bits(mono_to_surround())
+            );
+            assert_eq!(
+                bits(in_blocks(resampled(), block_len, usize::MAX)),
+                bits(resampled())
+            );
+        }
+    }
+
+    #[test]
+    fn sources_end_the_same() {
+        let source = || ramp(2, 1001).amplify(Factor::Linear(0.5)).stoppable();
+        assert_eq!(bits(in_blocks(source(), 64, usize::MAX)), bits(source()));
+        let mut pulled = source().pull_blocks();
+        assert_eq!(pulled.by_ref().count(), 1001);
+        assert_eq!(pulled.next(), None);
+        assert_eq!(pulled.next_block(&mut [0.0; 8]), 0);
+    }
+}
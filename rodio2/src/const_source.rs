@@ -7,24 +7,39 @@ use rodio::FixedSource;
 use rodio::Sample;
 use rodio::SampleRate;
 use rodio::Source as DynamicSource; // will be renamed to this upstream
+use rodio::source::SeekError;
 
 // pub mod adaptor; replaced with into_fixed_source and into_const_source
+pub mod balance;
+pub mod buffer;
+pub mod buffered;
 pub mod conversions;
+pub mod limiter;
 pub mod list;
+pub mod loudness;
 pub mod mixer;
 pub mod periodic_access;
 pub mod queue;
 pub mod take;
+pub mod trim_silence;
 
 pub mod signal_generator;
 pub use signal_generator::{SawtoothWave, SineWave, SquareWave, TriangleWave};
 
 use periodic_access::PeriodicAccess;
 
+use crate::block::Block;
+use crate::const_source::balance::Balance;
+use crate::const_source::buffer::SamplesBuffer;
+use crate::const_source::buffered::Buffered;
 use crate::const_source::conversions::channelcount::ChannelConvertor;
+use crate::const_source::limiter::{LimitSettings, Limiter};
+use crate::const_source::loudness::{LoudnessHandle, LoudnessMeter};
 use crate::const_source::periodic_access::WithData;
 use crate::const_source::take::TakeDuration;
 use crate::const_source::take::TakeSamples;
+use crate::const_source::trim_silence::TrimSilence;
+use crate::fixed_source::amplify::Factor;
 
 pub trait ConstSource<const SR: u32, const CH: u16>: Iterator<Item = Sample> {
     fn sample_rate(&self) -> SampleRate {
@@ -37,6 +52,13 @@ pub trait ConstSource<const SR: u32, const CH: u16>: Iterator<Item = Sample> {
     /// This value is free to change at any time
     fn total_duration(&self) -> Option<Duration>;
 
+    /// Most sources can not seek yet
+    fn try_seek(&mut self, _pos: Duration) -> Result<(), SeekError> {
+        Err(SeekError::NotSupported {
+            underlying_source: std::any::type_name::<Self>(),
+        })
+    }
+
     fn into_dynamic_source(self) -> ConstSourceAdaptor<SR, CH, Self>
     where
         Self: Sized,
@@ -74,15 +96,81 @@ pub trait ConstSource<const SR: u32, const CH: u16>: Iterator<Item = Sample> {
         TakeDuration::new(self, duration)
     }
 
-    fn periodic_access(
+    fn periodic_access<F>(self, call_every: Duration, access: F) -> PeriodicAccess<SR, CH, Self, F>
+    where
+        Self: Sized,
+        F: FnMut(&mut Self) + Send,
+    {
+        periodic_access::PeriodicAccess::new(self, call_every, access)
+    }
+
+    /// Soft limits the source so it never goes over `settings.threshold`.
+    /// Use this after mixing or amplifying to prevent clipping.
+    fn limit(self, settings: LimitSettings) -> Limiter<SR, CH, Self>
+    where
+        Self: Sized,
+    {
+        Limiter::new(self, settings)
+    }
+
+    /// Pans a stereo source, see [`Balance`]. Fails to compile for any
+    /// other channel count.
+    fn balance(self, balance: f32) -> Balance<SR, CH, Self>
+    where
+        Self: Sized,
+    {
+        Balance::new(self, balance)
+    }
+
+    /// Plays the source into memory. Handy to inspect what it produces or
+    /// to play it more then once.
+    fn collect_buffer(self) -> SamplesBuffer<SR, CH>
+    where
+        Self: Sized,
+    {
+        SamplesBuffer::new(self.collect::<Vec<_>>())
+    }
+
+    /// Renders the source to a 32 bit float WAV file, see
+    /// [`crate::dev_tools::write_wav`].
+    #[cfg(feature = "dev-tools")]
+    fn write_wav(self, path: impl AsRef<std::path::Path>) -> Result<(), hound::Error>
+    where
+        Self: Sized,
+    {
+        crate::dev_tools::write_wav_const(self, path)
+    }
+
+    /// Reads ahead `buffer` worth of audio on a separate thread, see
+    /// [`Buffered`].
+    fn buffered(self, buffer: Duration) -> Buffered<SR, CH>
+    where
+        Self: Sized + Send + 'static,
+    {
+        Buffered::new(self, buffer)
+    }
+
+    /// Measures the loudness of everything that passes through. See
+    /// [`LoudnessMeter`].
+    fn measure_loudness(self) -> (LoudnessMeter<SR, CH, Self>, LoudnessHandle)
+    where
+        Self: Sized,
+    {
+        LoudnessMeter::new(self)
+    }
+
+    /// Skips silence at the start and, if `trim_end` is set, at the end.
+    /// See [`TrimSilence`] for details.
+    fn trim_silence(
         self,
-        call_every: Duration,
-        arg: fn(&mut Self),
-    ) -> PeriodicAccess<SR, CH, Self>
+        threshold: Factor,
+        min_silence: Duration,
+        trim_end: bool,
+    ) -> TrimSilence<SR, CH, Self>
     where
         Self: Sized,
     {
-        periodic_access::PeriodicAccess::new(self, call_every, arg)
+        TrimSilence::new(self, threshold, min_silence, trim_end)
     }
 
     fn with_data<D>(self, data: D) -> WithData<SR, CH, Self, D>
@@ -110,6 +198,24 @@ where
     fn next(&mut self) -> Option<Self::Item> {
         self.inner.next()
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<const SR: u32, const CH: u16, S> ExactSizeIterator for ConstSourceAdaptor<SR, CH, S> where
+    S: ConstSource<SR, CH> + ExactSizeIterator
+{
+}
+
+impl<const SR: u32, const CH: u16, S> Block for ConstSourceAdaptor<SR, CH, S>
+where
+    S: ConstSource<SR, CH> + Block,
+{
+    fn next_block(&mut self, out: &mut [Sample]) -> usize {
+        self.inner.next_block(out)
+    }
 }
 
 impl<const SR: u32, const CH: u16, S> FixedSource for ConstSourceAdaptor<SR, CH, S>
@@ -148,12 +254,20 @@ where
     fn total_duration(&self) -> Option<std::time::Duration> {
         self.inner.total_duration()
     }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)
+    }
 }
 
 impl<const SR: u32, const CH: u16> ConstSource<SR, CH> for Box<dyn ConstSource<SR, CH>> {
     fn total_duration(&self) -> Option<Duration> {
         self.as_ref().total_duration()
     }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.as_mut().try_seek(pos)
+    }
 }
 
 pub trait CollectConstSource<const SR: u32, const CH: u16, const N: usize, S>
@@ -169,7 +283,7 @@ where
     S: ConstSource<SR, CH>,
 {
     fn collect_mixed(self) -> mixer::UniformArrayMixer<SR, CH, N, S> {
-        mixer::UniformArrayMixer { sources: self }
+        mixer::UniformArrayMixer::new(self)
     }
     fn collect_list(self) -> list::UniformArrayList<SR, CH, N, S> {
         list::UniformArrayList {
@@ -0,0 +1,172 @@
+use std::f32::consts::{FRAC_PI_4, SQRT_2};
+use std::time::Duration;
+
+use rodio::Sample;
+
+use crate::{Block, ConstSource};
+
+/// Shifts a stereo source towards the left or right speaker.
+///
+/// Uses constant power panning scaled such that a balance of zero leaves the
+/// source untouched. Fully to one side mutes the other channel and raises the
+/// remaining one by 3 dB.
+pub struct Balance<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> {
+    inner: S,
+    balance: f32,
+    left_gain: f32,
+    right_gain: f32,
+    next_is_left: bool,
+}
+
+/// Left and right gain for a balance in -1.0..=1.0
+fn gains(balance: f32) -> (f32, f32) {
+    if balance == 0.0 {
+        // the formula below is only approximately one here
+        return (1.0, 1.0);
+    }
+    let angle = (balance + 1.0) * FRAC_PI_4;
+    let (sin, cos) = angle.sin_cos();
+    // cos(π/2) ends up just below zero in f32
+    (SQRT_2 * cos.max(0.0), SQRT_2 * sin.max(0.0))
+}
+
+impl<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> Balance<SR, CH, S> {
+    pub(crate) fn new(source: S, balance: f32) -> Self {
+        const { assert!(CH == 2, "Balance only works on stereo sources") };
+        let mut this = Self {
+            inner: source,
+            balance: 0.0,
+            left_gain: 1.0,
+            right_gain: 1.0,
+            next_is_left: true,
+        };
+        this.set_balance(balance);
+        this
+    }
+
+    /// -1.0 is fully left, 0.0 is centered and 1.0 fully right. Values
+    /// outside that range are clamped.
+    pub fn set_balance(&mut self, balance: f32) {
+        self.balance = balance.clamp(-1.0, 1.0);
+        (self.left_gain, self.right_gain) = gains(self.balance);
+    }
+
+    pub fn balance(&self) -> f32 {
+        self.balance
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> ConstSource<SR, CH>
+    for Balance<SR, CH, S>
+{
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+impl<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> Iterator for Balance<SR, CH, S> {
+    type Item = Sample;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let sample = self.inner.next()?;
+        let gain = if self.next_is_left {
+            self.left_gain
+        } else {
+            self.right_gain
+        };
+        self.next_is_left = !self.next_is_left;
+        Some(sample * gain)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<const SR: u32, const CH: u16, S> ExactSizeIterator for Balance<SR, CH, S> where
+    S: ConstSource<SR, CH> + ExactSizeIterator
+{
+}
+
+impl<const SR: u32, const CH: u16, S> Block for Balance<SR, CH, S>
+where
+    S: ConstSource<SR, CH> + Block,
+{
+    fn next_block(&mut self, out: &mut [Sample]) -> usize {
+        let pulled = self.inner.next_block(out);
+        let gains = if self.next_is_left {
+            [self.left_gain, self.right_gain]
+        } else {
+            [self.right_gain, self.left_gain]
+        };
+        for pair in out[..pulled].chunks_mut(2) {
+            for (sample, gain) in pair.iter_mut().zip(gains) {
+                *sample *= gain;
+            }
+        }
+        if pulled % 2 == 1 {
+            self.next_is_left = !self.next_is_left;
+        }
+        pulled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::nz;
+
+    use crate::ConstSource;
+    use crate::const_source::SineWave;
+    use crate::fixed_source::FixedSourceExt;
+    use crate::fixed_source::buffer::SamplesBuffer;
+
+    fn stereo_sine() -> Vec<f32> {
+        SineWave::<44100>::new(440.0)
+            .take(441)
+            .flat_map(|s| [s, 0.5 * s])
+            .collect()
+    }
+
+    fn balanced(samples: Vec<f32>, balance: f32) -> Vec<f32> {
+        SamplesBuffer::new(nz!(2), nz!(44100), samples)
+            .try_into_const_source::<44100, 2>()
+            .unwrap()
+            .balance(balance)
+            .collect()
+    }
+
+    #[test]
+    fn centered_is_pass_through() {
+        let input = stereo_sine();
+        let output = balanced(input.clone(), 0.0);
+        assert_eq!(
+            input.iter().map(|s| s.to_bits()).collect::<Vec<_>>(),
+            output.iter().map(|s| s.to_bits()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn fully_to_one_side_mutes_the_other() {
+        let input = stereo_sine();
+
+        let left = balanced(input.clone(), -1.0);
+        assert!(left.iter().skip(1).step_by(2).all(|s| *s == 0.0));
+        assert!(left.iter().step_by(2).any(|s| *s != 0.0));
+
+        let right = balanced(input, 1.0);
+        assert!(right.iter().step_by(2).all(|s| *s == 0.0));
+        assert!(right.iter().skip(1).step_by(2).any(|s| *s != 0.0));
+    }
+}
@@ -0,0 +1,122 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use rodio::Sample;
+
+use crate::ConstSource;
+
+/// A buffer of samples treated as a source. Cheap to clone.
+#[derive(Debug, Clone)]
+pub struct SamplesBuffer<const SR: u32, const CH: u16> {
+    data: Arc<[Sample]>,
+    pos: usize,
+}
+
+/// The samples given to a [`SamplesBuffer`] end halfway through a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("Sources may not emit half frames, got {samples} samples for {channels} channels")]
+pub struct HalfFrame {
+    pub samples: usize,
+    pub channels: u16,
+}
+
+impl<const SR: u32, const CH: u16> SamplesBuffer<SR, CH> {
+    /// For samples from a source, which never emits half frames. See
+    /// [`SamplesBuffer::try_new`] for anything else.
+    ///
+    /// # Panics
+    ///
+    /// If `data` does not contain a whole number of frames.
+    pub fn new(data: impl Into<Vec<Sample>>) -> Self {
+        match Self::try_new(data) {
+            Ok(buffer) => buffer,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    pub fn try_new(data: impl Into<Vec<Sample>>) -> Result<Self, HalfFrame> {
+        let data: Arc<[Sample]> = data.into().into();
+        if !data.len().is_multiple_of(CH as usize) {
+            return Err(HalfFrame {
+                samples: data.len(),
+                channels: CH,
+            });
+        }
+        Ok(Self { data, pos: 0 })
+    }
+
+    /// All the samples, including those already played
+    pub fn samples(&self) -> &[Sample] {
+        &self.data
+    }
+}
+
+impl<const SR: u32, const CH: u16> ConstSource<SR, CH> for SamplesBuffer<SR, CH> {
+    fn total_duration(&self) -> Option<Duration> {
+        let frames = (self.data.len() / CH as usize) as f64;
+        Some(Duration::from_secs_f64(frames / SR as f64))
+    }
+}
+
+impl<const SR: u32, const CH: u16> Iterator for SamplesBuffer<SR, CH> {
+    type Item = Sample;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let sample = self.data.get(self.pos)?;
+        self.pos += 1;
+        Some(*sample)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.data.len() - self.pos;
+        (left, Some(left))
+    }
+}
+
+impl<const SR: u32, const CH: u16> ExactSizeIterator for SamplesBuffer<SR, CH> {}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{HalfFrame, SamplesBuffer};
+    use crate::ConstSource;
+    use crate::const_source::SineWave;
+
+    #[test]
+    fn collect_buffer_replays_the_source() {
+        let buffer = SineWave::<1000>::new(50.0)
+            .with_channel_count::<2>()
+            .take_samples(500)
+            .collect_buffer();
+        assert_eq!(buffer.len(), 500);
+        assert_eq!(buffer.total_duration(), Some(Duration::from_millis(250)));
+
+        let expected: Vec<_> = SineWave::<1000>::new(50.0)
+            .take(250)
+            .flat_map(|s| [s, s])
+            .collect();
+        assert_eq!(buffer.samples(), expected);
+        assert_eq!(buffer.clone().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn half_frames_and_bad_frequencies_are_errors() {
+        let half = SamplesBuffer::<44100, 2>::try_new(vec![0.0; 3]).unwrap_err();
+        assert_eq!(
+            half,
+            HalfFrame {
+                samples: 3,
+                channels: 2
+            }
+        );
+        assert!(SamplesBuffer::<44100, 2>::try_new(vec![0.0; 4]).is_ok());
+
+        for frequency in [0.0, -440.0, f32::NAN] {
+            assert!(SineWave::<44100>::try_new(frequency).is_err());
+        }
+        assert!(SineWave::<44100>::try_new(440.0).is_ok());
+    }
+}
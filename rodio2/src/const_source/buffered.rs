@@ -0,0 +1,337 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, mpsc};
+use std::thread;
+use std::time::Duration;
+
+use rodio::Sample;
+use rodio::source::SeekError;
+
+use crate::ConstSource;
+
+/// How long the worker sleeps when the ring is full
+const FULL_BACKOFF: Duration = Duration::from_millis(5);
+
+/// Single producer single consumer ring of samples. The producer only ever
+/// publishes whole frames.
+struct Ring {
+    /// f32 bits, atomics so both sides can access them without locking
+    samples: Box<[AtomicU32]>,
+    /// Total samples ever written/read. The difference is what is in the
+    /// ring, modulo the length gives the index.
+    written: AtomicUsize,
+    read: AtomicUsize,
+}
+
+impl Ring {
+    fn new(len: usize) -> Self {
+        Self {
+            samples: (0..len).map(|_| AtomicU32::new(0)).collect(),
+            written: AtomicUsize::new(0),
+            read: AtomicUsize::new(0),
+        }
+    }
+
+    /// Only call from the producer. Returns false if the frame does not fit.
+    fn push_frame(&self, frame: &[Sample]) -> bool {
+        let written = self.written.load(Ordering::Relaxed);
+        let read = self.read.load(Ordering::Acquire);
+        if written - read + frame.len() > self.samples.len() {
+            return false;
+        }
+        for (offset, sample) in frame.iter().enumerate() {
+            let idx = (written + offset) % self.samples.len();
+            self.samples[idx].store(sample.to_bits(), Ordering::Relaxed);
+        }
+        self.written.store(written + frame.len(), Ordering::Release);
+        true
+    }
+
+    /// Only call from the consumer
+    fn pop(&self) -> Option<Sample> {
+        let read = self.read.load(Ordering::Relaxed);
+        let written = self.written.load(Ordering::Acquire);
+        if read == written {
+            return None;
+        }
+        let bits = self.samples[read % self.samples.len()].load(Ordering::Relaxed);
+        self.read.store(read + 1, Ordering::Release);
+        Some(Sample::from_bits(bits))
+    }
+
+    /// Number of samples waiting to be read
+    fn len(&self) -> usize {
+        self.written.load(Ordering::Acquire) - self.read.load(Ordering::Relaxed)
+    }
+}
+
+enum Control {
+    /// Replies with the write position right after seeking, everything
+    /// before it is from the old position.
+    Seek(Duration, mpsc::Sender<Result<usize, SeekError>>),
+}
+
+struct Shared {
+    ring: Ring,
+    /// The inner source has ended, set after its last frame is in the ring
+    finished: AtomicBool,
+}
+
+/// Decodes ahead on a separate thread.
+///
+/// The inner source is moved to a worker thread that keeps a ring buffer
+/// filled. Reading from the ring never blocks or locks. If the worker can not
+/// keep up the buffer plays silence until it catches up, every time that
+/// happens [`Buffered::underruns`] goes up by one. Seeking blocks until the
+/// worker has seeked the inner source. The worker stops once this is dropped.
+pub struct Buffered<const SR: u32, const CH: u16> {
+    shared: Arc<Shared>,
+    control: mpsc::Sender<Control>,
+    total_duration: Option<Duration>,
+    /// Samples of silence left to emit to finish the current frame
+    silence_left: u16,
+    /// Position in the current frame
+    channel: u16,
+    starving: bool,
+    underruns: Arc<AtomicU64>,
+}
+
+impl<const SR: u32, const CH: u16> Buffered<SR, CH> {
+    pub(crate) fn new<S>(source: S, buffer: Duration) -> Self
+    where
+        S: ConstSource<SR, CH> + Send + 'static,
+    {
+        let frames = ((buffer.as_secs_f64() * SR as f64) as usize).max(1);
+        let shared = Arc::new(Shared {
+            ring: Ring::new(frames * CH as usize),
+            finished: AtomicBool::new(false),
+        });
+        let (control, control_rx) = mpsc::channel();
+        let total_duration = source.total_duration();
+
+        let worker_shared = Arc::clone(&shared);
+        thread::Builder::new()
+            .name("buffered-source".to_string())
+            .spawn(move || fill(source, &worker_shared, &control_rx))
+            .expect("should be able to spawn threads");
+
+        Self {
+            shared,
+            control,
+            total_duration,
+            silence_left: 0,
+            channel: 0,
+            starving: false,
+            underruns: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// How many times the buffer ran empty before the source ended
+    pub fn underruns(&self) -> u64 {
+        self.underruns.load(Ordering::Relaxed)
+    }
+
+    /// Counts underruns in `counter` instead, so the underruns of many
+    /// sources can be added up while they play on the audio thread
+    pub fn count_underruns_in(mut self, counter: Arc<AtomicU64>) -> Self {
+        self.underruns = counter;
+        self
+    }
+}
+
+/// Runs on the worker thread until the [`Buffered`] is dropped
+fn fill<const SR: u32, const CH: u16, S>(
+    mut source: S,
+    shared: &Shared,
+    control: &mpsc::Receiver<Control>,
+) where
+    S: ConstSource<SR, CH>,
+{
+    let mut frame = Vec::with_capacity(CH as usize);
+    loop {
+        let message = if shared.finished.load(Ordering::Relaxed) {
+            // nothing left to do untill someone seeks
+            control.recv().map_err(|_| mpsc::TryRecvError::Disconnected)
+        } else {
+            control.try_recv()
+        };
+        match message {
+            Ok(Control::Seek(pos, reply)) => {
+                frame.clear();
+                let result = source.try_seek(pos).map(|()| {
+                    shared.finished.store(false, Ordering::Relaxed);
+                    shared.ring.written.load(Ordering::Relaxed)
+                });
+                let _ = reply.send(result);
+            }
+            Err(mpsc::TryRecvError::Disconnected) => return,
+            Err(mpsc::TryRecvError::Empty) => (),
+        }
+
+        if frame.is_empty() {
+            frame.extend(source.by_ref().take(CH as usize));
+            if frame.is_empty() {
+                shared.finished.store(true, Ordering::Release);
+                continue;
+            }
+        }
+        if shared.ring.push_frame(&frame) {
+            frame.clear();
+        } else {
+            thread::sleep(FULL_BACKOFF);
+        }
+    }
+}
+
+impl<const SR: u32, const CH: u16> ConstSource<SR, CH> for Buffered<SR, CH> {
+    fn total_duration(&self) -> Option<Duration> {
+        self.total_duration
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        let (reply, reply_rx) = mpsc::channel();
+        self.control
+            .send(Control::Seek(pos, reply))
+            .expect("worker only stops once we are dropped");
+        let seeked_at = reply_rx
+            .recv()
+            .expect("worker only stops once we are dropped")?;
+
+        // drop everything from before the seek
+        self.shared.ring.read.store(seeked_at, Ordering::Release);
+        // the ring only holds whole frames, finish a partially played one
+        // with silence to keep the channels in order
+        self.silence_left = (CH - self.channel) % CH;
+        Ok(())
+    }
+}
+
+impl<const SR: u32, const CH: u16> Iterator for Buffered<SR, CH> {
+    type Item = Sample;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.silence_left > 0 {
+            self.silence_left -= 1;
+            self.channel = (self.channel + 1) % CH;
+            return Some(0.0);
+        }
+
+        if let Some(sample) = self.shared.ring.pop() {
+            self.starving = false;
+            self.channel = (self.channel + 1) % CH;
+            return Some(sample);
+        }
+
+        // The worker only publishes whole frames so an empty ring means we
+        // are on a frame boundary.
+        if self.shared.finished.load(Ordering::Acquire) {
+            // the last frames could have come in after we looked
+            if let Some(sample) = self.shared.ring.pop() {
+                self.channel = (self.channel + 1) % CH;
+                return Some(sample);
+            }
+            return None;
+        }
+
+        if !self.starving {
+            self.starving = true;
+            self.underruns.fetch_add(1, Ordering::Relaxed);
+        }
+        self.silence_left = CH - 1;
+        self.channel = (self.channel + 1) % CH;
+        Some(0.0)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let buffered = self.shared.ring.len() + self.silence_left as usize;
+        (buffered, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use rodio::Sample;
+    use rodio::source::SeekError;
+
+    use crate::ConstSource;
+
+    /// Counts up from one, frame by frame, sleeping every now and then
+    struct Slow {
+        next: u32,
+        end: u32,
+        sleep_every: u32,
+    }
+
+    impl ConstSource<1000, 2> for Slow {
+        fn total_duration(&self) -> Option<Duration> {
+            None
+        }
+
+        fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+            self.next = (pos.as_millis() as u32 * 2).min(self.end);
+            Ok(())
+        }
+    }
+
+    impl Iterator for Slow {
+        type Item = Sample;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            if self.next == self.end {
+                return None;
+            }
+            if self.next.is_multiple_of(self.sleep_every) {
+                thread::sleep(Duration::from_millis(10));
+            }
+            self.next += 1;
+            Some(self.next as f32)
+        }
+    }
+
+    #[test]
+    fn resumes_after_underruns() {
+        let slow = Slow {
+            next: 0,
+            end: 2000,
+            sleep_every: 400,
+        };
+        let mut buffered = slow.buffered(Duration::from_millis(50));
+
+        let mut played = Vec::new();
+        let mut silent_frames = 0;
+        while let Some(left) = buffered.next() {
+            let right = buffered.next().expect("only whole frames");
+            if left == 0.0 {
+                assert_eq!(right, 0.0, "silence comes in whole frames");
+                silent_frames += 1;
+            } else {
+                played.extend([left, right]);
+            }
+        }
+
+        let expected: Vec<_> = (1..=2000).map(|n| n as f32).collect();
+        assert_eq!(played, expected);
+        assert!(silent_frames > 0);
+        assert!(buffered.underruns() > 0);
+    }
+
+    #[test]
+    fn seek_flushes_the_buffer() {
+        let slow = Slow {
+            next: 0,
+            end: 2000,
+            sleep_every: u32::MAX,
+        };
+        let mut buffered = slow.buffered(Duration::from_millis(100));
+        while buffered.next() == Some(0.0) {}
+        // stop mid frame, seek should keep the channels in order
+        buffered.try_seek(Duration::from_millis(500)).unwrap();
+
+        assert_eq!(buffered.next(), Some(0.0), "finishes the frame");
+        let mut after_seek = buffered.by_ref().skip_while(|s| *s == 0.0);
+        assert_eq!(after_seek.next(), Some(1001.0));
+        assert_eq!(after_seek.next(), Some(1002.0));
+    }
+}
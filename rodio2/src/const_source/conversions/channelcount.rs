@@ -69,4 +69,19 @@ impl<const SR: u32, const CH_IN: u16, const CH_OUT: u16, S: ConstSource<SR, CH_I
         }
         result
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // Not exact in the middle of a frame, the upper bound allows for
+        // the rest of the current frame.
+        let in_frames = |samples: usize| samples / CH_IN as usize;
+        let (lower, upper) = self.input.size_hint();
+        (
+            in_frames(lower).saturating_mul(CH_OUT as usize),
+            upper.map(|upper| {
+                in_frames(upper)
+                    .saturating_add(1)
+                    .saturating_mul(CH_OUT as usize)
+            }),
+        )
+    }
 }
@@ -0,0 +1,256 @@
+use std::time::Duration;
+
+use rodio::Sample;
+
+use crate::fixed_source::amplify::Factor;
+use crate::{Block, ConstSource};
+
+/// Gains this close to one are treated as one so a source that stopped
+/// clipping goes back to being passed through unchanged.
+const UNITY_EPSILON: f32 = 1e-6;
+
+/// How far below the threshold compression starts
+const KNEE_WIDTH: Factor = Factor::Decibel(-1.0);
+
+#[derive(Debug, Clone, Copy)]
+pub struct LimitSettings {
+    /// No sample will be louder then this. Compression starts 1 dB below it
+    /// and increases smoothly towards the threshold.
+    pub threshold: Factor,
+    /// How quickly the gain is lowered when the signal gets too loud.
+    pub attack: Duration,
+    /// How quickly the gain recovers once the signal gets quieter.
+    pub release: Duration,
+}
+
+impl Default for LimitSettings {
+    fn default() -> Self {
+        Self {
+            threshold: Factor::Decibel(-1.0),
+            attack: Duration::from_millis(5),
+            release: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Soft knee limiter without lookahead.
+///
+/// The gain follows a smoothed envelope. Since there is no lookahead it is
+/// additionally capped at what keeps the current frame below the threshold.
+/// The same gain is applied to every sample in a frame to keep the channels
+/// balanced.
+pub struct Limiter<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> {
+    inner: S,
+    threshold: f32,
+    knee_start: f32,
+    attack: f32,
+    release: f32,
+    gain: f32,
+    frame: Vec<Sample>,
+    next_in_frame: usize,
+}
+
+/// Coefficient for a one pole smoother that gets ~63% of the way in `time`
+fn smoothing_coefficient<const SR: u32>(time: Duration) -> f32 {
+    let frames = time.as_secs_f32() * SR as f32;
+    if frames <= 1.0 {
+        1.0
+    } else {
+        1.0 - (-1.0 / frames).exp()
+    }
+}
+
+impl<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> Limiter<SR, CH, S> {
+    pub(crate) fn new(source: S, settings: LimitSettings) -> Self {
+        let threshold = settings.threshold.as_linear();
+        Self {
+            inner: source,
+            threshold,
+            knee_start: threshold * KNEE_WIDTH.as_linear(),
+            attack: smoothing_coefficient::<SR>(settings.attack),
+            release: smoothing_coefficient::<SR>(settings.release),
+            gain: 1.0,
+            frame: Vec::with_capacity(CH as usize),
+            next_in_frame: 0,
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// The gain needed to bring `peak` down onto the soft knee curve
+    fn target_gain(&self, peak: f32) -> f32 {
+        if peak <= self.knee_start {
+            return 1.0;
+        }
+        let knee_width = self.threshold - self.knee_start;
+        let limited = self.knee_start + knee_width * ((peak - self.knee_start) / knee_width).tanh();
+        limited / peak
+    }
+
+    fn next_frame(&mut self) -> Option<()> {
+        self.frame.clear();
+        self.next_in_frame = 0;
+        self.frame.extend(self.inner.by_ref().take(CH as usize));
+        if self.frame.is_empty() {
+            return None;
+        }
+        let mut frame = std::mem::take(&mut self.frame);
+        self.limit_frame(&mut frame);
+        self.frame = frame;
+        Some(())
+    }
+
+    /// Moves the envelope on by one frame and applies it
+    fn limit_frame(&mut self, frame: &mut [Sample]) {
+        let peak = frame.iter().fold(0f32, |peak, s| peak.max(s.abs()));
+        let target = self.target_gain(peak);
+        let coefficient = if target < self.gain {
+            self.attack
+        } else {
+            self.release
+        };
+        self.gain += (target - self.gain) * coefficient;
+        if self.gain > 1.0 - UNITY_EPSILON {
+            self.gain = 1.0;
+        }
+
+        // Without lookahead the envelope can lag behind, never let that push
+        // this frame over the threshold.
+        let gain = self.gain.min(target);
+        if gain != 1.0 {
+            for sample in frame {
+                *sample *= gain;
+            }
+        }
+    }
+}
+
+impl<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> ConstSource<SR, CH>
+    for Limiter<SR, CH, S>
+{
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+impl<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> Iterator for Limiter<SR, CH, S> {
+    type Item = Sample;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next_in_frame == self.frame.len() {
+            self.next_frame()?;
+        }
+        let sample = self.frame[self.next_in_frame];
+        self.next_in_frame += 1;
+        Some(sample)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.inner.size_hint();
+        let buffered = self.frame.len() - self.next_in_frame;
+        (
+            lower.saturating_add(buffered),
+            upper.and_then(|upper| upper.checked_add(buffered)),
+        )
+    }
+}
+
+impl<const SR: u32, const CH: u16, S> Block for Limiter<SR, CH, S>
+where
+    S: ConstSource<SR, CH> + Block,
+{
+    /// Limits whole frames in place in `out`
+    fn next_block(&mut self, out: &mut [Sample]) -> usize {
+        let mut written = 0;
+        while self.next_in_frame < self.frame.len() && written < out.len() {
+            out[written] = self.frame[self.next_in_frame];
+            self.next_in_frame += 1;
+            written += 1;
+        }
+
+        let whole = (out.len() - written) / CH as usize * CH as usize;
+        let pulled = self.inner.next_block(&mut out[written..written + whole]);
+        // a source ending halfway through a frame gets that part limited too
+        for frame in out[written..written + pulled].chunks_mut(CH as usize) {
+            self.limit_frame(frame);
+        }
+        written += pulled;
+        if pulled < whole {
+            return written;
+        }
+
+        while written < out.len() {
+            match self.next() {
+                Some(sample) => out[written] = sample,
+                None => break,
+            }
+            written += 1;
+        }
+        written
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::nz;
+
+    use super::LimitSettings;
+    use crate::ConstSource;
+    use crate::const_source::SineWave;
+    use crate::fixed_source::FixedSourceExt;
+    use crate::fixed_source::buffer::SamplesBuffer;
+
+    #[test]
+    fn loud_sine_stays_below_threshold() {
+        let settings = LimitSettings::default();
+        let threshold = settings.threshold.as_linear();
+        let loud: Vec<_> = SineWave::<44100>::new(440.0)
+            .map(|s| s * 2.0)
+            .take(44100)
+            .collect();
+        let limited: Vec<_> = SamplesBuffer::new(nz!(1), nz!(44100), loud)
+            .try_into_const_source::<44100, 1>()
+            .unwrap()
+            .limit(settings)
+            .collect();
+
+        let peak = limited.iter().fold(0f32, |peak, s| peak.max(s.abs()));
+        assert!(peak <= threshold, "peak: {peak}");
+
+        // the unlimited sine moves at most 2 * 2π * 440 / 44100 ≈ 0.125 per
+        // sample, a hard gain jump would show up as a bigger step
+        let biggest_step = limited
+            .windows(2)
+            .map(|w| (w[1] - w[0]).abs())
+            .fold(0f32, f32::max);
+        assert!(biggest_step < 0.15, "biggest step: {biggest_step}");
+    }
+
+    #[test]
+    fn quiet_signal_is_bit_exact() {
+        let quiet: Vec<_> = SineWave::<44100>::new(440.0)
+            // -2.5 dBFS, just under where the knee starts
+            .map(|s| s * 0.75)
+            .take(2 * 4410)
+            .collect();
+        let limited: Vec<_> = SamplesBuffer::new(nz!(2), nz!(44100), quiet.clone())
+            .try_into_const_source::<44100, 2>()
+            .unwrap()
+            .limit(LimitSettings::default())
+            .collect();
+        assert_eq!(
+            quiet.iter().map(|s| s.to_bits()).collect::<Vec<_>>(),
+            limited.iter().map(|s| s.to_bits()).collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,324 @@
+use std::f64::consts::PI;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rodio::Sample;
+
+use crate::ConstSource;
+
+/// Blocks are 400 ms long and overlap by 75%, so a new one starts every
+/// 100 ms. We sum per 100 ms and combine the last four sums into a block.
+const STEPS_PER_BLOCK: usize = 4;
+const STEPS_PER_SECOND: u32 = 10;
+const ABSOLUTE_GATE: f64 = -70.0; // LUFS
+const RELATIVE_GATE: f64 = -10.0; // LU
+
+/// Second order IIR filter, transposed direct form II
+#[derive(Debug, Clone, Copy)]
+struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    fn new(b0: f64, b1: f64, b2: f64, a1: f64, a2: f64) -> Self {
+        Self {
+            b0,
+            b1,
+            b2,
+            a1,
+            a2,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    /// The shelving part of the BS.1770 K-weighting, models the acoustic
+    /// effect of the head. The standard only lists coefficients for 48 kHz,
+    /// these are derived from the analog prototype so they work at any rate.
+    fn pre_filter(sample_rate: f64) -> Self {
+        let f0 = 1681.974450955533;
+        let gain_db = 3.999843853973347;
+        let q = 0.7071752369554196;
+
+        let k = (PI * f0 / sample_rate).tan();
+        let vh = 10f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        Self::new(
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+            2.0 * (k * k - 1.0) / a0,
+            (1.0 - k / q + k * k) / a0,
+        )
+    }
+
+    /// The high pass part of the BS.1770 K-weighting (RLB curve)
+    fn rlb_filter(sample_rate: f64) -> Self {
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+
+        let k = (PI * f0 / sample_rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        Self::new(
+            1.0,
+            -2.0,
+            1.0,
+            2.0 * (k * k - 1.0) / a0,
+            (1.0 - k / q + k * k) / a0,
+        )
+    }
+
+    fn process(&mut self, input: f64) -> f64 {
+        let output = self.b0 * input + self.z1;
+        self.z1 = self.b1 * input - self.a1 * output + self.z2;
+        self.z2 = self.b2 * input - self.a2 * output;
+        output
+    }
+}
+
+/// BS.1770 channel weight. Assumes the usual channel order, for 5.1 that is
+/// L R C LFE Ls Rs. The LFE channel is ignored and the surrounds count extra.
+fn channel_weight(channel: usize, channels: u16) -> f64 {
+    match (channels, channel) {
+        (6, 3) => 0.0,
+        (6, 4 | 5) => 1.41,
+        _ => 1.0,
+    }
+}
+
+fn loudness(mean_energy: f64) -> f64 {
+    -0.691 + 10.0 * mean_energy.log10()
+}
+
+#[derive(Debug, Default)]
+struct Measurements {
+    /// Weighted mean square of every complete 400 ms block
+    block_energies: Vec<f64>,
+    peak: f32,
+}
+
+/// Read what a [`LoudnessMeter`] measured so far. Keeps working after the
+/// meter is dropped.
+#[derive(Debug, Clone)]
+pub struct LoudnessHandle(Arc<Mutex<Measurements>>);
+
+impl LoudnessHandle {
+    /// The gated integrated loudness in LUFS. None if nothing above the
+    /// absolute gate (-70 LUFS) has been measured yet.
+    pub fn integrated(&self) -> Option<f32> {
+        let measurements = self.0.lock().expect("meter never panics holding the lock");
+        let above = |gate: f64| {
+            let (sum, count) = measurements
+                .block_energies
+                .iter()
+                .filter(|energy| loudness(**energy) > gate)
+                .fold((0.0, 0usize), |(sum, count), energy| {
+                    (sum + energy, count + 1)
+                });
+            (count > 0).then(|| sum / count as f64)
+        };
+
+        let relative_gate = loudness(above(ABSOLUTE_GATE)?) + RELATIVE_GATE;
+        let gated = above(relative_gate.max(ABSOLUTE_GATE))?;
+        Some(loudness(gated) as f32)
+    }
+
+    /// The largest absolute sample value seen. This is the sample peak,
+    /// the true (inter sample) peak can be a bit higher.
+    pub fn peak(&self) -> f32 {
+        self.0
+            .lock()
+            .expect("meter never panics holding the lock")
+            .peak
+    }
+}
+
+/// Passes the source through unchanged while measuring its loudness
+/// following EBU R128 (ITU-R BS.1770). Use the [`LoudnessHandle`] to read
+/// the results.
+///
+/// Only complete 400 ms blocks count towards the loudness, anything shorter
+/// then that measures as None.
+pub struct LoudnessMeter<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> {
+    inner: S,
+    /// K-weighting filters and weight for each channel
+    filters: Vec<([Biquad; 2], f64)>,
+    channel: usize,
+    /// Weighted sum of squares over the current 100 ms
+    step_sum: f64,
+    frames_in_step: u32,
+    /// Sums of the last few steps, oldest first
+    steps: [f64; STEPS_PER_BLOCK],
+    steps_seen: usize,
+    peak: f32,
+    measurements: Arc<Mutex<Measurements>>,
+}
+
+impl<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> LoudnessMeter<SR, CH, S> {
+    const FRAMES_PER_STEP: u32 = SR / STEPS_PER_SECOND;
+
+    pub fn new(source: S) -> (Self, LoudnessHandle) {
+        let filters = (0..CH as usize)
+            .map(|channel| {
+                (
+                    [Biquad::pre_filter(SR as f64), Biquad::rlb_filter(SR as f64)],
+                    channel_weight(channel, CH),
+                )
+            })
+            .collect();
+        let measurements = Arc::new(Mutex::new(Measurements::default()));
+        let meter = Self {
+            inner: source,
+            filters,
+            channel: 0,
+            step_sum: 0.0,
+            frames_in_step: 0,
+            steps: [0.0; STEPS_PER_BLOCK],
+            steps_seen: 0,
+            peak: 0.0,
+            measurements: Arc::clone(&measurements),
+        };
+        (meter, LoudnessHandle(measurements))
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn measure(&mut self, sample: Sample) {
+        self.peak = self.peak.max(sample.abs());
+
+        let ([pre, rlb], weight) = &mut self.filters[self.channel];
+        let filtered = rlb.process(pre.process(sample as f64));
+        self.step_sum += *weight * filtered * filtered;
+
+        self.channel += 1;
+        if self.channel < CH as usize {
+            return;
+        }
+        self.channel = 0;
+        self.frames_in_step += 1;
+        if self.frames_in_step == Self::FRAMES_PER_STEP {
+            self.finish_step();
+        }
+    }
+
+    #[cold]
+    fn finish_step(&mut self) {
+        self.steps.rotate_left(1);
+        self.steps[STEPS_PER_BLOCK - 1] = self.step_sum;
+        self.steps_seen += 1;
+        self.step_sum = 0.0;
+        self.frames_in_step = 0;
+
+        let mut measurements = self
+            .measurements
+            .lock()
+            .expect("handle never panics holding the lock");
+        measurements.peak = self.peak;
+        if self.steps_seen >= STEPS_PER_BLOCK {
+            let frames = (STEPS_PER_BLOCK as u32 * Self::FRAMES_PER_STEP) as f64;
+            let energy = self.steps.iter().sum::<f64>() / frames;
+            measurements.block_energies.push(energy);
+        }
+    }
+}
+
+impl<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> ConstSource<SR, CH>
+    for LoudnessMeter<SR, CH, S>
+{
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+impl<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> Iterator for LoudnessMeter<SR, CH, S> {
+    type Item = Sample;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Some(sample) = self.inner.next() else {
+            // the peak of a trailing partial step would otherwise be lost
+            self.measurements
+                .lock()
+                .expect("handle never panics holding the lock")
+                .peak = self.peak;
+            return None;
+        };
+        self.measure(sample);
+        Some(sample)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::nz;
+
+    use crate::ConstSource;
+    use crate::const_source::SineWave;
+    use crate::fixed_source::FixedSourceExt;
+    use crate::fixed_source::buffer::SamplesBuffer;
+
+    /// Stereo 1 kHz sine with a peak of `dbfs`, the signal used by
+    /// EBU Tech 3341.
+    fn sine(dbfs: f32, secs: usize) -> Vec<f32> {
+        let amplitude = 10f32.powf(dbfs / 20.0);
+        SineWave::<48000>::new(1000.0)
+            .take(secs * 48000)
+            .flat_map(|s| [s * amplitude; 2])
+            .collect()
+    }
+
+    fn measure(samples: Vec<f32>) -> (Option<f32>, f32) {
+        let (meter, handle) = SamplesBuffer::new(nz!(2), nz!(48000), samples)
+            .try_into_const_source::<48000, 2>()
+            .unwrap()
+            .measure_loudness();
+        meter.for_each(drop);
+        (handle.integrated(), handle.peak())
+    }
+
+    #[test]
+    fn tech_3341_sines() {
+        // test case 1 and 2 of EBU Tech 3341
+        for level in [-23.0, -33.0] {
+            let (loudness, peak) = measure(sine(level, 20));
+            let loudness = loudness.unwrap();
+            assert!((loudness - level).abs() < 0.1, "{level}: {loudness}");
+            assert!((peak - 10f32.powf(level / 20.0)).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn tech_3341_relative_gate() {
+        // test case 3: the quiet parts fall below the relative gate
+        let samples = [sine(-36.0, 10), sine(-23.0, 60), sine(-36.0, 10)].concat();
+        let loudness = measure(samples).0.unwrap();
+        assert!((loudness + 23.0).abs() < 0.1, "{loudness}");
+    }
+
+    #[test]
+    fn silence_has_no_loudness() {
+        assert_eq!(measure(sine(-200.0, 2)).0, None);
+        let too_short = sine(-23.0, 1)[..2 * 48000 * 3 / 10].to_vec();
+        assert_eq!(measure(too_short).0, None, "no complete blocks");
+    }
+}
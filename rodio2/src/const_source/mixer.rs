@@ -6,17 +6,81 @@ use rodio::Sample;
 use super::ConstSource;
 
 mod array;
+mod dynamic;
 mod mpsc;
 mod tuple;
 mod vec;
 
+pub use dynamic::{DynamicMixer, MixerDropped, MixerHandle, SourceId};
+pub use tuple::TupleMixer;
+pub use vec::{UniformVecMixer, VecMixer};
+
+/// Number of samples mixed in one go by the [`UniformArrayMixer`]
+const BLOCK: usize = 256;
+
 /// An optimal mixer that mixer `N` identical sources each with samplerate `SR`
 /// and channel count `CH`
+///
+/// Mixes a block of samples at the time, the per sample iterator overhead
+/// otherwise dominates.
 pub struct UniformArrayMixer<const SR: u32, const CH: u16, const N: usize, S>
 where
     S: ConstSource<SR, CH>,
 {
     pub(crate) sources: [S; N],
+    finished: [bool; N],
+    block: [Sample; BLOCK],
+    block_len: usize,
+    next_in_block: usize,
+}
+
+impl<const SR: u32, const CH: u16, const N: usize, S> UniformArrayMixer<SR, CH, N, S>
+where
+    S: ConstSource<SR, CH>,
+{
+    pub(crate) fn new(sources: [S; N]) -> Self {
+        Self {
+            sources,
+            finished: [false; N],
+            block: [0.0; BLOCK],
+            block_len: 0,
+            next_in_block: 0,
+        }
+    }
+
+    fn mix_block(&mut self) {
+        let mut any_mixed = false;
+        self.block_len = 0;
+
+        for (source, finished) in self.sources.iter_mut().zip(&mut self.finished) {
+            if *finished {
+                continue;
+            }
+
+            let mut buffer = [0.0; BLOCK];
+            let mut pulled = 0;
+            // zip takes from the buffer first so this never pulls a sample
+            // too many out of the source.
+            for (slot, sample) in buffer.iter_mut().zip(source.by_ref()) {
+                *slot = sample;
+                pulled += 1;
+            }
+            // The rest of the buffer stays zero. Sources can not restart so
+            // we skip this one from now on.
+            *finished = pulled < BLOCK;
+            self.block_len = self.block_len.max(pulled);
+
+            if any_mixed {
+                for (mixed, sample) in self.block.iter_mut().zip(buffer) {
+                    *mixed += sample;
+                }
+            } else {
+                self.block = buffer;
+                any_mixed = true;
+            }
+        }
+        self.next_in_block = 0;
+    }
 }
 
 impl<const SR: u32, const CH: u16, const N: usize, S> ConstSource<SR, CH>
@@ -39,7 +103,16 @@ where
     type Item = Sample;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.sources.iter_mut().filter_map(|s| s.next()).sum1()
+        if self.next_in_block == self.block_len {
+            self.mix_block();
+            if self.block_len == 0 {
+                return None;
+            }
+        }
+
+        let sample = self.block[self.next_in_block];
+        self.next_in_block += 1;
+        Some(sample)
     }
 }
 
@@ -68,3 +141,33 @@ where
 
 // Same restriction as with ConstMix requires us to have two different Mixers
 pub struct ConstMixer<const SR: u32, const CH: u16, T>(T);
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+
+    use super::UniformArrayMixer;
+    use crate::ConstSource;
+    use crate::const_source::SineWave;
+
+    #[test]
+    fn block_mixing_matches_per_sample_mixing() {
+        // lengths chosen to end before, on and after a block boundary
+        let sources = || {
+            [100, 256, 300, 1000, 517]
+                .map(|len: u64| SineWave::<44100>::new(len as f32).take_samples(len))
+        };
+
+        let naive: Vec<_> = {
+            let mut sources = sources();
+            std::iter::from_fn(|| sources.iter_mut().filter_map(|s| s.next()).sum1()).collect()
+        };
+        let blocked: Vec<_> = UniformArrayMixer::new(sources()).collect();
+
+        assert_eq!(naive.len(), 1000);
+        assert_eq!(blocked.len(), naive.len());
+        for (idx, (a, b)) in naive.iter().zip(&blocked).enumerate() {
+            assert_eq!(a.to_bits(), b.to_bits(), "sample {idx} differs");
+        }
+    }
+}
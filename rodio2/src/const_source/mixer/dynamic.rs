@@ -0,0 +1,183 @@
+use rodio::Sample;
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, mpsc};
+use std::time::Duration;
+
+use super::super::ConstSource;
+
+type BoxedSource<const SR: u32, const CH: u16> = Box<dyn ConstSource<SR, CH> + Send>;
+
+enum Control<const SR: u32, const CH: u16> {
+    Add(SourceId, BoxedSource<SR, CH>),
+    Remove(SourceId),
+}
+
+/// A mixer to which sources can be added and from which they can be removed
+/// while it is playing. Use the [`MixerHandle`] returned by
+/// [`new`](DynamicMixer::new) for that.
+///
+/// Changes take effect at the next frame boundary. Sources that run out are
+/// removed automatically. The mixer itself never ends, it plays silence when
+/// it has no sources.
+pub struct DynamicMixer<const SR: u32, const CH: u16> {
+    sources: Vec<(SourceId, BoxedSource<SR, CH>)>,
+    control: mpsc::Receiver<Control<SR, CH>>,
+    /// kept between zero and `CH`
+    sample_counter: u16,
+}
+
+pub struct MixerHandle<const SR: u32, const CH: u16> {
+    mixer_id: u32,
+    next_id: Arc<AtomicU32>,
+    tx: mpsc::Sender<Control<SR, CH>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceId {
+    pub mixer_id: u32,
+    pub source_id: u32,
+}
+
+#[derive(Debug)]
+pub struct MixerDropped;
+
+impl<const SR: u32, const CH: u16> DynamicMixer<SR, CH> {
+    pub fn new() -> (Self, MixerHandle<SR, CH>) {
+        static MIXER_ID: AtomicU32 = AtomicU32::new(0);
+
+        let mixer_id = MIXER_ID.fetch_add(1, Ordering::Relaxed);
+        assert!(mixer_id < u32::MAX, "Can not create 4 billion mixers");
+
+        let (tx, rx) = mpsc::channel();
+        (
+            Self {
+                sources: Vec::with_capacity(10),
+                control: rx,
+                sample_counter: 0,
+            },
+            MixerHandle {
+                mixer_id,
+                next_id: Arc::new(AtomicU32::new(0)),
+                tx,
+            },
+        )
+    }
+
+    #[cold]
+    fn handle_control(&mut self, control: Control<SR, CH>) {
+        match control {
+            // this may allocate, same as for the ReceiverMixer
+            Control::Add(id, source) => self.sources.push((id, source)),
+            Control::Remove(id) => {
+                if let Some(idx) = self.sources.iter().position(|(s_id, _)| *s_id == id) {
+                    self.sources.swap_remove(idx);
+                }
+            }
+        }
+    }
+
+    /// Applies queued adds and removes then mixes the first sample of the
+    /// frame. Sources only end on a frame boundary so this is where we
+    /// reap them.
+    fn start_frame(&mut self) -> Sample {
+        while let Ok(control) = self.control.try_recv() {
+            self.handle_control(control);
+        }
+
+        let mut sum = 0.0;
+        self.sources.retain_mut(|(_, source)| {
+            if let Some(sample) = source.next() {
+                sum += sample;
+                true
+            } else {
+                false
+            }
+        });
+        sum
+    }
+}
+
+impl<const SR: u32, const CH: u16> MixerHandle<SR, CH> {
+    pub fn add(
+        &self,
+        source: impl ConstSource<SR, CH> + Send + 'static,
+    ) -> Result<SourceId, MixerDropped> {
+        // wraps on overflow, should be okay as long as there are < 4 million
+        // sources in the mixer.
+        let id = SourceId {
+            mixer_id: self.mixer_id,
+            source_id: self.next_id.fetch_add(1, Ordering::Relaxed),
+        };
+        self.tx
+            .send(Control::Add(id, Box::new(source)))
+            .map_err(|_| MixerDropped)?;
+        Ok(id)
+    }
+
+    /// Stops mixing in the source with this id. Does nothing if that source
+    /// already finished.
+    pub fn remove(&self, id: SourceId) -> Result<(), MixerDropped> {
+        self.tx.send(Control::Remove(id)).map_err(|_| MixerDropped)
+    }
+}
+
+impl<const SR: u32, const CH: u16> ConstSource<SR, CH> for DynamicMixer<SR, CH> {
+    fn total_duration(&self) -> Option<Duration> {
+        None // endless
+    }
+}
+
+impl<const SR: u32, const CH: u16> Iterator for DynamicMixer<SR, CH> {
+    type Item = Sample;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let sum = if self.sample_counter == 0 {
+            self.start_frame()
+        } else {
+            self.sources.iter_mut().filter_map(|(_, s)| s.next()).sum()
+        };
+
+        self.sample_counter += 1;
+        self.sample_counter %= CH;
+        Some(sum)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::nz;
+
+    use super::DynamicMixer;
+    use crate::ConstSource;
+    use crate::fixed_source::FixedSourceExt;
+    use crate::fixed_source::buffer::SamplesBuffer;
+
+    fn constant(value: f32, frames: usize) -> impl ConstSource<44100, 2> + Send {
+        SamplesBuffer::new(nz!(2), nz!(44100), vec![value; frames * 2])
+            .try_into_const_source::<44100, 2>()
+            .unwrap()
+    }
+
+    #[test]
+    fn add_remove_and_reap() {
+        let (mut mixer, handle) = DynamicMixer::<44100, 2>::new();
+        assert_eq!(mixer.next(), Some(0.0), "empty mixer plays silence");
+        assert_eq!(mixer.next(), Some(0.0));
+
+        let long = handle.add(constant(0.25, 100)).unwrap();
+        handle.add(constant(0.5, 2)).unwrap();
+        for _ in 0..4 {
+            assert_eq!(mixer.next(), Some(0.75));
+        }
+        // the short source ran out and gets reaped
+        assert_eq!(mixer.next(), Some(0.25));
+        assert_eq!(mixer.sources.len(), 1);
+
+        // removal waits for the frame to finish
+        handle.remove(long).unwrap();
+        assert_eq!(mixer.next(), Some(0.25));
+        assert_eq!(mixer.next(), Some(0.0));
+        assert!(mixer.sources.is_empty());
+    }
+}
@@ -29,18 +29,29 @@ impl<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> ConstMix<SR, CH> for
 }
 
 impl<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> ReceiverMixer<SR, CH, S> {
-    fn update_active(&mut self) {
-        if self.sample_counter == 0 {
-            // is kept between zero and `CH`
-            // TODO perf, benchmark if checking an atomic makes sense.
-            // Or only doing this once every few samples
-            if let Ok(new) = self.rx.try_recv() {
-                // this may allocate, I hate that. We may be able to do that on
-                // the sender thread but that requires a dedicated sender struct
-                // and that is not as elegant... (a perfectly fine reason)
-                self.active.push(new);
-            }
+    /// Takes in new sources, then mixes the first sample of the frame. Sources
+    /// only end on a frame boundary so this is also where finished ones are
+    /// dropped.
+    fn start_frame(&mut self) -> f64 {
+        // TODO perf, benchmark if checking an atomic makes sense.
+        // Or only doing this once every few samples
+        while let Ok(new) = self.rx.try_recv() {
+            // this may allocate, I hate that. We may be able to do that on
+            // the sender thread but that requires a dedicated sender struct
+            // and that is not as elegant... (a perfectly fine reason)
+            self.active.push(new);
         }
+
+        let mut sum = 0f64;
+        self.active.retain_mut(|source| {
+            if let Some(sample) = source.next() {
+                sum += sample as f64;
+                true
+            } else {
+                false
+            }
+        });
+        sum
     }
 }
 
@@ -59,18 +70,77 @@ impl<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> Iterator for Receiver
     type Item = Sample;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.update_active();
-
         // accumulate into f64 to prevent overflow
-        let (sum, n_mixed) = self
-            .active
-            .iter_mut()
-            .filter_map(|s| s.next())
-            .map(|s| (s as f64, 1))
-            .reduce(|(sum, summed), (sample, _)| (sum + sample, summed + 1))?;
+        let sum = if self.sample_counter == 0 {
+            self.start_frame()
+        } else {
+            self.active
+                .iter_mut()
+                .filter_map(|s| s.next())
+                .map(|s| s as f64)
+                .sum()
+        };
+
+        if self.active.is_empty() {
+            return None;
+        }
 
+        // is kept between zero and `CH`
         self.sample_counter += 1;
         self.sample_counter %= CH;
-        Some((sum / n_mixed as f64) as f32)
+        Some(sum as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use rodio::nz;
+
+    use crate::ConstSource;
+    use crate::const_source::mixer::ConstMix;
+    use crate::fixed_source::FixedSourceExt;
+    use crate::fixed_source::buffer::SamplesBuffer;
+
+    fn constant(value: f32, frames: usize) -> impl ConstSource<44100, 2> {
+        SamplesBuffer::new(nz!(2), nz!(44100), vec![value; frames * 2])
+            .try_into_const_source::<44100, 2>()
+            .unwrap()
+    }
+
+    fn next_frame(mixer: &mut impl Iterator<Item = f32>) -> Option<f32> {
+        let frame = [mixer.next(), mixer.next()];
+        assert_eq!(frame[0], frame[1]);
+        frame[0]
+    }
+
+    #[test]
+    fn staggered_sources_are_summed_and_reaped() {
+        let (tx, rx) = mpsc::channel();
+        tx.send(constant(0.25, 4)).unwrap();
+        let mut mixer = rx.mix();
+
+        assert_eq!(next_frame(&mut mixer), Some(0.25));
+        assert_eq!(next_frame(&mut mixer), Some(0.25));
+
+        tx.send(constant(0.5, 4)).unwrap();
+        assert_eq!(next_frame(&mut mixer), Some(0.75));
+        assert_eq!(next_frame(&mut mixer), Some(0.75));
+        assert_eq!(mixer.active.len(), 2);
+
+        // the first source ends as the third starts, the second one must not
+        // get louder because of that
+        tx.send(constant(0.125, 4)).unwrap();
+        assert_eq!(next_frame(&mut mixer), Some(0.625));
+        assert_eq!(mixer.active.len(), 2);
+        assert_eq!(next_frame(&mut mixer), Some(0.625));
+
+        assert_eq!(next_frame(&mut mixer), Some(0.125));
+        assert_eq!(mixer.active.len(), 1);
+        assert_eq!(next_frame(&mut mixer), Some(0.125));
+
+        assert_eq!(mixer.next(), None);
+        assert!(mixer.active.is_empty());
     }
 }
@@ -1,46 +1,104 @@
+use itertools::Itertools;
 use rodio::Sample;
 use std::time::Duration;
 
 use super::super::ConstSource;
 use super::ConstMix;
-use super::ConstMixer;
-
-// TODO generate more with a macro, take care to move accumulator type to f64
-// when appropriate. Also benchmark to proof that makes sense.
-impl<const SR: u32, const CH: u16, S1: ConstSource<SR, CH>, S2: ConstSource<SR, CH>>
-    ConstMix<SR, CH> for (S1, S2)
-{
-    type Mixer = ConstMixer<SR, CH, Self>;
-    fn mix(self) -> ConstMixer<SR, CH, Self>
-    where
-        Self: Sized,
-    {
-        ConstMixer(self)
-    }
-}
 
-impl<const SR: u32, const CH: u16, S1: ConstSource<SR, CH>, S2: ConstSource<SR, CH>>
-    ConstSource<SR, CH> for ConstMixer<SR, CH, (S1, S2)>
-{
-    fn total_duration(&self) -> Option<Duration> {
-        self.0
-            .0
-            .total_duration()
-            .and_then(|a| self.0.1.total_duration().map(|b| a.max(b)))
-    }
+/// Mixes the sources in a tuple by summing them. Can be created by calling
+/// `mix()` on a tuple of two up to twelve sources.
+pub struct TupleMixer<const SR: u32, const CH: u16, T>(T);
+
+// TODO take care to move accumulator type to f64 when appropriate. Also
+// benchmark to proof that makes sense.
+macro_rules! impl_tuple_mix {
+    ($($S:ident $idx:tt),+) => {
+        impl<const SR: u32, const CH: u16, $($S: ConstSource<SR, CH>),+> ConstMix<SR, CH>
+            for ($($S,)+)
+        {
+            type Mixer = TupleMixer<SR, CH, Self>;
+            fn mix(self) -> TupleMixer<SR, CH, Self>
+            where
+                Self: Sized,
+            {
+                TupleMixer(self)
+            }
+        }
+
+        impl<const SR: u32, const CH: u16, $($S: ConstSource<SR, CH>),+> ConstSource<SR, CH>
+            for TupleMixer<SR, CH, ($($S,)+)>
+        {
+            fn total_duration(&self) -> Option<Duration> {
+                [$(self.0.$idx.total_duration()),+]
+                    .into_iter()
+                    .fold_options(Duration::ZERO, |longest, dur| longest.max(dur))
+            }
+        }
+
+        impl<const SR: u32, const CH: u16, $($S: ConstSource<SR, CH>),+> Iterator
+            for TupleMixer<SR, CH, ($($S,)+)>
+        {
+            type Item = Sample;
+
+            fn next(&mut self) -> Option<Self::Item> {
+                [$(self.0.$idx.next()),+]
+                    .into_iter()
+                    .flatten()
+                    .reduce(|sum, sample| sum + sample)
+            }
+        }
+    };
 }
 
-impl<const SR: u32, const CH: u16, S1: ConstSource<SR, CH>, S2: ConstSource<SR, CH>> Iterator
-    for ConstMixer<SR, CH, (S1, S2)>
-{
-    type Item = Sample;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        let (sum, counted) = [self.0.0.next(), self.0.1.next()]
-            .into_iter()
-            .flatten()
-            .map(|s| (s, 1))
-            .reduce(|(sum, summed), (sample, _)| (sum + sample, summed + 1))?;
-        Some(sum / counted as f32)
+impl_tuple_mix!(S1 0, S2 1);
+impl_tuple_mix!(S1 0, S2 1, S3 2);
+impl_tuple_mix!(S1 0, S2 1, S3 2, S4 3);
+impl_tuple_mix!(S1 0, S2 1, S3 2, S4 3, S5 4);
+impl_tuple_mix!(S1 0, S2 1, S3 2, S4 3, S5 4, S6 5);
+impl_tuple_mix!(S1 0, S2 1, S3 2, S4 3, S5 4, S6 5, S7 6);
+impl_tuple_mix!(S1 0, S2 1, S3 2, S4 3, S5 4, S6 5, S7 6, S8 7);
+impl_tuple_mix!(S1 0, S2 1, S3 2, S4 3, S5 4, S6 5, S7 6, S8 7, S9 8);
+impl_tuple_mix!(S1 0, S2 1, S3 2, S4 3, S5 4, S6 5, S7 6, S8 7, S9 8, S10 9);
+impl_tuple_mix!(S1 0, S2 1, S3 2, S4 3, S5 4, S6 5, S7 6, S8 7, S9 8, S10 9, S11 10);
+impl_tuple_mix!(S1 0, S2 1, S3 2, S4 3, S5 4, S6 5, S7 6, S8 7, S9 8, S10 9, S11 10, S12 11);
+
+#[cfg(test)]
+mod tests {
+    use rodio::nz;
+
+    use crate::ConstSource;
+    use crate::const_source::mixer::ConstMix;
+    use crate::const_source::signal_generator::{SineWave, SquareWave};
+    use crate::fixed_source::FixedSourceExt;
+    use crate::fixed_source::buffer::SamplesBuffer;
+
+    #[test]
+    fn sums_differently_typed_sources() {
+        let sine = SineWave::<44100>::new(440.0);
+        let square = SquareWave::<44100>::new(440.0);
+        let buffer = SamplesBuffer::new(nz!(1), nz!(44100), vec![0.1, 0.2, 0.3])
+            .try_into_const_source::<44100, 1>()
+            .unwrap();
+
+        let mut mixed = (sine.clone(), square.clone(), buffer).mix();
+        assert_eq!(mixed.total_duration(), None, "sine and square are endless");
+
+        let expected: Vec<_> = sine
+            .zip(square)
+            .map(|(a, b)| a + b)
+            .zip([0.1, 0.2, 0.3].into_iter().chain(std::iter::repeat(0.0)))
+            .map(|(a, b)| a + b)
+            .take(100)
+            .collect();
+        let got: Vec<_> = mixed.by_ref().take(100).collect();
+
+        for idx in [0, 1, 2, 3, 50, 99] {
+            assert!(
+                (got[idx] - expected[idx]).abs() < 1e-6,
+                "sample {idx}: got {}, expected {}",
+                got[idx],
+                expected[idx]
+            );
+        }
     }
 }
@@ -6,23 +6,32 @@ use std::time::Duration;
 use super::super::ConstSource;
 use super::ConstMix;
 
+/// Mixes the sources by summing them, like the other mixers. A source that
+/// ends is dropped without changing the level of the rest.
+///
 /// Need to hold the vec from the user so they can not modify it once we start
 /// iterating (since then they could mess up the channel inverleaving by
 /// introducing a new source mid frame
-pub struct VecMixer<const SR: u32, const CH: u16, S: ConstSource<SR, CH>>(Vec<S>);
+pub struct UniformVecMixer<const SR: u32, const CH: u16, S: ConstSource<SR, CH>>(Vec<S>);
+
+/// Mixes differently typed sources. This is the [`UniformVecMixer`] over boxed
+/// sources, a separate `ConstMix` impl would overlap with the one for `Vec<S>`
+/// since `Box<dyn ConstSource>` is a `ConstSource` too.
+pub type VecMixer<const SR: u32, const CH: u16> =
+    UniformVecMixer<SR, CH, Box<dyn ConstSource<SR, CH>>>;
 
 impl<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> ConstMix<SR, CH> for Vec<S> {
-    type Mixer = VecMixer<SR, CH, S>;
+    type Mixer = UniformVecMixer<SR, CH, S>;
     fn mix(self) -> Self::Mixer
     where
         Self: Sized,
     {
-        VecMixer(self)
+        UniformVecMixer(self)
     }
 }
 
 impl<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> ConstSource<SR, CH>
-    for VecMixer<SR, CH, S>
+    for UniformVecMixer<SR, CH, S>
 {
     fn total_duration(&self) -> Option<Duration> {
         self.0
@@ -32,17 +41,59 @@ impl<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> ConstSource<SR, CH>
     }
 }
 
-impl<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> Iterator for VecMixer<SR, CH, S> {
+impl<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> Iterator for UniformVecMixer<SR, CH, S> {
     type Item = Sample;
 
     fn next(&mut self) -> Option<Self::Item> {
         // accumulate into f64 to prevent overflow
-        let (sum, n_mixed) = self
-            .0
-            .iter_mut()
-            .filter_map(|s| s.next())
-            .map(|s| (s as f64, 1))
-            .reduce(|(sum, summed), (sample, _)| (sum + sample, summed + 1))?;
-        Some((sum / n_mixed as f64) as f32)
+        let mut sum = 0f64;
+        let mut idx = 0;
+        while idx < self.0.len() {
+            if let Some(sample) = self.0[idx].next() {
+                sum += sample as f64;
+                idx += 1;
+            } else {
+                // Sources only end on a frame boundary, so this does not mess
+                // up the interleaving. The source moved into `idx` still needs
+                // to be polled.
+                self.0.swap_remove(idx);
+            }
+        }
+
+        if self.0.is_empty() {
+            None
+        } else {
+            Some(sum as f32)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::nz;
+
+    use crate::ConstSource;
+    use crate::const_source::mixer::ConstMix;
+    use crate::fixed_source::FixedSourceExt;
+    use crate::fixed_source::buffer::SamplesBuffer;
+
+    fn constant(value: f32, samples: usize) -> impl ConstSource<44100, 1> {
+        SamplesBuffer::new(nz!(1), nz!(44100), vec![value; samples])
+            .try_into_const_source::<44100, 1>()
+            .unwrap()
+    }
+
+    #[test]
+    fn finished_sources_are_dropped() {
+        let mut mixer = vec![constant(0.125, 1), constant(0.25, 3), constant(0.5, 2)].mix();
+        assert_eq!(mixer.0.len(), 3);
+        assert_eq!(mixer.next(), Some(0.875));
+        // the others keep their level
+        assert_eq!(mixer.next(), Some(0.75));
+        assert_eq!(mixer.0.len(), 2);
+        assert_eq!(mixer.next(), Some(0.25));
+        assert_eq!(mixer.0.len(), 1);
+        assert_eq!(mixer.next(), None);
+        assert!(mixer.0.is_empty());
     }
 }
@@ -3,50 +3,115 @@ use std::time::Duration;
 use rodio::Sample;
 
 use crate::ConstSource;
+use crate::block::{self, Block};
 
-pub struct PeriodicAccess<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> {
+pub struct PeriodicAccess<const SR: u32, const CH: u16, S: ConstSource<SR, CH>, F> {
     inner: S,
-    access: fn(&mut S),
-    update_period: u32, // in samples
-    samples_until_update: u32,
+    access: F,
+    update_period: u32, // in frames
+    frames_until_update: u32,
+    /// kept between zero and the channel count
+    sample_counter: u16,
 }
 
-impl<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> PeriodicAccess<SR, CH, S> {
-    pub(crate) fn new(source: S, update_period: Duration, access: fn(&mut S)) -> Self {
-        let update_period = 1.0 / update_period.as_secs_f64() * SR as f64;
+impl<const SR: u32, const CH: u16, S, F> PeriodicAccess<SR, CH, S, F>
+where
+    S: ConstSource<SR, CH>,
+    F: FnMut(&mut S) + Send,
+{
+    pub(crate) fn new(source: S, update_period: Duration, access: F) -> Self {
+        let update_period = update_period.as_secs_f64() * SR as f64;
         Self {
             inner: source,
             access,
-            update_period: update_period as u32,
-            samples_until_update: 0,
+            update_period: (update_period as u32).max(1),
+            // zero so the first access happens right away
+            frames_until_update: 0,
+            sample_counter: 0,
         }
     }
 
     #[cold]
     fn do_access(&mut self) {
         (self.access)(&mut self.inner);
-        self.samples_until_update = self.update_period;
+        self.frames_until_update = self.update_period;
     }
 }
 
-impl<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> ConstSource<SR, CH>
-    for PeriodicAccess<SR, CH, S>
+impl<const SR: u32, const CH: u16, S, F> ConstSource<SR, CH> for PeriodicAccess<SR, CH, S, F>
+where
+    S: ConstSource<SR, CH>,
+    F: FnMut(&mut S) + Send,
 {
     fn total_duration(&self) -> Option<std::time::Duration> {
         self.inner.total_duration()
     }
 }
 
-impl<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> Iterator for PeriodicAccess<SR, CH, S> {
+impl<const SR: u32, const CH: u16, S, F> Iterator for PeriodicAccess<SR, CH, S, F>
+where
+    S: ConstSource<SR, CH>,
+    F: FnMut(&mut S) + Send,
+{
     type Item = Sample;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.samples_until_update > self.update_period {
-            self.do_access(); // separate fn so we can hint this branch is cold
+        if self.sample_counter == 0 {
+            if self.frames_until_update == 0 {
+                self.do_access(); // separate fn so we can hint this branch is cold
+            }
+            self.frames_until_update -= 1;
+        }
+        self.sample_counter += 1;
+        if self.sample_counter == CH {
+            self.sample_counter = 0;
         }
 
         self.inner.next()
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<const SR: u32, const CH: u16, S, F> ExactSizeIterator for PeriodicAccess<SR, CH, S, F>
+where
+    S: ConstSource<SR, CH> + ExactSizeIterator,
+    F: FnMut(&mut S) + Send,
+{
+}
+
+impl<const SR: u32, const CH: u16, S, F> Block for PeriodicAccess<SR, CH, S, F>
+where
+    S: ConstSource<SR, CH> + Block,
+    F: FnMut(&mut S) + Send,
+{
+    /// Pulls from inner in runs that end where the next access is due
+    fn next_block(&mut self, out: &mut [Sample]) -> usize {
+        let mut written = 0;
+        while written < out.len() {
+            if self.sample_counter == 0 && self.frames_until_update == 0 {
+                self.do_access();
+            }
+            let run = block::until_access(self.sample_counter, self.frames_until_update, CH)
+                .min(out.len() - written);
+            let pulled = self.inner.next_block(&mut out[written..written + run]);
+            written += pulled;
+            // the call to `next` that found the end counts too
+            let calls = pulled + usize::from(pulled < run);
+            block::advance(
+                &mut self.sample_counter,
+                &mut self.frames_until_update,
+                CH,
+                calls,
+            );
+            if pulled < run {
+                break;
+            }
+        }
+        written
+    }
 }
 
 pub struct WithData<const SR: u32, const CH: u16, S: ConstSource<SR, CH>, D> {
@@ -68,4 +133,47 @@ impl<const SR: u32, const CH: u16, S: ConstSource<SR, CH>, D> Iterator for WithD
     fn next(&mut self) -> Option<Self::Item> {
         self.inner.next()
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<const SR: u32, const CH: u16, S, D> ExactSizeIterator for WithData<SR, CH, S, D> where
+    S: ConstSource<SR, CH> + ExactSizeIterator
+{
+}
+
+impl<const SR: u32, const CH: u16, S, D> Block for WithData<SR, CH, S, D>
+where
+    S: ConstSource<SR, CH> + Block,
+{
+    fn next_block(&mut self, out: &mut [Sample]) -> usize {
+        self.inner.next_block(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use crate::ConstSource;
+    use crate::const_source::SineWave;
+
+    #[test]
+    fn access_is_called_every_period() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let source = SineWave::<44100>::new(440.0)
+            .with_channel_count::<2>()
+            .take_samples(3 * 2 * 44100)
+            .periodic_access(Duration::from_millis(50), move |_| {
+                counter.fetch_add(1, Ordering::Relaxed);
+            });
+
+        assert_eq!(source.count(), 3 * 2 * 44100);
+        assert_eq!(calls.load(Ordering::Relaxed), 60);
+    }
 }
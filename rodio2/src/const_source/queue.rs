@@ -1,14 +1,26 @@
+use std::num::NonZeroU16;
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, mpsc};
 
 use crate::ConstSource;
+use crate::frame::FramePosition;
 
 pub mod uniform;
 
+type Pending<const SR: u32, const CH: u16> = (
+    Box<dyn ConstSource<SR, CH>>,
+    u32,
+    Option<mpsc::Sender<SourceId>>,
+);
+
 pub struct Queue<const SR: u32, const CH: u16> {
+    queue_id: u32,
     current: Option<Box<dyn ConstSource<SR, CH>>>,
-    pending: mpsc::Receiver<(Box<dyn ConstSource<SR, CH>>, u32)>,
+    /// Notified once `current` has been fully consumed
+    current_done: Option<mpsc::Sender<SourceId>>,
+    pending: mpsc::Receiver<Pending<SR, CH>>,
     current_id: Arc<AtomicU32>,
+    frame: FramePosition,
 }
 
 impl<const SR: u32, const CH: u16> Queue<SR, CH> {
@@ -23,9 +35,14 @@ impl<const SR: u32, const CH: u16> Queue<SR, CH> {
 
         (
             Self {
+                queue_id,
                 current: None,
+                current_done: None,
                 pending: rx,
                 current_id: Arc::clone(&current_id),
+                frame: FramePosition::new(
+                    const { NonZeroU16::new(CH).expect("Channel count must be > 0") },
+                ),
             },
             QueueHandle {
                 queue_id,
@@ -35,15 +52,28 @@ impl<const SR: u32, const CH: u16> Queue<SR, CH> {
             },
         )
     }
+
+    #[cold]
+    fn current_finished(&mut self) {
+        self.current = None;
+        if let Some(done) = self.current_done.take() {
+            // the receiver might no longer care, that is fine
+            let _ = done.send(SourceId {
+                queue_id: self.queue_id,
+                source_id: self.current_id.load(Ordering::Relaxed),
+            });
+        }
+    }
 }
 
 pub struct QueueHandle<const SR: u32, const CH: u16> {
     queue_id: u32,
     next_id: Arc<AtomicU32>,
     current_id: Arc<AtomicU32>,
-    tx: mpsc::Sender<(Box<dyn ConstSource<SR, CH>>, u32)>,
+    tx: mpsc::Sender<Pending<SR, CH>>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct SourceId {
     pub queue_id: u32,
     pub source_id: u32,
@@ -54,11 +84,29 @@ pub struct QueueDropped;
 
 impl<const SR: u32, const CH: u16> QueueHandle<SR, CH> {
     pub fn add(&self, source: Box<dyn ConstSource<SR, CH>>) -> Result<SourceId, QueueDropped> {
+        self.push(source, None)
+    }
+
+    /// Like [`add`](Self::add) but sends the returned [`SourceId`] on `done`
+    /// once the queue has pulled the last sample out of `source`.
+    pub fn add_with_notify(
+        &self,
+        source: Box<dyn ConstSource<SR, CH>>,
+        done: mpsc::Sender<SourceId>,
+    ) -> Result<SourceId, QueueDropped> {
+        self.push(source, Some(done))
+    }
+
+    fn push(
+        &self,
+        source: Box<dyn ConstSource<SR, CH>>,
+        done: Option<mpsc::Sender<SourceId>>,
+    ) -> Result<SourceId, QueueDropped> {
         // wraps on overflow, should be okay as long as there are < 4 million
         // sources in the list.
         let source_id = self.next_id.fetch_add(1, Ordering::Relaxed);
         self.tx
-            .send((source, source_id))
+            .send((source, source_id, done))
             .map_err(|_| QueueDropped)?;
 
         Ok(SourceId {
@@ -86,20 +134,31 @@ impl<const SR: u32, const CH: u16> Iterator for Queue<SR, CH> {
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            if let Some(curr) = &mut self.current
-                && let Some(sample) = curr.next()
-            {
-                return Some(sample);
+            if let Some(curr) = &mut self.current {
+                if let Some(sample) = curr.next() {
+                    self.frame.advance();
+                    return Some(sample);
+                }
+                self.current_finished();
+            }
+
+            // a source that ended part way through a frame is padded with
+            // silence, starting the next one now would swap its channels
+            if !self.frame.at_frame_start() {
+                self.frame.advance();
+                return Some(0.0);
             }
 
             // No need to end the audio source when the queue handle drops
             // that should be handled with a `Stoppable` wrapper instead.
             let next = self.pending.try_recv().ok();
 
-            if let Some((source, id)) = next {
+            if let Some((source, id, done)) = next {
                 self.current = Some(source);
+                self.current_done = done;
                 self.current_id.store(id, Ordering::Relaxed);
             } else {
+                self.frame.advance();
                 return Some(0.0);
             }
         }
@@ -1,16 +1,24 @@
+use std::num::NonZeroU16;
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, mpsc};
 
 use crate::ConstSource;
+use crate::frame::FramePosition;
+
+type Pending<S> = (S, u32, Option<mpsc::Sender<SourceId>>);
 
 pub struct UniformQueue<const SR: u32, const CH: u16, S>
 where
     S: ConstSource<SR, CH>,
 {
+    queue_id: u32,
     current: Option<S>,
-    pending: mpsc::Receiver<(S, u32)>,
+    /// Notified once `current` has been fully consumed
+    current_done: Option<mpsc::Sender<SourceId>>,
+    pending: mpsc::Receiver<Pending<S>>,
     // zero means silence is 'playing'
     current_id: Arc<AtomicU32>,
+    frame: FramePosition,
 }
 
 impl<const SR: u32, const CH: u16, S> UniformQueue<SR, CH, S>
@@ -28,9 +36,14 @@ where
 
         (
             Self {
+                queue_id,
                 current: None,
+                current_done: None,
                 pending: rx,
                 current_id: Arc::clone(&current_id),
+                frame: FramePosition::new(
+                    const { NonZeroU16::new(CH).expect("Channel count must be > 0") },
+                ),
             },
             UniformQueueHandle {
                 queue_id,
@@ -40,6 +53,18 @@ where
             },
         )
     }
+
+    #[cold]
+    fn current_finished(&mut self) {
+        self.current = None;
+        if let Some(done) = self.current_done.take() {
+            // the receiver might no longer care, that is fine
+            let _ = done.send(SourceId {
+                queue_id: self.queue_id,
+                source_id: self.current_id.load(Ordering::Relaxed),
+            });
+        }
+    }
 }
 
 pub struct UniformQueueHandle<const SR: u32, const CH: u16, S>
@@ -49,9 +74,10 @@ where
     queue_id: u32,
     next_id: Arc<AtomicU32>,
     current_id: Arc<AtomicU32>,
-    tx: mpsc::Sender<(S, u32)>,
+    tx: mpsc::Sender<Pending<S>>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct SourceId {
     pub queue_id: u32,
     pub source_id: u32,
@@ -65,11 +91,29 @@ where
     S: ConstSource<SR, CH>,
 {
     pub fn add(&self, source: S) -> Result<SourceId, QueueDropped> {
+        self.push(source, None)
+    }
+
+    /// Like [`add`](Self::add) but sends the returned [`SourceId`] on `done`
+    /// once the queue has pulled the last sample out of `source`.
+    pub fn add_with_notify(
+        &self,
+        source: S,
+        done: mpsc::Sender<SourceId>,
+    ) -> Result<SourceId, QueueDropped> {
+        self.push(source, Some(done))
+    }
+
+    fn push(
+        &self,
+        source: S,
+        done: Option<mpsc::Sender<SourceId>>,
+    ) -> Result<SourceId, QueueDropped> {
         // wraps on overflow, should be okay as long as there are < 4 million
         // sources in the list.
         let source_id = self.next_id.fetch_add(1, Ordering::Relaxed);
         self.tx
-            .send((source, source_id))
+            .send((source, source_id, done))
             .map_err(|_| QueueDropped)?;
 
         Ok(SourceId {
@@ -103,20 +147,31 @@ where
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            if let Some(curr) = &mut self.current
-                && let Some(sample) = curr.next()
-            {
-                return Some(sample);
+            if let Some(curr) = &mut self.current {
+                if let Some(sample) = curr.next() {
+                    self.frame.advance();
+                    return Some(sample);
+                }
+                self.current_finished();
+            }
+
+            // a source that ended part way through a frame is padded with
+            // silence, starting the next one now would swap its channels
+            if !self.frame.at_frame_start() {
+                self.frame.advance();
+                return Some(0.0);
             }
 
             // No need to end the audio source when the queue handle drops
             // that should be handled with a `Stoppable` wrapper instead.
             let next = self.pending.try_recv().ok();
 
-            if let Some((source, id)) = next {
+            if let Some((source, id, done)) = next {
                 self.current = Some(source);
+                self.current_done = done;
                 self.current_id.store(id, Ordering::Relaxed);
             } else {
+                self.frame.advance();
                 return Some(0.0);
             }
         }
@@ -44,8 +44,25 @@ pub struct SignalGenerator<const SR: u32> {
     phase: f32,
 }
 
+/// The frequency of a [`SignalGenerator`] was not greater than zero
+#[derive(Debug, Clone, Copy, PartialEq, thiserror::Error)]
+#[error("frequency must be greater than zero, got: {0}")]
+pub struct InvalidFrequency(pub f32);
+
 impl<const SR: u32> SignalGenerator<SR> {
+    /// For constant frequencies, see [`SignalGenerator::try_new`].
+    ///
+    /// # Panics
+    ///
+    /// If `frequency` is not greater than zero.
     pub const fn new(frequency: f32, f: Function) -> Self {
+        match Self::try_new(frequency, f) {
+            Ok(generator) => generator,
+            Err(_) => panic!("frequency must be greater than zero"),
+        }
+    }
+
+    pub const fn try_new(frequency: f32, f: Function) -> Result<Self, InvalidFrequency> {
         let function: GeneratorFunction = match f {
             Function::Sine => sine_signal,
             Function::Triangle => triangle_signal,
@@ -53,20 +70,38 @@ impl<const SR: u32> SignalGenerator<SR> {
             Function::Sawtooth => sawtooth_signal,
         };
 
-        Self::with_function(frequency, function)
+        Self::try_with_function(frequency, function)
     }
 
+    /// For constant frequencies, see [`SignalGenerator::try_with_function`].
+    ///
+    /// # Panics
+    ///
+    /// If `frequency` is not greater than zero.
     pub const fn with_function(frequency: f32, generator_function: GeneratorFunction) -> Self {
-        assert!(frequency > 0.0, "frequency must be greater than zero");
+        match Self::try_with_function(frequency, generator_function) {
+            Ok(generator) => generator,
+            Err(_) => panic!("frequency must be greater than zero"),
+        }
+    }
+
+    pub const fn try_with_function(
+        frequency: f32,
+        generator_function: GeneratorFunction,
+    ) -> Result<Self, InvalidFrequency> {
+        // also catches NaN
+        if !(frequency > 0.0) {
+            return Err(InvalidFrequency(frequency));
+        }
         const { assert!(SR > 0, "Sample rate must be larger then zero") };
         let period = SR as f32 / frequency;
         let phase_step = 1.0f32 / period;
 
-        SignalGenerator {
+        Ok(SignalGenerator {
             function: generator_function,
             phase_step,
             phase: 0.0f32,
-        }
+        })
     }
 }
 
@@ -100,12 +135,23 @@ macro_rules! signal_new_type {
 
         impl<const SR: u32> $name<SR> {
             /// The frequency of the sine.
+            ///
+            /// # Panics
+            ///
+            /// If `freq` is not greater than zero, see `try_new`.
             #[inline]
             pub fn new(freq: f32) -> Self {
                 Self {
                     inner: SignalGenerator::new(freq, $function),
                 }
             }
+
+            #[inline]
+            pub fn try_new(freq: f32) -> Result<Self, InvalidFrequency> {
+                Ok(Self {
+                    inner: SignalGenerator::try_new(freq, $function)?,
+                })
+            }
         }
 
         impl<const SR: u32> Iterator for $name<SR> {
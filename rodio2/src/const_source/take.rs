@@ -33,6 +33,10 @@ impl<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> Iterator for TakeDura
     fn next(&mut self) -> Option<Self::Item> {
         self.0.next()
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
 }
 
 pub struct TakeSamples<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> {
@@ -59,4 +63,23 @@ impl<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> Iterator for TakeSamp
             None
         }
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = usize::try_from(self.left).unwrap_or(usize::MAX);
+        let (lower, upper) = self.inner.size_hint();
+        (
+            lower.min(left),
+            Some(upper.map_or(left, |upper| upper.min(left))),
+        )
+    }
+}
+
+impl<const SR: u32, const CH: u16, S> ExactSizeIterator for TakeDuration<SR, CH, S> where
+    S: ConstSource<SR, CH> + ExactSizeIterator
+{
+}
+
+impl<const SR: u32, const CH: u16, S> ExactSizeIterator for TakeSamples<SR, CH, S> where
+    S: ConstSource<SR, CH> + ExactSizeIterator
+{
 }
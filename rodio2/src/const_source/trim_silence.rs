@@ -0,0 +1,269 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use rodio::Sample;
+use rodio::source::SeekError;
+
+use crate::fixed_source::amplify::Factor;
+use crate::{Block, ConstSource};
+
+/// How far the end of a source is looked for. Silence in the middle is held
+/// back at most this long, so whatever feeds a [`TrimSilence`] that trims the
+/// end should be able to run this far ahead of playback.
+pub const LOOKAHEAD: Duration = Duration::from_secs(5);
+
+/// Removes silence from the start and (optionally) the end of a source.
+///
+/// A frame is silent if none of its samples reach the threshold. Only runs of
+/// silent frames that last at least `min_silence` are removed. Everything
+/// after the first loud frame plays unchanged, apart from a silent tail when
+/// trimming the end. Of a tail longer than [`LOOKAHEAD`] only its last
+/// `LOOKAHEAD` is removed.
+pub struct TrimSilence<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> {
+    inner: S,
+    threshold: f32,
+    min_silence: u64, // in frames
+    lookahead: usize, // in samples
+    trim_end: bool,
+    /// No loud frame has been seen yet
+    leading: bool,
+    /// Length of the current run of silent frames
+    silent_frames: u64,
+    /// The part of the current silent run that may still be trimmed
+    held: VecDeque<Sample>,
+    /// Ready to be played, in order: `ready` then `frame`
+    ready: VecDeque<Sample>,
+    frame: Vec<Sample>,
+    next_in_frame: usize,
+}
+
+impl<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> TrimSilence<SR, CH, S> {
+    pub(crate) fn new(source: S, threshold: Factor, min_silence: Duration, trim_end: bool) -> Self {
+        let min_silence = (min_silence.as_secs_f64() * SR as f64) as u64;
+        let lookahead = ((LOOKAHEAD.as_secs_f64() * SR as f64) as usize).max(1) * CH as usize;
+        Self {
+            inner: source,
+            threshold: threshold.as_linear(),
+            min_silence,
+            lookahead,
+            trim_end,
+            leading: true,
+            silent_frames: 0,
+            held: VecDeque::with_capacity(lookahead.min(min_silence as usize * CH as usize)),
+            ready: VecDeque::new(),
+            frame: Vec::with_capacity(CH as usize),
+            next_in_frame: 0,
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Reads frames until there is something to play. Returns None once
+    /// the inner source has ended and nothing is left.
+    #[cold]
+    fn refill(&mut self) -> Option<()> {
+        loop {
+            self.frame.clear();
+            self.next_in_frame = 0;
+            self.frame.extend(self.inner.by_ref().take(CH as usize));
+
+            if self.frame.is_empty() {
+                let long_run = self.silent_frames >= self.min_silence;
+                if !(long_run && (self.leading || self.trim_end)) {
+                    self.ready.extend(self.held.drain(..));
+                }
+                self.held.clear();
+                self.silent_frames = 0;
+                return (!self.ready.is_empty()).then_some(());
+            }
+
+            let peak = self.frame.iter().fold(0f32, |peak, s| peak.max(s.abs()));
+            if peak >= self.threshold {
+                // not the lead-in nor the tail after all
+                self.ready.extend(self.held.drain(..));
+                self.silent_frames = 0;
+                self.leading = false;
+                return Some(());
+            }
+
+            self.silent_frames += 1;
+            if self.leading {
+                if self.silent_frames >= self.min_silence {
+                    self.held.clear();
+                } else {
+                    self.held.extend(self.frame.drain(..));
+                }
+            } else if !self.trim_end {
+                return Some(());
+            } else {
+                self.held.extend(self.frame.drain(..));
+                if self.held.len() > self.lookahead {
+                    // too far from the end to be trimmed
+                    self.ready.extend(self.held.drain(..CH as usize));
+                    return Some(());
+                }
+            }
+        }
+    }
+}
+
+impl<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> ConstSource<SR, CH>
+    for TrimSilence<SR, CH, S>
+{
+    /// Without trimming the end this ignores what the lead-in might lose.
+    fn total_duration(&self) -> Option<Duration> {
+        if self.trim_end {
+            None // we can not know how much will be trimmed
+        } else {
+            self.inner.total_duration()
+        }
+    }
+
+    /// Drops what was held back. Silence right after the seek is only
+    /// trimmed when seeking to the start.
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)?;
+        self.leading = pos.is_zero();
+        self.silent_frames = 0;
+        self.held.clear();
+        self.ready.clear();
+        self.frame.clear();
+        self.next_in_frame = 0;
+        Ok(())
+    }
+}
+
+impl<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> Iterator for TrimSilence<SR, CH, S> {
+    type Item = Sample;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(sample) = self.ready.pop_front() {
+                return Some(sample);
+            }
+            if let Some(sample) = self.frame.get(self.next_in_frame) {
+                self.next_in_frame += 1;
+                return Some(*sample);
+            }
+            self.refill()?;
+        }
+    }
+
+    /// What is held back is counted as if it will play, trimming only ever
+    /// makes the source shorter.
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let pending = self.ready.len() + self.held.len() + self.frame.len() - self.next_in_frame;
+        let (lower, upper) = self.inner.size_hint();
+        (
+            lower.saturating_add(pending),
+            upper.and_then(|upper| upper.checked_add(pending)),
+        )
+    }
+}
+
+impl<const SR: u32, const CH: u16, S: ConstSource<SR, CH>> Block for TrimSilence<SR, CH, S> {}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rodio::nz;
+
+    use super::LOOKAHEAD;
+    use crate::ConstSource;
+    use crate::fixed_source::FixedSourceExt;
+    use crate::fixed_source::amplify::Factor;
+    use crate::fixed_source::buffer::SamplesBuffer;
+
+    /// 100 frames per second makes durations easy to count in frames
+    fn source(frames: &[[f32; 2]]) -> impl ConstSource<100, 2> {
+        let samples: Vec<_> = frames.iter().flatten().copied().collect();
+        SamplesBuffer::new(nz!(2), nz!(100), samples)
+            .try_into_const_source::<100, 2>()
+            .unwrap()
+    }
+
+    const LOUD: [f32; 2] = [0.5, -0.5];
+    const ONE_SIDED: [f32; 2] = [0.0, 0.2];
+    const QUIET: [f32; 2] = [0.001, -0.001];
+
+    fn trimmed(frames: &[[f32; 2]], trim_end: bool) -> Vec<f32> {
+        source(frames)
+            .trim_silence(
+                Factor::Linear(0.01),
+                Duration::from_millis(50), // 5 frames
+                trim_end,
+            )
+            .collect()
+    }
+
+    #[test]
+    fn long_lead_in_and_tail_are_removed() {
+        let mut frames = vec![QUIET; 7];
+        frames.extend([LOUD, ONE_SIDED, LOUD]);
+        frames.extend([[0.0; 2]; 12]);
+
+        assert_eq!(
+            trimmed(&frames, true),
+            [LOUD, ONE_SIDED, LOUD].concat(),
+            "a frame is loud if any channel is"
+        );
+
+        let mut expected = [LOUD, ONE_SIDED, LOUD].concat();
+        expected.extend([0.0; 24]);
+        assert_eq!(trimmed(&frames, false), expected);
+    }
+
+    #[test]
+    fn short_silence_is_kept_exactly() {
+        let frames = [QUIET, QUIET, LOUD, QUIET, QUIET, LOUD, QUIET];
+        assert_eq!(trimmed(&frames, true), frames.concat());
+    }
+
+    #[test]
+    fn long_silence_in_the_middle_plays_unchanged() {
+        let mut frames = vec![LOUD];
+        frames.extend([QUIET; 6]);
+        frames.push(LOUD);
+        frames.extend([QUIET; 20]);
+        frames.push(ONE_SIDED);
+        assert_eq!(trimmed(&frames, true), frames.concat());
+        assert_eq!(trimmed(&frames, false), frames.concat());
+    }
+
+    #[test]
+    fn only_the_end_of_a_long_tail_is_removed() {
+        let lookahead = (LOOKAHEAD.as_secs() * 100) as usize;
+        let mut frames = vec![LOUD];
+        frames.extend(vec![QUIET; lookahead + 30]);
+        let mut expected = vec![LOUD];
+        expected.extend([QUIET; 30]);
+        assert_eq!(trimmed(&frames, true), expected.concat());
+    }
+
+    #[test]
+    fn duration_is_known_unless_trimming_the_end() {
+        let frames = [QUIET, LOUD, QUIET];
+        let keep_end = source(&frames).trim_silence(Factor::Linear(0.01), Duration::ZERO, false);
+        assert_eq!(keep_end.total_duration(), Some(Duration::from_millis(30)));
+        assert_eq!(keep_end.size_hint().0, 6);
+        let trim_end = source(&frames).trim_silence(Factor::Linear(0.01), Duration::ZERO, true);
+        assert_eq!(trim_end.total_duration(), None);
+    }
+
+    #[test]
+    fn all_silent_source_ends_up_empty() {
+        assert!(trimmed(&[QUIET; 10], true).is_empty());
+        assert_eq!(trimmed(&[QUIET; 3], true), [QUIET; 3].concat());
+    }
+}
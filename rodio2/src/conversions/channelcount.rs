@@ -1,3 +1,4 @@
+use rodio::source::SeekError;
 use rodio::{ChannelCount, Sample};
 
 use rodio::Source as DynamicSource;
@@ -42,6 +43,14 @@ impl<S: DynamicSource> DynamicSource for VariableInputChannelConvertor<S> {
     fn total_duration(&self) -> Option<std::time::Duration> {
         self.input.total_duration()
     }
+
+    /// The input seeks to the start of a frame, so does the output
+    fn try_seek(&mut self, pos: std::time::Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)?;
+        self.sample_repeat = None;
+        self.next_output_sample_pos = 0;
+        Ok(())
+    }
 }
 
 impl<S: DynamicSource> Iterator for VariableInputChannelConvertor<S> {
@@ -80,4 +89,19 @@ impl<S: DynamicSource> Iterator for VariableInputChannelConvertor<S> {
         }
         result
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // Not exact in the middle of a frame, the upper bound allows for
+        // the rest of the current frame.
+        let in_frames = |samples: usize| samples / self.input.channels().get() as usize;
+        let (lower, upper) = self.input.size_hint();
+        (
+            in_frames(lower).saturating_mul(self.target.get() as usize),
+            upper.map(|upper| {
+                in_frames(upper)
+                    .saturating_add(1)
+                    .saturating_mul(self.target.get() as usize)
+            }),
+        )
+    }
 }
@@ -1,11 +1,16 @@
 use rodio::FixedSource;
 use rodio::{ChannelCount, Sample};
 
+use crate::Block;
+
 pub struct ChannelConverter<S> {
     input: S,
     pub(crate) target: ChannelCount,
     sample_repeat: Option<Sample>,
     next_output_sample_pos: u16,
+    /// Input frames for [`Block::next_block`], grows to the largest block
+    /// asked for once
+    input_block: Vec<Sample>,
 }
 
 impl<S: FixedSource> ChannelConverter<S> {
@@ -15,6 +20,7 @@ impl<S: FixedSource> ChannelConverter<S> {
             target,
             sample_repeat: None,
             next_output_sample_pos: 0,
+            input_block: Vec::new(),
         }
     }
 
@@ -74,4 +80,65 @@ impl<S: FixedSource> Iterator for ChannelConverter<S> {
         }
         result
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // Not exact in the middle of a frame, the upper bound allows for
+        // the rest of the current frame.
+        let in_frames = |samples: usize| samples / self.input.channels().get() as usize;
+        let (lower, upper) = self.input.size_hint();
+        (
+            in_frames(lower).saturating_mul(self.target.get() as usize),
+            upper.map(|upper| {
+                in_frames(upper)
+                    .saturating_add(1)
+                    .saturating_mul(self.target.get() as usize)
+            }),
+        )
+    }
+}
+
+impl<S: FixedSource + Block> Block for ChannelConverter<S> {
+    /// Converts whole frames at once, `next` takes care of a frame it
+    /// started and of the end of the block that is less than a frame
+    fn next_block(&mut self, out: &mut [Sample]) -> usize {
+        let mut written = 0;
+        while self.next_output_sample_pos != 0 && written < out.len() {
+            match self.next() {
+                Some(sample) => out[written] = sample,
+                None => return written,
+            }
+            written += 1;
+        }
+
+        let in_channels = self.input.channels().get() as usize;
+        let out_channels = self.target.get() as usize;
+        let frames = (out.len() - written) / out_channels;
+        self.input_block.resize(frames * in_channels, 0.0);
+        let pulled = self.input.next_block(&mut self.input_block) / in_channels;
+        let input = self.input_block[..pulled * in_channels].chunks_exact(in_channels);
+        let output = out[written..].chunks_exact_mut(out_channels);
+        for (input, output) in input.zip(output) {
+            for (channel, sample) in output.iter_mut().enumerate() {
+                *sample = match channel {
+                    c if c < in_channels => input[c],
+                    // mono to stereo
+                    1 => input[0],
+                    _ => 0.0,
+                };
+            }
+        }
+        written += pulled * out_channels;
+        if pulled < frames {
+            return written;
+        }
+
+        while written < out.len() {
+            match self.next() {
+                Some(sample) => out[written] = sample,
+                None => break,
+            }
+            written += 1;
+        }
+        written
+    }
 }
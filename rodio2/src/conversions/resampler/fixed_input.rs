@@ -4,6 +4,8 @@ use rubato::Resampler as _;
 
 use rodio::FixedSource;
 
+use crate::Block;
+
 pub struct Resampler<S> {
     input: S,
     next_sample: usize,
@@ -149,12 +151,44 @@ impl<S: FixedSource> Iterator for Resampler<S> {
         self.resample_buffer()?;
         self.next_sample()
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // Only a hint, the input is resampled in chunks and the last one is
+        // padded then trimmed.
+        let buffered = self.output_buffer.len().saturating_sub(self.next_sample);
+        let to_resample = self.input.size_hint().0 as f64
+            * (self.target_sample_rate.get() as f64 / self.input.sample_rate().get() as f64);
+        (buffered.saturating_add(to_resample as usize), None)
+    }
+}
+
+impl<S: FixedSource> Block for Resampler<S> {
+    fn next_block(&mut self, out: &mut [Sample]) -> usize {
+        let mut written = 0;
+        while written < out.len() {
+            let start = self.next_sample.min(self.output_buffer.len());
+            let left = &self.output_buffer[start..];
+            if left.is_empty() {
+                // `next` ends on an empty resampled buffer too
+                if self.resample_buffer().is_none() || self.output_buffer.is_empty() {
+                    break;
+                }
+                continue;
+            }
+            let copied = left.len().min(out.len() - written);
+            out[written..written + copied].copy_from_slice(&left[..copied]);
+            self.next_sample = start + copied;
+            written += copied;
+        }
+        written
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::FixedSource;
     use crate::fixed_source::buffer::SamplesBuffer;
+    use crate::test_support::{assert_non_zero_volume_fuzzy, median_peak_pitch};
 
     use super::Resampler;
     use std::time::Duration;
@@ -162,7 +196,6 @@ mod tests {
     use itertools::Itertools;
     use rodio::source::{Function, SignalGenerator};
     use rodio::{ChannelCount, SampleRate, Source, nz};
-    use spectrum_analyzer::{FrequencyLimit, scaling::divide_by_N_sqrt};
 
     pub(crate) fn sine(
         channels: ChannelCount,
@@ -176,69 +209,6 @@ mod tests {
         SamplesBuffer::new(channels, sample_rate, sine.collect_vec())
     }
 
-    #[derive(Debug)]
-    struct PeakPitch {
-        pub median: f32,
-        pub error: f32,
-    }
-
-    fn assert_non_zero_volume_fuzzy(source: impl FixedSource) {
-        let sample_rate = source.sample_rate();
-        let chunk_size = sample_rate.get() / 1000;
-        let ms_volume = source.into_iter().chunks(chunk_size as usize);
-        let ms_volume = ms_volume
-            .into_iter()
-            .map(|chunk| chunk.into_iter().map(|s| s.abs()).sum::<f32>() / chunk_size as f32);
-
-        for (millis, volume) in ms_volume.enumerate() {
-            assert!(
-                volume > 0.01,
-                "Volume about zero around {:?}",
-                Duration::from_millis(millis as u64)
-            )
-        }
-    }
-
-    fn median_peak_pitch(source: impl FixedSource) -> PeakPitch {
-        use spectrum_analyzer::{samples_fft_to_spectrum, windows::hann_window};
-
-        let channels = source.channels().get();
-        let sample_rate = source.sample_rate().get();
-        let nyquist_freq = (sample_rate / 2) as f32;
-        let hundred_millis: usize = usize::try_from(sample_rate / 10)
-            .unwrap()
-            .next_power_of_two();
-
-        // de-interleave (take channel 0)
-        let samples: Vec<_> = source.step_by(channels as usize).collect();
-        let mut resolution = 0f32;
-        let mut peaks = samples
-            .chunks_exact(hundred_millis)
-            .map(|chunk| {
-                let spectrum = samples_fft_to_spectrum(
-                    &hann_window(chunk),
-                    sample_rate,
-                    // only care about the human audible range (sorry bats)
-                    // (resamplers can include artifacts outside this range
-                    // we do not care about since we wont hear them anyway)
-                    FrequencyLimit::Range(20f32, 20_000f32.min(nyquist_freq)),
-                    Some(&divide_by_N_sqrt),
-                )
-                .unwrap();
-
-                resolution = resolution.max(spectrum.frequency_resolution());
-                spectrum.max().0
-            })
-            .collect_vec();
-
-        peaks.sort();
-        let median = peaks[peaks.len() / 2].val();
-        PeakPitch {
-            median,
-            error: resolution,
-        }
-    }
-
     #[test]
     fn constant_samplerate_preserves_length() {
         let test_signal = sine(nz!(3), nz!(48_000));
@@ -280,7 +250,7 @@ mod tests {
             (channel1_resampled, frequency_1),
         ] {
             let resampled = SamplesBuffer::new(nz!(1), sample_rate_resampled, resampled);
-            let peak_pitch = median_peak_pitch(resampled);
+            let peak_pitch = median_peak_pitch(nz!(1), sample_rate_resampled, resampled);
             assert!(
                 (peak_pitch.median - frequency).abs() < peak_pitch.error,
                 "pitch should be {frequency} but was {peak_pitch:?}"
@@ -291,7 +261,7 @@ mod tests {
     #[test]
     fn resampler_does_not_add_any_latency() {
         let resampled = Resampler::new(sine(nz!(1), nz!(48_000)), nz!(16_000));
-        assert_non_zero_volume_fuzzy(resampled);
+        assert_non_zero_volume_fuzzy(resampled.sample_rate(), resampled);
     }
 
     #[cfg(test)]
@@ -303,8 +273,13 @@ mod tests {
             let test_signal = sine(nz!(1), nz!(48_000));
             let resampled = Resampler::new(test_signal.clone(), nz!(16_000));
 
-            let peak_pitch_before = median_peak_pitch(test_signal);
-            let peak_pitch_after = median_peak_pitch(resampled);
+            let peak_pitch_before = median_peak_pitch(
+                test_signal.channels(),
+                test_signal.sample_rate(),
+                test_signal,
+            );
+            let peak_pitch_after =
+                median_peak_pitch(resampled.channels(), resampled.sample_rate(), resampled);
 
             assert!(
                 (peak_pitch_before.median - peak_pitch_after.median).abs()
@@ -318,8 +293,13 @@ mod tests {
             let test_signal = sine(nz!(2), nz!(48_000));
             let resampled = Resampler::new(test_signal.clone(), nz!(16_000));
 
-            let peak_pitch_before = median_peak_pitch(test_signal);
-            let peak_pitch_after = median_peak_pitch(resampled);
+            let peak_pitch_before = median_peak_pitch(
+                test_signal.channels(),
+                test_signal.sample_rate(),
+                test_signal,
+            );
+            let peak_pitch_after =
+                median_peak_pitch(resampled.channels(), resampled.sample_rate(), resampled);
             assert!(
                 (peak_pitch_before.median - peak_pitch_after.median).abs()
                     < peak_pitch_before.error.max(peak_pitch_after.error),
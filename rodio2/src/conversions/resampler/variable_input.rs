@@ -34,8 +34,10 @@
 
 use std::iter;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
 use audioadapter_buffers::direct::InterleavedSlice;
+use rodio::source::SeekError;
 use rodio::{ChannelCount, Sample, SampleRate, Source};
 use rubato::{Resampler, SincInterpolationParameters, calculate_cutoff};
 
@@ -100,14 +102,20 @@ impl<S: Source> VariableInputResampler<S> {
             resampler,
             input,
         };
-        this.resample_buffer();
-
-        let output_delay = this.resampler.output_delay();
-        let output_delay = output_delay * this.inner_mut().channels().get() as usize;
-        let _ = this.by_ref().take(output_delay).count();
+        this.skip_output_delay();
         this
     }
 
+    /// The resampler starts with a bit of its own output before the input
+    /// comes out
+    fn skip_output_delay(&mut self) {
+        self.resample_buffer();
+
+        let output_delay = self.resampler.output_delay();
+        let output_delay = output_delay * self.inner_mut().channels().get() as usize;
+        let _ = self.by_ref().take(output_delay).count();
+    }
+
     pub fn inner_mut(&mut self) -> &mut S {
         &mut self.input
     }
@@ -225,6 +233,17 @@ impl<S: Source> Source for VariableInputResampler<S> {
     fn total_duration(&self) -> Option<std::time::Duration> {
         self.input.total_duration()
     }
+
+    /// Forgets the input from before the seek, it would otherwise bleed
+    /// into the new position
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)?;
+        self.resampler.reset();
+        self.output_buffer.clear();
+        self.next_sample = 0;
+        self.skip_output_delay();
+        Ok(())
+    }
 }
 
 impl<S: Source> VariableInputResampler<S> {
@@ -246,6 +265,14 @@ impl<S: Source> Iterator for VariableInputResampler<S> {
         self.resample_buffer()?;
         self.next_sample()
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // Only a hint, the input is resampled in chunks and the last one is
+        // padded then trimmed.
+        let buffered = self.output_buffer.len().saturating_sub(self.next_sample);
+        let to_resample = self.input.size_hint().0 as f64 * self.resample_ratio();
+        (buffered.saturating_add(to_resample as usize), None)
+    }
 }
 
 #[cfg(test)]
@@ -256,9 +283,9 @@ mod tests {
     use rodio::buffer::SamplesBuffer;
     use rodio::source::{Function, SignalGenerator};
     use rodio::{ChannelCount, SampleRate, Source, nz};
-    use spectrum_analyzer::{FrequencyLimit, scaling::divide_by_N_sqrt};
 
     use super::VariableInputResampler;
+    use crate::test_support::{assert_non_zero_volume_fuzzy, median_peak_pitch};
 
     pub(crate) fn sine(channels: ChannelCount, sample_rate: SampleRate) -> impl Source + Clone {
         let sine = SignalGenerator::new(sample_rate, 400.0, Function::Sine)
@@ -269,72 +296,6 @@ mod tests {
         SamplesBuffer::new(channels, sample_rate, sine.collect_vec())
     }
 
-    #[derive(Debug)]
-    struct PeakPitch {
-        pub median: f32,
-        pub error: f32,
-    }
-
-    fn assert_non_zero_volume_fuzzy(source: impl Source) {
-        let sample_rate = source.sample_rate();
-        let chunk_size = sample_rate.get() / 1000;
-        let ms_volume = source
-            .into_iter()
-            .inspect(|s| print!("{s}\n"))
-            .chunks(chunk_size as usize);
-        let ms_volume = ms_volume
-            .into_iter()
-            .map(|chunk| chunk.into_iter().map(|s| s.abs()).sum::<f32>() / chunk_size as f32);
-
-        for (millis, volume) in ms_volume.enumerate() {
-            assert!(
-                volume > 0.01,
-                "Volume about zero around {:?}",
-                Duration::from_millis(millis as u64)
-            )
-        }
-    }
-
-    fn median_peak_pitch(source: impl Source) -> PeakPitch {
-        use spectrum_analyzer::{samples_fft_to_spectrum, windows::hann_window};
-
-        let channels = source.channels().get();
-        let sample_rate = source.sample_rate().get();
-        let nyquist_freq = (sample_rate / 2) as f32;
-        let hundred_millis: usize = usize::try_from(sample_rate / 10)
-            .unwrap()
-            .next_power_of_two();
-
-        // de-interleave (take channel 0)
-        let samples: Vec<_> = source.step_by(channels as usize).collect();
-        let mut resolution = 0f32;
-        let mut peaks = samples
-            .chunks_exact(hundred_millis)
-            .map(|chunk| {
-                let spectrum = samples_fft_to_spectrum(
-                    &hann_window(chunk),
-                    sample_rate,
-                    // only care about the human audible range (sorry bats)
-                    // (resamplers can include artifacts outside this range
-                    // we do not care about since we wont hear them anyway)
-                    FrequencyLimit::Range(20f32, 20_000f32.min(nyquist_freq)),
-                    Some(&divide_by_N_sqrt),
-                )
-                .unwrap();
-
-                resolution = resolution.max(spectrum.frequency_resolution());
-                spectrum.max().0
-            })
-            .collect_vec();
-
-        peaks.sort();
-        let median = peaks[peaks.len() / 2].val();
-        PeakPitch {
-            median,
-            error: resolution,
-        }
-    }
-
     #[test]
     fn constant_samplerate_preserves_length() {
         let test_signal = sine(nz!(3), nz!(48_000));
@@ -380,7 +341,7 @@ mod tests {
             (channel1_resampled, frequency_1),
         ] {
             let resampled = SamplesBuffer::new(nz!(1), sample_rate_resampled, resampled);
-            let peak_pitch = median_peak_pitch(resampled);
+            let peak_pitch = median_peak_pitch(nz!(1), sample_rate_resampled, resampled);
             assert!(
                 (peak_pitch.median - frequency).abs() < peak_pitch.error,
                 "pitch should be {frequency} but was {peak_pitch:?}"
@@ -391,7 +352,7 @@ mod tests {
     #[test]
     fn resampler_does_not_add_any_latency() {
         let resampled = VariableInputResampler::new(sine(nz!(1), nz!(48_000)), nz!(16_000));
-        assert_non_zero_volume_fuzzy(resampled);
+        assert_non_zero_volume_fuzzy(resampled.sample_rate(), resampled);
     }
 
     #[cfg(test)]
@@ -403,8 +364,13 @@ mod tests {
             let test_signal = sine(nz!(1), nz!(48_000));
             let resampled = VariableInputResampler::new(test_signal.clone(), nz!(16_000));
 
-            let peak_pitch_before = median_peak_pitch(test_signal);
-            let peak_pitch_after = median_peak_pitch(resampled);
+            let peak_pitch_before = median_peak_pitch(
+                test_signal.channels(),
+                test_signal.sample_rate(),
+                test_signal,
+            );
+            let peak_pitch_after =
+                median_peak_pitch(resampled.channels(), resampled.sample_rate(), resampled);
 
             assert!(
                 (peak_pitch_before.median - peak_pitch_after.median).abs()
@@ -418,8 +384,13 @@ mod tests {
             let test_signal = sine(nz!(2), nz!(48_000));
             let resampled = VariableInputResampler::new(test_signal.clone(), nz!(16_000));
 
-            let peak_pitch_before = median_peak_pitch(test_signal);
-            let peak_pitch_after = median_peak_pitch(resampled);
+            let peak_pitch_before = median_peak_pitch(
+                test_signal.channels(),
+                test_signal.sample_rate(),
+                test_signal,
+            );
+            let peak_pitch_after =
+                median_peak_pitch(resampled.channels(), resampled.sample_rate(), resampled);
             assert!(
                 (peak_pitch_before.median - peak_pitch_after.median).abs()
                     < peak_pitch_before.error.max(peak_pitch_after.error),
@@ -0,0 +1,72 @@
+//! Helpers for looking at what a source actually produces. Open the written
+//! files in something like Audacity to find glitches.
+
+use std::path::Path;
+
+use rodio::{ChannelCount, FixedSource, Sample, SampleRate};
+
+use crate::ConstSource;
+
+/// Renders the source to a 32 bit float WAV file at `path`.
+///
+/// Never returns for endless sources, limit those first using
+/// `take_duration`.
+pub fn write_wav(source: impl FixedSource, path: impl AsRef<Path>) -> Result<(), hound::Error> {
+    let (channels, sample_rate) = (source.channels(), source.sample_rate());
+    write_samples(source, channels, sample_rate, path)
+}
+
+/// Renders the source to a 32 bit float WAV file at `path`.
+///
+/// Never returns for endless sources, limit those first using
+/// `take_duration`.
+pub fn write_wav_const<const SR: u32, const CH: u16>(
+    source: impl ConstSource<SR, CH>,
+    path: impl AsRef<Path>,
+) -> Result<(), hound::Error> {
+    let (channels, sample_rate) = (source.channels(), source.sample_rate());
+    write_samples(source, channels, sample_rate, path)
+}
+
+fn write_samples(
+    samples: impl Iterator<Item = Sample>,
+    channels: ChannelCount,
+    sample_rate: SampleRate,
+    path: impl AsRef<Path>,
+) -> Result<(), hound::Error> {
+    let spec = hound::WavSpec {
+        channels: channels.get(),
+        sample_rate: sample_rate.get(),
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let mut writer = hound::WavWriter::create(path, spec)?;
+    for sample in samples {
+        writer.write_sample(sample)?;
+    }
+    writer.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ConstSource;
+    use crate::const_source::SineWave;
+
+    #[test]
+    fn wav_round_trips() {
+        let path = std::env::temp_dir().join("rodio2-dev-tools-wav-round-trips.wav");
+        let source = || {
+            SineWave::<8000>::new(440.0)
+                .with_channel_count::<2>()
+                .take_samples(1000)
+        };
+        source().write_wav(&path).unwrap();
+
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec().channels, 2);
+        assert_eq!(reader.spec().sample_rate, 8000);
+        let read: Vec<f32> = reader.samples().map(Result::unwrap).collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read, source().collect::<Vec<_>>());
+    }
+}
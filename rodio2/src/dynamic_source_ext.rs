@@ -1,3 +1,4 @@
+use rodio::source::SeekError;
 use rodio::{ChannelCount, Sample, SampleRate, FixedSource};
 
 use crate::conversions::channelcount::VariableInputChannelConvertor;
@@ -32,6 +33,10 @@ impl<S: DynamicSource> FixedSource for IntoFixedSource<S> {
     fn total_duration(&self) -> Option<std::time::Duration> {
         self.0.total_duration()
     }
+
+    fn try_seek(&mut self, pos: std::time::Duration) -> Result<(), SeekError> {
+        self.0.try_seek(pos)
+    }
 }
 
 impl<S: DynamicSource> Iterator for IntoFixedSource<S> {
@@ -40,6 +45,10 @@ impl<S: DynamicSource> Iterator for IntoFixedSource<S> {
     fn next(&mut self) -> Option<Self::Item> {
         self.0.next()
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
 }
 
 impl<S: DynamicSource> ExtendDynamicSource for S {
@@ -4,12 +4,16 @@ use rodio::ChannelCount;
 use rodio::FixedSource;
 use rodio::Sample;
 use rodio::SampleRate;
+use rodio::source::SeekError;
 
 use crate::conversions::channelcount::fixed_input::ChannelConverter;
 use crate::conversions::resampler::fixed_input::Resampler;
 
+use crate::block::{Block, PullBlocks};
 use crate::fixed_source::amplify::Amplify;
+use crate::fixed_source::frame_aligned::FrameAligned;
 use crate::ConstSource;
+use crate::frame::FramePosition;
 use crate::fixed_source::pausable::Pausable;
 use crate::fixed_source::periodic_access::PeriodicAccess;
 use crate::fixed_source::periodic_access::WithData;
@@ -17,6 +21,7 @@ use crate::fixed_source::stoppable::Stoppable;
 
 pub mod amplify;
 pub mod buffer;
+pub mod frame_aligned;
 pub mod pausable;
 pub mod periodic_access;
 pub mod queue;
@@ -31,11 +36,12 @@ pub trait FixedSourceExt: FixedSource {
         take::TakeDuration::new(self, duration)
     }
 
-    fn periodic_access(self, call_every: Duration, arg: fn(&mut Self)) -> PeriodicAccess<Self>
+    fn periodic_access<F>(self, call_every: Duration, access: F) -> PeriodicAccess<Self, F>
     where
         Self: Sized,
+        F: FnMut(&mut Self) + Send,
     {
-        periodic_access::PeriodicAccess::new(self, call_every, arg)
+        periodic_access::PeriodicAccess::new(self, call_every, access)
     }
 
     fn with_data<D>(self, data: D) -> WithData<Self, D>
@@ -45,6 +51,16 @@ pub trait FixedSourceExt: FixedSource {
         periodic_access::WithData { inner: self, data }
     }
 
+    /// Renders the source to a 32 bit float WAV file, see
+    /// [`crate::dev_tools::write_wav`].
+    #[cfg(feature = "dev-tools")]
+    fn write_wav(self, path: impl AsRef<std::path::Path>) -> Result<(), hound::Error>
+    where
+        Self: Sized,
+    {
+        crate::dev_tools::write_wav(self, path)
+    }
+
     fn with_sample_rate(self, sample_rate: SampleRate) -> Resampler<Self>
     where
         Self: Sized,
@@ -84,6 +100,7 @@ pub trait FixedSourceExt: FixedSource {
         Self: Sized,
     {
         Stoppable {
+            frame: FramePosition::new(self.channels()),
             inner: self,
             stop: false,
         }
@@ -94,11 +111,22 @@ pub trait FixedSourceExt: FixedSource {
         Self: Sized,
     {
         Pausable {
+            frame: FramePosition::new(self.channels()),
             inner: self,
             paused,
+            silent: paused,
         }
     }
 
+    /// Panics in debug builds if the source ends part way through a frame,
+    /// see [`FrameAligned`].
+    fn frame_aligned(self) -> FrameAligned<Self>
+    where
+        Self: Sized,
+    {
+        FrameAligned::new(self)
+    }
+
     fn amplify(self, amplify: amplify::Factor) -> Amplify<Self>
     where
         Self: Sized,
@@ -108,18 +136,45 @@ pub trait FixedSourceExt: FixedSource {
             factor: amplify.as_linear(),
         }
     }
+
+    /// Pulls this source in blocks while handing out samples one at a time,
+    /// see [`crate::block`]
+    fn pull_blocks(self) -> PullBlocks<Self>
+    where
+        Self: Sized + Block,
+    {
+        PullBlocks::new(self)
+    }
 }
 
 impl<S: FixedSource> FixedSourceExt for S {}
 
 pub struct IntoConstSource<const SR: u32, const CH: u16, S: FixedSource>(S);
 
+impl<const SR: u32, const CH: u16, S: FixedSource> IntoConstSource<SR, CH, S> {
+    pub fn inner(&self) -> &S {
+        &self.0
+    }
+
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.0
+    }
+
+    pub fn into_inner(self) -> S {
+        self.0
+    }
+}
+
 impl<const SR: u32, const CH: u16, S: FixedSource> ConstSource<SR, CH>
     for IntoConstSource<SR, CH, S>
 {
     fn total_duration(&self) -> Option<Duration> {
         self.0.total_duration()
     }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.0.try_seek(pos)
+    }
 }
 
 impl<const SR: u32, const CH: u16, S: FixedSource> Iterator for IntoConstSource<SR, CH, S> {
@@ -128,6 +183,21 @@ impl<const SR: u32, const CH: u16, S: FixedSource> Iterator for IntoConstSource<
     fn next(&mut self) -> Option<Self::Item> {
         self.0.next()
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<const SR: u32, const CH: u16, S> ExactSizeIterator for IntoConstSource<SR, CH, S> where
+    S: FixedSource + ExactSizeIterator
+{
+}
+
+impl<const SR: u32, const CH: u16, S: FixedSource + Block> Block for IntoConstSource<SR, CH, S> {
+    fn next_block(&mut self, out: &mut [Sample]) -> usize {
+        self.0.next_block(out)
+    }
 }
 
 #[derive(Debug)]
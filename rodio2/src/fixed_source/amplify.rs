@@ -1,6 +1,8 @@
 use rodio::math::db_to_linear;
 use rodio::{FixedSource, Sample};
 
+use crate::Block;
+
 use crate::add_inner_methods;
 
 fn normalized_to_linear(normalized: f32) -> f32 {
@@ -84,4 +86,20 @@ impl<S: FixedSource> Iterator for Amplify<S> {
     fn next(&mut self) -> Option<Self::Item> {
         self.inner.next().map(|value| value * self.factor)
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S: FixedSource + ExactSizeIterator> ExactSizeIterator for Amplify<S> {}
+
+impl<S: FixedSource + Block> Block for Amplify<S> {
+    fn next_block(&mut self, out: &mut [Sample]) -> usize {
+        let pulled = self.inner.next_block(out);
+        for sample in &mut out[..pulled] {
+            *sample *= self.factor;
+        }
+        pulled
+    }
 }
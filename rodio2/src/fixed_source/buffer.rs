@@ -1,10 +1,13 @@
 use std::sync::Arc;
 use std::time::Duration;
 
+use rodio::source::SeekError;
 use rodio::{ChannelCount, Sample, SampleRate};
 
 use rodio::FixedSource;
 
+use crate::Block;
+
 /// A buffer of samples treated as a source.
 #[derive(Debug, Clone)]
 pub struct SamplesBuffer {
@@ -16,27 +19,19 @@ pub struct SamplesBuffer {
 }
 
 impl SamplesBuffer {
-    /// Builds a new `SamplesBuffer`.
-    ///
-    /// # Panics
-    ///
-    /// - Panics if the number of channels is zero.
-    /// - Panics if the samples rate is zero.
-    /// - Panics if the length of the buffer is larger than approximately 16 billion elements.
-    ///   This is because the calculation of the duration would overflow.
-    ///
+    /// Builds a new `SamplesBuffer`. Channels and sample rate can not be
+    /// zero, and the duration is computed wide enough to never overflow, so
+    /// this can not fail.
     pub fn new<D>(channels: ChannelCount, sample_rate: SampleRate, data: D) -> SamplesBuffer
     where
         D: Into<Vec<Sample>>,
     {
-        assert!(channels.get() >= 1);
-        assert!(sample_rate.get() >= 1);
         let data: Arc<[f32]> = data.into().into();
-        let duration_ns = 1_000_000_000u64.checked_mul(data.len() as u64).unwrap()
-            / sample_rate.get() as u64
-            / channels.get() as u64;
+        let duration_ns = 1_000_000_000u128 * data.len() as u128
+            / sample_rate.get() as u128
+            / channels.get() as u128;
         let duration = Duration::new(
-            duration_ns / 1_000_000_000,
+            (duration_ns / 1_000_000_000) as u64,
             (duration_ns % 1_000_000_000) as u32,
         );
         SamplesBuffer {
@@ -61,23 +56,21 @@ impl FixedSource for SamplesBuffer {
     fn total_duration(&self) -> Option<Duration> {
         Some(self.duration)
     }
-    // /// This jumps in memory till the sample for `pos`.
-    // #[inline]
-    // fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
-    //     // This is fast because all the samples are in memory already
-    //     // and due to the constant sample_rate we can jump to the right
-    //     // sample directly.
-    //     let curr_channel = self.pos % self.channels() as usize;
-    //     let new_pos = pos.as_secs_f32() * self.sample_rate() as f32 * self.channels() as f32;
-    //     // saturate pos at the end of the source
-    //     let new_pos = new_pos as usize;
-    //     let new_pos = new_pos.min(self.data.len());
-    //     // make sure the next sample is for the right channel
-    //     let new_pos = new_pos.next_multiple_of(self.channels() as usize);
-    //     let new_pos = new_pos - curr_channel;
-    //     self.pos = new_pos;
-    //     Ok(())
-    // }
+    /// This jumps in memory till the sample for `pos`.
+    #[inline]
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        // This is fast because all the samples are in memory already
+        // and due to the constant sample_rate we can jump to the right
+        // sample directly.
+        let channels = self.channels.get() as usize;
+        let curr_channel = self.pos % channels;
+        let frame = (pos.as_secs_f64() * self.sample_rate.get() as f64) as usize;
+        // make sure the next sample is for the right channel, saturating
+        // at the end of the source
+        let new_pos = frame.saturating_mul(channels).saturating_add(curr_channel);
+        self.pos = new_pos.min(self.data.len());
+        Ok(())
+    }
 }
 
 impl Iterator for SamplesBuffer {
@@ -90,6 +83,61 @@ impl Iterator for SamplesBuffer {
     }
     #[inline]
     fn size_hint(&self) -> (usize, Option<usize>) {
-        (self.data.len(), Some(self.data.len()))
+        let left = self.data.len() - self.pos;
+        (left, Some(left))
+    }
+}
+
+impl ExactSizeIterator for SamplesBuffer {}
+
+impl Block for SamplesBuffer {
+    fn next_block(&mut self, out: &mut [Sample]) -> usize {
+        let left = &self.data[self.pos..];
+        let copied = left.len().min(out.len());
+        out[..copied].copy_from_slice(&left[..copied]);
+        self.pos += copied;
+        copied
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rodio::{FixedSource, nz};
+
+    use super::SamplesBuffer;
+    use crate::fixed_source::FixedSourceExt;
+    use crate::fixed_source::amplify::Factor;
+
+    #[test]
+    fn size_hint_survives_wrapping() {
+        let mut buffer = SamplesBuffer::new(nz!(2), nz!(44100), vec![0.5; 1002]);
+        buffer.next();
+        buffer.next();
+        assert_eq!(buffer.len(), 1000);
+
+        let wrapped = buffer
+            .amplify(Factor::Linear(0.5))
+            .with_data(())
+            .periodic_access(Duration::from_millis(5), |_| {});
+        assert_eq!(wrapped.len(), 1000);
+
+        let collected: Vec<_> = wrapped.collect();
+        assert_eq!(collected.len(), 1000);
+        assert_eq!(collected.capacity(), collected.len());
+    }
+
+    #[test]
+    fn seeking_keeps_the_channel_and_stops_at_the_end() {
+        let samples: Vec<f32> = (0..20).map(|n| n as f32).collect();
+        let mut buffer = SamplesBuffer::new(nz!(2), nz!(4), samples);
+        buffer.next();
+        buffer.try_seek(Duration::from_secs(1)).unwrap();
+        assert_eq!(buffer.next(), Some(9.0));
+        buffer.try_seek(Duration::ZERO).unwrap();
+        assert_eq!(buffer.next(), Some(0.0));
+        buffer.try_seek(Duration::from_secs(60)).unwrap();
+        assert_eq!(buffer.next(), None);
     }
 }
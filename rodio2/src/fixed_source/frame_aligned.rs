@@ -0,0 +1,132 @@
+use std::time::Duration;
+
+use rodio::source::SeekError;
+use rodio::{FixedSource, Sample};
+
+use crate::Block;
+#[cfg(debug_assertions)]
+use crate::frame::FramePosition;
+
+/// Panics once the source ends part way through a frame. Queues and mixers
+/// play whatever comes next on the wrong channels after such a source, this
+/// catches it where it happens.
+///
+/// Only checks in debug builds. In release this passes everything through
+/// and costs nothing.
+pub struct FrameAligned<S: FixedSource> {
+    pub(crate) inner: S,
+    #[cfg(debug_assertions)]
+    pub(crate) frame: FramePosition,
+}
+
+crate::add_inner_methods!(FrameAligned<S>);
+
+impl<S: FixedSource> FrameAligned<S> {
+    pub(crate) fn new(inner: S) -> Self {
+        Self {
+            #[cfg(debug_assertions)]
+            frame: FramePosition::new(inner.channels()),
+            inner,
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    fn played(&mut self, samples: usize, ended: bool) {
+        self.frame.advance_by(samples);
+        assert!(
+            !ended || self.frame.at_frame_start(),
+            "{} ended {} samples into a frame of {} channels",
+            std::any::type_name::<S>(),
+            self.frame.played(),
+            self.frame.channels(),
+        );
+    }
+
+    #[cfg(not(debug_assertions))]
+    #[inline(always)]
+    fn played(&mut self, _samples: usize, _ended: bool) {}
+}
+
+impl<S: FixedSource> FixedSource for FrameAligned<S> {
+    fn channels(&self) -> rodio::ChannelCount {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> rodio::SampleRate {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)
+    }
+}
+
+impl<S: FixedSource> Iterator for FrameAligned<S> {
+    type Item = Sample;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let sample = self.inner.next();
+        self.played(sample.is_some() as usize, sample.is_none());
+        sample
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S: FixedSource + ExactSizeIterator> ExactSizeIterator for FrameAligned<S> {}
+
+impl<S: FixedSource + Block> Block for FrameAligned<S> {
+    fn next_block(&mut self, out: &mut [Sample]) -> usize {
+        let pulled = self.inner.next_block(out);
+        self.played(pulled, pulled < out.len());
+        pulled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::nz;
+
+    use crate::Block;
+    use crate::fixed_source::FixedSourceExt;
+    use crate::fixed_source::buffer::SamplesBuffer;
+
+    #[test]
+    fn whole_frames_pass() {
+        let source = SamplesBuffer::new(nz!(2), nz!(44100), vec![0.5; 6]);
+        assert_eq!(source.frame_aligned().count(), 6);
+
+        let mut source = SamplesBuffer::new(nz!(2), nz!(44100), vec![0.5; 6]).frame_aligned();
+        let mut out = [0.0; 4];
+        assert_eq!(source.next_block(&mut out), 4);
+        assert_eq!(source.next_block(&mut out), 2);
+    }
+
+    #[test]
+    #[cfg_attr(
+        debug_assertions,
+        should_panic(expected = "ended 1 samples into a frame")
+    )]
+    fn half_frames_panic_in_debug() {
+        let source = SamplesBuffer::new(nz!(2), nz!(44100), vec![0.5; 5]);
+        assert_eq!(source.frame_aligned().count(), 5);
+    }
+
+    #[test]
+    #[cfg_attr(
+        debug_assertions,
+        should_panic(expected = "ended 1 samples into a frame")
+    )]
+    fn half_frame_blocks_panic_in_debug() {
+        let mut source = SamplesBuffer::new(nz!(2), nz!(44100), vec![0.5; 5]).frame_aligned();
+        let mut out = [0.0; 8];
+        assert_eq!(source.next_block(&mut out), 5);
+    }
+}
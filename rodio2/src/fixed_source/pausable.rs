@@ -1,10 +1,17 @@
 use rodio::{FixedSource, Sample};
 
+use crate::Block;
+use crate::frame::FramePosition;
+
 pub struct Pausable<S: FixedSource> {
     pub(crate) inner: S,
     // TODO we need to ramp samples up/down when this changes
     // (though current rodio neglects to do this as well...)
     pub(crate) paused: bool,
+    /// Follows `paused` once the current frame is complete, pausing mid
+    /// frame would swap the channels on resume
+    pub(crate) silent: bool,
+    pub(crate) frame: FramePosition,
 }
 
 crate::add_inner_methods!(Pausable<S>);
@@ -33,10 +40,69 @@ impl<S: FixedSource> Iterator for Pausable<S> {
     type Item = Sample;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.paused {
-            Some(0.0)
-        } else {
-            self.inner.next()
+        if self.frame.at_frame_start() {
+            self.silent = self.paused;
+        }
+        let sample = if self.silent { 0.0 } else { self.inner.next()? };
+        self.frame.advance();
+        Some(sample)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // while paused this plays silence for as long as it takes
+        (self.inner.size_hint().0, None)
+    }
+}
+
+impl<S: FixedSource + Block> Block for Pausable<S> {
+    fn next_block(&mut self, out: &mut [Sample]) -> usize {
+        // finish the frame the previous block ended in first
+        let mut written = 0;
+        while !self.frame.at_frame_start() && written < out.len() {
+            match self.next() {
+                Some(sample) => out[written] = sample,
+                None => return written,
+            }
+            written += 1;
         }
+
+        self.silent = self.paused;
+        let rest = &mut out[written..];
+        let pulled = if self.silent {
+            rest.fill(0.0);
+            rest.len()
+        } else {
+            self.inner.next_block(rest)
+        };
+        self.frame.advance_by(pulled);
+        written + pulled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::nz;
+
+    use crate::Block;
+    use crate::fixed_source::FixedSourceExt;
+    use crate::fixed_source::buffer::SamplesBuffer;
+
+    #[test]
+    fn pausing_waits_for_the_frame_to_complete() {
+        let samples: Vec<f32> = (1..=8).map(|n| n as f32).collect();
+        let mut source = SamplesBuffer::new(nz!(2), nz!(44100), samples).pausable(false);
+        assert_eq!(source.next(), Some(1.0));
+        source.set_paused(true);
+        assert_eq!(source.next(), Some(2.0));
+        assert_eq!(source.next(), Some(0.0));
+        assert_eq!(source.next(), Some(0.0));
+        source.set_paused(false);
+
+        let mut out = [0.0; 3];
+        assert_eq!(source.next_block(&mut out), 3);
+        assert_eq!(out, [3.0, 4.0, 5.0]);
+        source.set_paused(true);
+        assert_eq!(source.next_block(&mut out), 3);
+        assert_eq!(out, [6.0, 0.0, 0.0]);
     }
 }
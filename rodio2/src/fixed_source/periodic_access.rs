@@ -3,35 +3,50 @@ use std::time::Duration;
 use rodio::Sample;
 
 use crate::FixedSource;
+use crate::block::{self, Block};
 
-pub struct PeriodicAccess<S: FixedSource> {
+pub struct PeriodicAccess<S: FixedSource, F> {
     inner: S,
-    access: fn(&mut S),
-    update_period: u32, // in samples
-    samples_until_update: u32,
+    access: F,
+    update_period: u32, // in frames
+    frames_until_update: u32,
+    /// kept between zero and the channel count
+    sample_counter: u16,
 }
 
-crate::add_inner_methods!(PeriodicAccess<S>);
+impl<S: FixedSource, F> PeriodicAccess<S, F> {
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
 
-impl<S: FixedSource> PeriodicAccess<S> {
-    pub(crate) fn new(source: S, update_period: Duration, access: fn(&mut S)) -> Self {
-        let update_period = 1.0 / update_period.as_secs_f64() * source.sample_rate().get() as f64;
+impl<S: FixedSource, F: FnMut(&mut S) + Send> PeriodicAccess<S, F> {
+    pub(crate) fn new(source: S, update_period: Duration, access: F) -> Self {
+        let update_period = update_period.as_secs_f64() * source.sample_rate().get() as f64;
         Self {
             inner: source,
             access,
-            update_period: update_period as u32,
-            samples_until_update: 0,
+            update_period: (update_period as u32).max(1),
+            // zero so the first access happens right away
+            frames_until_update: 0,
+            sample_counter: 0,
         }
     }
 
     #[cold]
     fn do_access(&mut self) {
         (self.access)(&mut self.inner);
-        self.samples_until_update = self.update_period;
+        self.frames_until_update = self.update_period;
     }
 }
 
-impl<S: FixedSource> FixedSource for PeriodicAccess<S> {
+impl<S: FixedSource, F: FnMut(&mut S) + Send> FixedSource for PeriodicAccess<S, F> {
     fn total_duration(&self) -> Option<std::time::Duration> {
         self.inner.total_duration()
     }
@@ -45,16 +60,67 @@ impl<S: FixedSource> FixedSource for PeriodicAccess<S> {
     }
 }
 
-impl<S: FixedSource> Iterator for PeriodicAccess<S> {
+impl<S: FixedSource, F: FnMut(&mut S) + Send> Iterator for PeriodicAccess<S, F> {
     type Item = Sample;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.samples_until_update > self.update_period {
-            self.do_access(); // separate fn so we can hint this branch is cold
+        if self.sample_counter == 0 {
+            if self.frames_until_update == 0 {
+                self.do_access(); // separate fn so we can hint this branch is cold
+            }
+            self.frames_until_update -= 1;
+        }
+        self.sample_counter += 1;
+        if self.sample_counter == self.inner.channels().get() {
+            self.sample_counter = 0;
         }
 
         self.inner.next()
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S, F> ExactSizeIterator for PeriodicAccess<S, F>
+where
+    S: FixedSource + ExactSizeIterator,
+    F: FnMut(&mut S) + Send,
+{
+}
+
+impl<S, F> Block for PeriodicAccess<S, F>
+where
+    S: FixedSource + Block,
+    F: FnMut(&mut S) + Send,
+{
+    /// Pulls from inner in runs that end where the next access is due
+    fn next_block(&mut self, out: &mut [Sample]) -> usize {
+        let channels = self.inner.channels().get();
+        let mut written = 0;
+        while written < out.len() {
+            if self.sample_counter == 0 && self.frames_until_update == 0 {
+                self.do_access();
+            }
+            let run = block::until_access(self.sample_counter, self.frames_until_update, channels)
+                .min(out.len() - written);
+            let pulled = self.inner.next_block(&mut out[written..written + run]);
+            written += pulled;
+            // the call to `next` that found the end counts too
+            let calls = pulled + usize::from(pulled < run);
+            block::advance(
+                &mut self.sample_counter,
+                &mut self.frames_until_update,
+                channels,
+                calls,
+            );
+            if pulled < run {
+                break;
+            }
+        }
+        written
+    }
 }
 
 pub struct WithData<S: FixedSource, D> {
@@ -94,4 +160,61 @@ impl<S: FixedSource, D> Iterator for WithData<S, D> {
     fn next(&mut self) -> Option<Self::Item> {
         self.inner.next()
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S: FixedSource + ExactSizeIterator, D> ExactSizeIterator for WithData<S, D> {}
+
+impl<S: FixedSource + Block, D> Block for WithData<S, D> {
+    fn next_block(&mut self, out: &mut [Sample]) -> usize {
+        self.inner.next_block(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use rodio::nz;
+
+    use crate::fixed_source::FixedSourceExt;
+    use crate::fixed_source::buffer::SamplesBuffer;
+
+    #[test]
+    fn closure_runs_at_configured_interval() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let one_second = SamplesBuffer::new(nz!(1), nz!(44100), vec![0.0; 44100]);
+
+        let pulled = one_second
+            .periodic_access(Duration::from_millis(100), move |_| {
+                counter.fetch_add(1, Ordering::Relaxed);
+            })
+            .count();
+        assert_eq!(pulled, 44100);
+
+        let calls = calls.load(Ordering::Relaxed);
+        assert!((9..=11).contains(&calls), "called {calls} times");
+    }
+
+    #[test]
+    fn period_is_in_frames_and_first_call_is_immediate() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let three_seconds = SamplesBuffer::new(nz!(2), nz!(44100), vec![0.0; 3 * 2 * 44100]);
+
+        let mut source = three_seconds.periodic_access(Duration::from_millis(50), move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+        source.next();
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+
+        for _ in source {}
+        assert_eq!(calls.load(Ordering::Relaxed), 60);
+    }
 }
@@ -1,18 +1,88 @@
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, mpsc};
+use std::time::Duration;
 
 use rodio::FixedSource;
 use rodio::{ChannelCount, SampleRate};
 
+use crate::frame::FramePosition;
 
 pub mod uniform;
 
+pub(crate) struct Pending<S> {
+    source: S,
+    id: u32,
+    done: Option<mpsc::Sender<SourceId>>,
+    /// what was added to [`Buffered`] for this source
+    samples: u64,
+}
+
+/// How many samples the queue plays before updating [`Buffered`]. Keeps the
+/// atomics out of the per sample path.
+const SYNC_BUFFERED_EVERY: u32 = 1024;
+
+/// Shared between a queue and its handle so the handle can tell how much audio
+/// is waiting to be played.
+#[derive(Clone, Default)]
+pub(crate) struct Buffered {
+    /// sources added but not yet picked up by the queue
+    pending: Arc<AtomicUsize>,
+    /// samples left in the pending sources and the current source
+    samples: Arc<AtomicU64>,
+}
+
+impl Buffered {
+    fn added(&self, samples: u64) {
+        self.pending.fetch_add(1, Ordering::Relaxed);
+        self.samples.fetch_add(samples, Ordering::Relaxed);
+    }
+
+    fn picked_up(&self) {
+        self.pending.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    fn played(&self, samples: u64) {
+        self.samples.fetch_sub(samples, Ordering::Relaxed);
+    }
+
+    pub(crate) fn pending(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn duration(&self, channels: ChannelCount, sample_rate: SampleRate) -> Duration {
+        let samples = self.samples.load(Ordering::Relaxed);
+        Duration::from_secs_f64(
+            samples as f64 / sample_rate.get() as f64 / channels.get() as f64,
+        )
+    }
+}
+
+/// Number of samples `source` will produce, zero if that is not known.
+pub(crate) fn expected_samples(source: &(impl FixedSource + ?Sized)) -> u64 {
+    match source.total_duration() {
+        Some(duration) => {
+            let frames = duration.as_secs_f64() * source.sample_rate().get() as f64;
+            (frames * source.channels().get() as f64) as u64
+        }
+        None => source.size_hint().0 as u64,
+    }
+}
+
 pub struct Queue {
     channels: ChannelCount,
     sample_rate: SampleRate,
+    queue_id: u32,
     current: Option<Box<dyn FixedSource>>,
-    pending: mpsc::Receiver<(Box<dyn FixedSource>, u32)>,
+    /// Notified once `current` has been fully consumed
+    current_done: Option<mpsc::Sender<SourceId>>,
+    pending: mpsc::Receiver<Pending<Box<dyn FixedSource>>>,
     current_id: Arc<AtomicU32>,
+    buffered: Buffered,
+    /// samples the current source still counts for in `buffered`
+    current_samples: u64,
+    /// samples played since `buffered` was last updated
+    unsynced: u32,
+    frame: FramePosition,
 }
 
 impl Queue {
@@ -22,6 +92,7 @@ impl Queue {
         let queue_id = QUEUE_ID.fetch_add(1, Ordering::Relaxed);
         assert!(queue_id < u32::MAX, "Can not create 4 billion queues");
         let current_id = Arc::new(AtomicU32::new(0));
+        let buffered = Buffered::default();
 
         let (tx, rx) = mpsc::channel();
 
@@ -29,9 +100,15 @@ impl Queue {
             Self {
                 channels,
                 sample_rate,
+                queue_id,
                 current: None,
+                current_done: None,
                 pending: rx,
                 current_id: Arc::clone(&current_id),
+                buffered: buffered.clone(),
+                current_samples: 0,
+                unsynced: 0,
+                frame: FramePosition::new(channels),
             },
             QueueHandle {
                 channels,
@@ -39,10 +116,34 @@ impl Queue {
                 queue_id,
                 next_id: Arc::new(AtomicU32::new(0)),
                 current_id,
+                buffered,
                 tx,
             },
         )
     }
+
+    #[cold]
+    fn sync_buffered(&mut self) {
+        let played = u64::from(self.unsynced).min(self.current_samples);
+        self.buffered.played(played);
+        self.current_samples -= played;
+        self.unsynced = 0;
+    }
+
+    #[cold]
+    fn current_finished(&mut self) {
+        self.current = None;
+        self.buffered.played(self.current_samples);
+        self.current_samples = 0;
+        self.unsynced = 0;
+        if let Some(done) = self.current_done.take() {
+            // the receiver might no longer care, that is fine
+            let _ = done.send(SourceId {
+                queue_id: self.queue_id,
+                source_id: self.current_id.load(Ordering::Relaxed),
+            });
+        }
+    }
 }
 
 pub struct QueueHandle {
@@ -51,9 +152,11 @@ pub struct QueueHandle {
     queue_id: u32,
     next_id: Arc<AtomicU32>,
     current_id: Arc<AtomicU32>,
-    tx: mpsc::Sender<(Box<dyn FixedSource>, u32)>,
+    buffered: Buffered,
+    tx: mpsc::Sender<Pending<Box<dyn FixedSource>>>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct SourceId {
     pub queue_id: u32,
     pub source_id: u32,
@@ -74,6 +177,24 @@ pub enum AddError {
 
 impl QueueHandle {
     pub fn add(&self, source: Box<dyn FixedSource>) -> Result<SourceId, AddError> {
+        self.push(source, None)
+    }
+
+    /// Like [`add`](Self::add) but sends the returned [`SourceId`] on `done`
+    /// once the queue has pulled the last sample out of `source`.
+    pub fn add_with_notify(
+        &self,
+        source: Box<dyn FixedSource>,
+        done: mpsc::Sender<SourceId>,
+    ) -> Result<SourceId, AddError> {
+        self.push(source, Some(done))
+    }
+
+    fn push(
+        &self,
+        source: Box<dyn FixedSource>,
+        done: Option<mpsc::Sender<SourceId>>,
+    ) -> Result<SourceId, AddError> {
         if source.channels() != self.channels {
             return Err(AddError::WrongChannelCount {
                 got: source.channels(),
@@ -90,8 +211,16 @@ impl QueueHandle {
         // wraps on overflow, should be okay as long as there are < 4 million
         // sources in the list.
         let source_id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let samples = expected_samples(&*source);
+        // count before sending so the queue can never pick it up first
+        self.buffered.added(samples);
         self.tx
-            .send((source, source_id))
+            .send(Pending {
+                source,
+                id: source_id,
+                done,
+                samples,
+            })
             .map_err(|_| AddError::QueueDropped)?;
 
         Ok(SourceId {
@@ -106,10 +235,23 @@ impl QueueHandle {
             source_id: self.current_id.load(Ordering::Relaxed),
         }
     }
+
+    /// Number of sources added that the queue has not yet started playing.
+    pub fn pending(&self) -> usize {
+        self.buffered.pending()
+    }
+
+    /// How much audio is left in the pending sources and the one playing now.
+    ///
+    /// Sources that do not know their length count as zero. This lags behind
+    /// playback by a few milliseconds.
+    pub fn buffered_duration(&self) -> Duration {
+        self.buffered.duration(self.channels, self.sample_rate)
+    }
 }
 
 impl FixedSource for Queue {
-    fn total_duration(&self) -> Option<std::time::Duration> {
+    fn total_duration(&self) -> Option<Duration> {
         None // endless
     }
 
@@ -127,20 +269,43 @@ impl Iterator for Queue {
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            if let Some(curr) = &mut self.current
-                && let Some(sample) = curr.next()
-            {
-                return Some(sample);
+            if let Some(curr) = &mut self.current {
+                if let Some(sample) = curr.next() {
+                    self.frame.advance();
+                    self.unsynced += 1;
+                    if self.unsynced >= SYNC_BUFFERED_EVERY {
+                        self.sync_buffered();
+                    }
+                    return Some(sample);
+                }
+                self.current_finished();
+            }
+
+            // a source that ended part way through a frame is padded with
+            // silence, starting the next one now would swap its channels
+            if !self.frame.at_frame_start() {
+                self.frame.advance();
+                return Some(0.0);
             }
 
             // No need to end the audio source when the queue handle drops
             // that should be handled with a `Stoppable` wrapper instead.
             let next = self.pending.try_recv().ok();
 
-            if let Some((source, id)) = next {
+            if let Some(Pending {
+                source,
+                id,
+                done,
+                samples,
+            }) = next
+            {
+                self.buffered.picked_up();
                 self.current = Some(source);
+                self.current_done = done;
+                self.current_samples = samples;
                 self.current_id.store(id, Ordering::Relaxed);
             } else {
+                self.frame.advance();
                 return Some(0.0);
             }
         }
@@ -1,18 +1,31 @@
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, mpsc};
+use std::time::Duration;
 
 use rodio::FixedSource;
-use rodio::{ChannelCount, SampleRate};
+use rodio::{ChannelCount, Sample, SampleRate};
 
-use super::AddError;
+use crate::Block;
+use crate::frame::FramePosition;
+
+use super::{AddError, Buffered, Pending, SYNC_BUFFERED_EVERY, expected_samples};
 
 pub struct UniformQueue<S: FixedSource> {
     channels: ChannelCount,
     sample_rate: SampleRate,
+    queue_id: u32,
     current: Option<S>,
-    pending: mpsc::Receiver<(S, u32)>,
+    /// Notified once `current` has been fully consumed
+    current_done: Option<mpsc::Sender<SourceId>>,
+    pending: mpsc::Receiver<Pending<S>>,
     // zero means silence is 'playing'
     current_id: Arc<AtomicU32>,
+    buffered: Buffered,
+    /// samples the current source still counts for in `buffered`
+    current_samples: u64,
+    /// samples played since `buffered` was last updated
+    unsynced: u32,
+    frame: FramePosition,
 }
 
 impl<S: FixedSource> UniformQueue<S> {
@@ -22,6 +35,7 @@ impl<S: FixedSource> UniformQueue<S> {
         let queue_id = QUEUE_ID.fetch_add(1, Ordering::Relaxed);
         assert!(queue_id < u32::MAX, "Can not create 4 billion queues");
         let current_id = Arc::new(AtomicU32::new(0));
+        let buffered = Buffered::default();
 
         let (tx, rx) = mpsc::channel();
 
@@ -29,9 +43,15 @@ impl<S: FixedSource> UniformQueue<S> {
             Self {
                 channels,
                 sample_rate,
+                queue_id,
                 current: None,
+                current_done: None,
                 pending: rx,
                 current_id: Arc::clone(&current_id),
+                buffered: buffered.clone(),
+                current_samples: 0,
+                unsynced: 0,
+                frame: FramePosition::new(channels),
             },
             UniformQueueHandle {
                 channels,
@@ -39,10 +59,57 @@ impl<S: FixedSource> UniformQueue<S> {
                 queue_id,
                 next_id: Arc::new(AtomicU32::new(0)),
                 current_id,
+                buffered,
                 tx,
             },
         )
     }
+
+    #[cold]
+    fn sync_buffered(&mut self) {
+        let played = u64::from(self.unsynced).min(self.current_samples);
+        self.buffered.played(played);
+        self.current_samples -= played;
+        self.unsynced = 0;
+    }
+
+    /// Moves the next pending source in as current, false if there is none
+    #[cold]
+    fn pick_up_next(&mut self) -> bool {
+        // No need to end the audio source when the queue handle drops
+        // that should be handled with a `Stoppable` wrapper instead.
+        let Ok(Pending {
+            source,
+            id,
+            done,
+            samples,
+        }) = self.pending.try_recv()
+        else {
+            return false;
+        };
+        debug_assert!(self.frame.at_frame_start(), "switching sources mid frame");
+        self.buffered.picked_up();
+        self.current = Some(source);
+        self.current_done = done;
+        self.current_samples = samples;
+        self.current_id.store(id, Ordering::Relaxed);
+        true
+    }
+
+    #[cold]
+    fn current_finished(&mut self) {
+        self.current = None;
+        self.buffered.played(self.current_samples);
+        self.current_samples = 0;
+        self.unsynced = 0;
+        if let Some(done) = self.current_done.take() {
+            // the receiver might no longer care, that is fine
+            let _ = done.send(SourceId {
+                queue_id: self.queue_id,
+                source_id: self.current_id.load(Ordering::Relaxed),
+            });
+        }
+    }
 }
 
 pub struct UniformQueueHandle<S: FixedSource> {
@@ -51,9 +118,11 @@ pub struct UniformQueueHandle<S: FixedSource> {
     queue_id: u32,
     next_id: Arc<AtomicU32>,
     current_id: Arc<AtomicU32>,
-    tx: mpsc::Sender<(S, u32)>,
+    buffered: Buffered,
+    tx: mpsc::Sender<Pending<S>>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct SourceId {
     pub queue_id: u32,
     pub source_id: u32,
@@ -61,6 +130,20 @@ pub struct SourceId {
 
 impl<S: FixedSource> UniformQueueHandle<S> {
     pub fn add(&self, source: S) -> Result<SourceId, AddError> {
+        self.push(source, None)
+    }
+
+    /// Like [`add`](Self::add) but sends the returned [`SourceId`] on `done`
+    /// once the queue has pulled the last sample out of `source`.
+    pub fn add_with_notify(
+        &self,
+        source: S,
+        done: mpsc::Sender<SourceId>,
+    ) -> Result<SourceId, AddError> {
+        self.push(source, Some(done))
+    }
+
+    fn push(&self, source: S, done: Option<mpsc::Sender<SourceId>>) -> Result<SourceId, AddError> {
         if source.channels() != self.channels {
             return Err(AddError::WrongChannelCount {
                 got: source.channels(),
@@ -77,8 +160,16 @@ impl<S: FixedSource> UniformQueueHandle<S> {
         // wraps on overflow, should be okay as long as there are < 4 million
         // sources in the list.
         let source_id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let samples = expected_samples(&source);
+        // count before sending so the queue can never pick it up first
+        self.buffered.added(samples);
         self.tx
-            .send((source, source_id))
+            .send(Pending {
+                source,
+                id: source_id,
+                done,
+                samples,
+            })
             .map_err(|_| AddError::QueueDropped)?;
 
         Ok(SourceId {
@@ -93,10 +184,23 @@ impl<S: FixedSource> UniformQueueHandle<S> {
             source_id: self.current_id.load(Ordering::Relaxed),
         }
     }
+
+    /// Number of sources added that the queue has not yet started playing.
+    pub fn pending(&self) -> usize {
+        self.buffered.pending()
+    }
+
+    /// How much audio is left in the pending sources and the one playing now.
+    ///
+    /// Sources that do not know their length count as zero. This lags behind
+    /// playback by a few milliseconds.
+    pub fn buffered_duration(&self) -> Duration {
+        self.buffered.duration(self.channels, self.sample_rate)
+    }
 }
 
 impl<S: FixedSource> FixedSource for UniformQueue<S> {
-    fn total_duration(&self) -> Option<std::time::Duration> {
+    fn total_duration(&self) -> Option<Duration> {
         None // endless
     }
 
@@ -114,22 +218,161 @@ impl<S: FixedSource> Iterator for UniformQueue<S> {
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            if let Some(curr) = &mut self.current
-                && let Some(sample) = curr.next()
-            {
-                return Some(sample);
+            if let Some(curr) = &mut self.current {
+                if let Some(sample) = curr.next() {
+                    self.frame.advance();
+                    self.unsynced += 1;
+                    if self.unsynced >= SYNC_BUFFERED_EVERY {
+                        self.sync_buffered();
+                    }
+                    return Some(sample);
+                }
+                self.current_finished();
             }
 
-            // No need to end the audio source when the queue handle drops
-            // that should be handled with a `Stoppable` wrapper instead.
-            let next = self.pending.try_recv().ok();
-
-            if let Some((source, id)) = next {
-                self.current = Some(source);
-                self.current_id.store(id, Ordering::Relaxed);
-            } else {
+            // a source that ended part way through a frame is padded with
+            // silence, starting the next one now would swap its channels
+            if !self.frame.at_frame_start() || !self.pick_up_next() {
+                self.frame.advance();
                 return Some(0.0);
             }
         }
     }
 }
+
+impl<S: FixedSource + Block> Block for UniformQueue<S> {
+    fn next_block(&mut self, out: &mut [Sample]) -> usize {
+        let mut written = 0;
+        loop {
+            if let Some(curr) = &mut self.current {
+                let pulled = curr.next_block(&mut out[written..]);
+                written += pulled;
+                self.frame.advance_by(pulled);
+                self.unsynced += pulled as u32;
+                if self.unsynced >= SYNC_BUFFERED_EVERY {
+                    self.sync_buffered();
+                }
+                if written == out.len() {
+                    return written;
+                }
+                self.current_finished();
+            }
+
+            // pad a frame the source left incomplete, see `next`
+            let padding = self.frame.left_in_frame().min(out.len() - written);
+            out[written..written + padding].fill(0.0);
+            written += padding;
+            self.frame.advance_by(padding);
+            if written == out.len() {
+                return written;
+            }
+
+            if !self.pick_up_next() {
+                self.frame.advance_by(out.len() - written);
+                out[written..].fill(0.0);
+                return out.len();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use rodio::nz;
+
+    use super::UniformQueue;
+    use crate::Block;
+    use crate::fixed_source::buffer::SamplesBuffer;
+
+    #[test]
+    fn completion_is_signalled_in_order_after_last_sample() {
+        let (mut queue, handle) = UniformQueue::new(nz!(1), nz!(44100));
+        let (tx, rx) = mpsc::channel();
+
+        let first = handle
+            .add_with_notify(
+                SamplesBuffer::new(nz!(1), nz!(44100), vec![1.0; 4]),
+                tx.clone(),
+            )
+            .unwrap();
+        let second = handle
+            .add_with_notify(SamplesBuffer::new(nz!(1), nz!(44100), vec![2.0; 4]), tx)
+            .unwrap();
+
+        for _ in 0..4 {
+            assert_eq!(queue.next(), Some(1.0));
+        }
+        assert!(rx.try_recv().is_err(), "first source not yet exhausted");
+
+        assert_eq!(queue.next(), Some(2.0));
+        assert_eq!(rx.try_recv(), Ok(first));
+        for _ in 0..3 {
+            assert_eq!(queue.next(), Some(2.0));
+        }
+        assert!(rx.try_recv().is_err(), "second source not yet exhausted");
+
+        assert_eq!(queue.next(), Some(0.0));
+        assert_eq!(rx.try_recv(), Ok(second));
+        assert!(rx.try_recv().is_err(), "each source completes only once");
+    }
+
+    #[test]
+    fn pending_and_buffered_follow_playback() {
+        let tenth_of_a_second = || SamplesBuffer::new(nz!(2), nz!(44100), vec![0.5; 8820]);
+        let (mut queue, handle) = UniformQueue::new(nz!(2), nz!(44100));
+        assert_eq!(handle.pending(), 0);
+        assert_eq!(handle.buffered_duration(), Duration::ZERO);
+
+        handle.add(tenth_of_a_second()).unwrap();
+        handle.add(tenth_of_a_second()).unwrap();
+        assert_eq!(handle.pending(), 2);
+        assert_eq!(handle.buffered_duration(), Duration::from_millis(200));
+
+        queue.next();
+        assert_eq!(handle.pending(), 1);
+
+        // finish the first source, the second one starts
+        for _ in 0..8820 {
+            queue.next();
+        }
+        assert_eq!(handle.pending(), 0);
+        let left = handle.buffered_duration();
+        assert!(
+            left <= Duration::from_millis(100) && left > Duration::from_millis(90),
+            "{left:?}"
+        );
+
+        for _ in 0..8820 {
+            queue.next();
+        }
+        assert_eq!(handle.pending(), 0);
+        assert_eq!(handle.buffered_duration(), Duration::ZERO);
+    }
+
+    #[test]
+    fn half_frames_are_padded_before_the_next_source() {
+        let (mut queue, handle) = UniformQueue::new(nz!(2), nz!(44100));
+        handle
+            .add(SamplesBuffer::new(nz!(2), nz!(44100), vec![1.0; 3]))
+            .unwrap();
+        handle
+            .add(SamplesBuffer::new(nz!(2), nz!(44100), vec![2.0; 2]))
+            .unwrap();
+        let played: Vec<_> = queue.by_ref().take(6).collect();
+        assert_eq!(played, [1.0, 1.0, 1.0, 0.0, 2.0, 2.0]);
+
+        let (mut queue, handle) = UniformQueue::new(nz!(2), nz!(44100));
+        handle
+            .add(SamplesBuffer::new(nz!(2), nz!(44100), vec![1.0; 3]))
+            .unwrap();
+        handle
+            .add(SamplesBuffer::new(nz!(2), nz!(44100), vec![2.0; 2]))
+            .unwrap();
+        let mut out = [9.0; 7];
+        assert_eq!(queue.next_block(&mut out), 7);
+        assert_eq!(out, [1.0, 1.0, 1.0, 0.0, 2.0, 2.0, 0.0]);
+    }
+}
@@ -1,8 +1,15 @@
+use rodio::source::SeekError;
 use rodio::{FixedSource, Sample};
 
+use crate::Block;
+use crate::frame::FramePosition;
+
+/// Ends the source on [`stop`](Self::stop). The frame that is playing at
+/// that moment is still completed.
 pub struct Stoppable<S: FixedSource> {
     pub(crate) inner: S,
     pub(crate) stop: bool,
+    pub(crate) frame: FramePosition,
 }
 
 crate::add_inner_methods!(Stoppable<S>);
@@ -25,16 +32,75 @@ impl<S: FixedSource> FixedSource for Stoppable<S> {
     fn total_duration(&self) -> Option<std::time::Duration> {
         self.inner.total_duration()
     }
+
+    fn try_seek(&mut self, pos: std::time::Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)
+    }
 }
 
 impl<S: FixedSource> Iterator for Stoppable<S> {
     type Item = Sample;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if self.stop && self.frame.at_frame_start() {
+            return None;
+        }
+        let sample = self.inner.next()?;
+        self.frame.advance();
+        Some(sample)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
         if self.stop {
-            None
+            (0, Some(self.frame.left_in_frame()))
         } else {
-            self.inner.next()
+            (0, self.inner.size_hint().1)
         }
     }
 }
+
+impl<S: FixedSource + Block> Block for Stoppable<S> {
+    fn next_block(&mut self, out: &mut [Sample]) -> usize {
+        let out = if self.stop {
+            let left = self.frame.left_in_frame().min(out.len());
+            if left == 0 {
+                return 0;
+            }
+            &mut out[..left]
+        } else {
+            out
+        };
+        let pulled = self.inner.next_block(out);
+        self.frame.advance_by(pulled);
+        pulled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::nz;
+
+    use crate::Block;
+    use crate::fixed_source::FixedSourceExt;
+    use crate::fixed_source::buffer::SamplesBuffer;
+
+    #[test]
+    fn stopping_completes_the_frame() {
+        let samples: Vec<f32> = (1..=8).map(|n| n as f32).collect();
+        let mut source = SamplesBuffer::new(nz!(2), nz!(44100), samples.clone())
+            .stoppable()
+            .frame_aligned();
+        assert_eq!(source.next(), Some(1.0));
+        source.inner_mut().stop();
+        assert_eq!(source.next(), Some(2.0));
+        assert_eq!(source.next(), None);
+
+        let mut source = SamplesBuffer::new(nz!(2), nz!(44100), samples).stoppable();
+        let mut out = [0.0; 3];
+        assert_eq!(source.next_block(&mut out), 3);
+        source.stop();
+        assert_eq!(source.next_block(&mut out), 1);
+        assert_eq!(out[0], 4.0);
+        assert_eq!(source.next_block(&mut out), 0);
+    }
+}
@@ -47,8 +47,14 @@ impl<S: FixedSource> Iterator for TakeDuration<S> {
     fn next(&mut self) -> Option<Self::Item> {
         self.0.next()
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
 }
 
+impl<S: FixedSource + ExactSizeIterator> ExactSizeIterator for TakeDuration<S> {}
+
 pub struct TakeSamples<S: FixedSource> {
     pub(crate) inner: S,
     pub(crate) left: u64,
@@ -81,4 +87,15 @@ impl<S: FixedSource> Iterator for TakeSamples<S> {
             None
         }
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = usize::try_from(self.left).unwrap_or(usize::MAX);
+        let (lower, upper) = self.inner.size_hint();
+        (
+            lower.min(left),
+            Some(upper.map_or(left, |upper| upper.min(left))),
+        )
+    }
 }
+
+impl<S: FixedSource + ExactSizeIterator> ExactSizeIterator for TakeSamples<S> {}
@@ -0,0 +1,85 @@
+//! Keeping track of where in a frame a source is.
+//!
+//! A frame holds one sample for every channel. Anything that switches what
+//! it plays (pausing, stopping, moving on to the next source) has to do so
+//! between frames. Switching part way through shifts every sample after it
+//! onto the wrong channel.
+
+use rodio::ChannelCount;
+
+/// Counts the samples played into the current frame.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FramePosition {
+    channels: u16,
+    /// samples of the current frame that have been played
+    played: u16,
+}
+
+impl FramePosition {
+    pub(crate) fn new(channels: ChannelCount) -> Self {
+        Self {
+            channels: channels.get(),
+            played: 0,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn advance(&mut self) {
+        self.played += 1;
+        if self.played == self.channels {
+            self.played = 0;
+        }
+    }
+
+    #[inline]
+    pub(crate) fn advance_by(&mut self, samples: usize) {
+        let channels = self.channels as usize;
+        self.played = ((self.played as usize + samples) % channels) as u16;
+    }
+
+    #[inline]
+    pub(crate) fn at_frame_start(&self) -> bool {
+        self.played == 0
+    }
+
+    /// Samples still needed to complete the current frame, zero at the
+    /// start of one.
+    #[inline]
+    pub(crate) fn left_in_frame(&self) -> usize {
+        (self.channels - self.played) as usize % self.channels as usize
+    }
+
+    #[cfg(debug_assertions)]
+    pub(crate) fn played(&self) -> u16 {
+        self.played
+    }
+
+    #[cfg(debug_assertions)]
+    pub(crate) fn channels(&self) -> u16 {
+        self.channels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rodio::nz;
+
+    use super::FramePosition;
+
+    #[test]
+    fn wraps_at_the_end_of_a_frame() {
+        let mut frame = FramePosition::new(nz!(3));
+        assert!(frame.at_frame_start());
+        assert_eq!(frame.left_in_frame(), 0);
+
+        frame.advance();
+        assert!(!frame.at_frame_start());
+        assert_eq!(frame.left_in_frame(), 2);
+
+        frame.advance_by(2);
+        assert!(frame.at_frame_start());
+
+        frame.advance_by(7);
+        assert_eq!(frame.left_in_frame(), 2);
+    }
+}
@@ -9,14 +9,21 @@
 pub use rodio::Source as DynamicSource;
 pub use rodio::source as dynamic_source;
 pub use rodio::speakers;
-pub use rodio::{ChannelCount, SampleRate};
+pub use rodio::{ChannelCount, Sample, SampleRate};
 pub use rodio::{Decoder, MixerOsSink, mixer, nz};
 
+pub mod block;
 pub mod const_source;
 pub mod conversions;
+#[cfg(feature = "dev-tools")]
+pub mod dev_tools;
 pub mod dynamic_source_ext;
 pub mod fixed_source;
+pub(crate) mod frame;
+#[cfg(test)]
+pub(crate) mod test_support;
 
+pub use block::Block;
 pub use const_source::ConstSource;
 pub use rodio::FixedSource;
 
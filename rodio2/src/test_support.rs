@@ -0,0 +1,77 @@
+//! Helpers shared between test modules
+
+use std::time::Duration;
+
+use itertools::Itertools;
+use rodio::{ChannelCount, Sample, SampleRate};
+use spectrum_analyzer::{FrequencyLimit, scaling::divide_by_N_sqrt};
+
+#[derive(Debug)]
+pub(crate) struct PeakPitch {
+    pub median: f32,
+    pub error: f32,
+}
+
+/// Panics if any millisecond of `samples` is about silent
+pub(crate) fn assert_non_zero_volume_fuzzy(
+    sample_rate: SampleRate,
+    samples: impl Iterator<Item = Sample>,
+) {
+    let chunk_size = sample_rate.get() / 1000;
+    let ms_volume = samples.chunks(chunk_size as usize);
+    let ms_volume = ms_volume
+        .into_iter()
+        .map(|chunk| chunk.into_iter().map(|s| s.abs()).sum::<f32>() / chunk_size as f32);
+
+    for (millis, volume) in ms_volume.enumerate() {
+        assert!(
+            volume > 0.01,
+            "Volume about zero around {:?}",
+            Duration::from_millis(millis as u64)
+        )
+    }
+}
+
+/// The median of the loudest frequency in each 100ms of the first channel
+pub(crate) fn median_peak_pitch(
+    channels: ChannelCount,
+    sample_rate: SampleRate,
+    samples: impl Iterator<Item = Sample>,
+) -> PeakPitch {
+    use spectrum_analyzer::{samples_fft_to_spectrum, windows::hann_window};
+
+    let sample_rate = sample_rate.get();
+    let nyquist_freq = (sample_rate / 2) as f32;
+    let hundred_millis: usize = usize::try_from(sample_rate / 10)
+        .unwrap()
+        .next_power_of_two();
+
+    // de-interleave (take channel 0)
+    let samples: Vec<_> = samples.step_by(channels.get() as usize).collect();
+    let mut resolution = 0f32;
+    let mut peaks = samples
+        .chunks_exact(hundred_millis)
+        .map(|chunk| {
+            let spectrum = samples_fft_to_spectrum(
+                &hann_window(chunk),
+                sample_rate,
+                // only care about the human audible range (sorry bats)
+                // (resamplers can include artifacts outside this range
+                // we do not care about since we wont hear them anyway)
+                FrequencyLimit::Range(20f32, 20_000f32.min(nyquist_freq)),
+                Some(&divide_by_N_sqrt),
+            )
+            .unwrap();
+
+            resolution = resolution.max(spectrum.frequency_resolution());
+            spectrum.max().0
+        })
+        .collect_vec();
+
+    peaks.sort();
+    let median = peaks[peaks.len() / 2].val();
+    PeakPitch {
+        median,
+        error: resolution,
+    }
+}
@@ -0,0 +1,364 @@
+//! Finds the cover art of songs. Shared by `albumart`, `readpicture` and the
+//! artwork HTTP endpoint so they always agree.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::SystemTime;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::{Result, Section, eyre::Context};
+use lofty::file::TaggedFileExt;
+use lofty::picture::PictureType;
+use lofty::probe::read_from_path;
+
+#[cfg(feature = "artwork-http")]
+pub mod http;
+
+/// Image files next to a song that hold the cover of its album, in the
+/// order they are tried
+const FOLDER_IMAGES: &[&str] = &["cover", "folder", "front"];
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "webp"];
+/// Directories of a multi-disc album, their cover is usually one level up
+const DISC_FOLDERS: &[&str] = &["cd", "disc", "disk"];
+/// Lookups remembered by [`Cache`]
+const CACHED: usize = 16;
+
+/// What was looked up, the modification time it was looked up at and what
+/// was found
+type CacheEntry = (Utf8PathBuf, Lookup, Option<SystemTime>, Option<Artwork>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Artwork {
+    pub data: Arc<[u8]>,
+    /// Like `image/png`
+    pub mime: &'static str,
+}
+
+/// Where to look for the artwork of a song
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lookup {
+    /// The art of the song's album, what `albumart` sends. Tried in order: an
+    /// image file in the directory, the picture in the first song of the
+    /// directory and for disc folders an image file in the album directory.
+    Album,
+    /// A picture in the song's tags, what `readpicture` sends
+    Embedded,
+    /// The album art, or the song's own picture if the album has none
+    Any,
+}
+
+/// Finds the artwork of the song or directory at `path`, an absolute path
+pub fn find(path: &Utf8Path, lookup: Lookup) -> Result<Option<Artwork>> {
+    match lookup {
+        Lookup::Album => album(&album_dir(path)),
+        Lookup::Embedded => embedded(path),
+        Lookup::Any => match album(&album_dir(path))? {
+            Some(art) => Ok(Some(art)),
+            None => embedded(path),
+        },
+    }
+}
+
+/// The directory itself for a directory, otherwise the one the song is in
+fn album_dir(path: &Utf8Path) -> Utf8PathBuf {
+    if path.is_dir() {
+        path.to_owned()
+    } else {
+        path.parent().unwrap_or(path).to_owned()
+    }
+}
+
+fn album(dir: &Utf8Path) -> Result<Option<Artwork>> {
+    if let Some(art) = folder_image(dir)? {
+        return Ok(Some(art));
+    }
+    if let Some(art) = first_song_picture(dir)? {
+        return Ok(Some(art));
+    }
+    match dir.parent() {
+        Some(album) if is_disc_folder(dir) => folder_image(album),
+        _ => Ok(None),
+    }
+}
+
+fn folder_image(dir: &Utf8Path) -> Result<Option<Artwork>> {
+    for name in FOLDER_IMAGES {
+        for extension in IMAGE_EXTENSIONS {
+            let image = dir.join(format!("{name}.{extension}"));
+            match std::fs::read(&image) {
+                Ok(data) => return Ok(Some(Artwork::new(data))),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(e)
+                        .wrap_err("Could not read album art")
+                        .with_note(|| format!("path: {image}"));
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// The picture of the first file, by name, that has tags. Only that one
+/// song is tried, reading all of a large directory would take too long.
+fn first_song_picture(dir: &Utf8Path) -> Result<Option<Artwork>> {
+    let entries = match dir.read_dir_utf8() {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(e)
+                .wrap_err("Could not list the directory")
+                .with_note(|| format!("path: {dir}"));
+        }
+    };
+    let mut files: Vec<_> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_file()))
+        .map(|entry| entry.into_path())
+        .collect();
+    files.sort_unstable();
+    // images and other files without tags fail to read
+    Ok(files.iter().find_map(|file| embedded(file).ok()).flatten())
+}
+
+/// Like `CD 2`, `Disc2` or `disk 01`
+fn is_disc_folder(dir: &Utf8Path) -> bool {
+    let Some(name) = dir.file_name() else {
+        return false;
+    };
+    let name = name.to_lowercase();
+    DISC_FOLDERS.iter().any(|prefix| {
+        name.strip_prefix(prefix).is_some_and(|number| {
+            let number = number.trim_start_matches([' ', '_', '-']);
+            !number.is_empty() && number.chars().all(|c| c.is_ascii_digit())
+        })
+    })
+}
+
+/// The front cover if the tags have one, otherwise the first picture
+fn embedded(path: &Utf8Path) -> Result<Option<Artwork>> {
+    let tagged = read_from_path(path)
+        .wrap_err("Could not read the tags")
+        .with_note(|| format!("path: {path}"))?;
+    let pictures = tagged.tags().iter().flat_map(|tag| tag.pictures());
+    let picture = pictures
+        .clone()
+        .find(|picture| picture.pic_type() == PictureType::CoverFront)
+        .or_else(|| pictures.clone().next());
+    Ok(picture.map(|picture| Artwork::new(picture.data().to_vec())))
+}
+
+impl Artwork {
+    fn new(data: Vec<u8>) -> Self {
+        Self {
+            mime: mime(&data),
+            data: data.into(),
+        }
+    }
+}
+
+/// From the magic bytes, file names and tags are not to be trusted
+fn mime(data: &[u8]) -> &'static str {
+    if data.starts_with(b"\x89PNG") {
+        "image/png"
+    } else if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        "image/jpeg"
+    } else if data.starts_with(b"GIF8") {
+        "image/gif"
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(&b"WEBP"[..]) {
+        "image/webp"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+/// Remembers the last few lookups. Clients fetch art in chunks and ask for
+/// it again for every song of an album, scrolling through an album grid asks
+/// for many directories.
+///
+/// Album art is remembered per directory and embedded pictures per song. An
+/// entry is used as long as the modification time of its directory or song
+/// did not change, adding or removing a cover changes the directory's.
+#[derive(Default)]
+pub struct Cache {
+    entries: Mutex<VecDeque<CacheEntry>>,
+}
+
+impl Cache {
+    /// Like [`find`]
+    pub fn find(&self, path: &Utf8Path, lookup: Lookup) -> Result<Option<Artwork>> {
+        match lookup {
+            Lookup::Album => self.cached(&album_dir(path), Lookup::Album),
+            Lookup::Embedded => self.cached(path, Lookup::Embedded),
+            Lookup::Any => match self.find(path, Lookup::Album)? {
+                Some(art) => Ok(Some(art)),
+                None => self.find(path, Lookup::Embedded),
+            },
+        }
+    }
+
+    fn cached(&self, path: &Utf8Path, lookup: Lookup) -> Result<Option<Artwork>> {
+        let modified = path.metadata().and_then(|meta| meta.modified()).ok();
+        let cached = self
+            .entries()
+            .iter()
+            .find(|(cached, cached_lookup, cached_modified, _)| {
+                cached == path && *cached_lookup == lookup && *cached_modified == modified
+            })
+            .map(|(_, _, _, art)| art.clone());
+        if let Some(art) = cached {
+            return Ok(art);
+        }
+
+        // not locked while reading, the files can be large
+        let art = match lookup {
+            Lookup::Album => album(path)?,
+            _ => embedded(path)?,
+        };
+        let mut entries = self.entries();
+        entries.retain(|(cached, cached_lookup, ..)| cached != path || *cached_lookup != lookup);
+        if entries.len() == CACHED {
+            entries.pop_front();
+        }
+        entries.push_back((path.to_owned(), lookup, modified, art.clone()));
+        Ok(art)
+    }
+
+    fn entries(&self) -> MutexGuard<'_, VecDeque<CacheEntry>> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use lofty::config::WriteOptions;
+    use lofty::picture::{MimeType, Picture};
+    use lofty::tag::{Tag, TagExt, TagType};
+
+    use super::*;
+
+    /// The smallest valid PNG, one transparent pixel
+    pub(crate) const PNG: &[u8] = &[
+        0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44,
+        0x52, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x06, 0x00, 0x00, 0x00, 0x1F,
+        0x15, 0xC4, 0x89, 0x00, 0x00, 0x00, 0x0B, 0x49, 0x44, 0x41, 0x54, 0x78, 0x9C, 0x63, 0x60,
+        0x00, 0x02, 0x00, 0x00, 0x05, 0x00, 0x01, 0x7A, 0x5E, 0xAB, 0x3F, 0x00, 0x00, 0x00, 0x00,
+        0x49, 0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82,
+    ];
+
+    /// A short wav with [`PNG`] as its embedded front cover
+    pub(crate) fn write_song_with_cover(path: &Utf8Path) {
+        crate::player::tests::write_wav(path, std::time::Duration::from_millis(100));
+        let mut tag = Tag::new(TagType::Id3v2);
+        tag.push_picture(Picture::new_unchecked(
+            PictureType::CoverFront,
+            Some(MimeType::Png),
+            None,
+            PNG.to_vec(),
+        ));
+        tag.save_to_path(path, WriteOptions::default()).unwrap();
+    }
+
+    fn temp_dir(name: &str) -> Utf8PathBuf {
+        let dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mpdhaj-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn embedded_and_folder_art_are_found() {
+        let dir = temp_dir("artwork");
+        let song = dir.join("song.wav");
+        write_song_with_cover(&song);
+
+        let embedded = find(&song, Lookup::Embedded).unwrap().unwrap();
+        assert_eq!((&*embedded.data, embedded.mime), (PNG, "image/png"));
+        assert_eq!(find(&song, Lookup::Any).unwrap(), Some(embedded));
+
+        let jpeg = [0xFF, 0xD8, 0xFF, 0xE0];
+        std::fs::write(dir.join("folder.jpg"), jpeg).unwrap();
+        let folder = find(&song, Lookup::Any).unwrap().unwrap();
+        assert_eq!((&*folder.data, folder.mime), (&jpeg[..], "image/jpeg"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn album_art_falls_back_in_order() {
+        let root = temp_dir("artwork-fallback");
+        let jpeg = [0xFF, 0xD8, 0xFF, 0xE0];
+        // an image file next to the songs wins
+        let folder = root.join("Folder");
+        std::fs::create_dir_all(&folder).unwrap();
+        write_song_with_cover(&folder.join("1.wav"));
+        std::fs::write(folder.join("front.jpg"), jpeg).unwrap();
+        // then the picture in the first song
+        let embedded = root.join("Embedded");
+        std::fs::create_dir_all(&embedded).unwrap();
+        crate::player::tests::write_wav(
+            &embedded.join("0.wav"),
+            std::time::Duration::from_millis(100),
+        );
+        write_song_with_cover(&embedded.join("1.wav"));
+        let first_has_cover = root.join("First");
+        std::fs::create_dir_all(&first_has_cover).unwrap();
+        write_song_with_cover(&first_has_cover.join("1.wav"));
+        // then the album directory for a disc folder
+        let disc = root.join("Album/Disc 2");
+        std::fs::create_dir_all(&disc).unwrap();
+        crate::player::tests::write_wav(&disc.join("1.wav"), std::time::Duration::from_millis(100));
+        std::fs::write(root.join("Album/cover.jpg"), jpeg).unwrap();
+        // but not for any directory
+        let not_a_disc = root.join("Album/Bonus");
+        std::fs::create_dir_all(&not_a_disc).unwrap();
+        crate::player::tests::write_wav(
+            &not_a_disc.join("1.wav"),
+            std::time::Duration::from_millis(100),
+        );
+
+        let data = |path: &Utf8Path| {
+            find(path, Lookup::Album)
+                .unwrap()
+                .map(|art| art.data.to_vec())
+        };
+        assert_eq!(data(&folder.join("1.wav")), Some(jpeg.to_vec()));
+        assert_eq!(data(&folder), Some(jpeg.to_vec()));
+        // 0.wav has no picture, only the first song is tried
+        assert_eq!(data(&embedded.join("1.wav")), None);
+        assert_eq!(data(&first_has_cover), Some(PNG.to_vec()));
+        assert_eq!(data(&disc.join("1.wav")), Some(jpeg.to_vec()));
+        assert_eq!(data(&disc), Some(jpeg.to_vec()));
+        assert_eq!(data(&not_a_disc.join("1.wav")), None);
+        assert_eq!(data(&root.join("Album")), Some(jpeg.to_vec()));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn disc_folders() {
+        for disc in ["CD 2", "Disc2", "disk 01", "Disc_1"] {
+            assert!(is_disc_folder(Utf8Path::new(disc)), "{disc}");
+        }
+        for other in ["CD", "Discovery", "Disc 1 - Live", "Bonus"] {
+            assert!(!is_disc_folder(Utf8Path::new(other)), "{other}");
+        }
+    }
+
+    #[test]
+    fn the_cache_is_invalidated_by_changes_to_the_directory() {
+        let dir = temp_dir("artwork-cache");
+        let song = dir.join("song.wav");
+        crate::player::tests::write_wav(&song, std::time::Duration::from_millis(100));
+
+        let cache = Cache::default();
+        assert_eq!(cache.find(&song, Lookup::Album).unwrap(), None);
+        std::fs::write(dir.join("cover.png"), PNG).unwrap();
+        let cover = cache.find(&dir, Lookup::Album).unwrap().unwrap();
+        assert_eq!(&*cover.data, PNG);
+
+        // rewriting a file in place leaves the directory as is
+        std::fs::write(dir.join("cover.png"), [0xFF, 0xD8, 0xFF, 0xE0]).unwrap();
+        assert_eq!(cache.find(&song, Lookup::Album).unwrap(), Some(cover));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,161 @@
+//! A tiny HTTP server for the artwork, for dashboards and notification
+//! daemons that can not speak the binary part of the mpd protocol.
+//!
+//! - `GET /art?file=URI` the cover of the song at URI
+//! - `GET /current` the cover of the current song
+
+use std::sync::Arc;
+
+use axum::Router;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use camino::Utf8PathBuf;
+use color_eyre::Result;
+use color_eyre::eyre::Context;
+use serde::Deserialize;
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+use tokio::task;
+
+use crate::artwork::{Cache, Lookup};
+use crate::system::System;
+
+/// The art of a song only changes when its files do, a day is plenty
+const ART_CACHE_CONTROL: &str = "public, max-age=86400";
+/// The current song changes all the time
+const CURRENT_CACHE_CONTROL: &str = "no-cache";
+
+#[derive(Deserialize)]
+struct ArtQuery {
+    file: Utf8PathBuf,
+}
+
+pub async fn serve(system: Arc<Mutex<System>>, listener: TcpListener) -> Result<()> {
+    let app = Router::new()
+        .route("/art", get(art))
+        .route("/current", get(current))
+        .with_state(system);
+    axum::serve(listener, app)
+        .await
+        .wrap_err("Artwork server stopped")
+}
+
+async fn art(State(system): State<Arc<Mutex<System>>>, Query(query): Query<ArtQuery>) -> Response {
+    let found = {
+        let system = system.lock().await;
+        system
+            .song_path(&query.file)
+            .map(|path| (path, Arc::clone(&system.artwork)))
+    };
+    match found {
+        Ok((path, cache)) => respond(path, cache, ART_CACHE_CONTROL).await,
+        Err(_) => (StatusCode::NOT_FOUND, "No such song").into_response(),
+    }
+}
+
+async fn current(State(system): State<Arc<Mutex<System>>>) -> Response {
+    let found = {
+        let system = system.lock().await;
+        system.current_song().and_then(|song| {
+            song.map(|song| system.song_path(&song.path))
+                .transpose()
+                .map(|path| path.map(|path| (path, Arc::clone(&system.artwork))))
+        })
+    };
+    match found {
+        Ok(Some((path, cache))) => respond(path, cache, CURRENT_CACHE_CONTROL).await,
+        Ok(None) => (StatusCode::NOT_FOUND, "Nothing is playing").into_response(),
+        Err(e) => internal_error(e),
+    }
+}
+
+/// Reads the art without holding the System lock, the files can be large
+async fn respond(path: Utf8PathBuf, cache: Arc<Cache>, cache_control: &'static str) -> Response {
+    let art = task::spawn_blocking(move || cache.find(&path, Lookup::Any))
+        .await
+        .wrap_err("Artwork lookup panicked");
+    match art {
+        Ok(Ok(Some(art))) => (
+            [(CONTENT_TYPE, art.mime), (CACHE_CONTROL, cache_control)],
+            art.data.to_vec(),
+        )
+            .into_response(),
+        Ok(Ok(None)) => (StatusCode::NOT_FOUND, "No artwork").into_response(),
+        Ok(Err(e)) | Err(e) => internal_error(e),
+    }
+}
+
+fn internal_error(e: color_eyre::Report) -> Response {
+    tracing::warn!("Could not serve artwork: {e:#}");
+    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    use super::*;
+    use crate::artwork::tests::{PNG, write_song_with_cover};
+    use crate::player::Player;
+    use crate::player::device::Silent;
+
+    async fn get(port: u16, target: &str) -> Vec<u8> {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let request =
+            format!("GET {target} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+        response
+    }
+
+    fn head(response: &[u8]) -> String {
+        let end = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .unwrap();
+        String::from_utf8_lossy(&response[..end]).to_lowercase()
+    }
+
+    #[tokio::test]
+    async fn art_of_a_song_is_served() {
+        let music_dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mpdhaj-artwork-http-{}", std::process::id()));
+        std::fs::create_dir_all(&music_dir).unwrap();
+        write_song_with_cover(&music_dir.join("song.wav"));
+        let system = System::with_parts(
+            Connection::open_in_memory().unwrap(),
+            |volume, paused| Player::with_device(volume, paused, Silent),
+            music_dir.clone(),
+            None,
+        )
+        .unwrap();
+        system
+            .db
+            .execute("INSERT INTO songs (path, mtime) VALUES ('song.wav', 0)", [])
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(serve(Arc::new(Mutex::new(system)), listener));
+
+        let response = get(port, "/art?file=song.wav").await;
+        let ok = head(&response);
+        assert!(ok.starts_with("http/1.1 200"), "{ok}");
+        assert!(ok.contains("content-type: image/png"), "{ok}");
+        assert!(ok.contains("cache-control: public, max-age=86400"), "{ok}");
+        assert!(response.ends_with(PNG));
+
+        let response = get(port, "/art?file=..%2F..%2Fetc%2Fpasswd").await;
+        assert!(head(&response).starts_with("http/1.1 404"));
+        let response = get(port, "/current").await;
+        assert!(head(&response).starts_with("http/1.1 404"));
+        std::fs::remove_dir_all(&music_dir).unwrap();
+    }
+}
@@ -32,4 +32,33 @@ pub(crate) enum Commands {
 pub struct RunArgs {
     pub(crate) music_dir: Utf8PathBuf,
     pub(crate) playlist_dir: Option<Utf8PathBuf>,
+    /// Skip silent lead-ins and tails of songs
+    #[clap(long)]
+    pub(crate) trim_silence: bool,
+    /// Audio kept in the output's buffer in microseconds, like mpd's
+    /// buffer_time. Raise it if a wireless speaker crackles. Clients can
+    /// change it with `outputset 0 buffer_time`. Zero leaves it to the
+    /// device.
+    #[clap(long)]
+    pub(crate) buffer_time: Option<u64>,
+    /// Let `searchadd` leave out songs that are already in the queue.
+    /// Clients can choose per command with a trailing
+    /// `x-mpdhaj-skip-queued 0|1`.
+    #[clap(long)]
+    pub(crate) skip_queued: bool,
+    /// Rescan files as soon as other programs add, change or remove them
+    #[clap(long)]
+    pub(crate) watch: bool,
+    /// Serve album art over http on this port, at /art?file=URI and /current
+    #[cfg(feature = "artwork-http")]
+    #[clap(long)]
+    pub(crate) artwork_http_port: Option<u16>,
+    /// Serve metrics for Prometheus on this port, at /metrics
+    #[cfg(feature = "metrics-http")]
+    #[clap(long)]
+    pub(crate) metrics_port: Option<u16>,
+    /// Submit plays to ListenBrainz with this user token
+    #[cfg(feature = "scrobble")]
+    #[clap(long)]
+    pub(crate) listenbrainz_token: Option<String>,
 }
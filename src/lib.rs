@@ -0,0 +1,67 @@
+//! The music database and player behind mpdhaj, for programs that play music
+//! without speaking the mpd protocol to themselves.
+//!
+//! A [`System`] owns the database and the audio output. Everything it does
+//! goes through `&mut self`, share it between tasks the way the server does:
+//! in an `Arc<tokio::sync::Mutex<System>>`. It is `Send`, not `Sync`.
+//!
+//! The player reports songs that ended through its output events, hand those
+//! to [`System::handle_output_event`] or the queue will not move on. Changes
+//! anyone should show, like a new current song or an edited queue, come out
+//! of [`System::subscribe`] as the [`SubSystem`](mpd_protocol::SubSystem) that
+//! changed.
+//!
+//! Errors are [`color_eyre::Report`]s. Ones caused by the request, like an
+//! unknown queue id, carry an [`Ack`](mpd_protocol::ack::Ack) with the error
+//! mpd would give.
+//!
+//! ```no_run
+//! use mpdhaj::mpd_protocol::SubSystem;
+//! use mpdhaj::system::playback::Target;
+//! use mpdhaj::{Config, System};
+//!
+//! # async fn run() -> color_eyre::Result<()> {
+//! let db = rusqlite::Connection::open("library.sqlite")?;
+//! let mut system = System::open(Config::new("/home/me/Music".into()), db)?;
+//! system.rescan().await?;
+//!
+//! let mut events = system.player.take_output_events().unwrap();
+//! let mut changes = system.subscribe();
+//! system.add_many_to_queue(&["album/01.flac".into(), "album/02.flac".into()], None)?;
+//! system.set_playback(Target::Play).await?;
+//! loop {
+//!     tokio::select! {
+//!         Some(event) = events.recv() => system.handle_output_event(event).await,
+//!         Ok(SubSystem::Player) = changes.recv() => {
+//!             println!("now playing {:?}", system.current_song()?.map(|song| song.title));
+//!         }
+//!         else => return Ok(()),
+//!     }
+//! }
+//! # }
+//! ```
+
+pub mod mpd_protocol;
+pub mod player;
+pub mod playlist;
+pub mod scan;
+pub mod system;
+pub mod util;
+
+// the rest of the server, the binary runs these
+#[doc(hidden)]
+pub mod artwork;
+#[doc(hidden)]
+pub mod mpd_client;
+#[cfg(feature = "mpris")]
+#[doc(hidden)]
+pub mod mpris;
+#[doc(hidden)]
+pub mod proxy;
+#[cfg(feature = "scrobble")]
+#[doc(hidden)]
+pub mod scrobble;
+mod watch;
+mod waveform;
+
+pub use system::{Config, System};
@@ -1,4 +1,5 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use clap::Parser;
 use color_eyre::{Result, Section, eyre::Context};
@@ -6,22 +7,11 @@ use etcetera::BaseStrategy;
 use tokio::{fs::remove_file, sync::Mutex};
 use tracing_subscriber::fmt::format::FmtSpan;
 
-use crate::{
-    cli::{Cli, Commands},
-    system::System,
-};
+use mpdhaj::{Config, System, mpd_client, player, proxy, scan, system};
 
-mod cli;
-mod mpd_client;
-mod mpd_protocol;
-mod player;
-mod playlist;
-mod proxy;
-mod scan;
-mod system;
+use crate::cli::{Cli, Commands};
 
-/// pub so doctests work
-pub mod util;
+mod cli;
 
 #[allow(unexpected_cfgs)]
 #[tokio::main(flavor = "local")]
@@ -35,16 +25,83 @@ async fn main() -> Result<()> {
         Commands::Proxy { address } => proxy::handle_clients(options.port, &address).await?,
         Commands::Run(args) => {
             let system = Arc::new(Mutex::new({
-                let mut s = System::new(args.music_dir, args.playlist_dir)
-                    .wrap_err("Could not start system")?;
+                let buffer_time = args.buffer_time.filter(|micros| *micros > 0);
+                let config = Config {
+                    music_dir: args.music_dir,
+                    playlist_dir: args.playlist_dir,
+                    trim_silence: args.trim_silence,
+                    buffer_time: buffer_time.map(Duration::from_micros),
+                    skip_queued: args.skip_queued,
+                };
+                let mut s = System::new(config).wrap_err("Could not start system")?;
                 s.rescan().await?;
                 s
             }));
+            tokio::task::spawn({
+                let system = Arc::clone(&system);
+                async move {
+                    if let Err(e) = scan::backfill_playtime(system).await {
+                        tracing::warn!("Could not backfill playtimes: {e:#}");
+                    }
+                }
+            });
+            if args.watch {
+                let changes = scan::watch::start(&mut *system.lock().await);
+                tokio::task::spawn(scan::watch::apply(Arc::clone(&system), changes));
+            }
+            #[cfg(feature = "artwork-http")]
+            if let Some(port) = args.artwork_http_port {
+                let listener = tokio::net::TcpListener::bind(("0.0.0.0", port))
+                    .await
+                    .wrap_err("Could not open the artwork port")
+                    .with_note(|| format!("port: {port}"))?;
+                let system = Arc::clone(&system);
+                tokio::task::spawn(async move {
+                    if let Err(e) = mpdhaj::artwork::http::serve(system, listener).await {
+                        eprintln!("{e:?}");
+                    }
+                });
+            }
+            #[cfg(feature = "metrics-http")]
+            if let Some(port) = args.metrics_port {
+                let listener = tokio::net::TcpListener::bind(("0.0.0.0", port))
+                    .await
+                    .wrap_err("Could not open the metrics port")
+                    .with_note(|| format!("port: {port}"))?;
+                let system = Arc::clone(&system);
+                tokio::task::spawn(async move {
+                    if let Err(e) = system::metrics::serve(system, listener).await {
+                        eprintln!("{e:?}");
+                    }
+                });
+            }
+            #[cfg(feature = "scrobble")]
+            if let Some(token) = args.listenbrainz_token {
+                system.lock().await.scrobbling = true;
+                use mpdhaj::scrobble;
+                let listenbrainz = scrobble::ListenBrainz::new(scrobble::LISTENBRAINZ, token);
+                tokio::task::spawn(scrobble::run(Arc::clone(&system), listenbrainz));
+            }
+            // the MPRIS name is released when the connection is dropped
+            #[cfg(feature = "mpris")]
+            let _mpris = {
+                #[cfg(feature = "artwork-http")]
+                let art_port = args.artwork_http_port;
+                #[cfg(not(feature = "artwork-http"))]
+                let art_port = None;
+                mpdhaj::mpris::start(Arc::clone(&system), art_port)
+                    .await
+                    .inspect_err(|e| tracing::warn!("MPRIS is not available: {e:#}"))
+                    .ok()
+            };
             mpd_client::handle_clients(system, options.port).await?;
         }
         Commands::Scan(args) => {
-            let mut system = System::new(args.music_dir, args.playlist_dir)
-                .wrap_err("Could not start system")?;
+            let config = Config {
+                playlist_dir: args.playlist_dir,
+                ..Config::new(args.music_dir)
+            };
+            let mut system = System::new(config).wrap_err("Could not start system")?;
             system.rescan().await?
         }
         Commands::ListOutputs { beep } => {
@@ -1,43 +1,196 @@
 use std::collections::HashSet;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
-use color_eyre::eyre::{Context, OptionExt, eyre};
-use color_eyre::{Result, Section};
+use camino::Utf8Path;
+use color_eyre::eyre::{Context, OptionExt};
+use color_eyre::{Report, Result, Section};
 use futures::FutureExt;
+use rusqlite::Connection;
 use itertools::Itertools;
-use strum::{IntoEnumIterator, VariantNames};
+use strum::VariantNames;
 use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio::net::TcpListener;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, MutexGuard};
 use tokio::task;
 use tracing::{debug, info, instrument, warn};
 
-use crate::mpd_protocol::{self, response_format, PlaybackState, SubSystem, Tag, VolumeChange};
+use crate::artwork::Lookup;
+use crate::mpd_protocol::ack::{self, Ack, ErrorCode};
+use crate::mpd_protocol::{
+    self, response_format, BrowseEntry, QueueEntry, SongBlock, SubSystem, Tag, TimeOrOffset,
+    VolumeChange,
+};
+use crate::player::OpenFirst;
+use crate::playlist;
+use crate::scan::decoders;
+use crate::waveform;
+use crate::system::clients::{DEFAULT_PARTITION, Permission, Registration};
+use crate::system::list_files;
+use crate::system::metrics::Metrics;
+use crate::system::playback::Target;
+use crate::system::query::{self, Matching};
+use crate::system::updates;
 use crate::{mpd_protocol::Command, system::System};
 
-// stuff that's specific to a single client connection
+#[cfg(test)]
+mod e2e;
+mod pages;
+
+/// MPD's default for `binarylimit`
+const DEFAULT_BINARY_LIMIT: u64 = 8192;
+
+/// The commands we handle and the permission each needs, None if every
+/// client may use it. `commands` lists these, `notcommands` the other
+/// commands mpd has.
+const IMPLEMENTED: &[(&str, Option<Permission>)] = &[
+    ("add", Some(Permission::Add)),
+    ("addid", Some(Permission::Add)),
+    ("albumart", Some(Permission::Read)),
+    ("binarylimit", None),
+    ("channels", Some(Permission::Read)),
+    ("clear", Some(Permission::Control)),
+    ("clearerror", Some(Permission::Control)),
+    ("commands", None),
+    ("consume", Some(Permission::Control)),
+    ("count", Some(Permission::Read)),
+    ("currentsong", Some(Permission::Read)),
+    ("decoders", Some(Permission::Read)),
+    ("delete", Some(Permission::Control)),
+    ("deleteid", Some(Permission::Control)),
+    ("find", Some(Permission::Read)),
+    ("findadd", Some(Permission::Add)),
+    ("getvol", Some(Permission::Read)),
+    ("idle", Some(Permission::Read)),
+    ("list", Some(Permission::Read)),
+    ("listall", Some(Permission::Read)),
+    ("listallinfo", Some(Permission::Read)),
+    ("listfiles", Some(Permission::Read)),
+    ("listpartitions", Some(Permission::Read)),
+    ("listplaylists", Some(Permission::Read)),
+    ("load", Some(Permission::Add)),
+    ("lsinfo", Some(Permission::Read)),
+    ("move", Some(Permission::Control)),
+    ("moveid", Some(Permission::Control)),
+    ("next", Some(Permission::Control)),
+    ("noidle", Some(Permission::Read)),
+    ("notcommands", None),
+    ("outputset", Some(Permission::Admin)),
+    ("partition", Some(Permission::Read)),
+    ("pause", Some(Permission::Control)),
+    ("ping", None),
+    ("play", Some(Permission::Control)),
+    ("playid", Some(Permission::Control)),
+    ("playlistadd", Some(Permission::Control)),
+    ("playlistclear", Some(Permission::Control)),
+    ("playlistdelete", Some(Permission::Control)),
+    ("playlistid", Some(Permission::Read)),
+    ("playlistinfo", Some(Permission::Read)),
+    ("playlistmove", Some(Permission::Control)),
+    ("previous", Some(Permission::Control)),
+    ("random", Some(Permission::Control)),
+    ("readcomments", Some(Permission::Read)),
+    ("readmessages", Some(Permission::Read)),
+    ("readpicture", Some(Permission::Read)),
+    ("rename", Some(Permission::Control)),
+    ("repeat", Some(Permission::Control)),
+    ("rescan", Some(Permission::Control)),
+    ("rm", Some(Permission::Control)),
+    ("save", Some(Permission::Control)),
+    ("search", Some(Permission::Read)),
+    ("searchadd", Some(Permission::Add)),
+    ("searchaddpl", Some(Permission::Control)),
+    ("seek", Some(Permission::Control)),
+    ("seekcur", Some(Permission::Control)),
+    ("seekid", Some(Permission::Control)),
+    ("sendmessage", Some(Permission::Read)),
+    ("setvol", Some(Permission::Control)),
+    ("shuffle", Some(Permission::Control)),
+    ("single", Some(Permission::Control)),
+    ("stats", Some(Permission::Read)),
+    ("stats_internal", Some(Permission::Read)),
+    ("status", Some(Permission::Read)),
+    ("stop", Some(Permission::Control)),
+    ("subscribe", Some(Permission::Read)),
+    ("tagtypes", None),
+    ("unsubscribe", Some(Permission::Read)),
+    ("update", Some(Permission::Control)),
+    ("volume", Some(Permission::Control)),
+    ("x-mpdhaj", Some(Permission::Read)),
+];
+
+// stuff that's specific to a single client connection, what other clients
+// need to see lives in the registry
 pub struct ClientState {
+    pub registration: Registration,
+    /// Those the client wants to see, always some of [`Tag::SUPPORTED`]
     pub tag_types: HashSet<Tag>,
+    pub binary_limit: u64,
 }
 
-pub(crate) async fn handle_clients(system: Arc<Mutex<System>>, port: u16) -> Result<()> {
+impl ClientState {
+    fn new(registration: Registration) -> Self {
+        Self {
+            registration,
+            tag_types: Tag::SUPPORTED.iter().copied().collect(),
+            binary_limit: DEFAULT_BINARY_LIMIT,
+        }
+    }
+
+    fn partition(&self) -> String {
+        self.registration.with(|info| info.partition.clone())
+    }
+}
+
+pub async fn handle_clients(system: Arc<Mutex<System>>, port: u16) -> Result<()> {
     let listener = TcpListener::bind(format!("0.0.0.0:{port}")).await?;
+    serve(system, listener).await
+}
+
+/// Handles the clients connecting to `listener`, tests pass in one on an
+/// ephemeral port
+async fn serve(system: Arc<Mutex<System>>, listener: TcpListener) -> Result<()> {
+    let (clients, metrics, output_events, playlist_changes) = {
+        let mut system = system.lock().await;
+        (
+            system.clients.clone(),
+            Arc::clone(&system.metrics),
+            system.player.take_output_events(),
+            playlist::watch::start(&mut system),
+        )
+    };
+    task::spawn(playlist::watch::apply(Arc::clone(&system), playlist_changes));
+    task::spawn(updates::run(Arc::clone(&system)));
+    if let Some(mut output_events) = output_events {
+        let system = Arc::clone(&system);
+        task::spawn(async move {
+            while let Some(event) = output_events.recv().await {
+                system.lock().await.handle_output_event(event).await;
+            }
+        });
+    }
 
     loop {
-        let stream = match listener.accept().await {
-            Ok((stream, _addr)) => stream,
+        let (stream, registration) = match listener.accept().await {
+            Ok((stream, addr)) => {
+                metrics.connection();
+                (stream, clients.register(Some(addr)))
+            }
             Err(e) => return Err(e).wrap_err("Could not accept connection"),
         };
         let (reader, writer) = tokio::io::split(stream);
         let reader = BufReader::new(reader).lines();
         let system = Arc::clone(&system);
+        let metrics = Arc::clone(&metrics);
         task::spawn(async move {
-            if let Err(e) = handle_client(reader, writer, system).await {
+            let id = registration.id();
+            if let Err(e) = handle_client(reader, writer, system, metrics, registration).await {
                 // use eprintln instead of tracing::warn as color_eyre gives
                 // us pretty colors that we dont get to see with tracing
-                eprintln!("error handling client: {e:?}");
+                eprintln!("error handling client {id}: {e:?}");
             } else {
-                info!("Client disconnected");
+                info!("Client {id} disconnected");
             }
         });
     }
@@ -47,20 +200,29 @@ async fn handle_client(
     mut reader: tokio::io::Lines<impl AsyncBufRead + Unpin>,
     mut writer: impl AsyncWrite + Send + 'static + Unpin + Send,
     system: Arc<Mutex<System>>,
+    metrics: Arc<Metrics>,
+    registration: Registration,
 ) -> Result<()> {
     writer
         .write_all(format!("OK MPD {}\n", mpd_protocol::VERSION).as_bytes())
         .await
         .wrap_err("Could not send handshake to client")?;
-    let mut state = ClientState {
-        tag_types: Tag::iter().collect(),
-    };
+    let mut state = ClientState::new(registration);
 
-    while let Some(line) = reader
-        .next_line()
-        .await
-        .wrap_err("Could not get next line from client")?
-    {
+    // the line that ended an idle
+    let mut pending = None;
+    loop {
+        let line = match pending.take() {
+            Some(line) => line,
+            None => match reader
+                .next_line()
+                .await
+                .wrap_err("Could not get next line from client")?
+            {
+                Some(line) => line,
+                None => break,
+            },
+        };
         if line == "command_list_ok_begin" {
             handle_command_list(&mut reader, &mut writer, &system, &mut state, true).await?;
             continue;
@@ -69,27 +231,196 @@ async fn handle_client(
             continue;
         }
 
-        let command = Command::parse(&line)?;
-        let command = if let Command::Idle(sub_systems) = command {
-            let Some(command_after_idle) =
-                handle_idle(&mut reader, &mut writer, &system, sub_systems).await?
+        let command = match Command::parse(&line) {
+            Ok(command) => command,
+            Err(e) => {
+                let (name, e) = parse_failure(&line, e);
+                report_ack(&mut writer, e, 0, name).await?;
+                continue;
+            }
+        };
+        // clients usually idle again right after they are woken up
+        if let Command::Idle(sub_systems) = command {
+            let Some(line_after_idle) =
+                handle_idle(&mut reader, &mut writer, &state.registration, sub_systems).await?
             else {
                 return Ok(());
             };
-            command_after_idle
-        } else {
-            command
-        };
-        let mut response = perform_command(command, &system, &mut state).await?;
+            pending = Some(line_after_idle);
+            continue;
+        }
+        // clients ping to check the connection, that should not wait on a
+        // command holding the System lock
+        if let Command::Ping = command {
+            metrics.command(&command);
+            acknowledge(&mut writer).await?;
+            continue;
+        }
+        let name: &'static str = (&command).into();
+        match write_reply(command, &mut writer, &system, &mut state).await {
+            Ok(()) => acknowledge(&mut writer).await?,
+            Err(e) => report_ack(&mut writer, e, 0, name).await?,
+        }
+    }
+    Ok(())
+}
 
-        response.push_str("OK\n");
+/// Sends the client the [`Ack`](ack::Ack) in `error`, any other error is
+/// returned and closes the connection
+async fn report_ack(
+    writer: &mut (impl AsyncWrite + Unpin),
+    error: Report,
+    index: usize,
+    command: &str,
+) -> Result<()> {
+    let Some(ack) = ack::find(&error) else {
+        return Err(error);
+    };
+    warn!("{command} failed: {error:?}");
+    writer
+        .write_all(ack.line(index, command).as_bytes())
+        .await
+        .wrap_err("Failed to send error to client")
+}
+
+/// Writes the reply to `command`, long listings a page at a time
+async fn write_reply(
+    command: Command,
+    writer: &mut (impl AsyncWrite + Unpin),
+    system: &Mutex<System>,
+    client_state: &mut ClientState,
+) -> Result<()> {
+    if matches!(command, Command::AlbumArt(..) | Command::ReadPicture(..)) {
+        let reply = artwork_reply(&command, system, client_state).await?;
+        return writer
+            .write_all(&reply)
+            .await
+            .wrap_err("Failed to write response to client");
+    }
+    if let Command::Waveform(uri, buckets) = &command {
+        let reply = waveform_reply(uri, *buckets, system).await?;
+        return writer
+            .write_all(reply.as_bytes())
+            .await
+            .wrap_err("Failed to write response to client");
+    }
+    let Some(mut pages) = pages::paged(&command, &client_state.tag_types) else {
+        let response = perform_command(command, system, client_state).await?;
         debug!("reply: {response}");
-        writer
+        return writer
             .write_all(response.as_bytes())
             .await
+            .wrap_err("Failed to write response to client");
+    };
+    debug!("paged reply to: {command:?}");
+    let reader = {
+        let system = system.lock().await;
+        system.metrics.command(&command);
+        system.readers.as_ref().map(|readers| readers.get()).transpose()?
+    };
+    loop {
+        // the lock is released before the page is written
+        let page = match reader {
+            Some(ref reader) => pages.next_page(reader)?,
+            None => pages.next_page(&system.lock().await.db)?,
+        };
+        let Some(page) = page else {
+            return Ok(());
+        };
+        writer
+            .write_all(page.as_bytes())
+            .await
+            .wrap_err("Failed to write response to client")?;
+        writer
+            .flush()
+            .await
             .wrap_err("Failed to write response to client")?;
     }
-    Ok(())
+}
+
+/// `albumart` and `readpicture` send the art in chunks of at most
+/// `binarylimit` bytes, the client asks again with the offset of the next one
+async fn artwork_reply(
+    command: &Command,
+    system: &Mutex<System>,
+    client_state: &ClientState,
+) -> Result<Vec<u8>> {
+    let (uri, offset, lookup) = match command {
+        Command::AlbumArt(uri, offset) => (uri, *offset, Lookup::Album),
+        Command::ReadPicture(uri, offset) => (uri, *offset, Lookup::Embedded),
+        other => unreachable!("{other:?} has no binary reply"),
+    };
+    let (path, cache) = {
+        let system = system.lock().await;
+        system.metrics.command(command);
+        // album grids ask for the art of the album directories
+        let path = if lookup == Lookup::Album && system.is_directory(uri)? {
+            system.music_dir.join(uri)
+        } else {
+            system.song_path(uri)?
+        };
+        (path, Arc::clone(&system.artwork))
+    };
+    let art = task::spawn_blocking(move || cache.find(&path, lookup))
+        .await
+        .wrap_err("Artwork lookup panicked")??;
+    let Some(art) = art else {
+        return match lookup {
+            // like mpd, which only answers OK for songs without a picture
+            Lookup::Embedded => Ok(Vec::new()),
+            _ => Err(Ack::new(ErrorCode::NoExist, "No file exists"))
+                .with_note(|| format!("uri: {uri}")),
+        };
+    };
+
+    let rest = usize::try_from(offset)
+        .ok()
+        .and_then(|offset| art.data.get(offset..))
+        .ok_or_eyre("Bad file offset")
+        .with_note(|| format!("offset: {offset}, size: {}", art.data.len()))?;
+    let limit = usize::try_from(client_state.binary_limit).unwrap_or(usize::MAX);
+    let chunk = &rest[..rest.len().min(limit)];
+    let mut header = format!("size: {}\n", art.data.len());
+    if lookup == Lookup::Embedded {
+        header += &format!("type: {}\n", art.mime);
+    }
+    header += &format!("binary: {}\n", chunk.len());
+
+    let mut reply = header.into_bytes();
+    reply.extend_from_slice(chunk);
+    reply.push(b'\n');
+    Ok(reply)
+}
+
+/// Decodes the song without holding the System lock, clients drawing a list
+/// of seek bars ask for many at once
+async fn waveform_reply(
+    uri: &Utf8Path,
+    buckets: u32,
+    system: &Mutex<System>,
+) -> Result<String> {
+    if !(1..=waveform::MAX_BUCKETS).contains(&buckets) {
+        let message = format!(
+            "The number of buckets must be between 1 and {}",
+            waveform::MAX_BUCKETS
+        );
+        return Err(Ack::new(ErrorCode::Arg, message)).with_note(|| format!("buckets: {buckets}"));
+    }
+    let (path, decoder) = {
+        let system = system.lock().await;
+        system.metrics.command(&Command::Waveform(uri.to_owned(), buckets));
+        if let Some(waveform) = waveform::cached(&system.db, uri, buckets)? {
+            return Ok(waveform::format(&waveform));
+        }
+        (system.song_path(uri)?, Arc::clone(&system.waveforms))
+    };
+    let waveform = decoder
+        .waveform(path, buckets)
+        .await
+        .wrap_err("Could not decode the waveform")
+        .with_note(|| format!("uri: {uri}"))?;
+    waveform::store(&system.lock().await.db, uri, &waveform)?;
+    Ok(waveform::format(&waveform))
 }
 
 async fn handle_command_list(
@@ -101,12 +432,17 @@ async fn handle_command_list(
 ) -> Result<()> {
     debug!("handling command list");
     let mut command_executed = 0;
+    // read ahead while gathering playlistadds
+    let mut pending = None;
     loop {
-        let line = reader
-            .next_line()
-            .await
-            .wrap_err("Could not get next line from client")?
-            .ok_or_eyre("Connection closed before command list ended")?;
+        let line = match pending.take() {
+            Some(line) => line,
+            None => reader
+                .next_line()
+                .await
+                .wrap_err("Could not get next line from client")?
+                .ok_or_eyre("Connection closed before command list ended")?,
+        };
         if line == "command_list_end" {
             if ack_each_command {
                 for _ in 0..command_executed {
@@ -116,68 +452,132 @@ async fn handle_command_list(
             return acknowledge(writer).await;
         }
 
-        let command = Command::parse(&line)?;
-        if matches!(command, Command::Idle(_) | Command::NoIdle) {
-            return Err(eyre!("Idle and NoIde are not allowed in command lists"));
+        let mut executed = 1;
+        let (name, result) = match Command::parse(&line) {
+            Ok(Command::PlaylistClear(playlist)) => {
+                let (uris, next) = playlist_adds(reader, &playlist).await?;
+                pending = Some(next);
+                executed += uris.len();
+                let mut system = system.lock().await;
+                system
+                    .metrics
+                    .command(&Command::PlaylistClear(playlist.clone()));
+                let result = system
+                    .replace_playlist_with(&playlist, uris)
+                    .wrap_err("Could not fill the stored playlist");
+                ("playlistclear", result.map(|()| String::new()))
+            }
+            Ok(command @ (Command::Idle(_) | Command::NoIdle)) => {
+                let ack = Ack::new(
+                    ErrorCode::Arg,
+                    "idle and noidle are not allowed in command lists",
+                );
+                ((&command).into(), Err(ack.into()))
+            }
+            Ok(command) => {
+                let name: &'static str = (&command).into();
+                (name, write_reply(command, writer, system, client_state).await)
+            }
+            Err(e) => {
+                let (name, e) = parse_failure(&line, e);
+                (name, Err(e))
+            }
+        };
+        if let Err(e) = result {
+            if ack_each_command {
+                for _ in 0..command_executed {
+                    acknowledge_cmd_list_entry(writer).await?;
+                }
+            }
+            report_ack(writer, e, command_executed, name).await?;
+            if pending.as_deref() == Some("command_list_end") {
+                return Ok(());
+            }
+            // like mpd the rest of the list is not executed
+            while reader
+                .next_line()
+                .await
+                .wrap_err("Could not get next line from client")?
+                .is_some_and(|line| line != "command_list_end")
+            {}
+            return Ok(());
         }
-        let response = perform_command(command, system, client_state).await?;
-        command_executed += 1;
+        command_executed += executed;
+    }
+}
 
-        debug!("reply: {response}");
-        writer
-            .write_all(response.as_bytes())
+/// Clients fill a stored playlist with a `playlistclear` followed by a
+/// `playlistadd` per song. Taking those adds together replaces the playlist
+/// in one go, rather than leaving it empty for a while. Returns the first
+/// line that is not such an add as well.
+async fn playlist_adds(
+    reader: &mut tokio::io::Lines<impl AsyncBufRead + Unpin>,
+    playlist: &playlist::PlaylistName,
+) -> Result<(Vec<camino::Utf8PathBuf>, String)> {
+    let mut uris = Vec::new();
+    loop {
+        let line = reader
+            .next_line()
             .await
-            .wrap_err("Failed to write response to client")?;
+            .wrap_err("Could not get next line from client")?
+            .ok_or_eyre("Connection closed before command list ended")?;
+        match Command::parse(&line) {
+            Ok(Command::PlaylistAdd(name, uri, None)) if name == *playlist => uris.push(uri),
+            _ => return Ok((uris, line)),
+        }
     }
 }
 
+/// Waits for a change or for the client to end the idle, returns the line
+/// that follows. It is not parsed here so one that does not parse is
+/// ACKed like any other.
 #[tracing::instrument(skip_all, fields(sub_systems))]
 async fn handle_idle(
     reader: &mut tokio::io::Lines<impl AsyncBufRead + Unpin>,
     writer: &mut (impl AsyncWrite + 'static + Unpin),
-    system: &Arc<Mutex<System>>,
+    registration: &Registration,
     sub_systems: Vec<SubSystem>,
-) -> Result<Option<Command>> {
+) -> Result<Option<String>> {
     use futures_concurrency::prelude::*;
     debug!("Entering idle mode");
 
-    let mut rx = system.lock().await.idle(sub_systems);
     #[derive(Debug)]
     enum Potato {
-        MpdEvent(Option<SubSystem>),
+        MpdEvent(Vec<SubSystem>),
         NextLine(Result<Option<String>, std::io::Error>),
     }
     let next_line = reader.next_line().map(Potato::NextLine);
-    let next_event = rx.recv().map(Potato::MpdEvent);
+    let next_event = registration.idle(sub_systems).map(Potato::MpdEvent);
+    let woken_by = (next_line, next_event).race().await;
+    registration.end_idle();
 
-    Ok(Some(match (next_line, next_event).race().await {
-        Potato::MpdEvent(Some(sub_system)) => {
+    Ok(Some(match woken_by {
+        Potato::MpdEvent(changed) => {
             writer
-                .write_all(response_format::subsystem(sub_system).as_bytes())
+                .write_all(response_format::subsystems(&changed).as_bytes())
                 .await?;
             let Some(line) = reader.next_line().await? else {
                 return Ok(None);
             };
-            Command::parse(&line)?
+            line
         }
-        Potato::MpdEvent(None) => unreachable!("System should not drop ever"),
-        Potato::NextLine(Ok(Some(line))) => {
-            let command = Command::parse(&line)?;
-            if let Command::NoIdle = command {
+        Potato::NextLine(Ok(Some(line))) => match Command::parse(&line) {
+            Ok(Command::NoIdle) => {
                 acknowledge(writer).await?;
                 debug!("Waiting for command after idle");
                 let Some(line) = reader.next_line().await? else {
                     return Ok(None);
                 };
-                Command::parse(&line)?
-            } else {
+                line
+            }
+            _ => {
                 warn!(
                     "bad client, sent something other than noidle after idle. \
-                    The client send us: {command:?}"
+                    The client send us: {line:?}"
                 );
-                command
+                line
             }
-        }
+        },
         Potato::NextLine(Ok(None)) => {
             info!("client closed connection");
             return Ok(None);
@@ -186,6 +586,36 @@ async fn handle_idle(
     }))
 }
 
+/// `tagtype:` lines for the supported tags that pass `filter`
+fn tag_types(filter: impl Fn(&Tag) -> bool) -> String {
+    Tag::SUPPORTED
+        .iter()
+        .filter(|tag| filter(tag))
+        .map(|tag| format!("tagtype: {tag}\n"))
+        .collect()
+}
+
+/// Refuses the whole list if a tag is not one we list, rather than saying
+/// OK and then never sending it
+fn supported(tags: &[Tag]) -> Result<impl Iterator<Item = Tag>> {
+    if let Some(tag) = tags.iter().find(|tag| !Tag::SUPPORTED.contains(tag)) {
+        return Err(Ack::new(ErrorCode::Arg, format!("Unsupported tag type: {tag}")).into());
+    }
+    Ok(tags.iter().copied())
+}
+
+/// mpd's ACK for a line that did not parse and the name to put in it, which
+/// is empty for an unknown command
+fn parse_failure(line: &str, error: Report) -> (&str, Report) {
+    let name = line.split_whitespace().next().unwrap_or_default();
+    if Command::from_str(name).is_ok() {
+        (name, error.wrap_err(Ack::new(ErrorCode::Arg, "Bad arguments")))
+    } else {
+        let ack = Ack::new(ErrorCode::Unknown, format!("unknown command \"{name}\""));
+        ("", error.wrap_err(ack))
+    }
+}
+
 async fn acknowledge(writer: &mut (impl AsyncWrite + 'static + Unpin)) -> Result<()> {
     writer
         .write_all(b"OK\n")
@@ -202,44 +632,57 @@ async fn acknowledge_cmd_list_entry(
         .wrap_err("Failed to acknowledge cmd list item to client")
 }
 
-#[instrument(skip(system, client_state), ret)]
+#[instrument(skip(shared, client_state), ret)]
 pub async fn perform_command(
     request: Command,
-    system: &Mutex<System>,
+    shared: &Mutex<System>,
     client_state: &mut ClientState,
 ) -> color_eyre::Result<String> {
     use Command::*;
-    let mut system = system.lock().await;
+    let mut system = shared.lock().await;
+    system.metrics.command(&request);
+    if let LsInfo(uri) | ReadComments(uri) = &request {
+        system
+            .scan_if_unknown(uri)
+            .await
+            .wrap_err("Could not scan a path missing from the database")
+            .with_note(|| format!("uri: {uri}"))?;
+    }
+    check_partition(&request, &client_state.partition())?;
     Ok(match &request {
-        BinaryLimit(_) => String::new(),
-        Commands => response_format::to_string(&supported_command_list())?,
-        Status => {
-            response_format::to_string(&system.status()?).wrap_err("Failed to get system status")?
-        }
-        PlaylistInfo(_pos_or_range) => {
-            response_format::to_string(&system.queue().wrap_err("Failed to get current queue")?)?
+        BinaryLimit(limit) => {
+            client_state.binary_limit = *limit;
+            String::new()
         }
+        Commands => response_format::to_string(&command_list(client_state, true))?,
+        NotCommands => response_format::to_string(&command_list(client_state, false))?,
+        Decoders => response_format::to_string(&decoders::report())?,
+        Status => response_format::to_string(&status(&system, client_state)?)
+            .wrap_err("Failed to get system status")?,
         ListPlayLists => response_format::to_string(&system.playlists())
             .wrap_err("Failed to get list of playlists")?,
-        ListPlaylistInfo(playlist_name, _range) => response_format::to_string(
-            &system
+        ListPlaylistInfo(playlist_name, _range) => {
+            let playlist = system
                 .get_playlist(playlist_name)
                 .wrap_err("Failed to get playlist")
-                .with_note(|| format!("playlist name: {playlist_name:?}"))?,
-        )?,
+                .with_note(|| format!("playlist name: {playlist_name:?}"))?;
+            drop(system);
+            response_format::to_string(&playlist.only_tags(&client_state.tag_types))?
+        }
         PlaylistId(id) => {
+            let enabled = &client_state.tag_types;
             if let Some(id) = id {
-                response_format::to_string(&system.song_by_id(*id)?)?
+                let entry = system.song_by_id(*id)?.map(|entry| entry.only_tags(enabled));
+                response_format::to_string(&entry)?
             } else if let Some(current) = system.current_song()? {
-                response_format::to_string(&current)?
+                response_format::to_string(&current.only_tags(enabled))?
             } else {
                 String::new()
             }
         }
         Clear => {
-            system.clear()?;
-            system.playing = PlaybackState::Stop;
-            response_format::to_string(&system.status()?)?
+            system.clear().await?;
+            response_format::to_string(&status(&system, client_state)?)?
         }
         ListAll(dir) => response_format::to_string(
             &system
@@ -253,134 +696,1223 @@ pub async fn perform_command(
             window,
         }) => {
             if !group_by.is_empty() || query.is_some() || window.is_some() {
-                return Err(eyre!(
-                    "group_by/query/window in List command not yet supported"
-                ));
+                let message = "group_by/query/window in List command not yet supported";
+                return Err(Ack::new(ErrorCode::Unknown, message).into());
             }
 
-            let results = system
-                .list_tag(tag_to_list)
+            let tag_to_list = *tag_to_list;
+            let results = read(system, move |db| crate::system::list_tag(db, &tag_to_list))
+                .await
                 .wrap_err("Failed to list tags")
                 .with_note(|| format!("Tag type: {tag_to_list}"))?;
             response_format::to_string(&results)?
         }
-        LsInfo(song) => response_format::to_string(
-            &system
+        LsInfo(path) if system.is_directory(path)? => {
+            let mut entries = system.subdirectories(path)?;
+            entries.extend(system.songs_in(path)?.into_iter().map(|song| {
+                let entry =
+                    QueueEntry::from_song(song, None, None).only_tags(&client_state.tag_types);
+                BrowseEntry::File(SongBlock::Info(Box::new(entry)))
+            }));
+            // mpd shows them in the root too, clients browse to them there
+            if crate::scan::normalize_path(path).as_str().is_empty() {
+                entries.extend(system.browse_playlists());
+            }
+            response_format::to_string(&entries)?
+        }
+        LsInfo(song) => {
+            let song_info = system
                 .get_song_by_path(song)
                 .wrap_err("Failed to get song info")
-                .with_note(|| format!("song path: {song:?}"))?,
-        )?,
-        Volume(VolumeChange(volume)) => {
-            assert!((0..=100).contains(volume));
-            system.player.set_volume(*volume as f32/100.0);
-            system.db.execute("UPDATE state SET volume = ?", [volume])?;
+                .with_note(|| format!("song path: {song:?}"))?;
+            drop(system);
+            let entry = QueueEntry::from_song(song_info, None, None);
+            response_format::to_string(&entry.only_tags(&client_state.tag_types))?
+        }
+        ReadComments(uri) => {
+            let path = system.song_path(uri)?;
+            drop(system);
+            crate::scan::read_comments(&path)
+                .await?
+                .into_iter()
+                .map(|(key, value)| format!("{key}: {}\n", value.replace('\n', " ")))
+                .collect()
+        }
+        Update(path) | Rescan(path) => {
+            let id = system.queue_update(path.as_deref())?;
+            format!("updating_db: {id}\n")
+        }
+        SetVol(volume) => {
+            let volume = mpd_protocol::Volume::try_from(*volume)
+                .map_err(|e| Ack::new(ErrorCode::Arg, e.to_string()))?;
+            system.set_volume(volume.get())?;
             String::new()
         },
-        Play(pos) => {
-            system.playing = PlaybackState::Play;
-            system.db.execute("UPDATE state SET paused = ?", [false])?;
-            let path = if let Some(pos) = pos {
-                system.song_by_pos(*pos)
-            } else {
-                system.current_song()
-            }?
-            .ok_or_eyre("Couldn't find song")?
-            .path;
-
-            let path = if path.is_absolute() {
-                path
-            } else {
-                system.music_dir.join(path)
-            };
-
+        Volume(VolumeChange(change)) => {
+            system.change_volume(*change)?;
+            String::new()
+        }
+        GetVol => format!("volume: {}\n", system.volume()?.get()),
+        Random(random) => {
+            system.set_random(*random)?;
+            String::new()
+        }
+        Repeat(repeat) => {
+            system.set_repeat(*repeat)?;
+            String::new()
+        }
+        Single(single) => {
+            system.set_single(*single)?;
+            String::new()
+        }
+        Consume(consume) => {
+            system.set_consume(*consume)?;
+            String::new()
+        }
+        Play(_) => {
+            system = start_playback(&request, shared, system).await?;
+            response_format::to_string(&status(&system, client_state)?)?
+        }
+        Pause(state) => {
+            system.set_playback(Target::Pause(*state)).await?;
+            response_format::to_string(&status(&system, client_state)?)?
+        }
+        Stop => {
+            system.set_playback(Target::Stop).await?;
+            response_format::to_string(&status(&system, client_state)?)?
+        }
+        Next | Previous | PlayId(_) | Seek(..) | SeekId(..) | SeekCur(_) => {
+            start_playback(&request, shared, system).await?;
+            String::new()
+        }
+        Delete(what) => {
             system
-                .player
-                .add(&path)
+                .delete_pos_or_range(*what)
                 .await
-                .wrap_err("Could not play song")?;
-            response_format::to_string(&system.status()?)?
+                .wrap_err("Could not delete from the queue")?;
+            String::new()
         }
-        Pause(state) => {
-            system.playing = match state {
-                Some(true) => PlaybackState::Pause,
-                Some(false) => PlaybackState::Play,
-                None => system.playing.toggle(),
+        DeleteId(id) => {
+            system
+                .delete_id(*id)
+                .await
+                .wrap_err("Could not delete from the queue")?;
+            String::new()
+        }
+        Shuffle(range) => {
+            system.shuffle(*range).wrap_err("Could not shuffle the queue")?;
+            String::new()
+        }
+        Move(what, to) => {
+            let Some(what) = what else {
+                return Err(Ack::new(ErrorCode::Arg, "Bad song index").into());
             };
-            system.db.execute("UPDATE state SET paused = ?", [system.playing == PlaybackState::Pause])?;
-            if system.playing == PlaybackState::Play {
-                system.player.unpause();
-            } else {
-                system.player.pause();
-            }
-            response_format::to_string(&system.status()?)?
+            system
+                .move_entries(*what, *to)
+                .wrap_err("Could not move within the queue")?;
+            String::new()
+        }
+        MoveId(id, to) => {
+            system
+                .move_id(*id, *to)
+                .wrap_err("Could not move within the queue")?;
+            String::new()
+        }
+        Load(name, range, position) => {
+            system
+                .load_playlist(name, *range, *position)
+                .wrap_err("Could not load the stored playlist")?;
+            String::new()
+        }
+        Save(name, mode) => {
+            system
+                .save_queue(name, mode.unwrap_or_default())
+                .wrap_err("Could not save the queue")?;
+            String::new()
+        }
+        Rename(from, to) => {
+            system
+                .rename_playlist(from, to)
+                .wrap_err("Could not rename the stored playlist")?;
+            String::new()
+        }
+        Rm(name) => {
+            system
+                .remove_playlist(name)
+                .wrap_err("Could not remove the stored playlist")?;
+            String::new()
+        }
+        PlaylistClear(name) => {
+            system
+                .replace_playlist_with(name, Vec::<playlist::PlaylistEntry>::new())
+                .wrap_err("Could not clear the stored playlist")?;
+            String::new()
+        }
+        PlaylistAdd(name, uri, position) => {
+            system
+                .playlist_add(name, uri, *position)
+                .wrap_err("Could not add to the stored playlist")?;
+            String::new()
+        }
+        PlaylistDelete(name, what) => {
+            system
+                .playlist_delete(name, *what)
+                .wrap_err("Could not delete from the stored playlist")?;
+            String::new()
+        }
+        PlaylistMove(name, Some(what), to) => {
+            system
+                .playlist_move(name, *what, *to)
+                .wrap_err("Could not move within the stored playlist")?;
+            String::new()
+        }
+        Add(dir, position) if system.is_directory(dir)? => {
+            let songs = system
+                .list_all_in(dir)?
+                .into_iter()
+                .filter_map(BrowseEntry::into_file)
+                .collect_vec();
+            system
+                .add_many_to_queue(&songs, *position)
+                .map_err(ack::or_system)
+                .wrap_err("Failed to add directory to queue")
+                .with_note(|| format!("directory: {dir:?}"))?;
+            String::new()
         }
-        Stop => {
-            system.playing = PlaybackState::Stop;
-            system.player.pause(); // TODO: actually stop?
-            response_format::to_string(&system.status()?)?
-        }
-        Next => todo!(),
-        Previous => todo!(),
-        PlayId(_pos_in_playlist) => todo!(),
-        Load(_playlist_name, _range, _position) => todo!(),
         add @ (Add(song, position) | AddId(song, position)) => {
-            // TODO: handle add with directory (adds all recursively)
             let id = system
                 .add_to_queue(song, position)
+                .map_err(ack::or_system)
                 .wrap_err("Failed to add song to queue")
                 .with_note(|| format!("song path: {song:?}"))
                 .with_note(|| format!("position: {position:?}"))?;
             if matches!(add, Add(..)) {
                 String::new()
             } else {
-                format!("Id: {}", id.0)
+                format!("Id: {}\n", id.0)
             }
         }
-        Find(query, _sort, _range) => response_format::to_string(
-            &system
-                .handle_find(query)
-                .wrap_err("Failed to handle find")
-                .with_note(|| format!("query: {query:?}"))?,
-        )?,
-        FindAdd(query, _sort, _range, position) => {
+        Find(query, sort, window) => {
+            let (owned, sort, window) = (query.clone(), *sort, window.clone());
+            let found = read(system, move |db| {
+                query::handle_find(db, &owned, sort.as_ref(), window, Matching::Exact)
+            })
+            .await
+            .wrap_err("Failed to handle find")
+            .with_note(|| format!("query: {query:?}"))?;
+            response_format::to_string(&found)?
+        }
+        Search(query, sort, window) => {
+            let (owned, sort, window) = (query.clone(), *sort, window.clone());
+            let found = read(system, move |db| {
+                query::handle_find(db, &owned, sort.as_ref(), window, Matching::IgnoreCase)
+            })
+            .await
+            .wrap_err("Failed to handle search")
+            .with_note(|| format!("query: {query:?}"))?;
+            response_format::to_string(&found)?
+        }
+        Count(query, group) => {
+            let (owned, group_by) = (query.clone(), *group);
+            let counts = read(system, move |db| query::count(db, &owned, group_by))
+                .await
+                .wrap_err("Failed to count")
+                .with_note(|| format!("query: {query:?}"))?;
+            let mut response = String::new();
+            for (value, count) in counts {
+                if let Some((tag, value)) = group.zip(value) {
+                    response += &format!("{tag}: {value}\n");
+                }
+                response += &response_format::to_string(&count)?;
+            }
+            response
+        }
+        Undecodable => {
+            let songs = read(system, decoders::undecodable)
+                .await
+                .wrap_err("Failed to list the songs that do not decode")?;
+            response_format::to_string(&songs)?
+        }
+        FindAdd(query, sort, window, position) => {
             let results = system
-                .handle_find(query)
+                .handle_find(query, sort.as_ref(), window.clone())
                 .wrap_err("Failed to handle find")
                 .with_note(|| format!("query: {query:?}"))?;
-            for result in results {
+            let paths = results.into_iter().map(|result| result.path).collect_vec();
+            system
+                .add_many_to_queue(&paths, *position)
+                .wrap_err("Could not add matching songs to queue")?;
+            String::new()
+        }
+        SearchAdd(query, sort, range, position, skip_queued) => {
+            let results = system
+                .handle_search(query, sort.as_ref(), range.map(|range| range.window()))
+                .wrap_err("Failed to handle search")
+                .with_note(|| format!("query: {query:?}"))?;
+            let paths = results.into_iter().map(|result| result.path).collect_vec();
+            if skip_queued.unwrap_or(system.skip_queued) {
+                let (added, skipped) = system
+                    .add_unqueued_to_queue(&paths, *position)
+                    .wrap_err("Could not add matching songs to queue")?;
+                let added = added.len();
+                tracing::info!("searchadd added {added} songs, skipped {skipped} already queued");
+            } else {
                 system
-                    .add_to_queue(&result.path, position)
-                    .wrap_err("Could not add matching song to queue")
-                    .with_note(|| format!("song: {result:?}"))?;
+                    .add_many_to_queue(&paths, *position)
+                    .wrap_err("Could not add matching songs to queue")?;
             }
             String::new()
         }
+        SearchAddPl(name, query, sort, range, position, replace) => {
+            let results = system
+                .handle_search(query, sort.as_ref(), range.map(|range| range.window()))
+                .wrap_err("Failed to handle search")
+                .with_note(|| format!("query: {query:?}"))?;
+            let found = results
+                .into_iter()
+                .map(|result| playlist::PlaylistEntry::new(result.path));
+            let entries = if *replace {
+                found.collect_vec()
+            } else {
+                let mut entries = system
+                    .playlists
+                    .get(name)
+                    .map(|playlist| playlist.entries.clone())
+                    .unwrap_or_default();
+                let at = match position {
+                    None => entries.len(),
+                    Some(mpd_protocol::Position::Absolute(at)) if *at as usize <= entries.len() => {
+                        *at as usize
+                    }
+                    Some(_) => return Err(Ack::new(ErrorCode::Arg, "Bad song index").into()),
+                };
+                entries.splice(at..at, found);
+                entries
+            };
+            system
+                .replace_playlist_with(name, entries)
+                .wrap_err("Could not add matching songs to the stored playlist")?;
+            String::new()
+        }
         CurrentSong => response_format::to_string(
             &system
                 .current_song()
-                .wrap_err("Could not get current song")?,
+                .wrap_err("Could not get current song")?
+                .map(|entry| entry.only_tags(&client_state.tag_types)),
         )?,
 
+        TagTypes => tag_types(|tag| client_state.tag_types.contains(tag)),
+        TagTypesAvailable => tag_types(|_| true),
         TagTypesEnable(tags) => {
-            client_state.tag_types.extend(tags);
+            client_state.tag_types.extend(supported(tags)?);
+            String::new()
+        }
+        TagTypesDisable(tags) => {
+            client_state.tag_types.retain(|tag| !tags.contains(tag));
+            String::new()
+        }
+        TagTypesReset(tags) => {
+            client_state.tag_types = supported(tags)?.collect();
             String::new()
         }
         TagTypesClear => {
             client_state.tag_types.clear();
             String::new()
         }
+        TagTypesAll => {
+            client_state.tag_types = Tag::SUPPORTED.iter().copied().collect();
+            String::new()
+        }
+
+        OutputSet(0, attribute, value) if attribute == "balance" => {
+            let balance: f32 = value
+                .parse()
+                .wrap_err(Ack::new(ErrorCode::Arg, "balance must be a number"))
+                .with_note(|| format!("balance: {value}"))?;
+            if !(-1.0..=1.0).contains(&balance) {
+                let message = format!("balance must be between -1 and 1, got: {balance}");
+                return Err(Ack::new(ErrorCode::Arg, message).into());
+            }
+            system.player.set_balance(balance);
+            system.notify(SubSystem::Output);
+            String::new()
+        }
+        OutputSet(0, attribute, value) if attribute == "buffer_time" => {
+            let micros: u64 = value
+                .parse()
+                .wrap_err(Ack::new(
+                    ErrorCode::Arg,
+                    "buffer_time must be a whole number of microseconds",
+                ))
+                .with_note(|| format!("buffer_time: {value}"))?;
+            // zero leaves it to the device, like not configuring it
+            let buffer_time = (micros > 0).then(|| Duration::from_micros(micros));
+            system.player.set_buffer_time(buffer_time);
+            system.notify(SubSystem::Output);
+            String::new()
+        }
+        OutputSet(id, attribute, _) => {
+            let message = format!("Output {id} has no attribute: {attribute}");
+            return Err(Ack::new(ErrorCode::NoExist, message).into());
+        }
+
+        Subscribe(channel) => {
+            if !client_state
+                .registration
+                .with(|info| info.subscriptions.insert(channel.clone()))
+            {
+                let message = format!("Already subscribed to channel: {}", channel.0);
+                return Err(Ack::new(ErrorCode::Exist, message).into());
+            }
+            system.notify(SubSystem::Subscription);
+            String::new()
+        }
+        Unsubscribe(channel) => {
+            if !client_state
+                .registration
+                .with(|info| info.subscriptions.remove(channel))
+            {
+                let message = format!("Not subscribed to channel: {}", channel.0);
+                return Err(Ack::new(ErrorCode::NoExist, message).into());
+            }
+            system.notify(SubSystem::Subscription);
+            String::new()
+        }
+        Channels => response_format::to_string(
+            &client_state
+                .registration
+                .clients()
+                .channels()
+                .into_iter()
+                .map(|channel| format!("channel: {}", channel.0))
+                .collect_vec(),
+        )?,
+        SendMessage(channel, message) => {
+            if client_state
+                .registration
+                .clients()
+                .send_message(channel, message)
+                == 0
+            {
+                let message = format!("No such channel: {}", channel.0);
+                return Err(Ack::new(ErrorCode::NoExist, message).into());
+            }
+            String::new()
+        }
+        ReadMessages => response_format::to_string(
+            &client_state
+                .registration
+                .with(|info| info.messages.drain(..).collect_vec())
+                .into_iter()
+                .flat_map(|(channel, message)| {
+                    [format!("channel: {}", channel.0), format!("message: {message}")]
+                })
+                .collect_vec(),
+        )?,
+
+        Partition(name) if name == DEFAULT_PARTITION => {
+            client_state
+                .registration
+                .with(|info| info.partition = name.clone());
+            String::new()
+        }
+        Partition(name) => {
+            return Err(Ack::new(ErrorCode::NoExist, "partition does not exist"))
+                .with_note(|| format!("partition: {name}"));
+        }
+        ListPartitions => format!("partition: {DEFAULT_PARTITION}\n"),
+        NewPartition(name) | DelPartition(name) => {
+            // clients wait for this before they look at the partitions again
+            system.notify(SubSystem::Partition);
+            let message = format!("Only the {DEFAULT_PARTITION} partition is supported");
+            return Err(Ack::new(ErrorCode::Unknown, message))
+                .with_note(|| format!("partition: {name}"));
+        }
+
+        ClearError => {
+            system.player.clear_error();
+            String::new()
+        }
 
-        Stats => todo!(), // there is some commented out code already, search for that
-        Idle(_) | NoIdle => panic!("These should be handled in the outer loop"),
+        Stats => response_format::to_string(&system.stats()?)?,
+        StatsInternal => crate::system::metrics::render(&system)?,
+        Idle(_) => panic!("This should be handled in the outer loop"),
+        AlbumArt(..) | ReadPicture(..) => panic!("Binary replies are written by write_reply"),
+        Waveform(..) => panic!("Waveforms are decoded by write_reply"),
+        ListAllInfo(_) | PlaylistInfo(_) => panic!("Written a page at a time by write_reply"),
+        ListFiles(path) => {
+            let (music_dir, path) = (system.music_dir.clone(), path.clone());
+            drop(system);
+            let entries = task::spawn_blocking(move || list_files::list(&music_dir, &path))
+                .await
+                .wrap_err("Listing files panicked")?
+                .wrap_err("Could not list files")?;
+            response_format::to_string(&entries)?
+        }
+        // only means something while idling, handle_idle takes care of that
+        NoIdle => String::new(),
         Ping => String::new(),
-        other => unimplemented!("{other:?}"),
+        other => return Err(not_implemented()).with_note(|| format!("command: {other:?}")),
     })
 }
 
-fn supported_command_list() -> Vec<String> {
+/// For commands that parse but do nothing yet, a client must not be able to
+/// take the server down with them
+fn not_implemented() -> Report {
+    Ack::new(ErrorCode::Unknown, "Not implemented").into()
+}
+
+/// Runs `f` on a connection of its own when there is one, the System lock is
+/// released first so other clients go on meanwhile. The query runs on the
+/// blocking pool, a slow disk does not hold up the runtime.
+async fn read<T: Send + 'static>(
+    system: MutexGuard<'_, System>,
+    f: impl FnOnce(&Connection) -> Result<T> + Send + 'static,
+) -> Result<T> {
+    let Some(readers) = system.readers.clone() else {
+        return f(&system.db);
+    };
+    drop(system);
+    task::spawn_blocking(move || f(&readers.get()?))
+        .await
+        .wrap_err("Database query panicked")?
+}
+
+/// Runs a command that may start a song. The System lock is released while
+/// the song's file is opened, a slow disk does not hold up other clients.
+/// Until the song starts the command only reads, so it runs again from the
+/// top once the file is open.
+async fn start_playback<'a>(
+    request: &Command,
+    shared: &'a Mutex<System>,
+    mut system: MutexGuard<'a, System>,
+) -> Result<MutexGuard<'a, System>> {
+    loop {
+        system.player.open_first(true);
+        let result = playback(request, &mut system).await;
+        system.player.open_first(false);
+        let Err(e) = result else {
+            return Ok(system);
+        };
+        let Some(OpenFirst(path)) = e.downcast_ref::<OpenFirst>() else {
+            return Err(e);
+        };
+        let path = path.clone();
+        let opener = system.player.opener();
+        drop(system);
+        opener.open(path).await;
+        system = shared.lock().await;
+    }
+}
+
+/// The part of [`start_playback`] that runs under the lock
+async fn playback(request: &Command, system: &mut System) -> Result<()> {
+    use Command::*;
+    match request {
+        Play(pos) => {
+            let target = pos.map_or(Target::Play, Target::PlayAt);
+            system
+                .set_playback(target)
+                .await
+                .wrap_err("Could not play song")
+        }
+        Next => system.next().await.wrap_err("Could not skip to the next song"),
+        Previous => system
+            .previous()
+            .await
+            .wrap_err("Could not skip to the previous song"),
+        PlayId(id) => system.play_id(*id).await.wrap_err("Could not play song"),
+        Seek(pos, secs) => {
+            let to = TimeOrOffset::Absolute(*secs);
+            system.seek(Some(*pos), to).await.wrap_err("Could not seek")
+        }
+        SeekId(id, secs) => {
+            let to = TimeOrOffset::Absolute(*secs);
+            system.seek_id(*id, to).await.wrap_err("Could not seek")
+        }
+        SeekCur(to) => system.seek(None, *to).await.wrap_err("Could not seek"),
+        other => unreachable!("{other:?} does not start songs"),
+    }
+}
+
+/// Queue ids are handed out per partition, a client may only use the ids of
+/// the partition it is in. For now every id belongs to the default one.
+fn check_partition(request: &Command, partition: &str) -> Result<()> {
+    use Command::*;
+    let takes_id = matches!(
+        request,
+        PlayId(Some(_))
+            | SeekId(..)
+            | DeleteId(_)
+            | MoveId(..)
+            | PlaylistId(Some(_))
+            | PrioId(..)
+            | RangeId(..)
+            | SwapId(..)
+            | AddTagId(..)
+            | ClearTagId(..)
+    );
+    if takes_id && partition != DEFAULT_PARTITION {
+        return Err(Ack::new(ErrorCode::NoExist, "No such song"))
+            .with_note(|| format!("the queue ids belong to partition {DEFAULT_PARTITION}"))
+            .with_note(|| format!("the client is in partition {partition}"));
+    }
+    Ok(())
+}
+
+/// Like [`System::status`] but with the partition this client is in
+fn status(system: &System, client_state: &ClientState) -> Result<mpd_protocol::Status> {
+    let mut status = system.status()?;
+    status.partition = client_state.partition();
+    Ok(status)
+}
+
+/// The commands this client may use, or all the others
+fn command_list(client_state: &ClientState, allowed: bool) -> Vec<String> {
+    let permissions = client_state
+        .registration
+        .with(|info| info.permissions.clone());
+    let usable = IMPLEMENTED
+        .iter()
+        .filter(|(_, needs)| needs.is_none_or(|needs| permissions.contains(&needs)))
+        .map(|(name, _)| *name)
+        .collect_vec();
     Command::VARIANTS
         .iter()
-        .map(|name| name.replace("-", ""))
+        .copied()
+        .chain(IMPLEMENTED.iter().map(|(name, _)| *name))
+        .filter(|name| usable.contains(name) == allowed)
+        .sorted()
+        .dedup()
         .map(|command| format!("command: {command}"))
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::panic::AssertUnwindSafe;
+
+    use rusqlite::Connection;
+
+    use super::*;
+    use crate::player::Player;
+    use crate::player::device::Silent;
+    use crate::system::empty_system;
+
+    /// Arguments that do no harm for every implemented command
+    const EXAMPLES: &[&str] = &[
+        "add a.flac",
+        "addid a.flac",
+        "albumart a.flac 0",
+        "binarylimit 4096",
+        "channels",
+        "clear",
+        "clearerror",
+        "commands",
+        "consume oneshot",
+        r#"count "((Artist == a))" group Album"#,
+        "currentsong",
+        "decoders",
+        "delete 0",
+        "deleteid 1",
+        r#"find "((Artist == a))""#,
+        r#"findadd "((Artist == a))""#,
+        "getvol",
+        "idle",
+        "list Artist",
+        "listall",
+        "listallinfo",
+        "listfiles",
+        "listpartitions",
+        "listplaylists",
+        "load mix.m3u",
+        "lsinfo",
+        "move 0 1",
+        "moveid 1 +0",
+        "next",
+        "noidle",
+        "notcommands",
+        "outputset 0 balance 0",
+        "partition default",
+        "pause",
+        "ping",
+        "play",
+        "playid",
+        "playlistadd mix.m3u a.flac",
+        "playlistclear mix.m3u",
+        "playlistdelete mix.m3u 0",
+        "playlistid",
+        "playlistinfo",
+        "playlistmove mix.m3u 0 0",
+        "previous",
+        "random 0",
+        "readcomments a.flac",
+        "readmessages",
+        "readpicture a.flac 0",
+        "rename mix.m3u other.m3u",
+        "repeat 0",
+        "rescan",
+        "rm mix.m3u",
+        "save mix.m3u",
+        r#"search "((Artist == a))""#,
+        r#"searchadd "((Artist == a))""#,
+        r#"searchaddpl mix.m3u "((Artist == a))""#,
+        "seek 0 1",
+        "seekcur +1",
+        "seekid 1 1",
+        "sendmessage chat hi",
+        "setvol 50",
+        "shuffle",
+        "single 0",
+        "stats",
+        "stats_internal",
+        "status",
+        "stop",
+        "subscribe chat",
+        "tagtypes",
+        "unsubscribe chat",
+        "update",
+        "volume +5",
+        "x-mpdhaj waveform a.flac 100",
+    ];
+
+    #[tokio::test]
+    async fn listed_commands_are_implemented() {
+        let system = empty_system(Connection::open_in_memory().unwrap());
+        let mut state = ClientState::new(system.clients.register(None));
+        let system = Mutex::new(system);
+
+        let listed = command_list(&state, true);
+        assert_eq!(listed.len(), IMPLEMENTED.len());
+        for line in listed {
+            let name = line.strip_prefix("command: ").unwrap();
+            let example = EXAMPLES
+                .iter()
+                .find(|example| example.split(' ').next() == Some(name))
+                .unwrap_or_else(|| panic!("no example for {name}"));
+            let command = Command::parse(example)
+                .unwrap_or_else(|e| panic!("{example} does not parse: {e:?}"));
+            if matches!(command, Command::Idle(_)) {
+                continue; // handled by the connection loop
+            }
+            // errors are fine, the queue is empty after all
+            let mut reply = Vec::new();
+            let result = AssertUnwindSafe(write_reply(command, &mut reply, &system, &mut state))
+                .catch_unwind()
+                .await;
+            let Ok(result) = result else {
+                panic!("{example} panicked");
+            };
+            let unknown = result.as_ref().err().and_then(ack::find);
+            assert!(
+                unknown.is_none_or(|ack| ack.code != ErrorCode::Unknown),
+                "{example} is not implemented"
+            );
+        }
+    }
+
+    #[test]
+    fn missing_permissions_move_commands_to_notcommands() {
+        let clients = crate::system::clients::Clients::default();
+        let state = ClientState::new(clients.register(None));
+        state
+            .registration
+            .with(|info| info.permissions.remove(&Permission::Admin));
+
+        let commands = command_list(&state, true);
+        let not_commands = command_list(&state, false);
+        let outputset = "command: outputset".to_owned();
+        assert!(!commands.contains(&outputset));
+        assert!(not_commands.contains(&outputset));
+        assert!(not_commands.contains(&"command: getfingerprint".to_owned()));
+        assert!(commands.iter().all(|command| !not_commands.contains(command)));
+    }
+
+    #[tokio::test]
+    async fn find_sorted_by_last_modified_lists_the_newest_first() {
+        let system = empty_system(Connection::open_in_memory().unwrap());
+        for i in 0..12 {
+            system
+                .db
+                .execute(
+                    "INSERT INTO songs (path, mtime, date_added, artist)
+                        VALUES (?1, ?2, '2025-11-07 15:33:17', 'a')",
+                    rusqlite::params![format!("{i}.flac"), 1_750_000_000 + i * 60],
+                )
+                .unwrap();
+        }
+        let mut state = ClientState::new(system.clients.register(None));
+        let system = Mutex::new(system);
+
+        let command =
+            Command::parse(r#"find "((Artist == a))" sort -Last-Modified window 0:10"#).unwrap();
+        let response = perform_command(command, &system, &mut state).await.unwrap();
+        let files = response
+            .lines()
+            .filter_map(|line| line.strip_prefix("file: "))
+            .collect_vec();
+        let newest_first = (2..12).rev().map(|i| format!("{i}.flac")).collect_vec();
+        assert_eq!(files, newest_first);
+        assert!(response.starts_with(
+            "file: 11.flac\nLast-Modified: 2025-06-15T15:17:40Z\nAdded: 2025-11-07T15:33:17Z\n"
+        ));
+    }
+
+    #[tokio::test]
+    async fn lsinfo_lists_a_file_like_playlistinfo_without_its_place_in_the_queue() {
+        let system = empty_system(Connection::open_in_memory().unwrap());
+        system
+            .db
+            .execute_batch(
+                "INSERT INTO songs (path, mtime, date_added, duration, title, artist, album,
+                                    album_artist, track, date, genre, label, disc,
+                                    sample_rate, bit_depth, channels)
+                    VALUES ('Taylor Swift/1989/01 Welcome To New York.mp3', 1750025186,
+                            '2025-11-07 15:33:05', 212.6, 'Welcome To New York',
+                            'Taylor Swift', '1989 (Deluxe)', 'Taylor Swift', 19, '2014',
+                            'Country & Folk', 'Taylor Swift', 1, 44100, 16, 2);",
+            )
+            .unwrap();
+        let mut state = ClientState::new(system.clients.register(None));
+        let system = Mutex::new(system);
+
+        let command = Command::parse(r#"lsinfo "Taylor Swift/1989/01 Welcome To New York.mp3""#);
+        let response = perform_command(command.unwrap(), &system, &mut state)
+            .await
+            .unwrap();
+        pretty_assertions::assert_eq!(
+            response,
+            "file: Taylor Swift/1989/01 Welcome To New York.mp3
+Last-Modified: 2025-06-15T22:06:26Z
+Added: 2025-11-07T15:33:05Z
+Format: 44100:16:2
+Artist: Taylor Swift
+AlbumArtist: Taylor Swift
+Title: Welcome To New York
+Album: 1989 (Deluxe)
+Track: 19
+Date: 2014
+Genre: Country & Folk
+Label: Taylor Swift
+Disc: 1
+duration: 212.600
+"
+        );
+    }
+
+    #[tokio::test]
+    async fn lsinfo_lists_one_level_of_a_directory() {
+        use crate::playlist::{Playlist, PlaylistName};
+
+        let mut system = empty_system(Connection::open_in_memory().unwrap());
+        system
+            .db
+            .execute_batch(
+                "INSERT INTO songs (path, mtime) VALUES
+                    ('a/b/c.mp3', 0), ('a/d.mp3', 0), ('a/b/e/f.mp3', 0), ('g.mp3', 0);
+                INSERT INTO directories (path, parent, mtime) VALUES
+                    ('a', '', '2024-01-01T00:00:00Z'),
+                    ('a/b', 'a', '2024-01-02T00:00:00Z'),
+                    ('a/b/e', 'a/b', '2024-01-03T00:00:00Z');",
+            )
+            .unwrap();
+        system.playlists.insert(
+            PlaylistName("mix".to_string()),
+            Playlist {
+                entries: Vec::new(),
+                last_modified: "2024-02-01T00:00:00Z".parse().unwrap(),
+            },
+        );
+        let mut state = ClientState::new(system.clients.register(None));
+        let system = Mutex::new(system);
+        let mut perform = async |line: &str| {
+            let command = Command::parse(line).unwrap();
+            perform_command(command, &system, &mut state).await.unwrap()
+        };
+        let entries = |listing: String| {
+            listing
+                .lines()
+                .filter(|line| {
+                    ["directory: ", "file: ", "playlist: "]
+                        .iter()
+                        .any(|key| line.starts_with(key))
+                })
+                .map(str::to_owned)
+                .collect_vec()
+        };
+
+        let root = perform(r#"lsinfo """#).await;
+        assert_eq!(entries(root.clone()), ["directory: a", "file: g.mp3", "playlist: mix"]);
+        assert!(root.starts_with("directory: a\nLast-Modified: 2024-01-01T00:00:00Z\n"));
+        assert!(root.ends_with("playlist: mix\nLast-Modified: 2024-02-01T00:00:00Z\n"));
+        assert_eq!(perform("lsinfo").await, root);
+        assert_eq!(
+            entries(perform("lsinfo a").await),
+            ["directory: a/b", "file: a/d.mp3"]
+        );
+        assert_eq!(
+            entries(perform("lsinfo a/b").await),
+            ["directory: a/b/e", "file: a/b/c.mp3"]
+        );
+    }
+
+    #[tokio::test]
+    async fn disabled_tags_are_left_out() {
+        let system = empty_system(Connection::open_in_memory().unwrap());
+        system
+            .db
+            .execute_batch(
+                "INSERT INTO songs (path, mtime, date_added, duration, title, artist, album)
+                    VALUES ('a.flac', 1750025186, '2025-11-07 15:33:05', 60, 'A', 'Abba',
+                            'Arrival');
+                INSERT INTO queue_entries (song, key) VALUES (1, 1);",
+            )
+            .unwrap();
+        let mut state = ClientState::new(system.clients.register(None));
+        let system = Mutex::new(system);
+        // the listings are written a page at a time, not by perform_command
+        let mut perform = async |line: &str| {
+            let command = Command::parse(line).unwrap();
+            let mut reply = Vec::new();
+            write_reply(command, &mut reply, &system, &mut state).await?;
+            Ok::<_, Report>(String::from_utf8(reply).unwrap())
+        };
+
+        perform("tagtypes disable Album").await.unwrap();
+        pretty_assertions::assert_eq!(
+            perform("lsinfo a.flac").await.unwrap(),
+            "file: a.flac
+Last-Modified: 2025-06-15T22:06:26Z
+Added: 2025-11-07T15:33:05Z
+Artist: Abba
+Title: A
+duration: 60.000
+"
+        );
+        let listed = perform("tagtypes").await.unwrap();
+        assert!(listed.contains("tagtype: Artist\n"));
+        assert!(!listed.contains("tagtype: Album\n"));
+        for listing in ["playlistinfo", "listallinfo"] {
+            let reply = perform(listing).await.unwrap();
+            assert!(reply.contains("Title: A\n"), "{listing}: {reply}");
+            assert!(!reply.contains("Album: "), "{listing}: {reply}");
+        }
+        perform("tagtypes all").await.unwrap();
+        assert!(perform("lsinfo a.flac").await.unwrap().contains("Album: Arrival\n"));
+        assert!(perform("playlistinfo").await.unwrap().contains("Album: Arrival\n"));
+        assert!(perform("listallinfo").await.unwrap().contains("Album: Arrival\n"));
+    }
+
+    #[tokio::test]
+    async fn tags_we_do_not_list_can_not_be_enabled() {
+        let system = empty_system(Connection::open_in_memory().unwrap());
+        let mut state = ClientState::new(system.clients.register(None));
+        let system = Mutex::new(system);
+        let mut perform = async |line: &str| {
+            let command = Command::parse(line).unwrap();
+            perform_command(command, &system, &mut state).await
+        };
+
+        let available = perform("tagtypes available").await.unwrap();
+        assert!(available.contains("tagtype: Genre\n"));
+        assert!(!available.contains("tagtype: Composer\n"));
+
+        perform("tagtypes clear").await.unwrap();
+        let refused = perform("tagtypes enable Title Composer").await.unwrap_err();
+        assert_eq!(ack::find(&refused).unwrap().code, ErrorCode::Arg);
+        // nothing of the refused command took effect
+        assert_eq!(perform("tagtypes").await.unwrap(), "");
+        perform("tagtypes enable Title").await.unwrap();
+        assert_eq!(perform("tagtypes").await.unwrap(), "tagtype: Title\n");
+    }
+
+    #[tokio::test]
+    async fn update_answers_with_the_job_id() {
+        let system = empty_system(Connection::open_in_memory().unwrap());
+        let mut state = ClientState::new(system.clients.register(None));
+        let system = Mutex::new(system);
+        let mut perform = async |line: &str| {
+            let command = Command::parse(line).unwrap();
+            perform_command(command, &system, &mut state).await
+        };
+
+        assert_eq!(perform("update").await.unwrap(), "updating_db: 1\n");
+        assert_eq!(perform("rescan").await.unwrap(), "updating_db: 1\n");
+        assert!(perform("status").await.unwrap().contains("updating_db: 1\n"));
+        system.lock().await.run_update().await;
+        assert!(!perform("status").await.unwrap().contains("updating_db"));
+        assert_eq!(perform("update").await.unwrap(), "updating_db: 2\n");
+    }
+
+    #[tokio::test]
+    async fn searchadd_can_skip_queued_songs() {
+        let mut system = empty_system(Connection::open_in_memory().unwrap());
+        system
+            .db
+            .execute_batch(
+                "INSERT INTO songs (path, mtime, artist) VALUES
+                    ('a.flac', 0, 'Abba'), ('b.flac', 0, 'Abba'), ('c.flac', 0, 'Abba Tribute');",
+            )
+            .unwrap();
+        system.skip_queued = true;
+        let mut state = ClientState::new(system.clients.register(None));
+        let system = Mutex::new(system);
+        let mut perform = async |line: &str| {
+            perform_command(Command::parse(line).unwrap(), &system, &mut state)
+                .await
+                .unwrap();
+            system.lock().await.status().unwrap().playlistlength
+        };
+
+        let abba = r#"searchadd "((Artist == abba))""#;
+        assert_eq!(perform(abba).await, 2);
+        assert_eq!(perform(abba).await, 2);
+        assert_eq!(perform(r#"searchadd "((Artist contains 'abba'))""#).await, 3);
+        assert_eq!(perform(&format!("{abba} x-mpdhaj-skip-queued 0")).await, 5);
+
+        system.lock().await.skip_queued = false;
+        assert_eq!(perform(abba).await, 7);
+        assert_eq!(perform(&format!("{abba} x-mpdhaj-skip-queued 1")).await, 7);
+    }
+
+    #[tokio::test]
+    async fn count_adds_up_songs_and_playtime() {
+        let system = empty_system(Connection::open_in_memory().unwrap());
+        system
+            .db
+            .execute_batch(
+                "INSERT INTO songs (path, mtime, artist, album, duration) VALUES
+                    ('a.flac', 0, 'Abba', 'Arrival', 180.4), ('b.flac', 0, 'Abba', NULL, 200.0),
+                    ('c.flac', 0, 'Queen', 'Jazz', 100.0);",
+            )
+            .unwrap();
+        let mut state = ClientState::new(system.clients.register(None));
+        let system = Mutex::new(system);
+        let mut perform = async |line: &str| {
+            let command = Command::parse(line).unwrap();
+            perform_command(command, &system, &mut state).await.unwrap()
+        };
+
+        assert_eq!(perform("count").await, "songs: 3\nplaytime: 480\n");
+        assert_eq!(perform(r#"count """#).await, "songs: 3\nplaytime: 480\n");
+        assert_eq!(
+            perform(r#"count "((Artist == 'Abba'))""#).await,
+            "songs: 2\nplaytime: 380\n"
+        );
+        assert_eq!(
+            perform(r#"count "((Artist == 'Abba'))" group Album"#).await,
+            "Album: \nsongs: 1\nplaytime: 200\nAlbum: Arrival\nsongs: 1\nplaytime: 180\n"
+        );
+        assert_eq!(
+            perform("count group Artist").await,
+            "Artist: Abba\nsongs: 2\nplaytime: 380\nArtist: Queen\nsongs: 1\nplaytime: 100\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn pictures_are_sent_in_chunks() {
+        use crate::artwork::tests::{PNG, write_song_with_cover};
+
+        let music_dir = camino::Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mpdhaj-readpicture-{}", std::process::id()));
+        std::fs::create_dir_all(&music_dir).unwrap();
+        write_song_with_cover(&music_dir.join("song.wav"));
+        let system = System::with_parts(
+            Connection::open_in_memory().unwrap(),
+            |volume, paused| Player::with_device(volume, paused, Silent),
+            music_dir.clone(),
+            None,
+        )
+        .unwrap();
+        system
+            .db
+            .execute("INSERT INTO songs (path, mtime) VALUES ('song.wav', 0)", [])
+            .unwrap();
+        let mut state = ClientState::new(system.clients.register(None));
+        state.binary_limit = 64;
+        let system = Mutex::new(system);
+
+        let mut reply = Vec::new();
+        let command = Command::parse("readpicture song.wav 0").unwrap();
+        write_reply(command, &mut reply, &system, &mut state)
+            .await
+            .unwrap();
+        let mut expected = b"size: 68\ntype: image/png\nbinary: 64\n".to_vec();
+        expected.extend_from_slice(&PNG[..64]);
+        expected.push(b'\n');
+        assert_eq!(reply, expected);
+
+        let mut reply = Vec::new();
+        let command = Command::parse("readpicture song.wav 64").unwrap();
+        write_reply(command, &mut reply, &system, &mut state)
+            .await
+            .unwrap();
+        let mut expected = b"size: 68\ntype: image/png\nbinary: 4\n".to_vec();
+        expected.extend_from_slice(&PNG[64..]);
+        expected.push(b'\n');
+        assert_eq!(reply, expected);
+
+        // albumart falls back to the picture in the first song, without a type
+        let mut reply = Vec::new();
+        let command = Command::parse("albumart song.wav 0").unwrap();
+        write_reply(command, &mut reply, &system, &mut state)
+            .await
+            .unwrap();
+        assert!(reply.starts_with(b"size: 68\nbinary: 64\n"));
+        let command = Command::parse("readpicture ../../etc/passwd 0").unwrap();
+        let result = write_reply(command, &mut Vec::new(), &system, &mut state).await;
+        assert!(result.is_err());
+
+        // without any art albumart fails like mpd, readpicture sends nothing
+        std::fs::create_dir_all(music_dir.join("bare")).unwrap();
+        crate::player::tests::write_wav(
+            &music_dir.join("bare/song.wav"),
+            std::time::Duration::from_millis(100),
+        );
+        system
+            .lock()
+            .await
+            .db
+            .execute_batch(
+                "INSERT INTO directories (path, parent, mtime)
+                    VALUES ('bare', '', '2024-01-01T00:00:00Z');
+                INSERT INTO songs (path, mtime) VALUES ('bare/song.wav', 0);",
+            )
+            .unwrap();
+        for uri in ["bare", "bare/song.wav"] {
+            let command = Command::parse(&format!("albumart {uri} 0")).unwrap();
+            let result = write_reply(command, &mut Vec::new(), &system, &mut state).await;
+            assert!(result.is_err(), "{uri}");
+        }
+        let mut reply = Vec::new();
+        let command = Command::parse("readpicture bare/song.wav 0").unwrap();
+        write_reply(command, &mut reply, &system, &mut state)
+            .await
+            .unwrap();
+        assert!(reply.is_empty());
+        std::fs::remove_dir_all(&music_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn bad_requests_are_acked_and_the_connection_stays_open() {
+        let system = empty_system(Connection::open_in_memory().unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        task::spawn(serve(Arc::new(Mutex::new(system)), listener));
+        let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let greeting = lines.next_line().await.unwrap().unwrap();
+        assert!(greeting.starts_with("OK MPD "), "{greeting}");
+
+        for (line, reply) in [
+            ("setvol 150", "ACK [2@0] {setvol} Invalid volume value"),
+            ("setvol -5", "ACK [2@0] {setvol} Bad arguments"),
+            ("setvol 99999999999", "ACK [2@0] {setvol} Bad arguments"),
+            ("frobnicate", r#"ACK [5@0] {} unknown command "frobnicate""#),
+            ("stats", "ACK [5@0] {stats} Not implemented"),
+            ("setvol 30", "OK"),
+        ] {
+            writer.write_all(format!("{line}\n").as_bytes()).await.unwrap();
+            let got = lines.next_line().await.unwrap();
+            assert_eq!(got.as_deref(), Some(reply), "{line}");
+        }
+
+        let list = "command_list_begin\nsetvol 30\nsetvol x\nping\ncommand_list_end\n";
+        writer.write_all(list.as_bytes()).await.unwrap();
+        let got = lines.next_line().await.unwrap();
+        assert_eq!(got.as_deref(), Some("ACK [2@1] {setvol} Bad arguments"));
+    }
+
+    #[tokio::test]
+    async fn volume_changes_stay_within_0_and_100() {
+        let mut system = empty_system(Connection::open_in_memory().unwrap());
+        let mut state = ClientState::new(system.clients.register(None));
+        let mut mixer = system.idle(vec![SubSystem::Mixer]);
+        let system = Mutex::new(system);
+        let mut perform = async |line: &str| {
+            perform_command(Command::parse(line).unwrap(), &system, &mut state)
+                .await
+                .unwrap()
+        };
+
+        assert_eq!(perform("setvol 40").await, "");
+        assert_eq!(mixer.try_recv(), Ok(SubSystem::Mixer));
+        assert_eq!(perform("getvol").await, "volume: 40\n");
+        for (line, volume) in [
+            ("volume +5", 45),
+            ("volume -5", 40),
+            ("volume 70", 100),
+            ("volume -100", 0),
+            ("volume +3", 3),
+        ] {
+            perform(line).await;
+            assert_eq!(mixer.try_recv(), Ok(SubSystem::Mixer), "{line}");
+            assert_eq!(perform("getvol").await, format!("volume: {volume}\n"), "{line}");
+        }
+        let status = perform("status").await;
+        assert!(status.contains("\nvolume: 3\n"), "{status}");
+    }
+
+    #[tokio::test]
+    async fn commands_are_counted() {
+        let system = empty_system(Connection::open_in_memory().unwrap());
+        let mut state = ClientState::new(system.clients.register(None));
+        let system = Mutex::new(system);
+
+        for _ in 0..2 {
+            perform_command(Command::Status, &system, &mut state)
+                .await
+                .unwrap();
+        }
+        let metrics = perform_command(Command::StatsInternal, &system, &mut state)
+            .await
+            .unwrap();
+        assert!(metrics.contains("\nmpdhaj_commands_total{command=\"status\"} 2\n"));
+        assert!(metrics.contains("\nmpdhaj_clients 1\n"));
+    }
+
+    #[tokio::test]
+    async fn only_the_default_partition_exists() {
+        let mut system = empty_system(Connection::open_in_memory().unwrap());
+        system
+            .db
+            .execute("INSERT INTO songs (path, mtime) VALUES ('a.flac', 0)", [])
+            .unwrap();
+        let id = system.add_to_queue(camino::Utf8Path::new("a.flac"), &None).unwrap();
+        let mut state = ClientState::new(system.clients.register(None));
+        let mut partition_events = system.idle(vec![SubSystem::Partition]);
+        let system = Mutex::new(system);
+        let mut perform = async |line: &str| {
+            perform_command(Command::parse(line).unwrap(), &system, &mut state).await
+        };
+
+        let status = perform("status").await.unwrap();
+        assert!(status.contains("\npartition: default\n"), "{status}");
+        assert_eq!(perform("partition default").await.unwrap(), "");
+        assert_eq!(
+            perform("listpartitions").await.unwrap(),
+            "partition: default\n"
+        );
+        let song = perform(&format!("playlistid {}", id.0)).await.unwrap();
+        assert!(song.lines().any(|line| line == "file: a.flac"), "{song}");
+
+        let no_such_partition = perform("partition kitchen").await.unwrap_err();
+        assert_eq!(ack::find(&no_such_partition).unwrap().code, ErrorCode::NoExist);
+        let status = perform("status").await.unwrap();
+        assert!(status.contains("\npartition: default\n"), "{status}");
+
+        // so clients waiting on the change do not wait forever
+        assert!(perform("newpartition kitchen").await.is_err());
+        assert_eq!(partition_events.try_recv(), Ok(SubSystem::Partition));
+        assert!(perform("delpartition kitchen").await.is_err());
+        assert_eq!(partition_events.try_recv(), Ok(SubSystem::Partition));
+
+        let playlistid = Command::parse(&format!("playlistid {}", id.0)).unwrap();
+        assert!(check_partition(&playlistid, DEFAULT_PARTITION).is_ok());
+        let other_partition = check_partition(&playlistid, "kitchen").unwrap_err();
+        assert_eq!(ack::find(&other_partition).unwrap().code, ErrorCode::NoExist);
+        assert!(check_partition(&Command::Status, "kitchen").is_ok());
+    }
+}
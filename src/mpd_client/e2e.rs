@@ -0,0 +1,575 @@
+//! Drives a whole session over TCP, like a real client would: handshake,
+//! command lists, idle and binary replies. The audio goes to [`Silent`].
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use rusqlite::Connection;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::time::timeout;
+
+use super::*;
+use crate::artwork::tests::PNG;
+use crate::player::Player;
+use crate::player::device::Silent;
+use crate::scan::tests::write_song;
+
+/// Long enough for a slow CI machine, short enough to not hang forever
+const PATIENCE: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Reply {
+    lines: Vec<String>,
+    binary: Vec<u8>,
+}
+
+impl Reply {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.lines
+            .iter()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix(": "))
+    }
+}
+
+struct Client {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl Client {
+    async fn connect(port: u16) -> Self {
+        let (reader, writer) = TcpStream::connect(("127.0.0.1", port))
+            .await
+            .unwrap()
+            .into_split();
+        let mut client = Self {
+            reader: BufReader::new(reader),
+            writer,
+        };
+        let handshake = client.line().await;
+        assert!(handshake.starts_with("OK MPD "), "{handshake}");
+        client
+    }
+
+    async fn line(&mut self) -> String {
+        let mut line = String::new();
+        let read = timeout(PATIENCE, self.reader.read_line(&mut line))
+            .await
+            .expect("mpdhaj should answer")
+            .unwrap();
+        assert!(read > 0, "mpdhaj closed the connection");
+        line.pop();
+        line
+    }
+
+    async fn send(&mut self, line: &str) {
+        self.writer
+            .write_all(format!("{line}\n").as_bytes())
+            .await
+            .unwrap();
+    }
+
+    /// Everything up to the final `OK`
+    async fn reply(&mut self) -> Reply {
+        let mut reply = Reply::default();
+        loop {
+            let line = self.line().await;
+            if line == "OK" {
+                return reply;
+            }
+            assert!(!line.starts_with("ACK"), "{line}");
+            if let Some(len) = line.strip_prefix("binary: ") {
+                let mut binary = vec![0; len.parse::<usize>().unwrap() + 1];
+                self.reader.read_exact(&mut binary).await.unwrap();
+                assert_eq!(binary.pop(), Some(b'\n'));
+                reply.binary.extend(binary);
+            }
+            reply.lines.push(line);
+        }
+    }
+
+    async fn command(&mut self, line: &str) -> Reply {
+        self.send(line).await;
+        self.reply().await
+    }
+}
+
+/// Two songs in a temporary music dir with a cover next to them, served on
+/// an ephemeral port
+async fn start(name: &str) -> (u16, Arc<Mutex<System>>, Utf8PathBuf) {
+    start_with(name, |volume, paused| Player::with_device(volume, paused, Silent)).await
+}
+
+/// Like [`start`] with a player of your own
+async fn start_with(
+    name: &str,
+    player: impl FnOnce(f32, bool) -> Player,
+) -> (u16, Arc<Mutex<System>>, Utf8PathBuf) {
+    let music_dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+        .unwrap()
+        .join(format!("mpdhaj-e2e-{name}-{}", std::process::id()));
+    std::fs::create_dir_all(music_dir.join("playlists")).unwrap();
+    write_song(&music_dir.join("a.wav"));
+    write_song(&music_dir.join("b.wav"));
+    std::fs::write(music_dir.join("cover.png"), PNG).unwrap();
+
+    let mut system = System::with_parts(
+        Connection::open_in_memory().unwrap(),
+        player,
+        music_dir.clone(),
+        None,
+    )
+    .unwrap();
+    system.rescan().await.unwrap();
+    let system = Arc::new(Mutex::new(system));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    task::spawn(serve(Arc::clone(&system), listener));
+    (port, system, music_dir)
+}
+
+/// Waits till the server registered the idle of a client, the change meant
+/// to wake it would otherwise be taken for one from before the idle
+async fn wait_for_idler(system: &Mutex<System>, sub_system: SubSystem) {
+    let clients = system.lock().await.clients.clone();
+    timeout(PATIENCE, async {
+        while clients.idling(sub_system) == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("client should start idling");
+}
+
+#[tokio::test]
+async fn a_realistic_session() {
+    let (port, _system, music_dir) = start("session").await;
+    let mut client = Client::connect(port).await;
+
+    let status = client.command("status").await;
+    assert_eq!(status.get("state"), Some("stop"));
+    assert_eq!(status.get("playlistlength"), Some("0"));
+
+    client.command("add a.wav").await;
+    let added = client.command(r#"addid "b.wav""#).await;
+    assert!(added.get("Id").is_some());
+    let queue = client.command("playlistinfo").await;
+    let files = queue
+        .lines
+        .iter()
+        .filter_map(|line| line.strip_prefix("file: "))
+        .collect_vec();
+    assert_eq!(files, ["a.wav", "b.wav"]);
+
+    client.command("play 0").await;
+    assert_eq!(client.command("status").await.get("state"), Some("play"));
+    let current = client.command("currentsong").await;
+    assert_eq!(current.get("file"), Some("a.wav"));
+    client.command("pause 1").await;
+    assert_eq!(client.command("status").await.get("state"), Some("pause"));
+    client.command("pause 0").await;
+    assert_eq!(client.command("status").await.get("state"), Some("play"));
+
+    client.send("command_list_ok_begin").await;
+    client.send("ping").await;
+    client.send("currentsong").await;
+    client.send("command_list_end").await;
+    let list = client.reply().await;
+    assert_eq!(
+        list.lines.iter().filter(|line| *line == "list_OK").count(),
+        2
+    );
+    assert_eq!(list.get("file"), Some("a.wav"));
+
+    client.command("clear").await;
+    let status = client.command("status").await;
+    assert_eq!(status.get("playlistlength"), Some("0"));
+    assert_eq!(status.get("state"), Some("stop"));
+
+    std::fs::remove_dir_all(&music_dir).unwrap();
+}
+
+#[tokio::test]
+async fn idle_wakes_up_on_a_queue_change_by_another_client() {
+    let (port, system, music_dir) = start("idle").await;
+    let mut idler = Client::connect(port).await;
+    let mut other = Client::connect(port).await;
+
+    idler.send("idle playlist").await;
+    wait_for_idler(&system, SubSystem::Playlist).await;
+    other.command("add a.wav").await;
+    let woken = idler.reply().await;
+    assert_eq!(woken.lines, ["changed: playlist"]);
+
+    // noidle ends an idle without changes
+    idler.send("idle playlist").await;
+    idler.send("noidle").await;
+    assert_eq!(idler.reply().await, Reply::default());
+    assert_eq!(idler.command("ping").await, Reply::default());
+
+    std::fs::remove_dir_all(&music_dir).unwrap();
+}
+
+#[tokio::test]
+async fn lines_that_do_not_parse_after_idle_are_acked() {
+    let (port, system, music_dir) = start("idlegarbage").await;
+    let mut idler = Client::connect(port).await;
+    let mut other = Client::connect(port).await;
+
+    idler.send("idle playlist").await;
+    idler.send("noidle").await;
+    assert_eq!(idler.reply().await, Reply::default());
+    idler.send("garbage !!").await;
+    assert_eq!(idler.line().await, r#"ACK [5@0] {} unknown command "garbage""#);
+    assert_eq!(idler.command("ping").await, Reply::default());
+
+    // in place of the noidle
+    idler.send("idle playlist").await;
+    idler.send("setvol loud").await;
+    assert_eq!(idler.line().await, "ACK [2@0] {setvol} Bad arguments");
+    assert_eq!(idler.command("ping").await, Reply::default());
+
+    // after being woken up
+    idler.send("idle playlist").await;
+    wait_for_idler(&system, SubSystem::Playlist).await;
+    other.command("add a.wav").await;
+    assert_eq!(idler.reply().await.lines, ["changed: playlist"]);
+    idler.send("garbage").await;
+    assert_eq!(idler.line().await, r#"ACK [5@0] {} unknown command "garbage""#);
+    assert_eq!(idler.command("ping").await, Reply::default());
+
+    std::fs::remove_dir_all(&music_dir).unwrap();
+}
+
+#[tokio::test]
+async fn idling_again_and_again_registers_nothing_new() {
+    let (port, system, music_dir) = start("idleloop").await;
+    let mut client = Client::connect(port).await;
+    let subscribers = |system: &System| system.idlers.values().map(Vec::len).sum::<usize>();
+    let before = subscribers(&*system.lock().await);
+
+    for _ in 0..1_000 {
+        client.send("idle").await;
+        client.send("noidle").await;
+        assert_eq!(client.reply().await, Reply::default());
+    }
+    let system_now = system.lock().await;
+    assert_eq!(subscribers(&system_now), before);
+    assert_eq!(system_now.clients.len(), 1);
+    assert_eq!(system_now.clients.idling(SubSystem::Player), 0);
+    drop(system_now);
+
+    // changes while not idling are not lost
+    client.command("add a.wav").await;
+    client.send("idle playlist").await;
+    assert_eq!(client.reply().await.lines, ["changed: playlist"]);
+
+    drop(client);
+    timeout(PATIENCE, async {
+        while !system.lock().await.clients.is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("the registration should go with the connection");
+
+    std::fs::remove_dir_all(&music_dir).unwrap();
+}
+
+#[tokio::test]
+async fn oneshot_modes_end_with_the_song_and_tell_idlers() {
+    let (port, system, music_dir) = start("oneshot").await;
+    let mut client = Client::connect(port).await;
+
+    client.command("add a.wav").await;
+    client.command("add b.wav").await;
+    client.command("single oneshot").await;
+    client.command("consume oneshot").await;
+    client.command("play 0").await;
+    let status = client.command("status").await;
+    assert_eq!(status.get("single"), Some("oneshot"));
+    assert_eq!(status.get("consume"), Some("oneshot"));
+
+    // connects after the commands, so only the end can wake it
+    let mut idler = Client::connect(port).await;
+    idler.send("idle options").await;
+    wait_for_idler(&system, SubSystem::Options).await;
+    // what the player reports once a song played to its end
+    system.lock().await.current_finished(None).await.unwrap();
+    assert_eq!(idler.reply().await.lines, ["changed: options"]);
+
+    let status = client.command("status").await;
+    assert_eq!(status.get("single"), Some("0"));
+    assert_eq!(status.get("consume"), Some("0"));
+    assert_eq!(status.get("state"), Some("stop"));
+    assert_eq!(status.get("playlistlength"), Some("1"));
+
+    std::fs::remove_dir_all(&music_dir).unwrap();
+}
+
+#[tokio::test]
+async fn album_art_arrives_in_chunks() {
+    let (port, _system, music_dir) = start("art").await;
+    let mut client = Client::connect(port).await;
+
+    client.command("binarylimit 64").await;
+    let mut art = Vec::new();
+    loop {
+        let reply = client
+            .command(&format!("albumart a.wav {}", art.len()))
+            .await;
+        assert_eq!(reply.get("size"), Some(PNG.len().to_string().as_str()));
+        if reply.binary.is_empty() {
+            break;
+        }
+        art.extend(reply.binary);
+        if art.len() == PNG.len() {
+            break;
+        }
+    }
+    assert_eq!(art, PNG);
+
+    // the music dir holds the cover
+    client.command("binarylimit 8192").await;
+    let album = client.command(r#"albumart "" 0"#).await;
+    assert_eq!(album.binary, PNG);
+
+    std::fs::remove_dir_all(&music_dir).unwrap();
+}
+
+#[tokio::test]
+async fn files_copied_in_after_the_scan_can_be_browsed() {
+    let (port, system, music_dir) = start("unscanned").await;
+    let mut client = Client::connect(port).await;
+    std::fs::create_dir_all(music_dir.join("new")).unwrap();
+    write_song(&music_dir.join("new/c.wav"));
+
+    let song = client.command("lsinfo new/c.wav").await;
+    assert_eq!(song.get("file"), Some("new/c.wav"));
+    assert!(
+        song.lines
+            .iter()
+            .any(|line| line.to_lowercase() == "title: café")
+    );
+    let comments = client.command("readcomments new/c.wav").await;
+    assert!(comments.lines.iter().any(|line| line.ends_with(": Café")));
+
+    std::fs::create_dir_all(music_dir.join("newer")).unwrap();
+    write_song(&music_dir.join("newer/d.wav"));
+    let dir = client.command("lsinfo newer").await;
+    assert!(dir.lines.iter().any(|line| line.ends_with(": newer/d.wav")));
+    // the scan is kept, the next update has nothing left to do
+    assert!(
+        system
+            .lock()
+            .await
+            .is_directory(Utf8Path::new("newer"))
+            .unwrap()
+    );
+
+    std::fs::remove_dir_all(&music_dir).unwrap();
+}
+
+#[tokio::test]
+async fn bad_arguments_are_acked_and_the_connection_stays_open() {
+    let (port, _system, music_dir) = start("ack").await;
+    let mut client = Client::connect(port).await;
+    client.command("add a.wav").await;
+
+    // the queue length appends
+    let added = client.command("addid b.wav 1").await;
+    assert!(added.get("Id").is_some());
+
+    client.send("addid a.wav 3").await;
+    assert_eq!(client.line().await, "ACK [2@0] {addid} Bad song index");
+    client.send("addid missing.wav").await;
+    assert_eq!(client.line().await, "ACK [50@0] {addid} No such song");
+
+    client.command("play 1").await;
+    client.send("addid a.wav -5").await;
+    assert_eq!(client.line().await, "ACK [2@0] {addid} Bad song index");
+
+    // the rest of a list is skipped after a failure
+    client.send("command_list_begin").await;
+    client.send("ping").await;
+    client.send("addid a.wav 9").await;
+    client.send("clear").await;
+    client.send("command_list_end").await;
+    assert_eq!(client.line().await, "ACK [2@1] {addid} Bad song index");
+
+    let status = client.command("status").await;
+    assert_eq!(status.get("playlistlength"), Some("2"));
+
+    std::fs::remove_dir_all(&music_dir).unwrap();
+}
+
+#[tokio::test]
+async fn protocol_errors_are_acked_and_the_connection_stays_open() {
+    let (port, _system, music_dir) = start("protocol-errors").await;
+    let mut client = Client::connect(port).await;
+    client.command("subscribe chat").await;
+
+    for (line, ack) in [
+        (
+            "outputset 0 balance 2",
+            "ACK [2@0] {outputset} balance must be between -1 and 1, got: 2",
+        ),
+        (
+            "outputset 0 balance left",
+            "ACK [2@0] {outputset} balance must be a number",
+        ),
+        (
+            "outputset 0 volume 3",
+            "ACK [50@0] {outputset} Output 0 has no attribute: volume",
+        ),
+        (
+            "subscribe chat",
+            "ACK [56@0] {subscribe} Already subscribed to channel: chat",
+        ),
+        (
+            "unsubscribe news",
+            "ACK [50@0] {unsubscribe} Not subscribed to channel: news",
+        ),
+        (
+            "sendmessage news hello",
+            "ACK [50@0] {sendmessage} No such channel: news",
+        ),
+        (
+            "partition upstairs",
+            "ACK [50@0] {partition} partition does not exist",
+        ),
+        (
+            "newpartition upstairs",
+            "ACK [5@0] {newpartition} Only the default partition is supported",
+        ),
+        (
+            "delpartition upstairs",
+            "ACK [5@0] {delpartition} Only the default partition is supported",
+        ),
+        (
+            "x-mpdhaj waveform a.wav 0",
+            "ACK [2@0] {x-mpdhaj} The number of buckets must be between 1 and 4096",
+        ),
+    ] {
+        client.send(line).await;
+        assert_eq!(client.line().await, ack);
+        assert_eq!(client.command("ping").await, Reply::default(), "after {line}");
+    }
+
+    client.send("command_list_begin").await;
+    client.send("ping").await;
+    client.send("idle").await;
+    client.send("command_list_end").await;
+    assert_eq!(
+        client.line().await,
+        "ACK [2@1] {idle} idle and noidle are not allowed in command lists"
+    );
+    assert_eq!(client.command("ping").await, Reply::default());
+
+    std::fs::remove_dir_all(&music_dir).unwrap();
+}
+
+#[tokio::test]
+async fn stored_playlists_are_refilled_in_one_go() {
+    let (port, system, music_dir) = start("storedplaylist").await;
+    let mut client = Client::connect(port).await;
+    let stored = music_dir.join("playlists/mix.m3u");
+
+    client.command("add b.wav").await;
+    client.command("save mix.m3u replace").await;
+    assert_eq!(
+        std::fs::read_to_string(&stored).unwrap(),
+        "#EXTM3U\nb.wav\n"
+    );
+
+    let mut idle = system.lock().await.idle(vec![SubSystem::StoredPlaylist]);
+    client.send("command_list_ok_begin").await;
+    client.send("playlistclear mix.m3u").await;
+    client.send("playlistadd mix.m3u a.wav").await;
+    client.send("playlistadd mix.m3u b.wav").await;
+    client.send("command_list_end").await;
+    assert_eq!(client.reply().await.lines, ["list_OK"; 3]);
+    assert_eq!(
+        std::fs::read_to_string(&stored).unwrap(),
+        "#EXTM3U\na.wav\nb.wav\n"
+    );
+    // never seen empty in between
+    assert_eq!(idle.try_recv(), Ok(SubSystem::StoredPlaylist));
+    assert!(idle.try_recv().is_err());
+
+    client.send("command_list_begin").await;
+    client.send("playlistclear ../mix.m3u").await;
+    client.send("playlistadd ../mix.m3u a.wav").await;
+    client.send("ping").await;
+    client.send("command_list_end").await;
+    assert_eq!(
+        client.line().await,
+        "ACK [2@0] {playlistclear} Bad playlist name"
+    );
+    assert_eq!(client.command("ping").await, Reply::default());
+
+    std::fs::remove_dir_all(&music_dir).unwrap();
+}
+
+#[tokio::test]
+async fn stats_count_the_library_and_when_it_was_scanned() {
+    let before = jiff::Timestamp::now().as_second();
+    let (port, _system, music_dir) = start("stats").await;
+    let mut client = Client::connect(port).await;
+
+    client.command("add a.wav").await;
+    let stats = client.command("stats").await;
+    assert_eq!(stats.get("songs"), Some("2"));
+    let number = |key: &str| stats.get(key).unwrap().parse::<i64>().unwrap();
+    assert!(number("uptime") >= 0);
+    assert!(number("db_playtime") >= number("playtime"));
+    // start scanned the music dir just now
+    let db_update = number("db_update");
+    assert!(db_update >= before, "{db_update} is before {before}");
+    assert!(db_update <= jiff::Timestamp::now().as_second());
+
+    std::fs::remove_dir_all(&music_dir).unwrap();
+}
+
+#[tokio::test]
+async fn status_answers_while_a_song_opens_on_a_slow_disk() {
+    const SPIN_UP: Duration = Duration::from_millis(500);
+    let (port, _system, music_dir) = start_with("slow-disk", |volume, paused| {
+        Player::with_device(volume, paused, Silent).with_file_opener(|path| {
+            std::thread::sleep(SPIN_UP);
+            std::fs::File::open(path)
+        })
+    })
+    .await;
+    let mut player = Client::connect(port).await;
+    let mut other = Client::connect(port).await;
+
+    player.command("add a.wav").await;
+    player.command("add b.wav").await;
+    player.send("play 0").await;
+    // give play the time to get to opening the song
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let started = tokio::time::Instant::now();
+    let status = timeout(Duration::from_millis(100), other.command("status"))
+        .await
+        .expect("status should not wait for the song to open");
+    assert_eq!(status.get("state"), Some("stop"));
+    assert!(started.elapsed() < SPIN_UP);
+
+    assert_eq!(player.reply().await.get("state"), Some("play"));
+    // the next song is opened while it is prefetched, that is not waited
+    // for either
+    let started = tokio::time::Instant::now();
+    let status = timeout(Duration::from_millis(100), other.command("status"))
+        .await
+        .expect("status should not wait for the next song to open");
+    assert_eq!(status.get("song"), Some("0"));
+    assert_eq!(status.get("nextsong"), Some("1"));
+    assert!(started.elapsed() < SPIN_UP);
+
+    std::fs::remove_dir_all(&music_dir).unwrap();
+}
@@ -0,0 +1,478 @@
+//! Long listings are written a page at a time. Pages are read on a
+//! connection of their own, or with the System lock held only while a page is
+//! read for in-memory databases, so other clients get their turn during a
+//! dump of the whole library. Only one page is in memory at once.
+
+use std::collections::HashSet;
+use std::ops::Range;
+
+use camino::Utf8Path;
+use color_eyre::{Result, Section};
+use rusqlite::Connection;
+
+use crate::mpd_protocol::ack::{Ack, ErrorCode};
+use crate::mpd_protocol::{
+    BrowseEntry, Command, PosOrRange, Position, SongBlock, Tag, response_format,
+};
+use crate::system::list_all::{ListAll, song_info};
+use crate::system::queue_snapshot::QueueSnapshot;
+
+/// Rows per page
+pub(crate) const PAGE_ROWS: u32 = 1000;
+
+pub(crate) trait Pages: Send {
+    /// The next part of the reply, None once it is complete
+    fn next_page(&mut self, db: &Connection) -> Result<Option<String>>;
+}
+
+/// The pages of the reply to `command`, None if it is answered at once. The
+/// songs in them only have the tags in `tag_types`.
+pub(crate) fn paged(command: &Command, tag_types: &HashSet<Tag>) -> Option<Box<dyn Pages>> {
+    match command {
+        Command::ListAll(dir) => Some(Box::new(ListAll::new(
+            dir.as_deref().unwrap_or(Utf8Path::new("")),
+        ))),
+        Command::ListAllInfo(dir) => Some(Box::new(ListAllInfo {
+            list: ListAll::new(dir.as_deref().unwrap_or(Utf8Path::new(""))),
+            tag_types: tag_types.clone(),
+        })),
+        Command::PlaylistInfo(what) => {
+            let Range { start, end } = queue_part(*what);
+            Some(Box::new(Queue {
+                snapshot: None,
+                next: start,
+                end,
+                tag_types: tag_types.clone(),
+            }))
+        }
+        _ => None,
+    }
+}
+
+impl Pages for ListAll {
+    fn next_page(&mut self, db: &Connection) -> Result<Option<String>> {
+        let Some(items) = self.next_items(db, PAGE_ROWS as usize)? else {
+            return Ok(None);
+        };
+        Ok(Some(response_format::to_string(&items)?))
+    }
+}
+
+/// `listall` with the info of every song
+struct ListAllInfo {
+    list: ListAll,
+    tag_types: HashSet<Tag>,
+}
+
+impl Pages for ListAllInfo {
+    fn next_page(&mut self, db: &Connection) -> Result<Option<String>> {
+        let Some(items) = self.list.next_items(db, PAGE_ROWS as usize)? else {
+            return Ok(None);
+        };
+        let mut page = String::new();
+        for item in items {
+            match item {
+                BrowseEntry::File(SongBlock::Path { path }) => {
+                    // None if it was removed after it was listed
+                    if let Some(info) = song_info(db, &path)? {
+                        page += &response_format::to_string(&info.only_tags(&self.tag_types))?;
+                    }
+                }
+                other => page += &response_format::to_string(&other)?,
+            }
+        }
+        Ok(Some(page))
+    }
+}
+
+/// All of the queue, what `playlistinfo` lists without an argument
+const WHOLE_QUEUE: Range<usize> = 0..usize::MAX;
+
+/// The positions `playlistinfo` lists
+fn queue_part(what: Option<PosOrRange>) -> Range<usize> {
+    match what {
+        None => WHOLE_QUEUE,
+        Some(PosOrRange::Position(Position::Absolute(pos))) => pos as usize..pos as usize + 1,
+        Some(PosOrRange::Range(range)) => {
+            let Range { start, end } = range.window();
+            start as usize..end as usize
+        }
+        // the parser only hands out absolute positions, this is refused as
+        // being past the end
+        Some(PosOrRange::Position(Position::Relative(_))) => usize::MAX..usize::MAX,
+    }
+}
+
+/// Every page lists the queue as it was when the first one was read, even if
+/// it changed in between
+struct Queue {
+    snapshot: Option<QueueSnapshot>,
+    next: usize,
+    /// Past the last position to list, may be past the end of the queue
+    end: usize,
+    tag_types: HashSet<Tag>,
+}
+
+impl Pages for Queue {
+    fn next_page(&mut self, db: &Connection) -> Result<Option<String>> {
+        let snapshot = match &mut self.snapshot {
+            Some(snapshot) => snapshot,
+            None => {
+                let snapshot = QueueSnapshot::take(db)?;
+                // like mpd a part may run past the end, but not start there
+                let asked = self.next..self.end;
+                if asked != WHOLE_QUEUE && self.next >= snapshot.len().min(self.end) {
+                    return Err(Ack::new(ErrorCode::Arg, "Bad song index")).with_note(|| {
+                        format!("asked for {asked:?}, queue length is {}", snapshot.len())
+                    });
+                }
+                self.snapshot.insert(snapshot)
+            }
+        };
+        if self.next >= snapshot.len().min(self.end) {
+            return Ok(None);
+        }
+        let end = (self.next + PAGE_ROWS as usize).min(self.end);
+        let page = snapshot.entries(db, self.next..end)?;
+        self.next = end;
+        Ok(Some(response_format::to_string(&page.only_tags(&self.tag_types))?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::pin::Pin;
+    use std::task::{self, Poll};
+    use std::time::Duration;
+
+    use rusqlite::Connection;
+    use tokio::io::{AsyncReadExt, AsyncWrite};
+    use tokio::sync::{Mutex, mpsc};
+
+    use super::*;
+    use crate::mpd_client::{ClientState, perform_command, write_reply};
+    use crate::mpd_protocol::Position;
+    use crate::system::readers::Readers;
+    use crate::system::{System, empty_system, shift_current};
+
+    fn system_with_songs(count: u32) -> System {
+        with_songs(Connection::open_in_memory().unwrap(), count)
+    }
+
+    fn with_songs(db: Connection, count: u32) -> System {
+        let mut system = empty_system(db);
+        let t = system.db.transaction().unwrap();
+        {
+            let mut insert = t
+                .prepare("INSERT INTO songs (path, mtime) VALUES (?1, 0)")
+                .unwrap();
+            for i in 0..count {
+                insert.execute([format!("{i:06}.flac")]).unwrap();
+            }
+        }
+        t.commit().unwrap();
+        system
+    }
+
+    fn pages(system: &System, command: &Command) -> Vec<String> {
+        let mut pages = paged(command, &Tag::SUPPORTED.iter().copied().collect()).unwrap();
+        std::iter::from_fn(|| pages.next_page(&system.db).unwrap()).collect()
+    }
+
+    #[test]
+    fn a_library_dump_is_written_in_pages() {
+        let system = system_with_songs(100_000);
+        let pages = pages(&system, &Command::ListAll(None));
+        assert_eq!(pages.len(), 100);
+        assert!(
+            pages
+                .iter()
+                .all(|page| page.lines().count() == PAGE_ROWS as usize)
+        );
+        assert_eq!(pages[0].lines().next(), Some("file: 000000.flac"));
+        assert_eq!(pages[99].lines().last(), Some("file: 099999.flac"));
+    }
+
+    #[test]
+    fn the_queue_is_written_in_pages() {
+        let system = system_with_songs(2500);
+        system
+            .db
+            .execute(
+                "INSERT INTO queue_entries (song, key) SELECT rowid, rowid FROM songs",
+                [],
+            )
+            .unwrap();
+        let pages = pages(&system, &Command::PlaylistInfo(None));
+        assert_eq!(pages.len(), 3);
+        assert_eq!(
+            pages.concat(),
+            response_format::to_string(&system.queue().unwrap()).unwrap()
+        );
+    }
+
+    #[test]
+    fn only_the_requested_part_of_the_queue_is_listed() {
+        let system = system_with_songs(2500);
+        system
+            .db
+            .execute(
+                "INSERT INTO queue_entries (song, key) SELECT rowid, rowid FROM songs",
+                [],
+            )
+            .unwrap();
+        let positions = |what| -> Vec<String> {
+            pages(&system, &Command::PlaylistInfo(Some(what)))
+                .iter()
+                .flat_map(|page| page.lines())
+                .filter_map(|line| line.strip_prefix("Pos: "))
+                .map(str::to_owned)
+                .collect()
+        };
+        let position = |pos| PosOrRange::Position(Position::Absolute(pos));
+        let range = |start, end| PosOrRange::Range(crate::mpd_protocol::Range::new(start, end));
+
+        assert_eq!(positions(position(3)), ["3"]);
+        assert_eq!(positions(range(998, Some(1002))), ["998", "999", "1000", "1001"]);
+        assert_eq!(positions(range(2498, None)), ["2498", "2499"]);
+        // a range may run past the end
+        assert_eq!(positions(range(2499, Some(3000))), ["2499"]);
+
+        for what in [position(2500), range(2500, None), range(5, Some(5))] {
+            let mut pages = paged(&Command::PlaylistInfo(Some(what)), &HashSet::new()).unwrap();
+            let error = pages.next_page(&system.db).unwrap_err();
+            let ack = crate::mpd_protocol::ack::find(&error).unwrap();
+            assert_eq!(ack.code, ErrorCode::Arg, "{what:?}");
+        }
+    }
+
+    fn queue_ids(system: &System) -> Vec<u32> {
+        system
+            .db
+            .prepare("SELECT id FROM queue ORDER BY position")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn the_queue_listing_is_consistent_while_it_changes() {
+        let mut system = system_with_songs(3000);
+        system
+            .add_many_to_queue(
+                &(0..2500)
+                    .map(|i| format!("{i:06}.flac").into())
+                    .collect::<Vec<_>>(),
+                None,
+            )
+            .unwrap();
+        let mut versions = HashMap::new();
+        versions.insert(system.status().unwrap().playlist, queue_ids(&system));
+        let system = Arc::new(Mutex::new(system));
+
+        let changes = tokio::spawn({
+            let system = Arc::clone(&system);
+            async move {
+                for i in 0..200u32 {
+                    let mut system = system.lock().await;
+                    if i % 2 == 0 {
+                        let path = format!("{:06}.flac", 2500 + i);
+                        let at = Position::Absolute(i * 7 % 2000);
+                        system.add_many_to_queue(&[path.into()], Some(at)).unwrap();
+                    } else {
+                        // stands in for `delete`
+                        let pos = i * 13 % 2000;
+                        let t = system.db.unchecked_transaction().unwrap();
+                        t.execute(
+                            "DELETE FROM queue_entries
+                                WHERE id = (SELECT id FROM queue WHERE position = ?1)",
+                            [pos],
+                        )
+                        .unwrap();
+                        shift_current(&t, pos + 1, -1).unwrap();
+                        t.execute("UPDATE state SET queue_version = queue_version + 1", [])
+                            .unwrap();
+                        t.commit().unwrap();
+                    }
+                    versions.insert(system.status().unwrap().playlist, queue_ids(&system));
+                    drop(system);
+                    tokio::task::yield_now().await;
+                }
+                versions
+            }
+        });
+
+        let mut listings = Vec::new();
+        while !changes.is_finished() {
+            let mut queue = Queue {
+                snapshot: None,
+                next: WHOLE_QUEUE.start,
+                end: WHOLE_QUEUE.end,
+                tag_types: HashSet::new(),
+            };
+            let mut listing = String::new();
+            while let Some(page) = queue.next_page(&system.lock().await.db).unwrap() {
+                listing += &page;
+                tokio::task::yield_now().await;
+            }
+            listings.push((queue.snapshot.unwrap().version, listing));
+        }
+        let versions = changes.await.unwrap();
+
+        assert!(listings.len() > 1, "the changes were over too soon");
+        for (version, listing) in listings {
+            let field = |name: &str| -> Vec<u32> {
+                listing
+                    .lines()
+                    .filter_map(|line| line.strip_prefix(name))
+                    .map(|value| value.parse().unwrap())
+                    .collect()
+            };
+            let positions = field("Pos: ");
+            assert!(
+                positions.iter().copied().eq(0..positions.len() as u32),
+                "version {version} has a gap or a duplicate"
+            );
+            assert_eq!(field("Id: "), versions[&version], "version {version}");
+        }
+    }
+
+    #[tokio::test]
+    async fn other_clients_go_on_during_a_dump() {
+        let system = system_with_songs(10_000);
+        let mut state = ClientState::new(system.clients.register(None));
+        let mut other = ClientState::new(system.clients.register(None));
+        let system = Arc::new(Mutex::new(system));
+
+        // smaller than the reply, writing it stalls until the client reads
+        let (mut client, mut server) = tokio::io::duplex(64 * 1024);
+        let dump = tokio::spawn({
+            let system = Arc::clone(&system);
+            async move { write_reply(Command::ListAll(None), &mut server, &system, &mut state).await }
+        });
+        let mut first = [0; 5];
+        client.read_exact(&mut first).await.unwrap();
+        assert_eq!(&first, b"file:");
+
+        let status = tokio::time::timeout(
+            Duration::from_secs(1),
+            perform_command(Command::Status, &system, &mut other),
+        )
+        .await
+        .expect("the dump should not hold the System lock")
+        .unwrap();
+        assert!(status.contains("state: stop"));
+
+        let mut rest = String::new();
+        client.read_to_string(&mut rest).await.unwrap();
+        dump.await.unwrap().unwrap();
+        assert_eq!(rest.lines().count(), 10_000);
+    }
+
+    #[test]
+    fn listallinfo_shows_every_song() {
+        let system = system_with_songs(1500);
+        system
+            .db
+            .execute("UPDATE songs SET duration = 1.5", [])
+            .unwrap();
+        let pages = pages(&system, &Command::ListAllInfo(None));
+        assert_eq!(pages.len(), 2);
+        let files = pages
+            .iter()
+            .flat_map(|page| page.lines())
+            .filter(|line| line.starts_with("file: "))
+            .count();
+        assert_eq!(files, 1500);
+        assert!(pages[0].starts_with("file: 000000.flac\nLast-Modified: "));
+        assert!(pages[0].contains("duration: 1.500\n"));
+    }
+
+    /// Tells `written` about every page and holds on to it until `resume`
+    /// says to go on. Once `resume` is dropped pages go through at once.
+    struct Paused {
+        written: mpsc::UnboundedSender<()>,
+        resume: mpsc::UnboundedReceiver<()>,
+        told: bool,
+    }
+
+    impl AsyncWrite for Paused {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut task::Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            if !self.told {
+                let _ = self.written.send(());
+                self.told = true;
+            }
+            task::ready!(self.resume.poll_recv(cx));
+            self.told = false;
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(
+            self: Pin<&mut Self>,
+            _: &mut task::Context<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: Pin<&mut Self>,
+            _: &mut task::Context<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn status_answers_between_the_pages_of_listallinfo() {
+        let path =
+            std::env::temp_dir().join(format!("mpdhaj-stress-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut system = with_songs(Connection::open(&path).unwrap(), 5_000);
+        system.readers = Some(Arc::new(Readers::new(&system.db, path.clone()).unwrap()));
+        let mut state = ClientState::new(system.clients.register(None));
+        let mut other = ClientState::new(system.clients.register(None));
+        let system = Arc::new(Mutex::new(system));
+
+        let (written, mut pages) = mpsc::unbounded_channel();
+        let (resume, paused) = mpsc::unbounded_channel();
+        let dump = tokio::spawn({
+            let system = Arc::clone(&system);
+            async move {
+                let mut writer = Paused {
+                    written,
+                    resume: paused,
+                    told: false,
+                };
+                write_reply(Command::ListAllInfo(None), &mut writer, &system, &mut state).await
+            }
+        });
+        for page in 0..3 {
+            pages.recv().await.expect("listallinfo ended before its pages");
+            // the dump is stuck writing this page
+            assert!(system.try_lock().is_ok(), "lock held while writing page {page}");
+            let status = tokio::time::timeout(
+                Duration::from_secs(1),
+                perform_command(Command::Status, &system, &mut other),
+            )
+            .await
+            .expect("status should not wait for the dump")
+            .unwrap();
+            assert!(status.contains("state: stop"));
+            resume.send(()).unwrap();
+        }
+        drop(resume);
+        dump.await.unwrap().unwrap();
+        drop(system);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
+}
@@ -1,15 +1,17 @@
 // pub mod command_format;
+pub mod ack;
 pub mod command_parser;
 pub mod query;
 pub mod response_format;
 
+use std::collections::HashSet;
+use std::fmt;
 use std::time::Duration;
 
 use camino::Utf8PathBuf;
-use jiff::Timestamp;
 use rodio::{ChannelCount, SampleRate, nz};
 use serde::{Deserialize, Serialize};
-use strum::{Display, EnumIter, EnumString, VariantNames};
+use strum::{Display, EnumIter, EnumString, IntoStaticStr, VariantNames};
 use tracing::instrument;
 
 use crate::{mpd_protocol::query::Query, playlist::PlaylistName};
@@ -19,7 +21,7 @@ pub const VERSION: &str = "0.24.4";
 // TODO: in general these should be using URIs instead of Utf8PathBuf
 
 /// see <https://mpd.readthedocs.io/en/stable/protocol.html#command-reference>
-#[derive(Debug, Default, VariantNames, EnumString, PartialEq)]
+#[derive(Debug, Default, VariantNames, EnumString, IntoStaticStr, PartialEq)]
 #[strum(serialize_all = "lowercase")]
 pub enum Command {
     // Query Status:
@@ -30,6 +32,9 @@ pub enum Command {
     #[default]
     Status,
     Stats,
+    /// Not mpd's, our metrics in the Prometheus text format
+    #[strum(serialize = "stats_internal")]
+    StatsInternal,
 
     // Playback Options:
     Consume(ConsumeState),
@@ -38,9 +43,9 @@ pub enum Command {
     MixRampDelay(u32), // seconds
     Random(bool),
     Repeat(bool),
-    SetVol(i8),
+    SetVol(i32),
     GetVol,
-    Single(bool),
+    Single(SingleState),
     ReplayGainMode(ReplayGainMode),
     ReplayGainStatus,
     Volume(VolumeChange),
@@ -99,7 +104,7 @@ pub enum Command {
 
     // Interact with database:
     AlbumArt(Utf8PathBuf, u64), // offset in bytes
-    Count(Query, Option<Tag>),  // TODO: the group field here is weird, query can be optional?
+    Count(Query, Option<Tag>), // group, a left out filter is Query::everything
     GetFingerprint(Utf8PathBuf),
     Find(Query, Option<Sort>, Option<core::ops::Range<u32>>),
     FindAdd(Query, Option<Sort>, Option<core::ops::Range<u32>>, Option<Position>),
@@ -112,14 +117,27 @@ pub enum Command {
     LsInfo(Utf8PathBuf),
     ReadComments(Utf8PathBuf),
     ReadPicture(Utf8PathBuf, u64), // offset in bytes
-    Search(Query, Option<Sort>, Option<Range>),
-    SearchAdd(Query, Option<Sort>, Option<Range>, Option<Position>),
+    /// Not mpd's, `x-mpdhaj waveform URI BUCKETS` sends the peak and RMS
+    /// level of a song in that many buckets for drawing seek bars
+    #[strum(serialize = "x-mpdhaj")]
+    Waveform(Utf8PathBuf, u32),
+    /// Not mpd's, `x-mpdhaj undecodable` lists the songs that will not play
+    /// and why, see [`crate::scan::decoders`]
+    #[strum(serialize = "x-mpdhaj")]
+    Undecodable,
+    Search(Query, Option<Sort>, Option<core::ops::Range<u32>>),
+    /// The last field is not mpd's, a trailing `x-mpdhaj-skip-queued 0|1`
+    /// overrides [`crate::system::Config::skip_queued`] for one command
+    SearchAdd(Query, Option<Sort>, Option<Range>, Option<Position>, Option<bool>),
+    /// The last field is not mpd's, a trailing `x-mpdhaj-replace 1` swaps out
+    /// the whole playlist for the results instead of adding to it
     SearchAddPl(
         PlaylistName,
         Query,
         Option<Sort>,
         Option<Range>,
         Option<Position>,
+        bool,
     ),
     SearchCount(Query, Option<Tag>),
     Update(Option<Utf8PathBuf>),
@@ -210,7 +228,7 @@ pub enum Command {
     SendMessage(ChannelName, String),
 }
 
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Hash, EnumIter, EnumString)]
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, Hash, EnumIter, EnumString)]
 pub enum SubSystem {
     /// the song database has been modified after update.
     Database,
@@ -305,6 +323,27 @@ pub enum Tag {
     MusicbrainzReleasegroupId,
     MusicbrainzReleaseTrackId,
     MusicbrainzWorkId,
+    /// Not a real tag, only valid in filters where it matches any tag
+    #[strum(serialize = "any", ascii_case_insensitive)]
+    #[serde(rename = "any")]
+    Any,
+}
+
+impl Tag {
+    /// The tags songs are listed with, in the order of [`QueueEntry`]. The
+    /// others have a column in the database but the scanner does not fill
+    /// them in yet, `tagtypes` does not offer those.
+    pub const SUPPORTED: &[Tag] = &[
+        Tag::Artist,
+        Tag::AlbumArtist,
+        Tag::Title,
+        Tag::Album,
+        Tag::Track,
+        Tag::Date,
+        Tag::Genre,
+        Tag::Label,
+        Tag::Disc,
+    ];
 }
 
 impl Command {
@@ -320,13 +359,15 @@ pub struct PlaylistList(pub Vec<PlayList>);
 #[derive(Debug, Serialize)]
 pub struct PlayList {
     playlist: PlaylistName,
+    #[serde(rename = "Last-Modified")]
+    #[serde(serialize_with = "response_format::iso_seconds")]
     last_modified: jiff::Timestamp,
 }
 impl PlayList {
-    pub(crate) fn from_name(name: PlaylistName) -> PlayList {
+    pub(crate) fn new(name: PlaylistName, last_modified: jiff::Timestamp) -> PlayList {
         PlayList {
             playlist: name,
-            last_modified: jiff::Timestamp::new(42, 42).unwrap(),
+            last_modified,
         }
     }
 }
@@ -335,24 +376,53 @@ impl PlayList {
 pub struct PlaylistId(pub u32);
 
 #[derive(Debug, Default, Deserialize, PartialEq, Eq)]
+/// In percent, `volume -5` is `VolumeChange(-5)`
 pub struct VolumeChange(pub i8);
 
 #[derive(Debug, Serialize)]
 pub struct Volume(u8);
 
 impl Volume {
-    pub fn new(val: u8) -> Self {
-        if (0..=100).contains(&val) {
-            Self(val)
-        } else {
-            panic!("Volume value must be between 0 and 101")
-        }
+    /// For constants, use [`Volume::try_from`] for anything a client or the
+    /// database could have set.
+    ///
+    /// # Panics
+    ///
+    /// If `val` is more than 100.
+    pub const fn new(val: u8) -> Self {
+        assert!(val <= 100, "Volume value must be between 0 and 100");
+        Self(val)
     }
     pub fn get(&self) -> u8 {
         self.0
     }
 }
 
+impl TryFrom<i32> for Volume {
+    type Error = InvalidVolume;
+
+    fn try_from(val: i32) -> Result<Self, Self::Error> {
+        u8::try_from(val)
+            .ok()
+            .filter(|val| *val <= 100)
+            .map(Self)
+            .ok_or(InvalidVolume(val))
+    }
+}
+
+/// A volume outside of 0..=100
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidVolume(pub i32);
+
+impl fmt::Display for InvalidVolume {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // mpd's message
+        f.write_str("Invalid volume value")
+    }
+}
+
+impl std::error::Error for InvalidVolume {}
+
 /// Unique Id for a song in the database. Set on scan.
 ///
 /// Note:
@@ -384,22 +454,12 @@ pub enum PlaybackState {
     Stop,
 }
 
-impl PlaybackState {
-    pub fn toggle(self) -> Self {
-        use PlaybackState::*;
-        match self {
-            Play => Pause,
-            Pause => Play,
-            Stop => Play,
-        }
-    }
-}
-
 // custom serialize as: samplerate:bits:channels
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct AudioParams {
     pub samplerate: SampleRate,
-    pub bits: u64,
+    /// None for lossy formats, they decode to floats
+    pub bits: Option<u8>,
     pub channels: ChannelCount,
 }
 
@@ -407,7 +467,7 @@ impl Default for AudioParams {
     fn default() -> Self {
         Self {
             samplerate: nz!(44100),
-            bits: 16,
+            bits: Some(16),
             channels: nz!(2),
         }
     }
@@ -416,43 +476,160 @@ impl Default for AudioParams {
 #[derive(Serialize, Debug)]
 pub struct QueueInfo(pub Vec<QueueEntry>);
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, PartialEq)]
 #[serde(rename_all = "PascalCase")]
 pub struct QueueEntry {
     #[serde(rename = "file")]
     pub path: Utf8PathBuf,
+    /// The part of the song that plays, left out when that is all of it
+    #[serde(serialize_with = "response_format::option_float_range")]
+    pub range: Option<FloatRange>,
     #[serde(rename = "Last-Modified")]
     pub last_modified: jiff::Timestamp, // as 2025-06-15T22:06:58Z
     pub added: jiff::Timestamp, // as 2025-06-15T22:06:58Z
-    #[serde(serialize_with = "response_format::audio_params")]
-    pub format: AudioParams,
-    pub artist: String,
-    pub album_artist: String,
+    #[serde(serialize_with = "response_format::option_audio_params")]
+    pub format: Option<AudioParams>,
+    /// One line per artist
+    pub artist: Vec<String>,
+    pub album_artist: Option<String>,
     /// the song title
-    pub title: String,
-    pub album: String,
+    pub title: Option<String>,
+    pub album: Option<String>,
     /// the decimal track number within the album.
-    pub track: u64,
+    pub track: Option<u64>,
     /// Release date usually 4 digit year
-    pub date: String,
-    /// the music genre
-    pub genre: Option<String>,
+    pub date: Option<String>,
+    /// the music genres, one line each
+    pub genre: Vec<String>,
     /// the name of the label or publisher
-    pub label: String,
+    pub label: Option<String>,
     pub disc: Option<u64>,
     #[serde(serialize_with = "response_format::duration_millis_precise")]
     #[serde(rename = "duration")]
     pub duration: Duration,
-    pub pos: QueuePos,
-    pub id: QueueId,
+    /// None for songs outside the queue, like those `lsinfo` lists
+    pub pos: Option<QueuePos>,
+    /// None for entries of stored playlists
+    pub id: Option<QueueId>,
+    /// None for the default of zero and for entries of stored playlists
+    pub prio: Option<u8>,
 }
 
-#[derive(Serialize, Debug, Hash, PartialEq, Eq)]
-#[serde(rename_all = "lowercase")]
-// TODO: check with yara, it doesn't seem to return directories on my machine? maybe mpc filters them out?
-pub enum ListItem {
-    Directory(Utf8PathBuf),
-    File(Utf8PathBuf),
+impl QueueEntry {
+    /// Leaves out the tags a client did not enable with `tagtypes`
+    pub fn only_tags(mut self, enabled: &HashSet<Tag>) -> Self {
+        let off = |tag| !enabled.contains(&tag);
+        if off(Tag::Artist) {
+            self.artist.clear();
+        }
+        if off(Tag::AlbumArtist) {
+            self.album_artist = None;
+        }
+        if off(Tag::Title) {
+            self.title = None;
+        }
+        if off(Tag::Album) {
+            self.album = None;
+        }
+        if off(Tag::Track) {
+            self.track = None;
+        }
+        if off(Tag::Date) {
+            self.date = None;
+        }
+        if off(Tag::Genre) {
+            self.genre.clear();
+        }
+        if off(Tag::Label) {
+            self.label = None;
+        }
+        if off(Tag::Disc) {
+            self.disc = None;
+        }
+        self
+    }
+}
+
+impl QueueInfo {
+    /// See [`QueueEntry::only_tags`]
+    pub fn only_tags(self, enabled: &HashSet<Tag>) -> Self {
+        QueueInfo(self.0.into_iter().map(|entry| entry.only_tags(enabled)).collect())
+    }
+}
+
+/// An entry of the listings that mix files, directories and playlists like
+/// `lsinfo` and `listall`. Each starts with its lowercase `file:`,
+/// `directory:` or `playlist:` line, the lines after it up to the next such
+/// line belong to it. Clients split the listing on those keys.
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum BrowseEntry {
+    File(SongBlock),
+    Directory {
+        #[serde(rename = "directory")]
+        path: Utf8PathBuf,
+        /// Left out by `listall`
+        #[serde(rename = "Last-Modified")]
+        #[serde(serialize_with = "response_format::option_iso_seconds")]
+        last_modified: Option<jiff::Timestamp>,
+    },
+    Playlist {
+        #[serde(rename = "playlist")]
+        name: PlaylistName,
+        #[serde(rename = "Last-Modified")]
+        #[serde(serialize_with = "response_format::option_iso_seconds")]
+        last_modified: Option<jiff::Timestamp>,
+    },
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum SongBlock {
+    /// Only the `file:` line, for `listall`
+    Path {
+        #[serde(rename = "file")]
+        path: Utf8PathBuf,
+    },
+    /// The whole block `lsinfo` shows
+    Info(Box<QueueEntry>),
+}
+
+impl BrowseEntry {
+    /// A song listed by its path alone
+    pub fn file(path: Utf8PathBuf) -> Self {
+        Self::File(SongBlock::Path { path })
+    }
+
+    /// The path of a song, None for directories and playlists
+    pub fn into_file(self) -> Option<Utf8PathBuf> {
+        match self {
+            Self::File(SongBlock::Path { path }) => Some(path),
+            Self::File(SongBlock::Info(entry)) => Some(entry.path),
+            Self::Directory { .. } | Self::Playlist { .. } => None,
+        }
+    }
+}
+
+/// One line of `listfiles`, read from the filesystem instead of the
+/// database so files that are not music show up too
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum FileEntry {
+    Directory {
+        #[serde(rename = "directory")]
+        name: String,
+        #[serde(rename = "Last-Modified")]
+        #[serde(serialize_with = "response_format::iso_seconds")]
+        last_modified: jiff::Timestamp,
+    },
+    File {
+        #[serde(rename = "file")]
+        name: String,
+        size: u64,
+        #[serde(rename = "Last-Modified")]
+        #[serde(serialize_with = "response_format::iso_seconds")]
+        last_modified: jiff::Timestamp,
+    },
 }
 
 #[derive(Serialize, Debug)]
@@ -463,46 +640,28 @@ pub struct FindResult {
     #[serde(rename = "Last-Modified")]
     pub last_modified: jiff::Timestamp,
     pub added: jiff::Timestamp,
-    #[serde(serialize_with = "response_format::audio_params")]
-    pub format: AudioParams,
+    #[serde(serialize_with = "response_format::option_audio_params")]
+    pub format: Option<AudioParams>,
     #[serde(serialize_with = "response_format::duration_millis_precise")]
     pub duration: Duration,
 }
 
-impl QueueEntry {
-    /// almost all fields are todo!
-    pub fn mostly_fake(pos: u32, id: QueueId, song: crate::system::Song) -> Self {
-        Self {
-            path: song.path,
-            last_modified: Timestamp::constant(0, 0),
-            added: Timestamp::constant(0, 0),
-            format: AudioParams {
-                samplerate: nz!(42),
-                bits: 16,
-                channels: nz!(42),
-            },
-            artist: song.artist.unwrap_or("unknown".to_owned()),
-            album_artist: "todo".to_string(),
-            title: song.title.unwrap_or("unknown".to_owned()),
-            album: song.album.unwrap_or("unknown".to_owned()),
-            track: 42,
-            date: "todo".to_string(),
-            genre: None,
-            label: "todo".to_string(),
-            disc: None,
-            duration: song.playtime,
-            pos: QueuePos(pos),
-            id,
-        }
-    }
+/// `count`, grouped there is one per tag value
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub struct CountResult {
+    pub songs: usize,
+    #[serde(serialize_with = "response_format::duration_seconds")]
+    pub playtime: Duration,
 }
 
 #[derive(Serialize, Debug)]
 pub struct Status {
     pub repeat: bool,
     pub random: bool,
-    pub single: bool,
-    pub consume: bool,
+    #[serde(serialize_with = "response_format::single_state")]
+    pub single: SingleState,
+    #[serde(serialize_with = "response_format::consume_state")]
+    pub consume: ConsumeState,
     /// Name of the current partition
     ///
     /// A partition is one frontend of a multi-player MPD process: it has
@@ -524,6 +683,10 @@ pub struct Status {
     pub song: Option<QueuePos>,
     /// the current song stopped on or playing
     pub songid: Option<QueueId>,
+    /// Deprecated `elapsed:duration` in whole seconds, still read by older
+    /// clients. Only while playing or paused.
+    #[serde(serialize_with = "response_format::option_elapsed_and_total")]
+    pub time: Option<(Duration, Duration)>,
     #[serde(serialize_with = "response_format::option_duration_millis_precise")]
     pub elapsed: Option<Duration>,
     pub bitrate: Option<u64>,
@@ -532,6 +695,8 @@ pub struct Status {
     pub duration: Option<Duration>,
     #[serde(serialize_with = "response_format::option_audio_params")]
     pub audio: Option<AudioParams>,
+    /// The id of the update job that is running or next in line
+    pub updating_db: Option<u32>,
     pub error: Option<String>,
     ///the next song to be played
     pub nextsong: Option<QueuePos>,
@@ -563,6 +728,16 @@ pub enum ReplayGainMode {
     Auto,
 }
 
+/// Oneshot stops after the current song once and then turns itself off
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum SingleState {
+    #[default]
+    Off,
+    #[serde(rename = "1")]
+    On,
+    Oneshot,
+}
+
 #[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq, Default)]
 pub enum ConsumeState {
     #[default]
@@ -604,12 +779,35 @@ pub struct Range {
     end: Option<u32>,
 }
 
+impl Range {
+    pub fn new(start: u32, end: Option<u32>) -> Self {
+        Self { start, end }
+    }
+
+    /// Without an end the window runs to the end of the results
+    pub fn window(&self) -> core::ops::Range<u32> {
+        self.start..self.end.unwrap_or(u32::MAX)
+    }
+}
+
 #[derive(Deserialize, Debug, Copy, Clone, PartialEq)]
 pub struct FloatRange {
     start: Option<f32>,
     end: Option<f32>,
 }
 
+impl FloatRange {
+    /// In seconds, without an end it plays to the end of the song
+    pub fn new(start: Option<f32>, end: Option<f32>) -> Self {
+        Self { start, end }
+    }
+
+    /// Starts at the start and has no end
+    pub fn is_whole_song(&self) -> bool {
+        self.start.unwrap_or_default() == 0.0 && self.end.is_none()
+    }
+}
+
 #[derive(Deserialize, Debug, Copy, Clone, PartialEq)]
 pub enum PosOrRange {
     Position(Position),
@@ -632,12 +830,12 @@ pub enum PlaylistSaveMode {
 
 #[derive(Deserialize, Debug, Copy, Clone, PartialEq)]
 pub struct Sort {
-    reverse: bool,
-    kind: SortType,
+    pub reverse: bool,
+    pub kind: SortType,
 }
 
 #[derive(Deserialize, Debug, Copy, Clone, PartialEq)]
-enum SortType {
+pub enum SortType {
     Tag(Tag),
     Mtime,
     Prio,
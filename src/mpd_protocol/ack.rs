@@ -0,0 +1,103 @@
+//! Errors the client is told about in an `ACK` line, the connection stays
+//! open. Any other error closes it.
+//!
+//! see <https://mpd.readthedocs.io/en/stable/protocol.html#failure-responses>
+
+use std::fmt;
+
+use color_eyre::Report;
+
+/// mpd's `enum ack` from `src/protocol/Ack.hxx`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    NotList = 1,
+    Arg = 2,
+    Password = 3,
+    Permission = 4,
+    Unknown = 5,
+    NoExist = 50,
+    PlaylistMax = 51,
+    System = 52,
+    PlaylistLoad = 53,
+    UpdateAlready = 54,
+    PlayerSync = 55,
+    Exist = 56,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ack {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl Ack {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// The failure response to `command`, the `index`-th in its command list
+    /// or zero outside of one
+    pub fn line(&self, index: usize, command: &str) -> String {
+        format!(
+            "ACK [{}@{index}] {{{command}}} {}\n",
+            self.code as u8, self.message
+        )
+    }
+}
+
+impl fmt::Display for Ack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Ack {}
+
+/// The Ack `error` started from or was wrapped in, if any
+pub fn find(error: &Report) -> Option<&Ack> {
+    error.downcast_ref::<Ack>()
+}
+
+/// Turns an error without an Ack into a system error for the client, for
+/// commands that should never close the connection
+pub fn or_system(error: Report) -> Report {
+    if find(&error).is_some() {
+        return error;
+    }
+    let message = error.to_string();
+    error.wrap_err(Ack::new(ErrorCode::System, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use color_eyre::eyre::{WrapErr, eyre};
+
+    use super::*;
+
+    #[test]
+    fn acks_are_found_through_wrapping() {
+        let ack = Ack::new(ErrorCode::Arg, "Bad song index");
+        let error = Err::<(), _>(ack.clone())
+            .wrap_err("Failed to add song to queue")
+            .wrap_err("outer")
+            .unwrap_err();
+        assert_eq!(find(&error), Some(&ack));
+
+        let plain = eyre!("disk on fire");
+        assert_eq!(find(&plain), None);
+        let system = or_system(plain);
+        assert_eq!(find(&system).unwrap().code, ErrorCode::System);
+        assert_eq!(find(&system).unwrap().message, "disk on fire");
+    }
+
+    #[test]
+    fn line_has_mpds_format() {
+        let ack = Ack::new(ErrorCode::Arg, "Bad song index");
+        assert_eq!(ack.line(0, "addid"), "ACK [2@0] {addid} Bad song index\n");
+        let ack = Ack::new(ErrorCode::NoExist, "No such song");
+        assert_eq!(ack.line(3, "add"), "ACK [50@3] {add} No such song\n");
+    }
+}
@@ -2,77 +2,184 @@
 
 use camino::Utf8PathBuf;
 use color_eyre::{Section, eyre::Context};
-use itertools::Itertools;
 use peg::{RuleResult, RuleResult::*};
 use std::str::FromStr;
 
-mod query;
+pub mod query;
 
 use crate::mpd_protocol::{
+    ChannelName,
     Command::{self, *},
-    List, Position, QueueId, Sort, SubSystem, Tag, VolumeChange,
+    ConsumeState,
+    List, PlaylistSaveMode, PosOrRange, Position, QueueId, QueuePos, Range, Sort, SortType,
+    SingleState, SubSystem, Tag, TimeOrOffset, VolumeChange,
     query::Query,
 };
+use crate::playlist::PlaylistName;
 
 peg::parser! {
 grammar command() for str {
     pub rule line() -> Command
         = v:command() {v}
     rule command() -> Command
-        = query_state() / playback_options() / control_playback() / manipulate_queue() / manipulate_playlist() / interact_with_database() / mounts_and_neighbors() / stickers() / connection_settings() / partitions() / audio_outputs() / client_to_client() / command_without_arguments()
+        = query_state() / playback_options() / control_playback() / manipulate_queue() / manipulate_playlist() / interact_with_database() / mounts_and_neighbors() / stickers() / connection_settings() / partitions() / audio_outputs() / client_to_client() / extensions() / command_without_arguments()
 
     rule query_state() -> Command
-    = "idle" s:list(<subsystem()>) { Command::Idle(s) }
+    = "idle" s:list(<subsystem()>) { Command::Idle(s) } /
+      "stats_internal" { Command::StatsInternal }
 
     rule playback_options() -> Command
-    = "todo" { todo!() }
+    = "random" _ random:flag() { Command::Random(random) } /
+      "repeat" _ repeat:flag() { Command::Repeat(repeat) } /
+      "single" _ single:single_state() { Command::Single(single) } /
+      "consume" _ consume:consume_state() { Command::Consume(consume) }
     rule control_playback() -> Command
-    = pause() / setvol()
+    = pause() / play() / seek() / setvol()
     rule manipulate_queue() -> Command
-    = add() / playlistid()
+    = add() / playlistinfo() / playlistid() / delete() / move_entries() / shuffle()
     rule manipulate_playlist() -> Command
-    = "todo" { todo!() }
+    = load() / save() / playlistclear() / playlistadd() / playlistdelete() / playlistmove() / rename() / rm()
     rule interact_with_database() -> Command
-    = list_tag() / lsinfo() / find()
+    = list_tag() / lsinfo() / update() / count() / find_add() / find() / search_add_pl() / search_add() / search() / artwork()
     rule mounts_and_neighbors() -> Command
-    = "todo" { todo!() }
+    = "todo" {? Err("not yet supported") }
     rule stickers() -> Command
-    = "todo" { todo!() }
+    = "todo" {? Err("not yet supported") }
     rule connection_settings() -> Command =
         "binarylimit" _ n:number() { Command::BinaryLimit(n) } /
         "tagtypes" _ t:tagtypes() {t}
     rule partitions() -> Command
-    = "todo" { todo!() }
+    = "partition" _ name:name() { Command::Partition(name) } /
+      "newpartition" _ name:name() { Command::NewPartition(name) } /
+      "delpartition" _ name:name() { Command::DelPartition(name) }
     rule audio_outputs() -> Command
-    = "todo" { todo!() }
+    = "outputset" _ id:number() _ attribute:name() _ value:name() { Command::OutputSet(id, attribute, value) }
     rule client_to_client() -> Command
-    = "todo" { todo!() }
+    = "subscribe" _ c:channel() { Command::Subscribe(c) } /
+      "unsubscribe" _ c:channel() { Command::Unsubscribe(c) } /
+      "sendmessage" _ c:channel() _ message:name() { Command::SendMessage(c, message) }
+    rule extensions() -> Command
+    = "x-mpdhaj" _ "waveform" _ uri:uri() _ buckets:number() { Command::Waveform(uri, buckets) } /
+      "x-mpdhaj" _ "undecodable" { Command::Undecodable }
     rule command_without_arguments() -> Command
         = c:$(['a'..='z' | 'A'..='Z']+) {? Command::from_str(c).or(Err("invalid command character"))  }
 
     // control_playback
     rule setvol() -> Command
-        = "setvol" _ v:number() { Command::Volume(VolumeChange(v)) }
+        = "setvol" _ v:number() { Command::SetVol(v) } /
+          "volume" _ "+"? v:number::<i8>() { Command::Volume(VolumeChange(v)) } /
+          "volume" _ "-" v:number::<i8>() { Command::Volume(VolumeChange(-v)) }
     rule pause() -> Command
-        = "pause" is_paused:(_ state:(['1' | '0']) {state})? { Command::Pause(is_paused.map(|s| s == '0')) }
+        = "pause" is_paused:(_ state:(['1' | '0']) {state})? { Command::Pause(is_paused.map(|s| s == '1')) }
+    rule play() -> Command
+        = "playid" _ id:song_id() { Command::PlayId(Some(id)) } /
+          "play" _ pos:number() { Command::Play(Some(QueuePos(pos))) }
+    rule seek() -> Command
+        = "seekcur" _ t:time_or_offset() { Command::SeekCur(t) } /
+          "seekid" _ id:song_id() _ t:seconds() { Command::SeekId(id, t) } /
+          "seek" _ pos:number() _ t:seconds() { Command::Seek(QueuePos(pos), t) }
     // manipulate queue
+    rule playlistinfo() -> Command
+    = "playlistinfo" what:(_ what:(
+        range:range() { PosOrRange::Range(range) } /
+        "\""? pos:number() "\""? { PosOrRange::Position(Position::Absolute(pos)) }
+      ) {what})? { Command::PlaylistInfo(what) }
     rule playlistid() -> Command
     = "playlistid" id:(_ "\""? id:song_id() "\""? {id})? { Command::PlaylistId(id) }
+    rule delete() -> Command
+    = "deleteid" _ id:song_id() { Command::DeleteId(id) } /
+      "delete" _ what:pos_or_range() { Command::Delete(Some(what)) }
+    rule move_entries() -> Command
+    = "moveid" _ id:song_id() _ to:position() { Command::MoveId(id, to) } /
+      "move" _ what:pos_or_range() _ to:position() { Command::Move(Some(what), to) }
+    rule shuffle() -> Command
+    = "shuffle" _ range:range() { Command::Shuffle(Some(range)) }
     rule add() -> Command
-    = "add" _ uri:uri() pos:(_ pos:position() {pos})? { Command::Add(uri, pos) }
+    = "addid" _ uri:uri() pos:(_ pos:position() {pos})? { Command::AddId(uri, pos) } /
+      "add" _ uri:uri() pos:(_ pos:position() {pos})? { Command::Add(uri, pos) }
+
+    // manipulate_playlist
+    rule load() -> Command
+    = "load" _ name:playlist_name() range:(_ range:range() {range})? pos:(_ pos:position() {pos})? {
+        Command::Load(name, range, pos)
+    }
+    rule save() -> Command
+    = "save" _ name:playlist_name() mode:(_ mode:save_mode() {mode})? { Command::Save(name, mode) }
+    rule save_mode() -> PlaylistSaveMode
+    = "\""? mode:(
+        "create" { PlaylistSaveMode::Create } /
+        "append" { PlaylistSaveMode::Append } /
+        "replace" { PlaylistSaveMode::Replace }
+    ) "\""? {mode}
+    rule playlistclear() -> Command
+    = "playlistclear" _ name:playlist_name() { Command::PlaylistClear(name) }
+    rule playlistadd() -> Command
+    = "playlistadd" _ name:playlist_name() _ uri:uri() pos:(_ pos:number() {QueuePos(pos)})? {
+        Command::PlaylistAdd(name, uri, pos)
+    }
+    rule playlistdelete() -> Command
+    = "playlistdelete" _ name:playlist_name() _ what:pos_or_range() {
+        Command::PlaylistDelete(name, what)
+    }
+    rule rename() -> Command
+    = "rename" _ from:playlist_name() _ to:playlist_name() { Command::Rename(from, to) }
+    rule rm() -> Command
+    = "rm" _ name:playlist_name() { Command::Rm(name) }
+    rule playlistmove() -> Command
+    = "playlistmove" _ name:playlist_name() _ what:pos_or_range() _ to:number() {
+        Command::PlaylistMove(name, Some(what), QueuePos(to))
+    }
 
     // interact_with_database
     rule lsinfo() -> Command
-        = ("lsinfo" / "listall") uri:(_ uri:uri() {uri})? {
-        Command::ListAll(uri)
-    }
+        = "lsinfo" uri:(_ uri:uri() {uri})? { Command::LsInfo(uri.unwrap_or_default()) } /
+          "listallinfo" uri:(_ uri:uri() {uri})? { Command::ListAllInfo(uri) } /
+          "listall" uri:(_ uri:uri() {uri})? { Command::ListAll(uri) } /
+          "listfiles" uri:(_ uri:uri() {uri})? { Command::ListFiles(uri.unwrap_or_default()) } /
+          "readcomments" _ uri:uri() { Command::ReadComments(uri) }
+    rule update() -> Command
+        = "update" uri:(_ uri:uri() {uri})? { Command::Update(uri) } /
+          "rescan" uri:(_ uri:uri() {uri})? { Command::Rescan(uri) }
+    rule artwork() -> Command
+        = "albumart" _ uri:uri() _ offset:number() { Command::AlbumArt(uri, offset) } /
+          "readpicture" _ uri:uri() _ offset:number() { Command::ReadPicture(uri, offset) }
     rule list_tag() -> Command
-        = "list" _ tag_to_list:tag() query:(_ query:filter() {query})? group_by:(_ "group" group_by:tag() {group_by})* window:(_ window:window() {window})? {
+        = "list" _ tag_to_list:listable_tag() query:(_ query:filter() {query})? group_by:(_ "group" _ group_by:tag() {group_by})* window:(_ "window" _ window:window() {window})? {
         Command::List(List { tag_to_list, query, group_by, window })
     }
+    rule listable_tag() -> Tag
+        = tag:tag() {? if tag == Tag::Any { Err("a tag other than any") } else { Ok(tag) } }
+    rule count() -> Command
+        = "count" q:(_ "\"\"" {None} / _ q:filter() {Some(q)})? group:(_ "group" _ g:listable_tag() {g})? {
+            Command::Count(q.flatten().unwrap_or_else(Query::everything), group)
+        }
     rule find() -> Command
-        = "find" _ q:filter() sort:sort()?  range:(_ w:window() {w})?
+        = "find" _ q:filter() sort:sort()?  range:(_ "window" _ w:window() {w})?
             { Command::Find(q, sort, range) }
+    rule search() -> Command
+        = "search" _ q:filter() sort:sort()?  range:(_ "window" _ w:window() {w})?
+            { Command::Search(q, sort, range) }
+    rule find_add() -> Command
+        = "findadd" _ q:filter() sort:sort()?  range:(_ "window" _ w:window() {w})? pos:add_position()?
+            { Command::FindAdd(q, sort, range, pos) }
+    rule search_add() -> Command
+        = "searchadd" _ q:filter() sort:sort()?  range:(_ "window" _ w:window() {w})? pos:add_position()?
+          skip:skip_queued()?
+            {
+                let range = range.map(|w| Range { start: w.start, end: Some(w.end) });
+                Command::SearchAdd(q, sort, range, pos, skip)
+            }
+    rule search_add_pl() -> Command
+        = "searchaddpl" _ name:playlist_name() _ q:filter() sort:sort()? range:(_ "window" _ w:window() {w})?
+          pos:add_position()? replace:(_ "x-mpdhaj-replace" _ replace:flag() {replace})?
+            {
+                let range = range.map(|w| Range { start: w.start, end: Some(w.end) });
+                Command::SearchAddPl(name, q, sort, range, pos, replace.unwrap_or_default())
+            }
+    rule add_position() -> Position
+        = _ "position" _ p:position() {p}
+    rule skip_queued() -> bool
+        = _ "x-mpdhaj-skip-queued" _ "\""? skip:['0' | '1'] "\""? { skip == '1' }
 
     // util
 
@@ -84,20 +191,24 @@ grammar command() for str {
     rule filter() -> Query = #{query::parse }
 
     rule sort() -> Sort
-    = "todo" {
-        todo!()
+    = _ "sort" _ reverse:"-"? kind:sort_type() {
+        Sort { reverse: reverse.is_some(), kind }
     }
+    rule sort_type() -> SortType
+    = "Last-Modified" { SortType::Mtime } /
+      "prio" { SortType::Prio } /
+      tag:tag() { SortType::Tag(tag) }
 
     // connection settings
     rule tagtypes() -> Command =
         // ???? why does this one have quotes but not the others, 
         // maybe we need a real tokenizer...
-        "\"clear\"" { TagTypesClear } /
+        "\""? "clear" "\""? { TagTypesClear } /
         "all" { TagTypesAll } /
         "available" { TagTypesAvailable } /
         "enable" _ types:(tag() ++ _) { TagTypesEnable(types) } /
-        "disable" _ types:(tag() ++ _) { TagTypesEnable(types) } /
-        "reset" _ types:(tag() ++ _) { TagTypesEnable(types) }
+        "disable" _ types:(tag() ++ _) { TagTypesDisable(types) } /
+        "reset" _ types:(tag() ++ _) { TagTypesReset(types) }
 
     // util
     rule list<T>(x: rule<T>) -> Vec<T>
@@ -105,17 +216,48 @@ grammar command() for str {
 
     rule number<T: std::str::FromStr>() -> T
     = "\""? s:$(['0'..='9']+) "\""? {? s.parse().or(Err("number")) }
+    rule flag() -> bool
+    = "\""? f:['0' | '1'] "\""? { f == '1' }
+    rule single_state() -> SingleState
+    = "\""? c:(
+        "0" { SingleState::Off } /
+        "1" { SingleState::On } /
+        "oneshot" { SingleState::Oneshot }
+    ) "\""? {c}
+    rule consume_state() -> ConsumeState
+    = "\""? c:(
+        "0" { ConsumeState::Off } /
+        "1" { ConsumeState::On } /
+        "oneshot" { ConsumeState::Oneshot }
+    ) "\""? {c}
+    rule seconds() -> f32
+    = "\""? s:$(['0'..='9']+ ("." ['0'..='9']*)?) "\""? {? s.parse().or(Err("seconds")) }
+    rule time_or_offset() -> TimeOrOffset
+    = "\""? t:(
+        "+" s:seconds() { TimeOrOffset::Relative(s) } /
+        "-" s:seconds() { TimeOrOffset::Relative(-s) } /
+        s:seconds() { TimeOrOffset::Absolute(s) }
+    ) "\""? {t}
     rule name() -> String = #{ string }
     rule tag() -> Tag = #{ try_from_str }
     rule subsystem() -> SubSystem = #{ try_from_str }
     // = s:$(['A'..='Z'|'a'..='z'](['A'..='Z'|'a'..='z'|'0'..='9']+)) { s.to_owned() }
 
+    rule channel() -> ChannelName = name:name() { ChannelName(name) }
+    rule playlist_name() -> PlaylistName = name:name() { PlaylistName(name) }
     rule song_id() -> QueueId
     = id:number() { QueueId(id) }
+    rule range() -> Range
+    = "\""? start:number() ":" end:number()? "\""? { Range { start, end } }
+    rule pos_or_range() -> PosOrRange
+    = range:range() { PosOrRange::Range(range) } /
+      pos:position() { PosOrRange::Position(pos) }
     rule position() -> Position
-    =     n:number() { Position::Absolute(n) } /
-      "+" n:number::<i32>() { Position::Relative(n + 1 ) } /
-      "-" n:number::<i32>() { Position::Relative(-n) }
+    = "\""? p:(
+            n:number() { Position::Absolute(n) } /
+        "+" n:number::<i32>() {? n.checked_add(1).map(Position::Relative).ok_or("position") } /
+        "-" n:number::<i32>() { Position::Relative(-n) }
+    ) "\""? {p}
 
     rule uri() -> Utf8PathBuf = #{ uri }
     rule _() = quiet!{[' '|'\t']}
@@ -147,36 +289,27 @@ fn string(input: &str, pos: usize) -> RuleResult<String> {
 }
 
 // TODO: make \ escaping work correctly on windows...
+/// A backslash escapes the character after it. Consumed counts bytes of the
+/// input, escapes and quotes included.
 fn possibly_quoted_string(input: &str, unquoted_ends_on: &str) -> RuleResult<String> {
-    if !input.starts_with(['"', '\'']) {
-        return if let Some(len) = input.find(unquoted_ends_on) {
-            Matched(len, input[..len].to_owned())
-        } else {
-            Matched(input.len(), input.to_owned())
-        };
-    }
-    let quote = input
-        .chars()
-        .next()
-        .expect("we return if quote is not found");
+    let mut chars = input.char_indices();
+    let quote = match chars.next() {
+        Some((_, quote @ ('"' | '\''))) => quote,
+        _ => {
+            let len = input.find(unquoted_ends_on).unwrap_or(input.len());
+            return Matched(len, input[..len].to_owned());
+        }
+    };
+
     let mut output = String::new();
-    let padded = input.chars();
-
-    for w @ (_, _) in padded.tuple_windows() {
-        if quote == '"' {
-            match w {
-                ('\\', c @ ('\\' | '"')) => output.push(c),
-                (_, '\\') => {}
-                (_, '"') => return Matched(output.len() + 2, output),
-                (_, c) => output.push(c),
-            }
-        } else {
-            match w {
-                ('\\', c @ ('\\' | '\'')) => output.push(c),
-                (_, '\\') => {}
-                (_, '\'') => return Matched(output.len() + 2, output),
-                (_, c) => output.push(c),
-            }
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some((_, escaped)) => output.push(escaped),
+                None => break,
+            },
+            c if c == quote => return Matched(i + c.len_utf8(), output),
+            c => output.push(c),
         }
     }
     // unclosed string
@@ -194,7 +327,8 @@ pub fn parse(s: &str) -> color_eyre::Result<Command> {
     match result {
         Ok(c) => Ok(c),
         Err(e) => {
-            Report::build(
+            // Only a debugging aid, a broken stderr should not take the client down
+            let _ = Report::build(
                 ReportKind::Error,
                 e.location.column - 1..e.location.column - 1,
             )
@@ -204,8 +338,7 @@ pub fn parse(s: &str) -> color_eyre::Result<Command> {
                     .with_message(format!("Expected one of {}", e.expected)),
             )
             .finish()
-            .print(Source::from(s))
-            .unwrap();
+            .print(Source::from(s));
 
             Err(e)
                 .wrap_err("Could not parse line")
@@ -246,6 +379,309 @@ mod tests {
         assert_eq!("asdf\\asdf", string(s, 0).unwrap());
     }
 
+    #[test]
+    fn outputset() {
+        assert_eq!(
+            parse("outputset 0 balance -0.2").unwrap(),
+            OutputSet(0, "balance".to_string(), "-0.2".to_string())
+        )
+    }
+
+    #[test]
+    fn count_takes_an_optional_filter_and_group() {
+        let everything = Count(Query::everything(), None);
+        assert_eq!(parse("count").unwrap(), everything);
+        assert_eq!(parse(r#"count """#).unwrap(), everything);
+        assert_eq!(
+            parse("count group Artist").unwrap(),
+            Count(Query::everything(), Some(Tag::Artist))
+        );
+        let Count(_, group) = parse(r#"count "((Album == 'Discovery'))" group AlbumArtist"#)
+            .unwrap()
+        else {
+            panic!("not a count");
+        };
+        assert_eq!(group, Some(Tag::AlbumArtist));
+    }
+
+    #[test]
+    fn update_takes_an_optional_path() {
+        assert_eq!(parse("update").unwrap(), Update(None));
+        assert_eq!(
+            parse(r#"update "Daft Punk/Discovery""#).unwrap(),
+            Update(Some("Daft Punk/Discovery".into()))
+        );
+        assert_eq!(parse("rescan a").unwrap(), Rescan(Some("a".into())));
+    }
+
+    #[test]
+    fn play_takes_a_position_and_playid_an_id() {
+        assert_eq!(parse("play").unwrap(), Play(None));
+        assert_eq!(parse("play 3").unwrap(), Play(Some(QueuePos(3))));
+        assert_eq!(parse("playid").unwrap(), PlayId(None));
+        assert_eq!(parse(r#"playid "12""#).unwrap(), PlayId(Some(QueueId(12))));
+    }
+
+    #[test]
+    fn delete_takes_a_position_or_a_range() {
+        let range = |start, end| Some(PosOrRange::Range(Range { start, end }));
+        assert_eq!(
+            parse("delete 3").unwrap(),
+            Delete(Some(PosOrRange::Position(Position::Absolute(3))))
+        );
+        assert_eq!(parse("delete 2:5").unwrap(), Delete(range(2, Some(5))));
+        assert_eq!(parse(r#"delete "2:""#).unwrap(), Delete(range(2, None)));
+        assert_eq!(parse("delete").unwrap(), Delete(None));
+        assert_eq!(parse("deleteid 7").unwrap(), DeleteId(QueueId(7)));
+    }
+
+    #[test]
+    fn playlistinfo_takes_an_optional_position_or_range() {
+        let range = |start, end| Some(PosOrRange::Range(Range { start, end }));
+        assert_eq!(
+            parse("playlistinfo 3").unwrap(),
+            PlaylistInfo(Some(PosOrRange::Position(Position::Absolute(3))))
+        );
+        assert_eq!(parse("playlistinfo 2:5").unwrap(), PlaylistInfo(range(2, Some(5))));
+        assert_eq!(parse(r#"playlistinfo "2:""#).unwrap(), PlaylistInfo(range(2, None)));
+        assert_eq!(parse("playlistinfo").unwrap(), PlaylistInfo(None));
+        assert!(parse("playlistinfo +1").is_err());
+    }
+
+    #[test]
+    fn move_takes_a_position_or_range_and_a_target() {
+        assert_eq!(
+            parse("move 3 0").unwrap(),
+            Move(Some(PosOrRange::Position(Position::Absolute(3))), Position::Absolute(0))
+        );
+        assert_eq!(
+            parse(r#"move "1:3" "+0""#).unwrap(),
+            Move(Some(PosOrRange::Range(Range { start: 1, end: Some(3) })), Position::Relative(1))
+        );
+        assert_eq!(parse("moveid 7 -1").unwrap(), MoveId(QueueId(7), Position::Relative(-1)));
+    }
+
+    #[test]
+    fn random_takes_a_flag_and_shuffle_a_range() {
+        assert_eq!(parse("random 1").unwrap(), Random(true));
+        assert_eq!(parse(r#"random "0""#).unwrap(), Random(false));
+        assert!(parse("random 2").is_err());
+        assert_eq!(parse("shuffle").unwrap(), Shuffle(None));
+        assert_eq!(
+            parse("shuffle 2:4").unwrap(),
+            Shuffle(Some(Range { start: 2, end: Some(4) }))
+        );
+    }
+
+    #[test]
+    fn options_take_a_flag_and_single_and_consume_oneshot() {
+        assert_eq!(parse("repeat 1").unwrap(), Repeat(true));
+        assert_eq!(parse(r#"single "0""#).unwrap(), Single(SingleState::Off));
+        assert_eq!(parse("single oneshot").unwrap(), Single(SingleState::Oneshot));
+        assert_eq!(parse("consume 1").unwrap(), Consume(ConsumeState::On));
+        assert_eq!(parse("consume oneshot").unwrap(), Consume(ConsumeState::Oneshot));
+        assert_eq!(parse(r#"consume "0""#).unwrap(), Consume(ConsumeState::Off));
+        assert!(parse("consume twice").is_err());
+    }
+
+    #[test]
+    fn setvol_is_absolute_and_volume_relative() {
+        assert_eq!(parse("setvol 150").unwrap(), SetVol(150));
+        assert_eq!(parse("volume 5").unwrap(), Volume(VolumeChange(5)));
+        assert_eq!(parse("volume +5").unwrap(), Volume(VolumeChange(5)));
+        assert_eq!(parse("volume -5").unwrap(), Volume(VolumeChange(-5)));
+        assert_eq!(parse("getvol").unwrap(), GetVol);
+    }
+
+    #[test]
+    fn seek_takes_seconds_and_seekcur_an_offset() {
+        assert_eq!(parse("seek 2 61.5").unwrap(), Seek(QueuePos(2), 61.5));
+        assert_eq!(parse(r#"seekid "7" "3""#).unwrap(), SeekId(QueueId(7), 3.0));
+        assert_eq!(parse("seekcur 10").unwrap(), SeekCur(TimeOrOffset::Absolute(10.0)));
+        assert_eq!(parse("seekcur +10").unwrap(), SeekCur(TimeOrOffset::Relative(10.0)));
+        assert_eq!(parse(r#"seekcur "-2.5""#).unwrap(), SeekCur(TimeOrOffset::Relative(-2.5)));
+    }
+
+    #[test]
+    fn sendmessage() {
+        assert_eq!(
+            parse(r#"sendmessage chat "hello there""#).unwrap(),
+            SendMessage(ChannelName("chat".to_owned()), "hello there".to_owned())
+        );
+        assert_eq!(
+            parse("subscribe chat").unwrap(),
+            Subscribe(ChannelName("chat".to_owned()))
+        );
+    }
+
+    #[test]
+    fn tagtypes() {
+        assert_eq!(parse("tagtypes").unwrap(), TagTypes);
+        assert_eq!(parse("tagtypes clear").unwrap(), TagTypesClear);
+        assert_eq!(parse(r#"tagtypes "clear""#).unwrap(), TagTypesClear);
+        assert_eq!(
+            parse("tagtypes disable Album Genre").unwrap(),
+            TagTypesDisable(vec![Tag::Album, Tag::Genre])
+        );
+        assert_eq!(
+            parse("tagtypes reset Artist").unwrap(),
+            TagTypesReset(vec![Tag::Artist])
+        );
+    }
+
+    #[test]
+    fn any_can_not_be_listed() {
+        assert!(parse("list any").is_err());
+        assert_eq!(
+            parse("list Artist").unwrap(),
+            List(crate::mpd_protocol::List {
+                tag_to_list: Tag::Artist,
+                query: None,
+                group_by: Vec::new(),
+                window: None,
+            })
+        );
+    }
+
+    #[test]
+    fn list_takes_a_filter_and_groups() {
+        assert_eq!(
+            parse(r#"list Album "((Artist == Abba))" group AlbumArtist group Date"#).unwrap(),
+            List(crate::mpd_protocol::List {
+                tag_to_list: Tag::Album,
+                query: Some(Query(QueryNode::Filter(Filter::TagEqual {
+                    tag: Tag::Artist,
+                    needle: "Abba".to_string(),
+                }))),
+                group_by: vec![Tag::AlbumArtist, Tag::Date],
+                window: None,
+            })
+        );
+    }
+
+    #[test]
+    fn search_with_sort() {
+        assert_eq!(
+            parse(r#"search "((Artist == abba))" sort -Title"#).unwrap(),
+            Search(
+                Query(QueryNode::Filter(Filter::TagEqual {
+                    tag: Tag::Artist,
+                    needle: "abba".to_string(),
+                })),
+                Some(Sort {
+                    reverse: true,
+                    kind: SortType::Tag(Tag::Title),
+                }),
+                None
+            )
+        )
+    }
+
+    #[test]
+    fn find_takes_a_window_after_the_sort() {
+        let Find(_, sort, window) =
+            parse(r#"find "((Artist == a))" sort -Last-Modified window 0:10"#).unwrap()
+        else {
+            panic!("not a find");
+        };
+        assert_eq!(
+            sort,
+            Some(Sort {
+                reverse: true,
+                kind: SortType::Mtime,
+            })
+        );
+        assert_eq!(window, Some(0..10));
+        let SearchAdd(_, None, range, Some(Position::Absolute(0)), None) =
+            parse(r#"searchadd "((Artist == a))" window "1:3" position 0"#).unwrap()
+        else {
+            panic!("not a searchadd");
+        };
+        assert_eq!(range, Some(Range::new(1, Some(3))));
+        assert!(parse(r#"find "((Artist == a))" 0:10"#).is_err());
+    }
+
+    #[test]
+    fn addid_with_position() {
+        assert_eq!(
+            parse(r#"addid "a/b.flac" -1"#).unwrap(),
+            AddId("a/b.flac".into(), Some(Position::Relative(-1)))
+        );
+        assert_eq!(parse("add a.flac").unwrap(), Add("a.flac".into(), None));
+    }
+
+    #[test]
+    fn findadd_with_position() {
+        let artist = || {
+            Query(QueryNode::Filter(Filter::TagEqual {
+                tag: Tag::Artist,
+                needle: "Abba".to_string(),
+            }))
+        };
+        assert_eq!(
+            parse(r#"findadd "((Artist == Abba))" position +0"#).unwrap(),
+            FindAdd(artist(), None, None, Some(Position::Relative(1)))
+        );
+        assert_eq!(
+            parse(r#"searchadd "((Artist == Abba))""#).unwrap(),
+            SearchAdd(artist(), None, None, None, None)
+        );
+        assert_eq!(
+            parse(r#"searchadd "((Artist == Abba))" position 0 x-mpdhaj-skip-queued 1"#).unwrap(),
+            SearchAdd(artist(), None, None, Some(Position::Absolute(0)), Some(true))
+        );
+    }
+
+    #[test]
+    fn stored_playlists_are_named_first() {
+        let mix = || PlaylistName("mix.m3u".to_owned());
+        assert_eq!(parse("save mix.m3u").unwrap(), Save(mix(), None));
+        assert_eq!(
+            parse(r#"save "mix.m3u" "replace""#).unwrap(),
+            Save(mix(), Some(PlaylistSaveMode::Replace))
+        );
+        assert_eq!(parse("playlistclear mix.m3u").unwrap(), PlaylistClear(mix()));
+        assert_eq!(
+            parse(r#"save mix.m3u "append""#).unwrap(),
+            Save(mix(), Some(PlaylistSaveMode::Append))
+        );
+        assert_eq!(
+            parse(r#"rename mix.m3u "old mix.m3u""#).unwrap(),
+            Rename(mix(), PlaylistName("old mix.m3u".to_owned()))
+        );
+        assert_eq!(parse("rm mix.m3u").unwrap(), Rm(mix()));
+        assert_eq!(parse("load mix.m3u").unwrap(), Load(mix(), None, None));
+        assert_eq!(
+            parse(r#"load mix.m3u "1:" +0"#).unwrap(),
+            Load(mix(), Some(Range::new(1, None)), Some(Position::Relative(1)))
+        );
+        assert_eq!(
+            parse(r#"playlistadd mix.m3u "a b.flac" 2"#).unwrap(),
+            PlaylistAdd(mix(), "a b.flac".into(), Some(QueuePos(2)))
+        );
+        assert_eq!(
+            parse("playlistdelete mix.m3u 1:3").unwrap(),
+            PlaylistDelete(mix(), PosOrRange::Range(Range::new(1, Some(3))))
+        );
+        assert_eq!(
+            parse("playlistmove mix.m3u 4 0").unwrap(),
+            PlaylistMove(
+                mix(),
+                Some(PosOrRange::Position(Position::Absolute(4))),
+                QueuePos(0)
+            )
+        );
+
+        let artist = Query(QueryNode::Filter(Filter::TagEqual {
+            tag: Tag::Artist,
+            needle: "Abba".to_string(),
+        }));
+        assert_eq!(
+            parse(r#"searchaddpl mix.m3u "((Artist == Abba))" x-mpdhaj-replace 1"#).unwrap(),
+            SearchAddPl(mix(), artist, None, None, None, true)
+        );
+    }
+
     #[test]
     fn find() {
         let s = r#"find "((Artist == Abba))""#;
@@ -261,4 +697,100 @@ mod tests {
             )
         )
     }
+
+    #[test]
+    fn unterminated_quotes_are_errors() {
+        assert!(parse(r#"add "Non-Album/Necry-Talkie/北上のススメ"#).is_err());
+        assert!(parse(r#"add "北上のススメ\"#).is_err());
+        assert!(parse(r#"find "((Artist == 'ススメ))""#).is_err());
+    }
+
+    #[test]
+    fn escapes_before_multibyte_characters() {
+        assert_eq!(
+            parse(r#"add "\\北上" +0"#).unwrap(),
+            Add("\\北上".into(), Some(Position::Relative(1)))
+        );
+        assert_eq!(
+            parse(r#"add "\"北上\"""#).unwrap(),
+            Add("\"北上\"".into(), None)
+        );
+    }
+
+    #[test]
+    fn unsupported_syntax_is_an_error() {
+        assert!(parse("todo").is_err());
+        assert!(parse(r#"find "((todo))""#).is_err());
+        assert!(parse("add a +2147483647").is_err());
+    }
+
+    // a taste of what the targets in fuzz/ do, for every `cargo test`
+    #[test]
+    fn mangled_commands_never_panic() {
+        let commands = [
+            r#"find "((Artist == 'Necry Talkie'))" sort -Title window 0:10"#,
+            r#"add "Non-Album/Necry-Talkie/北上のススメ.flac" +0"#,
+            r#"searchadd "((any contains \"ススメ\"))" position -1"#,
+            "sendmessage chat 'hello \\ there'",
+            "idle player mixer options",
+        ];
+        let insertions = ["\"", "'", "\\", "(", ")", " ", "北", "+", "-"];
+        for command in commands {
+            for (i, _) in command.char_indices() {
+                let _ = parse(&command[..i]);
+                let _ = parse(&command[i..]);
+                for insertion in insertions {
+                    let _ = parse(&format!("{}{insertion}{}", &command[..i], &command[i..]));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pause_one_pauses() {
+        assert_eq!(parse("pause 1").unwrap(), Pause(Some(true)));
+        assert_eq!(parse("pause 0").unwrap(), Pause(Some(false)));
+        assert_eq!(parse("pause").unwrap(), Pause(None));
+    }
+
+    #[test]
+    fn partitions() {
+        assert_eq!(
+            parse("partition default").unwrap(),
+            Partition("default".to_owned())
+        );
+        assert_eq!(
+            parse(r#"newpartition "living room""#).unwrap(),
+            NewPartition("living room".to_owned())
+        );
+        assert_eq!(
+            parse("delpartition kitchen").unwrap(),
+            DelPartition("kitchen".to_owned())
+        );
+        assert_eq!(parse("listpartitions").unwrap(), ListPartitions);
+    }
+
+    #[test]
+    fn waveform() {
+        assert_eq!(
+            parse(r#"x-mpdhaj waveform "a b.flac" 800"#).unwrap(),
+            Waveform("a b.flac".into(), 800)
+        );
+        assert!(parse("x-mpdhaj waveform a.flac").is_err());
+    }
+
+    #[test]
+    fn listfiles_defaults_to_the_music_dir() {
+        assert_eq!(parse("listfiles").unwrap(), ListFiles(Utf8PathBuf::new()));
+        assert_eq!(
+            parse(r#"listfiles "a b""#).unwrap(),
+            ListFiles(Utf8PathBuf::from("a b"))
+        );
+    }
+
+    #[test]
+    fn undecodable() {
+        assert_eq!(parse("x-mpdhaj undecodable").unwrap(), Undecodable);
+        assert!(parse("x-mpdhaj").is_err());
+    }
 }
@@ -6,8 +6,8 @@ use crate::mpd_protocol::Tag;
 use crate::mpd_protocol::query::{Filter, Query, QueryNode};
 
 // TODO pretty sure we can inline this. But it might be nice for tests
+/// Parses the filter starting at `pos`, the rest of `input` is left alone
 pub fn parse(input: &str, pos: usize) -> RuleResult<Query> {
-    dbg!(&input[pos..]);
     if let Ok((e, consumed)) = query::expression(&input[pos..]) {
         RuleResult::Matched(pos + consumed, Query(e))
     } else {
@@ -17,6 +17,8 @@ pub fn parse(input: &str, pos: usize) -> RuleResult<Query> {
 
 peg::parser! {
 grammar query() for str {
+    // the rest of the command follows the filter
+    #[no_eof]
     pub rule expression() -> (QueryNode, usize)
         = "\""? "(" node:node() ")" "\""? consumed:position!() { (node, consumed) }
     rule node() -> QueryNode
@@ -24,34 +26,34 @@ grammar query() for str {
     rule nested() -> QueryNode
         = "(" n:node() ")" { n }
     rule not() -> QueryNode
-        = "todo" { todo!() }
+        = "todo" {? Err("not yet supported") }
     rule and() -> QueryNode
-        = "todo" { todo!() }
+        = "todo" {? Err("not yet supported") }
 
     rule filter() -> QueryNode
         = filter:(tag_equal() / tag_contains() / tag_starts_with() / tag_regex() / file_equal() / base() / modified_since() / added_since() / audioformat_equals() / audioformat_mask() / pio()) { QueryNode::Filter(filter) }
     rule tag_equal() -> Filter
         = tag:tag() _ "==" _ needle:value() { Filter::TagEqual { tag, needle} }
     rule tag_contains() -> Filter
-        = "todo" { todo!() }
+        = tag:tag() _ "contains" _ needle:value() { Filter::TagContains { tag, needle } }
     rule tag_starts_with() -> Filter
-        = "todo" { todo!() }
+        = "todo" {? Err("not yet supported") }
     rule tag_regex() -> Filter
-        = "todo" { todo!() }
+        = "todo" {? Err("not yet supported") }
     rule file_equal() -> Filter
-        = "todo" { todo!() }
+        = "todo" {? Err("not yet supported") }
     rule base() -> Filter
-        = "todo" { todo!() }
+        = "todo" {? Err("not yet supported") }
     rule modified_since() -> Filter
-        = "todo" { todo!() }
+        = "todo" {? Err("not yet supported") }
     rule added_since() -> Filter
-        = "todo" { todo!() }
+        = "todo" {? Err("not yet supported") }
     rule audioformat_equals() -> Filter
-        = "todo" { todo!() }
+        = "todo" {? Err("not yet supported") }
     rule audioformat_mask() -> Filter
-        = "todo" { todo!() }
+        = "todo" {? Err("not yet supported") }
     rule pio() -> Filter
-        = "todo" { todo!() }
+        = "todo" {? Err("not yet supported") }
 
 
     // UTIL
@@ -126,4 +128,15 @@ mod tests {
             })
         );
     }
+
+    #[test]
+    fn any_contains() {
+        assert_eq!(
+            parse("((any contains 'foo'))").unwrap(),
+            QueryNode::Filter(Filter::TagContains {
+                tag: Tag::Any,
+                needle: "foo".to_string()
+            })
+        );
+    }
 }
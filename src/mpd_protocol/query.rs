@@ -7,7 +7,7 @@ use rodio::{ChannelCount, SampleRate};
 
 use crate::mpd_protocol::Tag;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Filter {
     /// (TAG == 'VALUE'): match a tag value; if there are multiple values of the
     /// given type, at least one must match.
@@ -60,7 +60,7 @@ impl Default for Filter {
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum QueryNode {
     Filter(Filter),
     NegatedFilter(Filter),
@@ -81,8 +81,15 @@ impl Default for QueryNode {
 /// (EXPRESSION1 AND EXPRESSION2 ...): combine two or more expressions with
 /// logical “and”. Note that each expression must be enclosed in parentheses,
 /// e.g. ((artist == 'FOO') AND (album == 'BAR'))
-#[derive(Debug, Default, PartialEq, Eq)]
-pub(crate) struct Query(pub QueryNode);
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Query(pub QueryNode);
+
+impl Query {
+    /// Matches every song, for commands where the filter may be left out
+    pub fn everything() -> Self {
+        Self(QueryNode::And(Vec::new()))
+    }
+}
 
 // #[cfg(test)]
 // mod tests {
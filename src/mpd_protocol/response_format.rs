@@ -5,7 +5,7 @@ mod ser;
 
 use std::time::Duration;
 
-use crate::mpd_protocol::{AudioParams, SubSystem};
+use crate::mpd_protocol::{AudioParams, ConsumeState, FloatRange, SingleState, SubSystem};
 
 pub use ser::to_string;
 
@@ -44,6 +44,40 @@ where
     }
 }
 
+/// Like `12.000-95.500`, the end is left empty when it plays to the end of
+/// the song
+pub fn option_float_range<S>(range: &Option<FloatRange>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    match range {
+        Some(range) if !range.is_whole_song() => {
+            let start = range.start.unwrap_or_default();
+            match range.end {
+                Some(end) => serializer.collect_str(&format_args!("{start:.3}-{end:.3}")),
+                None => serializer.collect_str(&format_args!("{start:.3}-")),
+            }
+        }
+        _ => serializer.serialize_none(),
+    }
+}
+
+/// Like `75:320`, rounded to whole seconds the way mpd does
+pub fn option_elapsed_and_total<S>(
+    time: &Option<(Duration, Duration)>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    if let Some((elapsed, total)) = time {
+        let seconds = |duration: &Duration| duration.as_secs_f64().round() as u64;
+        serializer.collect_str(&format_args!("{}:{}", seconds(elapsed), seconds(total)))
+    } else {
+        serializer.serialize_none()
+    }
+}
+
 pub fn option_audio_params<S>(
     params: &Option<AudioParams>,
     serializer: S,
@@ -69,12 +103,66 @@ pub fn audio_params<S>(
 where
     S: serde::Serializer,
 {
+    let bits = bits.map_or_else(|| "f".to_owned(), |bits| bits.to_string());
     serializer.serialize_str(&format!("{samplerate}:{bits}:{channels}"))
 }
 
-pub fn subsystem(s: SubSystem) -> String {
-    let s = ser::to_string(&s).expect("Subsystem should always serialize");
-    format!("changed: {s}\nOK\n")
+/// `0`, `1` or `oneshot`, clients need the last to show the mode
+pub fn single_state<S>(single: &SingleState, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_str(match single {
+        SingleState::Off => "0",
+        SingleState::On => "1",
+        SingleState::Oneshot => "oneshot",
+    })
+}
+
+/// Like [`single_state`]
+pub fn consume_state<S>(consume: &ConsumeState, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_str(match consume {
+        ConsumeState::Off => "0",
+        ConsumeState::On => "1",
+        ConsumeState::Oneshot => "oneshot",
+    })
+}
+
+/// The reply to an idle, a line per subsystem
+pub fn subsystems(changed: &[SubSystem]) -> String {
+    let mut reply = String::new();
+    for s in changed {
+        let s = ser::to_string(s).expect("Subsystem should always serialize");
+        reply += &format!("changed: {s}\n");
+    }
+    reply + "OK\n"
+}
+
+/// Like `2025-06-15T22:06:58Z`, mpd has no use for fractions of seconds
+pub fn iso_seconds<S>(ts: &jiff::Timestamp, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    let seconds =
+        jiff::Timestamp::from_second(ts.as_second()).map_err(serde::ser::Error::custom)?;
+    serializer.collect_str(&seconds)
+}
+
+pub fn option_iso_seconds<S>(
+    ts: &Option<jiff::Timestamp>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    if let Some(ts) = ts {
+        iso_seconds(ts, serializer)
+    } else {
+        serializer.serialize_none()
+    }
 }
 
 pub fn unix_time<S>(ts: &jiff::Timestamp, serializer: S) -> Result<S::Ok, S::Error>
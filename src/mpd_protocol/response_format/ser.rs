@@ -4,6 +4,9 @@ use super::error::{Error, Result};
 
 pub struct Serializer {
     output: String,
+    /// Key of the struct field being serialized, a sequence there repeats
+    /// it for every element
+    field: Option<&'static str>,
 }
 
 pub fn to_string<T>(value: &T) -> Result<String>
@@ -12,6 +15,7 @@ where
 {
     let mut serializer = Serializer {
         output: String::new(),
+        field: None,
     };
     value.serialize(&mut serializer)?;
     Ok(serializer.output)
@@ -211,6 +215,15 @@ impl ser::SerializeSeq for &mut Serializer {
     where
         T: ?Sized + Serialize,
     {
+        // `Artist: a\nArtist: b`, the field wrote the first key already
+        if let Some(field) = self.field {
+            if !self.output.ends_with(": ") {
+                self.output.push('\n');
+                self.output += field;
+                self.output += ": ";
+            }
+            return value.serialize(&mut **self);
+        }
         value.serialize(&mut **self)?;
         if !self.output.ends_with('\n') {
             self.output.push('\n');
@@ -222,6 +235,10 @@ impl ser::SerializeSeq for &mut Serializer {
         // if !self.output.ends_with('\n') {
         //     self.output.push('\n');
         // }
+        if self.field.is_some() && self.output.ends_with(": ") {
+            // an empty sequence is left out, like None
+            return ser::Serializer::serialize_none(self);
+        }
         Ok(())
     }
 }
@@ -320,7 +337,10 @@ impl ser::SerializeStruct for &mut Serializer {
     {
         self.output += key;
         self.output += ": ";
-        value.serialize(&mut **self)?;
+        self.field = Some(key);
+        let serialized = value.serialize(&mut **self);
+        self.field = None;
+        serialized?;
         self.output.push('\n');
         Ok(())
     }
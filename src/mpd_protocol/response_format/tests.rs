@@ -3,8 +3,8 @@ use std::time::Duration;
 use rodio::nz;
 
 use crate::mpd_protocol::{
-    AudioParams, ListItem, PlaybackState, QueueEntry, QueueId, QueueInfo, QueuePos, Status, Volume,
-    response_format,
+    AudioParams, BrowseEntry, ConsumeState, FloatRange, PlaybackState, QueueEntry, QueueId,
+    QueueInfo, QueuePos, SingleState, SongBlock, Status, Volume, response_format,
 };
 
 #[test]
@@ -13,8 +13,8 @@ fn serialize_status() {
         response_format::to_string(&Status {
             repeat: false,
             random: true,
-            single: false,
-            consume: true,
+            single: SingleState::Oneshot,
+            consume: ConsumeState::On,
             partition: "default".to_string(),
             volume: Volume::new(50),
             playlist: 22,
@@ -24,14 +24,16 @@ fn serialize_status() {
             xfade: Duration::from_secs(5),
             song: Some(QueuePos(5)),
             songid: Some(QueueId(5)),
+            time: None,
             elapsed: Some(Duration::from_secs(2)),
             bitrate: Some(320_000),
             duration: Some(Duration::from_secs(320)),
             audio: Some(AudioParams {
                 samplerate: nz!(44100),
-                bits: 24,
+                bits: Some(24),
                 channels: nz!(2)
             }),
+            updating_db: Some(3),
             error: Some("Failed to open \"usb dac attached to pi\" (alsa); Failed to open ALSA device \"hw:CARD=UD110v2,DEV=1\": No such device".to_string()),
             nextsong: Some(QueuePos(1)),
             nextsongid: Some(QueueId(1)),
@@ -39,7 +41,7 @@ fn serialize_status() {
         .unwrap(),
         "repeat: 0
 random: 1
-single: 0
+single: oneshot
 consume: 1
 partition: default
 volume: 50
@@ -53,6 +55,7 @@ elapsed: 2.000
 bitrate: 320000
 duration: 320.000
 audio: 44100:24:2
+updating_db: 3
 error: Failed to open \"usb dac attached to pi\" (alsa); Failed to open ALSA device \"hw:CARD=UD110v2,DEV=1\": No such device
 nextsong: 1
 nextsongid: 1
@@ -60,6 +63,58 @@ nextsongid: 1
     );
 }
 
+#[test]
+fn serialize_playing_status() {
+    pretty_assertions::assert_eq!(
+        response_format::to_string(&Status {
+            repeat: false,
+            random: false,
+            single: SingleState::On,
+            consume: ConsumeState::Oneshot,
+            partition: "default".to_string(),
+            volume: Volume::new(100),
+            playlist: 3,
+            playlistlength: 2,
+            state: PlaybackState::Play,
+            lastloadedplaylist: None,
+            xfade: Duration::from_secs(0),
+            song: Some(QueuePos(0)),
+            songid: Some(QueueId(1)),
+            time: Some((
+                Duration::from_millis(74_600),
+                Duration::from_millis(319_800)
+            )),
+            elapsed: Some(Duration::from_millis(74_600)),
+            bitrate: None,
+            duration: Some(Duration::from_millis(319_800)),
+            audio: None,
+            updating_db: None,
+            error: None,
+            nextsong: Some(QueuePos(1)),
+            nextsongid: Some(QueueId(2)),
+        })
+        .unwrap(),
+        "repeat: 0
+random: 0
+single: 1
+consume: oneshot
+partition: default
+volume: 100
+playlist: 3
+playlistlength: 2
+state: play
+xfade: 0
+song: 0
+songid: 1
+time: 75:320
+elapsed: 74.600
+duration: 319.800
+nextsong: 1
+nextsongid: 2
+"
+    );
+}
+
 #[test]
 fn serialize_playlistinfo() {
     pretty_assertions::assert_eq!(
@@ -68,67 +123,74 @@ fn serialize_playlistinfo() {
                 path: "Lukas Graham/7 Years.mp3".into(),
                 last_modified: "2025-06-15T22:08:17Z".parse().unwrap(),
                 added: "2025-11-07T15:33:17Z".parse().unwrap(),
-                format: AudioParams {
+                format: Some(AudioParams {
                     samplerate: nz!(44100),
-                    bits: 16,
+                    bits: Some(16),
                     channels: nz!(2)
-                },
+                }),
                 disc: None,
-                date: "2023".to_string(),
-                album_artist: "Various Artists".to_string(),
-                track: 15,
-                label: "Warner Music Group - X5 Music Group".to_string(),
-                genre: None,
-                album: "do you ever think about dying".to_string(),
-                title: "7 Years".to_string(),
-                artist: "Lukas Graham".to_string(),
+                date: Some("2023".to_string()),
+                album_artist: Some("Various Artists".to_string()),
+                track: Some(15),
+                label: Some("Warner Music Group - X5 Music Group".to_string()),
+                genre: Vec::new(),
+                album: Some("do you ever think about dying".to_string()),
+                title: Some("7 Years".to_string()),
+                artist: vec!["Lukas Graham".to_string()],
                 duration: Duration::from_secs_f64(237.3),
-                pos: QueuePos(0),
-                id: QueueId(294),
+                pos: Some(QueuePos(0)),
+                id: Some(QueueId(294)),
+                // all of the song is as good as no range
+                range: Some(FloatRange::new(None, None)),
+                prio: None,
             },
             QueueEntry {
                 path: "Taylor Swift/1989/01 Welcome To New York.mp3".into(),
                 last_modified: "2025-06-15T22:06:26Z".parse().unwrap(),
                 added: "2025-11-07T15:33:05Z".parse().unwrap(),
-                format: AudioParams {
+                format: Some(AudioParams {
                     samplerate: nz!(44100),
-                    bits: 16,
+                    bits: Some(16),
                     channels: nz!(2)
-                },
-                artist: "Taylor Swift".to_string(),
-                album_artist: "Taylor Swift".to_string(),
-                title: "Welcome To New York".to_string(),
-                album: "1989 (Deluxe)".to_string(),
-                track: 19,
-                date: "2014".to_string(),
-                genre: Some("Country & Folk".to_string()),
+                }),
+                artist: vec!["Taylor Swift".to_string()],
+                album_artist: Some("Taylor Swift".to_string()),
+                title: Some("Welcome To New York".to_string()),
+                album: Some("1989 (Deluxe)".to_string()),
+                track: Some(19),
+                date: Some("2014".to_string()),
+                genre: vec!["Country & Folk".to_string()],
                 disc: Some(1),
-                label: "Taylor Swift".to_string(),
+                label: Some("Taylor Swift".to_string()),
                 duration: Duration::from_secs_f64(212.6),
-                pos: QueuePos(1),
-                id: QueueId(295),
+                pos: Some(QueuePos(1)),
+                id: Some(QueueId(295)),
+                range: Some(FloatRange::new(Some(12.0), Some(95.5))),
+                prio: Some(3),
             },
             QueueEntry {
                 path: "Chappell Roan/EPs/Chappell Roan - School Nights (2017) [24B-44.1kHz]/03. Meantime.flac".into(),
                 last_modified: "2025-06-15T22:14:00Z".parse().unwrap(),
                 added: "2025-11-07T15:36:03Z".parse().unwrap(),
-                format: AudioParams {
+                format: Some(AudioParams {
                     samplerate: nz!(44100),
-                    bits: 24,
+                    bits: Some(24),
                     channels: nz!(2)
-                },
-                album_artist: "Chappell Roan".to_string(),
-                label: "Atlantic Records".to_string(),
-                artist: "Chappell Roan".to_string(),
-                title: "Meantime".to_string(),
-                album: "School Nights".to_string(),
-                date: "2017-09-22".to_string(),
-                genre: "Pop, Rock, Alternatif et Indé".to_string().into(),
-                track: 3,
+                }),
+                album_artist: Some("Chappell Roan".to_string()),
+                label: Some("Atlantic Records".to_string()),
+                artist: vec!["Chappell Roan".to_string()],
+                title: Some("Meantime".to_string()),
+                album: Some("School Nights".to_string()),
+                date: Some("2017-09-22".to_string()),
+                genre: vec!["Pop, Rock, Alternatif et Indé".to_string()],
+                track: Some(3),
                 disc: None,
                 duration: Duration::from_secs_f64(183.448),
-                pos: QueuePos(2),
-                id: QueueId(296),
+                pos: Some(QueuePos(2)),
+                id: Some(QueueId(296)),
+                range: Some(FloatRange::new(Some(60.0), None)),
+                prio: Some(255),
 
             }
         ]))
@@ -148,6 +210,7 @@ duration: 237.300
 Pos: 0
 Id: 294
 file: Taylor Swift/1989/01 Welcome To New York.mp3
+Range: 12.000-95.500
 Last-Modified: 2025-06-15T22:06:26Z
 Added: 2025-11-07T15:33:05Z
 Format: 44100:16:2
@@ -163,7 +226,9 @@ Disc: 1
 duration: 212.600
 Pos: 1
 Id: 295
+Prio: 3
 file: Chappell Roan/EPs/Chappell Roan - School Nights (2017) [24B-44.1kHz]/03. Meantime.flac
+Range: 60.000-
 Last-Modified: 2025-06-15T22:14:00Z
 Added: 2025-11-07T15:36:03Z
 Format: 44100:24:2
@@ -178,6 +243,7 @@ Label: Atlantic Records
 duration: 183.448
 Pos: 2
 Id: 296
+Prio: 255
 "
     );
 }
@@ -186,11 +252,142 @@ Id: 296
 fn listall() {
     pretty_assertions::assert_eq!(
         response_format::to_string(&vec![
-            ListItem::Directory("test/directory with spaces/subdir".into()),
-            ListItem::File("test_file".into())
+            BrowseEntry::Directory {
+                path: "test/directory with spaces/subdir".into(),
+                last_modified: None,
+            },
+            BrowseEntry::file("test_file".into())
         ])
         .unwrap(),
         "directory: test/directory with spaces/subdir\n\
         file: test_file\n",
     )
 }
+
+/// Laid out like mpd answers `lsinfo`, each entry's lines follow its key
+#[test]
+fn lsinfo_interleaves_directories_songs_and_playlists() {
+    let song = QueueEntry {
+        path: "Taylor Swift/1989/01 Welcome To New York.mp3".into(),
+        last_modified: "2025-06-15T22:06:26Z".parse().unwrap(),
+        added: "2025-11-07T15:33:05Z".parse().unwrap(),
+        format: Some(AudioParams {
+            samplerate: nz!(44100),
+            bits: Some(16),
+            channels: nz!(2),
+        }),
+        artist: vec!["Taylor Swift".to_string()],
+        album_artist: None,
+        title: Some("Welcome To New York".to_string()),
+        album: Some("1989 (Deluxe)".to_string()),
+        track: Some(1),
+        date: Some("2014".to_string()),
+        genre: Vec::new(),
+        disc: None,
+        label: None,
+        duration: Duration::from_secs_f64(212.6),
+        pos: None,
+        id: None,
+        range: None,
+        prio: None,
+    };
+    pretty_assertions::assert_eq!(
+        response_format::to_string(&vec![
+            BrowseEntry::Directory {
+                path: "Taylor Swift/1989/Bonus".into(),
+                // mpd has no use for fractions of seconds
+                last_modified: Some("2025-06-15T22:06:58.25Z".parse().unwrap()),
+            },
+            BrowseEntry::File(SongBlock::Info(Box::new(song))),
+            BrowseEntry::Playlist {
+                name: crate::playlist::PlaylistName("road trip".to_string()),
+                last_modified: Some("2025-11-07T15:40:00Z".parse().unwrap()),
+            },
+        ])
+        .unwrap(),
+        "directory: Taylor Swift/1989/Bonus
+Last-Modified: 2025-06-15T22:06:58Z
+file: Taylor Swift/1989/01 Welcome To New York.mp3
+Last-Modified: 2025-06-15T22:06:26Z
+Added: 2025-11-07T15:33:05Z
+Format: 44100:16:2
+Artist: Taylor Swift
+Title: Welcome To New York
+Album: 1989 (Deluxe)
+Track: 1
+Date: 2014
+duration: 212.600
+playlist: road trip
+Last-Modified: 2025-11-07T15:40:00Z
+"
+    );
+}
+
+#[test]
+fn lossy_formats_have_float_samples() {
+    #[derive(serde::Serialize)]
+    struct Song {
+        file: &'static str,
+        #[serde(serialize_with = "response_format::option_audio_params")]
+        #[serde(rename = "Format")]
+        format: Option<AudioParams>,
+    }
+    let opus = Song {
+        file: "a.opus",
+        format: Some(AudioParams {
+            samplerate: nz!(48000),
+            bits: None,
+            channels: nz!(2),
+        }),
+    };
+    let unknown = Song {
+        file: "a.opus",
+        format: None,
+    };
+    assert_eq!(
+        response_format::to_string(&opus).unwrap(),
+        "file: a.opus\nFormat: 48000:f:2\n"
+    );
+    assert_eq!(
+        response_format::to_string(&unknown).unwrap(),
+        "file: a.opus\n"
+    );
+}
+
+#[test]
+fn every_value_of_a_tag_gets_a_line() {
+    #[derive(serde::Serialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct Song {
+        #[serde(rename = "file")]
+        file: &'static str,
+        artist: Vec<&'static str>,
+        genre: Vec<&'static str>,
+        title: &'static str,
+    }
+    let song = Song {
+        file: "a.flac",
+        artist: vec!["Herbie Hancock", "Chick Corea"],
+        genre: vec!["Jazz", "Fusion", "Funk"],
+        title: "Liza",
+    };
+    assert_eq!(
+        response_format::to_string(&song).unwrap(),
+        "file: a.flac\n\
+         Artist: Herbie Hancock\n\
+         Artist: Chick Corea\n\
+         Genre: Jazz\n\
+         Genre: Fusion\n\
+         Genre: Funk\n\
+         Title: Liza\n"
+    );
+    let untagged = Song {
+        artist: Vec::new(),
+        genre: Vec::new(),
+        ..song
+    };
+    assert_eq!(
+        response_format::to_string(&untagged).unwrap(),
+        "file: a.flac\nTitle: Liza\n"
+    );
+}
@@ -13,6 +13,26 @@ fn parse_binary_limit() {
     );
 }
 
+#[test]
+fn parse_stats_internal() {
+    assert_eq!(
+        Command::parse("stats_internal").unwrap(),
+        Command::StatsInternal
+    );
+}
+
+#[test]
+fn parse_artwork() {
+    assert_eq!(
+        Command::parse("albumart \"Abba/Gold/01 Dancing Queen.flac\" 8192").unwrap(),
+        Command::AlbumArt("Abba/Gold/01 Dancing Queen.flac".into(), 8192)
+    );
+    assert_eq!(
+        Command::parse("readpicture Abba/Gold/01.flac 0").unwrap(),
+        Command::ReadPicture("Abba/Gold/01.flac".into(), 0)
+    );
+}
+
 #[test]
 fn parse_idle_with_args() {
     assert_eq!(
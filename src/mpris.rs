@@ -0,0 +1,481 @@
+//! The MPRIS D-Bus interface, so media keys and desktop sound menus control
+//! mpdhaj without a bridge like mpDris2. Changes reach D-Bus through the same
+//! idle notifications mpd clients get.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+
+use color_eyre::Result;
+use color_eyre::eyre::Context;
+use tokio::sync::{Mutex, mpsc};
+use zbus::connection::Builder;
+use zbus::object_server::InterfaceRef;
+use zbus::zvariant::{ObjectPath, OwnedValue, Value};
+use zbus::{Connection, fdo, interface};
+
+use crate::mpd_protocol::{PlaybackState, QueueId, SingleState, SubSystem, TimeOrOffset};
+use crate::system::System;
+use crate::system::playback::Target;
+
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.mpdhaj";
+const PATH: &str = "/org/mpris/MediaPlayer2";
+/// Tracks with no queue id, the spec reserves this path for them
+const NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+
+/// Claims the MPRIS name on the session bus. `art_port` is the port of the
+/// artwork HTTP endpoint, songs get an art url if it is set.
+pub async fn start(system: Arc<Mutex<System>>, art_port: Option<u16>) -> Result<Connection> {
+    let builder = Builder::session()
+        .wrap_err("Could not connect to the session bus")?
+        .name(BUS_NAME)
+        .wrap_err("Invalid bus name")?;
+    serve(builder, system, art_port).await
+}
+
+async fn serve(
+    builder: Builder<'_>,
+    system: Arc<Mutex<System>>,
+    art_port: Option<u16>,
+) -> Result<Connection> {
+    let player = MprisPlayer {
+        system: Arc::clone(&system),
+        art_port,
+    };
+    let connection = builder
+        .serve_at(PATH, Root)
+        .wrap_err("Could not serve the MPRIS root interface")?
+        .serve_at(PATH, player)
+        .wrap_err("Could not serve the MPRIS player interface")?
+        .build()
+        .await
+        .wrap_err("Could not set up the D-Bus connection")?;
+
+    let changes = system.lock().await.idle(vec![
+        SubSystem::Player,
+        SubSystem::Mixer,
+        SubSystem::Options,
+    ]);
+    let player = connection
+        .object_server()
+        .interface::<_, MprisPlayer>(PATH)
+        .await
+        .wrap_err("The MPRIS player interface is not served")?;
+    tokio::task::spawn(emit_changes(player, changes));
+    Ok(connection)
+}
+
+/// Sends PropertiesChanged for everything a subsystem covers
+async fn emit_changes(player: InterfaceRef<MprisPlayer>, mut changes: mpsc::Receiver<SubSystem>) {
+    while let Some(subsystem) = changes.recv().await {
+        let emitter = player.signal_emitter();
+        let interface = player.get().await;
+        let emitted = match subsystem {
+            SubSystem::Player => {
+                let status = interface.playback_status_changed(emitter).await;
+                status.and(interface.metadata_changed(emitter).await)
+            }
+            SubSystem::Mixer => interface.volume_changed(emitter).await,
+            SubSystem::Options => {
+                let shuffle = interface.shuffle_changed(emitter).await;
+                shuffle.and(interface.loop_status_changed(emitter).await)
+            }
+            _ => Ok(()),
+        };
+        if let Err(e) = emitted {
+            tracing::warn!("Could not send MPRIS property change: {e}");
+        }
+    }
+}
+
+fn failed(e: color_eyre::Report) -> fdo::Error {
+    fdo::Error::Failed(format!("{e:#}"))
+}
+
+struct Root;
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+    fn raise(&self) {}
+
+    fn quit(&self) {}
+
+    #[zbus(property)]
+    fn can_quit(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_raise(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn identity(&self) -> &str {
+        "mpdhaj"
+    }
+
+    #[zbus(property)]
+    fn supported_uri_schemes(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    #[zbus(property)]
+    fn supported_mime_types(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+struct MprisPlayer {
+    system: Arc<Mutex<System>>,
+    art_port: Option<u16>,
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl MprisPlayer {
+    async fn play_pause(&self) -> fdo::Result<()> {
+        let mut system = self.system.lock().await;
+        // pausing while stopped does nothing
+        let target = if system.playing == PlaybackState::Stop {
+            Target::Play
+        } else {
+            Target::Pause(None)
+        };
+        system.set_playback(target).await.map_err(failed)
+    }
+
+    async fn play(&self) -> fdo::Result<()> {
+        let mut system = self.system.lock().await;
+        system.set_playback(Target::Play).await.map_err(failed)
+    }
+
+    async fn pause(&self) -> fdo::Result<()> {
+        let mut system = self.system.lock().await;
+        system
+            .set_playback(Target::Pause(Some(true)))
+            .await
+            .map_err(failed)
+    }
+
+    async fn stop(&self) -> fdo::Result<()> {
+        let mut system = self.system.lock().await;
+        system.set_playback(Target::Stop).await.map_err(failed)
+    }
+
+    async fn next(&self) -> fdo::Result<()> {
+        let mut system = self.system.lock().await;
+        system.next().await.map_err(failed)
+    }
+
+    async fn previous(&self) -> fdo::Result<()> {
+        let mut system = self.system.lock().await;
+        system.previous().await.map_err(failed)
+    }
+
+    async fn seek(&self, offset: i64) -> fdo::Result<()> {
+        let offset = TimeOrOffset::Relative(offset as f32 / 1_000_000.0);
+        let mut system = self.system.lock().await;
+        system.seek(None, offset).await.map_err(failed)
+    }
+
+    async fn set_position(&self, track_id: ObjectPath<'_>, position: i64) -> fdo::Result<()> {
+        let mut system = self.system.lock().await;
+        let current = system
+            .current_song()
+            .map_err(failed)?
+            .and_then(|song| song.id);
+        // the spec ignores negative positions and tracks that are no longer
+        // current, the call raced a song change
+        let Some(id) = current.filter(|id| track_id.as_str() == track_path(*id)) else {
+            return Ok(());
+        };
+        if position < 0 {
+            return Ok(());
+        }
+        let to = TimeOrOffset::Absolute(position as f32 / 1_000_000.0);
+        system.seek_id(id, to).await.map_err(failed)
+    }
+
+    fn open_uri(&self, _uri: &str) -> fdo::Result<()> {
+        Err(fdo::Error::NotSupported(
+            "use an mpd client to add songs".to_owned(),
+        ))
+    }
+
+    #[zbus(property)]
+    async fn playback_status(&self) -> &str {
+        match self.system.lock().await.playing {
+            PlaybackState::Play => "Playing",
+            PlaybackState::Pause => "Paused",
+            PlaybackState::Stop => "Stopped",
+        }
+    }
+
+    #[zbus(property)]
+    async fn loop_status(&self) -> fdo::Result<&str> {
+        let status = self.system.lock().await.status().map_err(failed)?;
+        Ok(match (status.repeat, status.single) {
+            (false, _) => "None",
+            (true, SingleState::Off) => "Playlist",
+            (true, _) => "Track",
+        })
+    }
+
+    #[zbus(property)]
+    async fn set_loop_status(&self, status: &str) -> zbus::Result<()> {
+        let (repeat, single) = match status {
+            "None" => (false, SingleState::Off),
+            "Track" => (true, SingleState::On),
+            "Playlist" => (true, SingleState::Off),
+            other => return Err(fdo::Error::InvalidArgs(format!("loop status {other}")).into()),
+        };
+        let mut system = self.system.lock().await;
+        system.set_repeat(repeat).map_err(failed)?;
+        system.set_single(single).map_err(failed)?;
+        Ok(())
+    }
+
+    #[zbus(property)]
+    fn rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn minimum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn maximum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    async fn shuffle(&self) -> fdo::Result<bool> {
+        let status = self.system.lock().await.status().map_err(failed)?;
+        Ok(status.random)
+    }
+
+    #[zbus(property)]
+    async fn set_shuffle(&self, shuffle: bool) -> zbus::Result<()> {
+        let mut system = self.system.lock().await;
+        system.set_random(shuffle).map_err(|e| failed(e).into())
+    }
+
+    #[zbus(property)]
+    async fn metadata(&self) -> fdo::Result<HashMap<String, OwnedValue>> {
+        let song = self.system.lock().await.current_song().map_err(failed)?;
+        let Some(song) = song else {
+            return Ok(HashMap::new());
+        };
+
+        let track_id = match song.id {
+            Some(id) => track_path(id),
+            None => NO_TRACK.to_owned(),
+        };
+        let length = i64::try_from(song.duration.as_micros()).unwrap_or(i64::MAX);
+        let mut metadata = vec![
+            (
+                "mpris:trackid",
+                owned(ObjectPath::try_from(track_id).map_err(invalid)?)?,
+            ),
+            ("mpris:length", owned(length)?),
+            ("xesam:title", owned(song.title.unwrap_or_default())?),
+            ("xesam:album", owned(song.album.unwrap_or_default())?),
+            ("xesam:artist", owned(vec![song.artist])?),
+            (
+                "xesam:albumArtist",
+                owned(vec![song.album_artist.unwrap_or_default()])?,
+            ),
+            (
+                "xesam:trackNumber",
+                owned(song.track.unwrap_or_default() as i32)?,
+            ),
+        ];
+        if let Some(port) = self.art_port {
+            let url = format!(
+                "http://localhost:{port}/art?file={}",
+                url_encode(song.path.as_str())
+            );
+            metadata.push(("mpris:artUrl", owned(url)?));
+        }
+        Ok(metadata
+            .into_iter()
+            .map(|(key, value)| (key.to_owned(), value))
+            .collect())
+    }
+
+    #[zbus(property)]
+    async fn volume(&self) -> fdo::Result<f64> {
+        let status = self.system.lock().await.status().map_err(failed)?;
+        Ok(f64::from(status.volume.get()) / 100.0)
+    }
+
+    #[zbus(property)]
+    async fn set_volume(&self, volume: f64) -> zbus::Result<()> {
+        let volume = (volume.clamp(0.0, 1.0) * 100.0).round() as u8;
+        let mut system = self.system.lock().await;
+        system.set_volume(volume).map_err(|e| failed(e).into())
+    }
+
+    #[zbus(property(emits_changed_signal = "false"))]
+    async fn position(&self) -> i64 {
+        let elapsed = self.system.lock().await.player.status().heard();
+        i64::try_from(elapsed.as_micros()).unwrap_or(i64::MAX)
+    }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        true
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn can_control(&self) -> bool {
+        true
+    }
+}
+
+fn owned<'a>(value: impl Into<Value<'a>>) -> fdo::Result<OwnedValue> {
+    value.into().try_to_owned().map_err(invalid)
+}
+
+fn track_path(id: QueueId) -> String {
+    format!("/org/mpdhaj/track/{}", id.0)
+}
+
+fn invalid(e: zbus::zvariant::Error) -> fdo::Error {
+    fdo::Error::Failed(e.to_string())
+}
+
+/// Percent encodes everything but unreserved characters and slashes
+fn url_encode(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            let _ = write!(encoded, "%{byte:02X}");
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use camino::Utf8PathBuf;
+    use rusqlite::Connection as Db;
+    use tokio::net::UnixStream;
+    use zbus::proxy::CacheProperties;
+    use zbus::{Guid, Proxy};
+
+    use super::*;
+    use crate::player::Player;
+    use crate::player::device::Silent;
+    use crate::player::tests::write_wav;
+
+    #[test]
+    fn art_urls_are_escaped() {
+        assert_eq!(
+            url_encode("Abba/Gold/01 Ça va.flac"),
+            "Abba/Gold/01%20%C3%87a%20va.flac"
+        );
+    }
+
+    #[tokio::test]
+    async fn play_pause_over_dbus() {
+        let music_dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mpdhaj-mpris-{}", std::process::id()));
+        std::fs::create_dir_all(&music_dir).unwrap();
+        write_wav(&music_dir.join("song.wav"), Duration::from_secs(3));
+        let mut system = System::with_parts(
+            Db::open_in_memory().unwrap(),
+            |volume, paused| Player::with_device(volume, paused, Silent),
+            music_dir.clone(),
+            None,
+        )
+        .unwrap();
+        system
+            .db
+            .execute("INSERT INTO songs (path, mtime) VALUES ('song.wav', 0)", [])
+            .unwrap();
+        system
+            .add_many_to_queue(&["song.wav".into()], None)
+            .unwrap();
+        system
+            .db
+            .execute("UPDATE state SET current = 0", [])
+            .unwrap();
+        let system = Arc::new(Mutex::new(system));
+
+        // a private bus of our own, no session bus needed
+        let (server, client) = UnixStream::pair().unwrap();
+        let server = Builder::unix_stream(server)
+            .server(Guid::generate())
+            .unwrap()
+            .p2p();
+        let client = Builder::unix_stream(client).p2p().build();
+        let (_server, client) = tokio::try_join!(
+            async { serve(server, Arc::clone(&system), Some(8080)).await },
+            async { client.await.map_err(color_eyre::Report::from) },
+        )
+        .unwrap();
+        let player = Proxy::builder(&client)
+            .destination(BUS_NAME)
+            .unwrap()
+            .path(PATH)
+            .unwrap()
+            .interface("org.mpris.MediaPlayer2.Player")
+            .unwrap()
+            .cache_properties(CacheProperties::No)
+            .build()
+            .await
+            .unwrap();
+        let status = async || {
+            player
+                .get_property::<String>("PlaybackStatus")
+                .await
+                .unwrap()
+        };
+
+        assert_eq!(status().await, "Stopped");
+        player.call_method("PlayPause", &()).await.unwrap();
+        assert_eq!(status().await, "Playing");
+        assert_eq!(system.lock().await.playing, PlaybackState::Play);
+        player.call_method("PlayPause", &()).await.unwrap();
+        assert_eq!(status().await, "Paused");
+        assert_eq!(system.lock().await.playing, PlaybackState::Pause);
+
+        let metadata: HashMap<String, OwnedValue> = player.get_property("Metadata").await.unwrap();
+        let art = String::try_from(metadata["mpris:artUrl"].clone()).unwrap();
+        assert_eq!(art, "http://localhost:8080/art?file=song.wav");
+        std::fs::remove_dir_all(&music_dir).unwrap();
+    }
+}
@@ -1,43 +1,58 @@
 use atomic_float::AtomicF32;
-use color_eyre::{Result, Section, eyre::Context};
+use color_eyre::{
+    Result, Section,
+    eyre::{Context, eyre},
+};
 use std::{
     fs::File,
     io::BufReader,
     sync::{
-        Arc,
-        atomic::{AtomicBool, Ordering},
-        mpsc,
+        Arc, Mutex, MutexGuard, OnceLock, PoisonError,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError},
     },
     thread,
     time::Duration,
 };
 
-use camino::Utf8Path;
+use camino::{Utf8Path, Utf8PathBuf};
 use rodio::{
     Decoder, DynamicSource, FixedSource, const_source, dynamic_source,
-    dynamic_source_ext::{ExtendDynamicSource, IntoFixedSource},
-    fixed_source::{
-        self,
-        amplify::{Amplify, Factor},
-        pausable::Pausable,
-        periodic_access::{PeriodicAccess, WithData},
-    },
-    mixer, nz, speakers,
+    dynamic_source_ext::ExtendDynamicSource,
+    fixed_source::{self, amplify::Factor, periodic_access::PeriodicAccess},
+    mixer, nz,
 };
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::sync::watch;
 
 use rodio::{
-    self, ConstSource,
-    fixed_source::FixedSourceExt,
-    fixed_source::queue::uniform::{UniformQueue, UniformQueueHandle},
+    self, Block, ChannelCount, ConstSource, SampleRate, fixed_source::FixedSourceExt,
+    fixed_source::queue::uniform::SourceId,
 };
 
+use crate::mpd_protocol::Tag;
+use crate::scan::decoders::DecodeError;
+
+pub mod device;
 pub mod outputs;
+pub mod pipeline;
+
+use device::{DefaultSpeakers, Device, OnError};
+use pipeline::{Output, PlayerPipeline, TrackQueue};
 const AUDIO_THREAD_RESPONSE_LATENCY: Duration = Duration::from_millis(50);
+const SILENCE_THRESHOLD: Factor = Factor::Decibel(-60.0);
+const MIN_SILENCE: Duration = Duration::from_millis(500);
+/// Decoding happens ahead of playback so slow storage does not cause gaps
+const DECODE_AHEAD: Duration = Duration::from_millis(250);
+/// A seek is done at the next periodic access of the song, well within this
+const SEEK_TIMEOUT: Duration = Duration::from_secs(1);
 
 struct PlayerParams {
     // range: 0..=1.0, weight such that 10%
     // louder sounds 10% louder
     volume: AtomicF32,
+    // range: -1.0 (left) ..=1.0 (right)
+    balance: AtomicF32,
     paused: AtomicBool,
 }
 
@@ -45,21 +60,129 @@ struct PlayingHandle {
     abort: AtomicBool,
 }
 
+/// What the player is doing, see [`Player::status`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlayerStatus {
+    /// The song being played, None once only silence is left
+    pub source: Option<SourceId>,
+    /// How much of `source` has been played, in steps of
+    /// `AUDIO_THREAD_RESPONSE_LATENCY`
+    pub elapsed: Duration,
+    pub paused: bool,
+    /// Set while the output is broken or once a song failed to play, until
+    /// the output recovers or [`Player::clear_error`] is called
+    pub error: Option<String>,
+    /// The format the output is fed
+    pub sample_rate: SampleRate,
+    pub channels: ChannelCount,
+    /// Audio in the device's buffer, `elapsed` runs this far ahead of what
+    /// is heard
+    pub latency: Duration,
+}
+
+impl PlayerStatus {
+    /// How much of `source` has been heard
+    pub fn heard(&self) -> Duration {
+        self.elapsed.saturating_sub(self.latency)
+    }
+}
+
 impl PlayerParams {
     fn volume(&self) -> f32 {
         self.volume.load(Ordering::Relaxed)
     }
+    fn balance(&self) -> f32 {
+        self.balance.load(Ordering::Relaxed)
+    }
     fn paused(&self) -> bool {
         self.paused.load(Ordering::Relaxed)
     }
 }
 
 pub struct Player {
-    queue: UniformQueueHandle<MpdTrack>,
+    queue: Box<dyn TrackQueue>,
+    /// Picked for the output when the player was created
+    pipeline: PlayerPipeline,
     params: Arc<PlayerParams>,
     /// Signal the output stream holder thread to stop on drop
-    audio_output_abort_handle: mpsc::Sender<()>,
+    audio_output_abort_handle: mpsc::Sender<Holder>,
+    output_events: Option<UnboundedReceiver<OutputEvent>>,
+    /// For [`TagSender`]s, their tags arrive with the output events
+    tag_events: UnboundedSender<OutputEvent>,
     last_song_abort_handle: Option<AbortHandle>,
+    /// Set when the song after the current one has been prefetched
+    next_song_abort_handle: Option<AbortHandle>,
+    trim_silence: bool,
+    status: Arc<watch::Sender<PlayerStatus>>,
+    /// Told about every song the queue finished
+    finished: mpsc::Sender<SourceId>,
+    /// Times decoding could not keep up, over all songs
+    underruns: Arc<AtomicU64>,
+    buffer_time: Option<Duration>,
+    /// Taken by the song it is for at its next periodic access
+    seek: Arc<Mutex<Option<SeekRequest>>>,
+    opener: Opener,
+    /// See [`Player::open_first`]
+    open_first: bool,
+}
+
+/// Opens the file of a song, tests swap in a slow disk
+pub type OpenFile = fn(&Utf8Path) -> std::io::Result<File>;
+
+/// Opens songs for the player without needing the player, so a slow disk
+/// does not hold up whoever shares it. See [`Player::opener`].
+#[derive(Clone)]
+pub struct Opener {
+    open_file: OpenFile,
+    /// Taken by [`Player::add`] if it is the song it adds
+    opened: Arc<Mutex<Option<(Utf8PathBuf, Result<Decoder<BufReader<File>>>)>>>,
+}
+
+impl Opener {
+    /// Opens and probes `path` on the blocking pool, for the next
+    /// [`Player::add`] of `path`
+    pub async fn open(&self, path: Utf8PathBuf) {
+        let open_file = self.open_file;
+        let opened = Arc::clone(&self.opened);
+        // the add probes it again if this panicked
+        let _ = tokio::task::spawn_blocking(move || {
+            let decoder = probe(open_file, &path);
+            *opened.lock().unwrap_or_else(PoisonError::into_inner) = Some((path, decoder));
+        })
+        .await;
+    }
+
+    fn take(&self, path: &Utf8Path) -> Option<Result<Decoder<BufReader<File>>>> {
+        let mut opened = self.opened.lock().unwrap_or_else(PoisonError::into_inner);
+        opened
+            .take_if(|(opened, _)| *opened == path)
+            .map(|(_, decoder)| decoder)
+    }
+
+    fn has(&self, path: &Utf8Path) -> bool {
+        let opened = self.opened.lock().unwrap_or_else(PoisonError::into_inner);
+        opened.as_ref().is_some_and(|(opened, _)| opened == path)
+    }
+}
+
+/// A song had to be opened while [`Player::open_first`] was set. Open it
+/// with an [`Opener`] and try again.
+#[derive(Debug)]
+pub struct OpenFirst(pub Utf8PathBuf);
+
+impl std::fmt::Display for OpenFirst {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} has to be opened first", self.0)
+    }
+}
+
+impl std::error::Error for OpenFirst {}
+
+/// See [`Player::seek`]
+struct SeekRequest {
+    source: SourceId,
+    to: Duration,
+    done: tokio::sync::oneshot::Sender<Result<(), String>>,
 }
 
 /// Aborts the Source this is connected to when it is dropped
@@ -83,63 +206,49 @@ impl Drop for AbortHandle {
 
 impl Player {
     pub fn new(volume: f32, paused: bool) -> Self {
+        Self::with_device(volume, paused, DefaultSpeakers::default())
+    }
+
+    pub fn with_device(volume: f32, paused: bool, device: impl Device) -> Self {
         let params = Arc::new(PlayerParams {
             volume: AtomicF32::new(volume),
+            balance: AtomicF32::new(0.0),
             paused: AtomicBool::new(paused),
         });
 
-        let builder = speakers::SpeakersBuilder::new()
-            .default_device()
-            .unwrap()
-            .default_config()
-            .unwrap()
-            .prefer_channel_counts([nz!(2)])
-            .prefer_sample_rates([nz!(44100)]);
-
         // The rodio Outputstream gets closed when its dropped. Therefore we
         // need to hold it. We want Player to be send but the Outputstream is
         // not. We therefore hold the stream hostage in this thread until Player
         // drops.
+        let pipeline = PlayerPipeline::for_output(device.preferred_sample_rate());
         let (tx, rx) = mpsc::channel();
-        let (audio_output_abort_handle, abort_rx) = mpsc::channel();
-        let params_clone = Arc::clone(&params);
+        let (audio_output_abort_handle, holder_rx) = mpsc::channel();
+        let runtime_errors = audio_output_abort_handle.clone();
+        let (events_tx, events) = tokio::sync::mpsc::unbounded_channel();
+        let (status, _) = watch::channel(PlayerStatus {
+            source: None,
+            elapsed: Duration::ZERO,
+            paused,
+            error: None,
+            sample_rate: pipeline.sample_rate(),
+            channels: nz!(2),
+            latency: Duration::ZERO,
+        });
+        let status = Arc::new(status);
+        let (finished, finished_rx) = mpsc::channel();
+        let tag_events = events_tx.clone();
+        let output = Output {
+            device,
+            params: Arc::clone(&params),
+            status: Arc::clone(&status),
+            finished: finished_rx,
+            events: events_tx,
+            holder: holder_rx,
+            runtime_errors,
+        };
         thread::Builder::new()
             .name("audio-output-stream-holder".to_string())
-            .spawn(move || {
-                let sink = builder.get_config();
-                let (queue, handle) = UniformQueue::<MpdTrack>::new(nz!(2), nz!(44100));
-                let queue = queue
-                    .pausable(params_clone.paused())
-                    .amplify(Factor::Normalized(volume))
-                    .with_data(params_clone)
-                    .periodic_access(AUDIO_THREAD_RESPONSE_LATENCY, update_params);
-                let needs_resample = sink.sample_rate != queue.sample_rate();
-                let needs_rechannel = sink.channel_count != queue.channels();
-
-                // TODO move all this into builder::play and friends
-                let _sink = match (needs_resample, needs_rechannel) {
-                    (true, true) => builder
-                        .play(
-                            queue
-                                .with_channel_count(sink.channel_count)
-                                .with_sample_rate(sink.sample_rate),
-                        )
-                        .unwrap(),
-                    (true, false) => builder
-                        .play(queue.with_sample_rate(sink.sample_rate))
-                        .unwrap(),
-
-                    (false, true) => builder
-                        .play(queue.with_channel_count(sink.channel_count))
-                        .unwrap(),
-
-                    (false, false) => builder.play(queue).unwrap(),
-                };
-
-                tx.send(handle);
-
-                let _ = abort_rx.recv();
-            })
+            .spawn(move || pipeline.run(output, tx))
             .expect("should be able to spawn threads");
         let queue = rx
             .recv()
@@ -147,35 +256,249 @@ impl Player {
 
         Self {
             queue,
+            pipeline,
             audio_output_abort_handle,
+            output_events: Some(events),
+            tag_events,
             params,
             last_song_abort_handle: None,
+            next_song_abort_handle: None,
+            trim_silence: false,
+            status,
+            finished,
+            underruns: Arc::default(),
+            buffer_time: None,
+            seek: Arc::default(),
+            opener: Opener {
+                open_file: |path| File::open(path),
+                opened: Arc::default(),
+            },
+            open_first: false,
         }
     }
 
-    pub async fn add(&mut self, path: &Utf8Path) -> Result<()> {
-        let file = BufReader::new(
-            File::open(path)
-                .wrap_err("Could not open file")
-                .with_note(|| format!("file: {}", path))?,
-        );
-        let params = Arc::clone(&self.params);
+    /// Songs are opened with `open_file` instead of [`File::open`]
+    #[cfg(test)]
+    pub fn with_file_opener(mut self, open_file: OpenFile) -> Self {
+        self.opener.open_file = open_file;
+        self
+    }
+
+    pub fn opener(&self) -> Opener {
+        self.opener.clone()
+    }
+
+    /// While set, a song that is not yet opened by the [`Opener`] is not
+    /// opened here but reported by [`Player::check_opened`]. For callers
+    /// that can let go of what they share the player with meanwhile.
+    pub fn open_first(&mut self, open_first: bool) {
+        self.open_first = open_first;
+    }
+
+    /// An [`OpenFirst`] if [`Player::add`] would have to open `path` itself
+    /// while [`Player::open_first`] is set
+    pub fn check_opened(&self, path: &Utf8Path) -> Result<()> {
+        if self.open_first && !self.opener.has(path) {
+            return Err(OpenFirst(path.to_owned()).into());
+        }
+        Ok(())
+    }
+
+    /// For the decoder of `source` to report tags that change while it
+    /// plays, like the title of a stream
+    pub fn tag_sender(&self, source: SourceId) -> TagSender {
+        TagSender {
+            source,
+            events: self.tag_events.clone(),
+        }
+    }
+
+    /// The rate songs are decoded at, see [`PlayerPipeline`]
+    pub fn pipeline(&self) -> PlayerPipeline {
+        self.pipeline
+    }
+
+    /// Times decoding could not keep up and silence was played instead
+    pub fn underruns(&self) -> u64 {
+        self.underruns.load(Ordering::Relaxed)
+    }
+
+    /// What the player is doing right now
+    pub fn status(&self) -> PlayerStatus {
+        self.status.borrow().clone()
+    }
+
+    /// Changes whenever [`Player::status`] does, except for elapsed which
+    /// changes continuously while playing
+    pub fn subscribe(&self) -> watch::Receiver<PlayerStatus> {
+        self.status.subscribe()
+    }
+
+    /// Shows up in [`PlayerStatus::error`] until cleared
+    pub fn report_error(&self, error: String) {
+        self.status.send_modify(|status| status.error = Some(error));
+    }
+
+    pub fn clear_error(&self) {
+        self.status
+            .send_if_modified(|status| status.error.take().is_some());
+    }
+
+    /// Failures of the output device, its recovery and the songs that
+    /// finished. Can only be taken once.
+    pub fn take_output_events(&mut self) -> Option<UnboundedReceiver<OutputEvent>> {
+        self.output_events.take()
+    }
+
+    /// Reopens the output with this much audio in its buffer, None leaves it
+    /// to the device. Playback goes on where it was once it is reopened.
+    pub fn set_buffer_time(&mut self, buffer_time: Option<Duration>) {
+        if std::mem::replace(&mut self.buffer_time, buffer_time) != buffer_time {
+            let _ = self
+                .audio_output_abort_handle
+                .send(Holder::Reopen(buffer_time));
+        }
+    }
+
+    pub fn buffer_time(&self) -> Option<Duration> {
+        self.buffer_time
+    }
+
+    /// Skip silence at the start and end of songs that are opened from now on
+    pub fn set_trim_silence(&mut self, trim: bool) {
+        self.trim_silence = trim;
+    }
+
+    /// The song is decoded once it is queued, at the rate of the pipeline
+    fn open(&self, decoder: Decoding) -> (Opened, AbortHandle) {
         let abort_handle = AbortHandle::new();
+        let opened = Opened {
+            decoder,
+            should_stop: abort_handle.clone(),
+            id: Arc::default(),
+            status: Arc::clone(&self.status),
+            seek: Arc::clone(&self.seek),
+            underruns: Arc::clone(&self.underruns),
+            trim_silence: self.trim_silence,
+        };
+        (opened, abort_handle)
+    }
 
-        // this drops any previous abort handle.
-        // Causing any playing song to stop
-        self.last_song_abort_handle = Some(abort_handle.clone());
+    fn enqueue(&self, song: Opened) -> Result<SourceId> {
+        self.queue.add(song, self.finished.clone())
+    }
 
-        let source = Decoder::try_from(file)?
-            .into_fixed_source(nz!(44100), nz!(2))
-            .stoppable()
-            .with_data(abort_handle)
-            .periodic_access(AUDIO_THREAD_RESPONSE_LATENCY, stop_on_abort);
+    /// Stops whatever plays and plays `path` instead. Unless the [`Opener`]
+    /// already did, the file is opened and probed on the blocking pool, a
+    /// slow disk does not hold up the runtime.
+    pub async fn add(&mut self, path: &Utf8Path) -> Result<SourceId> {
+        let decoder = match self.opener.take(path) {
+            Some(decoder) => decoder?,
+            None => {
+                let open_file = self.opener.open_file;
+                let owned = path.to_owned();
+                tokio::task::spawn_blocking(move || probe(open_file, &owned))
+                    .await
+                    .wrap_err("Opening the song panicked")
+                    .with_note(|| format!("file: {path}"))??
+            }
+        };
+        let (song, abort_handle) = self.open(Decoding::Probed(decoder));
+
+        // this drops any previous abort handle.
+        // Causing any playing (or prefetched) song to stop
+        self.last_song_abort_handle = Some(abort_handle);
+        self.next_song_abort_handle = None;
 
         // ensure the previous song has been stopped before the new one starts
         tokio::time::sleep(AUDIO_THREAD_RESPONSE_LATENCY).await;
-        self.queue.add(source);
-        Ok(())
+        self.enqueue(song)
+    }
+
+    /// Aborts the playing and the prefetched song. Once this returns the
+    /// output takes no more samples from them.
+    pub async fn stop(&mut self) {
+        let playing = self.last_song_abort_handle.take().is_some();
+        self.next_song_abort_handle = None;
+        if playing {
+            // the audio thread notices the abort at its next access
+            tokio::time::sleep(AUDIO_THREAD_RESPONSE_LATENCY).await;
+        }
+        self.status.send_if_modified(|status| {
+            status.elapsed = Duration::ZERO;
+            status.source.take().is_some()
+        });
+    }
+
+    /// Queue the song after the current one so it starts without a gap.
+    ///
+    /// We never buffer more then one song ahead. Returns None if a song is
+    /// already waiting in the queue. Cancelled songs do not count, they wait
+    /// in the queue but play nothing.
+    ///
+    /// The file is opened by the thread that decodes it. One that does not
+    /// open plays nothing, it is up to whoever reaches it to skip it.
+    pub fn prefetch(&mut self, path: &Utf8Path) -> Result<Option<SourceId>> {
+        if self.next_song_abort_handle.is_some() {
+            return Ok(None);
+        }
+
+        let (song, abort_handle) = self.open(Decoding::Unopened {
+            path: path.to_owned(),
+            open_file: self.opener.open_file,
+        });
+        let source_id = self.enqueue(song)?;
+        self.next_song_abort_handle = Some(abort_handle);
+        Ok(Some(source_id))
+    }
+
+    /// Aborts the prefetched song before it makes a sound. The queue still
+    /// reports it finished once it gets to it.
+    pub fn cancel_prefetch(&mut self) {
+        self.next_song_abort_handle = None;
+    }
+
+    /// The prefetched song took over from the one before it, it can now be
+    /// stopped like a song started with [`Player::add`]
+    pub fn prefetch_took_over(&mut self) {
+        if let Some(handle) = self.next_song_abort_handle.take() {
+            self.last_song_abort_handle = Some(handle);
+        }
+    }
+
+    /// Plays `source` from `to` on. While paused the seek waits for playback
+    /// to resume, the status shows the new position right away.
+    pub async fn seek(&self, source: SourceId, to: Duration) -> Result<()> {
+        let (done, done_rx) = tokio::sync::oneshot::channel();
+        *self.seek.lock().unwrap_or_else(PoisonError::into_inner) =
+            Some(SeekRequest { source, to, done });
+        self.status.send_if_modified(|status| {
+            if status.source == Some(source) {
+                status.elapsed = to;
+            }
+            false
+        });
+        if self.params.paused() {
+            return Ok(());
+        }
+        match tokio::time::timeout(SEEK_TIMEOUT, done_rx).await {
+            Ok(Ok(result)) => result
+                .map_err(|e| eyre!("Could not seek: {e}"))
+                .with_note(|| format!("seeking to: {to:?}")),
+            // dropped unanswered when another seek replaced it
+            Ok(Err(_)) => Err(eyre!("Another seek came first")),
+            Err(_) => {
+                // the song ended before it got to it
+                let mut seek = self.seek.lock().unwrap_or_else(PoisonError::into_inner);
+                seek.take_if(|request| request.source == source);
+                Err(eyre!("The song is no longer playing"))
+            }
+        }
+    }
+
+    /// How much audio is queued up that has not yet been played
+    pub fn buffered(&self) -> Duration {
+        self.queue.buffered_duration()
     }
 
     pub fn pause(&self) {
@@ -188,29 +511,647 @@ impl Player {
     pub fn set_volume(&self, volume: f32) {
         self.params.volume.store(volume, Ordering::Relaxed);
     }
+    /// balance needs to be between -1 (left) and 1 (right)
+    pub fn set_balance(&self, balance: f32) {
+        self.params.balance.store(balance, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Player {
+    fn drop(&mut self) {
+        // the thread holds a sender too, the channel closing is not enough
+        let _ = self.audio_output_abort_handle.send(Holder::Stop);
+    }
+}
+
+/// Messages for the audio-output-stream-holder thread
+enum Holder {
+    Stop,
+    Failed(String),
+    /// Open the device again with this buffer time
+    Reopen(Option<Duration>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputEvent {
+    Failed(String),
+    /// The device plays again after failing
+    Recovered,
+    /// A song ended or was stopped
+    Finished {
+        source: SourceId,
+        /// How much of it played, None if the next song already took over
+        elapsed: Option<Duration>,
+    },
+    /// The playing song has new tags, see [`TagSender`]
+    Tags {
+        source: SourceId,
+        tags: Vec<(Tag, String)>,
+    },
+}
+
+/// Reports tags of a song that change while it plays, see
+/// [`Player::tag_sender`]
+#[derive(Clone)]
+pub struct TagSender {
+    source: SourceId,
+    events: UnboundedSender<OutputEvent>,
+}
+
+impl TagSender {
+    /// These replace the earlier values of the same tags
+    pub fn send(&self, tags: Vec<(Tag, String)>) {
+        // nobody listening is fine
+        let _ = self.events.send(OutputEvent::Tags {
+            source: self.source,
+            tags,
+        });
+    }
+}
+
+/// Keeps (re)opening the device until the player drops. A failing device is
+/// reported once until it recovers.
+fn hold_output<S: FixedSource + Block + Send + 'static>(
+    device: &mut impl Device,
+    source: Reclaimable<S>,
+    holder_rx: &mpsc::Receiver<Holder>,
+    runtime_errors: &mpsc::Sender<Holder>,
+    events: &UnboundedSender<OutputEvent>,
+    status: &watch::Sender<PlayerStatus>,
+) {
+    let mut last_error = None;
+    let mut report = |error: Option<String>| {
+        if error == last_error {
+            return;
+        }
+        status.send_modify(|status| status.error = error.clone());
+        let event = match &error {
+            Some(error) => OutputEvent::Failed(error.clone()),
+            None => OutputEvent::Recovered,
+        };
+        // nobody listening is fine
+        let _ = events.send(event);
+        last_error = error;
+    };
+
+    loop {
+        let runtime_errors = runtime_errors.clone();
+        let on_error: OnError = Box::new(move |error| {
+            let _ = runtime_errors.send(Holder::Failed(error));
+        });
+        // the device pulls per sample, this pulls the chain in blocks
+        let stream = match device.play(source.clone().pull_blocks(), on_error) {
+            Ok(stream) => {
+                report(None);
+                let latency = device.latency();
+                status.send_if_modified(|status| {
+                    std::mem::replace(&mut status.latency, latency) != latency
+                });
+                Some(stream)
+            }
+            Err(e) => {
+                report(Some(format!("{e:#}")));
+                None
+            }
+        };
+
+        let message = match stream {
+            Some(_) => holder_rx.recv().map_err(RecvTimeoutError::from),
+            None => holder_rx.recv_timeout(device.retry_after()),
+        };
+        match message {
+            Ok(Holder::Stop) | Err(RecvTimeoutError::Disconnected) => return,
+            Ok(Holder::Failed(error)) => {
+                drop(stream);
+                report(Some(error));
+                match holder_rx.recv_timeout(device.retry_after()) {
+                    Ok(Holder::Stop) | Err(RecvTimeoutError::Disconnected) => return,
+                    Ok(Holder::Reopen(buffer_time)) => device.set_buffer_time(buffer_time),
+                    Ok(Holder::Failed(_)) | Err(RecvTimeoutError::Timeout) => (),
+                }
+            }
+            // the same as recovering from a failure, minus the wait
+            Ok(Holder::Reopen(buffer_time)) => {
+                drop(stream);
+                device.set_buffer_time(buffer_time);
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+        }
+    }
+}
+
+/// The output chain shared with the device so it survives the device failing
+/// and can be played again when it recovers.
+struct Reclaimable<S>(Arc<Mutex<S>>);
+
+impl<S> Clone for Reclaimable<S> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<S: FixedSource> Reclaimable<S> {
+    fn lock(&self) -> MutexGuard<'_, S> {
+        // a panic in the audio callback already took down playback
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<S: FixedSource> FixedSource for Reclaimable<S> {
+    fn channels(&self) -> rodio::ChannelCount {
+        self.lock().channels()
+    }
+
+    fn sample_rate(&self) -> rodio::SampleRate {
+        self.lock().sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.lock().total_duration()
+    }
+}
+
+impl<S: FixedSource> Iterator for Reclaimable<S> {
+    type Item = rodio::Sample;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.lock().next()
+    }
+}
+
+/// Locks once per block instead of once per sample
+impl<S: FixedSource + Block> Block for Reclaimable<S> {
+    fn next_block(&mut self, out: &mut [rodio::Sample]) -> usize {
+        self.lock().next_block(out)
+    }
+}
+
+/// Opens `path` and reads enough of it to pick a decoder, this blocks
+fn probe(open_file: OpenFile, path: &Utf8Path) -> Result<Decoder<BufReader<File>>> {
+    let file = BufReader::new(
+        open_file(path)
+            .wrap_err("Could not open file")
+            .with_note(|| format!("file: {}", path))?,
+    );
+    Decoder::try_from(file)
+        .map_err(|e| DecodeError(e.to_string()))
+        .with_note(|| format!("file: {path}"))
+}
+
+/// The file of an [`Opened`] song, probed already or left to the thread
+/// that decodes it
+enum Decoding {
+    Probed(Decoder<BufReader<File>>),
+    Unopened { path: Utf8PathBuf, open_file: OpenFile },
+}
+
+/// A song opened by [`Player::open`], see [`Opened::into_track`]
+struct Opened {
+    decoder: Decoding,
+    should_stop: AbortHandle,
+    /// Unknown until the track is queued, set then
+    id: Arc<OnceLock<SourceId>>,
+    status: Arc<watch::Sender<PlayerStatus>>,
+    seek: Arc<Mutex<Option<SeekRequest>>>,
+    underruns: Arc<AtomicU64>,
+    trim_silence: bool,
+}
+
+impl Opened {
+    /// Decodes at `R`, the rate of the pipeline it is queued in
+    fn into_track<const R: u32>(self) -> MpdTrack<R> {
+        let Self {
+            decoder,
+            should_stop,
+            id: source_id,
+            status,
+            seek,
+            underruns,
+            trim_silence,
+        } = self;
+        let mut elapsed = Duration::ZERO;
+        let on_access: OnAccess<R> = Box::new(move |stoppable: &mut MpdTrackInner<R>| {
+            if should_stop.should_abort() {
+                stoppable.stop();
+            } else if let Some(&id) = source_id.get() {
+                // never wait for the lock on the audio thread
+                let request = seek
+                    .try_lock()
+                    .ok()
+                    .and_then(|mut seek| seek.take_if(|request| request.source == id));
+                if let Some(request) = request {
+                    let result = stoppable.try_seek(request.to);
+                    if result.is_ok() {
+                        elapsed = request.to;
+                    }
+                    let _ = request.done.send(result.map_err(|e| e.to_string()));
+                }
+                // elapsed alone is not worth waking everyone up for
+                status.send_if_modified(|status| {
+                    status.elapsed = elapsed;
+                    status.source.replace(id) != Some(id)
+                });
+            }
+            elapsed += AUDIO_THREAD_RESPONSE_LATENCY;
+        });
+        let decoded = match decoder {
+            Decoding::Probed(decoder) => Lazy::Opened(convert(decoder)),
+            Decoding::Unopened { path, open_file } => Lazy::Unopened { path, open_file },
+        };
+        let decoded = if trim_silence {
+            // trimming reads ahead to find the end of the song
+            let lookahead = rodio::const_source::trim_silence::LOOKAHEAD;
+            let decoded = decoded.buffered(DECODE_AHEAD + lookahead);
+            MaybeTrimmed::Trimmed(decoded.count_underruns_in(underruns).trim_silence(
+                SILENCE_THRESHOLD,
+                MIN_SILENCE,
+                true,
+            ))
+        } else {
+            MaybeTrimmed::Untrimmed(decoded.buffered(DECODE_AHEAD).count_underruns_in(underruns))
+        };
+        decoded
+            .into_fixed_source()
+            .stoppable()
+            .periodic_access(AUDIO_THREAD_RESPONSE_LATENCY, on_access)
+            // a song ending mid frame would swap the channels of the next,
+            // this catches that in debug builds (and so in the tests)
+            .frame_aligned()
+    }
+}
+
+use rodio::const_source::{ConstSourceAdaptor, buffered::Buffered, trim_silence::TrimSilence};
+use rodio::dynamic_source_ext::IntoFixedSource;
+use rodio::fixed_source::{IntoConstSource, frame_aligned::FrameAligned, stoppable::Stoppable};
+
+type Converted<const R: u32> = IntoConstSource<R, 2, IntoFixedSource<Decoder<BufReader<File>>>>;
+
+fn convert<const R: u32>(decoder: Decoder<BufReader<File>>) -> Converted<R> {
+    let rate = SampleRate::new(R).expect("pipelines have a sample rate");
+    decoder
+        .into_fixed_source(rate, nz!(2))
+        .try_into_const_source::<R, 2>()
+        .expect("into_fixed_source converts to exactly these parameters")
+}
+
+/// Opens the file on the first sample asked of it, which [`Buffered`] does
+/// on its own thread. A file that does not open ends the song right away.
+enum Lazy<const R: u32> {
+    Opened(Converted<R>),
+    Unopened { path: Utf8PathBuf, open_file: OpenFile },
+    Failed,
+}
+
+impl<const R: u32> Lazy<R> {
+    fn opened(&mut self) -> Option<&mut Converted<R>> {
+        if let Self::Unopened { path, open_file } = self {
+            *self = match probe(*open_file, path) {
+                Ok(decoder) => Self::Opened(convert(decoder)),
+                Err(e) => {
+                    tracing::warn!("Could not open the next song: {e:#}");
+                    Self::Failed
+                }
+            };
+        }
+        match self {
+            Self::Opened(decoded) => Some(decoded),
+            Self::Unopened { .. } | Self::Failed => None,
+        }
+    }
+}
+
+impl<const R: u32> ConstSource<R, 2> for Lazy<R> {
+    /// Unknown until opened, [`Buffered`] asks before it is
+    fn total_duration(&self) -> Option<Duration> {
+        match self {
+            Self::Opened(decoded) => decoded.total_duration(),
+            Self::Unopened { .. } | Self::Failed => None,
+        }
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), dynamic_source::SeekError> {
+        match self.opened() {
+            Some(decoded) => decoded.try_seek(pos),
+            None => Err(dynamic_source::SeekError::NotSupported {
+                underlying_source: "a song that could not be opened",
+            }),
+        }
+    }
+}
+
+impl<const R: u32> Iterator for Lazy<R> {
+    type Item = rodio::Sample;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.opened()?.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            Self::Opened(decoded) => decoded.size_hint(),
+            Self::Unopened { .. } => (0, None),
+            Self::Failed => (0, Some(0)),
+        }
+    }
+}
+
+impl<const R: u32> Block for Lazy<R> {}
+
+type Decoded<const R: u32> = Buffered<R, 2>;
+
+/// Songs only pay for trimming silence when it is on
+enum MaybeTrimmed<const R: u32> {
+    Trimmed(TrimSilence<R, 2, Decoded<R>>),
+    Untrimmed(Decoded<R>),
+}
+
+impl<const R: u32> ConstSource<R, 2> for MaybeTrimmed<R> {
+    fn total_duration(&self) -> Option<Duration> {
+        match self {
+            Self::Trimmed(source) => source.total_duration(),
+            Self::Untrimmed(source) => source.total_duration(),
+        }
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), dynamic_source::SeekError> {
+        match self {
+            Self::Trimmed(source) => source.try_seek(pos),
+            Self::Untrimmed(source) => source.try_seek(pos),
+        }
+    }
 }
 
-use rodio::fixed_source::stoppable::Stoppable;
+impl<const R: u32> Iterator for MaybeTrimmed<R> {
+    type Item = rodio::Sample;
 
-type DecodeFile = Decoder<BufReader<File>>;
-type MpdTrackInner = WithData<Stoppable<IntoFixedSource<DecodeFile>>, AbortHandle>;
-type MpdTrack = PeriodicAccess<MpdTrackInner>;
-type MpdQueueInner = WithData<Amplify<Pausable<UniformQueue<MpdTrack>>>, Arc<PlayerParams>>;
-type MpdQueue = PeriodicAccess<MpdQueueInner>;
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Trimmed(source) => source.next(),
+            Self::Untrimmed(source) => source.next(),
+        }
+    }
 
-fn stop_on_abort(source: &mut MpdTrackInner) {
-    let abort_handle = &source.data;
-    if abort_handle.should_abort() {
-        let stoppable = source.inner_mut();
-        stoppable.stop();
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            Self::Trimmed(source) => source.size_hint(),
+            Self::Untrimmed(source) => source.size_hint(),
+        }
     }
 }
 
-fn update_params(source: &mut MpdQueueInner) {
-    let params = &source.data;
+impl<const R: u32> Block for MaybeTrimmed<R> {}
+
+type MpdTrackInner<const R: u32> = Stoppable<ConstSourceAdaptor<R, 2, MaybeTrimmed<R>>>;
+// boxed since the queue needs to name the type of the tracks
+type OnAccess<const R: u32> = Box<dyn FnMut(&mut MpdTrackInner<R>) + Send>;
+type MpdTrack<const R: u32> = FrameAligned<PeriodicAccess<MpdTrackInner<R>, OnAccess<R>>>;
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Plays nothing, fails when told to
+    #[derive(Clone, Default)]
+    struct FakeDevice {
+        unplugged: Arc<AtomicBool>,
+        on_error: Arc<Mutex<Option<OnError>>>,
+    }
+
+    impl FakeDevice {
+        fn unplug(&self) {
+            self.unplugged.store(true, Ordering::Relaxed);
+            if let Some(on_error) = self.on_error.lock().unwrap().as_mut() {
+                on_error("device unplugged".to_string());
+            }
+        }
+        fn plug_in(&self) {
+            self.unplugged.store(false, Ordering::Relaxed);
+        }
+    }
+
+    impl Device for FakeDevice {
+        type Stream = ();
+
+        fn play<S: FixedSource + Send + 'static>(
+            &mut self,
+            _source: S,
+            on_error: OnError,
+        ) -> Result<Self::Stream> {
+            if self.unplugged.load(Ordering::Relaxed) {
+                return Err(eyre!("No such device"));
+            }
+            *self.on_error.lock().unwrap() = Some(on_error);
+            Ok(())
+        }
+
+        fn retry_after(&self) -> Duration {
+            Duration::from_millis(10)
+        }
+    }
+
+    #[test]
+    fn output_failures_are_reported_until_recovered() {
+        let device = FakeDevice::default();
+        device.unplug();
+        let mut player = Player::with_device(1.0, true, device.clone());
+        let mut events = player.take_output_events().unwrap();
+        assert!(player.take_output_events().is_none());
+
+        let failed = |error: &str| Some(OutputEvent::Failed(error.to_string()));
+        assert_eq!(events.blocking_recv(), failed("No such device"));
+        device.plug_in();
+        assert_eq!(events.blocking_recv(), Some(OutputEvent::Recovered));
+
+        device.unplug();
+        assert_eq!(events.blocking_recv(), failed("device unplugged"));
+        assert_eq!(events.blocking_recv(), failed("No such device"));
+        device.plug_in();
+        assert_eq!(events.blocking_recv(), Some(OutputEvent::Recovered));
+
+        drop(player);
+        assert_eq!(events.blocking_recv(), None);
+    }
+
+    /// Plays nothing, remembers the buffer time it was opened with each time
+    #[derive(Clone, Default)]
+    struct Recording {
+        buffer_time: Option<Duration>,
+        opened: Arc<Mutex<Vec<Option<Duration>>>>,
+    }
+
+    impl Device for Recording {
+        type Stream = ();
+
+        fn play<S: FixedSource + Send + 'static>(
+            &mut self,
+            _source: S,
+            _on_error: OnError,
+        ) -> Result<Self::Stream> {
+            self.opened.lock().unwrap().push(self.buffer_time);
+            Ok(())
+        }
+
+        fn set_buffer_time(&mut self, buffer_time: Option<Duration>) {
+            self.buffer_time = buffer_time;
+        }
+
+        fn latency(&self) -> Duration {
+            self.buffer_time.unwrap_or_default()
+        }
+    }
+
+    #[tokio::test]
+    async fn a_new_buffer_time_reopens_the_output() {
+        let device = Recording::default();
+        let mut player = Player::with_device(1.0, true, device.clone());
+        let mut status = player.subscribe();
+        let long = Duration::from_millis(300);
 
-    let mut amplify = &mut source.inner;
-    amplify.set_factor(Factor::Normalized(params.volume()));
-    let pausable = amplify.inner_mut();
-    pausable.set_paused(params.paused());
+        player.set_buffer_time(Some(long));
+        wait_for(&mut status, |s| s.latency == long).await;
+        // unchanged, the output is left alone
+        player.set_buffer_time(Some(long));
+        player.set_buffer_time(None);
+        wait_for(&mut status, |s| s.latency == Duration::ZERO).await;
+        assert_eq!(*device.opened.lock().unwrap(), [None, Some(long), None]);
+    }
+
+    /// Prefers 48 kHz like many USB outputs, remembers the rate it is fed
+    #[derive(Clone, Default)]
+    struct Prefers48k {
+        fed: Arc<Mutex<Vec<SampleRate>>>,
+    }
+
+    impl Device for Prefers48k {
+        type Stream = ();
+
+        fn play<S: FixedSource + Send + 'static>(
+            &mut self,
+            source: S,
+            _on_error: OnError,
+        ) -> Result<Self::Stream> {
+            self.fed.lock().unwrap().push(source.sample_rate());
+            Ok(())
+        }
+
+        fn preferred_sample_rate(&self) -> Option<SampleRate> {
+            Some(nz!(48000))
+        }
+    }
+
+    #[test]
+    fn the_pipeline_runs_at_the_rate_the_output_prefers() {
+        let device = Prefers48k::default();
+        let player = Player::with_device(1.0, true, device.clone());
+        assert_eq!(player.pipeline(), PlayerPipeline::Hz48000);
+        assert_eq!(player.status().sample_rate, nz!(48000));
+        // the output is opened on its own thread, it gets the samples at the
+        // rate of the chain
+        let fed = || device.fed.lock().unwrap().clone();
+        for _ in 0..500 {
+            if !fed().is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(fed(), [nz!(48000)]);
+
+        let player = Player::with_device(1.0, true, FakeDevice::default());
+        assert_eq!(player.pipeline(), PlayerPipeline::Hz44100);
+    }
+
+    /// Pulls samples ten times faster than a sound card would
+    pub(crate) struct FastForward;
+
+    impl Device for FastForward {
+        type Stream = AbortHandle;
+
+        fn play<S: FixedSource + Send + 'static>(
+            &mut self,
+            mut source: S,
+            _on_error: OnError,
+        ) -> Result<Self::Stream> {
+            let playing = AbortHandle::new();
+            let stop = Arc::clone(&playing.0);
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    // 10ms of audio
+                    for _ in 0..441 * 2 {
+                        source.next();
+                    }
+                    thread::sleep(Duration::from_millis(1));
+                }
+            });
+            Ok(playing)
+        }
+    }
+
+    pub(crate) fn write_wav(path: &Utf8Path, duration: Duration) {
+        let frames = (duration.as_secs_f64() * 44100.0) as u32;
+        let data_len = frames * 4;
+        let mut wav = Vec::new();
+        wav.extend(b"RIFF");
+        wav.extend((36 + data_len).to_le_bytes());
+        wav.extend(b"WAVEfmt ");
+        wav.extend(16u32.to_le_bytes());
+        wav.extend(1u16.to_le_bytes()); // pcm
+        wav.extend(2u16.to_le_bytes());
+        wav.extend(44100u32.to_le_bytes());
+        wav.extend((44100u32 * 4).to_le_bytes());
+        wav.extend(4u16.to_le_bytes());
+        wav.extend(16u16.to_le_bytes());
+        wav.extend(b"data");
+        wav.extend(data_len.to_le_bytes());
+        wav.resize(wav.len() + data_len as usize, 0);
+        std::fs::write(path, wav).unwrap();
+    }
+
+    async fn wait_for(
+        status: &mut watch::Receiver<PlayerStatus>,
+        f: impl FnMut(&PlayerStatus) -> bool,
+    ) -> PlayerStatus {
+        tokio::time::timeout(Duration::from_secs(5), status.wait_for(f))
+            .await
+            .expect("the player should get there")
+            .unwrap()
+            .clone()
+    }
+
+    #[tokio::test]
+    async fn status_follows_playback() {
+        let dir = camino::Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mpdhaj-player-status-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (first, second) = (dir.join("first.wav"), dir.join("second.wav"));
+        write_wav(&first, Duration::from_secs(2));
+        write_wav(&second, Duration::from_millis(500));
+
+        let mut player = Player::with_device(1.0, true, FastForward);
+        let mut status = player.subscribe();
+        let stopped = player.status();
+        assert_eq!((stopped.source, stopped.paused), (None, true));
+        assert_eq!(
+            (stopped.sample_rate, stopped.channels),
+            (nz!(44100), nz!(2))
+        );
+
+        player.unpause();
+        let first = player.add(&first).await.unwrap();
+        wait_for(&mut status, |s| s.source == Some(first) && !s.paused).await;
+
+        player.pause();
+        let paused = wait_for(&mut status, |s| s.paused).await;
+        assert_eq!(paused.source, Some(first));
+
+        player.unpause();
+        let second = player.prefetch(&second).unwrap().unwrap();
+        assert_eq!(player.prefetch(&dir.join("third.wav")).unwrap(), None);
+        let next = wait_for(&mut status, |s| s.source != Some(first)).await;
+        assert_eq!(next.source, Some(second));
+        wait_for(&mut status, |s| s.source.is_none()).await;
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,167 @@
+use std::any::Any;
+use std::time::Duration;
+
+use color_eyre::{Result, eyre::Context};
+use rodio::{FixedSource, SampleRate, fixed_source::FixedSourceExt, nz, speakers};
+
+/// How long to wait before opening a device again after it failed
+const RETRY_AFTER: Duration = Duration::from_secs(2);
+
+const OPEN_FAILED: &str = "Could not open the output";
+
+/// Called with a description of the failure when a playing device breaks
+pub type OnError = Box<dyn FnMut(String) + Send>;
+
+/// Where the player sends its audio. A trait so tests can swap in a device
+/// that fails on demand.
+pub trait Device: Send + 'static {
+    /// Plays until dropped
+    type Stream;
+
+    fn play<S: FixedSource + Send + 'static>(
+        &mut self,
+        source: S,
+        on_error: OnError,
+    ) -> Result<Self::Stream>;
+
+    fn retry_after(&self) -> Duration {
+        RETRY_AFTER
+    }
+
+    /// The rate the device plays without resampling, if it has one. The
+    /// player picks its [`PlayerPipeline`](super::pipeline::PlayerPipeline) by it.
+    fn preferred_sample_rate(&self) -> Option<SampleRate> {
+        None
+    }
+
+    /// Audio to keep in the device's buffer once it is next opened, None
+    /// leaves it to the device. Larger buffers stop wireless outputs from
+    /// crackling.
+    fn set_buffer_time(&mut self, _buffer_time: Option<Duration>) {}
+
+    /// How long a sample takes from the source to being heard, as of the
+    /// last time the device was opened
+    fn latency(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+/// The system's default output
+#[derive(Default)]
+pub struct DefaultSpeakers {
+    buffer_time: Option<Duration>,
+    latency: Duration,
+}
+
+/// The buffer size to ask the device for, in frames at `sample_rate`
+pub(crate) fn buffer_frames(buffer_time: Duration, sample_rate: SampleRate) -> u32 {
+    let frames = buffer_time.as_secs_f64() * f64::from(sample_rate.get());
+    (frames.round() as u32).max(1)
+}
+
+impl Device for DefaultSpeakers {
+    type Stream = Box<dyn Any>;
+
+    fn play<S: FixedSource + Send + 'static>(
+        &mut self,
+        source: S,
+        mut on_error: OnError,
+    ) -> Result<Self::Stream> {
+        let builder = speakers::SpeakersBuilder::new()
+            .default_device()
+            .wrap_err("Could not get the default output")?
+            .default_config()
+            .wrap_err("Could not get the output's default config")?
+            .prefer_channel_counts([nz!(2)])
+            .prefer_sample_rates([source.sample_rate()]);
+        let builder = match self.buffer_time {
+            Some(buffer_time) => {
+                let rate = builder.get_config().sample_rate;
+                builder.prefer_buffer_sizes(buffer_frames(buffer_time, rate)..)
+            }
+            None => builder,
+        }
+        .with_error_callback(move |err| on_error(err.to_string()));
+
+        let sink = builder.get_config();
+        // the device picks its default size when none is asked for, that
+        // latency is unknown and usually small
+        self.latency = match self.buffer_time {
+            Some(buffer_time) => Duration::from_secs_f64(
+                f64::from(buffer_frames(buffer_time, sink.sample_rate))
+                    / f64::from(sink.sample_rate.get()),
+            ),
+            None => Duration::ZERO,
+        };
+        let needs_resample = sink.sample_rate != source.sample_rate();
+        let needs_rechannel = sink.channel_count != source.channels();
+
+        // TODO move all this into builder::play and friends
+        let stream: Box<dyn Any> = match (needs_resample, needs_rechannel) {
+            (true, true) => Box::new(
+                builder
+                    .play(
+                        source
+                            .with_channel_count(sink.channel_count)
+                            .with_sample_rate(sink.sample_rate),
+                    )
+                    .wrap_err(OPEN_FAILED)?,
+            ),
+            (true, false) => Box::new(
+                builder
+                    .play(source.with_sample_rate(sink.sample_rate))
+                    .wrap_err(OPEN_FAILED)?,
+            ),
+            (false, true) => Box::new(
+                builder
+                    .play(source.with_channel_count(sink.channel_count))
+                    .wrap_err(OPEN_FAILED)?,
+            ),
+            (false, false) => Box::new(builder.play(source).wrap_err(OPEN_FAILED)?),
+        };
+        Ok(stream)
+    }
+
+    fn preferred_sample_rate(&self) -> Option<SampleRate> {
+        let builder = speakers::SpeakersBuilder::new().default_device().ok()?;
+        Some(builder.default_config().ok()?.get_config().sample_rate)
+    }
+
+    fn set_buffer_time(&mut self, buffer_time: Option<Duration>) {
+        self.buffer_time = buffer_time;
+    }
+
+    fn latency(&self) -> Duration {
+        self.latency
+    }
+}
+
+/// Plays nothing and never fails, for tests that need a [`Player`](super::Player)
+#[cfg(test)]
+pub(crate) struct Silent;
+
+#[cfg(test)]
+impl Device for Silent {
+    type Stream = ();
+
+    fn play<S: FixedSource + Send + 'static>(
+        &mut self,
+        _source: S,
+        _on_error: OnError,
+    ) -> Result<Self::Stream> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffer_time_becomes_whole_frames() {
+        let ms = Duration::from_millis;
+        assert_eq!(buffer_frames(ms(500), nz!(44100)), 22050);
+        assert_eq!(buffer_frames(ms(20), nz!(48000)), 960);
+        assert_eq!(buffer_frames(Duration::from_micros(1), nz!(44100)), 1);
+    }
+}
@@ -0,0 +1,170 @@
+//! The chain from the queue to the output is built on const generic sample
+//! rates, those can not be picked at runtime. It is instantiated for a few
+//! rates here, the player picks the one the output plays natively when it
+//! starts. That saves the output resampling everything once more.
+
+use std::sync::{Arc, mpsc};
+use std::time::Duration;
+
+use color_eyre::{Result, eyre::eyre};
+use rodio::const_source::limiter::LimitSettings;
+use rodio::fixed_source::FixedSourceExt;
+use rodio::fixed_source::amplify::Factor;
+use rodio::fixed_source::queue::uniform::{SourceId, UniformQueue, UniformQueueHandle};
+use rodio::{ConstSource, SampleRate, nz};
+use tokio::sync::{mpsc::UnboundedSender, watch};
+
+use super::device::Device;
+use super::{
+    AUDIO_THREAD_RESPONSE_LATENCY, Holder, MpdTrack, Opened, OutputEvent, PlayerParams,
+    PlayerStatus, Reclaimable, hold_output,
+};
+
+/// The rate the player decodes and mixes at
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PlayerPipeline {
+    #[default]
+    Hz44100,
+    Hz48000,
+    Hz96000,
+}
+
+impl PlayerPipeline {
+    /// The pipeline for the rate the output prefers, 44.1 kHz if there is
+    /// none for that rate
+    pub fn for_output(preferred: Option<SampleRate>) -> Self {
+        match preferred.map(SampleRate::get) {
+            Some(48000) => Self::Hz48000,
+            Some(96000) => Self::Hz96000,
+            _ => Self::Hz44100,
+        }
+    }
+
+    pub fn sample_rate(self) -> SampleRate {
+        match self {
+            Self::Hz44100 => nz!(44100),
+            Self::Hz48000 => nz!(48000),
+            Self::Hz96000 => nz!(96000),
+        }
+    }
+
+    /// Builds the chain and plays it until the player drops. The queue is
+    /// sent back as soon as songs can be added to it.
+    pub(super) fn run(self, output: Output<impl Device>, queue: mpsc::Sender<Box<dyn TrackQueue>>) {
+        match self {
+            Self::Hz44100 => run::<44100>(output, queue),
+            Self::Hz48000 => run::<48000>(output, queue),
+            Self::Hz96000 => run::<96000>(output, queue),
+        }
+    }
+}
+
+/// The queue at the start of the chain, whatever its rate
+pub(super) trait TrackQueue: Send {
+    /// Decodes `song` at the rate of the chain. `finished` is told once it
+    /// has been played.
+    fn add(&self, song: Opened, finished: mpsc::Sender<SourceId>) -> Result<SourceId>;
+
+    fn buffered_duration(&self) -> Duration;
+}
+
+impl<const R: u32> TrackQueue for UniformQueueHandle<MpdTrack<R>> {
+    fn add(&self, song: Opened, finished: mpsc::Sender<SourceId>) -> Result<SourceId> {
+        let id = Arc::clone(&song.id);
+        let source_id = self
+            .add_with_notify(song.into_track::<R>(), finished)
+            .map_err(|e| eyre!("Could not queue song: {e:?}"))?;
+        id.set(source_id)
+            .expect("only set once the source is queued");
+        Ok(source_id)
+    }
+
+    fn buffered_duration(&self) -> Duration {
+        UniformQueueHandle::buffered_duration(self)
+    }
+}
+
+/// Everything the audio-output-stream-holder thread needs
+pub(super) struct Output<D> {
+    pub(super) device: D,
+    pub(super) params: Arc<PlayerParams>,
+    pub(super) status: Arc<watch::Sender<PlayerStatus>>,
+    /// The songs the queue finished
+    pub(super) finished: mpsc::Receiver<SourceId>,
+    pub(super) events: UnboundedSender<OutputEvent>,
+    pub(super) holder: mpsc::Receiver<Holder>,
+    pub(super) runtime_errors: mpsc::Sender<Holder>,
+}
+
+fn run<const R: u32>(output: Output<impl Device>, queue_tx: mpsc::Sender<Box<dyn TrackQueue>>) {
+    let Output {
+        mut device,
+        params,
+        status,
+        finished,
+        events,
+        holder,
+        runtime_errors,
+    } = output;
+    let rate = SampleRate::new(R).expect("pipelines have a sample rate");
+    let (queue, handle) = UniformQueue::<MpdTrack<R>>::new(nz!(2), rate);
+    let access_status = Arc::clone(&status);
+    let finished_events = events.clone();
+    let queue = queue
+        .pausable(params.paused())
+        .amplify(Factor::Normalized(params.volume()))
+        .try_into_const_source::<R, 2>()
+        .expect("the queue is created with exactly these parameters")
+        .balance(params.balance())
+        .periodic_access(AUDIO_THREAD_RESPONSE_LATENCY, move |balance| {
+            balance.set_balance(params.balance());
+            let amplify = balance.inner_mut().inner_mut();
+            amplify.set_factor(Factor::Normalized(params.volume()));
+            let paused = params.paused();
+            amplify.inner_mut().set_paused(paused);
+            access_status.send_if_modified(|status| {
+                let mut changed = std::mem::replace(&mut status.paused, paused) != paused;
+                // the next song already took over if it followed without a gap
+                for source in finished.try_iter() {
+                    let mut elapsed = None;
+                    if status.source == Some(source) {
+                        elapsed = Some(status.elapsed);
+                        status.source = None;
+                        status.elapsed = Duration::ZERO;
+                        changed = true;
+                    }
+                    // nobody listening is fine
+                    let _ = finished_events.send(OutputEvent::Finished { source, elapsed });
+                }
+                changed
+            });
+        })
+        // gain stages can push samples beyond full scale
+        .limit(LimitSettings::default())
+        .into_fixed_source();
+    let _ = queue_tx.send(Box::new(handle));
+
+    hold_output(
+        &mut device,
+        Reclaimable(Arc::new(std::sync::Mutex::new(queue))),
+        &holder,
+        &runtime_errors,
+        &events,
+        &status,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rates_without_a_pipeline_use_the_default() {
+        let pipeline = |rate| PlayerPipeline::for_output(SampleRate::new(rate));
+        assert_eq!(pipeline(48000), PlayerPipeline::Hz48000);
+        assert_eq!(pipeline(96000), PlayerPipeline::Hz96000);
+        assert_eq!(pipeline(44100), PlayerPipeline::Hz44100);
+        assert_eq!(pipeline(22050), PlayerPipeline::Hz44100);
+        assert_eq!(PlayerPipeline::for_output(None), PlayerPipeline::Hz44100);
+    }
+}
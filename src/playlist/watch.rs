@@ -0,0 +1,89 @@
+//! Reloads stored playlists when other programs change the playlist dir.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::Mutex;
+
+use crate::mpd_protocol::SubSystem;
+use crate::system::System;
+use crate::watch::{self, Changes};
+
+const OPTIONS: watch::Options = watch::Options {
+    recursive: false,
+    debounce: Duration::from_millis(500),
+    poll_interval: Duration::from_secs(2),
+};
+
+/// Starts watching [`System::playlist_dir`], pass the changes on to [`apply`].
+/// Watching stops when the system drops.
+pub fn start(system: &mut System) -> Changes {
+    let (watcher, changes) = watch::watch(&system.playlist_dir, &OPTIONS);
+    system.playlist_watcher = Some(watcher);
+    changes
+}
+
+/// Reloads changed playlists and wakes up the clients idling on
+/// [`SubSystem::StoredPlaylist`] if any were. Returns once the watcher is
+/// dropped.
+pub async fn apply(system: Arc<Mutex<System>>, mut changes: Changes) {
+    while let Some(paths) = changes.recv().await {
+        let mut system = system.lock().await;
+        let mut changed = false;
+        for path in &paths {
+            changed |= system.reload_playlist(path);
+        }
+        if changed {
+            system.notify(SubSystem::StoredPlaylist);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use camino::Utf8PathBuf;
+    use rusqlite::Connection;
+
+    use super::*;
+    use crate::player::Player;
+    use crate::player::device::Silent;
+    use crate::playlist::PlaylistName;
+
+    #[tokio::test]
+    async fn new_playlists_wake_up_idle_clients() {
+        let music_dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mpdhaj-watch-{}", std::process::id()));
+        fs::create_dir_all(music_dir.join("playlists")).unwrap();
+
+        let system = System::with_parts(
+            Connection::open_in_memory().unwrap(),
+            |volume, paused| Player::with_device(volume, paused, Silent),
+            music_dir.clone(),
+            None,
+        )
+        .unwrap();
+        let system = Arc::new(Mutex::new(system));
+        let (changes, mut idle) = {
+            let mut system = system.lock().await;
+            (
+                start(&mut system),
+                system.idle(vec![SubSystem::StoredPlaylist]),
+            )
+        };
+        tokio::spawn(apply(Arc::clone(&system), changes));
+
+        fs::write(music_dir.join("playlists/new.m3u"), "song.flac\n").unwrap();
+        let event = tokio::time::timeout(OPTIONS.poll_interval * 5, idle.recv())
+            .await
+            .expect("the change should be noticed");
+        assert_eq!(event, Some(SubSystem::StoredPlaylist));
+
+        let system = system.lock().await;
+        let playlist = &system.playlists[&PlaylistName("new.m3u".to_owned())];
+        assert_eq!(playlist.entries[0].path, "song.flac");
+        fs::remove_dir_all(&music_dir).unwrap();
+    }
+}
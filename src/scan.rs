@@ -1,30 +1,58 @@
-use std::{ops::Deref, time::Duration};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
 
-use camino::{Utf8Path, Utf8PathBuf};
+use camino::{Utf8Component, Utf8Path, Utf8PathBuf};
 use color_eyre::Result;
+use color_eyre::eyre::Context;
+use futures::StreamExt;
 use jiff::Timestamp;
-use rusqlite::{Connection, Transaction};
+use rodio::dynamic_source_ext::ExtendDynamicSource;
+use rodio::fixed_source::FixedSourceExt;
+use rodio::{ConstSource, nz};
+use rusqlite::{Connection, OptionalExtension, Transaction};
 use tokio::task::spawn_blocking;
-use tracing::{info, info_span, trace_span};
+use tracing::{info, info_span, trace_span, warn};
+use unicode_normalization::UnicodeNormalization;
 
+use itertools::Itertools;
+
+use crate::mpd_protocol::{AudioParams, SubSystem, Tag};
 use crate::system::System;
+use crate::system::date::Date;
+use crate::system::multi_value;
 
+pub mod decoders;
 mod lofty;
 mod moosicbox_audiotags;
+pub mod watch;
 
 // TODO: this should probably just be the same struct as system::Song
 // TODO: all fields should be optional instead of using the "unknown" string here, that should go in the protocol impl when they're None
 #[derive(Debug)]
 pub struct Metadata {
     pub title: String,
+    /// All artists joined, see [`multi_value`]
     pub artist: String,
+    pub artists: Vec<String>,
+    pub genres: Vec<String>,
     pub album: String,
     pub file: Utf8PathBuf,
     pub playtime: Duration,
-    // TODO: add other tags, genre/release date/etc.
+    /// ReplayGain style track gain in dB, relative to [`REPLAY_GAIN_REFERENCE`]
+    pub track_gain: Option<f32>,
+    /// Linear sample peak
+    pub track_peak: Option<f32>,
+    pub format: Option<AudioParams>,
+    /// As written in the tag, see [`Date`](crate::system::date::Date)
+    pub date: Option<String>,
+    pub original_date: Option<String>,
+    // TODO: add other tags, genre/etc.
 }
 
 pub const UNKNOWN: &str = "unknown";
+/// Target loudness used by ReplayGain 2.0 in LUFS
+pub const REPLAY_GAIN_REFERENCE: f32 = -18.0;
 trait FormatScanner: Send + Sync {
     fn scan(&self, path: Utf8PathBuf) -> Result<Option<Metadata>>;
 }
@@ -34,6 +62,24 @@ trait FormatScanner: Send + Sync {
 const SCANNERS: &[&dyn FormatScanner] =
     &[&lofty::Scanner::new(), &moosicbox_audiotags::Scanner::new()];
 
+/// The samples of a whole file at 44.1 kHz stereo, for drawing its waveform
+pub(crate) fn decode(path: &Utf8Path) -> Result<impl ConstSource<44100, 2>> {
+    let file = std::fs::File::open(path).wrap_err("Could not open file")?;
+    Ok(rodio::Decoder::try_from(file)
+        .wrap_err("Can not decode music file")?
+        .into_fixed_source(nz!(44100), nz!(2))
+        .try_into_const_source::<44100, 2>()
+        .expect("into_fixed_source converts to exactly these parameters"))
+}
+
+/// The tags of a file for `readcomments`, see [`lofty::comments`]
+pub async fn read_comments(path: &Utf8Path) -> Result<Vec<(String, String)>> {
+    let path = path.to_path_buf();
+    spawn_blocking(move || lofty::comments(&path))
+        .await
+        .expect("Reading tags should never panic")
+}
+
 #[tracing::instrument(level = "trace")]
 pub async fn scan_path(path: &Utf8Path) -> Option<Metadata> {
     let path = path.to_path_buf();
@@ -47,123 +93,1140 @@ pub async fn scan_path(path: &Utf8Path) -> Option<Metadata> {
     .expect("Scanning should never panic")
 }
 
+/// Only one scan writes to the database at a time, others wait their turn
+static SCAN_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// The same name can be encoded in multiple ways (macOS for example
+/// decomposes accents). Paths are stored and looked up in NFC so each file
+/// gets one row.
+pub(crate) fn normalize_path(path: &Utf8Path) -> Utf8PathBuf {
+    path.as_str().nfc().collect::<String>().into()
+}
+
+/// For the column in `songs`, see [`multi_value`]
+fn joined(values: &[String]) -> Option<String> {
+    (!values.is_empty()).then(|| values.join(multi_value::JOINED_BY))
+}
+
+/// For [`Metadata::artist`]
+fn joined_or_unknown(values: &[String]) -> String {
+    joined(values).unwrap_or_else(|| UNKNOWN.to_owned())
+}
+
+fn store_multi_values(db: &Connection, song: u32, metadata: &Metadata) -> Result<()> {
+    multi_value::store(db, song, Tag::Artist, &metadata.artists)?;
+    multi_value::store(db, song, Tag::Genre, &metadata.genres)
+}
+
+/// Stored next to the date so queries can sort and filter on it
+fn date_key(date: Option<&str>) -> Option<u32> {
+    date.and_then(Date::parse).map(Date::key)
+}
+
+/// Files per sub-transaction, a crash only loses the files since the last
+/// commit
+const COMMIT_EVERY: usize = 2000;
+/// Generation bumps per statement, sqlite allows 32766 parameters
+const BUMP_CHUNK: usize = 500;
+
+/// Groups the writes of a scan into transactions of [`COMMIT_EVERY`] files
+/// and bumps the generation of unchanged songs in bulk. Rolls back what has
+/// not been committed when dropped.
+struct Batch<'a> {
+    db: &'a Connection,
+    generation: u32,
+    files: usize,
+    unchanged: Vec<u32>,
+}
+
+impl<'a> Batch<'a> {
+    fn begin(db: &'a Connection, generation: u32) -> Result<Self> {
+        db.execute_batch("BEGIN EXCLUSIVE")?;
+        Ok(Self {
+            db,
+            generation,
+            files: 0,
+            unchanged: Vec::with_capacity(BUMP_CHUNK),
+        })
+    }
+
+    /// Unchanged songs are by far the most common on a rescan
+    fn unchanged(&mut self, song: u32) -> Result<()> {
+        self.unchanged.push(song);
+        if self.unchanged.len() >= BUMP_CHUNK {
+            self.bump_generations()?;
+        }
+        Ok(())
+    }
+
+    fn bump_generations(&mut self) -> Result<()> {
+        if self.unchanged.is_empty() {
+            return Ok(());
+        }
+        let placeholders = std::iter::repeat_n("?", self.unchanged.len()).join(", ");
+        // full chunks share one cached statement
+        self.db
+            .prepare_cached(&format!(
+                "UPDATE songs SET generation = {} WHERE rowid IN ({placeholders})",
+                self.generation
+            ))?
+            .execute(rusqlite::params_from_iter(self.unchanged.drain(..)))?;
+        Ok(())
+    }
+
+    fn file_done(&mut self) -> Result<()> {
+        self.files += 1;
+        if self.files.is_multiple_of(COMMIT_EVERY) {
+            self.bump_generations()?;
+            info_span!("commit scan batch")
+                .in_scope(|| self.db.execute_batch("COMMIT; BEGIN EXCLUSIVE"))?;
+        }
+        Ok(())
+    }
+
+    fn commit(mut self) -> Result<()> {
+        self.bump_generations()?;
+        info_span!("commit scan batch").in_scope(|| self.db.execute_batch("COMMIT"))?;
+        Ok(())
+    }
+}
+
+impl Drop for Batch<'_> {
+    fn drop(&mut self) {
+        if !self.db.is_autocommit() {
+            let _ = self.db.execute_batch("ROLLBACK");
+        }
+    }
+}
+
 enum ScanResult {
-    Cached,
+    /// Still up to date, its generation needs a bump
+    Cached(u32),
     Updated,
     Added,
     NotASong,
 }
 async fn scan_song(
-    db: &impl Deref<Target = Connection>,
+    db: &Connection,
     relpath: &Utf8Path,
     abspath: &Utf8Path,
-    // TODO: just use number for this, no need to parse/make human readable
     mtime: Timestamp,
     generation: u32,
 ) -> Result<ScanResult> {
-    let Ok((id, cached_mtime)) = trace_span!("path lookup").in_scope(|| {
-        db.query_one(
-            "SELECT rowid, mtime FROM songs WHERE path = ?1",
-            [relpath.as_str()],
-            |row| Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?)),
-        )
-    }) else {
+    let relpath = &normalize_path(relpath);
+    let Some((id, cached_mtime)) = trace_span!("path lookup").in_scope(|| {
+        db.prepare_cached("SELECT rowid, mtime FROM songs WHERE path = ?1")?
+            .query_one([relpath.as_str()], |row| {
+                Ok((row.get::<_, u32>(0)?, row.get::<_, i64>(1)?))
+            })
+            .optional()
+    })?
+    else {
         let Some(song_metadata) = scan_path(abspath).await else {
             return Ok(ScanResult::NotASong);
         };
         trace_span!("insertion").in_scope(|| {
-            db.execute(
-                "INSERT INTO songs (path, mtime, title, artist, album, generation)
-                           VALUES (?1,   ?2,    ?3,    ?4,     ?5,    ?6)",
-                (
-                    relpath.as_str(),
-                    mtime.to_string(),
-                    song_metadata.title,
-                    song_metadata.artist,
-                    song_metadata.album,
-                    generation,
-                ),
-            )
+            let id = db
+                .prepare_cached(
+                    "INSERT INTO songs (path, mtime, title, artist, album, generation, track_gain,
+                                       track_peak, sample_rate, bit_depth, channels, duration,
+                                       date_key, date, original_date_key, original_date, genre,
+                                       decode_error)
+                               VALUES (?1,   ?2,    ?3,    ?4,     ?5,    ?6,         ?7,
+                                       ?8,         ?9,          ?10,       ?11,      ?12,
+                                       ?13,      ?14,  ?15,               ?16,           ?17,
+                                       ?18)
+                        ON CONFLICT (path) DO UPDATE
+                        SET mtime = excluded.mtime, title = excluded.title,
+                            artist = excluded.artist, album = excluded.album,
+                            generation = excluded.generation, track_gain = excluded.track_gain,
+                            track_peak = excluded.track_peak, sample_rate = excluded.sample_rate,
+                            bit_depth = excluded.bit_depth, channels = excluded.channels,
+                            duration = excluded.duration, date = excluded.date,
+                            date_key = excluded.date_key, original_date = excluded.original_date,
+                            original_date_key = excluded.original_date_key,
+                            genre = excluded.genre, decode_error = excluded.decode_error
+                        RETURNING rowid",
+                )?
+                .query_one(
+                    rusqlite::params![
+                        relpath.as_str(),
+                        mtime.as_second(),
+                        song_metadata.title,
+                        song_metadata.artist,
+                        song_metadata.album,
+                        generation,
+                        song_metadata.track_gain,
+                        song_metadata.track_peak,
+                        song_metadata.format.map(|f| f.samplerate.get()),
+                        song_metadata.format.and_then(|f| f.bits),
+                        song_metadata.format.map(|f| f.channels.get()),
+                        song_metadata.playtime.as_secs_f64(),
+                        date_key(song_metadata.date.as_deref()),
+                        song_metadata.date,
+                        date_key(song_metadata.original_date.as_deref()),
+                        song_metadata.original_date,
+                        joined(&song_metadata.genres),
+                        decoders::unsupported(abspath),
+                    ],
+                    |row| row.get(0),
+                )?;
+            store_multi_values(db, id, &song_metadata)
         })?;
         return Ok(ScanResult::Added);
     };
 
-    if let Ok(cached_mtime) = cached_mtime.parse()
-        && mtime != cached_mtime
+    // stored in whole seconds, like mpd
+    if mtime.as_second() != cached_mtime
         && let Some(song_metadata) = scan_path(abspath).await
     {
         trace_span!("update").in_scope(|| {
-            db.execute(
+            db.prepare_cached(
                 "UPDATE songs
-                    SET mtime = ?2, title = ?3, artist = ?4, album = ?5, generation = ?6
-                    WHERE rowid = ?1
-                        ",
-                (
-                    id,
-                    relpath.as_str(),
-                    mtime.to_string(),
-                    song_metadata.title,
-                    song_metadata.artist,
-                    song_metadata.album,
-                    generation,
-                ),
-            )
+                    SET mtime = ?2, title = ?3, artist = ?4, album = ?5, generation = ?6,
+                        track_gain = ?7, track_peak = ?8, sample_rate = ?9, bit_depth = ?10,
+                        channels = ?11, duration = ?12, date_key = ?13, date = ?14,
+                        original_date_key = ?15, original_date = ?16, genre = ?17,
+                        decode_error = ?18
+                    WHERE rowid = ?1",
+            )?
+            .execute(rusqlite::params![
+                id,
+                mtime.as_second(),
+                song_metadata.title,
+                song_metadata.artist,
+                song_metadata.album,
+                generation,
+                song_metadata.track_gain,
+                song_metadata.track_peak,
+                song_metadata.format.map(|f| f.samplerate.get()),
+                song_metadata.format.and_then(|f| f.bits),
+                song_metadata.format.map(|f| f.channels.get()),
+                song_metadata.playtime.as_secs_f64(),
+                date_key(song_metadata.date.as_deref()),
+                song_metadata.date,
+                date_key(song_metadata.original_date.as_deref()),
+                song_metadata.original_date,
+                joined(&song_metadata.genres),
+                decoders::unsupported(abspath),
+            ])?;
+            store_multi_values(db, id, &song_metadata)
         })?;
         Ok(ScanResult::Updated)
     } else {
-        trace_span!("bump generation").in_scope(|| {
-            db.execute(
-                "UPDATE songs SET generation = ?2 WHERE rowid = ?1",
-                (id, generation),
-            )
-        })?;
-        Ok(ScanResult::Cached)
+        Ok(ScanResult::Cached(id))
     }
 }
 
 impl System {
+    /// Tells clients when it starts and ends, and whether the database
+    /// changed
     pub async fn rescan(&mut self) -> Result<()> {
-        let generation = self
+        self.notify(SubSystem::Update);
+        let scanned = self.scan(None).await;
+        self.notify(SubSystem::Update);
+        if scanned? {
+            self.notify(SubSystem::Database);
+        }
+        Ok(())
+    }
+
+    /// Scans `relpath` or, for None, the whole music dir without telling
+    /// anyone, except about queue entries of songs that are gone. Returns
+    /// whether the database changed.
+    pub(crate) async fn scan(&mut self, relpath: Option<&Utf8Path>) -> Result<bool> {
+        let queue_version = |db: &Connection| {
+            db.query_one("SELECT queue_version FROM state", [], |row| {
+                row.get::<_, u32>(0)
+            })
+        };
+        let before = queue_version(&self.db)?;
+        let started = std::time::Instant::now();
+        let scanned = match relpath {
+            None => scan_dir(&mut self.db, &self.music_dir).await,
+            Some(relpath) => rescan_path(&mut self.db, &self.music_dir, relpath).await,
+        };
+        self.metrics.scan(started.elapsed());
+        let changed = scanned?;
+        self.db.execute(
+            "UPDATE state SET last_db_update = ?1",
+            [Timestamp::now().as_second()],
+        )?;
+        // once for all the songs that left the queue
+        if queue_version(&self.db)? != before {
+            self.notify(SubSystem::Playlist);
+        }
+        Ok(changed)
+    }
+
+    /// Scans `uri` if it is in the music dir but not in the database, clients
+    /// ask about files copied in since the last update. What is found is
+    /// stored, the next update does not need to scan it again.
+    pub async fn scan_if_unknown(&mut self, uri: &Utf8Path) -> Result<()> {
+        let known = self.is_directory(uri)?
+            || self
+                .db
+                .query_one(
+                    "SELECT 1 FROM songs WHERE path = ?1",
+                    [normalize_path(uri).as_str()],
+                    |_| Ok(()),
+                )
+                .optional()?
+                .is_some();
+        if known || !in_music_dir(&self.music_dir, uri) {
+            return Ok(());
+        }
+        if rescan_path(&mut self.db, &self.music_dir, uri).await? {
+            self.notify(SubSystem::Database);
+        }
+        Ok(())
+    }
+}
+
+/// Whether `relpath` exists and stays inside the music dir, `..` and
+/// symlinks could lead out of it
+pub(crate) fn in_music_dir(music_dir: &Utf8Path, relpath: &Utf8Path) -> bool {
+    if !relpath
+        .components()
+        .all(|component| matches!(component, Utf8Component::Normal(_)))
+    {
+        return false;
+    }
+    match (
+        music_dir.canonicalize_utf8(),
+        music_dir.join(relpath).canonicalize_utf8(),
+    ) {
+        (Ok(music_dir), Ok(path)) => path.starts_with(music_dir),
+        _ => false,
+    }
+}
+
+/// Songs [`backfill_playtime`] reads at the same time
+const BACKFILL_CONCURRENCY: usize = 4;
+/// Songs [`backfill_playtime`] writes per transaction
+const BACKFILL_BATCH: usize = 64;
+
+/// Fills in the playtime of songs scanned before it was stored, without a
+/// forced rescan of everything. Meant to run in the background: the files are
+/// read without holding any lock and every batch waits for running scans.
+/// After a restart it continues with the songs that still have no playtime.
+pub async fn backfill_playtime(system: Arc<tokio::sync::Mutex<System>>) -> Result<()> {
+    const MISSING: &str = "(duration IS NULL OR duration = 0)";
+    let (music_dir, total) = {
+        let system = system.lock().await;
+        let total = system.db.query_one(
+            &format!("SELECT COUNT(*) FROM songs WHERE {MISSING}"),
+            [],
+            |row| row.get::<_, usize>(0),
+        )?;
+        (system.music_dir.clone(), total)
+    };
+    if total == 0 {
+        return Ok(());
+    }
+    info!("Reading the playtime of {total} songs scanned before it was stored");
+    // songs without a readable playtime are tried once per run
+    let mut after = 0;
+    let (mut done, mut filled) = (0, 0);
+    loop {
+        let songs: Vec<(u32, String)> = system
+            .lock()
+            .await
             .db
-            .query_one("SELECT generation FROM state", [], |row| {
-                Ok(row.get::<_, u32>(0)? + 1)
-            })?;
-        let music_dir = &self.music_dir;
-        let (mut cached, mut added, mut updated) = (0, 0, 0);
-        let t = Transaction::new(&mut self.db, rusqlite::TransactionBehavior::Exclusive)?;
-        for e in walkdir::WalkDir::new(music_dir) {
-            if let Ok(e) = e
-                && let Ok(metadata) = e.metadata()
-                && !metadata.is_dir()
-                && let Ok(Ok(mtime)) = metadata.modified().map(Timestamp::try_from)
-                && let Some(abspath) = Utf8Path::from_path(e.path())
-                && let Ok(relpath) = abspath.strip_prefix(music_dir)
-            {
-                match scan_song(&t, relpath, abspath, mtime, generation).await? {
-                    ScanResult::Cached => cached += 1,
-                    ScanResult::Added => added += 1,
-                    ScanResult::Updated => updated += 1,
-                    ScanResult::NotASong => {}
+            .prepare(&format!(
+                "SELECT rowid, path FROM songs WHERE {MISSING} AND rowid > ?1
+                    ORDER BY rowid LIMIT ?2"
+            ))?
+            .query_map([after, BACKFILL_BATCH as u32], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .try_collect()?;
+        let Some((last, _)) = songs.last() else {
+            break;
+        };
+        after = *last;
+        done += songs.len();
+
+        let playtimes: Vec<(u32, Duration)> = futures::stream::iter(songs)
+            .map(|(song, path)| {
+                let path = music_dir.join(path);
+                async move {
+                    scan_path(&path)
+                        .await
+                        .map(|metadata| (song, metadata.playtime))
                 }
+            })
+            .buffer_unordered(BACKFILL_CONCURRENCY)
+            .filter_map(|read| async move { read.filter(|(_, playtime)| !playtime.is_zero()) })
+            .collect()
+            .await;
+        {
+            // same order as scans, which hold the System while they take the
+            // scan lock
+            let system = system.lock().await;
+            let _scanning = SCAN_LOCK.lock().await;
+            let t = system.db.unchecked_transaction()?;
+            // a scan in the meantime could have changed the song
+            let mut update = t.prepare(&format!(
+                "UPDATE songs SET duration = ?2 WHERE rowid = ?1 AND {MISSING}"
+            ))?;
+            for (song, playtime) in &playtimes {
+                filled += update.execute((song, playtime.as_secs_f64()))?;
             }
+            drop(update);
+            t.commit()?;
         }
-        info_span!("commit scan transaction").in_scope(|| t.commit())?;
-        let old_size = self.db.query_one("SELECT COUNT(*) FROM songs", [], |row| {
-            row.get::<_, usize>(0)
-        })?;
-        self.db
-            .execute("UPDATE state SET generation = ?1", [generation])?;
-        self.db
-            .execute("DELETE FROM songs WHERE generation < ?1", [generation])?;
-        let new_size = self.db.query_one("SELECT COUNT(*) FROM songs", [], |row| {
-            row.get::<_, usize>(0)
-        })?;
-        // TODO: clean up queue removing any entries without a valid songid and fix up "current" if
-        // removed songs were before it
-        info!(
-            "Scan complete: {new_size} songs - {cached} cached - {added} added - {updated} updated - {} removed",
-            old_size - new_size
+        info!("Backfilled playtime: {done} of {total} songs read, {filled} filled in");
+    }
+    Ok(())
+}
+
+/// Returns whether the database changed
+async fn scan_dir(db: &mut Connection, music_dir: &Utf8Path) -> Result<bool> {
+    let _scanning = SCAN_LOCK.lock().await;
+    let generation = db.query_one("SELECT generation FROM state", [], |row| {
+        Ok(row.get::<_, u32>(0)? + 1)
+    })?;
+    let (mut cached, mut added, mut updated, mut failed) = (0, 0, 0, 0);
+    let mut dirs = HashMap::new();
+    let mut batch = Batch::begin(db, generation)?;
+    for e in walkdir::WalkDir::new(music_dir) {
+        let e = match e {
+            Ok(e) => e,
+            Err(err) => {
+                warn!("Skipping unreadable entry in music dir: {err}");
+                failed += 1;
+                continue;
+            }
+        };
+        record_dir(&mut dirs, &e, music_dir);
+        if let Ok(metadata) = e.metadata()
+            && !metadata.is_dir()
+            && let Ok(Ok(mtime)) = metadata.modified().map(Timestamp::try_from)
+            && let Some(abspath) = Utf8Path::from_path(e.path())
+            && let Ok(relpath) = abspath.strip_prefix(music_dir)
+        {
+            // A failing statement only undoes itself, the rest of the
+            // transaction is still fine to commit.
+            match scan_song(batch.db, relpath, abspath, mtime, generation).await {
+                Ok(ScanResult::Cached(song)) => {
+                    batch.unchanged(song)?;
+                    cached += 1;
+                }
+                Ok(ScanResult::Added) => added += 1,
+                Ok(ScanResult::Updated) => updated += 1,
+                Ok(ScanResult::NotASong) => {}
+                Err(err) => {
+                    warn!("Skipping {relpath}: {err:#}");
+                    failed += 1;
+                }
+            }
+            batch.file_done()?;
+        }
+    }
+    batch.commit()?;
+    let old_size = db.query_one("SELECT COUNT(*) FROM songs", [], |row| {
+        row.get::<_, usize>(0)
+    })?;
+    db.execute("UPDATE state SET generation = ?1", [generation])?;
+    let t = Transaction::new(db, rusqlite::TransactionBehavior::Exclusive)?;
+    let gone: Vec<u32> = t
+        .prepare("SELECT rowid FROM songs WHERE generation < ?1")?
+        .query_map([generation], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    let mut queue_changed = false;
+    for song in gone {
+        queue_changed |= remove_song(&t, song)?;
+    }
+    if queue_changed {
+        bump_queue_version(&t)?;
+    }
+    sync_directories(&t, music_dir, &dirs)?;
+    t.commit()?;
+    let new_size = db.query_one("SELECT COUNT(*) FROM songs", [], |row| {
+        row.get::<_, usize>(0)
+    })?;
+    // TODO: fix up "current" if removed songs were before it
+    info!(
+        "Scan complete: {new_size} songs - {cached} cached - {added} added - {updated} updated - {} removed - {failed} failed",
+        old_size - new_size
+    );
+    Ok(added + updated > 0 || old_size != new_size)
+}
+
+/// Rescans a single file or directory, relative to the music dir. Rows for
+/// files that no longer exist are removed. Returns whether the database
+/// changed.
+pub(crate) async fn rescan_path(
+    db: &mut Connection,
+    music_dir: &Utf8Path,
+    relpath: &Utf8Path,
+) -> Result<bool> {
+    let _scanning = SCAN_LOCK.lock().await;
+    // Not a new generation, that is only bumped by full scans which remove
+    // everything they did not see.
+    let generation = db.query_one("SELECT generation FROM state", [], |row| {
+        row.get::<_, u32>(0)
+    })?;
+    let relpath = normalize_path(relpath);
+    let root = music_dir.join(&relpath);
+    let mut changed = false;
+    let mut seen = HashSet::new();
+    let mut dirs = HashMap::new();
+
+    let mut batch = Batch::begin(db, generation)?;
+    // WalkDir reports a missing root as an error, here it means it was removed
+    let entries = root
+        .exists()
+        .then(|| walkdir::WalkDir::new(&root))
+        .into_iter()
+        .flatten();
+    for e in entries {
+        let e = match e {
+            Ok(e) => e,
+            Err(err) => {
+                warn!("Skipping unreadable entry in music dir: {err}");
+                continue;
+            }
+        };
+        record_dir(&mut dirs, &e, music_dir);
+        if let Ok(metadata) = e.metadata()
+            && !metadata.is_dir()
+            && let Ok(Ok(mtime)) = metadata.modified().map(Timestamp::try_from)
+            && let Some(abspath) = Utf8Path::from_path(e.path())
+            && let Ok(relpath) = abspath.strip_prefix(music_dir)
+        {
+            match scan_song(batch.db, relpath, abspath, mtime, generation).await {
+                Ok(ScanResult::Added | ScanResult::Updated) => changed = true,
+                Ok(ScanResult::Cached(song)) => batch.unchanged(song)?,
+                Ok(ScanResult::NotASong) => {}
+                Err(err) => warn!("Skipping {relpath}: {err:#}"),
+            }
+            seen.insert(normalize_path(relpath));
+            batch.file_done()?;
+        }
+    }
+    batch.commit()?;
+
+    let t = Transaction::new(db, rusqlite::TransactionBehavior::Exclusive)?;
+
+    // `relpath` itself or anything below it
+    let prefix = dir_prefix(&relpath);
+    let gone: Vec<u32> = t
+        .prepare(
+            "SELECT rowid, path FROM songs
+                WHERE path = ?1 OR substr(path, 1, length(?2)) = ?2",
+        )?
+        .query_map((relpath.as_str(), &prefix), |row| {
+            Ok((row.get(0)?, row.get::<_, String>(1)?))
+        })?
+        .filter_ok(|(_, path)| !seen.contains(Utf8Path::new(path)))
+        .map_ok(|(song, _)| song)
+        .collect::<Result<_, _>>()?;
+    let mut queue_changed = false;
+    for song in &gone {
+        queue_changed |= remove_song(&t, *song)?;
+    }
+    if queue_changed {
+        bump_queue_version(&t)?;
+    }
+    sync_directories(&t, music_dir, &dirs)?;
+    t.commit()?;
+
+    if changed || !gone.is_empty() {
+        info!("Rescanned {relpath}: {} removed", gone.len());
+    }
+    Ok(changed || !gone.is_empty())
+}
+
+/// What paths inside `dir` start with
+pub(crate) fn dir_prefix(dir: &Utf8Path) -> String {
+    if dir.as_str().is_empty() {
+        String::new()
+    } else {
+        format!("{dir}/")
+    }
+}
+
+/// Notes the modification time of directories the scan walks through
+fn record_dir(
+    dirs: &mut HashMap<Utf8PathBuf, Timestamp>,
+    entry: &walkdir::DirEntry,
+    music_dir: &Utf8Path,
+) {
+    if let Ok(metadata) = entry.metadata()
+        && metadata.is_dir()
+        && let Ok(Ok(mtime)) = metadata.modified().map(Timestamp::try_from)
+        && let Some(abspath) = Utf8Path::from_path(entry.path())
+        && let Ok(relpath) = abspath.strip_prefix(music_dir)
+        && !relpath.as_str().is_empty()
+    {
+        dirs.insert(normalize_path(relpath), mtime);
+    }
+}
+
+/// Makes the directories table list exactly the directories that (in)directly
+/// contain songs. `walked` are the directories the scan saw with their
+/// modification time. Others keep the time they had, new ones not walked are
+/// looked up.
+fn sync_directories(
+    db: &Connection,
+    music_dir: &Utf8Path,
+    walked: &HashMap<Utf8PathBuf, Timestamp>,
+) -> Result<()> {
+    let mut needed = HashSet::new();
+    let mut songs = db.prepare("SELECT path FROM songs")?;
+    for path in songs.query_map([], |row| row.get::<_, String>(0))? {
+        let path = Utf8PathBuf::from(path?);
+        needed.extend(
+            path.ancestors()
+                .skip(1)
+                .filter(|dir| !dir.as_str().is_empty())
+                .map(Utf8Path::to_owned),
         );
-        Ok(())
+    }
+    let existing: HashSet<Utf8PathBuf> = db
+        .prepare("SELECT path FROM directories")?
+        .query_map([], |row| Ok(Utf8PathBuf::from(row.get::<_, String>(0)?)))?
+        .collect::<Result<_, _>>()?;
+
+    for gone in existing.difference(&needed) {
+        db.execute("DELETE FROM directories WHERE path = ?1", [gone.as_str()])?;
+    }
+    let mut upsert = db.prepare(
+        "INSERT INTO directories (path, parent, mtime) VALUES (?1, ?2, ?3)
+            ON CONFLICT (path) DO UPDATE SET mtime = excluded.mtime",
+    )?;
+    for dir in &needed {
+        let mtime = match walked.get(dir) {
+            Some(mtime) => *mtime,
+            None if existing.contains(dir) => continue,
+            None => match std::fs::metadata(music_dir.join(dir))
+                .and_then(|metadata| metadata.modified())
+                .map(Timestamp::try_from)
+            {
+                Ok(Ok(mtime)) => mtime,
+                _ => Timestamp::UNIX_EPOCH,
+            },
+        };
+        let parent = dir.parent().unwrap_or(Utf8Path::new(""));
+        upsert.execute((dir.as_str(), parent.as_str(), mtime.to_string()))?;
+    }
+    Ok(())
+}
+
+/// Removes the song and its queue entries, the entries after those move up.
+/// Returns whether the queue changed, the caller bumps its version once for
+/// all the songs it removes.
+pub(crate) fn remove_song(db: &Connection, song: u32) -> Result<bool> {
+    let positions: Vec<u32> = db
+        .prepare("SELECT position FROM queue WHERE song = ?1 ORDER BY position DESC")?
+        .query_map([song], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    db.execute("DELETE FROM queue_entries WHERE song = ?1", [song])?;
+    for &position in &positions {
+        db.execute(
+            "UPDATE state SET current = NULL WHERE current = ?1",
+            [position],
+        )?;
+        crate::system::shift_current(db, position + 1, -1)?;
+    }
+    db.execute("DELETE FROM songs WHERE rowid = ?1", [song])?;
+    Ok(!positions.is_empty())
+}
+
+/// A new queue version for clients to fetch, once per transaction
+fn bump_queue_version(db: &Connection) -> rusqlite::Result<()> {
+    db.execute("UPDATE state SET queue_version = queue_version + 1", [])?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use lofty::config::WriteOptions;
+    use lofty::tag::{Accessor, ItemKey, ItemValue, Tag, TagExt, TagItem, TagType};
+
+    use super::*;
+
+    /// A tenth of a second of silence, with a title so lofty picks it up
+    pub(crate) fn write_song(path: &Utf8Path) {
+        let data_len = 4410u32 * 2;
+        let mut wav = Vec::new();
+        wav.extend(b"RIFF");
+        wav.extend((36 + data_len).to_le_bytes());
+        wav.extend(b"WAVEfmt ");
+        wav.extend(16u32.to_le_bytes());
+        wav.extend(1u16.to_le_bytes()); // PCM
+        wav.extend(1u16.to_le_bytes()); // mono
+        wav.extend(44100u32.to_le_bytes());
+        wav.extend((44100u32 * 2).to_le_bytes());
+        wav.extend(2u16.to_le_bytes());
+        wav.extend(16u16.to_le_bytes());
+        wav.extend(b"data");
+        wav.extend(data_len.to_le_bytes());
+        wav.resize(wav.len() + data_len as usize, 0);
+        std::fs::write(path, wav).unwrap();
+
+        let mut tag = Tag::new(TagType::Id3v2);
+        tag.set_title("Café".into());
+        tag.save_to_path(path, WriteOptions::default()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rescanning_a_decomposed_path_keeps_one_row() {
+        let music_dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mpdhaj-scan-{}", std::process::id()));
+        std::fs::create_dir_all(&music_dir).unwrap();
+        write_song(&music_dir.join("Cafe\u{301}.wav"));
+
+        let mut db = Connection::open_in_memory().unwrap();
+        crate::system::migrations::run(&mut db).unwrap();
+        scan_dir(&mut db, &music_dir).await.unwrap();
+        scan_dir(&mut db, &music_dir).await.unwrap();
+        std::fs::remove_dir_all(&music_dir).unwrap();
+
+        let paths: Vec<String> = db
+            .prepare("SELECT path FROM songs")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(paths, ["Caf\u{e9}.wav"]);
+    }
+
+    #[tokio::test]
+    async fn dates_are_stored_as_written_with_their_key() {
+        let music_dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mpdhaj-dates-{}", std::process::id()));
+        std::fs::create_dir_all(&music_dir).unwrap();
+        for (name, date) in [("a.wav", "1999-03-14"), ("b.wav", "2001")] {
+            let path = music_dir.join(name);
+            write_song(&path);
+            let mut tag = Tag::new(TagType::Id3v2);
+            tag.set_title("Café".into());
+            tag.insert_text(ItemKey::RecordingDate, date.to_owned());
+            tag.save_to_path(&path, WriteOptions::default()).unwrap();
+        }
+
+        let mut db = Connection::open_in_memory().unwrap();
+        crate::system::migrations::run(&mut db).unwrap();
+        scan_dir(&mut db, &music_dir).await.unwrap();
+        std::fs::remove_dir_all(&music_dir).unwrap();
+
+        let dates: Vec<(String, Option<u32>)> = db
+            .prepare("SELECT date, date_key FROM songs ORDER BY path")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            dates,
+            [
+                ("1999-03-14".to_owned(), Some(19990314)),
+                ("2001".to_owned(), Some(20010000)),
+            ]
+        );
+    }
+
+    /// Two artists in separate frames and three genres in one
+    pub(crate) fn write_jazz_song(path: &Utf8Path) {
+        write_song(path);
+        let mut tag = Tag::new(TagType::Id3v2);
+        tag.set_title("Liza".into());
+        for artist in ["Herbie Hancock", "Chick Corea"] {
+            tag.push(TagItem::new(
+                ItemKey::TrackArtist,
+                ItemValue::Text(artist.to_owned()),
+            ));
+        }
+        tag.set_genre("Jazz; Fusion; Funk".into());
+        tag.save_to_path(path, WriteOptions::default()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn every_artist_and_genre_is_stored() {
+        let music_dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mpdhaj-multi-{}", std::process::id()));
+        std::fs::create_dir_all(&music_dir).unwrap();
+        write_jazz_song(&music_dir.join("liza.wav"));
+
+        let mut db = Connection::open_in_memory().unwrap();
+        crate::system::migrations::run(&mut db).unwrap();
+        scan_dir(&mut db, &music_dir).await.unwrap();
+        std::fs::remove_dir_all(&music_dir).unwrap();
+
+        let (artist, genre): (String, String) = db
+            .query_one("SELECT artist, genre FROM songs", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(artist, "Herbie Hancock; Chick Corea");
+        assert_eq!(genre, "Jazz; Fusion; Funk");
+        let mut song = crate::system::Song::default();
+        multi_value::load(&db, 1, &mut song).unwrap();
+        assert_eq!(song.artists, ["Herbie Hancock", "Chick Corea"]);
+        assert_eq!(song.genres, ["Jazz", "Fusion", "Funk"]);
+    }
+
+    #[tokio::test]
+    async fn removed_files_leave_the_database_and_queue() {
+        let music_dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mpdhaj-rescan-{}", std::process::id()));
+        std::fs::create_dir_all(music_dir.join("album")).unwrap();
+        write_song(&music_dir.join("album/1.wav"));
+        write_song(&music_dir.join("album/2.wav"));
+
+        let mut db = Connection::open_in_memory().unwrap();
+        crate::system::migrations::run(&mut db).unwrap();
+        scan_dir(&mut db, &music_dir).await.unwrap();
+        db.execute_batch(
+            "INSERT INTO queue_entries (song, key)
+                SELECT rowid, ROW_NUMBER() OVER (ORDER BY path) FROM songs",
+        )
+        .unwrap();
+
+        std::fs::remove_file(music_dir.join("album/1.wav")).unwrap();
+        let changed = rescan_path(&mut db, &music_dir, Utf8Path::new("album"))
+            .await
+            .unwrap();
+        assert!(changed);
+        let unchanged = rescan_path(&mut db, &music_dir, Utf8Path::new("album"))
+            .await
+            .unwrap();
+        assert!(!unchanged);
+        std::fs::remove_dir_all(&music_dir).unwrap();
+
+        let queue: Vec<(String, u32)> = db
+            .prepare("SELECT s.path, q.position FROM queue q JOIN songs s ON s.rowid = q.song")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(queue, [("album/2.wav".to_owned(), 0)]);
+        let songs = db
+            .query_one("SELECT COUNT(*) FROM songs", [], |row| row.get::<_, u32>(0))
+            .unwrap();
+        assert_eq!(songs, 1);
+    }
+
+    fn directories(db: &Connection) -> Vec<(String, String)> {
+        db.prepare("SELECT path, parent FROM directories ORDER BY path")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn directories_mirror_the_library() {
+        let music_dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mpdhaj-directories-{}", std::process::id()));
+        for dir in ["a/b", "c", "empty", "no_music"] {
+            std::fs::create_dir_all(music_dir.join(dir)).unwrap();
+        }
+        write_song(&music_dir.join("a/b/1.wav"));
+        write_song(&music_dir.join("a/2.wav"));
+        write_song(&music_dir.join("c/3.wav"));
+        std::fs::write(music_dir.join("no_music/notes.txt"), "hi").unwrap();
+
+        let mut db = Connection::open_in_memory().unwrap();
+        crate::system::migrations::run(&mut db).unwrap();
+        scan_dir(&mut db, &music_dir).await.unwrap();
+        let pair = |path: &str, parent: &str| (path.to_owned(), parent.to_owned());
+        assert_eq!(
+            directories(&db),
+            [pair("a", ""), pair("a/b", "a"), pair("c", "")]
+        );
+
+        std::fs::remove_dir_all(music_dir.join("c")).unwrap();
+        std::fs::create_dir_all(music_dir.join("a/b/new")).unwrap();
+        write_song(&music_dir.join("a/b/new/4.wav"));
+        rescan_path(&mut db, &music_dir, Utf8Path::new("c"))
+            .await
+            .unwrap();
+        rescan_path(&mut db, &music_dir, Utf8Path::new("a/b/new"))
+            .await
+            .unwrap();
+        std::fs::remove_dir_all(&music_dir).unwrap();
+
+        assert_eq!(
+            directories(&db),
+            [pair("a", ""), pair("a/b", "a"), pair("a/b/new", "a/b")]
+        );
+    }
+
+    fn vorbis_comments(comment: &str) -> Vec<u8> {
+        let vendor = b"mpdhaj";
+        let mut block = Vec::new();
+        block.extend((vendor.len() as u32).to_le_bytes());
+        block.extend(vendor);
+        block.extend(1u32.to_le_bytes());
+        block.extend((comment.len() as u32).to_le_bytes());
+        block.extend(comment.as_bytes());
+        block
+    }
+
+    /// Only metadata blocks, lofty reads the format from STREAMINFO
+    fn write_flac(path: &Utf8Path, sample_rate: u64, bits: u64) {
+        let (channels, samples) = (2u64, 4410u64);
+        let mut flac = b"fLaC".to_vec();
+        flac.push(0); // STREAMINFO
+        flac.extend(&34u32.to_be_bytes()[1..]);
+        flac.extend(4096u16.to_be_bytes()); // min block size
+        flac.extend(4096u16.to_be_bytes()); // max block size
+        flac.extend([0; 6]); // unknown frame sizes
+        let packed = sample_rate << 44 | (channels - 1) << 41 | (bits - 1) << 36 | samples;
+        flac.extend(packed.to_be_bytes());
+        flac.extend([0; 16]); // md5
+        let comments = vorbis_comments("TITLE=Café");
+        flac.push(0x80 | 4); // last block, VORBIS_COMMENT
+        flac.extend(&(comments.len() as u32).to_be_bytes()[1..]);
+        flac.extend(comments);
+        std::fs::write(path, flac).unwrap();
+    }
+
+    /// Silent 128kbps stereo frames
+    fn write_mp3(path: &Utf8Path) {
+        let mut frame = vec![0xFF, 0xFB, 0x90, 0x00];
+        frame.resize(144 * 128_000 / 44100, 0);
+        std::fs::write(path, frame.repeat(10)).unwrap();
+
+        let mut tag = Tag::new(TagType::Id3v2);
+        tag.set_title("Café".into());
+        tag.save_to_path(path, WriteOptions::default()).unwrap();
+    }
+
+    fn ogg_page(header_type: u8, granule: u64, sequence: u32, packet: &[u8]) -> Vec<u8> {
+        assert!(packet.len() < 255, "one segment per page keeps this simple");
+        let mut page = b"OggS".to_vec();
+        page.push(0); // version
+        page.push(header_type);
+        page.extend(granule.to_le_bytes());
+        page.extend(1u32.to_le_bytes()); // stream serial
+        page.extend(sequence.to_le_bytes());
+        page.extend([0; 4]); // checksum, filled in below
+        page.push(1);
+        page.push(packet.len() as u8);
+        page.extend(packet);
+        let crc = page.iter().fold(0u32, |crc, byte| {
+            (0..8).fold(crc ^ (u32::from(*byte) << 24), |crc, _| {
+                if crc & 0x8000_0000 != 0 {
+                    (crc << 1) ^ 0x04c1_1db7
+                } else {
+                    crc << 1
+                }
+            })
+        });
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+        page
+    }
+
+    /// Headers and one silent packet
+    pub(crate) fn write_opus(path: &Utf8Path) {
+        let pre_skip = 312u16;
+        let mut head = b"OpusHead".to_vec();
+        head.push(1); // version
+        head.push(2); // channels
+        head.extend(pre_skip.to_le_bytes());
+        head.extend(48000u32.to_le_bytes());
+        head.extend(0i16.to_le_bytes()); // gain
+        head.push(0); // mapping family
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend(vorbis_comments("TITLE=Café"));
+
+        let mut ogg = ogg_page(0x02, 0, 0, &head);
+        ogg.extend(ogg_page(0, 0, 1, &tags));
+        ogg.extend(ogg_page(
+            0x04,
+            48000 + u64::from(pre_skip),
+            2,
+            &[0xF8, 0xFF, 0xFE],
+        ));
+        std::fs::write(path, ogg).unwrap();
+    }
+
+    #[tokio::test]
+    async fn audio_formats_are_stored() {
+        let music_dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mpdhaj-formats-{}", std::process::id()));
+        std::fs::create_dir_all(&music_dir).unwrap();
+        write_flac(&music_dir.join("16.flac"), 44100, 16);
+        write_flac(&music_dir.join("24.flac"), 96000, 24);
+        write_song(&music_dir.join("16.wav"));
+        write_mp3(&music_dir.join("a.mp3"));
+        write_opus(&music_dir.join("a.opus"));
+
+        let mut db = Connection::open_in_memory().unwrap();
+        crate::system::migrations::run(&mut db).unwrap();
+        scan_dir(&mut db, &music_dir).await.unwrap();
+        std::fs::remove_dir_all(&music_dir).unwrap();
+
+        type Format = (String, Option<u32>, Option<u8>, Option<u16>);
+        let formats: Vec<Format> = db
+            .prepare("SELECT path, sample_rate, bit_depth, channels FROM songs ORDER BY path")
+            .unwrap()
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let format =
+            |path: &str, rate, bits, channels| (path.to_owned(), Some(rate), bits, Some(channels));
+        assert_eq!(
+            formats,
+            [
+                format("16.flac", 44100, Some(16), 2),
+                format("16.wav", 44100, Some(16), 1),
+                format("24.flac", 96000, Some(24), 2),
+                // lossy
+                format("a.mp3", 44100, None, 2),
+                format("a.opus", 48000, None, 2),
+            ]
+        );
+    }
+
+    fn generations(db: &Connection) -> Vec<u32> {
+        db.prepare("SELECT generation FROM songs ORDER BY rowid")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn unchanged_songs_are_bumped_in_chunks() {
+        let mut db = Connection::open_in_memory().unwrap();
+        crate::system::migrations::run(&mut db).unwrap();
+        for i in 0..BUMP_CHUNK + 2 {
+            db.execute(
+                "INSERT INTO songs (path, mtime, generation) VALUES (?1, '0', 0)",
+                [i.to_string()],
+            )
+            .unwrap();
+        }
+
+        let mut batch = Batch::begin(&db, 1).unwrap();
+        // the last song was not seen
+        for song in 1..=BUMP_CHUNK as u32 + 1 {
+            batch.unchanged(song).unwrap();
+        }
+        batch.commit().unwrap();
+
+        let mut expected = vec![1; BUMP_CHUNK + 1];
+        expected.push(0);
+        assert_eq!(generations(&db), expected);
+    }
+
+    #[test]
+    fn uncommitted_files_are_rolled_back() {
+        let mut db = Connection::open_in_memory().unwrap();
+        crate::system::migrations::run(&mut db).unwrap();
+        db.execute(
+            "INSERT INTO songs (path, mtime, generation) VALUES ('a', '0', 0)",
+            [],
+        )
+        .unwrap();
+
+        let mut batch = Batch::begin(&db, 1).unwrap();
+        batch.unchanged(1).unwrap();
+        batch.bump_generations().unwrap();
+        drop(batch);
+
+        assert_eq!(generations(&db), [0]);
+        assert!(db.is_autocommit());
+    }
+
+    #[test]
+    fn only_paths_inside_the_music_dir_are_scanned() {
+        let root = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mpdhaj-contained-{}", std::process::id()));
+        let music_dir = root.join("music");
+        std::fs::create_dir_all(music_dir.join("album")).unwrap();
+        write_song(&music_dir.join("album/song.wav"));
+        write_song(&root.join("outside.wav"));
+        std::os::unix::fs::symlink(root.join("outside.wav"), music_dir.join("link.wav")).unwrap();
+
+        assert!(in_music_dir(&music_dir, Utf8Path::new("album/song.wav")));
+        assert!(in_music_dir(&music_dir, Utf8Path::new("album")));
+        assert!(!in_music_dir(
+            &music_dir,
+            Utf8Path::new("album/missing.wav")
+        ));
+        assert!(!in_music_dir(&music_dir, Utf8Path::new("../outside.wav")));
+        assert!(!in_music_dir(
+            &music_dir,
+            Utf8Path::new("album/../../outside.wav")
+        ));
+        assert!(!in_music_dir(&music_dir, &root.join("outside.wav")));
+        assert!(!in_music_dir(&music_dir, Utf8Path::new("link.wav")));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn missing_playtimes_are_backfilled() {
+        use crate::player::Player;
+        use crate::player::device::Silent;
+
+        let music_dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mpdhaj-backfill-{}", std::process::id()));
+        std::fs::create_dir_all(&music_dir).unwrap();
+        let songs = BACKFILL_BATCH + 2;
+        for i in 0..songs {
+            write_song(&music_dir.join(format!("{i}.wav")));
+        }
+        let mut system = System::with_parts(
+            Connection::open_in_memory().unwrap(),
+            |volume, paused| Player::with_device(volume, paused, Silent),
+            music_dir.clone(),
+            None,
+        )
+        .unwrap();
+        system.rescan().await.unwrap();
+        // like a database from before playtimes were stored, with a song
+        // that can not be read
+        system
+            .db
+            .execute_batch(
+                "UPDATE songs SET duration = CASE WHEN rowid % 2 = 0 THEN 0 END;
+                INSERT INTO songs (path, mtime) VALUES ('gone.wav', 0);",
+            )
+            .unwrap();
+        let system = Arc::new(tokio::sync::Mutex::new(system));
+
+        backfill_playtime(Arc::clone(&system)).await.unwrap();
+        std::fs::remove_dir_all(&music_dir).unwrap();
+
+        let system = system.lock().await;
+        let durations: Vec<(String, Option<f64>)> = system
+            .db
+            .prepare("SELECT path, duration FROM songs")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(durations.len(), songs + 1);
+        for (path, duration) in durations {
+            if path == "gone.wav" {
+                assert_eq!(duration, None);
+            } else {
+                let duration = duration.unwrap_or_else(|| panic!("{path} has no playtime"));
+                assert!((duration - 0.1).abs() < 0.01, "{path}: {duration}");
+            }
+        }
     }
 }
@@ -0,0 +1,141 @@
+//! What the compiled in decoders can play, for the `decoders` command and to
+//! flag files at scan time that will not play.
+//!
+//! rodio decodes through symphonia with its default format features, see
+//! `symphonia` in Cargo.lock. Keep [`DECODERS`] in step when they change.
+//! Files that fail anyway get the decoder's message once they are played,
+//! `x-mpdhaj undecodable` lists them all.
+
+use std::fmt;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::Result;
+use rusqlite::Connection;
+use serde::Serialize;
+
+pub struct Decoder {
+    pub plugin: &'static str,
+    pub suffixes: &'static [&'static str],
+    pub mime_types: &'static [&'static str],
+}
+
+pub const DECODERS: &[Decoder] = &[
+    Decoder {
+        plugin: "flac",
+        suffixes: &["flac"],
+        mime_types: &["audio/flac", "audio/x-flac"],
+    },
+    Decoder {
+        plugin: "mp3",
+        suffixes: &["mp3"],
+        mime_types: &["audio/mpeg"],
+    },
+    Decoder {
+        plugin: "aac",
+        suffixes: &["aac", "m4a", "mp4"],
+        mime_types: &["audio/aac", "audio/mp4"],
+    },
+    Decoder {
+        plugin: "vorbis",
+        suffixes: &["ogg", "oga"],
+        mime_types: &["audio/ogg", "audio/vorbis"],
+    },
+    Decoder {
+        plugin: "wav",
+        suffixes: &["wav"],
+        mime_types: &["audio/wav", "audio/x-wav"],
+    },
+];
+
+/// The reply to `decoders`, one block per plugin like mpd
+pub fn report() -> Vec<String> {
+    DECODERS
+        .iter()
+        .flat_map(|decoder| {
+            std::iter::once(format!("plugin: {}", decoder.plugin))
+                .chain(decoder.suffixes.iter().map(|s| format!("suffix: {s}")))
+                .chain(decoder.mime_types.iter().map(|m| format!("mime_type: {m}")))
+        })
+        .collect()
+}
+
+/// Why no decoder will take the file, judged by its extension alone
+pub fn unsupported(path: &Utf8Path) -> Option<String> {
+    let suffix = path.extension().unwrap_or_default().to_lowercase();
+    let supported = DECODERS
+        .iter()
+        .any(|decoder| decoder.suffixes.contains(&suffix.as_str()));
+    (!supported).then(|| format!("No decoder for .{suffix} files"))
+}
+
+/// rodio could not open the file as audio, the message is rodio's
+#[derive(Debug)]
+pub struct DecodeError(pub String);
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct Undecodable {
+    #[serde(rename = "file")]
+    pub path: Utf8PathBuf,
+    pub decode_error: String,
+}
+
+/// Every song flagged by a scan or a failed play, for `x-mpdhaj undecodable`
+pub fn undecodable(db: &Connection) -> Result<Vec<Undecodable>> {
+    let mut stmt = db.prepare(
+        "SELECT path, decode_error FROM songs WHERE decode_error IS NOT NULL ORDER BY path",
+    )?;
+    Ok(stmt
+        .query_map([], |row| {
+            Ok(Undecodable {
+                path: row.get::<_, String>(0)?.into(),
+                decode_error: row.get(1)?,
+            })
+        })?
+        .collect::<Result<_, _>>()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suffixes_decide_support() {
+        assert_eq!(unsupported(Utf8Path::new("a/b.FLAC")), None);
+        assert_eq!(unsupported(Utf8Path::new("b.m4a")), None);
+        assert_eq!(
+            unsupported(Utf8Path::new("b.wv")).as_deref(),
+            Some("No decoder for .wv files")
+        );
+        assert!(unsupported(Utf8Path::new("no_suffix")).is_some());
+    }
+
+    #[test]
+    fn report_lists_plugins_then_their_formats() {
+        let report = report();
+        assert_eq!(
+            report[..4],
+            [
+                "plugin: flac",
+                "suffix: flac",
+                "mime_type: audio/flac",
+                "mime_type: audio/x-flac"
+            ]
+        );
+        assert_eq!(
+            report
+                .iter()
+                .filter(|line| line.starts_with("plugin: "))
+                .count(),
+            DECODERS.len()
+        );
+    }
+}
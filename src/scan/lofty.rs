@@ -1,14 +1,36 @@
-use crate::scan::{FormatScanner, Metadata, UNKNOWN};
-use camino::Utf8PathBuf;
+use crate::mpd_protocol::AudioParams;
+use crate::scan::{FormatScanner, Metadata, UNKNOWN, joined_or_unknown};
+use crate::system::multi_value;
+use camino::{Utf8Path, Utf8PathBuf};
 use color_eyre::{Result, Section, eyre::Context};
 use lofty::{
     file::{AudioFile, TaggedFileExt},
     probe::read_from_path,
-    tag::Accessor,
+    tag::{Accessor, ItemKey},
 };
+use rodio::{ChannelCount, SampleRate};
 
 pub struct Scanner;
 
+/// Every text item in the tags of the file, keyed the way its tag format
+/// names them
+pub fn comments(path: &Utf8Path) -> Result<Vec<(String, String)>> {
+    let tagged_file = read_from_path(path)
+        .wrap_err("Could not open file for reading tags")
+        .with_note(|| format!("path is: {path}"))?;
+    let mut comments = Vec::new();
+    for tag in tagged_file.tags() {
+        for item in tag.items() {
+            if let Some(key) = item.key().map_key(tag.tag_type(), true)
+                && let Some(value) = item.value().text()
+            {
+                comments.push((key.to_owned(), value.to_owned()));
+            }
+        }
+    }
+    Ok(comments)
+}
+
 impl Scanner {
     pub const fn new() -> Self {
         Scanner
@@ -25,14 +47,40 @@ impl FormatScanner for Scanner {
             return Ok(None);
         };
 
-        let playtime = tagged_file.properties().duration();
+        let properties = tagged_file.properties();
+        let playtime = properties.duration();
+        let format = Option::zip(
+            properties.sample_rate().and_then(SampleRate::new),
+            properties
+                .channels()
+                .and_then(|channels| ChannelCount::new(channels.into())),
+        )
+        .map(|(samplerate, channels)| AudioParams {
+            samplerate,
+            // lofty only knows it for lossless formats
+            bits: properties.bit_depth(),
+            channels,
+        });
 
+        let artists = multi_value::split(tag.get_strings(&ItemKey::TrackArtist));
         Ok(Some(Metadata {
             title: tag.title().unwrap_or(UNKNOWN.into()).to_string(),
             file: path,
-            artist: tag.artist().unwrap_or(UNKNOWN.into()).to_string(),
+            artist: joined_or_unknown(&artists),
+            artists,
+            genres: multi_value::split(tag.get_strings(&ItemKey::Genre)),
             album: tag.album().unwrap_or(UNKNOWN.into()).to_string(),
             playtime,
+            track_gain: None,
+            track_peak: None,
+            format,
+            date: tag
+                .get_string(&ItemKey::RecordingDate)
+                .or_else(|| tag.get_string(&ItemKey::Year))
+                .map(str::to_owned),
+            original_date: tag
+                .get_string(&ItemKey::OriginalReleaseDate)
+                .map(str::to_owned),
         }))
     }
 }
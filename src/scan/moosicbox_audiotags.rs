@@ -1,10 +1,15 @@
 use std::time::Duration;
 
 use camino::Utf8PathBuf;
-use rodio::DynamicSource;
+use rodio::const_source::loudness::LoudnessHandle;
+use rodio::{
+    ConstSource, DynamicSource, dynamic_source_ext::ExtendDynamicSource,
+    fixed_source::FixedSourceExt, nz,
+};
 
 use crate::scan::Metadata;
-use crate::scan::{FormatScanner, UNKNOWN};
+use crate::scan::{FormatScanner, REPLAY_GAIN_REFERENCE, UNKNOWN, joined_or_unknown};
+use crate::system::multi_value;
 use color_eyre::{Result, Section, eyre::Context};
 use moosicbox_audiotags::{Error, Tag};
 
@@ -32,24 +37,91 @@ impl FormatScanner for Scanner {
             }
         };
 
-        let playtime = if let Some(duration) = tag.duration().map(Duration::from_secs_f64) {
-            duration
-        } else {
-            let file = std::fs::File::open(&path).wrap_err("Could not open file")?;
-            let source = rodio::Decoder::try_from(file).wrap_err("Can not decode music file")?;
-            source.total_duration().unwrap_or_default()
-        };
+        let (playtime, track_gain, track_peak) =
+            if let Some(duration) = tag.duration().map(Duration::from_secs_f64) {
+                (duration, None, None)
+            } else {
+                let file = std::fs::File::open(&path).wrap_err("Could not open file")?;
+                let decoder =
+                    rodio::Decoder::try_from(file).wrap_err("Can not decode music file")?;
+                // usually known from the frame count in the container
+                let known = decoder.total_duration();
+                let (decoded, loudness) = measure(decoder);
+                (
+                    known.unwrap_or(decoded),
+                    loudness
+                        .integrated()
+                        .map(|lufs| REPLAY_GAIN_REFERENCE - lufs),
+                    Some(loudness.peak()),
+                )
+            };
 
+        let artists = multi_value::split(tag.artists().unwrap_or_default());
         Ok(Some(Metadata {
             title: tag.title().unwrap_or(UNKNOWN).to_string(),
             file: path,
-            artist: tag.artist().unwrap_or(UNKNOWN).to_string(),
+            artist: joined_or_unknown(&artists),
+            artists,
+            genres: multi_value::split(tag.genre()),
             album: tag
                 .album()
                 .map(|album| album.title)
                 .unwrap_or(UNKNOWN)
                 .to_string(),
             playtime,
+            track_gain,
+            track_peak,
+            format: None,
+            date: tag.year().map(|year| year.to_string()),
+            original_date: None,
         }))
     }
 }
+
+/// How long `decoder` plays and how loud it is. That needs every sample, but
+/// not at any particular rate, so the usual formats are measured as decoded.
+fn measure(decoder: impl DynamicSource) -> (Duration, LoudnessHandle) {
+    match (decoder.sample_rate().get(), decoder.channels().get()) {
+        (44100, 2) => measured(AsDecoded::<44100, 2, _>(decoder)),
+        (48000, 2) => measured(AsDecoded::<48000, 2, _>(decoder)),
+        (96000, 2) => measured(AsDecoded::<96000, 2, _>(decoder)),
+        (44100, 1) => measured(AsDecoded::<44100, 1, _>(decoder)),
+        (48000, 1) => measured(AsDecoded::<48000, 1, _>(decoder)),
+        _ => measured(
+            decoder
+                .into_fixed_source(nz!(48000), nz!(2))
+                .try_into_const_source::<48000, 2>()
+                .expect("into_fixed_source converts to exactly these parameters"),
+        ),
+    }
+}
+
+fn measured<const SR: u32, const CH: u16>(
+    source: impl ConstSource<SR, CH>,
+) -> (Duration, LoudnessHandle) {
+    let (meter, loudness) = source.measure_loudness();
+    let frames = meter.count() / CH as usize;
+    (Duration::from_secs_f64(frames as f64 / SR as f64), loudness)
+}
+
+/// A decoder taken at its word that it sticks to the sample rate and channel
+/// count it started with
+struct AsDecoded<const SR: u32, const CH: u16, S>(S);
+
+impl<const SR: u32, const CH: u16, S: DynamicSource> ConstSource<SR, CH> for AsDecoded<SR, CH, S> {
+    fn total_duration(&self) -> Option<Duration> {
+        self.0.total_duration()
+    }
+}
+
+impl<const SR: u32, const CH: u16, S: DynamicSource> Iterator for AsDecoded<SR, CH, S> {
+    type Item = rodio::Sample;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
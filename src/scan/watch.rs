@@ -0,0 +1,125 @@
+//! Rescans the parts of the music dir other programs change, so new music
+//! shows up without an `update`.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use camino::Utf8PathBuf;
+use itertools::Itertools;
+use tokio::sync::Mutex;
+
+use crate::mpd_protocol::SubSystem;
+use crate::scan::rescan_path;
+use crate::system::System;
+use crate::watch::{self, Changes};
+
+const OPTIONS: watch::Options = watch::Options {
+    recursive: true,
+    // copying an album in takes a while, rescan it once it is done
+    debounce: Duration::from_secs(2),
+    // polling walks the whole library
+    poll_interval: Duration::from_secs(60),
+};
+
+/// Starts watching [`System::music_dir`], pass the changes on to [`apply`].
+/// Watching stops when the system drops.
+pub fn start(system: &mut System) -> Changes {
+    let (watcher, changes) = watch::watch(&system.music_dir, &OPTIONS);
+    system.music_watcher = Some(watcher);
+    changes
+}
+
+/// Rescans the changed paths and wakes up the clients idling on
+/// [`SubSystem::Database`] if that changed anything. A rename is a removal
+/// of the old path and an addition of the new one. Returns once the watcher
+/// is dropped.
+pub async fn apply(system: Arc<Mutex<System>>, mut changes: Changes) {
+    while let Some(paths) = changes.recv().await {
+        let mut system = system.lock().await;
+        let music_dir = system.music_dir.clone();
+        let mut changed = false;
+        for path in outermost(paths) {
+            let Ok(relpath) = path.strip_prefix(&music_dir) else {
+                continue;
+            };
+            // waits for a full scan that is in progress
+            match rescan_path(&mut system.db, &music_dir, relpath).await {
+                Ok(rescan_changed) => changed |= rescan_changed,
+                Err(e) => tracing::warn!("Could not rescan {relpath}: {e:#}"),
+            }
+        }
+        if changed {
+            system.notify(SubSystem::Database);
+        }
+    }
+}
+
+/// Drops paths inside other paths, rescanning a dir covers its contents
+fn outermost(paths: Vec<Utf8PathBuf>) -> Vec<Utf8PathBuf> {
+    let paths = paths.into_iter().sorted().dedup().collect_vec();
+    paths
+        .iter()
+        .filter(|path| {
+            !paths
+                .iter()
+                .any(|other| other != *path && path.starts_with(other))
+        })
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use camino::Utf8Path;
+    use rusqlite::Connection;
+
+    use super::*;
+    use crate::player::Player;
+    use crate::player::device::Silent;
+    use crate::scan::tests::write_song;
+
+    #[test]
+    fn nested_paths_are_rescanned_once() {
+        let paths = ["a/b/c.flac", "a/b", "d.flac", "a/b", "ab"].map(Utf8PathBuf::from);
+        assert_eq!(outermost(paths.to_vec()), ["a/b", "ab", "d.flac"]);
+    }
+
+    #[tokio::test]
+    async fn new_files_are_found_without_an_update() {
+        let music_dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mpdhaj-watch-music-{}", std::process::id()));
+        fs::create_dir_all(&music_dir).unwrap();
+
+        let system = System::with_parts(
+            Connection::open_in_memory().unwrap(),
+            |volume, paused| Player::with_device(volume, paused, Silent),
+            music_dir.clone(),
+            None,
+        )
+        .unwrap();
+        let system = Arc::new(Mutex::new(system));
+        let (changes, mut idle) = {
+            let mut system = system.lock().await;
+            system.rescan().await.unwrap();
+            (start(&mut system), system.idle(vec![SubSystem::Database]))
+        };
+        tokio::spawn(apply(Arc::clone(&system), changes));
+
+        fs::create_dir(music_dir.join("album")).unwrap();
+        write_song(&music_dir.join("album/new.wav"));
+        let event = tokio::time::timeout(OPTIONS.poll_interval * 2, idle.recv())
+            .await
+            .expect("the new file should be noticed");
+        assert_eq!(event, Some(SubSystem::Database));
+
+        let system = system.lock().await;
+        let song = system
+            .get_song_by_path(Utf8Path::new("album/new.wav"))
+            .unwrap();
+        assert_eq!(song.title, "Café");
+        fs::remove_dir_all(&music_dir).unwrap();
+    }
+}
@@ -0,0 +1,361 @@
+//! Submits plays to ListenBrainz. Playback queues them in the `scrobbles`
+//! table, this sends them on. Listens stay queued until ListenBrainz accepts
+//! them, with a growing wait between attempts while it can not be reached.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use color_eyre::Result;
+use itertools::Itertools;
+use jiff::Timestamp;
+use reqwest::StatusCode;
+use rusqlite::Connection;
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+use crate::system::System;
+
+pub const LISTENBRAINZ: &str = "https://api.listenbrainz.org";
+/// Listens per submission, ListenBrainz takes up to 1000
+const BATCH: u32 = 100;
+/// How often to look for new listens
+const POLL: Duration = Duration::from_secs(30);
+/// The wait after the first failure, doubles with every failure after that
+const FIRST_BACKOFF: i64 = 60;
+const MAX_BACKOFF: i64 = 6 * 60 * 60;
+
+pub struct ListenBrainz {
+    client: reqwest::Client,
+    /// Like [`LISTENBRAINZ`], tests point this at a local server
+    api: String,
+    token: String,
+}
+
+impl ListenBrainz {
+    pub fn new(api: impl Into<String>, token: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            api: api.into(),
+            token: token.into(),
+        }
+    }
+}
+
+/// Submits queued listens until mpdhaj exits
+pub async fn run(system: Arc<Mutex<System>>, listenbrainz: ListenBrainz) {
+    loop {
+        if let Err(e) = submit_due(&system, &listenbrainz, Timestamp::now()).await {
+            tracing::warn!("Could not scrobble: {e:#}");
+        }
+        tokio::time::sleep(POLL).await;
+    }
+}
+
+enum Outcome {
+    Accepted,
+    Rejected,
+    Failed,
+}
+
+#[derive(Debug)]
+struct Listen {
+    id: i64,
+    listened_at: i64,
+    artist: String,
+    title: String,
+    album: Option<String>,
+    duration: Option<f64>,
+    musicbrainz_track_id: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Submission<'a> {
+    listen_type: &'static str,
+    payload: Vec<Payload<'a>>,
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    listened_at: i64,
+    track_metadata: TrackMetadata<'a>,
+}
+
+#[derive(Serialize)]
+struct TrackMetadata<'a> {
+    artist_name: &'a str,
+    track_name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    release_name: Option<&'a str>,
+    additional_info: AdditionalInfo<'a>,
+}
+
+#[derive(Serialize)]
+struct AdditionalInfo<'a> {
+    submission_client: &'static str,
+    submission_client_version: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration_ms: Option<u64>,
+    /// Picard's track id tag holds the recording id
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recording_mbid: Option<&'a str>,
+}
+
+/// Submits the listens whose wait is over. Returns how many ListenBrainz
+/// accepted.
+async fn submit_due(
+    system: &Mutex<System>,
+    listenbrainz: &ListenBrainz,
+    now: Timestamp,
+) -> Result<usize> {
+    let listens = due(&system.lock().await.db, now)?;
+    if listens.is_empty() {
+        return Ok(0);
+    }
+
+    let submission = Submission {
+        listen_type: if listens.len() == 1 {
+            "single"
+        } else {
+            "import"
+        },
+        payload: listens.iter().map(Listen::payload).collect(),
+    };
+    let response = listenbrainz
+        .client
+        .post(format!("{}/1/submit-listens", listenbrainz.api))
+        .header("Authorization", format!("Token {}", listenbrainz.token))
+        .json(&submission)
+        .send()
+        .await;
+
+    let outcome = match response {
+        Ok(response) if response.status().is_success() => Outcome::Accepted,
+        // trying again will not help
+        Ok(response) if response.status() == StatusCode::BAD_REQUEST => {
+            let reason = response.text().await.unwrap_or_default();
+            tracing::warn!("ListenBrainz rejected {} listens: {reason}", listens.len());
+            Outcome::Rejected
+        }
+        Ok(response) => {
+            tracing::warn!("ListenBrainz answered {}", response.status());
+            Outcome::Failed
+        }
+        Err(e) => {
+            tracing::warn!("Could not reach ListenBrainz: {e}");
+            Outcome::Failed
+        }
+    };
+
+    let system = system.lock().await;
+    let ids = listens.iter().map(|listen| listen.id).join(",");
+    match outcome {
+        Outcome::Accepted | Outcome::Rejected => {
+            system
+                .db
+                .execute(&format!("DELETE FROM scrobbles WHERE id IN ({ids})"), [])?;
+        }
+        Outcome::Failed => {
+            system.db.execute(
+                &format!(
+                    "UPDATE scrobbles SET attempts = attempts + 1,
+                        retry_at = ?1 + min(?2 << attempts, ?3)
+                        WHERE id IN ({ids})"
+                ),
+                (now.as_second(), FIRST_BACKOFF, MAX_BACKOFF),
+            )?;
+        }
+    }
+    Ok(match outcome {
+        Outcome::Accepted => listens.len(),
+        Outcome::Rejected | Outcome::Failed => 0,
+    })
+}
+
+fn due(db: &Connection, now: Timestamp) -> Result<Vec<Listen>> {
+    let mut statement = db.prepare_cached(
+        "SELECT id, listened_at, artist, title, album, duration, musicbrainz_track_id
+            FROM scrobbles WHERE retry_at <= ?1 ORDER BY listened_at LIMIT ?2",
+    )?;
+    let listens = statement
+        .query_map((now.as_second(), BATCH), |row| {
+            Ok(Listen {
+                id: row.get(0)?,
+                listened_at: row.get(1)?,
+                artist: row.get(2)?,
+                title: row.get(3)?,
+                album: row.get(4)?,
+                duration: row.get(5)?,
+                musicbrainz_track_id: row.get(6)?,
+            })
+        })?
+        .try_collect()?;
+    Ok(listens)
+}
+
+impl Listen {
+    fn payload(&self) -> Payload<'_> {
+        Payload {
+            listened_at: self.listened_at,
+            track_metadata: TrackMetadata {
+                artist_name: &self.artist,
+                track_name: &self.title,
+                release_name: self.album.as_deref(),
+                additional_info: AdditionalInfo {
+                    submission_client: "mpdhaj",
+                    submission_client_version: env!("CARGO_PKG_VERSION"),
+                    duration_ms: self.duration.map(|seconds| (seconds * 1000.0) as u64),
+                    recording_mbid: self.musicbrainz_track_id.as_deref(),
+                },
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex as StdMutex;
+
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::system::empty_system;
+
+    /// Answers every request with the next status, remembers the headers and
+    /// bodies it got
+    async fn mock_listenbrainz(
+        statuses: Vec<u16>,
+    ) -> (String, Arc<StdMutex<Vec<(String, serde_json::Value)>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(StdMutex::new(Vec::new()));
+        let received = Arc::clone(&requests);
+        tokio::spawn(async move {
+            for status in statuses {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut stream = BufReader::new(stream);
+                let mut head = String::new();
+                loop {
+                    let mut line = String::new();
+                    stream.read_line(&mut line).await.unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    head += &line.to_lowercase();
+                }
+                let length = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length: "))
+                    .unwrap()
+                    .trim()
+                    .parse()
+                    .unwrap();
+                let mut body = vec![0; length];
+                stream.read_exact(&mut body).await.unwrap();
+                let body = serde_json::from_slice(&body).unwrap();
+                received.lock().unwrap().push((head, body));
+                let response = format!(
+                    "HTTP/1.1 {status} X\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    fn system_with_listens() -> Mutex<System> {
+        let system = empty_system(Connection::open_in_memory().unwrap());
+        system
+            .db
+            .execute_batch(
+                "INSERT INTO scrobbles
+                    (listened_at, artist, title, album, duration, musicbrainz_track_id)
+                    VALUES
+                    (1700000000, 'Abba', 'Waterloo', 'Waterloo', 165.5,
+                        '5f1a5c8e-0000-4000-8000-000000000000'),
+                    (1700000200, 'Abba', 'Honey, Honey', NULL, NULL, NULL);",
+            )
+            .unwrap();
+        Mutex::new(system)
+    }
+
+    fn queued(system: &System) -> Vec<(u32, i64)> {
+        system
+            .db
+            .prepare("SELECT attempts, retry_at FROM scrobbles")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .try_collect()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn listens_are_submitted_in_listenbrainz_format() {
+        let (url, requests) = mock_listenbrainz(vec![200]).await;
+        let system = system_with_listens();
+        let listenbrainz = ListenBrainz::new(url, "secret");
+
+        let now = Timestamp::from_second(1_700_001_000).unwrap();
+        let sent = submit_due(&system, &listenbrainz, now).await.unwrap();
+        assert_eq!(sent, 2);
+        assert!(queued(&*system.lock().await).is_empty());
+
+        let requests = requests.lock().unwrap();
+        let (head, body) = &requests[0];
+        assert!(head.starts_with("post /1/submit-listens "), "{head}");
+        assert!(head.contains("authorization: token secret"), "{head}");
+        assert_eq!(body["listen_type"], "import");
+        let waterloo = &body["payload"][0];
+        assert_eq!(waterloo["listened_at"], 1_700_000_000);
+        let metadata = &waterloo["track_metadata"];
+        assert_eq!(metadata["artist_name"], "Abba");
+        assert_eq!(metadata["track_name"], "Waterloo");
+        assert_eq!(metadata["release_name"], "Waterloo");
+        assert_eq!(metadata["additional_info"]["duration_ms"], 165_500);
+        assert_eq!(
+            metadata["additional_info"]["recording_mbid"],
+            "5f1a5c8e-0000-4000-8000-000000000000"
+        );
+        let honey = &body["payload"][1]["track_metadata"];
+        assert!(honey.get("release_name").is_none());
+        assert!(honey["additional_info"].get("recording_mbid").is_none());
+    }
+
+    #[tokio::test]
+    async fn failed_submissions_are_retried_later() {
+        let (url, requests) = mock_listenbrainz(vec![503, 503, 200]).await;
+        let system = system_with_listens();
+        let listenbrainz = ListenBrainz::new(url, "secret");
+        let at = |second| Timestamp::from_second(second).unwrap();
+
+        assert_eq!(submit_due(&system, &listenbrainz, at(0)).await.unwrap(), 0);
+        assert_eq!(queued(&*system.lock().await), [(1, 60), (1, 60)]);
+        // still waiting, nothing is sent
+        assert_eq!(submit_due(&system, &listenbrainz, at(59)).await.unwrap(), 0);
+        assert_eq!(requests.lock().unwrap().len(), 1);
+
+        assert_eq!(submit_due(&system, &listenbrainz, at(60)).await.unwrap(), 0);
+        assert_eq!(queued(&*system.lock().await), [(2, 180), (2, 180)]);
+        assert_eq!(
+            submit_due(&system, &listenbrainz, at(180)).await.unwrap(),
+            2
+        );
+        assert!(queued(&*system.lock().await).is_empty());
+        assert_eq!(requests.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn an_unreachable_server_keeps_the_listens() {
+        // nothing listens on the port once the listener is dropped
+        let url = {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            format!("http://{}", listener.local_addr().unwrap())
+        };
+        let system = system_with_listens();
+        let listenbrainz = ListenBrainz::new(url, "secret");
+        let now = Timestamp::from_second(1000).unwrap();
+        assert_eq!(submit_due(&system, &listenbrainz, now).await.unwrap(), 0);
+        assert_eq!(queued(&*system.lock().await), [(1, 1060), (1, 1060)]);
+    }
+}
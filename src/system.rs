@@ -5,236 +5,474 @@ use color_eyre::{Report, Result, eyre::eyre};
 use etcetera::BaseStrategy;
 use itertools::Itertools;
 use jiff::Timestamp;
-use rusqlite::Connection;
+use rodio::fixed_source::queue::uniform::SourceId;
+use rodio::{ChannelCount, SampleRate};
+use rusqlite::{Connection, OptionalExtension};
 use serde::{Deserialize, Serialize};
 use strum::IntoEnumIterator;
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc};
 use tracing::instrument;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
 
+use crate::artwork;
+use crate::mpd_protocol::ack::{self, Ack, ErrorCode};
 use crate::mpd_protocol::query::Query;
 use crate::mpd_protocol::{
-    self, AudioParams, FindResult, ListItem, PlayList, PlaybackState, Position, QueueEntry,
-    QueueId, QueueInfo, QueuePos, SongId, SubSystem, Tag, Volume,
+    self, AudioParams, BrowseEntry, ConsumeState, FindResult, FloatRange, PlayList,
+    PlaybackState, PlaylistSaveMode, PosOrRange, Position, QueueEntry, QueueId, QueueInfo,
+    QueuePos, SingleState, SongId, Sort, SubSystem, Tag, Volume,
 };
-use crate::player::Player;
-use crate::playlist::{self, PlaylistName};
+use crate::mpd_protocol::response_format;
+use crate::player::{OutputEvent, Player};
+use crate::playlist::{self, Playlist, PlaylistEntry, PlaylistName};
+use crate::watch::Watcher;
+use crate::waveform;
+use clients::Clients;
+use date::Date;
+use list_all::ListAll;
+use metrics::Metrics;
+use play_order::Rng;
+use readers::Readers;
+use updates::Updates;
 
-mod query;
+pub mod clients;
+mod collation;
+pub mod date;
+pub mod list_all;
+pub mod list_files;
+pub mod metrics;
+pub mod multi_value;
+pub(crate) mod migrations;
+mod next;
+mod overrides;
+pub mod play_order;
+pub mod playback;
+pub(crate) mod query;
+pub(crate) mod queue_keys;
+pub(crate) mod queue_snapshot;
+pub mod readers;
+mod stored_playlists;
+pub mod updates;
 
 pub fn sqlite_path() -> Result<PathBuf> {
     let dirs = etcetera::choose_base_strategy()?;
     Ok(dirs.cache_dir().join("mpdhaj").join("state.sqlite"))
 }
 
+/// What [`System::open`] needs besides the database
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub music_dir: Utf8PathBuf,
+    /// Defaults to `playlists` in the music dir
+    pub playlist_dir: Option<Utf8PathBuf>,
+    /// Skip silence at the start and end of songs
+    pub trim_silence: bool,
+    /// How much audio the output buffers, None leaves it to the device
+    pub buffer_time: Option<Duration>,
+    /// `searchadd` leaves out songs that are already in the queue
+    pub skip_queued: bool,
+}
+
+impl Config {
+    pub fn new(music_dir: Utf8PathBuf) -> Self {
+        Self {
+            music_dir,
+            ..Default::default()
+        }
+    }
+}
+
 pub struct System {
     pub db: Connection,
+    /// Clone this to read the database without holding the System lock.
+    /// None for in-memory databases.
+    pub readers: Option<Arc<Readers>>,
     pub player: Player,
     pub playing: PlaybackState,
-    pub playlists: HashMap<PlaylistName, Vec<Utf8PathBuf>>,
+    pub playlists: HashMap<PlaylistName, Playlist>,
+    /// Shown in the status after `load`, like mpd it is forgotten on restart
+    pub last_loaded_playlist: Option<PlaylistName>,
+    pub playlist_dir: Utf8PathBuf,
+    /// Reloads playlists changed by other programs while it is alive
+    pub playlist_watcher: Option<Watcher>,
+    /// Rescans files changed by other programs while it is alive, only set
+    /// when asked for
+    pub music_watcher: Option<Watcher>,
     pub idlers: HashMap<SubSystem, Vec<mpsc::Sender<SubSystem>>>,
+    /// Every notification, see [`System::subscribe`]
+    changes: broadcast::Sender<SubSystem>,
+    /// Clone this to reach the clients without holding the System lock
+    pub clients: Clients,
     pub music_dir: Utf8PathBuf,
     pub started_at: Timestamp, // for uptime
+    /// Shuffles the play order in random mode and the queue on `shuffle`
+    pub rng: Rng,
+    /// The player's id for the current song, tells its end apart from the
+    /// end of songs it replaced
+    pub current_source: Option<SourceId>,
+    /// Clone this to look up artwork without holding the System lock
+    pub artwork: Arc<artwork::Cache>,
+    /// Clone this to decode waveforms without holding the System lock
+    pub waveforms: Arc<waveform::Decoder>,
+    /// Queue plays in the `scrobbles` table, set when a scrobbler is
+    /// configured
+    pub scrobbling: bool,
+    /// Clone this to count commands that are answered without the System
+    /// lock
+    pub metrics: Arc<Metrics>,
+    /// The entry the player has queued to follow the current one without a
+    /// gap, and its id in the player
+    pub prefetched: Option<(QueueId, SourceId)>,
+    /// Replace the scanned tags of queue entries in responses, see
+    /// [`overrides`]
+    pub tag_overrides: HashMap<QueueId, Vec<(Tag, String)>>,
+    /// See [`Config::skip_queued`]
+    pub skip_queued: bool,
+    /// Scans asked for with `update`, see [`updates`]
+    pub updates: Updates,
 }
 
 impl System {
-    pub fn new(music_dir: Utf8PathBuf, playlist_dir: Option<Utf8PathBuf>) -> Result<Self> {
+    /// Opens the database in the cache dir, see [`sqlite_path`]
+    pub fn new(config: Config) -> Result<Self> {
         let cache = sqlite_path()?;
         std::fs::create_dir_all(cache.parent().unwrap())?;
-        let db = Connection::open(cache)?;
-        db.execute_batch(include_str!("tables.sql"))?;
+        let db = Connection::open(&cache)?;
+        Self::open(config, db)
+    }
+
+    /// Migrates `db` to the current schema and opens the default audio
+    /// output. A database in a file is also read from connections of its
+    /// own, see [`System::readers`].
+    pub fn open(config: Config, db: Connection) -> Result<Self> {
+        let file = db.path().filter(|path| !path.is_empty()).map(PathBuf::from);
+        let player = |volume, paused| Player::new(volume, paused);
+        let mut system = Self::with_parts(db, player, config.music_dir, config.playlist_dir)?;
+        system.player.set_trim_silence(config.trim_silence);
+        system.player.set_buffer_time(config.buffer_time);
+        system.skip_queued = config.skip_queued;
+        if let Some(file) = file {
+            system.readers = Some(Arc::new(Readers::new(&system.db, file)?));
+        }
+        Ok(system)
+    }
+
+    /// Like [`System::open`] but tests can pass in an in-memory database and a
+    /// player that does not need audio hardware.
+    pub(crate) fn with_parts(
+        mut db: Connection,
+        player: impl FnOnce(f32, bool) -> Player,
+        music_dir: Utf8PathBuf,
+        playlist_dir: Option<Utf8PathBuf>,
+    ) -> Result<Self> {
+        collation::register(&db).wrap_err("Could not register collation")?;
+        migrations::run(&mut db).wrap_err("Could not migrate the database")?;
+        // everything expects exactly one row, it only goes missing if someone
+        // edited the database
+        db.execute("INSERT OR IGNORE INTO state (rowid) VALUES (0)", [])?;
         let playlist_dir = playlist_dir.unwrap_or_else(|| music_dir.join("playlists"));
 
         let (paused, volume) = db.query_one("SELECT paused, volume FROM state", [], |row| {
             Ok((row.get::<_, bool>(0)?, row.get::<_, f32>(1)?))
         })?;
 
-        let playlists = match playlist::load_from_dir(&playlist_dir) {
+        let playlists = match playlist::load_from_dir(&playlist_dir, &music_dir) {
             Ok(p) => p,
             Err(e) => {
                 tracing::warn!("Playlists failed to load: {e:#}. Using an empty list...");
                 Default::default()
             }
         };
-        let player = Player::new(volume, paused);
-        Ok(System {
+        // stored in percent like clients set it
+        let player = player((volume / 100.0).clamp(0.0, 1.0), paused);
+        let system = System {
             db,
+            readers: None,
             music_dir,
+            playlist_dir,
             playlists,
+            last_loaded_playlist: None,
+            playlist_watcher: None,
+            music_watcher: None,
             player,
             playing: Default::default(),
             idlers: Default::default(),
+            changes: broadcast::channel(64).0,
+            clients: Default::default(),
             started_at: Timestamp::now(),
-        })
+            rng: Rng::new(Timestamp::now().as_nanosecond() as u64),
+            current_source: None,
+            artwork: Default::default(),
+            waveforms: Default::default(),
+            scrobbling: false,
+            metrics: Arc::default(),
+            prefetched: None,
+            tag_overrides: HashMap::new(),
+            skip_queued: false,
+            updates: Updates::default(),
+        };
+        system.verify_queue()?;
+        Ok(system)
     }
 
     pub fn status(&self) -> Result<mpd_protocol::Status> {
-        let (current, random, single, consume, repeat, volume) = self.db.query_one(
-            "SELECT current, random, single, consume, repeat, volume FROM state",
+        let (current, random, single, consume, repeat, volume, version) = self.db.query_one(
+            "SELECT current, random, single, consume, repeat, volume, queue_version FROM state",
             [],
             |row| {
                 Ok((
-                    row.get::<_, u32>(0)?,
+                    row.get::<_, Option<u32>>(0)?,
                     row.get(1)?,
-                    row.get(2)?,
-                    row.get(3)?,
+                    row.get::<_, u8>(2)?,
+                    row.get::<_, u8>(3)?,
                     row.get(4)?,
-                    row.get(5)?,
+                    row.get::<_, i32>(5)?,
+                    row.get(6)?,
                 ))
             },
         )?;
         let len = self
             .db
             .query_one("SELECT COUNT(*) FROM queue", [], |row| row.get::<_, u32>(0))?;
-        let (mut queue_pos, mut queue_id, mut next_pos, mut next_id) = (None, None, None, None);
-        if let Ok((id, pos)) = self.db.query_one(
-            "SELECT song, position FROM QUEUE WHERE id = ?1",
-            [current],
-            |row| Ok((row.get::<_, u32>(0)?, row.get(1)?)),
-        ) {
-            queue_pos = Some(QueuePos(pos));
-            queue_id = Some(QueueId(id));
-            if let Ok(id) = self.db.query_one(
-                "SELECT song FROM QUEUE WHERE position = ?1",
-                [pos + 1],
-                |row| row.get::<_, u32>(0),
-            ) {
-                next_pos = Some(QueuePos(pos + 1));
-                next_id = Some(QueueId(id));
-            }
-        }
+        // the `queue` view would number every entry to find this one
+        let entry = match current {
+            Some(pos) => self
+                .db
+                .query_one(
+                    "SELECT q.id, s.duration FROM queue_entries q
+                     LEFT JOIN songs s ON s.rowid = q.song
+                     ORDER BY q.key LIMIT 1 OFFSET ?1",
+                    [pos],
+                    |row| Ok((QueueId(row.get(0)?), row.get::<_, Option<f64>>(1)?)),
+                )
+                .optional()?,
+            None => None,
+        };
+        let queue_id = entry.map(|(id, _)| id);
+        let queue_pos = current.filter(|_| queue_id.is_some()).map(QueuePos);
+        let next = self.next_entry()?;
+        let player = self.player.status();
+        let duration = if self.playing == PlaybackState::Stop {
+            None
+        } else {
+            entry
+                .and_then(|(_, duration)| duration)
+                .and_then(|duration| Duration::try_from_secs_f64(duration).ok())
+        };
+        let elapsed = player.source.map(|_| player.heard());
         Ok(mpd_protocol::Status {
             repeat,
             random,
-            single,
-            consume,
-            partition: "default".to_string(),
-            volume: Volume::new(volume), // TODO: persist
-            playlist: 0,             // TODO
+            single: match single {
+                0 => SingleState::Off,
+                2 => SingleState::Oneshot,
+                _ => SingleState::On,
+            },
+            consume: match consume {
+                0 => ConsumeState::Off,
+                2 => ConsumeState::Oneshot,
+                _ => ConsumeState::On,
+            },
+            partition: clients::DEFAULT_PARTITION.to_string(),
+            volume: Volume::try_from(volume)?,
+            playlist: version,
             playlistlength: len as u64,
             state: self.playing,
-            lastloadedplaylist: None,
+            lastloadedplaylist: self.last_loaded_playlist.clone(),
             xfade: Duration::from_secs(0),
             song: queue_pos,
             songid: queue_id,
-            elapsed: None, // TODO
+            time: elapsed.zip(duration),
+            elapsed,
             bitrate: None,
-            duration: None, // TODO
+            duration,
             audio: None,
-            error: None,
-            nextsong: next_pos,
-            nextsongid: next_id,
+            updating_db: self.updating_db(),
+            error: player.error,
+            nextsong: next.map(|(pos, _)| pos),
+            nextsongid: next.map(|(_, id)| id),
         })
     }
 
-    pub fn queue(&self) -> Result<mpd_protocol::QueueInfo> {
-        let mut stmt = self.db.prepare(
-            "SELECT q.id, q.position, s.path, s.title, s.artist, s.album
-             FROM queue q
-             JOIN songs s ON s.rowid = q.song
-             ORDER BY q.position",
-        )?;
+    /// Between 0 and 100, kept across restarts
+    pub fn set_volume(&mut self, volume: u8) -> Result<()> {
+        self.player.set_volume(f32::from(volume) / 100.0);
+        self.db.execute("UPDATE state SET volume = ?", [volume])?;
+        self.notify(SubSystem::Mixer);
+        Ok(())
+    }
 
-        let songs = stmt
-            .query_and_then([], |row| {
-                let queue_id: u32 = row.get(0)?;
-                let position: u32 = row.get(1)?;
-                let song = Song {
-                    path: row.get::<_, String>(2)?.into(),
-                    title: row.get(3)?,
-                    artist: row.get(4)?,
-                    album: row.get(5)?,
-                    ..Default::default()
-                };
-                Ok::<_, Report>(QueueEntry::mostly_fake(position, QueueId(queue_id), song))
-            })?
-            .collect::<Result<_, _>>()?;
+    pub fn volume(&self) -> Result<Volume> {
+        let volume = self
+            .db
+            .query_one("SELECT volume FROM state", [], |row| row.get::<_, i32>(0))?;
+        Ok(Volume::try_from(volume)?)
+    }
 
-        Ok(mpd_protocol::QueueInfo(songs))
+    /// `volume`, changes the volume by `change` percent but not past 0 or
+    /// 100. Returns the new volume.
+    pub fn change_volume(&mut self, change: i8) -> Result<Volume> {
+        let volume = i32::from(self.volume()?.get()) + i32::from(change);
+        let volume = Volume::try_from(volume.clamp(0, 100))?;
+        self.set_volume(volume.get())?;
+        Ok(volume)
     }
 
+    pub fn set_repeat(&mut self, repeat: bool) -> Result<()> {
+        self.db.execute("UPDATE state SET repeat = ?", [repeat])?;
+        self.notify(SubSystem::Options);
+        self.prefetch_next();
+        Ok(())
+    }
+
+    /// `single`, playback stops after the current song. Stored like
+    /// [`System::set_consume`] does.
+    pub fn set_single(&mut self, single: SingleState) -> Result<()> {
+        let single = match single {
+            SingleState::Off => 0,
+            SingleState::On => 1,
+            SingleState::Oneshot => 2,
+        };
+        self.db.execute("UPDATE state SET single = ?", [single])?;
+        self.notify(SubSystem::Options);
+        self.prefetch_next();
+        Ok(())
+    }
+
+    /// `consume`, songs leave the queue once they played. Oneshot turns
+    /// itself off after one song. Stored as 0, 1 or 2 for oneshot, which
+    /// reads as `true` where only on or off matters.
+    pub fn set_consume(&mut self, consume: ConsumeState) -> Result<()> {
+        let consume = match consume {
+            ConsumeState::Off => 0,
+            ConsumeState::On => 1,
+            ConsumeState::Oneshot => 2,
+        };
+        self.db.execute("UPDATE state SET consume = ?", [consume])?;
+        self.notify(SubSystem::Options);
+        self.prefetch_next();
+        Ok(())
+    }
+
+    pub fn queue(&self) -> Result<mpd_protocol::QueueInfo> {
+        self.queue_range(0..u32::MAX)
+    }
+
+    /// The queue entries with their position in `positions`
+    pub fn queue_range(&self, positions: Range<u32>) -> Result<mpd_protocol::QueueInfo> {
+        queue_range(&self.db, positions)
+    }
+
+    /// Sorted by name so the listing does not change between runs
     pub fn playlists(&self) -> mpd_protocol::PlaylistList {
         let list = self
             .playlists
-            .keys()
-            .map(|name| PlayList::from_name(name.clone()))
+            .iter()
+            .sorted_by(|(a, _), (b, _)| collation::compare(&a.0, &b.0))
+            .map(|(name, playlist)| PlayList::new(name.clone(), playlist.last_modified))
             .collect_vec();
         mpd_protocol::PlaylistList(list)
     }
 
+    /// The stored playlists as `lsinfo` shows them in the root of the music
+    /// dir, sorted like [`System::playlists`]
+    pub fn browse_playlists(&self) -> Vec<BrowseEntry> {
+        self.playlists
+            .iter()
+            .sorted_by(|(a, _), (b, _)| collation::compare(&a.0, &b.0))
+            .map(|(name, playlist)| BrowseEntry::Playlist {
+                name: name.clone(),
+                last_modified: Some(playlist.last_modified),
+            })
+            .collect()
+    }
+
     fn song_id_from_path(&self, path: &Utf8Path) -> Result<SongId> {
         Ok(self.db.query_one(
             "SELECT rowid FROM songs WHERE path = ?1",
-            [path.as_str()],
+            [crate::scan::normalize_path(path).as_str()],
             |row| row.get(0).map(SongId),
         )?)
     }
 
     pub fn get_song(&self, id: SongId) -> Result<Song> {
-        self.db
+        let mut song = self
+            .db
             .query_one(
-                "SELECT path, title, artist, album FROM songs WHERE rowid = ?1",
+                &format!("SELECT {SONG_COLUMNS} FROM songs WHERE rowid = ?1"),
                 [id.0],
-                |row| {
-                    Ok(Song {
-                        path: row.get::<_, String>(0)?.into(),
-                        title: row.get(1)?,
-                        artist: row.get(2)?,
-                        album: row.get(3)?,
-                        ..Default::default()
-                    })
-                },
+                song_from_row,
             )
             .wrap_err("Couldn't find song in database")
-            .with_note(|| format!("song id: {id:?}"))
+            .with_note(|| format!("song id: {id:?}"))?;
+        multi_value::load(&self.db, id.0, &mut song)?;
+        Ok(song)
     }
 
     pub fn get_song_by_path(&self, path: &Utf8Path) -> Result<Song> {
-        Ok(self.db.query_one(
-            "SELECT title, artist, album FROM songs WHERE path = ?1",
-            [path.as_str()],
-            |r| {
-                Ok(Song {
-                    path: path.to_owned(),
-                    title: r.get(0)?,
-                    artist: r.get(1)?,
-                    album: r.get(2)?,
-                    ..Default::default()
-                })
-            },
-        )?)
+        song_by_path(&self.db, path)?
+            .ok_or_else(|| eyre!("Song is not in the database"))
+            .with_note(|| format!("path: {path}"))
+    }
+
+    /// Where the song at `uri` is on disk. Only songs in the database resolve,
+    /// so a uri can not point outside the music dir.
+    pub fn song_path(&self, uri: &Utf8Path) -> Result<Utf8PathBuf> {
+        let song = self.get_song_by_path(uri)?;
+        Ok(self.music_dir.join(song.path))
     }
 
     pub fn get_playlist(&self, name: &PlaylistName) -> Result<mpd_protocol::QueueInfo> {
-        let Some(paths) = self.playlists.get(name) else {
+        let Some(playlist) = self.playlists.get(name) else {
             tracing::warn!("No playlist found with name: {name:?}");
             return Ok(QueueInfo(Vec::new()));
         };
 
-        let song_ids: Vec<_> = paths
-            .iter()
-            .map(|path| self.song_id_from_path(path))
-            .collect::<Result<_, _>>()?;
+        Ok(QueueInfo(playlist_entries(&self.db, name, &playlist.entries)?))
+    }
 
-        let songs = song_ids
-            .into_iter()
-            .enumerate()
-            .map(|(pos, song_id)| {
-                let song = self.get_song(song_id)?;
-                Ok::<_, Report>(QueueEntry::mostly_fake(
-                    pos as u32,
-                    QueueId(42), // TODO
-                    song,
-                ))
-            })
-            .collect::<Result<_, _>>()?;
+    /// Picks up a playlist file that was added, changed or removed. Returns
+    /// whether that changed anything, files written by
+    /// [`Self::replace_playlist_with`] are already up to date.
+    pub fn reload_playlist(&mut self, path: &Utf8Path) -> bool {
+        if playlist::is_hidden(path.as_std_path()) {
+            return false;
+        }
+        if !path.is_file() {
+            return path.file_name().is_some_and(|name| {
+                self.playlists
+                    .remove(&PlaylistName(name.to_owned()))
+                    .is_some()
+            });
+        }
+        match playlist::load_file(path, &self.music_dir) {
+            Ok((name, playlist)) => self.playlists.insert(name, playlist.clone()) != Some(playlist),
+            Err(e) => {
+                tracing::warn!("Could not reload playlist: {e:#}");
+                false
+            }
+        }
+    }
 
-        Ok(mpd_protocol::QueueInfo(songs))
+    /// Swaps the content of a stored playlist, creating it if needed. Clients
+    /// and other programs never see it empty or half written in between.
+    pub fn replace_playlist_with(
+        &mut self,
+        name: &PlaylistName,
+        paths: impl IntoIterator<Item = impl Into<PlaylistEntry>>,
+    ) -> Result<()> {
+        let entries = paths.into_iter().map(Into::into).collect_vec();
+        self.store_playlist(name, &entries, PlaylistSaveMode::Replace)
     }
 
+    /// For listeners within mpdhaj, like mpris and the watchers, that stay
+    /// for good. Connected clients idle through their
+    /// [`Registration`](clients::Registration) instead.
     pub fn idle(&mut self, mut subsystems: Vec<SubSystem>) -> mpsc::Receiver<SubSystem> {
         if subsystems.is_empty() {
             subsystems.extend(SubSystem::iter());
@@ -250,164 +488,652 @@ impl System {
         rx
     }
 
-    pub fn add_to_queue(&self, path: &Utf8Path, position: &Option<Position>) -> Result<QueueId> {
-        let song = self.song_id_from_path(path)?;
-        let current = self
-            .db
-            .query_one("SELECT current FROM state", [], |row| row.get::<_, u32>(0))?;
-        if let Some(pos) = position {
-            let pos: u32 = match pos {
-                Position::Absolute(pos) => *pos,
-                Position::Relative(offset) => {
-                    if -offset > current as i32 {
-                        return Err(eyre!(
-                            "Position {offset} is invalid, current position is {current}"
-                        ));
-                    }
-                    (current as i32 + offset) as u32
+    /// Every subsystem that changes from now on. A receiver that falls
+    /// behind misses the oldest ones and gets a `Lagged` error.
+    pub fn subscribe(&self) -> broadcast::Receiver<SubSystem> {
+        self.changes.subscribe()
+    }
+
+    /// Wakes up the clients idling on this subsystem, those that are not
+    /// hear of it on their next idle
+    pub fn notify(&mut self, subsystem: SubSystem) {
+        self.clients.notify(subsystem);
+        // no subscribers is fine
+        let _ = self.changes.send(subsystem);
+        if let Some(subscribers) = self.idlers.get_mut(&subsystem) {
+            // a client that left idle dropped its receiver
+            subscribers.retain(|tx| match tx.try_send(subsystem) {
+                Ok(()) | Err(mpsc::error::TrySendError::Full(_)) => true,
+                Err(mpsc::error::TrySendError::Closed(_)) => false,
+            });
+        }
+    }
+
+    pub async fn handle_output_event(&mut self, event: OutputEvent) {
+        match event {
+            OutputEvent::Failed(error) => {
+                tracing::error!("Audio output failed: {error}");
+                self.metrics.output_failure();
+            }
+            OutputEvent::Recovered => tracing::info!("Audio output recovered"),
+            OutputEvent::Finished { source, elapsed } => {
+                if self.current_source == Some(source)
+                    && let Err(e) = self.current_finished(elapsed).await
+                {
+                    tracing::error!("Could not move on to the next song: {e:#}");
                 }
-            };
-            self.db.execute(
-                "UPDATE queue SET position = position + 1 WHERE position >= ?1",
-                [pos],
-            )?;
-            let mut stmt = self
-                .db
-                .prepare("INSERT INTO queue (song, position) VALUES (?1, ?2)")?;
-            Ok(stmt.insert([song.0, pos]).map(|n| QueueId(n as u32))?)
-        } else {
-            if current == 0 {
-                self.db.execute("UPDATE state SET current = 1", [])?;
+                return;
+            }
+            OutputEvent::Tags { source, tags } => {
+                if let Err(e) = self.stream_tags(source, tags) {
+                    tracing::error!("Could not update the tags of the current song: {e:#}");
+                }
+                return;
             }
-            let mut stmt = self.db.prepare(
-                "INSERT INTO queue (song, position)
-                    VALUES (?1, COALESCE((SELECT MAX(position) FROM queue), 0) + 1)",
-            )?;
-            Ok(stmt.insert([song.0]).map(|n| QueueId(n as u32))?)
         }
+        self.notify(SubSystem::Output);
+        self.notify(SubSystem::Player);
     }
 
-    pub fn list_all_in(&self, dir: &Utf8Path) -> Result<Vec<ListItem>> {
-        let mut stmt = self.db.prepare(&format!(
-            "SELECT path FROM songs WHERE path LIKE '{}%'",
-            dir.as_str()
-        ))?;
-        stmt.query_and_then([], |row| {
-            Result::Ok(ListItem::File(row.get::<_, String>(0)?.into()))
-        })?
-        .collect::<Result<Vec<_>, Report>>()
+    pub fn add_to_queue(
+        &mut self,
+        path: &Utf8Path,
+        position: &Option<Position>,
+    ) -> Result<QueueId> {
+        let ids = self.add_many_to_queue(&[path.to_owned()], *position)?;
+        Ok(ids[0])
+    }
+
+    /// Inserts the songs in order as one change to the queue. Nothing is added
+    /// if one of them is not in the database. Songs that are missing and
+    /// positions past the end of the queue are [`Ack`]s.
+    pub fn add_many_to_queue(
+        &mut self,
+        paths: &[Utf8PathBuf],
+        position: Option<Position>,
+    ) -> Result<Vec<QueueId>> {
+        let missing = |e: &Report| {
+            let e = e.downcast_ref::<rusqlite::Error>();
+            matches!(e, Some(rusqlite::Error::QueryReturnedNoRows))
+        };
+        let songs: Vec<SongId> = paths
+            .iter()
+            .map(|path| match self.song_id_from_path(path) {
+                Err(e) if missing(&e) => {
+                    Err(Ack::new(ErrorCode::NoExist, "No such song"))
+                        .with_note(|| format!("{path} is not in the database"))
+                }
+                other => other,
+            })
+            .try_collect()?;
+        if songs.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let t = self.db.unchecked_transaction()?;
+        let current = t.query_one("SELECT current FROM state", [], |row| {
+            row.get::<_, Option<u32>>(0)
+        })?;
+        let len: u32 = t.query_one("SELECT COUNT(*) FROM queue", [], |row| row.get(0))?;
+        let first = match position {
+            Some(Position::Absolute(pos)) => pos,
+            Some(Position::Relative(offset)) => {
+                let Some(current) = current else {
+                    return Err(Ack::new(ErrorCode::Arg, "No current song").into());
+                };
+                u32::try_from(current as i64 + offset as i64)
+                    .map_err(|_| Ack::new(ErrorCode::Arg, "Bad song index"))
+                    .with_note(|| format!("position {offset}, current position is {current}"))?
+            }
+            None => len,
+        };
+        // mpd appends at the length but leaves no gaps
+        if first > len {
+            return Err(Ack::new(ErrorCode::Arg, "Bad song index"))
+                .with_note(|| format!("position {first}, queue length is {len}"));
+        }
+        shift_current(&t, first, songs.len() as i64)?;
+        let keys = queue_keys::make_room(&t, first, songs.len() as u32)?;
+        let mut ids = Vec::with_capacity(songs.len());
+        {
+            // AUTOINCREMENT never hands out an id twice, not even after the
+            // row is deleted
+            let mut insert =
+                t.prepare("INSERT INTO queue_entries (song, key) VALUES (?1, ?2) RETURNING id")?;
+            for (key, song) in keys.into_iter().zip(&songs) {
+                ids.push(insert.query_row(
+                    rusqlite::params![song.0, key],
+                    |row| row.get(0).map(QueueId),
+                )?);
+            }
+        }
+        if current.is_none() {
+            t.execute("UPDATE state SET current = ?1", [first])?;
+        }
+        play_order::update(&t, &self.rng)?;
+        t.execute("UPDATE state SET queue_version = queue_version + 1", [])?;
+        t.commit()?;
+        self.notify(SubSystem::Playlist);
+        Ok(ids)
     }
 
-    pub fn list_tag(&self, tag_to_list: &Tag) -> Result<Vec<String>> {
-        let s = tag_to_list.to_string();
+    /// Adds the songs of a stored playlist, or those in `range` of it, at
+    /// `position` or the end of the queue. Entries that are not in the
+    /// database are skipped, playlists routinely outlive the files they list.
+    pub fn load_playlist(
+        &mut self,
+        name: &PlaylistName,
+        range: Option<mpd_protocol::Range>,
+        position: Option<Position>,
+    ) -> Result<Vec<QueueId>> {
+        let Some(playlist) = self.playlists.get(name) else {
+            return Err(Ack::new(ErrorCode::NoExist, "No such playlist"))
+                .with_note(|| format!("name: {:?}", name.0));
+        };
+        let window = range.map_or(0..u32::MAX, |range| range.window());
+        let len = playlist.entries.len();
+        if window.start as usize > len || window.start > window.end {
+            return Err(Ack::new(ErrorCode::Arg, "Bad song index"))
+                .with_note(|| format!("range {window:?}, the playlist has {len} entries"));
+        }
+
+        let mut known = self
+            .db
+            .prepare_cached("SELECT 1 FROM songs WHERE path = ?1")?;
+        let mut paths = Vec::new();
+        for entry in playlist
+            .entries
+            .iter()
+            .take(window.end as usize)
+            .skip(window.start as usize)
+        {
+            let path = crate::scan::normalize_path(&entry.path);
+            if known.exists([path.as_str()])? {
+                paths.push(entry.path.clone());
+            } else {
+                tracing::warn!(
+                    "Not loading {} from playlist {name:?}, it is not in the database",
+                    entry.path
+                );
+            }
+        }
+        drop(known);
+
+        let ids = self.add_many_to_queue(&paths, position)?;
+        self.last_loaded_playlist = Some(name.clone());
+        Ok(ids)
+    }
+
+    /// [`System::add_many_to_queue`] without the songs already in the queue,
+    /// a song listed twice is added once. Returns the new ids and how many
+    /// songs were left out.
+    pub fn add_unqueued_to_queue(
+        &mut self,
+        paths: &[Utf8PathBuf],
+        position: Option<Position>,
+    ) -> Result<(Vec<QueueId>, usize)> {
+        let mut queued: HashSet<Utf8PathBuf> = self
+            .db
+            .prepare("SELECT s.path FROM queue q JOIN songs s ON s.rowid = q.song")?
+            .query_map([], |row| row.get::<_, String>(0).map(Utf8PathBuf::from))?
+            .try_collect()?;
+        let (new, skipped): (Vec<_>, Vec<_>) = paths
+            .iter()
+            .cloned()
+            .partition(|path| queued.insert(path.clone()));
+        let ids = self.add_many_to_queue(&new, position)?;
+        Ok((ids, skipped.len()))
+    }
+
+    /// Like mpd, the songs in a directory come before its subdirectories
+    pub fn list_all_in(&self, dir: &Utf8Path) -> Result<Vec<BrowseEntry>> {
+        let mut walk = ListAll::new(dir);
+        let mut list = Vec::new();
+        while let Some(items) = walk.next_items(&self.db, usize::MAX)? {
+            list.extend(items);
+        }
+        Ok(list)
+    }
+
+    /// The directories directly inside `dir`, sorted
+    pub fn subdirectories(&self, dir: &Utf8Path) -> Result<Vec<BrowseEntry>> {
+        let dir = crate::scan::normalize_path(dir);
         let mut stmt = self
             .db
-            .prepare(&format!("SELECT DISTINCT {} FROM songs", s.to_lowercase()))?;
-        Ok(stmt
-            .query_and_then([], |row| dbg!(row.get::<_, String>(0)))?
-            .map(|result| result.map(|output| format!("{s}: {output}")))
-            .collect::<Result<Vec<_>, _>>()?)
+            .prepare("SELECT path, mtime FROM directories WHERE parent = ?1 ORDER BY path")?;
+        stmt.query_and_then([dir.as_str()], |row| {
+            Ok::<_, Report>(BrowseEntry::Directory {
+                path: row.get::<_, String>(0)?.into(),
+                last_modified: Some(row.get::<_, String>(1)?.parse()?),
+            })
+        })?
+        .collect()
+    }
+
+    /// The music dir itself or a directory with songs in it
+    pub fn is_directory(&self, dir: &Utf8Path) -> Result<bool> {
+        let dir = crate::scan::normalize_path(dir);
+        Ok(dir.as_str().is_empty()
+            || self
+                .db
+                .query_one(
+                    "SELECT 1 FROM directories WHERE path = ?1",
+                    [dir.as_str()],
+                    |_| Ok(()),
+                )
+                .optional()?
+                .is_some())
+    }
+
+    /// The songs directly inside `dir`, sorted
+    pub fn songs_in(&self, dir: &Utf8Path) -> Result<Vec<Song>> {
+        let dir = crate::scan::normalize_path(dir);
+        let paths: Vec<String> = self
+            .db
+            .prepare(
+                "SELECT path FROM songs WHERE substr(path, 1, length(?1)) = ?1
+                    AND instr(substr(path, length(?1) + 1), '/') = 0
+                    ORDER BY path",
+            )?
+            .query_map([crate::scan::dir_prefix(&dir)], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        paths
+            .iter()
+            .filter_map(|path| song_by_path(&self.db, Utf8Path::new(path)).transpose())
+            .collect()
     }
 
-    pub fn handle_find(&self, query: &Query) -> Result<Vec<FindResult>> {
-        query::handle_find(self, query)
+    pub fn handle_find(
+        &self,
+        query: &Query,
+        sort: Option<&Sort>,
+        window: Option<Range<u32>>,
+    ) -> Result<Vec<FindResult>> {
+        query::handle_find(&self.db, query, sort, window, query::Matching::Exact)
+    }
+
+    pub fn handle_search(
+        &self,
+        query: &Query,
+        sort: Option<&Sort>,
+        window: Option<Range<u32>>,
+    ) -> Result<Vec<FindResult>> {
+        query::handle_find(&self.db, query, sort, window, query::Matching::IgnoreCase)
     }
 
     #[instrument(skip(self), ret)]
     pub fn current_song(&self) -> Result<Option<QueueEntry>> {
-        let Ok(pos): Result<u32, _> = self
+        let pos: Option<u32> = self
             .db
-            .query_one("SELECT current FROM state", [], |row| row.get(0))
-        else {
-            return Ok(None);
-        };
-        if pos == 0 {
-            return Ok(None);
+            .query_one("SELECT current FROM state", [], |row| row.get(0))?;
+        match pos {
+            Some(pos) => self.song_by_pos(QueuePos(pos)),
+            None => Ok(None),
         }
-        self.song_by_pos(QueuePos(pos))
     }
 
     pub fn song_by_pos(&self, pos: QueuePos) -> Result<Option<QueueEntry>> {
-        let Ok((song, id)) = self.db.query_one(
-            "SELECT song, id FROM queue WHERE position = ?1",
+        let Ok((song, id, prio_and_range)) = self.db.query_one(
+            "SELECT song, id, prio, range_start, range_end FROM queue_entries
+             ORDER BY key LIMIT 1 OFFSET ?1",
             [pos.0],
-            |row| Ok((row.get(0)?, row.get(1)?)),
+            |row| Ok((row.get(0)?, row.get(1)?, prio_and_range(row)?)),
         ) else {
             return Err(eyre!("Couldn't find song #{} in the queue", pos.0));
         };
         let song = self.get_song(SongId(song))?;
-        Ok(Some(QueueEntry::mostly_fake(pos.0, QueueId(id), song)))
+        let mut entry = QueueEntry::from_song(song, Some(pos), Some(QueueId(id)));
+        (entry.prio, entry.range) = prio_and_range;
+        if let Some(tags) = self.tag_overrides.get(&QueueId(id)) {
+            overrides::apply(&mut entry, tags);
+        }
+        Ok(Some(entry))
     }
 
     pub fn song_by_id(&self, id: QueueId) -> Result<Option<QueueEntry>> {
-        let Ok((song, pos)) = self.db.query_one(
-            "SELECT song, position FROM queue WHERE id = ?1",
+        let Ok((song, pos, prio_and_range)) = self.db.query_one(
+            "SELECT song, (SELECT COUNT(*) FROM queue_entries WHERE key < e.key),
+                prio, range_start, range_end
+             FROM queue_entries e WHERE id = ?1",
             [id.0],
-            |row| Ok((row.get(0)?, row.get(1)?)),
+            |row| Ok((row.get(0)?, row.get(1)?, prio_and_range(row)?)),
         ) else {
             return Err(eyre!("Couldn't find song id {} in the queue", id.0));
         };
         let song = self.get_song(SongId(song))?;
-        Ok(Some(QueueEntry::mostly_fake(pos, id, song)))
+        let mut entry = QueueEntry::from_song(song, Some(QueuePos(pos)), Some(id));
+        (entry.prio, entry.range) = prio_and_range;
+        if let Some(tags) = self.tag_overrides.get(&id) {
+            overrides::apply(&mut entry, tags);
+        }
+        Ok(Some(entry))
     }
 
-    pub fn clear(&self) -> Result<()> {
+    /// Empties the queue. The audio is stopped first, it never plays a song
+    /// that is no longer in the queue.
+    pub async fn clear(&mut self) -> Result<()> {
+        // the end of the aborted song must not start the next one
+        self.current_source = None;
+        self.prefetched = None;
+        self.player.stop().await;
+        if self.playing != PlaybackState::Stop {
+            self.playing = PlaybackState::Stop;
+            self.notify(SubSystem::Player);
+        }
+        self.clear_queue()?;
+        self.notify(SubSystem::Playlist);
+        Ok(())
+    }
+
+    fn clear_queue(&self) -> Result<()> {
         self.db.execute_batch(
             "BEGIN;
-            UPDATE state SET current = 0;
-            DELETE FROM queue;
+            UPDATE state SET current = NULL, queue_version = queue_version + 1;
+            DELETE FROM queue_entries;
+            DELETE FROM play_order;
             COMMIT;",
         )?;
         Ok(())
     }
 
-    // pub fn stats(&self) -> Result<Stats> {
-    //     #[derive(Default)]
-    //     struct Counter {
-    //         artists: HashSet<String>,
-    //         albums: HashSet<String>,
-    //         songs: usize,
-    //         db_playtime: Duration,
-    //     }
-
-    //     let counter = self
-    //         .db
-    //         .library()
-    //         .iter()
-    //         .fold_ok(Counter::default(), |mut counter, song| {
-    //             counter.artists.insert(song.artist);
-    //             counter.albums.insert(song.album);
-    //             counter.songs += 1;
-    //             counter.db_playtime += song.playtime;
-    //             counter
-    //         })
-    //         .wrap_err("Could not read items from db for counting")?;
-
-    //     dbg!(self.queue()?);
-
-    //     let playtime = self
-    //         .queue()
-    //         .wrap_err("Could not get queue")?
-    //         .0
-    //         .into_iter()
-    //         .map(|entry| dbg!(entry).duration)
-    //         .sum();
-
-    //     Ok(Stats {
-    //         artists: counter.artists.len(),
-    //         albums: counter.albums.len(),
-    //         songs: counter.songs,
-    //         uptime: self.up_since.elapsed(),
-    //         db_playtime: counter.db_playtime,
-    //         db_update: self
-    //             .db
-    //             .last_db_update()
-    //             .get()
-    //             .wrap_err("Could not read last db update")?
-    //             .unwrap_or_default(),
-    //         playtime,
-    //     })
-    // }
+    /// `delete`, removes the entry at a position or the entries in a range.
+    /// A range without an end runs to the end of the queue.
+    pub async fn delete_pos_or_range(&mut self, what: Option<PosOrRange>) -> Result<()> {
+        let positions = match what {
+            Some(PosOrRange::Position(Position::Absolute(pos))) => pos..pos.saturating_add(1),
+            Some(PosOrRange::Range(range)) => range.window(),
+            Some(PosOrRange::Position(Position::Relative(_))) | None => {
+                return Err(Ack::new(ErrorCode::Arg, "Bad song index"))
+                    .with_note(|| format!("delete takes a position or range, got: {what:?}"));
+            }
+        };
+        self.delete(positions).await
+    }
+
+    /// `deleteid`, removes the entry with queue id `id`
+    pub async fn delete_id(&mut self, id: QueueId) -> Result<()> {
+        let pos = self.position_of(id)?;
+        self.delete(pos.0..pos.0 + 1).await
+    }
+
+    /// Removes the entries at `positions`, the ones after them move up. When
+    /// the current entry goes while playing the one that would have played
+    /// next starts, otherwise playback stops.
+    async fn delete(&mut self, positions: Range<u32>) -> Result<()> {
+        let len: u32 = self
+            .db
+            .query_one("SELECT COUNT(*) FROM queue", [], |row| row.get(0))?;
+        let positions = positions.start..positions.end.min(len);
+        if positions.is_empty() {
+            return Err(Ack::new(ErrorCode::Arg, "Bad song index"))
+                .with_note(|| format!("positions: {positions:?}, queue length is {len}"));
+        }
+        let current: Option<u32> = self
+            .db
+            .query_one("SELECT current FROM state", [], |row| row.get(0))?;
+        let removes_current = current.is_some_and(|current| positions.contains(&current));
+        let next = match self.playing {
+            PlaybackState::Play if removes_current => self.entry_after_current(true)?,
+            _ => None,
+        };
+        if removes_current {
+            // like clear, a song that left the queue is never heard again
+            self.current_source = None;
+            self.prefetched = None;
+            self.player.stop().await;
+            self.tag_overrides.clear();
+        }
+
+        let removed = positions.len() as i64;
+        let t = self.db.unchecked_transaction()?;
+        t.execute(
+            "DELETE FROM queue_entries WHERE id IN
+                (SELECT id FROM queue WHERE position >= ?1 AND position < ?2)",
+            [positions.start, positions.end],
+        )?;
+        shift_current(&t, positions.end, -removed)?;
+        if removes_current {
+            // the entry that followed, if any
+            let current = (positions.start < len - removed as u32).then_some(positions.start);
+            t.execute("UPDATE state SET current = ?1", [current])?;
+        }
+        t.execute("DELETE FROM play_order WHERE id NOT IN (SELECT id FROM queue)", [])?;
+        play_order::update(&t, &self.rng)?;
+        t.execute("UPDATE state SET queue_version = queue_version + 1", [])?;
+        t.commit()?;
+        self.notify(SubSystem::Playlist);
+
+        if !removes_current {
+            // the next entry may have been among them
+            self.prefetch_next();
+            return Ok(());
+        }
+        // it may have been deleted too
+        let next = next.and_then(|(_, id)| self.position_of(id).ok());
+        match next {
+            Some(pos) => self.start(pos).await,
+            None => {
+                if self.playing != PlaybackState::Stop {
+                    self.playing = PlaybackState::Stop;
+                    self.db.execute("UPDATE state SET paused = false", [])?;
+                    self.notify(SubSystem::Player);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// `move`, moves the entry at a position or the entries in a range so the
+    /// first of them ends up at `to`. A range without an end runs to the end
+    /// of the queue.
+    pub fn move_entries(&mut self, what: PosOrRange, to: Position) -> Result<()> {
+        let positions = match what {
+            PosOrRange::Position(Position::Absolute(pos)) => pos..pos.saturating_add(1),
+            PosOrRange::Range(range) => range.window(),
+            PosOrRange::Position(Position::Relative(_)) => {
+                return Err(Ack::new(ErrorCode::Arg, "Bad song index"))
+                    .with_note(|| format!("move takes a position or range, got: {what:?}"));
+            }
+        };
+        self.move_range(positions, to)
+    }
+
+    /// `moveid`, moves the entry with queue id `id` to `to`
+    pub fn move_id(&mut self, id: QueueId, to: Position) -> Result<()> {
+        let pos = self.position_of(id)?;
+        self.move_range(pos.0..pos.0 + 1, to)
+    }
+
+    /// Takes the entries at `positions` out and puts them back in so the
+    /// first one is at `to`. Like mpd a relative `to` counts from the current
+    /// song as it is once they are taken out. The current song stays current.
+    fn move_range(&mut self, positions: Range<u32>, to: Position) -> Result<()> {
+        let t = self.db.unchecked_transaction()?;
+        let len: u32 = t.query_one("SELECT COUNT(*) FROM queue", [], |row| row.get(0))?;
+        let positions = positions.start..positions.end.min(len);
+        if positions.is_empty() {
+            return Err(Ack::new(ErrorCode::Arg, "Bad song index"))
+                .with_note(|| format!("positions: {positions:?}, queue length is {len}"));
+        }
+        let moved = positions.len() as u32;
+        let to = match to {
+            Position::Absolute(to) => to,
+            Position::Relative(offset) => {
+                let current: Option<u32> =
+                    t.query_one("SELECT current FROM state", [], |row| row.get(0))?;
+                let Some(mut current) = current else {
+                    return Err(Ack::new(ErrorCode::Arg, "No current song").into());
+                };
+                if positions.contains(&current) {
+                    return Err(Ack::new(
+                        ErrorCode::Arg,
+                        "Cannot move current song relative to itself",
+                    )
+                    .into());
+                }
+                if current >= positions.end {
+                    current -= moved;
+                }
+                u32::try_from(current as i64 + offset as i64)
+                    .map_err(|_| Ack::new(ErrorCode::Arg, "Bad song index"))
+                    .with_note(|| format!("position {offset}, current position is {current}"))?
+            }
+        };
+        if to > len - moved {
+            return Err(Ack::new(ErrorCode::Arg, "Bad song index"))
+                .with_note(|| format!("moving {moved} entries to {to}, queue length is {len}"));
+        }
+        if to == positions.start {
+            return Ok(());
+        }
+
+        queue_keys::move_range(&t, positions.clone(), to)?;
+        // the current song moves along. ?1..?2 is the moved range, ?3 where
+        // it goes. Everything else first closes the gap it left and then
+        // makes room at ?3.
+        let new_position = |column: &str| {
+            format!(
+                "CASE
+                    WHEN {column} >= ?1 AND {column} < ?2 THEN ?3 + {column} - ?1
+                    WHEN {column} >= ?2 AND {column} - (?2 - ?1) >= ?3 THEN {column}
+                    WHEN {column} >= ?2 THEN {column} - (?2 - ?1)
+                    WHEN {column} >= ?3 THEN {column} + (?2 - ?1)
+                    ELSE {column}
+                END"
+            )
+        };
+        t.execute(
+            &format!(
+                "UPDATE state SET current = {}, queue_version = queue_version + 1",
+                new_position("current")
+            ),
+            [positions.start, positions.end, to],
+        )?;
+        t.commit()?;
+        self.notify(SubSystem::Playlist);
+        // what plays after the current song may have changed
+        self.prefetch_next();
+        Ok(())
+    }
+
+    /// `shuffle`, puts the entries in `range` (the whole queue if `None`) in
+    /// a random order. Like mpd the current song, while playing, goes first
+    /// and the rest is shuffled after it. Fewer then two entries is a no-op.
+    pub fn shuffle(&mut self, range: Option<mpd_protocol::Range>) -> Result<()> {
+        let t = self.db.unchecked_transaction()?;
+        let len: u32 = t.query_one("SELECT COUNT(*) FROM queue", [], |row| row.get(0))?;
+        let positions = range.map_or(0..len, |range| range.window());
+        if positions.start > len {
+            return Err(Ack::new(ErrorCode::Arg, "Bad song index"))
+                .with_note(|| format!("positions: {positions:?}, queue length is {len}"));
+        }
+        let mut ids: Vec<u32> = t
+            .prepare(
+                "SELECT id FROM queue WHERE position >= ?1 AND position < ?2
+                 ORDER BY position",
+            )?
+            .query_map([positions.start, positions.end], |row| row.get(0))?
+            .try_collect()?;
+        if ids.len() < 2 {
+            return Ok(());
+        }
+        let current: Option<u32> = t
+            .query_one(
+                "SELECT q.id FROM state JOIN queue q ON q.position = state.current",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let mut from = 0;
+        if self.playing != PlaybackState::Stop
+            && let Some(i) = ids.iter().position(|id| Some(*id) == current)
+        {
+            ids.swap(0, i);
+            from = 1;
+        }
+        // Fisher-Yates
+        for i in (from + 1..ids.len()).rev() {
+            let j = from + self.rng.below(i - from + 1);
+            ids.swap(i, j);
+        }
+
+        queue_keys::reorder(&t, positions, &ids)?;
+        t.execute(
+            "UPDATE state SET current = (
+                    SELECT (SELECT COUNT(*) FROM queue_entries WHERE key < e.key)
+                    FROM queue_entries e WHERE id = ?1
+                ), queue_version = queue_version + 1",
+            [current],
+        )?;
+        t.commit()?;
+        self.notify(SubSystem::Playlist);
+        // what plays after the current song may have changed
+        self.prefetch_next();
+        Ok(())
+    }
+
+    /// Positions are counted from the keys, they can not have gaps or
+    /// duplicates. What can still be off, after someone edited the database,
+    /// is a current song past the end of the queue. Returns whether anything
+    /// needed fixing.
+    pub fn verify_queue(&self) -> Result<bool> {
+        let t = self.db.unchecked_transaction()?;
+        let len: u32 = t.query_one("SELECT COUNT(*) FROM queue_entries", [], |row| row.get(0))?;
+        let current: Option<u32> = t.query_one("SELECT current FROM state", [], |row| row.get(0))?;
+        let Some(current) = current.filter(|current| *current >= len) else {
+            return Ok(false);
+        };
+        tracing::warn!("The current song at {current} was not in the queue");
+        t.execute(
+            "UPDATE state SET current = NULL, queue_version = queue_version + 1",
+            [],
+        )?;
+        t.commit()?;
+        Ok(true)
+    }
+
+    /// Counted by the database, the playtime is that of the queue. A song
+    /// by two artists counts both, the `artist` column only joins them.
+    pub fn stats(&self) -> Result<mpd_protocol::Stats> {
+        let (artists, albums, songs, db_playtime, db_update) = self
+            .db
+            .query_one(
+                "SELECT (SELECT COUNT(DISTINCT value) FROM song_tags WHERE tag = 'Artist'),
+                        COUNT(DISTINCT album), COUNT(*), TOTAL(duration),
+                        (SELECT last_db_update FROM state)
+                 FROM songs",
+                [],
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get::<_, f64>(3)?,
+                        row.get::<_, i64>(4)?,
+                    ))
+                },
+            )
+            .wrap_err("Could not count the songs in the database")?;
+        let playtime = self
+            .db
+            .query_one(
+                "SELECT TOTAL(s.duration) FROM queue q JOIN songs s ON s.rowid = q.song",
+                [],
+                |row| row.get::<_, f64>(0),
+            )
+            .wrap_err("Could not add up the length of the queue")?;
+        let seconds = |secs: f64| Duration::try_from_secs_f64(secs).unwrap_or_default();
+        Ok(mpd_protocol::Stats {
+            artists,
+            albums,
+            songs,
+            uptime: Duration::try_from(Timestamp::now().duration_since(self.started_at))
+                .unwrap_or_default(),
+            db_playtime: seconds(db_playtime),
+            db_update: Timestamp::from_second(db_update).unwrap_or_default(),
+            playtime: seconds(playtime),
+        })
+    }
 }
 
 #[derive(Deserialize, Serialize, Hash, Default)]
@@ -431,9 +1157,20 @@ pub struct Song {
     pub track: Option<u8>,
     pub name: Option<String>,
     pub genre: Option<String>,
+    /// Every artist and genre, `artist` and `genre` hold them joined. Empty
+    /// when not loaded.
+    #[serde(skip)]
+    pub artists: Vec<String>,
+    #[serde(skip)]
+    pub genres: Vec<String>,
     pub mood: Option<String>,
     pub date: Option<String>,
     pub original_date: Option<String>,
+    /// Parsed from `date` at scan time, for sorting and filtering
+    #[serde(skip)]
+    pub parsed_date: Option<Date>,
+    #[serde(skip)]
+    pub parsed_original_date: Option<Date>,
     pub composer: Option<String>,
     pub composer_sort: Option<String>,
     pub performer: Option<String>,
@@ -449,6 +1186,8 @@ pub struct Song {
     pub disc: Option<u8>,
     pub label: Option<String>,
     pub playtime: Duration,
+    #[serde(serialize_with = "response_format::option_audio_params")]
+    pub format: Option<AudioParams>,
 
     pub musicbrainz_artist_id: Option<String>,
     pub musicbrainz_album_id: Option<String>,
@@ -459,23 +1198,248 @@ pub struct Song {
     pub musicbrainz_work_id: Option<String>,
 }
 
+fn queue_range(
+    db: &Connection,
+    positions: Range<u32>,
+) -> Result<mpd_protocol::QueueInfo> {
+    let mut stmt = db.prepare_cached(&format!(
+        "SELECT q.id, q.position, q.song, q.prio, q.range_start, q.range_end, {SONG_COLUMNS}
+         FROM queue q
+         JOIN songs s ON s.rowid = q.song
+         WHERE q.position >= ?1 AND q.position < ?2
+         ORDER BY q.position"
+    ))?;
+
+    let songs = stmt
+        .query_and_then([positions.start, positions.end], |row| {
+            let queue_id: u32 = row.get("id")?;
+            let position: u32 = row.get("position")?;
+            let mut song = song_from_row(row)?;
+            multi_value::load(db, row.get("song")?, &mut song)?;
+            let mut entry =
+                QueueEntry::from_song(song, Some(QueuePos(position)), Some(QueueId(queue_id)));
+            (entry.prio, entry.range) = prio_and_range(row)?;
+            Ok::<_, Report>(entry)
+        })?
+        .collect::<Result<_, _>>()?;
+
+    Ok(mpd_protocol::QueueInfo(songs))
+}
+
+/// The current song moves along with the queue entries from position `from`
+/// on, which moved `by` places. Their keys stay as they are, see
+/// [`queue_keys`].
+pub(crate) fn shift_current(db: &Connection, from: u32, by: i64) -> rusqlite::Result<()> {
+    db.execute(
+        "UPDATE state SET current = current + ?2 WHERE current >= ?1",
+        rusqlite::params![from, by],
+    )?;
+    Ok(())
+}
+
+pub(crate) fn song_by_path(db: &Connection, path: &Utf8Path) -> Result<Option<Song>> {
+    let Some((rowid, mut song)) = db
+        .query_one(
+            &format!("SELECT rowid, {SONG_COLUMNS} FROM songs WHERE path = ?1"),
+            [crate::scan::normalize_path(path).as_str()],
+            |row| Ok((row.get::<_, u32>("rowid")?, song_from_row(row)?)),
+        )
+        .optional()?
+    else {
+        return Ok(None);
+    };
+    multi_value::load(db, rowid, &mut song)?;
+    Ok(Some(song))
+}
+
+/// Every column of `songs` that [`song_from_row`] reads. The names do not
+/// clash with those of `queue`, so they can be selected from a join too.
+pub(crate) const SONG_COLUMNS: &str = "path, mtime, generation, play_count, skip_count,
+    date_added, duration, title, artist, artist_sort, album, album_sort, album_artist,
+    album_artist_sort, title_sort, track, name, genre, mood, date, original_date, composer,
+    composer_sort, performer, conductor, work, ensemble, movement, movement_number,
+    show_movement, location, grouping, comment, disc, label, sample_rate, bit_depth, channels,
+    musicbrainz_artist_id, musicbrainz_album_id, musicbrainz_album_artist_id,
+    musicbrainz_track_id, musicbrainz_releasegroup_id, musicbrainz_release_track_id,
+    musicbrainz_work_id";
+
+/// A song as stored, without the values of multi-valued tags. Those come
+/// from [`multi_value::load`].
+pub(crate) fn song_from_row(row: &rusqlite::Row) -> rusqlite::Result<Song> {
+    let (mtime, date_added) = mtime_and_added(row)?;
+    // a tag can hold any number, those that do not fit are as good as absent
+    let small = |column: &str| {
+        row.get::<_, Option<i64>>(column)
+            .map(|n| n.and_then(|n| u8::try_from(n).ok()))
+    };
+    Ok(Song {
+        path: row.get::<_, String>("path")?.into(),
+        mtime,
+        generation: row.get::<_, Option<u64>>("generation")?.unwrap_or_default(),
+        play_count: row.get::<_, Option<u32>>("play_count")?.unwrap_or_default(),
+        skip_count: row.get::<_, Option<u32>>("skip_count")?.unwrap_or_default(),
+        date_added,
+        title: row.get("title")?,
+        artist: row.get("artist")?,
+        artist_sort: row.get("artist_sort")?,
+        album: row.get("album")?,
+        album_sort: row.get("album_sort")?,
+        album_artist: row.get("album_artist")?,
+        album_artist_sort: row.get("album_artist_sort")?,
+        title_sort: row.get("title_sort")?,
+        track: small("track")?,
+        name: row.get("name")?,
+        genre: row.get("genre")?,
+        mood: row.get("mood")?,
+        date: row.get("date")?,
+        original_date: row.get("original_date")?,
+        composer: row.get("composer")?,
+        composer_sort: row.get("composer_sort")?,
+        performer: row.get("performer")?,
+        conductor: row.get("conductor")?,
+        work: row.get("work")?,
+        ensemble: row.get("ensemble")?,
+        movement: row.get("movement")?,
+        movement_number: row.get("movement_number")?,
+        show_movement: row.get("show_movement")?,
+        location: row.get("location")?,
+        grouping: row.get("grouping")?,
+        comment: row.get("comment")?,
+        disc: small("disc")?,
+        label: row.get("label")?,
+        playtime: row
+            .get::<_, Option<f64>>("duration")?
+            .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+            .unwrap_or_default(),
+        format: audio_format(row)?,
+        musicbrainz_artist_id: row.get("musicbrainz_artist_id")?,
+        musicbrainz_album_id: row.get("musicbrainz_album_id")?,
+        musicbrainz_album_artist_id: row.get("musicbrainz_album_artist_id")?,
+        musicbrainz_track_id: row.get("musicbrainz_track_id")?,
+        musicbrainz_releasegroup_id: row.get("musicbrainz_releasegroup_id")?,
+        musicbrainz_release_track_i: row.get("musicbrainz_release_track_id")?,
+        musicbrainz_work_id: row.get("musicbrainz_work_id")?,
+        ..Default::default()
+    })
+}
+
+/// From the `mtime` and `date_added` columns, the epoch if they can not be
+/// read
+pub(crate) fn mtime_and_added(row: &rusqlite::Row) -> rusqlite::Result<(Timestamp, Timestamp)> {
+    let mtime = Timestamp::from_second(row.get("mtime")?).unwrap_or_default();
+    // sqlite's CURRENT_TIMESTAMP, which is in UTC
+    let added = row
+        .get::<_, Option<String>>("date_added")?
+        .and_then(|added| jiff::civil::DateTime::strptime("%Y-%m-%d %H:%M:%S", added).ok())
+        .and_then(|added| added.to_zoned(jiff::tz::TimeZone::UTC).ok())
+        .map(|added| added.timestamp())
+        .unwrap_or_default();
+    Ok((mtime, added))
+}
+
+/// From the `prio`, `range_start` and `range_end` columns of the queue. A
+/// priority of zero is the default and a range of the whole song is none
+/// at all, neither is shown to clients.
+pub(crate) fn prio_and_range(
+    row: &rusqlite::Row,
+) -> rusqlite::Result<(Option<u8>, Option<FloatRange>)> {
+    let prio = row.get::<_, Option<u8>>("prio")?.filter(|prio| *prio != 0);
+    let range = FloatRange::new(row.get("range_start")?, row.get("range_end")?);
+    Ok((prio, Some(range).filter(|range| !range.is_whole_song())))
+}
+
+/// From the `sample_rate`, `bit_depth` and `channels` columns
+pub(crate) fn audio_format(row: &rusqlite::Row) -> rusqlite::Result<Option<AudioParams>> {
+    let sample_rate = row.get::<_, Option<u32>>("sample_rate")?;
+    let bits = row.get("bit_depth")?;
+    let channels = row.get::<_, Option<u16>>("channels")?;
+    Ok(Option::zip(
+        sample_rate.and_then(SampleRate::new),
+        channels.and_then(ChannelCount::new),
+    )
+    .map(|(samplerate, channels)| AudioParams {
+        samplerate,
+        bits,
+        channels,
+    }))
+}
+
+/// Stored playlists outlive the files they point to, those entries are
+/// skipped unless the playlist gave them a title. Positions only count the
+/// remaining entries.
+fn playlist_entries(
+    db: &Connection,
+    name: &PlaylistName,
+    playlist: &[PlaylistEntry],
+) -> Result<Vec<QueueEntry>> {
+    let mut entries = Vec::new();
+    for entry in playlist {
+        let song = match (song_by_path(db, &entry.path)?, &entry.title) {
+            (Some(song), _) => song,
+            // streams for example are never in the database
+            (None, Some(title)) => Song {
+                path: entry.path.clone(),
+                title: Some(title.clone()),
+                playtime: entry.duration.unwrap_or_default(),
+                ..Default::default()
+            },
+            (None, None) => {
+                tracing::warn!(
+                    "Skipping {} in playlist {name:?}, it is not in the database",
+                    entry.path
+                );
+                continue;
+            }
+        };
+        // stored playlists have no queue ids
+        let pos = QueuePos(entries.len() as u32);
+        entries.push(QueueEntry::from_song(song, Some(pos), None));
+    }
+    Ok(entries)
+}
+
+/// Values differing only in case are listed once, in
+/// [`collation`] order. Multi-valued tags list each value on its own.
+pub(crate) fn list_tag(db: &Connection, tag_to_list: &Tag) -> Result<Vec<String>> {
+    let s = tag_to_list.to_string();
+    if multi_value::TAGS.contains(tag_to_list) {
+        return Ok(multi_value::list(db, *tag_to_list)?
+            .into_iter()
+            .map(|value| format!("{s}: {value}"))
+            .collect());
+    }
+    let column = s.to_lowercase();
+    let collation = collation::NAME;
+    let mut stmt = db.prepare(&format!(
+        "SELECT DISTINCT {column} COLLATE {collation} FROM songs ORDER BY 1"
+    ))?;
+    Ok(stmt
+        .query_and_then([], |row| row.get::<_, String>(0))?
+        .map(|result| result.map(|output| format!("{s}: {output}")))
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
 impl QueueEntry {
-    fn from_song(s: Song, pos: QueuePos, id: QueueId) -> Self {
+    /// The way every command lists a song, tags the song does not have are
+    /// left out. Songs outside the queue have no `pos` and `id`.
+    pub fn from_song(s: Song, pos: Option<QueuePos>, id: Option<QueueId>) -> Self {
         QueueEntry {
             path: s.path,
             last_modified: s.mtime,
             added: s.date_added,
-            format: AudioParams::default(), // TODO:
-            artist: s.artist.unwrap_or_default(),
-            album_artist: s.album_artist.unwrap_or_default(),
-            title: s.title.unwrap_or_default(),
-            album: s.album.unwrap_or_default(),
-            track: s.track.unwrap_or_default() as u64,
-            date: s.date.unwrap_or_default(),
-            genre: s.genre,
-            label: s.label.unwrap_or_default(),
+            format: s.format,
+            artist: s.tag_values(Tag::Artist).map(str::to_owned).collect(),
+            album_artist: s.album_artist,
+            title: s.title,
+            album: s.album,
+            track: s.track.map(u64::from),
+            date: s.date,
+            genre: s.tag_values(Tag::Genre).map(str::to_owned).collect(),
+            label: s.label,
             disc: s.disc.map(|n| n as u64),
             duration: s.playtime,
+            range: None,
+            prio: None,
             pos,
             id,
         }
@@ -483,3 +1447,853 @@ impl QueueEntry {
 }
 
 // TODO: use in-memory database for tests, pass connection into system::new instead of creating in there. also disable scanning?
+
+/// A system on `db` with a player that needs no audio hardware and a music
+/// dir that does not exist, for tests that only need the database
+#[cfg(test)]
+pub(crate) fn empty_system(db: Connection) -> System {
+    use crate::player::device::Silent;
+
+    System::with_parts(
+        db,
+        |volume, paused| Player::with_device(volume, paused, Silent),
+        Utf8PathBuf::from("/nonexistent"),
+        None,
+    )
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::device::Silent;
+
+    #[test]
+    fn system_can_move_between_threads() {
+        fn send<T: Send>() {}
+        send::<System>();
+    }
+
+    #[test]
+    fn subscribers_get_every_notification() {
+        let mut system = empty_system(Connection::open_in_memory().unwrap());
+        let mut changes = system.subscribe();
+        let mut idle = system.idle(vec![SubSystem::Player]);
+        system.set_repeat(true).unwrap();
+        system.notify(SubSystem::Player);
+
+        assert_eq!(changes.try_recv(), Ok(SubSystem::Options));
+        assert_eq!(changes.try_recv(), Ok(SubSystem::Player));
+        assert!(changes.try_recv().is_err());
+        // idlers only hear about what they wait for
+        assert_eq!(idle.try_recv(), Ok(SubSystem::Player));
+    }
+
+    #[tokio::test]
+    async fn queue_changes_and_scans_notify_and_left_idlers_are_dropped() {
+        let mut system = empty_system(Connection::open_in_memory().unwrap());
+        system
+            .db
+            .execute("INSERT INTO songs (path, mtime) VALUES ('a.flac', 0)", [])
+            .unwrap();
+        let mut playlist = system.idle(vec![SubSystem::Playlist]);
+        let mut database = system.idle(vec![SubSystem::Update, SubSystem::Database]);
+        drop(system.idle(vec![SubSystem::Playlist]));
+
+        system.add_to_queue(Utf8Path::new("a.flac"), &None).unwrap();
+        assert_eq!(system.idlers[&SubSystem::Playlist].len(), 1);
+        system.clear().await.unwrap();
+        assert_eq!(playlist.try_recv(), Ok(SubSystem::Playlist));
+        assert_eq!(playlist.try_recv(), Ok(SubSystem::Playlist));
+        assert!(playlist.try_recv().is_err());
+
+        // the music dir does not exist, the song leaves the database
+        system.rescan().await.unwrap();
+        assert_eq!(database.try_recv(), Ok(SubSystem::Update));
+        assert_eq!(database.try_recv(), Ok(SubSystem::Update));
+        assert_eq!(database.try_recv(), Ok(SubSystem::Database));
+        system.rescan().await.unwrap();
+        assert_eq!(database.try_recv(), Ok(SubSystem::Update));
+        assert_eq!(database.try_recv(), Ok(SubSystem::Update));
+        assert!(database.try_recv().is_err());
+    }
+
+    #[test]
+    fn playlists_skip_missing_songs() {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrations::run(&mut db).unwrap();
+        db.execute(
+            "INSERT INTO songs (path, mtime, title) VALUES ('good.flac', '0', 'Good')",
+            [],
+        )
+        .unwrap();
+
+        let name = PlaylistName("mix".to_owned());
+        let playlist = playlist::parse_m3u(
+            "deleted.flac\n\
+            good.flac\n\
+            #EXTINF:-1,Radio Paradise\n\
+            http://stream.radioparadise.com/flac\n",
+        );
+        let entries = playlist_entries(&db, &name, &playlist).unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].path, "good.flac");
+        assert_eq!(entries[0].title.as_deref(), Some("Good"));
+        assert_eq!(entries[0].pos, Some(QueuePos(0)));
+        assert_eq!(entries[0].id, None);
+        assert_eq!(entries[1].title.as_deref(), Some("Radio Paradise"));
+        assert_eq!(entries[1].pos, Some(QueuePos(1)));
+    }
+
+    #[test]
+    fn listall_puts_songs_before_subdirectories() {
+        let system = empty_system(Connection::open_in_memory().unwrap());
+        system
+            .db
+            .execute_batch(
+                "INSERT INTO songs (path, mtime) VALUES
+                    ('a/b/1.flac', '0'), ('a/2.flac', '0'), ('c/3.flac', '0'), ('4.flac', '0');
+                INSERT INTO directories (path, parent, mtime) VALUES
+                    ('a', '', '2024-01-01T00:00:00Z'),
+                    ('a/b', 'a', '2024-01-02T00:00:00Z'),
+                    ('c', '', '2024-01-03T00:00:00Z');",
+            )
+            .unwrap();
+
+        let file = |path: &str| BrowseEntry::file(path.into());
+        let dir = |path: &str| BrowseEntry::Directory {
+            path: path.into(),
+            last_modified: None,
+        };
+        assert_eq!(
+            system.list_all_in(Utf8Path::new("")).unwrap(),
+            [
+                file("4.flac"),
+                dir("a"),
+                file("a/2.flac"),
+                dir("a/b"),
+                file("a/b/1.flac"),
+                dir("c"),
+                file("c/3.flac"),
+            ]
+        );
+        assert_eq!(
+            system.list_all_in(Utf8Path::new("a")).unwrap(),
+            [file("a/2.flac"), dir("a/b"), file("a/b/1.flac")]
+        );
+
+        assert!(system.is_directory(Utf8Path::new("a/b")).unwrap());
+        assert!(!system.is_directory(Utf8Path::new("a/2.flac")).unwrap());
+        let subdirs = system.subdirectories(Utf8Path::new("")).unwrap();
+        assert_eq!(subdirs.len(), 2);
+        assert_eq!(
+            subdirs[1],
+            BrowseEntry::Directory {
+                path: "c".into(),
+                last_modified: Some("2024-01-03T00:00:00Z".parse().unwrap()),
+            }
+        );
+        let songs = system.songs_in(Utf8Path::new("a")).unwrap();
+        assert_eq!(songs.len(), 1);
+        assert_eq!(songs[0].path, "a/2.flac");
+    }
+
+    #[test]
+    fn a_new_database_has_empty_responses() {
+        let system = empty_system(Connection::open_in_memory().unwrap());
+
+        let status = system.status().unwrap();
+        assert_eq!(status.playlistlength, 0);
+        assert_eq!((status.song, status.songid), (None, None));
+        assert_eq!((status.nextsong, status.nextsongid), (None, None));
+        assert!(response_format::to_string(&status).is_ok());
+
+        let current = system.current_song().unwrap();
+        assert!(current.is_none());
+        assert_eq!(response_format::to_string(&current).unwrap(), "");
+
+        let queue = system.queue().unwrap();
+        assert!(queue.0.is_empty());
+        assert_eq!(response_format::to_string(&queue).unwrap(), "");
+    }
+
+    #[test]
+    fn the_volume_is_restored_on_start() {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrations::run(&mut db).unwrap();
+        db.execute("UPDATE state SET volume = 40", []).unwrap();
+
+        let mut restored = None;
+        let system = System::with_parts(
+            db,
+            |volume, paused| {
+                restored = Some(volume);
+                Player::with_device(volume, paused, Silent)
+            },
+            Utf8PathBuf::from("/nonexistent"),
+            None,
+        )
+        .unwrap();
+        assert_eq!(restored, Some(0.4));
+        assert_eq!(system.status().unwrap().volume.get(), 40);
+    }
+
+    #[test]
+    fn a_missing_state_row_is_recreated() {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrations::run(&mut db).unwrap();
+        db.execute("DELETE FROM state", []).unwrap();
+
+        let system = empty_system(db);
+        assert!(system.status().is_ok());
+        assert!(system.current_song().unwrap().is_none());
+    }
+
+    #[test]
+    fn stats_count_every_artist_of_a_song() {
+        let system = empty_system(Connection::open_in_memory().unwrap());
+        system
+            .db
+            .execute_batch(
+                "INSERT INTO songs (path, mtime, artist, album) VALUES
+                    ('a.flac', '0', 'A; B', 'X'), ('b.flac', '0', 'A', 'X'),
+                    ('c.flac', '0', 'B', 'Y');",
+            )
+            .unwrap();
+        for (song, artist) in [(1, "A; B"), (2, "A"), (3, "B")] {
+            let artists = multi_value::split([artist]);
+            multi_value::store(&system.db, song, Tag::Artist, &artists).unwrap();
+        }
+
+        // 'A; B' is not a third artist
+        let stats = system.stats().unwrap();
+        assert_eq!((stats.artists, stats.albums, stats.songs), (2, 2, 3));
+    }
+
+    #[test]
+    fn the_first_added_song_becomes_current() {
+        let mut system = empty_system(Connection::open_in_memory().unwrap());
+        system
+            .db
+            .execute("INSERT INTO songs (path, mtime) VALUES ('a.flac', '0')", [])
+            .unwrap();
+        let relative = Some(Position::Relative(0));
+        assert!(system.add_to_queue(Utf8Path::new("a.flac"), &relative).is_err());
+
+        let id = system.add_to_queue(Utf8Path::new("a.flac"), &None).unwrap();
+        assert_eq!(system.current_song().unwrap().unwrap().id, Some(id));
+        assert_eq!(system.status().unwrap().songid, Some(id));
+
+        system.clear_queue().unwrap();
+        assert!(system.current_song().unwrap().is_none());
+    }
+
+    #[test]
+    fn adding_many_songs_is_one_queue_change() {
+        let mut system = empty_system(Connection::open_in_memory().unwrap());
+        system
+            .db
+            .execute_batch(
+                "INSERT INTO songs (path, mtime) VALUES
+                    ('a.flac', '0'), ('b.flac', '0'), ('c.flac', '0'), ('d.flac', '0');",
+            )
+            .unwrap();
+        let paths = |names: &[&str]| names.iter().map(Utf8PathBuf::from).collect_vec();
+        system
+            .add_many_to_queue(&paths(&["a.flac", "d.flac"]), None)
+            .unwrap();
+        let version = system.status().unwrap().playlist;
+
+        let ids = system
+            .add_many_to_queue(&paths(&["b.flac", "c.flac"]), Some(Position::Absolute(1)))
+            .unwrap();
+        assert_eq!(system.status().unwrap().playlist, version + 1);
+        let queue = system.queue().unwrap().0;
+        assert_eq!(
+            queue.iter().map(|entry| entry.path.as_str()).collect_vec(),
+            ["a.flac", "b.flac", "c.flac", "d.flac"]
+        );
+        assert_eq!(queue[1].id, Some(ids[0]));
+        assert_eq!(queue[2].id, Some(ids[1]));
+
+        let missing = paths(&["a.flac", "missing.flac"]);
+        assert!(system.add_many_to_queue(&missing, None).is_err());
+        assert_eq!(system.queue().unwrap().0.len(), 4);
+        assert_eq!(system.status().unwrap().playlist, version + 1);
+    }
+
+    #[test]
+    fn add_positions_must_be_within_the_queue() {
+        let mut system = empty_system(Connection::open_in_memory().unwrap());
+        system
+            .db
+            .execute_batch(
+                "INSERT INTO songs (path, mtime) VALUES
+                    ('a.flac', '0'), ('b.flac', '0'), ('c.flac', '0');",
+            )
+            .unwrap();
+        let a = Utf8Path::new("a.flac");
+        let code = |result: Result<QueueId>| ack::find(&result.unwrap_err()).unwrap().code;
+        for _ in 0..3 {
+            system.add_to_queue(a, &None).unwrap();
+        }
+
+        // the length appends
+        let id = system
+            .add_to_queue(Utf8Path::new("b.flac"), &Some(Position::Absolute(3)))
+            .unwrap();
+        assert_eq!(system.queue().unwrap().0[3].id, Some(id));
+
+        let past_the_end = system.add_to_queue(a, &Some(Position::Absolute(5)));
+        assert_eq!(code(past_the_end), ErrorCode::Arg);
+
+        system.db.execute("UPDATE state SET current = 2", []).unwrap();
+        let before_the_start = system.add_to_queue(a, &Some(Position::Relative(-5)));
+        assert_eq!(code(before_the_start), ErrorCode::Arg);
+
+        let missing = system.add_to_queue(Utf8Path::new("missing.flac"), &None);
+        assert_eq!(code(missing), ErrorCode::NoExist);
+        assert_eq!(system.queue().unwrap().0.len(), 4);
+    }
+
+    #[test]
+    fn queue_entries_show_their_priority_and_range() {
+        let mut system = empty_system(Connection::open_in_memory().unwrap());
+        system
+            .db
+            .execute(
+                "INSERT INTO songs (path, mtime) VALUES ('a.flac', 0), ('b.flac', 0)",
+                [],
+            )
+            .unwrap();
+        let a = system.add_to_queue(Utf8Path::new("a.flac"), &None).unwrap();
+        let b = system.add_to_queue(Utf8Path::new("b.flac"), &None).unwrap();
+        system
+            .db
+            .execute(
+                "UPDATE queue_entries SET prio = 7, range_start = 1.5 WHERE id = ?1",
+                [b.0],
+            )
+            .unwrap();
+
+        let queue = system.queue().unwrap();
+        let shown = |entry: &QueueEntry| (entry.prio, entry.range);
+        assert_eq!(shown(&queue.0[0]), (None, None));
+        let set = (Some(7), Some(FloatRange::new(Some(1.5), None)));
+        assert_eq!(shown(&queue.0[1]), set);
+        assert_eq!(shown(&system.song_by_id(b).unwrap().unwrap()), set);
+        assert_eq!(shown(&system.song_by_pos(QueuePos(1)).unwrap().unwrap()), set);
+        assert_eq!(shown(&system.song_by_id(a).unwrap().unwrap()), (None, None));
+    }
+
+    #[test]
+    fn loading_a_playlist_skips_songs_not_in_the_database() {
+        let mut system = empty_system(Connection::open_in_memory().unwrap());
+        system
+            .db
+            .execute_batch(
+                "INSERT INTO songs (path, mtime) VALUES ('a.flac', 0), ('b.flac', 0);
+                INSERT INTO songs (path, mtime) VALUES ('c.flac', 0);",
+            )
+            .unwrap();
+        let name = PlaylistName("mix.m3u".to_owned());
+        let missing = system.load_playlist(&name, None, None).unwrap_err();
+        assert_eq!(ack::find(&missing).unwrap().code, ErrorCode::NoExist);
+
+        let playlist = Playlist {
+            entries: playlist::parse_m3u("a.flac\ngone.flac\nb.flac\nc.flac\n"),
+            last_modified: Timestamp::UNIX_EPOCH,
+        };
+        system.playlists.insert(name.clone(), playlist);
+        system.add_to_queue(Utf8Path::new("c.flac"), &None).unwrap();
+        let mut idle = system.idle(vec![SubSystem::Playlist]);
+
+        // the range counts the missing entry, the position the queue
+        let range = mpd_protocol::Range::new(0, Some(3));
+        let loaded = system
+            .load_playlist(&name, Some(range), Some(Position::Absolute(0)))
+            .unwrap();
+        assert_eq!(loaded.len(), 2);
+        let paths = system.queue().unwrap().0.into_iter().map(|entry| entry.path);
+        assert_eq!(paths.collect_vec(), ["a.flac", "b.flac", "c.flac"]);
+        assert_eq!(idle.try_recv(), Ok(SubSystem::Playlist));
+        let status = system.status().unwrap();
+        assert_eq!(status.lastloadedplaylist, Some(name.clone()));
+
+        let past_the_end = mpd_protocol::Range::new(5, None);
+        let past_the_end = system.load_playlist(&name, Some(past_the_end), None);
+        assert_eq!(ack::find(&past_the_end.unwrap_err()).unwrap().code, ErrorCode::Arg);
+    }
+
+    #[test]
+    fn queue_ids_are_never_reused() {
+        let mut system = empty_system(Connection::open_in_memory().unwrap());
+        system
+            .db
+            .execute("INSERT INTO songs (path, mtime) VALUES ('a.flac', '0')", [])
+            .unwrap();
+        let a = Utf8Path::new("a.flac");
+        system.add_to_queue(a, &None).unwrap();
+        let deleted = system.add_to_queue(a, &None).unwrap();
+        system
+            .db
+            .execute("DELETE FROM queue_entries WHERE id = ?1", [deleted.0])
+            .unwrap();
+
+        let added = system.add_to_queue(a, &None).unwrap();
+        assert!(added.0 > deleted.0);
+        let entry = system.song_by_id(added).unwrap().unwrap();
+        assert_eq!((entry.id, entry.path.as_str()), (Some(added), "a.flac"));
+
+        system.clear_queue().unwrap();
+        assert!(system.add_to_queue(a, &None).unwrap().0 > added.0);
+    }
+
+    #[test]
+    fn playlists_are_listed_by_name_with_their_mtime() {
+        let music_dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mpdhaj-listplaylists-{}", std::process::id()));
+        let playlist_dir = music_dir.join("playlists");
+        std::fs::create_dir_all(&playlist_dir).unwrap();
+        for (name, mtime) in [("Zebra.m3u", 1_700_000_000), ("élan.m3u", 1_600_000_000)] {
+            let path = playlist_dir.join(name);
+            std::fs::write(&path, "song.flac\n").unwrap();
+            let mtime = std::time::UNIX_EPOCH + Duration::new(mtime, 123_456_789);
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(mtime)
+                .unwrap();
+        }
+
+        let system = System::with_parts(
+            Connection::open_in_memory().unwrap(),
+            |volume, paused| Player::with_device(volume, paused, Silent),
+            music_dir.clone(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            response_format::to_string(&system.playlists()).unwrap(),
+            "playlist: élan.m3u\n\
+            Last-Modified: 2020-09-13T12:26:40Z\n\
+            playlist: Zebra.m3u\n\
+            Last-Modified: 2023-11-14T22:13:20Z\n"
+        );
+        std::fs::remove_dir_all(&music_dir).unwrap();
+    }
+
+    #[test]
+    fn replacing_a_playlist_writes_it_and_tells_clients_once() {
+        let music_dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mpdhaj-replaceplaylist-{}", std::process::id()));
+        let playlist_dir = music_dir.join("playlists");
+        std::fs::create_dir_all(&playlist_dir).unwrap();
+        std::fs::write(playlist_dir.join("mix.m3u"), "old.flac\n").unwrap();
+        let mut system = System::with_parts(
+            Connection::open_in_memory().unwrap(),
+            |volume, paused| Player::with_device(volume, paused, Silent),
+            music_dir.clone(),
+            None,
+        )
+        .unwrap();
+        let mut idle = system.idle(vec![SubSystem::StoredPlaylist]);
+
+        let name = PlaylistName("mix.m3u".to_owned());
+        let paths = [Utf8PathBuf::from("a.flac"), Utf8PathBuf::from("b.flac")];
+        system.replace_playlist_with(&name, paths.clone()).unwrap();
+        let entries = |system: &System| {
+            system.playlists[&name]
+                .entries
+                .iter()
+                .map(|entry| entry.path.clone())
+                .collect_vec()
+        };
+        assert_eq!(entries(&system), paths);
+        assert_eq!(idle.try_recv(), Ok(SubSystem::StoredPlaylist));
+        assert!(idle.try_recv().is_err());
+
+        // the watcher seeing the new file changes nothing
+        assert!(!system.reload_playlist(&playlist_dir.join("mix.m3u")));
+        assert!(!system.reload_playlist(&playlist_dir.join(".mix.m3u.tmp")));
+        let reloaded = playlist::load_from_dir(&playlist_dir, &music_dir).unwrap();
+        assert_eq!(reloaded[&name].entries, system.playlists[&name].entries);
+
+        let escape = PlaylistName("../escape.m3u".to_owned());
+        let escape = system.replace_playlist_with(&escape, paths).unwrap_err();
+        assert_eq!(ack::find(&escape).unwrap().code, ErrorCode::Arg);
+        std::fs::remove_dir_all(&music_dir).unwrap();
+    }
+
+    #[test]
+    fn verify_queue_forgets_a_current_song_past_the_end() {
+        let system = empty_system(Connection::open_in_memory().unwrap());
+        system
+            .db
+            .execute_batch(
+                "INSERT INTO queue_entries (id, song, key) VALUES (1, 1, 500), (2, 1, 7), (3, 1, 90);
+                UPDATE state SET current = 5;",
+            )
+            .unwrap();
+
+        // the keys have gaps, the positions never do
+        let order: Vec<(u32, u32)> = system
+            .db
+            .prepare("SELECT id, position FROM queue ORDER BY position")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .try_collect()
+            .unwrap();
+        assert_eq!(order, [(2, 0), (3, 1), (1, 2)]);
+        assert!(system.verify_queue().unwrap());
+        let current: Option<u32> = system
+            .db
+            .query_one("SELECT current FROM state", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(current, None);
+        assert!(!system.verify_queue().unwrap());
+    }
+
+    #[test]
+    fn queue_positions_stay_contiguous() {
+        const SONGS: [&str; 4] = ["a.flac", "b.flac", "c.flac", "d.flac"];
+        let mut system = empty_system(Connection::open_in_memory().unwrap());
+        let add_song = |system: &System, path: &str| {
+            system
+                .db
+                .execute("INSERT INTO songs (path, mtime) VALUES (?1, '0')", [path])
+                .unwrap();
+        };
+        for song in SONGS {
+            add_song(&system, song);
+        }
+
+        // what the queue should look like: ids and the song each plays
+        let mut model: Vec<(QueueId, &str)> = Vec::new();
+        let mut current: Option<usize> = None;
+        let rng = Rng::new(0x2545_f491_4f6c_dd1d);
+        for step in 0..500 {
+            match rng.below(12) {
+                0..=5 => {
+                    let songs = (0..1 + rng.below(3))
+                        .map(|_| SONGS[rng.below(SONGS.len())])
+                        .collect_vec();
+                    let at = rng.below(model.len() + 2);
+                    // one past the end means append
+                    let position = (at <= model.len()).then_some(Position::Absolute(at as u32));
+                    let at = at.min(model.len());
+                    let paths = songs.iter().map(Utf8PathBuf::from).collect_vec();
+                    let ids = system.add_many_to_queue(&paths, position).unwrap();
+                    model.splice(at..at, ids.into_iter().zip(songs.iter().copied()));
+                    current = match current {
+                        None => Some(at),
+                        Some(c) if c >= at => Some(c + songs.len()),
+                        Some(c) => Some(c),
+                    };
+                }
+                6..=8 => {
+                    let song = SONGS[rng.below(SONGS.len())];
+                    let rowid = system
+                        .db
+                        .query_one("SELECT rowid FROM songs WHERE path = ?1", [song], |row| {
+                            row.get(0)
+                        })
+                        .unwrap();
+                    crate::scan::remove_song(&system.db, rowid).unwrap();
+                    add_song(&system, song);
+                    for i in (0..model.len()).rev().filter(|i| model[*i].1 == song) {
+                        model.remove(i);
+                        current = match current {
+                            Some(c) if c == i => None,
+                            Some(c) if c > i => Some(c - 1),
+                            other => other,
+                        };
+                    }
+                }
+                9 | 10 if !model.is_empty() => {
+                    let start = rng.below(model.len());
+                    let end = start + 1 + rng.below(model.len() - start);
+                    let moved = end - start;
+                    let to = rng.below(model.len() - moved + 1);
+                    let what = PosOrRange::Range(mpd_protocol::Range::new(
+                        start as u32,
+                        Some(end as u32),
+                    ));
+                    system
+                        .move_entries(what, Position::Absolute(to as u32))
+                        .unwrap();
+                    let entries = model.drain(start..end).collect_vec();
+                    model.splice(to..to, entries);
+                    current = current.map(|c| match c {
+                        c if (start..end).contains(&c) => to + c - start,
+                        c => {
+                            let c = if c >= end { c - moved } else { c };
+                            if c >= to { c + moved } else { c }
+                        }
+                    });
+                }
+                9 | 10 => {}
+                _ => {
+                    system.clear_queue().unwrap();
+                    model.clear();
+                    current = None;
+                }
+            }
+
+            let queue = system.queue().unwrap().0;
+            let positions = queue.iter().map(|entry| entry.pos.unwrap().0 as usize).collect_vec();
+            assert_eq!(positions, (0..model.len()).collect_vec(), "step {step}");
+            let ids = queue.iter().map(|entry| entry.id.unwrap()).collect_vec();
+            assert_eq!(ids, model.iter().map(|(id, _)| *id).collect_vec(), "step {step}");
+            let status = system.status().unwrap();
+            assert_eq!(status.song, current.map(|c| QueuePos(c as u32)), "step {step}");
+            assert!(!system.verify_queue().unwrap(), "step {step}");
+        }
+    }
+
+    #[test]
+    fn move_keeps_the_current_song_current() {
+        let mut system = empty_system(Connection::open_in_memory().unwrap());
+        let songs = ["a", "b", "c", "d", "e", "f"].map(|name| format!("{name}.flac"));
+        for song in &songs {
+            system
+                .db
+                .execute("INSERT INTO songs (path, mtime) VALUES (?1, 0)", [song])
+                .unwrap();
+        }
+        let paths = songs.iter().map(Utf8PathBuf::from).collect_vec();
+        let ids = system.add_many_to_queue(&paths, None).unwrap();
+        system.db.execute("UPDATE state SET current = 2", []).unwrap();
+        let mut playlist = system.idle(vec![SubSystem::Playlist]);
+        let queued = |system: &System| {
+            let queue = system.queue().unwrap().0;
+            let positions = queue.iter().map(|entry| entry.pos.unwrap().0).collect_vec();
+            assert_eq!(positions, (0..queue.len() as u32).collect_vec());
+            queue
+                .into_iter()
+                .map(|entry| entry.path.as_str().trim_end_matches(".flac").to_owned())
+                .collect_vec()
+        };
+        let current = |system: &System| system.status().unwrap().songid;
+        let range = |start, end| PosOrRange::Range(mpd_protocol::Range::new(start, end));
+
+        // to the very end
+        system
+            .move_entries(range(0, Some(2)), Position::Absolute(4))
+            .unwrap();
+        assert_eq!(queued(&system), ["c", "d", "e", "f", "a", "b"]);
+        assert_eq!(current(&system), Some(ids[2]));
+        assert_eq!(playlist.try_recv(), Ok(SubSystem::Playlist));
+
+        // onto itself changes nothing
+        let before = system.status().unwrap().playlist;
+        system
+            .move_entries(range(1, None), Position::Absolute(1))
+            .unwrap();
+        assert_eq!(queued(&system), ["c", "d", "e", "f", "a", "b"]);
+        assert_eq!(system.status().unwrap().playlist, before);
+        assert!(playlist.try_recv().is_err());
+
+        // +0 is right after the current song
+        system.move_id(ids[0], Position::Relative(1)).unwrap();
+        assert_eq!(queued(&system), ["c", "a", "d", "e", "f", "b"]);
+        // the current song itself
+        system.move_id(ids[2], Position::Absolute(5)).unwrap();
+        assert_eq!(queued(&system), ["a", "d", "e", "f", "b", "c"]);
+        assert_eq!(current(&system), Some(ids[2]));
+        // -0 is right before it, counted without the moved entries
+        let first = PosOrRange::Position(Position::Absolute(0));
+        system.move_entries(first, Position::Relative(0)).unwrap();
+        assert_eq!(queued(&system), ["d", "e", "f", "b", "a", "c"]);
+        assert_eq!(current(&system), Some(ids[2]));
+
+        let before = system.status().unwrap().playlist;
+        for (what, to) in [
+            (range(0, Some(2)), Position::Absolute(5)),
+            (range(6, None), Position::Absolute(0)),
+            (range(4, None), Position::Relative(0)),
+            (PosOrRange::Position(Position::Relative(0)), Position::Absolute(0)),
+        ] {
+            let e = system.move_entries(what, to).unwrap_err();
+            assert_eq!(ack::find(&e).unwrap().code, ErrorCode::Arg, "{what:?} to {to:?}");
+        }
+        assert_eq!(system.status().unwrap().playlist, before);
+    }
+
+    #[test]
+    fn shuffle_keeps_every_entry_and_the_current_song() {
+        let mut system = empty_system(Connection::open_in_memory().unwrap());
+        let mut playlist = system.idle(vec![SubSystem::Playlist]);
+        system.shuffle(None).unwrap();
+        assert!(playlist.try_recv().is_err(), "an empty queue is left alone");
+
+        let songs = ["a", "b", "c", "d", "e", "f", "g", "h"].map(|name| format!("{name}.flac"));
+        for song in &songs {
+            system
+                .db
+                .execute("INSERT INTO songs (path, mtime) VALUES (?1, 0)", [song])
+                .unwrap();
+        }
+        let paths = songs.iter().map(Utf8PathBuf::from).collect_vec();
+        let ids = system.add_many_to_queue(&paths, None).unwrap();
+        system.db.execute("UPDATE state SET current = 5", []).unwrap();
+        let queued = |system: &System| {
+            let queue = system.queue().unwrap().0;
+            let positions = queue.iter().map(|entry| entry.pos.unwrap().0).collect_vec();
+            assert_eq!(positions, (0..queue.len() as u32).collect_vec());
+            queue.into_iter().map(|entry| entry.id.unwrap()).collect_vec()
+        };
+        let range = |start, end| Some(mpd_protocol::Range::new(start, end));
+
+        let before = system.status().unwrap().playlist;
+        system.shuffle(range(3, Some(4))).unwrap();
+        system.shuffle(range(8, None)).unwrap();
+        assert_eq!(queued(&system), ids);
+        assert_eq!(system.status().unwrap().playlist, before);
+        assert!(playlist.try_recv().is_err());
+        let e = system.shuffle(range(9, None)).unwrap_err();
+        assert_eq!(ack::find(&e).unwrap().code, ErrorCode::Arg);
+
+        // the odds of ten shuffles all changing nothing are about zero
+        for _ in 0..10 {
+            system.shuffle(range(2, Some(6))).unwrap();
+            if queued(&system) != ids {
+                break;
+            }
+        }
+        let shuffled = queued(&system);
+        assert_ne!(shuffled, ids);
+        assert_eq!(shuffled[..2], ids[..2]);
+        assert_eq!(shuffled[6..], ids[6..]);
+        let sorted = shuffled.iter().sorted_by_key(|id| id.0).copied().collect_vec();
+        assert_eq!(sorted, ids);
+        assert_eq!(system.status().unwrap().songid, Some(ids[5]));
+        assert_eq!(playlist.try_recv(), Ok(SubSystem::Playlist));
+
+        // while playing the current song goes first
+        system.playing = PlaybackState::Play;
+        system.shuffle(None).unwrap();
+        assert_eq!(queued(&system)[0], ids[5]);
+        assert_eq!(system.status().unwrap().song, Some(QueuePos(0)));
+    }
+
+    #[tokio::test]
+    async fn delete_closes_the_gap_it_leaves() {
+        let mut system = empty_system(Connection::open_in_memory().unwrap());
+        let songs = ["a", "b", "c", "d", "e", "f", "g"].map(|name| format!("{name}.flac"));
+        for song in &songs {
+            system
+                .db
+                .execute("INSERT INTO songs (path, mtime) VALUES (?1, 0)", [song])
+                .unwrap();
+        }
+        let paths = songs.iter().map(Utf8PathBuf::from).collect_vec();
+        let ids = system.add_many_to_queue(&paths, None).unwrap();
+        let mut playlist = system.idle(vec![SubSystem::Playlist]);
+        let queued = |system: &System| {
+            let queue = system.queue().unwrap().0;
+            let positions = queue.iter().map(|entry| entry.pos.unwrap().0).collect_vec();
+            assert_eq!(positions, (0..queue.len() as u32).collect_vec());
+            queue.into_iter().map(|entry| entry.path.to_string()).collect_vec()
+        };
+
+        let range = |start, end| Some(PosOrRange::Range(mpd_protocol::Range::new(start, end)));
+        system.delete_pos_or_range(range(1, Some(3))).await.unwrap();
+        assert_eq!(queued(&system), ["a.flac", "d.flac", "e.flac", "f.flac", "g.flac"]);
+        assert_eq!(playlist.try_recv(), Ok(SubSystem::Playlist));
+        system.delete_id(ids[4]).await.unwrap();
+        assert_eq!(queued(&system), ["a.flac", "d.flac", "f.flac", "g.flac"]);
+        let last = Some(PosOrRange::Position(Position::Absolute(3)));
+        system.delete_pos_or_range(last).await.unwrap();
+        assert_eq!(queued(&system), ["a.flac", "d.flac", "f.flac"]);
+
+        let before = system.status().unwrap().playlist;
+        for bad in [
+            range(3, None),
+            range(2, Some(1)),
+            Some(PosOrRange::Position(Position::Absolute(3))),
+            Some(PosOrRange::Position(Position::Relative(0))),
+            None,
+        ] {
+            let e = system.delete_pos_or_range(bad).await.unwrap_err();
+            assert_eq!(ack::find(&e).unwrap().code, ErrorCode::Arg, "{bad:?}");
+        }
+        let e = system.delete_id(ids[1]).await.unwrap_err();
+        assert_eq!(ack::find(&e).unwrap().code, ErrorCode::NoExist);
+        assert_eq!(system.status().unwrap().playlist, before);
+
+        // the current entry goes, the one after it becomes current
+        assert_eq!(system.status().unwrap().song, Some(QueuePos(0)));
+        system.delete_pos_or_range(range(0, None)).await.unwrap();
+        assert!(queued(&system).is_empty());
+        assert_eq!(system.status().unwrap().song, None);
+    }
+
+    #[tokio::test]
+    async fn clear_stops_the_audio_first() {
+        use crate::player::tests::{FastForward, write_wav};
+        use crate::system::playback::Target;
+
+        let music_dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mpdhaj-clear-{}", std::process::id()));
+        std::fs::create_dir_all(&music_dir).unwrap();
+        write_wav(&music_dir.join("a.wav"), Duration::from_secs(30));
+        write_wav(&music_dir.join("b.wav"), Duration::from_secs(30));
+        let mut system = System::with_parts(
+            Connection::open_in_memory().unwrap(),
+            |volume, paused| Player::with_device(volume, paused, FastForward),
+            music_dir.clone(),
+            None,
+        )
+        .unwrap();
+        let mut events = system.player.take_output_events().unwrap();
+        system
+            .db
+            .execute(
+                "INSERT INTO songs (path, mtime) VALUES ('a.wav', 0), ('b.wav', 0)",
+                [],
+            )
+            .unwrap();
+        system
+            .add_many_to_queue(&["a.wav".into(), "b.wav".into()], None)
+            .unwrap();
+        system.set_playback(Target::Play).await.unwrap();
+        let playing = system.current_source;
+        let mut status = system.player.subscribe();
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            status.wait_for(|status| status.source == playing),
+        )
+        .await
+        .expect("the song should start")
+        .unwrap();
+        let buffered = system.player.buffered();
+        assert!(
+            buffered > Duration::from_secs(10),
+            "most of a song is left, got {buffered:?}"
+        );
+
+        system.clear().await.unwrap();
+        let player = system.player.status();
+        assert_eq!((player.source, player.elapsed), (None, Duration::ZERO));
+        assert_eq!(system.player.buffered(), Duration::ZERO);
+        assert_eq!(system.status().unwrap().state, PlaybackState::Stop);
+
+        // the aborted song ended without anything taking its place
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(system.player.buffered(), Duration::ZERO);
+        assert_eq!(system.player.status().source, None);
+        while let Ok(event) = events.try_recv() {
+            system.handle_output_event(event).await;
+        }
+        assert_eq!(system.current_source, None);
+        assert_eq!(system.status().unwrap().state, PlaybackState::Stop);
+        std::fs::remove_dir_all(&music_dir).unwrap();
+    }
+}
@@ -0,0 +1,350 @@
+//! Who is connected and the per connection state other connections need to
+//! see, like channel subscriptions, message queues and what changed since a
+//! client last idled.
+//!
+//! This is kept out of the [`System`](super::System) mutex, a client waiting
+//! in idle holds on to [`Clients`] without blocking everyone else.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use strum::IntoEnumIterator;
+use tokio::sync::Notify;
+
+use crate::mpd_protocol::{ChannelName, SubSystem};
+
+/// MPD drops messages once a client has this many unread
+pub const MAX_MESSAGES: usize = 64;
+
+/// The only partition there is for now, every client starts in it
+pub const DEFAULT_PARTITION: &str = "default";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ClientId(u64);
+
+impl fmt::Display for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// see <https://mpd.readthedocs.io/en/stable/user.html#permissions-and-passwords>
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Permission {
+    Read,
+    Add,
+    Control,
+    Admin,
+}
+
+impl Permission {
+    /// What clients get without a password
+    pub fn default_set() -> HashSet<Permission> {
+        HashSet::from([
+            Permission::Read,
+            Permission::Add,
+            Permission::Control,
+            Permission::Admin,
+        ])
+    }
+}
+
+#[derive(Debug)]
+pub struct ClientInfo {
+    pub address: Option<SocketAddr>,
+    pub partition: String,
+    pub permissions: HashSet<Permission>,
+    pub subscriptions: HashSet<ChannelName>,
+    /// Oldest first
+    pub messages: VecDeque<(ChannelName, String)>,
+    idle: Idle,
+}
+
+/// Like mpd, changes are remembered from the moment a client connects. An
+/// idle for something that already changed returns right away.
+#[derive(Debug, Default)]
+struct Idle {
+    /// Since the client last heard of a change
+    changed: HashSet<SubSystem>,
+    /// What the client is idling on, empty while it is not
+    waits_for: HashSet<SubSystem>,
+    wake: Arc<Notify>,
+}
+
+impl ClientInfo {
+    fn new(address: Option<SocketAddr>) -> Self {
+        Self {
+            address,
+            partition: String::from(DEFAULT_PARTITION),
+            permissions: Permission::default_set(),
+            subscriptions: HashSet::new(),
+            messages: VecDeque::new(),
+            idle: Idle::default(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    next_id: u64,
+    clients: HashMap<ClientId, ClientInfo>,
+}
+
+/// Registry of connected clients. Cheap to clone, all clones share the same
+/// registry.
+///
+/// The lock is only held inside these methods so it is never held across an
+/// await.
+#[derive(Debug, Clone, Default)]
+pub struct Clients(Arc<Mutex<Registry>>);
+
+impl Clients {
+    fn lock(&self) -> MutexGuard<'_, Registry> {
+        self.0
+            .lock()
+            .expect("no code panics while holding the lock")
+    }
+
+    /// The client is removed again when the returned [`Registration`] drops
+    pub fn register(&self, address: Option<SocketAddr>) -> Registration {
+        let mut registry = self.lock();
+        let id = ClientId(registry.next_id);
+        registry.next_id += 1;
+        registry.clients.insert(id, ClientInfo::new(address));
+        Registration {
+            id,
+            clients: self.clone(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Access the info of a connected client, None if it disconnected
+    pub fn with<T>(&self, id: ClientId, f: impl FnOnce(&mut ClientInfo) -> T) -> Option<T> {
+        self.lock().clients.get_mut(&id).map(f)
+    }
+
+    /// Every channel with at least one subscriber, sorted
+    pub fn channels(&self) -> Vec<ChannelName> {
+        let registry = self.lock();
+        let mut channels: Vec<_> = registry
+            .clients
+            .values()
+            .flat_map(|client| client.subscriptions.iter().cloned())
+            .collect();
+        channels.sort_by(|a, b| a.0.cmp(&b.0));
+        channels.dedup();
+        channels
+    }
+
+    /// Remembers the change for every client, wakes up those idling on it
+    pub fn notify(&self, subsystem: SubSystem) {
+        for client in self.lock().clients.values_mut() {
+            client.idle.changed.insert(subsystem);
+            if client.idle.waits_for.contains(&subsystem) {
+                client.idle.wake.notify_one();
+            }
+        }
+    }
+
+    /// How many clients are idling on `subsystem`
+    pub fn idling(&self, subsystem: SubSystem) -> usize {
+        self.lock()
+            .clients
+            .values()
+            .filter(|client| client.idle.waits_for.contains(&subsystem))
+            .count()
+    }
+
+    /// Queues the message for every subscriber, returns how many there were.
+    /// Subscribers with a full queue miss the message.
+    pub fn send_message(&self, channel: &ChannelName, message: &str) -> usize {
+        let mut registry = self.lock();
+        let mut subscribers = 0;
+        for client in registry.clients.values_mut() {
+            if !client.subscriptions.contains(channel) {
+                continue;
+            }
+            subscribers += 1;
+            if client.messages.len() < MAX_MESSAGES {
+                client
+                    .messages
+                    .push_back((channel.clone(), message.to_owned()));
+            }
+        }
+        subscribers
+    }
+}
+
+/// A connected client, unregisters on drop
+#[derive(Debug)]
+pub struct Registration {
+    id: ClientId,
+    clients: Clients,
+}
+
+impl Registration {
+    pub fn id(&self) -> ClientId {
+        self.id
+    }
+
+    /// Info of this client
+    pub fn with<T>(&self, f: impl FnOnce(&mut ClientInfo) -> T) -> T {
+        self.clients
+            .with(self.id, f)
+            .expect("a client is registered until its Registration drops")
+    }
+
+    pub fn clients(&self) -> &Clients {
+        &self.clients
+    }
+
+    /// Waits for one of `subsystems` to change, any of them if it is empty.
+    /// Returns what changed, which the client will not hear about again.
+    /// Stop waiting with [`Self::end_idle`].
+    pub async fn idle(&self, subsystems: Vec<SubSystem>) -> Vec<SubSystem> {
+        let wake = self.with(|info| {
+            info.idle.waits_for = if subsystems.is_empty() {
+                SubSystem::iter().collect()
+            } else {
+                subsystems.into_iter().collect()
+            };
+            Arc::clone(&info.idle.wake)
+        });
+        loop {
+            let changed = self.with(|info| {
+                let idle = &mut info.idle;
+                let changed = SubSystem::iter()
+                    .filter(|s| idle.changed.contains(s) && idle.waits_for.contains(s))
+                    .collect::<Vec<_>>();
+                if !changed.is_empty() {
+                    // mpd forgets what the client did not wait for too
+                    idle.changed.clear();
+                    idle.waits_for.clear();
+                }
+                changed
+            });
+            if !changed.is_empty() {
+                return changed;
+            }
+            wake.notified().await;
+        }
+    }
+
+    /// Once the client sends `noidle`, or anything else
+    pub fn end_idle(&self) {
+        self.with(|info| info.idle.waits_for.clear());
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.clients.lock().clients.remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt;
+
+    use super::*;
+
+    fn channel(name: &str) -> ChannelName {
+        ChannelName(name.to_owned())
+    }
+
+    #[tokio::test]
+    async fn concurrent_clients() {
+        let clients = Clients::default();
+        let subscribed = Arc::new(tokio::sync::Barrier::new(5));
+        let sent = Arc::new(tokio::sync::Barrier::new(5));
+
+        let tasks: Vec<_> = (0..4)
+            .map(|i| {
+                let clients = clients.clone();
+                let (subscribed, sent) = (subscribed.clone(), sent.clone());
+                tokio::spawn(async move {
+                    let client = clients.register(None);
+                    client.with(|info| info.subscriptions.insert(channel(&format!("ch{}", i % 2))));
+                    subscribed.wait().await;
+                    sent.wait().await;
+                    client.with(|info| info.messages.drain(..).count())
+                })
+            })
+            .collect();
+
+        subscribed.wait().await;
+        assert_eq!(clients.len(), 4);
+        assert_eq!(clients.channels(), [channel("ch0"), channel("ch1")]);
+        assert_eq!(clients.send_message(&channel("ch0"), "hi"), 2);
+        assert_eq!(clients.send_message(&channel("nobody"), "hi"), 0);
+        sent.wait().await;
+
+        let mut received = Vec::new();
+        for task in tasks {
+            received.push(task.await.unwrap());
+        }
+        assert_eq!(received, [1, 0, 1, 0]);
+        assert!(clients.is_empty());
+        assert!(clients.channels().is_empty());
+    }
+
+    #[test]
+    fn ids_are_not_reused() {
+        let clients = Clients::default();
+        let first = clients.register(None).id();
+        let second = clients.register(None);
+        assert_ne!(first, second.id());
+        assert_eq!(clients.with(first, |_| ()), None);
+    }
+
+    #[test]
+    fn full_queues_drop_messages() {
+        let clients = Clients::default();
+        let client = clients.register(None);
+        client.with(|info| info.subscriptions.insert(channel("ch")));
+        for i in 0..MAX_MESSAGES + 1 {
+            clients.send_message(&channel("ch"), &i.to_string());
+        }
+        client.with(|info| {
+            assert_eq!(info.messages.len(), MAX_MESSAGES);
+            assert_eq!(
+                info.messages.back().unwrap().1,
+                (MAX_MESSAGES - 1).to_string()
+            );
+        });
+    }
+
+    #[tokio::test]
+    async fn idle_returns_what_changed_since_the_last_idle() {
+        let clients = Clients::default();
+        let client = clients.register(None);
+        clients.notify(SubSystem::Player);
+        clients.notify(SubSystem::Mixer);
+        let changed = client.idle(vec![SubSystem::Mixer, SubSystem::Player]).await;
+        assert_eq!(changed, [SubSystem::Player, SubSystem::Mixer]);
+        assert_eq!(clients.idling(SubSystem::Player), 0);
+
+        let idle = client.idle(vec![SubSystem::Options]);
+        let mut idle = std::pin::pin!(idle);
+        assert!(futures::poll!(idle.as_mut()).is_pending());
+        assert_eq!(clients.idling(SubSystem::Options), 1);
+        clients.notify(SubSystem::Player);
+        assert!(futures::poll!(idle.as_mut()).is_pending());
+        clients.notify(SubSystem::Options);
+        assert_eq!(idle.await, [SubSystem::Options]);
+
+        client.idle(Vec::new()).now_or_never();
+        assert_eq!(clients.idling(SubSystem::Sticker), 1);
+        client.end_idle();
+        assert_eq!(clients.idling(SubSystem::Sticker), 0);
+    }
+}
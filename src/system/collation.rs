@@ -0,0 +1,148 @@
+//! Ordering and comparing tag values the way people expect.
+//!
+//! SQLite compares strings byte by byte, which puts "Édith Piaf" after "ZZ
+//! Top" and treats "the beatles" and "The Beatles" as different artists. We
+//! register [`NAME`] on the connection so SQL and Rust code order and merge
+//! tag values the same way.
+//!
+//! Searching is looser still, there accents do not matter either so
+//! "beyonce" finds "Beyoncé". [`UNACCENT`] gives SQL the same comparison key.
+
+use std::cmp::Ordering;
+
+use rusqlite::Connection;
+use rusqlite::functions::FunctionFlags;
+use unicode_normalization::{UnicodeNormalization, char::is_combining_mark};
+
+/// Use as `COLLATE unicode_nocase` in queries
+pub(crate) const NAME: &str = "unicode_nocase";
+
+/// Use as `unaccent(column) LIKE unaccent(?1)`
+pub(crate) const UNACCENT: &str = "unaccent";
+
+pub(crate) fn register(db: &Connection) -> rusqlite::Result<()> {
+    db.create_collation(NAME, compare)?;
+    db.create_scalar_function(
+        UNACCENT,
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| Ok(ctx.get::<Option<String>>(0)?.map(|s| search_key(&s))),
+    )
+}
+
+/// Lowercase with accents, a different accent still makes a different value
+fn folded(s: &str) -> impl Iterator<Item = char> {
+    s.nfd().flat_map(char::to_lowercase)
+}
+
+/// Lowercase without accents, used to put É right next to E
+fn base_letters(s: &str) -> impl Iterator<Item = char> {
+    folded(s).filter(|c| !is_combining_mark(*c))
+}
+
+/// Orders on the letters first, only when those are the same do the accents
+/// matter. Case never matters.
+pub(crate) fn compare(a: &str, b: &str) -> Ordering {
+    base_letters(a)
+        .cmp(base_letters(b))
+        .then_with(|| folded(a).cmp(folded(b)))
+}
+
+/// Equal under [`compare`]
+pub(crate) fn eq(a: &str, b: &str) -> bool {
+    folded(a).eq(folded(b))
+}
+
+/// Lowercase without accents, compatibility characters (like ligatures and
+/// full width letters) are replaced by their plain form
+fn search_key(s: &str) -> String {
+    s.nfkd()
+        .flat_map(char::to_lowercase)
+        .filter(|c| !is_combining_mark(*c))
+        .collect()
+}
+
+/// Equal ignoring case and accents
+pub(crate) fn search_eq(value: &str, needle: &str) -> bool {
+    search_key(value) == search_key(needle)
+}
+
+/// Whether `needle` is part of `haystack` ignoring case and accents
+pub(crate) fn search_contains(haystack: &str, needle: &str) -> bool {
+    search_key(haystack).contains(&search_key(needle))
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use super::*;
+    use crate::mpd_protocol::Tag;
+    use crate::system::list_tag;
+
+    fn library(artists: &[&str]) -> Connection {
+        let db = Connection::open_in_memory().unwrap();
+        register(&db).unwrap();
+        db.execute("CREATE TABLE songs (artist TEXT)", []).unwrap();
+        for artist in artists {
+            db.execute("INSERT INTO songs (artist) VALUES (?1)", [artist])
+                .unwrap();
+        }
+        db
+    }
+
+    #[test]
+    fn accents_sort_next_to_their_base_letter() {
+        let mut artists = vec!["ZZ Top", "Édith Piaf", "abba", "Eagles", "Edith Piaf"];
+        artists.sort_by(|a, b| compare(a, b));
+        assert_eq!(
+            artists,
+            ["abba", "Eagles", "Edith Piaf", "Édith Piaf", "ZZ Top"]
+        );
+    }
+
+    #[test]
+    fn case_is_ignored_but_accents_are_not() {
+        assert!(eq("The Beatles", "the beatles"));
+        // precomposed and decomposed É
+        assert!(eq("\u{c9}dith", "E\u{301}dith"));
+        assert!(!eq("Édith", "Edith"));
+    }
+
+    #[test]
+    fn search_ignores_case_and_accents() {
+        assert!(search_contains("The Beatles", "BEAT"));
+        assert!(search_contains("E\u{301}dith Piaf", "\u{c9}dith"));
+        assert!(search_contains("Édith Piaf", "edith"));
+        assert!(search_eq("Beyoncé", "beyonce"));
+        assert!(search_eq("Sigur Rós", "sigur ros"));
+        assert!(search_eq("\u{fb01}n", "fin"));
+        assert!(!search_eq("Beyoncé", "beyonc"));
+    }
+
+    #[test]
+    fn unaccent_in_sql() {
+        let db = library(&["Beyoncé", "Björk", "Bon Iver"]);
+        let search = |needle: &str| -> Vec<String> {
+            db.prepare("SELECT artist FROM songs WHERE unaccent(artist) LIKE unaccent(?1)")
+                .unwrap()
+                .query_map([needle], |row| row.get(0))
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap()
+        };
+        assert_eq!(search("beyonce"), ["Beyoncé"]);
+        assert_eq!(search("%BJO%"), ["Björk"]);
+    }
+
+    #[test]
+    fn list_merges_and_orders() {
+        let db = library(&["ZZ Top", "the beatles", "Édith Piaf", "The Beatles", "abba"]);
+        let listed = list_tag(&db, &Tag::Artist).unwrap();
+        assert_eq!(listed.len(), 4, "{listed:?}");
+        assert_eq!(listed[0], "Artist: abba");
+        assert_eq!(listed[1], "Artist: Édith Piaf");
+        assert!(listed[2].eq_ignore_ascii_case("Artist: the beatles"));
+        assert_eq!(listed[3], "Artist: ZZ Top");
+    }
+}
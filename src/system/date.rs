@@ -0,0 +1,126 @@
+//! Release dates as tags write them, `2001`, `1999-03` or `1999-03-14`.
+//!
+//! Sorting and filtering use the parsed [`Date`] while responses always show
+//! the tag as written. Its [`key`](Date::key) is stored next to the text at
+//! scan time. Values that do not parse, like `199x`, have no key and sort
+//! after every date.
+
+/// Ordered by year then month then day, a missing part goes before any
+/// value so `1999` sorts before `1999-03`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date {
+    year: u16,
+    month: Option<u8>,
+    day: Option<u8>,
+}
+
+impl Date {
+    /// A year of four digits optionally followed by `-MM` and `-DD`. ID3v2.4
+    /// timestamps can go on with a time, that is ignored.
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let date = text.split_once(['T', ' ']).map_or(text, |(date, _)| date);
+        let mut parts = date.split('-');
+        let year = parts.next().filter(|year| year.len() == 4)?;
+        let month = parts.next().map(|month| number(month, 12)).transpose()?;
+        let day = parts.next().map(|day| number(day, 31)).transpose()?;
+        if parts.next().is_some() || (day.is_some() && month.is_none()) {
+            return None;
+        }
+        Some(Self {
+            year: year.parse().ok().filter(|_| all_digits(year))?,
+            month,
+            day,
+        })
+    }
+
+    /// `YYYYMMDD` with zeros for the missing parts, ordered the same as the
+    /// dates
+    pub fn key(self) -> u32 {
+        u32::from(self.year) * 10_000
+            + u32::from(self.month.unwrap_or(0)) * 100
+            + u32::from(self.day.unwrap_or(0))
+    }
+
+    pub fn from_key(key: u32) -> Option<Self> {
+        let part = |n: u32| u8::try_from(n).ok().filter(|n| *n != 0);
+        Some(Self {
+            year: u16::try_from(key / 10_000).ok()?,
+            month: part(key / 100 % 100),
+            day: part(key % 100),
+        })
+    }
+
+    /// Whether this date lies within `other`, like mpd `1999-03-14` is in
+    /// `1999` and in `1999-03`.
+    pub fn within(self, other: Self) -> bool {
+        self.year == other.year
+            && other.month.is_none_or(|month| self.month == Some(month))
+            && other.day.is_none_or(|day| self.day == Some(day))
+    }
+}
+
+fn all_digits(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Two digits from 1 up to `max`
+fn number(s: &str, max: u8) -> Option<u8> {
+    (s.len() == 2 && all_digits(s))
+        .then(|| s.parse().ok())
+        .flatten()
+        .filter(|n| (1..=max).contains(n))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_years_months_and_days() {
+        let date = |year, month, day| Date { year, month, day };
+        assert_eq!(Date::parse("2001"), Some(date(2001, None, None)));
+        assert_eq!(Date::parse("1999-03"), Some(date(1999, Some(3), None)));
+        assert_eq!(
+            Date::parse("1999-03-14"),
+            Some(date(1999, Some(3), Some(14)))
+        );
+        assert_eq!(
+            Date::parse("1999-03-14T20:15:00"),
+            Some(date(1999, Some(3), Some(14)))
+        );
+        for garbage in [
+            "199x",
+            "99",
+            "1999-3",
+            "1999-13",
+            "1999-03-14-1",
+            "",
+            "unknown",
+        ] {
+            assert_eq!(Date::parse(garbage), None, "{garbage}");
+        }
+    }
+
+    #[test]
+    fn keys_sort_like_dates() {
+        let dates = ["1999", "1999-03", "1999-03-14", "1999-12-01", "2001"].map(|text| {
+            let date = Date::parse(text).unwrap();
+            assert_eq!(Date::from_key(date.key()), Some(date), "{text}");
+            date
+        });
+        assert!(dates.is_sorted());
+        assert!(dates.map(Date::key).is_sorted());
+    }
+
+    #[test]
+    fn a_year_contains_its_days() {
+        let year = Date::parse("1999").unwrap();
+        let day = Date::parse("1999-03-14").unwrap();
+        assert!(day.within(year));
+        assert!(day.within(Date::parse("1999-03").unwrap()));
+        assert!(!day.within(Date::parse("1999-04").unwrap()));
+        assert!(!day.within(Date::parse("2001").unwrap()));
+        assert!(!year.within(day));
+    }
+}
@@ -0,0 +1,99 @@
+//! Walks the library for `listall` a bit at a time, so a listing of the whole
+//! library does not have to fit in memory at once.
+
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::Result;
+use itertools::Itertools;
+use rusqlite::Connection;
+
+use crate::mpd_protocol::{BrowseEntry, QueueEntry};
+use crate::scan::{dir_prefix, normalize_path};
+use crate::system::song_by_path;
+
+/// Lists the songs in a directory before its subdirectories, depth first and
+/// sorted by path.
+pub struct ListAll {
+    /// Directories still to list, the next one last
+    pending: Vec<Utf8PathBuf>,
+    /// The directory whose songs are being listed
+    current: Option<Cursor>,
+}
+
+struct Cursor {
+    dir: Utf8PathBuf,
+    /// The last song listed
+    after: String,
+}
+
+impl ListAll {
+    pub fn new(dir: &Utf8Path) -> Self {
+        let dir = normalize_path(dir);
+        Self {
+            pending: Vec::new(),
+            current: Some(Cursor {
+                after: dir_prefix(&dir),
+                dir,
+            }),
+        }
+    }
+
+    /// Up to `max` more items, None once the walk is done
+    pub fn next_items(&mut self, db: &Connection, max: usize) -> Result<Option<Vec<BrowseEntry>>> {
+        let mut items = Vec::new();
+        while items.len() < max {
+            let Some(cursor) = &mut self.current else {
+                let Some(dir) = self.pending.pop() else {
+                    break;
+                };
+                items.push(BrowseEntry::Directory {
+                    path: dir.clone(),
+                    last_modified: None,
+                });
+                self.current = Some(Cursor {
+                    after: dir_prefix(&dir),
+                    dir,
+                });
+                continue;
+            };
+
+            let wanted = max - items.len();
+            let songs: Vec<String> = db
+                .prepare_cached(
+                    "SELECT path FROM songs WHERE path > ?2
+                        AND substr(path, 1, length(?1)) = ?1
+                        AND instr(substr(path, length(?1) + 1), '/') = 0
+                        ORDER BY path LIMIT ?3",
+                )?
+                .query_map(
+                    (
+                        dir_prefix(&cursor.dir),
+                        &cursor.after,
+                        i64::try_from(wanted).unwrap_or(i64::MAX),
+                    ),
+                    |row| row.get(0),
+                )?
+                .try_collect()?;
+            if songs.len() < wanted {
+                let mut subdirs: Vec<Utf8PathBuf> = db
+                    .prepare_cached("SELECT path FROM directories WHERE parent = ?1")?
+                    .query_map([cursor.dir.as_str()], |row| {
+                        row.get::<_, String>(0).map(Utf8PathBuf::from)
+                    })?
+                    .try_collect()?;
+                subdirs.sort_by(|a, b| b.cmp(a));
+                self.pending.extend(subdirs);
+                self.current = None;
+            } else if let Some(last) = songs.last() {
+                cursor.after.clone_from(last);
+            }
+            items.extend(songs.into_iter().map(|path| BrowseEntry::file(path.into())));
+        }
+        Ok((!items.is_empty()).then_some(items))
+    }
+}
+
+/// What `listallinfo` shows for the song at `path`, None if it is no longer
+/// in the database
+pub fn song_info(db: &Connection, path: &Utf8Path) -> Result<Option<QueueEntry>> {
+    Ok(song_by_path(db, path)?.map(|song| QueueEntry::from_song(song, None, None)))
+}
@@ -0,0 +1,123 @@
+//! `listfiles` shows what is on disk, not what the database knows. Cover art,
+//! lyrics and files that did not scan show up as well.
+
+use std::cmp::Ordering;
+use std::fs;
+
+use camino::Utf8Path;
+use color_eyre::{Result, Section, eyre::Context};
+use jiff::Timestamp;
+use tracing::warn;
+
+use crate::mpd_protocol::FileEntry;
+use crate::mpd_protocol::ack::{Ack, ErrorCode};
+use crate::scan::in_music_dir;
+use crate::system::collation;
+
+/// The directories in `relpath` and then its files, both sorted. Hidden
+/// entries are left out, as mpd does. This blocks on the disk.
+pub fn list(music_dir: &Utf8Path, relpath: &Utf8Path) -> Result<Vec<FileEntry>> {
+    if !in_music_dir(music_dir, relpath) {
+        return Err(Ack::new(ErrorCode::NoExist, "No such directory"))
+            .with_note(|| format!("path: {relpath}"));
+    }
+    let dir = music_dir.join(relpath);
+    let mut entries = Vec::new();
+    for entry in dir
+        .read_dir_utf8()
+        .wrap_err("Could not list directory")
+        .with_note(|| format!("path: {dir}"))?
+    {
+        let entry = entry
+            .wrap_err("Could not list directory")
+            .with_note(|| format!("path: {dir}"))?;
+        let name = entry.file_name();
+        if name.starts_with('.') {
+            continue;
+        }
+        // follows symlinks, a link to a directory is listed as one
+        let metadata = match fs::metadata(entry.path()) {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                warn!("Skipping unreadable entry {}: {e}", entry.path());
+                continue;
+            }
+        };
+        let Ok(Ok(last_modified)) = metadata.modified().map(Timestamp::try_from) else {
+            continue;
+        };
+        entries.push(if metadata.is_dir() {
+            FileEntry::Directory {
+                name: name.to_owned(),
+                last_modified,
+            }
+        } else {
+            FileEntry::File {
+                name: name.to_owned(),
+                size: metadata.len(),
+                last_modified,
+            }
+        });
+    }
+    entries.sort_by(order);
+    Ok(entries)
+}
+
+fn order(a: &FileEntry, b: &FileEntry) -> Ordering {
+    match (a, b) {
+        (FileEntry::Directory { .. }, FileEntry::File { .. }) => Ordering::Less,
+        (FileEntry::File { .. }, FileEntry::Directory { .. }) => Ordering::Greater,
+        (
+            FileEntry::Directory { name: a, .. } | FileEntry::File { name: a, .. },
+            FileEntry::Directory { name: b, .. } | FileEntry::File { name: b, .. },
+        ) => collation::compare(a, b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use camino::Utf8PathBuf;
+
+    use super::*;
+    use crate::mpd_protocol::{ack, response_format};
+
+    #[test]
+    fn directories_come_before_files_and_nothing_leaves_the_music_dir() {
+        let music_dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mpdhaj-listfiles-{}", std::process::id()));
+        let album = music_dir.join("album");
+        fs::create_dir_all(album.join("scans")).unwrap();
+        fs::create_dir_all(album.join("Bonus")).unwrap();
+        fs::write(album.join("b.flac"), [0; 3]).unwrap();
+        fs::write(album.join("A.flac"), [0; 5]).unwrap();
+        fs::write(album.join("cover.jpg"), [0; 2]).unwrap();
+        fs::write(album.join(".hidden"), [0; 1]).unwrap();
+
+        let entries = list(&music_dir, Utf8Path::new("album")).unwrap();
+        let names: Vec<_> = entries
+            .iter()
+            .map(|entry| match entry {
+                FileEntry::Directory { name, .. } => format!("{name}/"),
+                FileEntry::File { name, size, .. } => format!("{name} {size}"),
+            })
+            .collect();
+        assert_eq!(
+            names,
+            ["Bonus/", "scans/", "A.flac 5", "b.flac 3", "cover.jpg 2"]
+        );
+        let listed = response_format::to_string(&entries[2..3]).unwrap();
+        assert!(
+            listed.starts_with("file: A.flac\nsize: 5\nLast-Modified: "),
+            "{listed}"
+        );
+
+        let root = list(&music_dir, Utf8Path::new("")).unwrap();
+        assert!(matches!(&root[..], [FileEntry::Directory { name, .. }] if name == "album"));
+        for escape in ["..", "album/../..", "/etc"] {
+            let error = list(&music_dir, Utf8Path::new(escape)).unwrap_err();
+            assert_eq!(ack::find(&error).unwrap().code, ErrorCode::NoExist);
+        }
+        fs::remove_dir_all(&music_dir).unwrap();
+    }
+}
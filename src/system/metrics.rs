@@ -0,0 +1,199 @@
+//! Counters for monitoring, shown by `stats_internal` and the optional
+//! Prometheus endpoint. They are plain atomics, cheap enough to always count.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+use color_eyre::Result;
+
+use crate::mpd_protocol::Command;
+use crate::system::System;
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Per command name
+    commands: Mutex<BTreeMap<&'static str, u64>>,
+    connections: AtomicU64,
+    scans: AtomicU64,
+    scan_micros: AtomicU64,
+    decode_failures: AtomicU64,
+    output_failures: AtomicU64,
+}
+
+impl Metrics {
+    pub fn command(&self, command: &Command) {
+        let name: &'static str = command.into();
+        let mut commands = self.commands.lock().unwrap_or_else(PoisonError::into_inner);
+        *commands.entry(name).or_default() += 1;
+    }
+
+    pub fn connection(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn scan(&self, took: Duration) {
+        self.scans.fetch_add(1, Ordering::Relaxed);
+        let micros = u64::try_from(took.as_micros()).unwrap_or(u64::MAX);
+        self.scan_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    pub fn decode_failure(&self) {
+        self.decode_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn output_failure(&self) {
+        self.output_failures.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Serves [`render`] at `/metrics` for Prometheus to scrape
+#[cfg(feature = "metrics-http")]
+pub async fn serve(
+    system: std::sync::Arc<tokio::sync::Mutex<System>>,
+    listener: tokio::net::TcpListener,
+) -> Result<()> {
+    use color_eyre::eyre::Context;
+
+    let app = axum::Router::new()
+        .route("/metrics", axum::routing::get(scrape))
+        .with_state(system);
+    axum::serve(listener, app)
+        .await
+        .wrap_err("Metrics server stopped")
+}
+
+#[cfg(feature = "metrics-http")]
+async fn scrape(
+    axum::extract::State(system): axum::extract::State<std::sync::Arc<tokio::sync::Mutex<System>>>,
+) -> axum::response::Response {
+    use axum::http::StatusCode;
+    use axum::http::header::CONTENT_TYPE;
+    use axum::response::IntoResponse;
+
+    let rendered = render(&*system.lock().await);
+    match rendered {
+        Ok(text) => ([(CONTENT_TYPE, "text/plain; version=0.0.4")], text).into_response(),
+        Err(e) => {
+            tracing::warn!("Could not render metrics: {e:#}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// In the Prometheus text format
+pub fn render(system: &System) -> Result<String> {
+    let metrics = &system.metrics;
+    let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+    let queue_length: u64 = system
+        .db
+        .query_one("SELECT COUNT(*) FROM queue", [], |row| row.get(0))?;
+
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: &dyn std::fmt::Display| {
+        let _ = write!(
+            out,
+            "# HELP mpdhaj_{name} {help}\n# TYPE mpdhaj_{name} {kind}\nmpdhaj_{name} {value}\n"
+        );
+    };
+    metric(
+        "clients",
+        "gauge",
+        "Connected clients",
+        &system.clients.len(),
+    );
+    metric(
+        "connections_total",
+        "counter",
+        "Accepted connections",
+        &load(&metrics.connections),
+    );
+    metric(
+        "scans_total",
+        "counter",
+        "Library scans",
+        &load(&metrics.scans),
+    );
+    metric(
+        "scan_seconds_total",
+        "counter",
+        "Time spent scanning the library",
+        &(load(&metrics.scan_micros) as f64 / 1e6),
+    );
+    metric(
+        "decode_failures_total",
+        "counter",
+        "Songs that stopped well before their end",
+        &load(&metrics.decode_failures),
+    );
+    metric(
+        "output_failures_total",
+        "counter",
+        "Times the audio output broke",
+        &load(&metrics.output_failures),
+    );
+    metric(
+        "underruns_total",
+        "counter",
+        "Times decoding could not keep up with playback",
+        &system.player.underruns(),
+    );
+    metric(
+        "output_latency_seconds",
+        "gauge",
+        "Audio in the output device's buffer",
+        &system.player.status().latency.as_secs_f64(),
+    );
+    metric("queue_length", "gauge", "Songs in the queue", &queue_length);
+
+    out += "# HELP mpdhaj_commands_total Commands handled\n";
+    out += "# TYPE mpdhaj_commands_total counter\n";
+    let commands = metrics
+        .commands
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    for (command, count) in commands.iter() {
+        let _ = writeln!(
+            out,
+            "mpdhaj_commands_total{{command=\"{command}\"}} {count}"
+        );
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use super::*;
+    use crate::system::empty_system;
+
+    #[test]
+    fn output_is_valid_prometheus_text() {
+        let system = empty_system(Connection::open_in_memory().unwrap());
+        system.metrics.command(&Command::Status);
+        system.metrics.scan(Duration::from_millis(1500));
+
+        let rendered = render(&system).unwrap();
+        let mut declared = Vec::new();
+        for line in rendered.lines() {
+            if let Some(comment) = line.strip_prefix("# ") {
+                let mut words = comment.split(' ');
+                let kind = words.next().unwrap();
+                assert!(["HELP", "TYPE"].contains(&kind), "{line}");
+                if kind == "TYPE" {
+                    declared.push(words.next().unwrap());
+                }
+                continue;
+            }
+            let (series, value) = line.rsplit_once(' ').unwrap();
+            let name = series.split('{').next().unwrap();
+            assert!(declared.contains(&name), "{name} has no TYPE");
+            assert!(value.parse::<f64>().is_ok(), "{line}");
+        }
+        assert!(rendered.contains("\nmpdhaj_scan_seconds_total 1.5\n"));
+        assert!(rendered.contains("\nmpdhaj_commands_total{command=\"status\"} 1\n"));
+    }
+}
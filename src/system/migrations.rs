@@ -0,0 +1,323 @@
+//! Upgrades the database schema to the one this binary expects.
+//!
+//! The version is stored in sqlite's `user_version` pragma. To change the
+//! schema add a script to [`MIGRATIONS`], never edit one that has shipped.
+
+use color_eyre::eyre::{Context, bail};
+use color_eyre::{Result, Section};
+use rusqlite::{Connection, TransactionBehavior};
+use tracing::info;
+
+/// Script `n` upgrades version `n` to `n + 1`
+const MIGRATIONS: &[&str] = &[
+    include_str!("migrations/0001_initial.sql"),
+    include_str!("migrations/0002_replay_gain.sql"),
+    include_str!("migrations/0003_unique_paths.sql"),
+    include_str!("migrations/0004_directories.sql"),
+    include_str!("migrations/0005_audio_format.sql"),
+    include_str!("migrations/0006_no_current_song.sql"),
+    include_str!("migrations/0007_queue_version.sql"),
+    include_str!("migrations/0008_unique_queue_positions.sql"),
+    include_str!("migrations/0009_integer_mtime.sql"),
+    include_str!("migrations/0010_decode_failures.sql"),
+    include_str!("migrations/0011_scrobbles.sql"),
+    include_str!("migrations/0012_play_order.sql"),
+    include_str!("migrations/0013_waveforms.sql"),
+    include_str!("migrations/0014_date_keys.sql"),
+    include_str!("migrations/0015_song_tags.sql"),
+    include_str!("migrations/0016_decode_errors.sql"),
+    include_str!("migrations/0017_last_db_update.sql"),
+    include_str!("migrations/0018_queue_keys.sql"),
+];
+
+/// The schema version this binary understands
+pub(crate) const VERSION: usize = MIGRATIONS.len();
+
+pub(crate) fn version(db: &Connection) -> Result<usize> {
+    db.query_one("PRAGMA user_version", [], |row| row.get(0))
+        .wrap_err("Could not read schema version")
+}
+
+/// Applies every migration the database is missing in a single transaction,
+/// if one fails none are applied.
+pub(crate) fn run(db: &mut Connection) -> Result<()> {
+    migrate_to(db, VERSION)
+}
+
+fn migrate_to(db: &mut Connection, target: usize) -> Result<()> {
+    let t = db.transaction_with_behavior(TransactionBehavior::Exclusive)?;
+    let current = version(&t)?;
+    if current > VERSION {
+        bail!(
+            "The database has schema version {current} while this version of \
+            mpdhaj only understands up to {VERSION}. Upgrade mpdhaj or delete \
+            the database."
+        );
+    }
+
+    for (from, script) in MIGRATIONS.iter().enumerate().take(target).skip(current) {
+        info!("Migrating database from version {from} to {}", from + 1);
+        t.execute_batch(script)
+            .wrap_err("Migration failed")
+            .with_note(|| format!("migrating from version {from} to {}", from + 1))?;
+    }
+    if target > current {
+        t.pragma_update(None, "user_version", target)?;
+    }
+    t.commit().wrap_err("Could not commit migrations")
+}
+
+#[cfg(test)]
+mod tests {
+    use camino::Utf8Path;
+
+    use super::*;
+    use crate::scan::decoders;
+    use crate::system::date::Date;
+
+    fn v1_with_song() -> Connection {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrate_to(&mut db, 1).unwrap();
+        db.execute(
+            "INSERT INTO songs (path, mtime, title) VALUES ('a.flac', '0', 'A')",
+            [],
+        )
+        .unwrap();
+        db.execute("UPDATE state SET volume = 42", []).unwrap();
+        db
+    }
+
+    #[test]
+    fn data_survives_migrating_from_v1() {
+        let mut db = v1_with_song();
+        assert_eq!(version(&db).unwrap(), 1);
+
+        run(&mut db).unwrap();
+        assert_eq!(version(&db).unwrap(), VERSION);
+        let (title, gain) = db
+            .query_one("SELECT title, track_gain FROM songs", [], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, Option<f32>>(1)?))
+            })
+            .unwrap();
+        assert_eq!((title.as_str(), gain), ("A", None));
+        let volume: u8 = db
+            .query_one("SELECT volume FROM state", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(volume, 42);
+    }
+
+    #[test]
+    fn duplicate_paths_from_v1_are_merged() {
+        let mut db = v1_with_song();
+        db.execute(
+            "INSERT INTO songs (path, mtime, title) VALUES ('a.flac', '0', 'B')",
+            [],
+        )
+        .unwrap();
+
+        run(&mut db).unwrap();
+        let titles: Vec<String> = db
+            .prepare("SELECT title FROM songs")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(titles, ["A"]);
+    }
+
+    #[test]
+    fn running_twice_changes_nothing() {
+        let mut db = Connection::open_in_memory().unwrap();
+        run(&mut db).unwrap();
+        run(&mut db).unwrap();
+        assert_eq!(version(&db).unwrap(), VERSION);
+    }
+
+    #[test]
+    fn no_current_song_becomes_null() {
+        let mut db = v1_with_song();
+        run(&mut db).unwrap();
+        let (current, volume): (Option<u32>, u8) = db
+            .query_one("SELECT current, volume FROM state", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!((current, volume), (None, 42));
+    }
+
+    #[test]
+    fn queue_positions_become_contiguous() {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrate_to(&mut db, 7).unwrap();
+        db.execute_batch(
+            "INSERT INTO queue (id, song, position) VALUES (1, 1, 5), (2, 1, 1), (3, 1, 1);
+            UPDATE state SET current = 5;",
+        )
+        .unwrap();
+
+        migrate_to(&mut db, 8).unwrap();
+        let ids: Vec<u32> = db
+            .prepare("SELECT id FROM queue ORDER BY position")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(ids, [2, 3, 1]);
+        let positions: Vec<u32> = db
+            .prepare("SELECT position FROM queue ORDER BY position")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(positions, [0, 1, 2]);
+        let current: u32 = db
+            .query_one("SELECT current FROM state", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(current, 2);
+        assert!(
+            db.execute("UPDATE queue SET position = 0 WHERE id = 1", [])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn stored_dates_get_their_keys_without_a_rescan() {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrate_to(&mut db, 13).unwrap();
+        let dates = [
+            "2001",
+            "1999-03",
+            "1999-03-14",
+            "1999-03-14T20:15:00",
+            "1999-03-14 20:15",
+            "199x",
+            "1999-13",
+            "1999-03-32",
+            "1999-03-14-1",
+        ];
+        for (i, date) in dates.iter().enumerate() {
+            db.execute(
+                "INSERT INTO songs (path, mtime, date, original_date) VALUES (?1, 7, ?2, ?2)",
+                (format!("{i}.flac"), date),
+            )
+            .unwrap();
+        }
+
+        migrate_to(&mut db, 14).unwrap();
+        let mut stmt = db
+            .prepare("SELECT date, date_key, original_date_key, mtime FROM songs")
+            .unwrap();
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Option<u32>>(1)?,
+                    row.get::<_, Option<u32>>(2)?,
+                    row.get::<_, i64>(3)?,
+                ))
+            })
+            .unwrap();
+        for row in rows {
+            let (date, key, original_key, mtime) = row.unwrap();
+            let expected = Date::parse(&date).map(Date::key);
+            assert_eq!(key, expected, "{date}");
+            assert_eq!(original_key, expected, "{date}");
+            assert_eq!(mtime, 7, "{date} would be scanned again");
+        }
+    }
+
+    #[test]
+    fn text_mtimes_become_seconds() {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrate_to(&mut db, 8).unwrap();
+        db.execute_batch(
+            "INSERT INTO songs (path, mtime) VALUES
+                ('a.flac', '2025-06-15T22:08:17.123456789Z'),
+                ('b.flac', '0'),
+                ('c.flac', 'garbage-');",
+        )
+        .unwrap();
+
+        run(&mut db).unwrap();
+        let mtimes: Vec<i64> = db
+            .prepare("SELECT mtime FROM songs ORDER BY path")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(mtimes, [1_750_025_297, 0, 0]);
+    }
+
+    #[test]
+    fn unsupported_suffixes_are_flagged_without_a_rescan() {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrate_to(&mut db, 15).unwrap();
+        db.execute_batch(
+            "INSERT INTO songs (path, mtime) VALUES
+                ('a.b/01.FLAC', 7), ('a.b/02.Mid', 7), ('a.b/03', 7), ('.hidden', 7);",
+        )
+        .unwrap();
+
+        run(&mut db).unwrap();
+        let songs: Vec<(i64, Option<String>)> = db
+            .prepare("SELECT mtime, decode_error FROM songs ORDER BY path")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let flagged = |path: &str| Some(decoders::unsupported(Utf8Path::new(path)).unwrap());
+        assert_eq!(
+            songs,
+            [
+                (7, flagged(".hidden")),
+                (7, None),
+                (7, flagged("a.b/02.Mid")),
+                (7, flagged("a.b/03")),
+            ]
+        );
+    }
+
+    #[test]
+    fn queue_keeps_its_order_and_ids_once_keyed() {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrate_to(&mut db, 17).unwrap();
+        db.execute_batch(
+            "INSERT INTO queue (song, position, prio) VALUES (1, 1, 3), (2, 0, 0), (3, 2, 0);
+            DELETE FROM queue WHERE song = 3;",
+        )
+        .unwrap();
+
+        run(&mut db).unwrap();
+        let queue: Vec<(u32, u32, u32, u8)> = db
+            .prepare("SELECT id, song, position, prio FROM queue ORDER BY position")
+            .unwrap()
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(queue, [(2, 2, 0, 0), (1, 1, 1, 3)]);
+        // the id of the deleted entry is not handed out again
+        let id: u32 = db
+            .query_one(
+                "INSERT INTO queue_entries (song, key) VALUES (4, 1) RETURNING id",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(id, 4);
+    }
+
+    #[test]
+    fn refuses_newer_schema() {
+        let mut db = Connection::open_in_memory().unwrap();
+        db.pragma_update(None, "user_version", VERSION + 1).unwrap();
+        assert!(run(&mut db).is_err());
+    }
+}
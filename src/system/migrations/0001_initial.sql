@@ -1,4 +1,5 @@
-BEGIN;
+-- Databases from before versioning are at version 0 but already contain
+-- these tables, hence the IF NOT EXISTS.
 CREATE TABLE IF NOT EXISTS songs (
     path        TEXT NOT NULL,
     mtime       TEXT NOT NULL,
@@ -50,9 +51,6 @@ CREATE TABLE IF NOT EXISTS songs (
     musicbrainz_work_id             TEXT
 );
 
--- this makes scanning significantly faster at the cost of a bit of extra space (~15% larger database)
--- CREATE UNIQUE INDEX IF NOT EXISTS idx_songs ON songs (path);
-
 CREATE TABLE IF NOT EXISTS state (
     -- used to remove deleted songs
     generation  INTEGER DEFAULT 0,
@@ -79,4 +77,3 @@ CREATE TABLE IF NOT EXISTS queue (
     range_start FLOAT,
     range_end   FLOAT
 );
-COMMIT;
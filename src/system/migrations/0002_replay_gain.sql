@@ -0,0 +1,4 @@
+-- ReplayGain style, in dB relative to -18 LUFS
+ALTER TABLE songs ADD COLUMN track_gain FLOAT;
+-- linear sample peak
+ALTER TABLE songs ADD COLUMN track_peak FLOAT;
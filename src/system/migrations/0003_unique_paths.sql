@@ -0,0 +1,5 @@
+-- this makes scanning significantly faster at the cost of a bit of extra space (~15% larger database)
+-- it also keeps racing scans from adding a file twice, before this
+-- migration that could happen so drop any duplicates first
+DELETE FROM songs WHERE rowid NOT IN (SELECT MIN(rowid) FROM songs GROUP BY path);
+CREATE UNIQUE INDEX idx_songs ON songs (path);
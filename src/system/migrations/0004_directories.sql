@@ -0,0 +1,9 @@
+-- every directory containing songs, directly or in a subdirectory. Filled in
+-- by the next scan. The music dir itself is not stored, its children have
+-- parent ''.
+CREATE TABLE directories (
+    path    TEXT PRIMARY KEY,
+    parent  TEXT NOT NULL,
+    mtime   TEXT NOT NULL
+);
+CREATE INDEX idx_directories_parent ON directories (parent);
@@ -0,0 +1,5 @@
+-- NULL when the scanner could not tell
+ALTER TABLE songs ADD COLUMN sample_rate INTEGER;
+-- also NULL for lossy formats, those decode to floats
+ALTER TABLE songs ADD COLUMN bit_depth INTEGER;
+ALTER TABLE songs ADD COLUMN channels INTEGER;
@@ -0,0 +1,23 @@
+-- "current" used 0 for no current song, which is also a position. SQLite can
+-- not change a column's default so the table is rebuilt.
+CREATE TABLE state_new (
+    -- used to remove deleted songs
+    generation  INTEGER DEFAULT 0,
+
+    -- position in queue, NULL when there is no current song
+    current     INTEGER DEFAULT NULL, -- TODO: also store songid of current
+
+    volume      INTEGER DEFAULT 10, -- TODO: remove me, for testing only
+    paused      BOOLEAN DEFAULT true,
+
+    repeat      BOOLEAN DEFAULT 0,
+    random      BOOLEAN DEFAULT 0,
+    single      BOOLEAN DEFAULT 0,
+    consume     BOOLEAN DEFAULT 0
+);
+INSERT INTO state_new
+    (rowid, generation, current, volume, paused, repeat, random, single, consume)
+    SELECT rowid, generation, NULLIF(current, 0), volume, paused, repeat, random, single, consume
+    FROM state;
+DROP TABLE state;
+ALTER TABLE state_new RENAME TO state;
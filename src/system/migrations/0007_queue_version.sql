@@ -0,0 +1,3 @@
+-- mpd's playlist version, bumped on every change to the queue so clients know
+-- to fetch it again
+ALTER TABLE state ADD COLUMN queue_version INTEGER DEFAULT 1;
@@ -0,0 +1,14 @@
+-- Positions become 0, 1, 2, ... in their current order, duplicates ordered by
+-- id. The current song keeps pointing at the same entry.
+UPDATE state SET current = CASE
+    WHEN EXISTS (SELECT 1 FROM queue WHERE position = state.current)
+    THEN (SELECT COUNT(*) FROM queue WHERE position < state.current)
+END;
+-- through negative numbers so no position is ever taken twice
+UPDATE queue SET position = -1 - (
+    SELECT COUNT(*) FROM queue AS other
+    WHERE other.position < queue.position
+        OR (other.position = queue.position AND other.id < queue.id)
+);
+UPDATE queue SET position = -1 - position;
+CREATE UNIQUE INDEX queue_position ON queue(position);
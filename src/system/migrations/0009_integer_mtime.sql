@@ -0,0 +1,9 @@
+-- mtime was the text form of a timestamp, store whole seconds since the epoch
+-- like mpd does. Songs with an unreadable mtime get 0 and are rescanned.
+ALTER TABLE songs ADD COLUMN mtime_seconds INTEGER NOT NULL DEFAULT 0;
+UPDATE songs SET mtime_seconds = CASE
+    WHEN mtime LIKE '%-%' THEN coalesce(CAST(strftime('%s', mtime) AS INTEGER), 0)
+    ELSE CAST(mtime AS INTEGER)
+END;
+ALTER TABLE songs DROP COLUMN mtime;
+ALTER TABLE songs RENAME COLUMN mtime_seconds TO mtime;
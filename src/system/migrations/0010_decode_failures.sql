@@ -0,0 +1,3 @@
+-- how often a song stopped playing well before its end, files that keep
+-- failing are probably damaged
+ALTER TABLE songs ADD COLUMN decode_failures INTEGER NOT NULL DEFAULT 0;
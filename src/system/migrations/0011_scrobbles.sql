@@ -0,0 +1,16 @@
+-- plays waiting to be submitted to ListenBrainz, they stay here until the
+-- submission succeeds so they survive network outages and restarts
+CREATE TABLE scrobbles (
+    id                      INTEGER PRIMARY KEY,
+    -- unix seconds at which the song started
+    listened_at             INTEGER NOT NULL,
+    artist                  TEXT NOT NULL,
+    title                   TEXT NOT NULL,
+    album                   TEXT,
+    duration                FLOAT,
+    musicbrainz_track_id    TEXT,
+    -- failed submissions so far, sets the backoff
+    attempts                INTEGER NOT NULL DEFAULT 0,
+    -- unix seconds before which the next attempt waits
+    retry_at                INTEGER NOT NULL DEFAULT 0
+);
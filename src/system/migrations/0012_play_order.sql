@@ -0,0 +1,7 @@
+-- the order random mode plays the queue in, by queue id. Shuffled when random
+-- is turned on, empty while it is off. Rows of entries that left the queue
+-- are ignored and dropped the next time the order changes.
+CREATE TABLE play_order (
+    id      INTEGER PRIMARY KEY,
+    idx     INTEGER NOT NULL
+);
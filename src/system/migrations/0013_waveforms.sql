@@ -0,0 +1,9 @@
+-- overviews for x-mpdhaj waveform, stale once the song's mtime changes
+CREATE TABLE waveforms (
+    path    TEXT NOT NULL,
+    mtime   INTEGER NOT NULL,
+    buckets INTEGER NOT NULL,
+    -- per bucket its peak then its RMS, as little endian f32
+    data    BLOB NOT NULL,
+    PRIMARY KEY (path, buckets)
+);
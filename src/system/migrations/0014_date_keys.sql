@@ -0,0 +1,42 @@
+-- Date::key of the date tags, NULL when missing or not a date
+ALTER TABLE songs ADD COLUMN date_key INTEGER;
+ALTER TABLE songs ADD COLUMN original_date_key INTEGER;
+
+-- the keys of the dates already stored, parsed the way Date::parse does.
+-- Songs scanned before dates were read get theirs from the rescan 0015 asks
+-- for.
+CREATE TEMP TABLE date_keys AS
+WITH texts AS (
+    SELECT trim(date) AS text FROM songs WHERE date IS NOT NULL
+    UNION
+    SELECT trim(original_date) FROM songs WHERE original_date IS NOT NULL
+), dates AS (
+    -- ID3v2.4 timestamps can go on with a time after a 'T' or a space
+    SELECT text, CASE
+        WHEN instr(text, 'T') > 0 AND (instr(text, ' ') = 0 OR instr(text, 'T') < instr(text, ' '))
+            THEN substr(text, 1, instr(text, 'T') - 1)
+        WHEN instr(text, ' ') > 0 THEN substr(text, 1, instr(text, ' ') - 1)
+        ELSE text
+    END AS date
+    FROM texts
+)
+SELECT text, CASE
+    WHEN date GLOB '[0-9][0-9][0-9][0-9]' THEN CAST(date AS INTEGER) * 10000
+    WHEN date GLOB '[0-9][0-9][0-9][0-9]-[0-9][0-9]'
+        AND substr(date, 6, 2) BETWEEN '01' AND '12'
+        THEN CAST(substr(date, 1, 4) AS INTEGER) * 10000
+            + CAST(substr(date, 6, 2) AS INTEGER) * 100
+    WHEN date GLOB '[0-9][0-9][0-9][0-9]-[0-9][0-9]-[0-9][0-9]'
+        AND substr(date, 6, 2) BETWEEN '01' AND '12'
+        AND substr(date, 9, 2) BETWEEN '01' AND '31'
+        THEN CAST(substr(date, 1, 4) AS INTEGER) * 10000
+            + CAST(substr(date, 6, 2) AS INTEGER) * 100
+            + CAST(substr(date, 9, 2) AS INTEGER)
+END AS key
+FROM dates;
+
+UPDATE songs SET
+    date_key = (SELECT key FROM date_keys WHERE text = trim(songs.date)),
+    original_date_key = (SELECT key FROM date_keys WHERE text = trim(songs.original_date));
+
+DROP TABLE date_keys;
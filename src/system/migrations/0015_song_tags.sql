@@ -0,0 +1,18 @@
+-- every value of the multi-valued tags, in the order the file lists them.
+-- The column in songs holds them joined.
+CREATE TABLE song_tags (
+    song  INTEGER NOT NULL,
+    tag   TEXT NOT NULL,
+    value TEXT NOT NULL,
+    ord   INTEGER NOT NULL,
+    PRIMARY KEY (song, tag, ord)
+);
+CREATE INDEX song_tags_value ON song_tags (tag, value);
+
+CREATE TRIGGER song_tags_of_removed_songs AFTER DELETE ON songs
+BEGIN
+    DELETE FROM song_tags WHERE song = old.rowid;
+END;
+
+-- only the first artist was stored and no genre, read every song again
+UPDATE songs SET mtime = 0;
@@ -0,0 +1,27 @@
+-- why a song will not play, set when no decoder takes its suffix or when
+-- playing it failed. Cleared by a rescan once the file changes.
+ALTER TABLE songs ADD COLUMN decode_error TEXT;
+
+-- flag what is already there the way scan::decoders::unsupported would, with
+-- the decoders there are as of this migration. rtrim strips up to the last
+-- '/' or '.', whatever is left is the name or the suffix.
+WITH names AS (
+    SELECT rowid AS song, substr(path, length(rtrim(path, replace(path, '/', ''))) + 1) AS name
+    FROM songs
+), suffixes AS (
+    SELECT song, CASE
+        -- no dot or only a leading one, like camino's extension()
+        WHEN length(rtrim(name, replace(name, '.', ''))) > 1
+            THEN lower(substr(name, length(rtrim(name, replace(name, '.', ''))) + 1))
+        ELSE ''
+    END AS suffix
+    FROM names
+)
+UPDATE songs
+SET decode_error = (
+    SELECT 'No decoder for .' || suffix || ' files' FROM suffixes WHERE song = songs.rowid
+)
+WHERE rowid IN (
+    SELECT song FROM suffixes
+    WHERE suffix NOT IN ('flac', 'mp3', 'aac', 'm4a', 'mp4', 'ogg', 'oga', 'wav')
+);
@@ -0,0 +1,2 @@
+-- unix time the last full scan finished, `stats` shows it as db_update
+ALTER TABLE state ADD COLUMN last_db_update INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,25 @@
+-- the queue is ordered by key instead of position. Keys are spaced apart so
+-- an entry can go in between two others without renumbering every entry
+-- after it. The queue view numbers the entries 0, 1, 2, ... like before.
+CREATE TABLE queue_entries (
+    -- can't use song as primary key, need to support duplicates
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    song        INTEGER, -- rowid in songs table
+    key         INTEGER NOT NULL, -- always positive outside of a transaction
+    prio        INTEGER DEFAULT 0,
+    range_start FLOAT,
+    range_end   FLOAT
+);
+CREATE UNIQUE INDEX queue_entries_key ON queue_entries(key);
+
+INSERT INTO queue_entries (id, song, key, prio, range_start, range_end)
+    SELECT id, song, (position + 1) * 65536, prio, range_start, range_end FROM queue;
+-- ids are never handed out twice, not even those of deleted entries
+DELETE FROM sqlite_sequence WHERE name = 'queue_entries';
+UPDATE sqlite_sequence SET name = 'queue_entries' WHERE name = 'queue';
+DROP TABLE queue;
+
+CREATE VIEW queue AS
+    SELECT id, song, ROW_NUMBER() OVER (ORDER BY key) - 1 AS position, key,
+        prio, range_start, range_end
+    FROM queue_entries;
@@ -0,0 +1,179 @@
+//! Tags that often hold more than one value, like a song by two artists.
+//!
+//! Files repeat the tag or separate the values with `;`. Every value is
+//! stored in order in the `song_tags` table, the column in `songs` keeps
+//! them joined for display. Filters and `list` look at every value and
+//! responses repeat the tag once per value.
+
+use std::collections::HashMap;
+
+use color_eyre::Result;
+use rusqlite::Connection;
+
+use crate::mpd_protocol::Tag;
+use crate::system::{Song, collation};
+
+/// The tags stored in `song_tags`
+pub const TAGS: [Tag; 2] = [Tag::Artist, Tag::Genre];
+/// Between the values in the `songs` column
+pub const JOINED_BY: &str = "; ";
+
+/// Splits values separated by `;`, or by the nul ID3v2.4 uses, and drops
+/// empty ones
+pub fn split<'a>(values: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    values
+        .into_iter()
+        .flat_map(|value| value.split([';', '\0']))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Replaces the values of `tag` for the song with rowid `song`
+pub(crate) fn store(db: &Connection, song: u32, tag: Tag, values: &[String]) -> Result<()> {
+    db.prepare_cached("DELETE FROM song_tags WHERE song = ?1 AND tag = ?2")?
+        .execute(rusqlite::params![song, tag.to_string()])?;
+    let mut insert =
+        db.prepare_cached("INSERT INTO song_tags (song, tag, value, ord) VALUES (?1, ?2, ?3, ?4)")?;
+    for (ord, value) in values.iter().enumerate() {
+        insert.execute(rusqlite::params![song, tag.to_string(), value, ord])?;
+    }
+    Ok(())
+}
+
+/// Fills in the values for the song with rowid `song`
+pub(crate) fn load(db: &Connection, song: u32, into: &mut Song) -> Result<()> {
+    let mut stmt =
+        db.prepare_cached("SELECT tag, value FROM song_tags WHERE song = ?1 ORDER BY tag, ord")?;
+    let mut rows = stmt.query([song])?;
+    while let Some(row) = rows.next()? {
+        if let Some(values) = values_mut(into, &row.get::<_, String>(0)?) {
+            values.push(row.get(1)?);
+        }
+    }
+    Ok(())
+}
+
+/// The values of every song, keyed by rowid. For queries that go through
+/// the whole library.
+pub(crate) fn load_all(db: &Connection) -> Result<HashMap<u32, Song>> {
+    let mut stmt = db.prepare("SELECT song, tag, value FROM song_tags ORDER BY song, tag, ord")?;
+    let mut rows = stmt.query([])?;
+    let mut songs: HashMap<u32, Song> = HashMap::new();
+    while let Some(row) = rows.next()? {
+        let song = songs.entry(row.get(0)?).or_default();
+        if let Some(values) = values_mut(song, &row.get::<_, String>(1)?) {
+            values.push(row.get(2)?);
+        }
+    }
+    Ok(songs)
+}
+
+/// Moves the values loaded by [`load_all`] into `song`
+pub(crate) fn take(from: &mut HashMap<u32, Song>, song: u32, into: &mut Song) {
+    if let Some(values) = from.remove(&song) {
+        into.artists = values.artists;
+        into.genres = values.genres;
+    }
+}
+
+fn values_mut<'a>(song: &'a mut Song, tag: &str) -> Option<&'a mut Vec<String>> {
+    match tag.parse().ok()? {
+        Tag::Artist => Some(&mut song.artists),
+        Tag::Genre => Some(&mut song.genres),
+        _ => None,
+    }
+}
+
+/// Every value of `tag` once, for `list`. Songs without rows in `song_tags`
+/// contribute the value in their `songs` column.
+pub(crate) fn list(db: &Connection, tag: Tag) -> Result<Vec<String>> {
+    let column = tag.to_string().to_lowercase();
+    let collation = collation::NAME;
+    let mut stmt = db.prepare(&format!(
+        "SELECT DISTINCT value COLLATE {collation} FROM (
+             SELECT value FROM song_tags WHERE tag = ?1
+             UNION ALL
+             SELECT {column} FROM songs
+             WHERE {column} IS NOT NULL
+               AND rowid NOT IN (SELECT song FROM song_tags WHERE tag = ?1)
+         )
+         ORDER BY 1"
+    ))?;
+    Ok(stmt
+        .query_map([tag.to_string()], |row| row.get(0))?
+        .collect::<Result<_, _>>()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn db() -> Connection {
+        let mut db = Connection::open_in_memory().unwrap();
+        crate::system::migrations::run(&mut db).unwrap();
+        collation::register(&db).unwrap();
+        db
+    }
+
+    #[test]
+    fn values_are_split_on_semicolons() {
+        assert_eq!(
+            split(["Jazz; Fusion;Funk", " ", "Soul\0Disco"]),
+            ["Jazz", "Fusion", "Funk", "Soul", "Disco"]
+        );
+        assert!(split(["", ";"]).is_empty());
+    }
+
+    #[test]
+    fn values_load_in_order() {
+        let db = db();
+        db.execute(
+            "INSERT INTO songs (path, mtime, artist, genre)
+             VALUES ('a.flac', 0, 'Zoë; Abba', 'Jazz; Fusion; Funk')",
+            [],
+        )
+        .unwrap();
+        let artists = split(["Zoë; Abba"]);
+        let genres = split(["Jazz; Fusion; Funk"]);
+        store(&db, 1, Tag::Artist, &artists).unwrap();
+        store(&db, 1, Tag::Genre, &genres).unwrap();
+
+        let mut song = Song::default();
+        load(&db, 1, &mut song).unwrap();
+        assert_eq!(song.artists, ["Zoë", "Abba"]);
+        assert_eq!(song.genres, ["Jazz", "Fusion", "Funk"]);
+
+        let mut all = load_all(&db).unwrap();
+        let mut song = Song::default();
+        take(&mut all, 1, &mut song);
+        assert_eq!(song.genres, ["Jazz", "Fusion", "Funk"]);
+
+        // storing again replaces the values
+        store(&db, 1, Tag::Genre, &split(["Funk"])).unwrap();
+        let mut song = Song::default();
+        load(&db, 1, &mut song).unwrap();
+        assert_eq!(song.genres, ["Funk"]);
+    }
+
+    #[test]
+    fn list_shows_every_value_once() {
+        let db = db();
+        db.execute_batch(
+            "INSERT INTO songs (path, mtime, genre) VALUES
+                 ('a.flac', 0, 'Jazz; Fusion'),
+                 ('b.flac', 0, 'jazz'),
+                 ('c.flac', 0, 'Blues');",
+        )
+        .unwrap();
+        store(&db, 1, Tag::Genre, &split(["Jazz; Fusion"])).unwrap();
+        store(&db, 2, Tag::Genre, &split(["jazz"])).unwrap();
+        // c.flac was scanned before values were stored separately
+
+        assert_eq!(list(&db, Tag::Genre).unwrap(), ["Blues", "Fusion", "Jazz"]);
+
+        db.execute("DELETE FROM songs WHERE rowid = 1", []).unwrap();
+        assert_eq!(list(&db, Tag::Genre).unwrap(), ["Blues", "jazz"]);
+    }
+}
@@ -0,0 +1,256 @@
+//! Decides which queue entry plays after the current one.
+//!
+//! Status shows it as `nextsong` and advancing to the next song plays it, so
+//! both go through [`System::next_entry`] and can not disagree. The `next`
+//! and `previous` commands skip along the same order.
+
+use color_eyre::Result;
+use itertools::Itertools;
+
+use crate::mpd_protocol::{PlaybackState, QueueId, QueuePos};
+use crate::system::System;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Modes {
+    pub random: bool,
+    pub repeat: bool,
+    pub single: bool,
+    pub consume: bool,
+}
+
+impl System {
+    /// The entry that plays once the current one finishes. None when stopped,
+    /// when nothing is current or when playback stops after this song.
+    pub fn next_entry(&self) -> Result<Option<(QueuePos, QueueId)>> {
+        if self.playing == PlaybackState::Stop {
+            return Ok(None);
+        }
+        self.entry_after_current(false)
+    }
+
+    /// The entry after the current one in the play order, stopped or not.
+    /// A `skip` ignores single mode, like mpd's `next` does.
+    pub(crate) fn entry_after_current(&self, skip: bool) -> Result<Option<(QueuePos, QueueId)>> {
+        let (order, current, mut modes) = self.play_order()?;
+        modes.single &= !skip;
+        let Some(current) = current else {
+            return Ok(None);
+        };
+        Ok(next_index(order.len(), current, modes).map(|i| order[i]))
+    }
+
+    /// The entry before the current one in the play order, the first entry
+    /// stays at itself. None when nothing is current.
+    pub(crate) fn entry_before_current(&self) -> Result<Option<(QueuePos, QueueId)>> {
+        let (order, current, _) = self.play_order()?;
+        Ok(current.map(|current| order[current.saturating_sub(1)]))
+    }
+
+    /// The queue in the order it plays and the index of the current entry
+    fn play_order(&self) -> Result<(Vec<(QueuePos, QueueId)>, Option<usize>, Modes)> {
+        let (current, modes) = self.db.query_one(
+            "SELECT current, random, repeat, single, consume FROM state",
+            [],
+            |row| {
+                let modes = Modes {
+                    random: row.get(1)?,
+                    repeat: row.get(2)?,
+                    single: row.get(3)?,
+                    consume: row.get(4)?,
+                };
+                Ok((row.get::<_, Option<u32>>(0)?, modes))
+            },
+        )?;
+        let order: Vec<(QueuePos, QueueId)> = self
+            .db
+            .prepare(if modes.random {
+                // entries missing from the play order go last
+                "SELECT q.position, q.id FROM queue q
+                 LEFT JOIN play_order o ON o.id = q.id
+                 ORDER BY o.idx IS NULL, o.idx, q.position"
+            } else {
+                "SELECT position, id FROM queue ORDER BY position"
+            })?
+            .query_map([], |row| Ok((QueuePos(row.get(0)?), QueueId(row.get(1)?))))?
+            .try_collect()?;
+        let current = order.iter().position(|(pos, _)| Some(pos.0) == current);
+        Ok((order, current, modes))
+    }
+}
+
+/// Index in the play order of the entry after `current`, for a play order of
+/// `len` entries.
+fn next_index(len: usize, current: usize, modes: Modes) -> Option<usize> {
+    let next = if modes.single {
+        // consume removes the song before it could be repeated
+        (modes.repeat && !modes.consume).then_some(current)?
+    } else if current + 1 < len {
+        current + 1
+    } else if modes.repeat {
+        0
+    } else {
+        return None;
+    };
+    // consume removes the current song once it finishes
+    (next != current || !modes.consume).then_some(next)
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use super::*;
+    use crate::system::empty_system;
+
+    /// Every combination of the mode flags, in the order of [`Modes`]
+    fn all_modes() -> impl Iterator<Item = Modes> {
+        (0..16u8).map(|bits| Modes {
+            random: bits & 1 != 0,
+            repeat: bits & 2 != 0,
+            single: bits & 4 != 0,
+            consume: bits & 8 != 0,
+        })
+    }
+
+    fn expected(len: usize, current: usize, modes: Modes) -> Option<usize> {
+        match (modes.single, modes.repeat, modes.consume) {
+            (true, true, false) => Some(current),
+            (true, _, _) => None,
+            (false, _, _) if current + 1 < len => Some(current + 1),
+            (false, false, _) => None,
+            (false, true, true) if len == 1 => None,
+            (false, true, _) => Some(0),
+        }
+    }
+
+    #[test]
+    fn next_index_for_all_modes() {
+        for modes in all_modes() {
+            for (len, current) in [(1, 0), (3, 0), (3, 1), (3, 2)] {
+                assert_eq!(
+                    next_index(len, current, modes),
+                    expected(len, current, modes),
+                    "{modes:?} at {current} of {len}"
+                );
+            }
+        }
+    }
+
+    fn system(queue_len: u32) -> System {
+        let system = empty_system(Connection::open_in_memory().unwrap());
+        for position in 1..=queue_len {
+            system
+                .db
+                .execute(
+                    "INSERT INTO songs (path, mtime) VALUES (?1, '0')",
+                    [format!("{position}.flac")],
+                )
+                .unwrap();
+            system
+                .db
+                .execute(
+                    "INSERT INTO queue_entries (song, key) VALUES (?1, ?1)",
+                    [position],
+                )
+                .unwrap();
+        }
+        system
+    }
+
+    fn set(system: &System, current: Option<u32>, modes: Modes) {
+        system
+            .db
+            .execute(
+                "UPDATE state
+                 SET current = ?1, random = ?2, repeat = ?3, single = ?4, consume = ?5",
+                rusqlite::params![
+                    current,
+                    modes.random,
+                    modes.repeat,
+                    modes.single,
+                    modes.consume
+                ],
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn nothing_is_next_when_stopped_or_empty() {
+        let mut system = system(3);
+        set(&system, Some(1), Modes::default());
+        assert_eq!(system.next_entry().unwrap(), None);
+
+        system.playing = PlaybackState::Play;
+        assert_eq!(
+            system.next_entry().unwrap(),
+            Some((QueuePos(2), QueueId(3)))
+        );
+
+        system.clear_queue().unwrap();
+        for modes in all_modes() {
+            set(&system, None, modes);
+            assert_eq!(system.next_entry().unwrap(), None, "{modes:?}");
+        }
+    }
+
+    #[test]
+    fn status_shows_the_next_entry() {
+        let mut system = system(3);
+        system.playing = PlaybackState::Play;
+        for modes in all_modes() {
+            for current in 0..3 {
+                set(&system, Some(current), modes);
+                let status = system.status().unwrap();
+                let next = system.next_entry().unwrap();
+                assert_eq!(status.song, Some(QueuePos(current)));
+                assert_eq!(status.nextsong, next.map(|(pos, _)| pos));
+                assert_eq!(status.nextsongid, next.map(|(_, id)| id));
+            }
+        }
+    }
+
+    #[test]
+    fn random_plays_every_entry_once_per_round() {
+        let mut system = system(5);
+        system.playing = PlaybackState::Play;
+        let modes = Modes {
+            random: true,
+            repeat: true,
+            ..Modes::default()
+        };
+        set(
+            &system,
+            Some(0),
+            Modes {
+                random: false,
+                ..modes
+            },
+        );
+        system.set_random(true).unwrap();
+
+        let mut played = vec![0];
+        for _ in 0..5 {
+            let (pos, _) = system.next_entry().unwrap().unwrap();
+            played.push(pos.0);
+            set(&system, Some(pos.0), modes);
+        }
+        assert_eq!(played.first(), played.last(), "repeat wraps around");
+        played.pop();
+        assert_eq!(played.iter().sorted().collect_vec(), [&0, &1, &2, &3, &4]);
+    }
+
+    #[test]
+    fn gaps_in_keys_do_not_hide_the_next_entry() {
+        let mut system = system(3);
+        system.playing = PlaybackState::Play;
+        system
+            .db
+            .execute("DELETE FROM queue_entries WHERE key = 2", [])
+            .unwrap();
+        set(&system, Some(0), Modes::default());
+        assert_eq!(
+            system.next_entry().unwrap(),
+            Some((QueuePos(1), QueueId(3)))
+        );
+    }
+}
@@ -0,0 +1,168 @@
+//! Tags that replace the scanned ones of a queue entry without touching the
+//! database, like the title a stream sends for every song it plays.
+//!
+//! They live as long as the entry is current, starting another song drops
+//! them.
+
+use color_eyre::Result;
+use rodio::fixed_source::queue::uniform::SourceId;
+use rusqlite::OptionalExtension;
+
+use crate::mpd_protocol::{QueueEntry, QueueId, SubSystem, Tag};
+use crate::system::System;
+
+impl System {
+    /// Tags the player reported for `source`, ignored once another song
+    /// took over
+    pub(crate) fn stream_tags(&mut self, source: SourceId, tags: Vec<(Tag, String)>) -> Result<()> {
+        if self.current_source != Some(source) {
+            return Ok(());
+        }
+        let current = self
+            .db
+            .query_one(
+                "SELECT q.id FROM state JOIN queue q ON q.position = state.current",
+                [],
+                |row| row.get(0).map(QueueId),
+            )
+            .optional()?;
+        let Some(current) = current else {
+            return Ok(());
+        };
+        let overrides = self.tag_overrides.entry(current).or_default();
+        overrides.retain(|(tag, _)| !tags.iter().any(|(new, _)| new == tag));
+        overrides.extend(tags);
+        self.notify(SubSystem::Player);
+        Ok(())
+    }
+}
+
+/// Puts the overrides of `entry` in place of its scanned tags
+pub(crate) fn apply(entry: &mut QueueEntry, overrides: &[(Tag, String)]) {
+    let values = |wanted: Tag| {
+        overrides
+            .iter()
+            .filter(move |(tag, _)| *tag == wanted)
+            .map(|(_, value)| value.clone())
+    };
+    if let Some(title) = values(Tag::Title).last() {
+        entry.title = Some(title);
+    }
+    if let Some(album) = values(Tag::Album).last() {
+        entry.album = Some(album);
+    }
+    let artists: Vec<_> = values(Tag::Artist).collect();
+    if !artists.is_empty() {
+        entry.artist = artists;
+    }
+    let genres: Vec<_> = values(Tag::Genre).collect();
+    if !genres.is_empty() {
+        entry.genre = genres;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use camino::Utf8PathBuf;
+    use rusqlite::Connection;
+
+    use super::*;
+    use crate::player::device::Silent;
+    use crate::player::tests::write_wav;
+    use crate::player::{OutputEvent, Player};
+    use crate::system::playback::Target;
+
+    #[tokio::test]
+    async fn stream_tags_show_up_in_currentsong() {
+        let music_dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mpdhaj-stream-tags-{}", std::process::id()));
+        std::fs::create_dir_all(&music_dir).unwrap();
+        let mut system = System::with_parts(
+            Connection::open_in_memory().unwrap(),
+            |volume, paused| Player::with_device(volume, paused, Silent),
+            music_dir.clone(),
+            None,
+        )
+        .unwrap();
+        for name in ["stream.wav", "next.wav"] {
+            write_wav(&music_dir.join(name), Duration::from_secs(3));
+            system
+                .db
+                .execute(
+                    "INSERT INTO songs (path, mtime, title) VALUES (?1, 0, 'scanned')",
+                    [name],
+                )
+                .unwrap();
+        }
+        system
+            .add_many_to_queue(&["stream.wav".into(), "next.wav".into()], None)
+            .unwrap();
+        let mut events = system.player.take_output_events().unwrap();
+        system.set_playback(Target::Play).await.unwrap();
+        let mut idle = system.idle(vec![SubSystem::Player]);
+
+        // stands in for a stream decoder
+        let sender = system.player.tag_sender(system.current_source.unwrap());
+        let stream = std::thread::spawn(move || {
+            for title in ["first", "second"] {
+                sender.send(vec![
+                    (Tag::Title, title.to_owned()),
+                    (Tag::Artist, "DJ".to_owned()),
+                ]);
+            }
+        });
+        stream.join().unwrap();
+
+        for title in ["first", "second"] {
+            loop {
+                let event = events.recv().await.unwrap();
+                let tags = matches!(event, OutputEvent::Tags { .. });
+                system.handle_output_event(event).await;
+                if tags {
+                    break;
+                }
+            }
+            assert_eq!(idle.try_recv(), Ok(SubSystem::Player));
+            let current = system.current_song().unwrap().unwrap();
+            assert_eq!(current.title.as_deref(), Some(title));
+            assert_eq!(current.artist, ["DJ"]);
+        }
+        let scanned: String = system
+            .db
+            .query_one(
+                "SELECT title FROM songs WHERE path = 'stream.wav'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(scanned, "scanned");
+
+        // the next song starts without them
+        let old = system.player.tag_sender(system.current_source.unwrap());
+        system.next().await.unwrap();
+        assert!(system.tag_overrides.is_empty());
+        assert_eq!(
+            system.current_song().unwrap().unwrap().title.as_deref(),
+            Some("scanned")
+        );
+        // tags of a song that no longer plays are ignored
+        old.send(vec![(Tag::Title, "late".to_owned())]);
+        loop {
+            let event = events.recv().await.unwrap();
+            let tags = matches!(event, OutputEvent::Tags { .. });
+            system.handle_output_event(event).await;
+            if tags {
+                break;
+            }
+        }
+        assert_eq!(
+            system.current_song().unwrap().unwrap().title.as_deref(),
+            Some("scanned")
+        );
+
+        std::fs::remove_dir_all(&music_dir).unwrap();
+    }
+}
@@ -0,0 +1,315 @@
+//! The order random mode plays the queue in.
+//!
+//! Like mpd's it is shuffled when random is turned on and then stays put.
+//! Added entries get a random spot among the ones that did not play yet and
+//! removed ones leave the rest where they were, so the upcoming songs do not
+//! change every time the queue does. Entries with a higher priority play
+//! before the others that did not play yet.
+
+use std::cmp::Reverse;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use color_eyre::Result;
+use itertools::Itertools;
+use rusqlite::{Connection, OptionalExtension};
+
+use crate::mpd_protocol::{QueueId, SubSystem};
+use crate::system::System;
+
+/// xorshift, seeded so tests can predict the order
+#[derive(Debug)]
+pub struct Rng(AtomicU64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // xorshift never leaves zero
+        Self(AtomicU64::new(seed.max(1)))
+    }
+
+    /// `n` must not be zero
+    pub fn below(&self, n: usize) -> usize {
+        let mut x = self.0.load(Ordering::Relaxed);
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.0.store(x, Ordering::Relaxed);
+        (x % n as u64) as usize
+    }
+}
+
+/// A queue entry and its priority
+type Entry = (QueueId, u8);
+
+impl System {
+    /// Turning random on shuffles the play order, the current song stays
+    /// current and everything else plays after it.
+    pub fn set_random(&mut self, random: bool) -> Result<()> {
+        let was: bool = self
+            .db
+            .query_one("SELECT random FROM state", [], |row| row.get(0))?;
+        if was == random {
+            return Ok(());
+        }
+        let t = self.db.unchecked_transaction()?;
+        t.execute("UPDATE state SET random = ?", [random])?;
+        if random {
+            shuffle(&t, &self.rng)?;
+        } else {
+            t.execute("DELETE FROM play_order", [])?;
+        }
+        t.commit()?;
+        self.notify(SubSystem::Options);
+        self.prefetch_next();
+        Ok(())
+    }
+
+    /// Changes the priority of the queue entries, in random mode the ones
+    /// that did not play yet are sorted by it.
+    pub fn set_priority(&mut self, prio: u8, ids: &[QueueId]) -> Result<()> {
+        let t = self.db.unchecked_transaction()?;
+        {
+            let mut update = t.prepare("UPDATE queue_entries SET prio = ?1 WHERE id = ?2")?;
+            for id in ids {
+                update.execute([u32::from(prio), id.0])?;
+            }
+        }
+        if random(&t)? {
+            let mut order = load(&t)?;
+            let from = upcoming(&order, current(&t)?);
+            sort_by_priority(&mut order[from..]);
+            store(&t, &order)?;
+        }
+        t.execute("UPDATE state SET queue_version = queue_version + 1", [])?;
+        t.commit()?;
+        self.notify(SubSystem::Playlist);
+        Ok(())
+    }
+}
+
+/// Gives queue entries that are not in the play order yet a random spot
+/// among the entries that did not play yet. Does nothing outside of random
+/// mode.
+pub(crate) fn update(db: &Connection, rng: &Rng) -> Result<()> {
+    if !random(db)? {
+        return Ok(());
+    }
+    let mut order = load(db)?;
+    let missing: Vec<Entry> = db
+        .prepare(
+            "SELECT id, prio FROM queue
+             WHERE id NOT IN (SELECT id FROM play_order)
+             ORDER BY position",
+        )?
+        .query_map([], entry)?
+        .try_collect()?;
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let from = upcoming(&order, current(db)?);
+    for added in missing {
+        let spot = from + rng.below(order.len() - from + 1);
+        order.insert(spot, added);
+    }
+    sort_by_priority(&mut order[from..]);
+    store(db, &order)
+}
+
+/// A new play order with the current entry first, it has already played
+fn shuffle(db: &Connection, rng: &Rng) -> Result<()> {
+    let mut order: Vec<Entry> = db
+        .prepare("SELECT id, prio FROM queue ORDER BY position")?
+        .query_map([], entry)?
+        .try_collect()?;
+    let mut from = 0;
+    if let Some(current) = current(db)?
+        && let Some(i) = order.iter().position(|(id, _)| *id == current)
+    {
+        order[..=i].rotate_right(1);
+        from = 1;
+    }
+    // Fisher-Yates
+    for i in (from + 1..order.len()).rev() {
+        let j = from + rng.below(i - from + 1);
+        order.swap(i, j);
+    }
+    sort_by_priority(&mut order[from..]);
+    store(db, &order)
+}
+
+fn random(db: &Connection) -> Result<bool> {
+    Ok(db.query_one("SELECT random FROM state", [], |row| row.get(0))?)
+}
+
+fn current(db: &Connection) -> Result<Option<QueueId>> {
+    Ok(db
+        .query_one(
+            "SELECT q.id FROM state JOIN queue q ON q.position = state.current",
+            [],
+            |row| row.get(0).map(QueueId),
+        )
+        .optional()?)
+}
+
+/// Entries removed from the queue drop out here
+fn load(db: &Connection) -> Result<Vec<Entry>> {
+    Ok(db
+        .prepare(
+            "SELECT q.id, q.prio FROM play_order o
+             JOIN queue q ON q.id = o.id
+             ORDER BY o.idx",
+        )?
+        .query_map([], entry)?
+        .try_collect()?)
+}
+
+fn store(db: &Connection, order: &[Entry]) -> Result<()> {
+    db.execute("DELETE FROM play_order", [])?;
+    let mut insert = db.prepare("INSERT INTO play_order (id, idx) VALUES (?1, ?2)")?;
+    for (idx, (id, _)) in order.iter().enumerate() {
+        insert.execute([id.0, idx as u32])?;
+    }
+    Ok(())
+}
+
+fn entry(row: &rusqlite::Row) -> rusqlite::Result<Entry> {
+    Ok((QueueId(row.get(0)?), row.get(1)?))
+}
+
+/// Where the entries that did not play yet start
+fn upcoming(order: &[Entry], current: Option<QueueId>) -> usize {
+    current
+        .and_then(|current| order.iter().position(|(id, _)| *id == current))
+        .map_or(0, |i| i + 1)
+}
+
+/// Stable, the shuffled order stays among entries with the same priority
+fn sort_by_priority(entries: &mut [Entry]) {
+    entries.sort_by_key(|(_, prio)| Reverse(*prio));
+}
+
+#[cfg(test)]
+mod tests {
+    use camino::Utf8PathBuf;
+    use rusqlite::Connection;
+
+    use super::*;
+    use crate::mpd_protocol::PlaybackState;
+    use crate::system::empty_system;
+
+    /// `len` entries with ids 1 to `len`, the first one current
+    fn system(len: u32) -> System {
+        let mut system = empty_system(Connection::open_in_memory().unwrap());
+        system.rng = Rng::new(42);
+        system.playing = PlaybackState::Play;
+        add(&mut system, len);
+        system
+    }
+
+    fn add(system: &mut System, count: u32) -> Vec<QueueId> {
+        let paths = (0..count)
+            .map(|_| {
+                let path = system
+                    .db
+                    .query_one(
+                        "INSERT INTO songs (path, mtime)
+                         VALUES ((SELECT COUNT(*) FROM songs) || '.flac', 0)
+                         RETURNING path",
+                        [],
+                        |row| row.get::<_, String>(0),
+                    )
+                    .unwrap();
+                Utf8PathBuf::from(path)
+            })
+            .collect_vec();
+        system.add_many_to_queue(&paths, None).unwrap()
+    }
+
+    fn order(system: &System) -> Vec<u32> {
+        load(&system.db)
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id.0)
+            .collect()
+    }
+
+    fn set_current(system: &System, id: QueueId) {
+        system
+            .db
+            .execute(
+                "UPDATE state SET current = (SELECT position FROM queue WHERE id = ?1)",
+                [id.0],
+            )
+            .unwrap();
+    }
+
+    /// The ids random mode plays after the current one
+    fn played(system: &System) -> Vec<u32> {
+        let mut played = Vec::new();
+        while let Some((pos, id)) = system.next_entry().unwrap() {
+            played.push(id.0);
+            system
+                .db
+                .execute("UPDATE state SET current = ?1", [pos.0])
+                .unwrap();
+        }
+        played
+    }
+
+    #[test]
+    fn the_seed_decides_the_order() {
+        let mut system = system(6);
+        system.set_random(true).unwrap();
+        assert_eq!(order(&system), [1, 3, 4, 2, 5, 6]);
+        assert_eq!(played(&system), [3, 4, 2, 5, 6]);
+
+        // the current song goes in front
+        system.set_random(false).unwrap();
+        set_current(&system, QueueId(3));
+        system.set_random(true).unwrap();
+        assert_eq!(order(&system), [3, 6, 1, 2, 5, 4]);
+    }
+
+    #[test]
+    fn queue_changes_leave_the_upcoming_songs_alone() {
+        let mut system = system(6);
+        system.set_random(true).unwrap();
+        set_current(&system, QueueId(4));
+        let before = order(&system);
+
+        let added = add(&mut system, 3);
+        assert_eq!(added, [QueueId(7), QueueId(8), QueueId(9)]);
+        assert_eq!(order(&system), [1, 3, 4, 2, 9, 5, 8, 7, 6]);
+        let mut kept = order(&system);
+        kept.retain(|id| *id < 7);
+        assert_eq!(kept, before);
+
+        system
+            .db
+            .execute("DELETE FROM queue_entries WHERE id = 5", [])
+            .unwrap();
+        system.verify_queue().unwrap();
+        assert_eq!(order(&system), [1, 3, 4, 2, 9, 8, 7, 6]);
+        assert_eq!(played(&system), [2, 9, 8, 7, 6]);
+    }
+
+    #[test]
+    fn higher_priorities_play_first() {
+        let mut system = system(6);
+        system.set_random(true).unwrap();
+        assert_eq!(order(&system), [1, 3, 4, 2, 5, 6]);
+
+        system.set_priority(10, &[QueueId(2), QueueId(5)]).unwrap();
+        assert_eq!(order(&system), [1, 2, 5, 3, 4, 6]);
+
+        // the songs that already played are left alone
+        set_current(&system, QueueId(5));
+        system.set_priority(20, &[QueueId(1), QueueId(6)]).unwrap();
+        assert_eq!(order(&system), [1, 2, 5, 6, 3, 4]);
+
+        // an added song gets a shuffled spot after the higher priorities
+        assert_eq!(add(&mut system, 1), [QueueId(7)]);
+        assert_eq!(order(&system), [1, 2, 5, 6, 3, 7, 4]);
+        system.set_priority(30, &[QueueId(7)]).unwrap();
+        assert_eq!(order(&system), [1, 2, 5, 7, 6, 3, 4]);
+    }
+}
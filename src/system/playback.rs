@@ -0,0 +1,1346 @@
+//! Starts songs and moves on to the next queue entry when one ends or is
+//! skipped.
+//!
+//! The next entry is queued in the player ahead of time so it follows without
+//! a gap. Which entry that is comes from [`System::next_entry`], the same as
+//! `nextsong` in the status, and it is queued again whenever that changes.
+
+use std::time::Duration;
+
+use camino::Utf8PathBuf;
+use color_eyre::Result;
+use color_eyre::eyre::OptionExt;
+use jiff::Timestamp;
+use rodio::fixed_source::queue::uniform::SourceId;
+use rusqlite::OptionalExtension;
+
+use crate::mpd_protocol::ack::{Ack, ErrorCode};
+use crate::mpd_protocol::{PlaybackState, QueueId, QueuePos, SubSystem, TimeOrOffset};
+use crate::scan::decoders::DecodeError;
+use crate::system::{System, shift_current};
+
+/// A song that stops this much before its scanned duration did not decode to
+/// the end
+const EARLY_END: Duration = Duration::from_secs(2);
+/// Listening this long always counts as a play
+const PLAYED: Duration = Duration::from_secs(4 * 60);
+/// Songs shorter than this are never counted
+const SHORTEST_PLAYED: Duration = Duration::from_secs(30);
+/// `previous` starts the current song over once this much of it was heard
+const RESTART_AFTER: Duration = Duration::from_secs(3);
+
+/// What `play`, `pause` and `stop` ask for, see [`System::set_playback`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// Resume, or start the current song when stopped
+    Play,
+    /// Start the song at this position, resumes if it is the paused one
+    PlayAt(QueuePos),
+    /// Pause with `Some(true)`, resume with `Some(false)` and toggle with
+    /// None
+    Pause(Option<bool>),
+    Stop,
+}
+
+/// The least the player has to do to reach a [`Target`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    Start(QueuePos),
+    Resume,
+    Pause,
+    Stop,
+}
+
+impl System {
+    /// Moves between playing, paused and stopped the way mpd does. Pausing
+    /// while stopped does nothing and playing while paused resumes the song
+    /// where it was instead of starting it over.
+    pub async fn set_playback(&mut self, target: Target) -> Result<()> {
+        use PlaybackState::{Pause, Play, Stop};
+
+        let current = self
+            .db
+            .query_one("SELECT current FROM state", [], |row| {
+                row.get::<_, Option<u32>>(0)
+            })?
+            .map(QueuePos);
+        let step = match (self.playing, target) {
+            (Stop, Target::Play) => {
+                let queued: u32 = self
+                    .db
+                    .query_one("SELECT COUNT(*) FROM queue", [], |row| row.get(0))?;
+                if queued == 0 {
+                    return Ok(());
+                }
+                Step::Start(current.unwrap_or_default())
+            }
+            (Play, Target::Play) => return Ok(()),
+            (Pause, Target::Play) => Step::Resume,
+            (Pause, Target::PlayAt(pos)) if Some(pos) == current => Step::Resume,
+            (_, Target::PlayAt(pos)) => Step::Start(pos),
+            (Stop, Target::Pause(_) | Target::Stop) => return Ok(()),
+            (Play, Target::Pause(Some(false))) | (Pause, Target::Pause(Some(true))) => {
+                return Ok(());
+            }
+            (Play, Target::Pause(_)) => Step::Pause,
+            (Pause, Target::Pause(_)) => Step::Resume,
+            (_, Target::Stop) => Step::Stop,
+        };
+
+        self.playing = match step {
+            Step::Start(pos) => return self.start(pos).await,
+            Step::Resume => {
+                self.player.unpause();
+                Play
+            }
+            Step::Pause => {
+                self.player.pause();
+                Pause
+            }
+            Step::Stop => {
+                self.player.pause(); // TODO: actually stop?
+                Stop
+            }
+        };
+        self.db
+            .execute("UPDATE state SET paused = ?1", [self.playing == Pause])?;
+        self.notify(SubSystem::Player);
+        self.prefetch_next();
+        Ok(())
+    }
+
+    /// Skips to the entry after the current one. After the last entry
+    /// playback stops, unless repeat is on and it starts over. While stopped
+    /// only the current entry moves.
+    pub async fn next(&mut self) -> Result<()> {
+        let queued: u32 = self
+            .db
+            .query_one("SELECT COUNT(*) FROM queue", [], |row| row.get(0))?;
+        if queued == 0 {
+            return Err(Ack::new(ErrorCode::NoExist, "The queue is empty").into());
+        }
+        let Some((pos, _)) = self.entry_after_current(true)? else {
+            return self.set_playback(Target::Stop).await;
+        };
+        if self.playing == PlaybackState::Stop {
+            self.db.execute("UPDATE state SET current = ?1", [pos.0])?;
+            self.notify(SubSystem::Player);
+            return Ok(());
+        }
+        self.start(pos).await
+    }
+
+    /// Skips to the entry before the current one, or starts the current one
+    /// over if more than a few seconds of it were heard. The first entry
+    /// starts over too. While stopped only the current entry moves.
+    pub async fn previous(&mut self) -> Result<()> {
+        let queued: u32 = self
+            .db
+            .query_one("SELECT COUNT(*) FROM queue", [], |row| row.get(0))?;
+        if queued == 0 {
+            return Err(Ack::new(ErrorCode::NoExist, "The queue is empty").into());
+        }
+        let player = self.player.status();
+        let restart = self.playing != PlaybackState::Stop
+            && player.source.is_some()
+            && player.source == self.current_source
+            && player.heard() > RESTART_AFTER;
+        let current = self
+            .db
+            .query_one("SELECT current FROM state", [], |row| {
+                row.get::<_, Option<u32>>(0)
+            })?
+            .map(QueuePos);
+        let target = match (restart, current) {
+            (true, Some(current)) => Some(current),
+            _ => self.entry_before_current()?.map(|(pos, _)| pos),
+        };
+        let Some(pos) = target else {
+            return Ok(());
+        };
+        if self.playing == PlaybackState::Stop {
+            self.db.execute("UPDATE state SET current = ?1", [pos.0])?;
+            self.notify(SubSystem::Player);
+            return Ok(());
+        }
+        self.start(pos).await
+    }
+
+    /// `playid`, plays the entry with queue id `id` like `play` does with its
+    /// position. Without an id it is `play`.
+    pub async fn play_id(&mut self, id: Option<QueueId>) -> Result<()> {
+        let Some(id) = id else {
+            return self.set_playback(Target::Play).await;
+        };
+        let pos = self.position_of(id)?;
+        self.set_playback(Target::PlayAt(pos)).await
+    }
+
+    /// `seek`, `seekid` and `seekcur`, plays the entry at `pos` from `to` on.
+    /// Another entry is started first, the current one keeps playing or
+    /// stays paused. Without a position it is the current entry, which has
+    /// to be playing or paused. The song can not be left through either end.
+    pub async fn seek(&mut self, pos: Option<QueuePos>, to: TimeOrOffset) -> Result<()> {
+        let current = self
+            .db
+            .query_one("SELECT current FROM state", [], |row| {
+                row.get::<_, Option<u32>>(0)
+            })?
+            .map(QueuePos);
+        let has_current = self.playing != PlaybackState::Stop && self.current_source.is_some();
+        let pos = match pos {
+            Some(pos) => pos,
+            None if has_current => current.ok_or_eyre("Nothing is current")?,
+            None => return Err(Ack::new(ErrorCode::PlayerSync, "Not playing").into()),
+        };
+        if !has_current || Some(pos) != current {
+            self.start(pos).await?;
+        }
+        let source = self.current_source.ok_or_eyre("Nothing is playing")?;
+
+        let player = self.player.status();
+        let at = if player.source == Some(source) {
+            player.heard()
+        } else {
+            Duration::ZERO
+        };
+        let secs = match to {
+            TimeOrOffset::Absolute(secs) => secs as f64,
+            TimeOrOffset::Relative(offset) => at.as_secs_f64() + offset as f64,
+        };
+        let duration: Option<f64> = self.db.query_one(
+            "SELECT s.duration FROM queue q JOIN songs s ON s.rowid = q.song
+             WHERE q.position = ?1",
+            [pos.0],
+            |row| row.get(0),
+        )?;
+        let secs = secs.min(duration.unwrap_or(f64::INFINITY)).max(0.0);
+        let to = Duration::try_from_secs_f64(secs).unwrap_or_default();
+        self.player.seek(source, to).await?;
+        self.notify(SubSystem::Player);
+        Ok(())
+    }
+
+    /// `seekid`, [`System::seek`] for the entry with queue id `id`
+    pub async fn seek_id(&mut self, id: QueueId, to: TimeOrOffset) -> Result<()> {
+        let pos = self.position_of(id)?;
+        self.seek(Some(pos), to).await
+    }
+
+    /// Where the entry with queue id `id` is in the queue
+    pub(crate) fn position_of(&self, id: QueueId) -> Result<QueuePos> {
+        let pos = self
+            .db
+            .query_one(
+                "SELECT (SELECT COUNT(*) FROM queue_entries WHERE key < e.key)
+                 FROM queue_entries e WHERE id = ?1",
+                [id.0],
+                |row| row.get(0).map(QueuePos),
+            )
+            .optional()?;
+        pos.ok_or_else(|| Ack::new(ErrorCode::NoExist, "No such song").into())
+    }
+
+    /// Plays the queue entry at `pos` from the start. A song rodio can not
+    /// decode gets the decoder's message in its `decode_error` column and in
+    /// the status.
+    ///
+    /// While the player has to [open first](crate::player::Player::open_first)
+    /// this returns an [`OpenFirst`](crate::player::OpenFirst) before
+    /// changing anything.
+    pub(crate) async fn start(&mut self, pos: QueuePos) -> Result<()> {
+        let song = self
+            .song_by_pos(pos)?
+            .ok_or_eyre("Couldn't find song")?
+            .path;
+        let path = if song.is_absolute() {
+            song.clone()
+        } else {
+            self.music_dir.join(&song)
+        };
+        self.player.check_opened(&path)?;
+        let replaced = self.player.status();
+        if replaced.source.is_some() && replaced.source == self.current_source {
+            self.count_play(replaced.elapsed)?;
+        }
+
+        self.playing = PlaybackState::Play;
+        // they belonged to the song that played before
+        self.tag_overrides.clear();
+        self.db
+            .execute("UPDATE state SET paused = ?1, current = ?2", (false, pos.0))?;
+        // adding aborts the prefetched song too
+        self.prefetched = None;
+        let source = match self.player.add(&path).await {
+            Ok(source) => source,
+            Err(e) => {
+                if let Some(DecodeError(message)) = e.downcast_ref::<DecodeError>() {
+                    self.metrics.decode_failure();
+                    self.player
+                        .report_error(format!("Could not decode {song}: {message}"));
+                    self.db.execute(
+                        "UPDATE songs SET decode_error = ?2 WHERE path = ?1",
+                        (song.as_str(), message),
+                    )?;
+                }
+                return Err(e);
+            }
+        };
+        self.current_source = Some(source);
+        self.player.unpause();
+        self.notify(SubSystem::Player);
+        self.prefetch_next();
+        Ok(())
+    }
+
+    /// Queues the next entry in the player, cancelling one queued earlier
+    /// that is no longer next. Single mode without repeat has nothing to
+    /// queue, with repeat the current entry is queued again.
+    pub(crate) fn prefetch_next(&mut self) {
+        if let Err(e) = self.try_prefetch_next() {
+            tracing::warn!("Could not prefetch the next song: {e:#}");
+        }
+    }
+
+    fn try_prefetch_next(&mut self) -> Result<()> {
+        let next = self.next_entry()?;
+        let next_id = next.map(|(_, id)| id);
+        if self.prefetched.map(|(id, _)| id) == next_id {
+            return Ok(());
+        }
+        if self.prefetched.take().is_some() {
+            self.player.cancel_prefetch();
+        }
+        let Some((pos, id)) = next else {
+            return Ok(());
+        };
+        let path = self.entry_path(pos)?;
+        if let Some(source) = self.player.prefetch(&path)? {
+            self.prefetched = Some((id, source));
+        }
+        Ok(())
+    }
+
+    /// The prefetched entry took over from the current one without a gap,
+    /// [`System::advance_current`] already made it current
+    fn prefetch_took_over(&mut self, source: SourceId) {
+        self.player.prefetch_took_over();
+        self.current_source = Some(source);
+        self.tag_overrides.clear();
+        self.prefetch_next();
+    }
+
+    fn entry_path(&self, pos: QueuePos) -> Result<Utf8PathBuf> {
+        let song = self
+            .song_by_pos(pos)?
+            .ok_or_eyre("Couldn't find song")?
+            .path;
+        Ok(if song.is_absolute() {
+            song
+        } else {
+            self.music_dir.join(&song)
+        })
+    }
+
+    /// The current song stopped by itself, plays the next entry if there is
+    /// one. A song that ended well before its duration is reported and
+    /// counted in the `decode_failures` column.
+    pub(crate) async fn current_finished(&mut self, elapsed: Option<Duration>) -> Result<()> {
+        self.current_source = None;
+        let current = self
+            .db
+            .query_one(
+                "SELECT s.rowid, s.path, s.duration FROM state
+                    JOIN queue q ON q.position = state.current
+                    JOIN songs s ON s.rowid = q.song",
+                [],
+                |row| {
+                    Ok((
+                        row.get::<_, u32>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, Option<f64>>(2)?,
+                    ))
+                },
+            )
+            .optional()?;
+        let scanned = current
+            .as_ref()
+            .and_then(|(_, _, duration)| *duration)
+            .and_then(|duration| Duration::try_from_secs_f64(duration).ok());
+        if let Some((song, path, Some(duration))) = current
+            && let Ok(duration) = Duration::try_from_secs_f64(duration)
+            && let Some(elapsed) = elapsed
+            && elapsed + EARLY_END < duration
+        {
+            tracing::warn!("{path} stopped after {elapsed:?} of {duration:?}, skipping it");
+            self.metrics.decode_failure();
+            self.player.report_error(format!(
+                "Could not decode all of {path}, skipped to the next song"
+            ));
+            self.db.execute(
+                "UPDATE songs SET decode_failures = decode_failures + 1 WHERE rowid = ?1",
+                [song],
+            )?;
+        }
+
+        // the next song took over without a gap, this one played to its end
+        let elapsed = elapsed.or(scanned);
+        if let Some(elapsed) = elapsed {
+            self.count_play(elapsed)?;
+        }
+
+        let next = self.next_entry()?.map(|(_, id)| id);
+        let next = match (next, self.advance_current(next)?) {
+            (Some(id), Some(_))
+                if self
+                    .prefetched
+                    .is_some_and(|(prefetched, _)| prefetched == id) =>
+            {
+                let (_, source) = self.prefetched.take().expect("just checked");
+                self.prefetch_took_over(source);
+                Ok(())
+            }
+            (_, Some(pos)) => self.start(pos).await,
+            (_, None) => {
+                self.playing = PlaybackState::Stop;
+                Ok(())
+            }
+        };
+        self.notify(SubSystem::Player);
+        next
+    }
+
+    /// Makes the entry with id `next` current and returns its position. In
+    /// consume mode the entry that was current leaves the queue in the same
+    /// transaction. Nothing sees the positions after it shift while
+    /// `current` still points at the old ones, and `next` is found by id so
+    /// entries added meanwhile can not make it the wrong one.
+    fn advance_current(&mut self, next: Option<QueueId>) -> Result<Option<QueuePos>> {
+        let t = self.db.unchecked_transaction()?;
+        let (current, single, consume) =
+            t.query_one("SELECT current, single, consume FROM state", [], |row| {
+                Ok((
+                    row.get::<_, Option<u32>>(0)?,
+                    row.get::<_, u8>(1)?,
+                    row.get::<_, u8>(2)?,
+                ))
+            })?;
+        let consumed = match current {
+            Some(current) if consume != 0 => {
+                t.execute(
+                    "DELETE FROM queue_entries WHERE id = (SELECT id FROM queue WHERE position = ?1)",
+                    [current],
+                )?;
+                shift_current(&t, current + 1, -1)?;
+                // the rest of the play order stays as it was shuffled
+                t.execute(
+                    "DELETE FROM play_order WHERE id NOT IN (SELECT id FROM queue)",
+                    [],
+                )?;
+                t.execute("UPDATE state SET queue_version = queue_version + 1", [])?;
+                true
+            }
+            _ => false,
+        };
+        // oneshot, see `System::set_consume`
+        let consume_ended = consumed && consume == 2;
+        if consume_ended {
+            t.execute("UPDATE state SET consume = 0", [])?;
+        }
+        // oneshot, see `System::set_single`, it held for the song that ended
+        let single_ended = current.is_some() && single == 2;
+        if single_ended {
+            t.execute("UPDATE state SET single = 0", [])?;
+        }
+        let next = match next {
+            Some(id) => t
+                .query_one(
+                    "SELECT (SELECT COUNT(*) FROM queue_entries WHERE key < e.key)
+                     FROM queue_entries e WHERE id = ?1",
+                    [id.0],
+                    |row| row.get(0).map(QueuePos),
+                )
+                .optional()?,
+            None => None,
+        };
+        // without consume a stopped queue keeps its last song current
+        if next.is_some() || consumed {
+            t.execute("UPDATE state SET current = ?1", [next.map(|pos| pos.0)])?;
+        }
+        t.commit()?;
+        if consumed {
+            self.notify(SubSystem::Playlist);
+        }
+        // nobody asked for this change, clients only hear of it here
+        if consume_ended || single_ended {
+            self.notify(SubSystem::Options);
+        }
+        Ok(next)
+    }
+
+    /// Counts a play of the current song if enough of it was heard, and
+    /// queues it for the scrobbler
+    fn count_play(&mut self, elapsed: Duration) -> Result<()> {
+        let current = self
+            .db
+            .query_one(
+                "SELECT s.rowid, s.duration FROM state
+                    JOIN queue q ON q.position = state.current
+                    JOIN songs s ON s.rowid = q.song",
+                [],
+                |row| Ok((row.get::<_, u32>(0)?, row.get::<_, Option<f64>>(1)?)),
+            )
+            .optional()?;
+        let Some((song, duration)) = current else {
+            return Ok(());
+        };
+        let duration = duration.and_then(|duration| Duration::try_from_secs_f64(duration).ok());
+        if !counts_as_played(elapsed, duration) {
+            return Ok(());
+        }
+
+        self.db.execute(
+            "UPDATE songs SET play_count = coalesce(play_count, 0) + 1 WHERE rowid = ?1",
+            [song],
+        )?;
+        if self.scrobbling {
+            let started = Timestamp::now().as_second() - elapsed.as_secs() as i64;
+            self.db.execute(
+                "INSERT INTO scrobbles
+                    (listened_at, artist, title, album, duration, musicbrainz_track_id)
+                    SELECT ?2, artist, title, album, duration, musicbrainz_track_id
+                    FROM songs WHERE rowid = ?1 AND artist IS NOT NULL AND title IS NOT NULL",
+                (song, started),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Half the song or four minutes, whichever comes first. The rule
+/// ListenBrainz and Last.fm use.
+fn counts_as_played(elapsed: Duration, duration: Option<Duration>) -> bool {
+    match duration {
+        Some(duration) if duration < SHORTEST_PLAYED => false,
+        Some(duration) => elapsed >= PLAYED || elapsed >= duration / 2,
+        None => elapsed >= PLAYED,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+    use std::sync::Arc;
+
+    use camino::Utf8PathBuf;
+    use rusqlite::Connection;
+    use tokio::sync::{Mutex, Notify};
+
+    use super::*;
+    use crate::mpd_protocol::{ConsumeState, Position, SingleState, ack};
+    use crate::player::device::{Device, Silent};
+    use crate::player::tests::{FastForward, write_wav};
+    use crate::player::{OutputEvent, Player};
+    use crate::scan::decoders::{Undecodable, undecodable};
+    use crate::scan::tests::{write_opus, write_song};
+    use crate::system::empty_system;
+
+    #[tokio::test]
+    async fn next_skips_along_the_queue() {
+        let music_dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mpdhaj-next-{}", std::process::id()));
+        std::fs::create_dir_all(&music_dir).unwrap();
+        let mut system = System::with_parts(
+            Connection::open_in_memory().unwrap(),
+            |volume, paused| Player::with_device(volume, paused, Silent),
+            music_dir.clone(),
+            None,
+        )
+        .unwrap();
+        let empty = system.next().await.unwrap_err();
+        assert_eq!(ack::find(&empty).unwrap().code, ErrorCode::NoExist);
+
+        let mut songs = Vec::new();
+        for name in ["a.wav", "b.wav", "c.wav"] {
+            write_wav(&music_dir.join(name), Duration::from_secs(3));
+            system
+                .db
+                .execute("INSERT INTO songs (path, mtime) VALUES (?1, 0)", [name])
+                .unwrap();
+            songs.push(name.into());
+        }
+        system.add_many_to_queue(&songs, None).unwrap();
+        let mut idle = system.idle(vec![SubSystem::Player]);
+
+        // stopped only moves the current entry
+        system.next().await.unwrap();
+        assert_eq!(system.status().unwrap().song, Some(QueuePos(1)));
+        assert_eq!(system.playing, PlaybackState::Stop);
+        assert_eq!(idle.try_recv(), Ok(SubSystem::Player));
+
+        system.set_playback(Target::Play).await.unwrap();
+        let started = system.current_source;
+        system.next().await.unwrap();
+        assert_eq!(system.status().unwrap().song, Some(QueuePos(2)));
+        assert_eq!(system.playing, PlaybackState::Play);
+        assert_ne!(system.current_source, started);
+
+        // past the last entry
+        system.next().await.unwrap();
+        assert_eq!(system.playing, PlaybackState::Stop);
+
+        system.set_repeat(true).unwrap();
+        system.set_playback(Target::Play).await.unwrap();
+        system.next().await.unwrap();
+        assert_eq!(system.status().unwrap().song, Some(QueuePos(0)));
+        assert_eq!(system.playing, PlaybackState::Play);
+
+        std::fs::remove_dir_all(&music_dir).unwrap();
+    }
+
+    fn wav_system(name: &str, songs: &[&str], device: impl Device) -> (System, Utf8PathBuf) {
+        let music_dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mpdhaj-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&music_dir).unwrap();
+        let system = System::with_parts(
+            Connection::open_in_memory().unwrap(),
+            |volume, paused| Player::with_device(volume, paused, device),
+            music_dir.clone(),
+            None,
+        )
+        .unwrap();
+        for song in songs {
+            write_wav(&music_dir.join(song), Duration::from_secs(10));
+            system
+                .db
+                .execute("INSERT INTO songs (path, mtime) VALUES (?1, 0)", [song])
+                .unwrap();
+        }
+        (system, music_dir)
+    }
+
+    #[tokio::test]
+    async fn previous_goes_back_until_the_first_entry() {
+        let (mut system, music_dir) = wav_system("previous", &["a.wav", "b.wav", "c.wav"], Silent);
+        let empty = system.previous().await.unwrap_err();
+        assert_eq!(ack::find(&empty).unwrap().code, ErrorCode::NoExist);
+
+        let songs = ["a.wav".into(), "b.wav".into(), "c.wav".into()];
+        system.add_many_to_queue(&songs, None).unwrap();
+        let mut idle = system.idle(vec![SubSystem::Player]);
+        system.previous().await.unwrap();
+        assert_eq!(system.status().unwrap().song, Some(QueuePos(0)));
+        assert_eq!(system.playing, PlaybackState::Stop);
+        assert_eq!(idle.try_recv(), Ok(SubSystem::Player));
+
+        system
+            .set_playback(Target::PlayAt(QueuePos(2)))
+            .await
+            .unwrap();
+        system.previous().await.unwrap();
+        assert_eq!(system.status().unwrap().song, Some(QueuePos(1)));
+        assert_eq!(system.playing, PlaybackState::Play);
+        system.previous().await.unwrap();
+        let first = system.current_source;
+        system.previous().await.unwrap();
+        assert_eq!(system.status().unwrap().song, Some(QueuePos(0)));
+        assert_ne!(system.current_source, first, "the first entry starts over");
+
+        std::fs::remove_dir_all(&music_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn previous_starts_a_song_over_once_it_was_heard() {
+        let (mut system, music_dir) = wav_system("restart", &["a.wav", "b.wav"], FastForward);
+        system
+            .add_many_to_queue(&["a.wav".into(), "b.wav".into()], None)
+            .unwrap();
+        system
+            .set_playback(Target::PlayAt(QueuePos(1)))
+            .await
+            .unwrap();
+        let started = system.current_source;
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while system.player.status().heard() <= RESTART_AFTER {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("fast forward plays ten times as fast");
+
+        system.previous().await.unwrap();
+        assert_eq!(system.status().unwrap().song, Some(QueuePos(1)));
+        assert_ne!(system.current_source, started);
+
+        std::fs::remove_dir_all(&music_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn playid_plays_the_entry_with_that_id() {
+        let (mut system, music_dir) = wav_system("playid", &["a.wav", "b.wav"], Silent);
+        let ids = system
+            .add_many_to_queue(&["a.wav".into(), "b.wav".into()], None)
+            .unwrap();
+        let unknown = system.play_id(Some(QueueId(999))).await.unwrap_err();
+        let unknown = ack::find(&unknown).unwrap();
+        assert_eq!(
+            (unknown.code, unknown.message.as_str()),
+            (ErrorCode::NoExist, "No such song")
+        );
+        assert_eq!(system.playing, PlaybackState::Stop);
+
+        system.play_id(Some(ids[1])).await.unwrap();
+        let status = system.status().unwrap();
+        assert_eq!(status.state, PlaybackState::Play);
+        assert_eq!(status.song, Some(QueuePos(1)));
+        assert!(system.current_source.is_some());
+
+        // without an id the paused song resumes
+        system
+            .set_playback(Target::Pause(Some(true)))
+            .await
+            .unwrap();
+        let paused = system.current_source;
+        system.play_id(None).await.unwrap();
+        assert_eq!(system.playing, PlaybackState::Play);
+        assert_eq!(system.current_source, paused);
+        assert_eq!(system.status().unwrap().song, Some(QueuePos(1)));
+
+        std::fs::remove_dir_all(&music_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn deleting_the_playing_entry_plays_the_next() {
+        let (mut system, music_dir) = wav_system("delete", &["a.wav", "b.wav", "c.wav"], Silent);
+        let ids = system
+            .add_many_to_queue(&["a.wav".into(), "b.wav".into(), "c.wav".into()], None)
+            .unwrap();
+        system.play_id(Some(ids[1])).await.unwrap();
+        let playing = system.current_source;
+
+        system.delete_id(ids[1]).await.unwrap();
+        let status = system.status().unwrap();
+        assert_eq!(status.state, PlaybackState::Play);
+        assert_eq!(
+            (status.song, status.songid),
+            (Some(QueuePos(1)), Some(ids[2]))
+        );
+        assert_ne!(system.current_source, playing);
+
+        // nothing follows the last entry
+        system.delete_id(ids[2]).await.unwrap();
+        let status = system.status().unwrap();
+        assert_eq!(status.state, PlaybackState::Stop);
+        assert_eq!(status.song, None);
+        assert_eq!(system.current_source, None);
+
+        std::fs::remove_dir_all(&music_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn status_has_the_time_only_while_playing_or_paused() {
+        let (mut system, music_dir) = wav_system("time", &["a.wav"], Silent);
+        system
+            .db
+            .execute("UPDATE songs SET duration = 10.0", [])
+            .unwrap();
+        system.add_many_to_queue(&["a.wav".into()], None).unwrap();
+        let status = system.status().unwrap();
+        assert_eq!((status.time, status.duration), (None, None));
+
+        system.set_playback(Target::Play).await.unwrap();
+        let status = system.status().unwrap();
+        assert_eq!(status.duration, Some(Duration::from_secs(10)));
+        assert_eq!(status.time.map(|(_, total)| total), status.duration);
+
+        system
+            .set_playback(Target::Pause(Some(true)))
+            .await
+            .unwrap();
+        assert!(system.status().unwrap().time.is_some());
+
+        system.set_playback(Target::Stop).await.unwrap();
+        let status = system.status().unwrap();
+        assert_eq!((status.time, status.duration), (None, None));
+
+        std::fs::remove_dir_all(&music_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn seek_moves_within_the_song_and_stays_inside_it() {
+        let (mut system, music_dir) = wav_system("seek", &["a.wav", "b.wav"], FastForward);
+        system
+            .db
+            .execute("UPDATE songs SET duration = 10.0", [])
+            .unwrap();
+        system
+            .add_many_to_queue(&["a.wav".into(), "b.wav".into()], None)
+            .unwrap();
+        let stopped = system.seek(None, TimeOrOffset::Relative(10.0)).await;
+        let stopped = stopped.unwrap_err();
+        assert_eq!(ack::find(&stopped).unwrap().code, ErrorCode::PlayerSync);
+        let unknown = system
+            .seek_id(QueueId(999), TimeOrOffset::Absolute(1.0))
+            .await;
+        assert_eq!(
+            ack::find(&unknown.unwrap_err()).unwrap().code,
+            ErrorCode::NoExist
+        );
+
+        system.set_playback(Target::Play).await.unwrap();
+        let mut idle = system.idle(vec![SubSystem::Player]);
+        system
+            .seek(None, TimeOrOffset::Absolute(5.0))
+            .await
+            .unwrap();
+        assert_eq!(idle.try_recv(), Ok(SubSystem::Player));
+        let elapsed = system.status().unwrap().elapsed.unwrap();
+        assert!(
+            (Duration::from_secs(5)..Duration::from_secs(8)).contains(&elapsed),
+            "elapsed: {elapsed:?}"
+        );
+
+        // paused nothing moves on, the status shows exactly where it seeked to
+        system
+            .set_playback(Target::Pause(Some(true)))
+            .await
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !system.player.status().paused {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        system
+            .seek(None, TimeOrOffset::Relative(-10.0))
+            .await
+            .unwrap();
+        assert_eq!(system.status().unwrap().elapsed, Some(Duration::ZERO));
+        system
+            .seek(None, TimeOrOffset::Relative(10.0))
+            .await
+            .unwrap();
+        assert_eq!(
+            system.status().unwrap().elapsed,
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(system.status().unwrap().song, Some(QueuePos(0)));
+
+        std::fs::remove_dir_all(&music_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn the_prefetched_entry_is_nextsong() {
+        let (mut system, music_dir) = wav_system("prefetch", &["a.wav", "b.wav"], Silent);
+        system
+            .add_many_to_queue(&["a.wav".into(), "b.wav".into()], None)
+            .unwrap();
+        let ids: Vec<QueueId> = system
+            .db
+            .prepare("SELECT id FROM queue ORDER BY position")
+            .unwrap()
+            .query_map([], |row| row.get(0).map(QueueId))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        let prefetched = |system: &System| {
+            let prefetched = system.prefetched.map(|(id, _)| id);
+            let next = system.next_entry().unwrap().map(|(_, id)| id);
+            assert_eq!(prefetched, next, "the prefetch follows nextsong");
+            prefetched
+        };
+        assert_eq!(prefetched(&system), None);
+
+        system.set_playback(Target::Play).await.unwrap();
+        assert_eq!(prefetched(&system), Some(ids[1]));
+        system.set_single(SingleState::On).unwrap();
+        assert_eq!(prefetched(&system), None);
+        system.set_repeat(true).unwrap();
+        assert_eq!(prefetched(&system), Some(ids[0]));
+        system.set_single(SingleState::Off).unwrap();
+        assert_eq!(prefetched(&system), Some(ids[1]));
+        system.set_playback(Target::Stop).await.unwrap();
+        assert_eq!(prefetched(&system), None);
+
+        std::fs::remove_dir_all(&music_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn single_and_repeat_decide_what_follows() {
+        // positions that played, at most three
+        let cases = [
+            (false, false, vec![0, 1]),
+            (true, false, vec![0]),
+            (true, true, vec![0, 0, 0]),
+            (false, true, vec![0, 1, 0]),
+        ];
+        for (single, repeat, expected) in cases {
+            let name = format!("modes-{single}-{repeat}");
+            let (mut system, music_dir) = wav_system(&name, &["a.wav", "b.wav"], FastForward);
+            system
+                .add_many_to_queue(&["a.wav".into(), "b.wav".into()], None)
+                .unwrap();
+            let single_state = if single { SingleState::On } else { SingleState::Off };
+            system.set_single(single_state).unwrap();
+            system.set_repeat(repeat).unwrap();
+            let mut events = system.player.take_output_events().unwrap();
+            system.set_playback(Target::Play).await.unwrap();
+
+            let case = format!("single: {single}, repeat: {repeat}");
+            let mut played = vec![system.status().unwrap().song.unwrap().0];
+            let mut sources = vec![system.current_source.unwrap()];
+            let mut finished = Vec::new();
+            while played.len() < 3 && system.playing == PlaybackState::Play {
+                let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+                    .await
+                    .expect("fast forward plays a song in a second")
+                    .unwrap();
+                if let OutputEvent::Finished { source, .. } = event {
+                    finished.push(source);
+                }
+                let prefetched = system.prefetched.map(|(_, source)| source);
+                system.handle_output_event(event).await;
+                if let Some(source) = system.current_source
+                    && Some(&source) != sources.last()
+                {
+                    assert_eq!(Some(source), prefetched, "{case}: took over without a gap");
+                    played.push(system.status().unwrap().song.unwrap().0);
+                    sources.push(source);
+                }
+            }
+            assert_eq!(played, expected, "{case}");
+            // nothing else got queued
+            assert!(
+                finished.iter().all(|source| sources.contains(source)),
+                "{case}"
+            );
+            std::fs::remove_dir_all(&music_dir).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn consume_survives_an_add_during_the_transition() {
+        let songs = ["a.wav", "b.wav", "c.wav", "x.wav"];
+        let (mut system, music_dir) = wav_system("consume", &songs, FastForward);
+        let ids = system
+            .add_many_to_queue(&["a.wav".into(), "b.wav".into(), "c.wav".into()], None)
+            .unwrap();
+        system
+            .db
+            .execute("UPDATE state SET consume = 1", [])
+            .unwrap();
+        let mut events = system.player.take_output_events().unwrap();
+        system.set_playback(Target::Play).await.unwrap();
+        let first = system.current_source.unwrap();
+        let (_, second) = system.prefetched.unwrap();
+        let system = Arc::new(Mutex::new(system));
+
+        // lands after the player finished `a` but before the system hears
+        // of it, inserting ahead of it so every position shifts
+        let (add, added) = (Arc::new(Notify::new()), Arc::new(Notify::new()));
+        let findadd = tokio::spawn({
+            let (system, add, added) = (Arc::clone(&system), Arc::clone(&add), Arc::clone(&added));
+            async move {
+                add.notified().await;
+                let x = ["x.wav".into()];
+                let mut system = system.lock().await;
+                system
+                    .add_many_to_queue(&x, Some(Position::Absolute(0)))
+                    .unwrap();
+                added.notify_one();
+            }
+        });
+        loop {
+            let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+                .await
+                .expect("fast forward plays a song in a second")
+                .unwrap();
+            if let OutputEvent::Finished { source, .. } = event
+                && source == first
+            {
+                add.notify_one();
+                added.notified().await;
+            }
+            let mut system = system.lock().await;
+            system.handle_output_event(event).await;
+            if system.current_source != Some(first) {
+                break;
+            }
+        }
+        findadd.await.unwrap();
+
+        let system = system.lock().await;
+        assert_eq!(system.status().unwrap().songid, Some(ids[1]));
+        assert_eq!(system.current_source, Some(second));
+        let queued: Vec<_> = system
+            .queue()
+            .unwrap()
+            .0
+            .into_iter()
+            .map(|entry| entry.path.to_string())
+            .collect();
+        assert_eq!(queued, ["x.wav", "b.wav", "c.wav"]);
+        assert!(!system.verify_queue().unwrap(), "no gaps");
+        assert_eq!(system.next_entry().unwrap().map(|(_, id)| id), Some(ids[2]));
+
+        std::fs::remove_dir_all(&music_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn consume_removes_played_songs_and_oneshot_only_one() {
+        let cases = [
+            (ConsumeState::On, vec![]),
+            (ConsumeState::Oneshot, vec!["b.wav", "c.wav"]),
+        ];
+        for (consume, left) in cases {
+            let name = format!("consume-{consume:?}");
+            let songs = ["a.wav", "b.wav", "c.wav"];
+            let (mut system, music_dir) = wav_system(&name, &songs, FastForward);
+            let paths = songs.map(Utf8PathBuf::from);
+            system.add_many_to_queue(&paths, None).unwrap();
+            system.set_consume(consume).unwrap();
+            let mut options = system.idle(vec![SubSystem::Options]);
+            let mut events = system.player.take_output_events().unwrap();
+            system.set_playback(Target::Play).await.unwrap();
+
+            while system.playing == PlaybackState::Play {
+                let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+                    .await
+                    .expect("fast forward plays a song in a second")
+                    .unwrap();
+                system.handle_output_event(event).await;
+            }
+            let queued: Vec<_> = system
+                .queue()
+                .unwrap()
+                .0
+                .into_iter()
+                .map(|entry| entry.path.to_string())
+                .collect();
+            assert_eq!(queued, left, "{consume:?}");
+            let after = match consume {
+                ConsumeState::Oneshot => ConsumeState::Off,
+                other => other,
+            };
+            assert_eq!(system.status().unwrap().consume, after, "{consume:?}");
+            let oneshot_ended = options.try_recv().is_ok();
+            assert_eq!(oneshot_ended, consume == ConsumeState::Oneshot);
+
+            std::fs::remove_dir_all(&music_dir).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn a_cancelled_prefetch_is_never_heard() {
+        let (mut system, music_dir) = wav_system("cancel", &["a.wav", "b.wav"], FastForward);
+        system
+            .add_many_to_queue(&["a.wav".into(), "b.wav".into()], None)
+            .unwrap();
+        let mut events = system.player.take_output_events().unwrap();
+        system.set_playback(Target::Play).await.unwrap();
+        let playing = system.current_source.unwrap();
+        let (_, cancelled) = system.prefetched.unwrap();
+
+        system.set_single(SingleState::On).unwrap();
+        assert_eq!(system.prefetched, None);
+        let mut finished = Vec::new();
+        while finished.len() < 2 {
+            let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+                .await
+                .expect("the queue reports the cancelled song finished too")
+                .unwrap();
+            if let OutputEvent::Finished { source, elapsed } = event {
+                finished.push((source, elapsed.is_some()));
+            }
+            system.handle_output_event(event).await;
+        }
+        // only a song that was the playing one has an elapsed time
+        assert_eq!(finished, [(playing, true), (cancelled, false)]);
+        let status = system.status().unwrap();
+        assert_eq!(status.state, PlaybackState::Stop);
+        assert_eq!(status.song, Some(QueuePos(0)));
+
+        std::fs::remove_dir_all(&music_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_song_that_ends_early_is_skipped() {
+        let music_dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mpdhaj-truncated-{}", std::process::id()));
+        std::fs::create_dir_all(&music_dir).unwrap();
+        // the header promises three seconds, the data stops after half of one
+        write_wav(&music_dir.join("truncated.wav"), Duration::from_secs(3));
+        OpenOptions::new()
+            .write(true)
+            .open(music_dir.join("truncated.wav"))
+            .unwrap()
+            .set_len(44 + 44100 * 4 / 2)
+            .unwrap();
+        write_wav(&music_dir.join("next.wav"), Duration::from_secs(3));
+
+        let mut system = System::with_parts(
+            Connection::open_in_memory().unwrap(),
+            |volume, paused| Player::with_device(volume, paused, FastForward),
+            music_dir.clone(),
+            None,
+        )
+        .unwrap();
+        system
+            .db
+            .execute_batch(
+                "INSERT INTO songs (path, mtime, duration) VALUES
+                    ('truncated.wav', 0, 3.0), ('next.wav', 0, 3.0);",
+            )
+            .unwrap();
+        system
+            .add_many_to_queue(&["truncated.wav".into(), "next.wav".into()], None)
+            .unwrap();
+        let mut events = system.player.take_output_events().unwrap();
+        let mut idle = system.idle(vec![SubSystem::Player]);
+
+        system.player.unpause();
+        system.set_playback(Target::Play).await.unwrap();
+        let truncated = system.current_source;
+        loop {
+            let event = tokio::time::timeout(Duration::from_secs(5), events.recv())
+                .await
+                .expect("the truncated song should end")
+                .unwrap();
+            let finished = matches!(event, OutputEvent::Finished { .. });
+            system.handle_output_event(event).await;
+            if finished {
+                break;
+            }
+        }
+
+        let error = system.status().unwrap().error.unwrap();
+        assert!(error.contains("truncated.wav"), "{error}");
+        let failures: u32 = system
+            .db
+            .query_one(
+                "SELECT decode_failures FROM songs WHERE path = 'truncated.wav'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(failures, 1);
+        assert_eq!(idle.try_recv(), Ok(SubSystem::Player));
+
+        let status = system.status().unwrap();
+        assert_eq!(status.state, PlaybackState::Play);
+        assert_eq!(status.song, Some(QueuePos(1)));
+        assert!(system.current_source.is_some());
+        assert_ne!(system.current_source, truncated);
+        std::fs::remove_dir_all(&music_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn songs_that_do_not_decode_are_flagged() {
+        let music_dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mpdhaj-undecodable-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&music_dir);
+        std::fs::create_dir_all(&music_dir).unwrap();
+        // lofty reads its tags, no decoder takes opus
+        write_opus(&music_dir.join("a.opus"));
+        write_song(&music_dir.join("b.wav"));
+
+        let mut system = System::with_parts(
+            Connection::open_in_memory().unwrap(),
+            |volume, paused| Player::with_device(volume, paused, Silent),
+            music_dir.clone(),
+            None,
+        )
+        .unwrap();
+        system.rescan().await.unwrap();
+        let guessed = "No decoder for .opus files".to_owned();
+        assert_eq!(
+            undecodable(&system.db).unwrap(),
+            [Undecodable {
+                path: "a.opus".into(),
+                decode_error: guessed.clone(),
+            }]
+        );
+
+        system.add_many_to_queue(&["a.opus".into()], None).unwrap();
+        let error = system.set_playback(Target::Play).await.unwrap_err();
+        let flagged = undecodable(&system.db).unwrap();
+        assert_eq!(flagged.len(), 1);
+        let message = &flagged[0].decode_error;
+        assert_ne!(
+            message, &guessed,
+            "the decoder's message replaces the guess"
+        );
+        assert_eq!(&error.to_string(), message);
+        let status = system.status().unwrap().error.unwrap();
+        assert!(
+            status.contains("a.opus") && status.contains(message),
+            "{status}"
+        );
+        std::fs::remove_dir_all(&music_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_song_heard_to_the_end_is_counted_and_scrobbled() {
+        let mut system = System::with_parts(
+            Connection::open_in_memory().unwrap(),
+            |volume, paused| Player::with_device(volume, paused, FastForward),
+            "/nonexistent".into(),
+            None,
+        )
+        .unwrap();
+        system.scrobbling = true;
+        system
+            .db
+            .execute_batch(
+                "INSERT INTO songs (path, mtime, duration, artist, title) VALUES
+                    ('a.flac', 0, 200.0, 'Abba', 'Waterloo');
+                INSERT INTO queue_entries (song, key) VALUES (1, 1);
+                UPDATE state SET current = 0;",
+            )
+            .unwrap();
+
+        system
+            .current_finished(Some(Duration::from_secs(99)))
+            .await
+            .unwrap();
+        system
+            .current_finished(Some(Duration::from_secs(100)))
+            .await
+            .unwrap();
+        let plays: u32 = system
+            .db
+            .query_one("SELECT play_count FROM songs", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(plays, 1);
+        let scrobbled: (String, String) = system
+            .db
+            .query_one("SELECT artist, title FROM scrobbles", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(scrobbled, ("Abba".to_owned(), "Waterloo".to_owned()));
+    }
+
+    #[test]
+    fn short_songs_are_never_counted() {
+        let secs = Duration::from_secs;
+        assert!(!counts_as_played(secs(20), Some(secs(25))));
+        assert!(counts_as_played(secs(15), Some(secs(30))));
+        assert!(counts_as_played(secs(240), Some(secs(3600))));
+        assert!(!counts_as_played(secs(239), None));
+    }
+
+    /// What the player did in a transition
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Did {
+        /// Started a song from the top
+        Start,
+        /// Paused, resumed or stopped the song it had
+        Keep,
+        /// Nothing at all, no clients are woken up either
+        Nothing,
+    }
+
+    #[tokio::test]
+    async fn playback_transitions_match_mpd() {
+        use PlaybackState::{Pause, Play, Stop};
+
+        let music_dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mpdhaj-transitions-{}", std::process::id()));
+        std::fs::create_dir_all(&music_dir).unwrap();
+        write_wav(&music_dir.join("a.wav"), Duration::from_secs(1));
+        write_wav(&music_dir.join("b.wav"), Duration::from_secs(1));
+
+        let (same, other) = (QueuePos(0), QueuePos(1));
+        #[rustfmt::skip]
+        let table = [
+            (Stop, Target::Play, Play, Did::Start),
+            (Stop, Target::PlayAt(other), Play, Did::Start),
+            (Stop, Target::Pause(None), Stop, Did::Nothing),
+            (Stop, Target::Pause(Some(true)), Stop, Did::Nothing),
+            (Stop, Target::Pause(Some(false)), Stop, Did::Nothing),
+            (Stop, Target::Stop, Stop, Did::Nothing),
+            (Play, Target::Play, Play, Did::Nothing),
+            // mpd starts the song over
+            (Play, Target::PlayAt(same), Play, Did::Start),
+            (Play, Target::PlayAt(other), Play, Did::Start),
+            (Play, Target::Pause(None), Pause, Did::Keep),
+            (Play, Target::Pause(Some(true)), Pause, Did::Keep),
+            (Play, Target::Pause(Some(false)), Play, Did::Nothing),
+            (Play, Target::Stop, Stop, Did::Keep),
+            (Pause, Target::Play, Play, Did::Keep),
+            (Pause, Target::PlayAt(same), Play, Did::Keep),
+            (Pause, Target::PlayAt(other), Play, Did::Start),
+            (Pause, Target::Pause(None), Play, Did::Keep),
+            (Pause, Target::Pause(Some(true)), Pause, Did::Nothing),
+            (Pause, Target::Pause(Some(false)), Play, Did::Keep),
+            (Pause, Target::Stop, Stop, Did::Keep),
+        ];
+        for (from, target, to, did) in table {
+            let mut system = System::with_parts(
+                Connection::open_in_memory().unwrap(),
+                |volume, paused| Player::with_device(volume, paused, Silent),
+                music_dir.clone(),
+                None,
+            )
+            .unwrap();
+            system
+                .db
+                .execute_batch(
+                    "INSERT INTO songs (path, mtime) VALUES ('a.wav', 0), ('b.wav', 0);
+                    INSERT INTO queue_entries (song, key) VALUES (1, 1), (2, 2);
+                    UPDATE state SET current = 0;",
+                )
+                .unwrap();
+            if from != Stop {
+                system.set_playback(Target::PlayAt(same)).await.unwrap();
+            }
+            if from == Pause {
+                system
+                    .set_playback(Target::Pause(Some(true)))
+                    .await
+                    .unwrap();
+            }
+            assert_eq!(system.playing, from);
+            let source = system.current_source;
+            let mut idle = system.idle(vec![SubSystem::Player]);
+
+            system.set_playback(target).await.unwrap();
+            let case = format!("{target:?} while {from:?}");
+            assert_eq!(system.status().unwrap().state, to, "{case}");
+            let paused: bool = system
+                .db
+                .query_one("SELECT paused FROM state", [], |row| row.get(0))
+                .unwrap();
+            assert_eq!(paused, to == Pause, "{case}");
+            let actual = if system.current_source != source {
+                Did::Start
+            } else if idle.try_recv().is_ok() {
+                Did::Keep
+            } else {
+                Did::Nothing
+            };
+            assert_eq!(actual, did, "{case}");
+        }
+        std::fs::remove_dir_all(&music_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn play_with_an_empty_queue_does_nothing() {
+        let mut system = empty_system(Connection::open_in_memory().unwrap());
+        system.set_playback(Target::Play).await.unwrap();
+        assert_eq!(system.playing, PlaybackState::Stop);
+        assert!(
+            system
+                .set_playback(Target::PlayAt(QueuePos(0)))
+                .await
+                .is_err()
+        );
+    }
+}
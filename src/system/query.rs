@@ -1,75 +1,549 @@
+use std::collections::HashMap;
+use std::ops::Range;
 use std::time::Duration;
 
 use color_eyre::Result;
-use itertools::Itertools;
-use rodio::nz;
+use itertools::{Either, Itertools};
+use strum::IntoEnumIterator;
 use tracing::debug;
 
 use crate::{
     mpd_protocol::{
-        self, AudioParams, FindResult, Tag,
+        self, CountResult, FindResult, Sort, SortType, Tag,
         query::{Filter, Query, QueryNode},
     },
-    system::Song,
+    system::{Song, collation, date::Date, multi_value},
 };
 
+/// How tag values are compared to the needle
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Matching {
+    /// `find`
+    Exact,
+    /// `search`, ignores case and accents and also looks at the sort tags
+    IgnoreCase,
+}
+
+impl Matching {
+    fn eq(self, value: &str, needle: &str) -> bool {
+        match self {
+            Matching::Exact => value == needle,
+            Matching::IgnoreCase => collation::search_eq(value, needle),
+        }
+    }
+
+    fn contains(self, value: &str, needle: &str) -> bool {
+        match self {
+            Matching::Exact => value.contains(needle),
+            Matching::IgnoreCase => collation::search_contains(value, needle),
+        }
+    }
+}
+
 // TODO: try translating query to sql WHERE statement(s)
-pub(crate) fn handle_find(system: &super::System, query: &Query) -> Result<Vec<FindResult>> {
-    let query_root = &query.0;
-
-    let mut stmt = system
-        .db
-        .prepare("SELECT path, title, artist, album FROM songs")?;
-    stmt.query_and_then([], |row| {
-        Result::Ok(Song {
-            path: row.get::<_, String>(0)?.into(),
-            title: row.get(1)?,
-            artist: row.get(2)?,
-            album: row.get(3)?,
-            ..Default::default()
+pub(crate) fn handle_find(
+    db: &rusqlite::Connection,
+    query: &Query,
+    sort: Option<&Sort>,
+    window: Option<Range<u32>>,
+    matching: Matching,
+) -> Result<Vec<FindResult>> {
+    let mut songs = matching_songs(db, query, matching)?;
+    if let Some(sort) = sort {
+        sort_songs(&mut songs, sort);
+    }
+    if let Some(window) = window {
+        songs.truncate(window.end as usize);
+        songs.drain(..songs.len().min(window.start as usize));
+    }
+
+    Ok(songs
+        .into_iter()
+        .map(|song| FindResult {
+            path: song.path,
+            last_modified: song.mtime,
+            added: song.date_added,
+            format: song.format,
+            duration: Duration::from_secs(69),
         })
-    })?
-    .filter_ok(|song| apply_query(song, query_root))
-    .map_ok(|song| FindResult {
-        path: song.path,
-        last_modified: jiff::Timestamp::constant(0, 0),
-        added: jiff::Timestamp::constant(0, 0),
-        format: AudioParams {
-            samplerate: nz!(42),
-            channels: nz!(1),
-            bits: 16,
-        },
-        duration: Duration::from_secs(69),
+        .collect())
+}
+
+/// `count`, how many songs match `query` and how long they play together.
+/// Grouped there is a count per value of `group`, songs without the tag are
+/// counted under an empty value like mpd does. Ungrouped the one count has
+/// no value.
+pub(crate) fn count(
+    db: &rusqlite::Connection,
+    query: &Query,
+    group: Option<Tag>,
+) -> Result<Vec<(Option<String>, CountResult)>> {
+    let songs = matching_songs(db, query, Matching::Exact)?;
+    let add = |count: &mut CountResult, song: &Song| {
+        count.songs += 1;
+        count.playtime += song.playtime;
+    };
+    let Some(group) = group else {
+        let mut total = CountResult::default();
+        songs.iter().for_each(|song| add(&mut total, song));
+        return Ok(vec![(None, total)]);
+    };
+
+    let mut groups: HashMap<&str, CountResult> = HashMap::new();
+    for song in &songs {
+        let mut values = song.tag_values(group).peekable();
+        if values.peek().is_none() {
+            add(groups.entry("").or_default(), song);
+        }
+        for value in values {
+            add(groups.entry(value).or_default(), song);
+        }
+    }
+    Ok(groups
+        .into_iter()
+        .sorted_by(|(a, _), (b, _)| collation::compare(a, b))
+        .map(|(value, count)| (Some(value.to_owned()), count))
+        .collect())
+}
+
+/// The songs in the database that match `query`, in no particular order
+fn matching_songs(
+    db: &rusqlite::Connection,
+    query: &Query,
+    matching: Matching,
+) -> Result<Vec<Song>> {
+    let mut values = multi_value::load_all(db)?;
+    let mut stmt = db.prepare("SELECT rowid AS song_id, * FROM songs")?;
+    let songs = stmt
+        .query_and_then([], |row| {
+            let mut song = song_with_tags(row)?;
+            multi_value::take(&mut values, row.get("song_id")?, &mut song);
+            Ok::<_, color_eyre::Report>(song)
+        })?
+        .filter_ok(|song| apply_query(song, &query.0, matching))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(songs)
+}
+
+/// Every text tag is needed to evaluate `any`
+fn song_with_tags(row: &rusqlite::Row) -> Result<Song> {
+    let (mtime, date_added) = super::mtime_and_added(row)?;
+    Ok(Song {
+        path: row.get::<_, String>("path")?.into(),
+        mtime,
+        date_added,
+        title: row.get("title")?,
+        artist: row.get("artist")?,
+        artist_sort: row.get("artist_sort")?,
+        album: row.get("album")?,
+        album_sort: row.get("album_sort")?,
+        album_artist: row.get("album_artist")?,
+        album_artist_sort: row.get("album_artist_sort")?,
+        title_sort: row.get("title_sort")?,
+        name: row.get("name")?,
+        genre: row.get("genre")?,
+        mood: row.get("mood")?,
+        date: row.get("date")?,
+        original_date: row.get("original_date")?,
+        parsed_date: date(row, "date_key")?,
+        parsed_original_date: date(row, "original_date_key")?,
+        composer: row.get("composer")?,
+        composer_sort: row.get("composer_sort")?,
+        performer: row.get("performer")?,
+        conductor: row.get("conductor")?,
+        work: row.get("work")?,
+        ensemble: row.get("ensemble")?,
+        movement: row.get("movement")?,
+        movement_number: row.get("movement_number")?,
+        location: row.get("location")?,
+        grouping: row.get("grouping")?,
+        comment: row.get("comment")?,
+        label: row.get("label")?,
+        musicbrainz_artist_id: row.get("musicbrainz_artist_id")?,
+        musicbrainz_album_id: row.get("musicbrainz_album_id")?,
+        musicbrainz_album_artist_id: row.get("musicbrainz_album_artist_id")?,
+        musicbrainz_track_id: row.get("musicbrainz_track_id")?,
+        musicbrainz_releasegroup_id: row.get("musicbrainz_releasegroup_id")?,
+        musicbrainz_release_track_i: row.get("musicbrainz_release_track_id")?,
+        musicbrainz_work_id: row.get("musicbrainz_work_id")?,
+        format: super::audio_format(row)?,
+        playtime: row
+            .get::<_, Option<f64>>("duration")?
+            .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+            .unwrap_or_default(),
+        ..Default::default()
     })
-    .collect::<Result<Vec<_>, _>>()
+}
+
+fn date(row: &rusqlite::Row, column: &str) -> rusqlite::Result<Option<Date>> {
+    Ok(row.get::<_, Option<u32>>(column)?.and_then(Date::from_key))
+}
+
+fn sort_songs(songs: &mut [Song], sort: &Sort) {
+    match sort.kind {
+        // dates that do not parse go after the ones that do, also when
+        // reversed
+        SortType::Tag(tag @ (Tag::Date | Tag::OriginalDate)) => {
+            songs.sort_by(
+                |a, b| match (a.parsed_date_of(tag), b.parsed_date_of(tag)) {
+                    (Some(a), Some(b)) if sort.reverse => b.cmp(&a),
+                    (Some(a), Some(b)) => a.cmp(&b),
+                    (None, None) => match (a.tag_value(tag), b.tag_value(tag)) {
+                        (Some(a), Some(b)) => collation::compare(a, b),
+                        (a, b) => b.is_some().cmp(&a.is_some()),
+                    },
+                    (a, b) => b.is_some().cmp(&a.is_some()),
+                },
+            )
+        }
+        // songs without the tag go last, also when reversed
+        SortType::Tag(tag) => songs.sort_by(|a, b| match (a.tag_value(tag), b.tag_value(tag)) {
+            (Some(a), Some(b)) if sort.reverse => collation::compare(b, a),
+            (Some(a), Some(b)) => collation::compare(a, b),
+            (a, b) => b.is_some().cmp(&a.is_some()),
+        }),
+        SortType::Mtime if sort.reverse => songs.sort_by(|a, b| b.mtime.cmp(&a.mtime)),
+        SortType::Mtime => songs.sort_by_key(|song| song.mtime),
+        other => debug!("sorting on {other:?} not yet supported"),
+    }
 }
 
 impl Song {
-    fn filter(&self, filter: &Filter) -> bool {
+    fn filter(&self, filter: &Filter, matching: Matching) -> bool {
         use mpd_protocol::query::Filter as F;
         match filter {
-            F::TagEqual { tag, needle } => self.tag_equals(*tag, needle),
+            F::TagEqual { tag, needle } => self.tag_equals(*tag, needle, matching),
+            F::TagContains { tag, needle } => {
+                self.tag_matches(*tag, matching, |value| matching.contains(value, needle))
+            }
             other => {
                 debug!("filter: {other:?} not yet supported, return false");
                 false
             }
         }
     }
-    fn tag_equals(&self, tag: Tag, needle: &str) -> bool {
+    /// Like mpd a date matches every date within it, `Date == '1999'` finds
+    /// `1999-03-14`
+    fn tag_equals(&self, tag: Tag, needle: &str, matching: Matching) -> bool {
+        if let Tag::Date | Tag::OriginalDate = tag
+            && let Some(needle) = Date::parse(needle)
+        {
+            return self
+                .parsed_date_of(tag)
+                .is_some_and(|date| date.within(needle));
+        }
+        self.tag_matches(tag, matching, |value| matching.eq(value, needle))
+    }
+
+    fn parsed_date_of(&self, tag: Tag) -> Option<Date> {
         match tag {
-            Tag::Album => false,
-            Tag::AlbumArtist => false,
-            Tag::Artist => self.artist == Some(needle.to_owned()),
-            _ => todo!(),
+            Tag::Date => self.parsed_date,
+            Tag::OriginalDate => self.parsed_original_date,
+            _ => None,
         }
     }
+
+    /// `any` is true if one of the tags matches. Searches also look at the
+    /// sort variant of the tag, libraries keep transliterations there.
+    fn tag_matches(&self, tag: Tag, matching: Matching, matches: impl Fn(&str) -> bool) -> bool {
+        let sort_tag = match matching {
+            Matching::Exact => None,
+            Matching::IgnoreCase => sort_variant(tag),
+        };
+        match tag {
+            Tag::Any => Tag::iter()
+                .flat_map(|tag| self.tag_values(tag))
+                .any(matches),
+            tag => [Some(tag), sort_tag]
+                .into_iter()
+                .flatten()
+                .flat_map(|tag| self.tag_values(tag))
+                .any(matches),
+        }
+    }
+
+    /// Each value of a [multi-valued](multi_value) tag on its own, for other
+    /// tags the one value
+    pub(crate) fn tag_values(&self, tag: Tag) -> impl Iterator<Item = &str> {
+        let values: &[String] = match tag {
+            Tag::AlbumArtist if self.album_artist.is_none() => &self.artists,
+            Tag::Artist => &self.artists,
+            Tag::Genre => &self.genres,
+            _ => &[],
+        };
+        if values.is_empty() {
+            Either::Left(self.tag_value(tag).into_iter())
+        } else {
+            Either::Right(values.iter().map(String::as_str))
+        }
+    }
+
+    /// The value of the text tags, None for tags we do not store as text. An
+    /// absent AlbumArtist falls back to Artist.
+    fn tag_value(&self, tag: Tag) -> Option<&str> {
+        match tag {
+            Tag::AlbumArtist if self.album_artist.is_none() => self.artist.as_deref(),
+            Tag::Artist => self.artist.as_deref(),
+            Tag::ArtistSort => self.artist_sort.as_deref(),
+            Tag::Album => self.album.as_deref(),
+            Tag::AlbumSort => self.album_sort.as_deref(),
+            Tag::AlbumArtist => self.album_artist.as_deref(),
+            Tag::AlbumArtistSort => self.album_artist_sort.as_deref(),
+            Tag::Title => self.title.as_deref(),
+            Tag::TitleSort => self.title_sort.as_deref(),
+            Tag::Name => self.name.as_deref(),
+            Tag::Genre => self.genre.as_deref(),
+            Tag::Mood => self.mood.as_deref(),
+            Tag::Date => self.date.as_deref(),
+            Tag::OriginalDate => self.original_date.as_deref(),
+            Tag::Composer => self.composer.as_deref(),
+            Tag::ComposerSort => self.composer_sort.as_deref(),
+            Tag::Performer => self.performer.as_deref(),
+            Tag::Conductor => self.conductor.as_deref(),
+            Tag::Work => self.work.as_deref(),
+            Tag::Ensemble => self.ensemble.as_deref(),
+            Tag::Movement => self.movement.as_deref(),
+            Tag::MovementNumber => self.movement_number.as_deref(),
+            Tag::Location => self.location.as_deref(),
+            Tag::Grouping => self.grouping.as_deref(),
+            Tag::Comment => self.comment.as_deref(),
+            Tag::Label => self.label.as_deref(),
+            Tag::MusicbrainzArtistId => self.musicbrainz_artist_id.as_deref(),
+            Tag::MusicbrainzAlbumId => self.musicbrainz_album_id.as_deref(),
+            Tag::MusicbrainzAlbumArtistId => self.musicbrainz_album_artist_id.as_deref(),
+            Tag::MusicbrainzTrackId => self.musicbrainz_track_id.as_deref(),
+            Tag::MusicbrainzReleasegroupId => self.musicbrainz_releasegroup_id.as_deref(),
+            Tag::MusicbrainzReleaseTrackId => self.musicbrainz_release_track_i.as_deref(),
+            Tag::MusicbrainzWorkId => self.musicbrainz_work_id.as_deref(),
+            Tag::Track | Tag::Disc | Tag::ShowMovement | Tag::Any => None,
+        }
+    }
+}
+
+fn sort_variant(tag: Tag) -> Option<Tag> {
+    Some(match tag {
+        Tag::Artist => Tag::ArtistSort,
+        Tag::Album => Tag::AlbumSort,
+        Tag::AlbumArtist => Tag::AlbumArtistSort,
+        Tag::Title => Tag::TitleSort,
+        Tag::Composer => Tag::ComposerSort,
+        _ => return None,
+    })
 }
 
-fn apply_query(song: &Song, node: &QueryNode) -> bool {
+fn apply_query(song: &Song, node: &QueryNode, matching: Matching) -> bool {
     use mpd_protocol::query::QueryNode as Q;
     match node {
-        Q::Filter(filter) => song.filter(filter),
-        Q::NegatedFilter(filter) => !song.filter(filter),
-        Q::And(query_nodes) => query_nodes.iter().all(|node| apply_query(song, node)),
+        Q::Filter(filter) => song.filter(filter, matching),
+        Q::NegatedFilter(filter) => !song.filter(filter, matching),
+        Q::And(query_nodes) => query_nodes
+            .iter()
+            .all(|node| apply_query(song, node, matching)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(artist: &str, title: Option<&str>) -> Song {
+        Song {
+            artist: Some(artist.to_owned()),
+            title: title.map(str::to_owned),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn search_ignores_case() {
+        let song = song("The Beatles", None);
+        assert!(song.tag_equals(Tag::Artist, "the beatles", Matching::IgnoreCase));
+        assert!(!song.tag_equals(Tag::Artist, "the beatles", Matching::Exact));
+    }
+
+    #[test]
+    fn search_ignores_accents_find_does_not() {
+        let fixtures = [
+            ("Beyoncé", "beyonce"),
+            ("Björk", "bjork"),
+            ("Sigur Rós", "sigur ros"),
+            ("Motörhead", "MOTORHEAD"),
+            ("Zoë Keating", "zoe keating"),
+        ];
+        for (artist, typed) in fixtures {
+            let song = song(artist, None);
+            assert!(
+                song.tag_equals(Tag::Artist, typed, Matching::IgnoreCase),
+                "{typed} should find {artist}"
+            );
+            assert!(!song.tag_equals(Tag::Artist, typed, Matching::Exact));
+        }
+    }
+
+    #[test]
+    fn search_looks_at_sort_tags() {
+        let mut song = song("坂本龍一", None);
+        song.artist_sort = Some("Sakamoto, Ryuichi".to_owned());
+        let contains = |matching| {
+            song.filter(
+                &Filter::TagContains {
+                    tag: Tag::Artist,
+                    needle: "sakamoto".to_owned(),
+                },
+                matching,
+            )
+        };
+        assert!(contains(Matching::IgnoreCase));
+        assert!(!contains(Matching::Exact));
+    }
+
+    #[test]
+    fn album_artist_falls_back_to_artist() {
+        let mut song = song("Abba", None);
+        assert!(song.tag_equals(Tag::AlbumArtist, "Abba", Matching::Exact));
+
+        song.album_artist = Some("Various Artists".to_owned());
+        assert!(!song.tag_equals(Tag::AlbumArtist, "Abba", Matching::Exact));
+        assert!(song.tag_equals(Tag::AlbumArtist, "Various Artists", Matching::Exact));
+    }
+
+    #[test]
+    fn any_matches_every_tag() {
+        let mut song = song("Abba", Some("Waterloo"));
+        song.genre = Some("Pop".to_owned());
+        let any_contains = |needle| {
+            song.filter(
+                &Filter::TagContains {
+                    tag: Tag::Any,
+                    needle: String::from(needle),
+                },
+                Matching::IgnoreCase,
+            )
+        };
+
+        assert!(any_contains("abb"));
+        assert!(any_contains("LOO"));
+        assert!(any_contains("pop"));
+        assert!(!any_contains("queen"));
+        assert!(song.tag_equals(Tag::Any, "Waterloo", Matching::Exact));
+        assert!(!song.tag_equals(Tag::Any, "waterloo", Matching::Exact));
+    }
+
+    fn dated(artist: &str, date: Option<&str>) -> Song {
+        Song {
+            date: date.map(str::to_owned),
+            parsed_date: date.and_then(Date::parse),
+            ..song(artist, None)
+        }
+    }
+
+    #[test]
+    fn a_year_finds_every_date_in_it() {
+        let day = dated("a", Some("1999-03-14"));
+        let year = dated("b", Some("2001"));
+        let garbage = dated("c", Some("199x"));
+
+        assert!(day.tag_equals(Tag::Date, "1999", Matching::Exact));
+        assert!(day.tag_equals(Tag::Date, "1999-03", Matching::Exact));
+        assert!(day.tag_equals(Tag::Date, "1999-03-14", Matching::IgnoreCase));
+        assert!(!day.tag_equals(Tag::Date, "2001", Matching::Exact));
+        assert!(year.tag_equals(Tag::Date, "2001", Matching::Exact));
+        assert!(!year.tag_equals(Tag::Date, "2001-01", Matching::Exact));
+        assert!(!garbage.tag_equals(Tag::Date, "1999", Matching::Exact));
+        assert!(garbage.tag_equals(Tag::Date, "199x", Matching::Exact));
+        assert!(!day.tag_equals(Tag::OriginalDate, "1999", Matching::Exact));
+    }
+
+    #[test]
+    fn dates_sort_on_their_value_with_garbage_last() {
+        let mut songs = vec![
+            dated("a", None),
+            dated("b", Some("199x")),
+            dated("c", Some("2001")),
+            dated("d", Some("1999-03-14")),
+            dated("e", Some("1999")),
+        ];
+        let artists = |songs: &[Song]| {
+            songs
+                .iter()
+                .map(|s| s.artist.clone().unwrap())
+                .collect::<String>()
+        };
+
+        let by_date = |reverse| Sort {
+            reverse,
+            kind: SortType::Tag(Tag::Date),
+        };
+        sort_songs(&mut songs, &by_date(false));
+        assert_eq!(artists(&songs), "edcba");
+        sort_songs(&mut songs, &by_date(true));
+        assert_eq!(artists(&songs), "cdeba");
+        // the text is shown as it was written
+        assert_eq!(songs[3].tag_value(Tag::Date), Some("199x"));
+    }
+
+    /// Two artists and three genres, like [`crate::scan::tests::write_jazz_song`]
+    fn jazz_song() -> Song {
+        Song {
+            artists: vec!["Herbie Hancock".to_owned(), "Chick Corea".to_owned()],
+            genres: ["Jazz", "Fusion", "Funk"].map(str::to_owned).to_vec(),
+            genre: Some("Jazz; Fusion; Funk".to_owned()),
+            ..song("Herbie Hancock; Chick Corea", Some("Liza"))
+        }
+    }
+
+    #[test]
+    fn filters_match_any_of_the_values() {
+        let song = jazz_song();
+        for genre in ["Jazz", "Fusion", "Funk"] {
+            assert!(
+                song.tag_equals(Tag::Genre, genre, Matching::Exact),
+                "{genre}"
+            );
+        }
+        assert!(!song.tag_equals(Tag::Genre, "Jazz; Fusion; Funk", Matching::Exact));
+        assert!(song.tag_equals(Tag::Artist, "Chick Corea", Matching::Exact));
+        assert!(song.tag_equals(Tag::AlbumArtist, "Chick Corea", Matching::Exact));
+        assert!(song.tag_equals(Tag::Any, "Funk", Matching::Exact));
+        assert!(song.filter(
+            &Filter::TagContains {
+                tag: Tag::Artist,
+                needle: "corea".to_owned(),
+            },
+            Matching::IgnoreCase,
+        ));
+
+        // scanned before the values were stored separately
+        let old = song("Herbie Hancock; Chick Corea", None);
+        assert!(old.tag_equals(Tag::Artist, "Herbie Hancock; Chick Corea", Matching::Exact));
+        assert_eq!(
+            jazz_song().tag_values(Tag::Genre).collect_vec(),
+            ["Jazz", "Fusion", "Funk"]
+        );
+    }
+
+    #[test]
+    fn sorts_in_collation_order_with_missing_last() {
+        let mut songs = vec![
+            song("a", Some("ZZ")),
+            song("b", None),
+            song("c", Some("édith")),
+            song("d", Some("Eagles")),
+        ];
+        let artists = |songs: &[Song]| {
+            songs
+                .iter()
+                .map(|s| s.artist.clone().unwrap())
+                .collect::<String>()
+        };
+
+        let by_title = |reverse| Sort {
+            reverse,
+            kind: SortType::Tag(Tag::Title),
+        };
+        sort_songs(&mut songs, &by_title(false));
+        assert_eq!(artists(&songs), "dcab");
+        sort_songs(&mut songs, &by_title(true));
+        assert_eq!(artists(&songs), "acdb");
     }
 }
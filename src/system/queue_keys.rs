@@ -0,0 +1,201 @@
+//! The order of the queue, kept in the `key` column of `queue_entries`.
+//!
+//! Keys are handed out [`GAP`] apart so entries can go in between others
+//! without renumbering everything after them. On an SD card those writes
+//! were most of the cost of a change to a long queue. Only once two
+//! neighbours have no room left between them is the queue spread out again.
+//! Clients never see keys, the `queue` view numbers the entries 0, 1, 2, ...
+//!
+//! Keys are positive. Entries being moved get theirs negated first, that
+//! takes them out of the order without clashing on the unique index.
+
+use std::ops::Range;
+
+use itertools::Itertools;
+use rusqlite::{Connection, params};
+
+/// The room between neighbours once the queue is spread out
+pub(crate) const GAP: i64 = 1 << 16;
+
+/// Keys for `count` entries that go in at position `at`, between the
+/// entries now at `at - 1` and `at`. `at` may be the length of the queue.
+pub(crate) fn make_room(db: &Connection, at: u32, count: u32) -> rusqlite::Result<Vec<i64>> {
+    if let Some(keys) = between(db, at, count)? {
+        return Ok(keys);
+    }
+    spread_out(db, at, count)?;
+    Ok(between(db, at, count)?.expect("spreading out left room for them"))
+}
+
+/// Puts the entries at `positions` at `to`, counted once they are taken out
+pub(crate) fn move_range(db: &Connection, positions: Range<u32>, to: u32) -> rusqlite::Result<()> {
+    let ids = ids_in(db, positions)?;
+    let mut set_key = db.prepare_cached("UPDATE queue_entries SET key = ?1 WHERE id = ?2")?;
+    for id in &ids {
+        db.execute("UPDATE queue_entries SET key = -key WHERE id = ?1", [id])?;
+    }
+    let count = u32::try_from(ids.len()).expect("the queue fits in u32 positions");
+    for (key, id) in make_room(db, to, count)?.into_iter().zip(&ids) {
+        set_key.execute(params![key, id])?;
+    }
+    Ok(())
+}
+
+/// Puts the entries at `positions` in the order of `ids`, which are the ids
+/// of those entries. Only their keys are swapped around.
+pub(crate) fn reorder(db: &Connection, positions: Range<u32>, ids: &[u32]) -> rusqlite::Result<()> {
+    let keys: Vec<i64> = db
+        .prepare_cached("SELECT key FROM queue_entries WHERE key > 0 ORDER BY key LIMIT ?2 OFFSET ?1")?
+        .query_map([positions.start, positions.len() as u32], |row| row.get(0))?
+        .try_collect()?;
+    db.execute(
+        "UPDATE queue_entries SET key = -key WHERE key >= ?1 AND key <= ?2",
+        params![keys.first(), keys.last()],
+    )?;
+    let mut set_key = db.prepare_cached("UPDATE queue_entries SET key = ?1 WHERE id = ?2")?;
+    for (key, id) in keys.iter().zip(ids) {
+        set_key.execute(params![key, id])?;
+    }
+    Ok(())
+}
+
+fn ids_in(db: &Connection, positions: Range<u32>) -> rusqlite::Result<Vec<u32>> {
+    db.prepare_cached("SELECT id FROM queue_entries WHERE key > 0 ORDER BY key LIMIT ?2 OFFSET ?1")?
+        .query_map([positions.start, positions.len() as u32], |row| row.get(0))?
+        .try_collect()
+}
+
+/// The keys of the entries at `at - 1` and `at`, in one walk along the index.
+/// Entries being moved do not count.
+fn neighbours(db: &Connection, at: u32) -> rusqlite::Result<(Option<i64>, Option<i64>)> {
+    let from = at.saturating_sub(1);
+    let keys: Vec<i64> = db
+        .prepare_cached("SELECT key FROM queue_entries WHERE key > 0 ORDER BY key LIMIT 2 OFFSET ?1")?
+        .query_map([from], |row| row.get(0))?
+        .try_collect()?;
+    Ok(if at == 0 {
+        (None, keys.first().copied())
+    } else {
+        (keys.first().copied(), keys.get(1).copied())
+    })
+}
+
+/// Keys evenly spread between the neighbours of `at`, None if they do not
+/// fit
+fn between(db: &Connection, at: u32, count: u32) -> rusqlite::Result<Option<Vec<i64>>> {
+    let (before, after) = neighbours(db, at)?;
+    let before = before.unwrap_or(0);
+    let count = i64::from(count);
+    let step = match after {
+        Some(after) => (after - before) / (count + 1),
+        None => GAP,
+    };
+    Ok((step > 0).then(|| (1..=count).map(|i| before + i * step).collect()))
+}
+
+/// Hands out new keys [`GAP`] apart, with room for `count` entries at `at`.
+/// Every entry is written, this is what the gaps are there to avoid.
+fn spread_out(db: &Connection, at: u32, count: u32) -> rusqlite::Result<()> {
+    let ids: Vec<u32> = db
+        .prepare_cached("SELECT id FROM queue_entries WHERE key > 0 ORDER BY key")?
+        .query_map([], |row| row.get(0))?
+        .try_collect()?;
+    tracing::debug!("Spreading out the keys of {} queue entries", ids.len());
+    // through negative keys, sqlite checks the unique index row by row
+    db.execute("UPDATE queue_entries SET key = -key WHERE key > 0", [])?;
+    let mut set_key = db.prepare_cached("UPDATE queue_entries SET key = ?1 WHERE id = ?2")?;
+    for (pos, id) in (0..).zip(&ids) {
+        let slot = if pos < i64::from(at) {
+            pos + 1
+        } else {
+            pos + 1 + i64::from(count)
+        };
+        set_key.execute(params![slot * GAP, id])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::migrations;
+
+    fn queue() -> Connection {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrations::run(&mut db).unwrap();
+        db
+    }
+
+    fn insert(db: &Connection, at: u32, count: u32) -> Vec<u32> {
+        make_room(db, at, count)
+            .unwrap()
+            .into_iter()
+            .map(|key| {
+                db.query_one(
+                    "INSERT INTO queue_entries (song, key) VALUES (1, ?1) RETURNING id",
+                    [key],
+                    |row| row.get(0),
+                )
+                .unwrap()
+            })
+            .collect()
+    }
+
+    /// The ids in position order, the keys are checked on the way
+    fn order(db: &Connection) -> Vec<u32> {
+        let rows: Vec<(u32, u32, i64)> = db
+            .prepare("SELECT id, position, key FROM queue ORDER BY position")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .try_collect()
+            .unwrap();
+        assert!(rows.iter().all(|(_, _, key)| *key > 0), "{rows:?}");
+        assert!(rows.iter().map(|(_, pos, _)| *pos).eq(0..rows.len() as u32));
+        rows.into_iter().map(|(id, _, _)| id).collect()
+    }
+
+    #[test]
+    fn inserting_at_one_spot_spreads_the_keys_out_once_full() {
+        let db = queue();
+        let mut model = insert(&db, 0, 2);
+        let written = |db: &Connection| db.total_changes();
+        let mut spread_outs = 0;
+        for _ in 0..100 {
+            let before = written(&db);
+            let id = insert(&db, 1, 1);
+            // one insert, or one for every entry when out of room
+            if written(&db) - before > 1 {
+                spread_outs += 1;
+            }
+            model.splice(1..1, id);
+            assert_eq!(order(&db), model);
+        }
+        // the gap halves every time
+        assert!((1..=10).contains(&spread_outs), "{spread_outs}");
+
+        let many = insert(&db, 50, 1000);
+        model.splice(50..50, many);
+        assert_eq!(order(&db), model);
+    }
+
+    #[test]
+    fn moved_entries_keep_their_ids() {
+        let db = queue();
+        let mut model = insert(&db, 0, 10);
+        move_range(&db, 2..5, 6).unwrap();
+        let moved = model.drain(2..5).collect_vec();
+        model.splice(6..6, moved);
+        assert_eq!(order(&db), model);
+
+        move_range(&db, 7..10, 0).unwrap();
+        let moved = model.drain(7..10).collect_vec();
+        model.splice(0..0, moved);
+        assert_eq!(order(&db), model);
+
+        let reversed = model[3..8].iter().rev().copied().collect_vec();
+        reorder(&db, 3..8, &reversed).unwrap();
+        model[3..8].reverse();
+        assert_eq!(order(&db), model);
+    }
+}
@@ -0,0 +1,107 @@
+//! The queue as it was at one version, for listings that are read a page at
+//! a time. Only the order is copied up front, the songs are read with each
+//! page. Entries moved or deleted in between still show where they were, so
+//! the pages never skip or repeat a position.
+
+use std::ops::Range;
+
+use color_eyre::Result;
+use itertools::Itertools;
+use rusqlite::{Connection, OptionalExtension};
+
+use crate::mpd_protocol::{FloatRange, QueueEntry, QueueId, QueueInfo, QueuePos};
+use crate::system::{SONG_COLUMNS, multi_value, prio_and_range, song_from_row};
+
+pub(crate) struct QueueSnapshot {
+    /// The `queue_version` the order was taken at
+    pub(crate) version: u32,
+    /// Queue id, song rowid, priority and range of every entry, by position
+    entries: Vec<(QueueId, u32, (Option<u8>, Option<FloatRange>))>,
+}
+
+impl QueueSnapshot {
+    /// Reads the order and its version in one transaction
+    pub(crate) fn take(db: &Connection) -> Result<Self> {
+        let t = db.unchecked_transaction()?;
+        let version = t.query_one("SELECT queue_version FROM state", [], |row| row.get(0))?;
+        let entries = t
+            .prepare_cached(
+                "SELECT id, song, prio, range_start, range_end FROM queue ORDER BY position",
+            )?
+            .query_map([], |row| {
+                Ok((QueueId(row.get(0)?), row.get(1)?, prio_and_range(row)?))
+            })?
+            .try_collect()?;
+        t.finish()?;
+        Ok(Self { version, entries })
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The entries at `positions` with the songs as they are now. A song
+    /// removed from the library since is left out.
+    pub(crate) fn entries(&self, db: &Connection, positions: Range<usize>) -> Result<QueueInfo> {
+        let mut stmt = db.prepare_cached(&format!(
+            "SELECT {SONG_COLUMNS} FROM songs WHERE rowid = ?1"
+        ))?;
+        let end = positions.end.min(self.entries.len());
+        let start = positions.start.min(end);
+        let mut entries = Vec::with_capacity(end - start);
+        for (pos, &(id, song, prio_and_range)) in self.entries[start..end].iter().enumerate() {
+            let found = stmt.query_row([song], song_from_row).optional()?;
+            let Some(mut found) = found else {
+                continue;
+            };
+            multi_value::load(db, song, &mut found)?;
+            let pos = (start + pos) as u32;
+            let mut entry = QueueEntry::from_song(found, Some(QueuePos(pos)), Some(id));
+            (entry.prio, entry.range) = prio_and_range;
+            entries.push(entry);
+        }
+        Ok(QueueInfo(entries))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use super::*;
+    use crate::system::{empty_system, shift_current};
+
+    #[test]
+    fn later_pages_show_the_queue_as_it_was() {
+        let system = empty_system(Connection::open_in_memory().unwrap());
+        system
+            .db
+            .execute_batch(
+                "INSERT INTO songs (path, mtime) VALUES ('a.flac', 0), ('b.flac', 0), ('c.flac', 0);
+                INSERT INTO queue_entries (song, key) VALUES (1, 1), (2, 2), (3, 3);",
+            )
+            .unwrap();
+        let snapshot = QueueSnapshot::take(&system.db).unwrap();
+        let first = snapshot.entries(&system.db, 0..1).unwrap();
+
+        // deletes the first entry
+        system
+            .db
+            .execute_batch(
+                "DELETE FROM queue_entries WHERE key = 1;
+                UPDATE state SET queue_version = queue_version + 1;",
+            )
+            .unwrap();
+        shift_current(&system.db, 1, -1).unwrap();
+        assert_ne!(system.status().unwrap().playlist, snapshot.version);
+
+        let rest = snapshot.entries(&system.db, 1..10).unwrap();
+        let listed: Vec<_> = first
+            .0
+            .iter()
+            .chain(&rest.0)
+            .map(|entry| (entry.pos.unwrap().0, entry.path.as_str()))
+            .collect();
+        assert_eq!(listed, [(0, "a.flac"), (1, "b.flac"), (2, "c.flac")]);
+    }
+}
@@ -0,0 +1,123 @@
+//! Read-only connections to the database, for long reads like `find` and
+//! `listallinfo` that should not hold the System lock.
+//!
+//! The database is in WAL mode so they read while the System connection
+//! writes, they see the database as it was when their statement started.
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use color_eyre::Result;
+use color_eyre::eyre::Context;
+use rusqlite::{Connection, OpenFlags};
+
+use crate::system::collation;
+
+/// Connections kept open between reads, more are opened when needed
+const MAX_IDLE: usize = 4;
+
+pub struct Readers {
+    path: PathBuf,
+    idle: Mutex<Vec<Connection>>,
+}
+
+impl Readers {
+    /// `db` is the connection that writes to the database at `path`
+    pub fn new(db: &Connection, path: PathBuf) -> Result<Self> {
+        db.pragma_update(None, "journal_mode", "WAL")
+            .wrap_err("Could not switch the database to WAL mode")?;
+        // syncs at checkpoints instead of on every commit. A power cut can
+        // lose the last changes but never corrupts the database, and a
+        // Pi's SD card is spared a sync per queue change.
+        db.pragma_update(None, "synchronous", "NORMAL")
+            .wrap_err("Could not set the database's synchronous mode")?;
+        Ok(Self {
+            path,
+            idle: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// An idle connection or a new one, it goes back once dropped
+    pub fn get(self: &Arc<Self>) -> Result<Reader> {
+        let idle = self.idle.lock().expect("never poisoned").pop();
+        let db = match idle {
+            Some(db) => db,
+            None => {
+                let db = Connection::open_with_flags(
+                    &self.path,
+                    OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+                )
+                .wrap_err("Could not open a read-only connection to the database")?;
+                collation::register(&db).wrap_err("Could not register collation")?;
+                db
+            }
+        };
+        Ok(Reader {
+            db: Some(db),
+            readers: Arc::clone(self),
+        })
+    }
+}
+
+pub struct Reader {
+    db: Option<Connection>,
+    readers: Arc<Readers>,
+}
+
+impl std::ops::Deref for Reader {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.db.as_ref().expect("only taken on drop")
+    }
+}
+
+impl Drop for Reader {
+    fn drop(&mut self) {
+        let mut idle = self.readers.idle.lock().expect("never poisoned");
+        if idle.len() < MAX_IDLE {
+            idle.extend(self.db.take());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readers_see_committed_writes_and_are_reused() {
+        let path =
+            std::env::temp_dir().join(format!("mpdhaj-readers-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db = Connection::open(&path).unwrap();
+        db.execute("CREATE TABLE t (v)", []).unwrap();
+        let readers = Arc::new(Readers::new(&db, path.clone()).unwrap());
+
+        let reader = readers.get().unwrap();
+        let count = |db: &Connection| {
+            db.query_one("SELECT COUNT(*) FROM t", [], |row| row.get::<_, u32>(0))
+                .unwrap()
+        };
+        assert_eq!(count(&reader), 0);
+        db.execute("INSERT INTO t VALUES (1)", []).unwrap();
+        assert_eq!(count(&reader), 1);
+        assert!(reader.execute("INSERT INTO t VALUES (2)", []).is_err());
+
+        // a write does not wait for an open read
+        let t = reader.unchecked_transaction().unwrap();
+        assert_eq!(count(&t), 1);
+        db.execute("INSERT INTO t VALUES (3)", []).unwrap();
+        assert_eq!(count(&t), 1, "a read sees one snapshot");
+        drop(t);
+
+        drop(reader);
+        assert_eq!(readers.idle.lock().unwrap().len(), 1);
+        let _again = readers.get().unwrap();
+        assert!(readers.idle.lock().unwrap().is_empty());
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+        let _ = std::fs::remove_file(path.with_extension("sqlite-wal"));
+        let _ = std::fs::remove_file(path.with_extension("sqlite-shm"));
+    }
+}
@@ -0,0 +1,312 @@
+//! Changing stored playlists from the protocol. Each change writes the whole
+//! file again through [`System::replace_playlist_with`], so it survives a
+//! restart and other programs see it too.
+
+use std::{fs, io};
+
+use camino::Utf8Path;
+use color_eyre::{Result, Section, eyre::Context};
+
+use crate::mpd_protocol::ack::{Ack, ErrorCode};
+use crate::mpd_protocol::{
+    BrowseEntry, PlaylistSaveMode, PosOrRange, Position, QueuePos, SubSystem,
+};
+use crate::playlist::{self, PlaylistEntry, PlaylistName};
+use crate::system::System;
+
+impl System {
+    /// `save`, the paths of the songs in the queue
+    pub fn save_queue(&mut self, name: &PlaylistName, mode: PlaylistSaveMode) -> Result<()> {
+        let entries: Vec<_> = self
+            .queue()?
+            .0
+            .into_iter()
+            .map(|entry| PlaylistEntry::new(entry.path))
+            .collect();
+        self.store_playlist(name, &entries, mode)
+    }
+
+    /// Writes the playlist and picks it up again, which is then already up
+    /// to date once the watcher sees the file change
+    pub(crate) fn store_playlist(
+        &mut self,
+        name: &PlaylistName,
+        entries: &[PlaylistEntry],
+        mode: PlaylistSaveMode,
+    ) -> Result<()> {
+        let path = playlist::save_to_dir(&self.playlist_dir, name, entries, mode)?;
+        let (_, playlist) = playlist::load_file(&path, &self.music_dir)?;
+        self.playlists.insert(name.clone(), playlist);
+        self.notify(SubSystem::StoredPlaylist);
+        Ok(())
+    }
+
+    /// `rename`, refuses to replace another playlist
+    pub fn rename_playlist(&mut self, from: &PlaylistName, to: &PlaylistName) -> Result<()> {
+        playlist::check_name(from)?;
+        playlist::check_name(to)?;
+        if !self.playlists.contains_key(from) {
+            return Err(Ack::new(ErrorCode::NoExist, "No such playlist"))
+                .with_note(|| format!("name: {:?}", from.0));
+        }
+        let new_path = self.playlist_dir.join(&to.0);
+        if self.playlists.contains_key(to) || new_path.exists() {
+            return Err(Ack::new(ErrorCode::Exist, "Playlist already exists"))
+                .with_note(|| format!("name: {:?}", to.0));
+        }
+        fs::rename(self.playlist_dir.join(&from.0), &new_path)
+            .wrap_err("Could not rename the playlist file")
+            .with_note(|| format!("to: {new_path}"))?;
+        let playlist = self
+            .playlists
+            .remove(from)
+            .expect("checked that it exists above");
+        self.playlists.insert(to.clone(), playlist);
+        self.notify(SubSystem::StoredPlaylist);
+        Ok(())
+    }
+
+    /// `rm`
+    pub fn remove_playlist(&mut self, name: &PlaylistName) -> Result<()> {
+        playlist::check_name(name)?;
+        let path = self.playlist_dir.join(&name.0);
+        match fs::remove_file(&path) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(Ack::new(ErrorCode::NoExist, "No such playlist"))
+                    .with_note(|| format!("path: {path}"));
+            }
+            Err(e) => {
+                return Err(e)
+                    .wrap_err("Could not remove the playlist file")
+                    .with_note(|| format!("path: {path}"));
+            }
+        }
+        self.playlists.remove(name);
+        self.notify(SubSystem::StoredPlaylist);
+        Ok(())
+    }
+
+    /// `playlistadd`, creates the playlist if needed. Like mpd a uri that is
+    /// not in the database is allowed, streams never are.
+    pub fn playlist_add(
+        &mut self,
+        name: &PlaylistName,
+        uri: &Utf8Path,
+        position: Option<QueuePos>,
+    ) -> Result<()> {
+        let mut entries = self
+            .playlists
+            .get(name)
+            .map(|playlist| playlist.entries.clone())
+            .unwrap_or_default();
+        let added = if self.is_directory(uri)? {
+            self.list_all_in(uri)?
+                .into_iter()
+                .filter_map(BrowseEntry::into_file)
+                .map(PlaylistEntry::new)
+                .collect()
+        } else {
+            if self.get_song_by_path(uri).is_err() {
+                tracing::info!("Adding {uri} to playlist {name:?}, it is not in the database");
+            }
+            vec![PlaylistEntry::new(uri)]
+        };
+
+        let at = position.map_or(entries.len(), |pos| pos.0 as usize);
+        if at > entries.len() {
+            return Err(Ack::new(ErrorCode::Arg, "Bad song index"))
+                .with_note(|| format!("position {at}, the playlist has {}", entries.len()));
+        }
+        entries.splice(at..at, added);
+        self.replace_playlist_with(name, entries)
+    }
+
+    /// `playlistdelete`, the entries after it move up
+    pub fn playlist_delete(&mut self, name: &PlaylistName, what: PosOrRange) -> Result<()> {
+        let mut entries = self.stored_entries(name)?;
+        let range = entry_range(what, entries.len())?;
+        entries.drain(range);
+        self.replace_playlist_with(name, entries)
+    }
+
+    /// `playlistmove`, the first of the moved entries ends up at `to`
+    pub fn playlist_move(
+        &mut self,
+        name: &PlaylistName,
+        what: PosOrRange,
+        to: QueuePos,
+    ) -> Result<()> {
+        let mut entries = self.stored_entries(name)?;
+        let range = entry_range(what, entries.len())?;
+        let moved: Vec<_> = entries.drain(range).collect();
+        let to = to.0 as usize;
+        if to > entries.len() {
+            return Err(Ack::new(ErrorCode::Arg, "Bad song index"))
+                .with_note(|| format!("moving {} entries to {to}", moved.len()));
+        }
+        entries.splice(to..to, moved);
+        self.replace_playlist_with(name, entries)
+    }
+
+    fn stored_entries(&self, name: &PlaylistName) -> Result<Vec<PlaylistEntry>> {
+        match self.playlists.get(name) {
+            Some(playlist) => Ok(playlist.entries.clone()),
+            None => Err(Ack::new(ErrorCode::NoExist, "No such playlist"))
+                .with_note(|| format!("name: {:?}", name.0)),
+        }
+    }
+}
+
+/// Stored playlists have no current entry, positions must be absolute
+fn entry_range(what: PosOrRange, len: usize) -> Result<std::ops::Range<usize>> {
+    let range = match what {
+        PosOrRange::Position(Position::Absolute(pos)) => pos as usize..pos as usize + 1,
+        PosOrRange::Range(range) => {
+            let window = range.window();
+            window.start as usize..(window.end as usize).min(len)
+        }
+        PosOrRange::Position(Position::Relative(_)) => {
+            return Err(Ack::new(ErrorCode::Arg, "Bad song index"))
+                .with_note(|| "stored playlists have no current song");
+        }
+    };
+    if range.is_empty() || range.end > len {
+        return Err(Ack::new(ErrorCode::Arg, "Bad song index"))
+            .with_note(|| format!("entries {range:?}, the playlist has {len}"));
+    }
+    Ok(range)
+}
+
+#[cfg(test)]
+mod tests {
+    use camino::Utf8PathBuf;
+    use itertools::Itertools;
+    use rusqlite::Connection;
+
+    use super::*;
+    use crate::mpd_protocol::Range;
+    use crate::mpd_protocol::ack;
+    use crate::player::Player;
+    use crate::player::device::Silent;
+    use crate::playlist;
+
+    #[test]
+    fn edits_round_trip_through_the_playlist_file() {
+        let music_dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mpdhaj-editplaylist-{}", std::process::id()));
+        std::fs::create_dir_all(music_dir.join("playlists")).unwrap();
+        let mut system = System::with_parts(
+            Connection::open_in_memory().unwrap(),
+            |volume, paused| Player::with_device(volume, paused, Silent),
+            music_dir.clone(),
+            None,
+        )
+        .unwrap();
+        let name = PlaylistName("mix.m3u".to_owned());
+        let paths = |system: &System| {
+            system.playlists[&name]
+                .entries
+                .iter()
+                .map(|entry| entry.path.to_string())
+                .collect_vec()
+        };
+
+        for song in ["a.flac", "b.flac", "c.flac", "d.flac"] {
+            system.playlist_add(&name, song.as_ref(), None).unwrap();
+        }
+        system
+            .playlist_add(
+                &name,
+                "http://example.org/stream".as_ref(),
+                Some(QueuePos(0)),
+            )
+            .unwrap();
+        assert_eq!(
+            paths(&system),
+            [
+                "http://example.org/stream",
+                "a.flac",
+                "b.flac",
+                "c.flac",
+                "d.flac"
+            ]
+        );
+
+        let first = PosOrRange::Position(Position::Absolute(0));
+        system.playlist_delete(&name, first).unwrap();
+        // b and c go to the end
+        let middle = PosOrRange::Range(Range::new(1, Some(3)));
+        system.playlist_move(&name, middle, QueuePos(2)).unwrap();
+        assert_eq!(paths(&system), ["a.flac", "d.flac", "b.flac", "c.flac"]);
+
+        let reloaded = playlist::load_from_dir(&music_dir.join("playlists"), &music_dir).unwrap();
+        assert_eq!(reloaded[&name].entries, system.playlists[&name].entries);
+
+        let past_the_end = PosOrRange::Position(Position::Absolute(4));
+        let error = system.playlist_delete(&name, past_the_end).unwrap_err();
+        assert_eq!(ack::find(&error).unwrap().code, ErrorCode::Arg);
+        let relative = PosOrRange::Position(Position::Relative(1));
+        let error = system
+            .playlist_move(&name, relative, QueuePos(0))
+            .unwrap_err();
+        assert_eq!(ack::find(&error).unwrap().code, ErrorCode::Arg);
+        let unknown = PlaylistName("unknown.m3u".to_owned());
+        let error = system.playlist_delete(&unknown, first).unwrap_err();
+        assert_eq!(ack::find(&error).unwrap().code, ErrorCode::NoExist);
+
+        std::fs::remove_dir_all(&music_dir).unwrap();
+    }
+
+    #[test]
+    fn renamed_and_removed_playlists_follow_their_files() {
+        let music_dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mpdhaj-renameplaylist-{}", std::process::id()));
+        let playlist_dir = music_dir.join("playlists");
+        std::fs::create_dir_all(&playlist_dir).unwrap();
+        let mut system = System::with_parts(
+            Connection::open_in_memory().unwrap(),
+            |volume, paused| Player::with_device(volume, paused, Silent),
+            music_dir.clone(),
+            None,
+        )
+        .unwrap();
+        let mut idle = system.idle(vec![SubSystem::StoredPlaylist]);
+        let (old, new) = (
+            PlaylistName("old.m3u".to_owned()),
+            PlaylistName("new.m3u".to_owned()),
+        );
+
+        system.save_queue(&old, PlaylistSaveMode::Create).unwrap();
+        assert!(playlist_dir.join("old.m3u").is_file());
+        system.rename_playlist(&old, &new).unwrap();
+        assert!(!playlist_dir.join("old.m3u").exists());
+        assert!(playlist_dir.join("new.m3u").is_file());
+        assert!(system.playlists.contains_key(&new) && !system.playlists.contains_key(&old));
+        // the watcher seeing the rename changes nothing
+        assert!(!system.reload_playlist(&playlist_dir.join("old.m3u")));
+        assert!(!system.reload_playlist(&playlist_dir.join("new.m3u")));
+
+        system.save_queue(&old, PlaylistSaveMode::Create).unwrap();
+        let taken = system.rename_playlist(&old, &new).unwrap_err();
+        assert_eq!(ack::find(&taken).unwrap().code, ErrorCode::Exist);
+        let escape = PlaylistName("../../etc/passwd".to_owned());
+        let escape = system.rename_playlist(&old, &escape).unwrap_err();
+        assert_eq!(ack::find(&escape).unwrap().code, ErrorCode::Arg);
+
+        system.remove_playlist(&new).unwrap();
+        assert!(!playlist_dir.join("new.m3u").exists());
+        assert!(!system.playlists.contains_key(&new));
+        let gone = system.remove_playlist(&new).unwrap_err();
+        assert_eq!(ack::find(&gone).unwrap().code, ErrorCode::NoExist);
+
+        // create, rename, create and remove
+        for _ in 0..4 {
+            assert_eq!(idle.try_recv(), Ok(SubSystem::StoredPlaylist));
+        }
+        assert!(idle.try_recv().is_err());
+        std::fs::remove_dir_all(&music_dir).unwrap();
+    }
+}
@@ -0,0 +1,183 @@
+//! `update` and `rescan` queue a scan and return right away with its job id,
+//! [`run`] works through the queue in the background.
+//!
+//! Like mpd only one scan runs at a time. A request for a path that is
+//! already waiting gets the id of the waiting job instead of a second scan.
+//! The job that is running does not count, it may have walked past the
+//! changed files already.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use camino::{Utf8Component, Utf8Path, Utf8PathBuf};
+use color_eyre::{Result, Section};
+use tokio::sync::{Mutex, Notify};
+
+use crate::mpd_protocol::SubSystem;
+use crate::mpd_protocol::ack::{Ack, ErrorCode};
+use crate::system::System;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    Queued,
+    Running,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpdateJob {
+    pub id: u32,
+    /// Relative to the music dir, None for all of it
+    pub path: Option<Utf8PathBuf>,
+    pub state: JobState,
+}
+
+/// The jobs that did not finish yet, the running one first
+#[derive(Debug, Default)]
+pub struct Updates {
+    last_id: u32,
+    jobs: VecDeque<UpdateJob>,
+    /// Wakes up [`run`] when a job is queued
+    queued: Arc<Notify>,
+}
+
+impl System {
+    /// `update` and `rescan`, returns the id of the job that will scan
+    /// `path`. Paths leading out of the music dir are refused.
+    pub fn queue_update(&mut self, path: Option<&Utf8Path>) -> Result<u32> {
+        let path = path.filter(|path| !path.as_str().is_empty());
+        if let Some(path) = path
+            && !path
+                .components()
+                .all(|component| matches!(component, Utf8Component::Normal(_)))
+        {
+            return Err(Ack::new(ErrorCode::Arg, "Malformed path"))
+                .with_note(|| format!("path: {path}"));
+        }
+        let updates = &mut self.updates;
+        let waiting = updates
+            .jobs
+            .iter()
+            .find(|job| job.state == JobState::Queued && job.path.as_deref() == path);
+        if let Some(job) = waiting {
+            return Ok(job.id);
+        }
+        updates.last_id += 1;
+        updates.jobs.push_back(UpdateJob {
+            id: updates.last_id,
+            path: path.map(Utf8Path::to_owned),
+            state: JobState::Queued,
+        });
+        updates.queued.notify_one();
+        Ok(updates.last_id)
+    }
+
+    /// For `updating_db` in the status, the job that is running or is next
+    pub fn updating_db(&self) -> Option<u32> {
+        self.updates.jobs.front().map(|job| job.id)
+    }
+
+    /// Marks the oldest job as running and tells the clients, None if
+    /// nothing is queued
+    pub(crate) fn start_update(&mut self) -> Option<UpdateJob> {
+        let job = self.updates.jobs.front_mut()?;
+        job.state = JobState::Running;
+        let job = job.clone();
+        self.notify(SubSystem::Update);
+        Some(job)
+    }
+
+    /// Drops the finished job, `changed` is whether the scan changed the
+    /// database
+    pub(crate) fn finish_update(&mut self, id: u32, changed: bool) {
+        self.updates.jobs.retain(|job| job.id != id);
+        self.notify(SubSystem::Update);
+        if changed {
+            self.notify(SubSystem::Database);
+        }
+    }
+
+    /// Does the oldest queued job, returns false if there was none
+    pub async fn run_update(&mut self) -> bool {
+        let Some(job) = self.start_update() else {
+            return false;
+        };
+        let changed = match self.scan(job.path.as_deref()).await {
+            Ok(changed) => changed,
+            Err(e) => {
+                tracing::warn!("Update {} failed: {e:#}", job.id);
+                false
+            }
+        };
+        self.finish_update(job.id, changed);
+        true
+    }
+}
+
+/// Runs the queued updates one at a time for as long as the system lives.
+/// The lock is let go between jobs, so clients get a turn.
+pub async fn run(system: Arc<Mutex<System>>) {
+    let queued = Arc::clone(&system.lock().await.updates.queued);
+    loop {
+        while system.lock().await.run_update().await {}
+        queued.notified().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use super::*;
+    use crate::mpd_protocol::ack;
+    use crate::player::Player;
+    use crate::player::device::Silent;
+
+    #[tokio::test]
+    async fn overlapping_updates_share_the_waiting_job() {
+        let music_dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mpdhaj-updates-{}", std::process::id()));
+        std::fs::create_dir_all(music_dir.join("a")).unwrap();
+        let mut system = System::with_parts(
+            Connection::open_in_memory().unwrap(),
+            |volume, paused| Player::with_device(volume, paused, Silent),
+            music_dir.clone(),
+            None,
+        )
+        .unwrap();
+        let mut events = system.idle(vec![SubSystem::Update]);
+        assert_eq!(system.updating_db(), None);
+        assert_eq!(system.status().unwrap().updating_db, None);
+
+        let first = system.queue_update(None).unwrap();
+        assert_eq!(system.status().unwrap().updating_db, Some(first));
+        let running = system.start_update().unwrap();
+        assert_eq!(running.id, first);
+        // the running job does not take new requests
+        let second = system.queue_update(None).unwrap();
+        let third = system.queue_update(Some(Utf8Path::new(""))).unwrap();
+        let other = system.queue_update(Some(Utf8Path::new("a"))).unwrap();
+        assert!(first < second && second < other);
+        assert_eq!(third, second);
+        let escape = system.queue_update(Some(Utf8Path::new("../etc")));
+        assert_eq!(
+            ack::find(&escape.unwrap_err()).unwrap().code,
+            ErrorCode::Arg
+        );
+
+        system.finish_update(first, false);
+        assert_eq!(system.status().unwrap().updating_db, Some(second));
+        assert!(system.run_update().await);
+        assert_eq!(system.updating_db(), Some(other));
+        assert!(system.run_update().await);
+        assert!(!system.run_update().await);
+        assert_eq!(system.status().unwrap().updating_db, None);
+
+        // a start and an end per scan, three in all
+        for _ in 0..6 {
+            assert_eq!(events.try_recv(), Ok(SubSystem::Update));
+        }
+        assert!(events.try_recv().is_err());
+        std::fs::remove_dir_all(&music_dir).unwrap();
+    }
+}
@@ -8,11 +8,11 @@ pub trait WhatItertoolsIsMissing {
     /// alone.
     ///
     /// ```
-    /// use util::WhatItertoolsIsMissing;
+    /// use mpdhaj::util::WhatItertoolsIsMissing;
     ///
     /// let input = vec![Ok(41), Err(false), Ok(11)];
-    /// let it = input.into_iter().enumerate_ok()
-    /// itertools::assert_equal(it, vec![Ok((0, 42)), Err(false), Ok((1,12))]);
+    /// let it = input.into_iter().enumerate_ok();
+    /// itertools::assert_equal(it, vec![Ok((0, 41)), Err(false), Ok((1, 11))]);
     /// ```
     fn enumerate_ok<T, E>(self) -> EnumerateOk<Self, T, E>
     where
@@ -0,0 +1,148 @@
+//! Watches a directory for changes made by other programs.
+//!
+//! Uses the OS's file notifications when built with the `watch` feature and
+//! they work, otherwise polls the directory.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+
+/// Batches of paths that were added, changed or removed
+pub type Changes = UnboundedReceiver<Vec<Utf8PathBuf>>;
+
+/// Stops watching when dropped
+pub struct Watcher(Box<dyn Send>);
+
+pub struct Options {
+    /// Also watch subdirectories
+    pub recursive: bool,
+    /// Changes are collected until none happened for this long, editors and
+    /// taggers often save in several writes.
+    pub debounce: Duration,
+    /// How often to look for changes when file notifications do not work
+    pub poll_interval: Duration,
+}
+
+pub fn watch(dir: &Utf8Path, options: &Options) -> (Watcher, Changes) {
+    let (tx, rx) = unbounded_channel();
+
+    #[cfg(feature = "watch")]
+    let watcher = match native(dir, options, tx.clone()) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            tracing::warn!("Could not watch {dir}, polling it instead: {e:#}");
+            poll(dir.to_owned(), options, tx)
+        }
+    };
+    #[cfg(not(feature = "watch"))]
+    let watcher = poll(dir.to_owned(), options, tx);
+
+    (watcher, rx)
+}
+
+#[cfg(feature = "watch")]
+fn native(
+    dir: &Utf8Path,
+    options: &Options,
+    tx: UnboundedSender<Vec<Utf8PathBuf>>,
+) -> color_eyre::Result<Watcher> {
+    use color_eyre::eyre::Context;
+    use itertools::Itertools;
+    use notify_debouncer_full::{DebounceEventResult, new_debouncer, notify::RecursiveMode};
+
+    let watched = dir.to_owned();
+    let mut debouncer = new_debouncer(
+        options.debounce,
+        None,
+        move |result: DebounceEventResult| match result {
+            Ok(events) => {
+                // reloading or rescanning reads the files, ignore that
+                let paths = events
+                    .iter()
+                    .filter(|event| !event.kind.is_access())
+                    .flat_map(|event| &event.paths)
+                    .filter_map(|path| Utf8PathBuf::from_path_buf(path.clone()).ok())
+                    .unique()
+                    .collect_vec();
+                if !paths.is_empty() {
+                    let _receiver_gone = tx.send(paths);
+                }
+            }
+            Err(errors) => {
+                for e in errors {
+                    tracing::warn!("Error while watching {watched}: {e}");
+                }
+            }
+        },
+    )
+    .wrap_err("Could not create file watcher")?;
+
+    let mode = if options.recursive {
+        RecursiveMode::Recursive
+    } else {
+        RecursiveMode::NonRecursive
+    };
+    debouncer
+        .watch(dir, mode)
+        .wrap_err("Could not watch directory")?;
+    Ok(Watcher(Box::new(debouncer)))
+}
+
+struct StopOnDrop(Arc<AtomicBool>);
+
+impl Drop for StopOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Compares modification times every poll interval
+fn poll(dir: Utf8PathBuf, options: &Options, tx: UnboundedSender<Vec<Utf8PathBuf>>) -> Watcher {
+    let stop = Arc::new(AtomicBool::new(false));
+    let should_stop = Arc::clone(&stop);
+    let (recursive, interval) = (options.recursive, options.poll_interval);
+    thread::Builder::new()
+        .name(format!("poll {dir}"))
+        .spawn(move || {
+            let mut known = snapshot(&dir, recursive);
+            while !should_stop.load(Ordering::Relaxed) {
+                thread::sleep(interval);
+                let current = snapshot(&dir, recursive);
+                let changed: Vec<_> = current
+                    .iter()
+                    .filter(|(path, mtime)| known.get(*path) != Some(*mtime))
+                    .map(|(path, _)| path.clone())
+                    .chain(
+                        known
+                            .keys()
+                            .filter(|path| !current.contains_key(*path))
+                            .cloned(),
+                    )
+                    .collect();
+                if !changed.is_empty() && tx.send(changed).is_err() {
+                    break;
+                }
+                known = current;
+            }
+        })
+        .expect("Should be able to spawn threads");
+    Watcher(Box::new(StopOnDrop(stop)))
+}
+
+fn snapshot(dir: &Utf8Path, recursive: bool) -> HashMap<Utf8PathBuf, SystemTime> {
+    let walk = walkdir::WalkDir::new(dir).min_depth(1);
+    let walk = if recursive { walk } else { walk.max_depth(1) };
+    walk.into_iter()
+        .flatten()
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            let mtime = metadata.is_file().then(|| metadata.modified().ok())??;
+            Some((Utf8PathBuf::from_path_buf(entry.into_path()).ok()?, mtime))
+        })
+        .collect()
+}
@@ -0,0 +1,314 @@
+//! Peak and RMS overview of a song for drawing seek bars, served by the
+//! `x-mpdhaj waveform` extension command.
+//!
+//! Decoding a whole song takes a while, so only [`MAX_DECODES`] run at the
+//! same time and the results are cached in the database. A decode stops when
+//! the request it belongs to is dropped.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::eyre::{Context, bail};
+use color_eyre::{Result, Section};
+use rusqlite::{Connection, OptionalExtension};
+use tokio::sync::Semaphore;
+
+use crate::scan;
+
+/// More than any seek bar has pixels
+pub const MAX_BUCKETS: u32 = 4096;
+/// Decodes running at the same time, the others wait their turn
+const MAX_DECODES: usize = 2;
+/// Stereo frames summarized together before they are spread over the
+/// buckets, memory use does not grow with the bucket count
+const BLOCK: usize = 256;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Bucket {
+    /// Highest absolute sample value
+    pub peak: f32,
+    pub rms: f32,
+}
+
+/// Limits the decodes, see the module docs
+#[derive(Debug)]
+pub struct Decoder {
+    decodes: Semaphore,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self {
+            decodes: Semaphore::new(MAX_DECODES),
+        }
+    }
+}
+
+impl Decoder {
+    /// `path` is absolute. Stops decoding when the returned future is
+    /// dropped.
+    pub async fn waveform(&self, path: Utf8PathBuf, buckets: u32) -> Result<Vec<Bucket>> {
+        let _permit = self
+            .decodes
+            .acquire()
+            .await
+            .expect("the semaphore is never closed");
+        let cancel = CancelOnDrop::default();
+        let cancelled = Arc::clone(&cancel.0);
+        tokio::task::spawn_blocking(move || compute(&path, buckets, &cancelled))
+            .await
+            .wrap_err("Decoding the waveform panicked")?
+    }
+}
+
+#[derive(Default)]
+struct CancelOnDrop(Arc<AtomicBool>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Peak, sum of squares and number of samples of a stretch of audio
+#[derive(Debug, Clone, Copy, Default)]
+struct Summary {
+    peak: f32,
+    squares: f64,
+    samples: usize,
+}
+
+impl Summary {
+    fn add(mut self, sample: f32) -> Self {
+        self.peak = self.peak.max(sample.abs());
+        self.squares += f64::from(sample) * f64::from(sample);
+        self.samples += 1;
+        self
+    }
+
+    fn merge(self, other: &Summary) -> Self {
+        Self {
+            peak: self.peak.max(other.peak),
+            squares: self.squares + other.squares,
+            samples: self.samples + other.samples,
+        }
+    }
+
+    fn bucket(&self) -> Bucket {
+        if self.samples == 0 {
+            return Bucket::default();
+        }
+        Bucket {
+            peak: self.peak,
+            rms: (self.squares / self.samples as f64).sqrt() as f32,
+        }
+    }
+}
+
+fn compute(path: &Utf8Path, buckets: u32, cancelled: &AtomicBool) -> Result<Vec<Bucket>> {
+    let mut samples = scan::decode(path).with_note(|| format!("path: {path}"))?;
+    let mut blocks = Vec::new();
+    loop {
+        if cancelled.load(Ordering::Relaxed) {
+            bail!("Waveform no longer needed");
+        }
+        let block = samples
+            .by_ref()
+            .take(BLOCK * 2)
+            .fold(Summary::default(), Summary::add);
+        if block.samples == 0 {
+            break;
+        }
+        blocks.push(block);
+    }
+    Ok(spread(&blocks, buckets as usize))
+}
+
+/// Every bucket covers an equal share of the blocks. With more buckets than
+/// blocks neighbouring buckets show the same block.
+fn spread(blocks: &[Summary], buckets: usize) -> Vec<Bucket> {
+    if blocks.is_empty() {
+        return vec![Bucket::default(); buckets];
+    }
+    (0..buckets)
+        .map(|i| {
+            let start = (blocks.len() * i / buckets).min(blocks.len() - 1);
+            let end = (blocks.len() * (i + 1) / buckets).max(start + 1);
+            blocks[start..end]
+                .iter()
+                .fold(Summary::default(), Summary::merge)
+                .bucket()
+        })
+        .collect()
+}
+
+/// A waveform computed earlier for the song as it is now, `uri` is relative
+/// to the music dir
+pub fn cached(db: &Connection, uri: &Utf8Path, buckets: u32) -> Result<Option<Vec<Bucket>>> {
+    let data: Option<Vec<u8>> = db
+        .query_one(
+            "SELECT w.data FROM waveforms w
+             JOIN songs s ON s.path = w.path AND s.mtime = w.mtime
+             WHERE w.path = ?1 AND w.buckets = ?2",
+            rusqlite::params![scan::normalize_path(uri).as_str(), buckets],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(data.map(|data| {
+        data.chunks_exact(8)
+            .map(|bucket| Bucket {
+                peak: f32::from_le_bytes(bucket[..4].try_into().unwrap()),
+                rms: f32::from_le_bytes(bucket[4..].try_into().unwrap()),
+            })
+            .collect()
+    }))
+}
+
+/// Replaces what was cached for the song at `uri` with this many buckets
+pub fn store(db: &Connection, uri: &Utf8Path, waveform: &[Bucket]) -> Result<()> {
+    let data: Vec<u8> = waveform
+        .iter()
+        .flat_map(|bucket| [bucket.peak.to_le_bytes(), bucket.rms.to_le_bytes()])
+        .flatten()
+        .collect();
+    db.execute(
+        "INSERT OR REPLACE INTO waveforms (path, mtime, buckets, data)
+         SELECT path, mtime, ?2, ?3 FROM songs WHERE path = ?1",
+        rusqlite::params![
+            scan::normalize_path(uri).as_str(),
+            waveform.len() as u32,
+            data
+        ],
+    )?;
+    Ok(())
+}
+
+/// One line of peaks and one of RMS values, in buckets from the start of the
+/// song to its end
+pub fn format(waveform: &[Bucket]) -> String {
+    let line = |value: fn(&Bucket) -> f32| {
+        waveform
+            .iter()
+            .map(|bucket| format!("{:.3}", value(bucket)))
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    format!(
+        "buckets: {}\npeak: {}\nrms: {}\n",
+        waveform.len(),
+        line(|bucket| bucket.peak),
+        line(|bucket| bucket.rms)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::player::tests::write_wav;
+
+    /// A second of a 441 Hz mono sine at half of full scale
+    fn write_sine(path: &Utf8Path) {
+        let samples: Vec<i16> = (0..44100)
+            .map(|i| ((TAU * 441.0 * i as f32 / 44100.0).sin() * 0.5 * 32767.0) as i16)
+            .collect();
+        let data_len = samples.len() as u32 * 2;
+        let mut wav = Vec::new();
+        wav.extend(b"RIFF");
+        wav.extend((36 + data_len).to_le_bytes());
+        wav.extend(b"WAVEfmt ");
+        wav.extend(16u32.to_le_bytes());
+        wav.extend(1u16.to_le_bytes()); // pcm
+        wav.extend(1u16.to_le_bytes()); // mono
+        wav.extend(44100u32.to_le_bytes());
+        wav.extend((44100u32 * 2).to_le_bytes());
+        wav.extend(2u16.to_le_bytes());
+        wav.extend(16u16.to_le_bytes());
+        wav.extend(b"data");
+        wav.extend(data_len.to_le_bytes());
+        wav.extend(samples.iter().flat_map(|sample| sample.to_le_bytes()));
+        std::fs::write(path, wav).unwrap();
+    }
+
+    fn dir(name: &str) -> Utf8PathBuf {
+        let dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("mpdhaj-waveform-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn a_sine_has_the_same_level_everywhere() {
+        let dir = dir("sine");
+        write_sine(&dir.join("sine.wav"));
+        let decoder = Decoder::default();
+
+        for buckets in [1, 7, 100] {
+            let waveform = decoder
+                .waveform(dir.join("sine.wav"), buckets)
+                .await
+                .unwrap();
+            assert_eq!(waveform.len(), buckets as usize);
+            for bucket in waveform {
+                assert!((bucket.peak - 0.5).abs() < 0.01, "{bucket:?}");
+                assert!((bucket.rms - 0.5 / 2f32.sqrt()).abs() < 0.01, "{bucket:?}");
+            }
+        }
+        // more buckets than blocks in the song
+        let fine = decoder
+            .waveform(dir.join("sine.wav"), MAX_BUCKETS)
+            .await
+            .unwrap();
+        assert_eq!(fine.len(), MAX_BUCKETS as usize);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn silence_is_all_zeros() {
+        let dir = dir("silence");
+        write_wav(&dir.join("silence.wav"), Duration::from_millis(500));
+
+        let waveform = Decoder::default()
+            .waveform(dir.join("silence.wav"), 16)
+            .await
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(waveform, vec![Bucket::default(); 16]);
+        assert!(format(&waveform).starts_with("buckets: 16\npeak: 0.000,0.000,"));
+    }
+
+    #[test]
+    fn a_cancelled_decode_stops() {
+        let dir = dir("cancel");
+        write_sine(&dir.join("sine.wav"));
+        let result = compute(&dir.join("sine.wav"), 10, &AtomicBool::new(true));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn the_cache_is_keyed_by_mtime_and_buckets() {
+        let mut db = Connection::open_in_memory().unwrap();
+        crate::system::migrations::run(&mut db).unwrap();
+        db.execute("INSERT INTO songs (path, mtime) VALUES ('a.flac', 1)", [])
+            .unwrap();
+        let uri = Utf8Path::new("a.flac");
+        let waveform = [
+            Bucket {
+                peak: 0.5,
+                rms: 0.25,
+            },
+            Bucket::default(),
+        ];
+
+        store(&db, uri, &waveform).unwrap();
+        assert_eq!(cached(&db, uri, 2).unwrap().unwrap(), waveform);
+        assert_eq!(cached(&db, uri, 3).unwrap(), None);
+        db.execute("UPDATE songs SET mtime = 2", []).unwrap();
+        assert_eq!(cached(&db, uri, 2).unwrap(), None);
+    }
+}